@@ -0,0 +1,124 @@
+//! Benchmarks for case-insensitive domain name handling.
+//!
+//! Run with `cargo bench --bench names`.
+
+use std::{
+    hash::{BuildHasher, Hasher, RandomState},
+    hint::black_box,
+    net::Ipv4Addr,
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use uwuhi::{
+    label,
+    name::{DomainName, Label},
+    packet::{
+        encoder::{MessageEncoder, Question},
+        QType,
+    },
+    service::{advertising::Advertiser, InstanceDetails, ServiceInstance, ServiceTransport},
+};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+/// The straightforward byte-by-byte comparison the optimized routines are measured against.
+fn naive_eq(a: &DomainName, b: &DomainName) -> bool {
+    a.labels().len() == b.labels().len()
+        && a.labels()
+            .iter()
+            .zip(b.labels())
+            .all(|(a, b)| a.as_bytes().eq_ignore_ascii_case(b.as_bytes()))
+}
+
+fn naive_lowercase(name: &DomainName) -> DomainName {
+    name.labels()
+        .iter()
+        .map(|label| Label::new(label.as_bytes().to_ascii_lowercase()))
+        .collect()
+}
+
+fn compare(c: &mut Criterion) {
+    let names = [
+        (
+            "short",
+            "MyHost._Device-Info._TCP.local",
+            "myhost._device-info._tcp.local",
+        ),
+        (
+            "long",
+            "Living Room Printer (HP OfficeJet Pro 9010)._ipp._tcp.local",
+            "living room printer (hp officejet pro 9010)._IPP._TCP.LOCAL",
+        ),
+    ];
+    let mut group = c.benchmark_group("eq_ignore_ascii_case");
+    for (id, a, b) in names {
+        let (a, b) = (name(a), name(b));
+        group.bench_with_input(BenchmarkId::new("naive", id), &(), |bench, _| {
+            bench.iter(|| naive_eq(black_box(&a), black_box(&b)))
+        });
+        group.bench_with_input(BenchmarkId::new("optimized", id), &(), |bench, _| {
+            bench.iter(|| black_box(&a).eq_ignore_ascii_case(black_box(&b)))
+        });
+    }
+    group.finish();
+}
+
+fn fold(c: &mut Criterion) {
+    let a = name("Living Room Printer (HP OfficeJet Pro 9010)._ipp._tcp.local");
+    let state = RandomState::new();
+    let mut group = c.benchmark_group("fold");
+    group.bench_function("naive_lowercase", |bench| {
+        bench.iter(|| naive_lowercase(black_box(&a)))
+    });
+    group.bench_function("to_ascii_lowercase", |bench| {
+        bench.iter(|| black_box(&a).to_ascii_lowercase())
+    });
+    group.bench_function("hash_lowercased", |bench| {
+        bench.iter(|| state.hash_one(naive_lowercase(black_box(&a))))
+    });
+    group.bench_function("hash_ignore_ascii_case", |bench| {
+        bench.iter(|| {
+            let mut hasher = state.build_hasher();
+            black_box(&a).hash_ignore_ascii_case(&mut hasher);
+            hasher.finish()
+        })
+    });
+    group.finish();
+}
+
+/// Measures how long a responder with many records takes to answer a query for one of them.
+fn responder(c: &mut Criterion) {
+    let mut group = c.benchmark_group("advertiser_lookup");
+    for count in [10, 100, 500] {
+        let mut adv = Advertiser::new(label!("host"), Ipv4Addr::new(10, 0, 0, 1).into()).unwrap();
+        for i in 0..count {
+            let instance = ServiceInstance::new(
+                Label::new(format!("Service Instance {i}")),
+                label!("_http"),
+                ServiceTransport::TCP,
+            );
+            adv.add_instance(instance, InstanceDetails::new(name("host.local"), 80));
+        }
+
+        let qname = name(&format!("SERVICE INSTANCE {}._http._tcp.local", count - 1));
+        let mut query = [0; 512];
+        let mut enc = MessageEncoder::new(&mut query);
+        enc.question(Question::new(&qname).ty(QType::SRV));
+        let len = enc.finish().unwrap();
+        let query = &query[..len];
+        let source = "10.0.0.2:5353".parse().unwrap();
+
+        group.bench_with_input(BenchmarkId::from_parameter(count), &count, |bench, _| {
+            bench.iter(|| {
+                let response = adv.handle_packet(black_box(query), source).unwrap();
+                assert!(response.is_some());
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, compare, fold, responder);
+criterion_main!(benches);
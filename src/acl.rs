@@ -0,0 +1,227 @@
+//! Source-address-based access control.
+//!
+//! An [`Acl`] decides whether a query from a given address should be answered. It is used by the
+//! mDNS [`Advertiser`] and by the unicast [`Server`] to restrict who gets answers, for example on
+//! networks shared by multiple tenants.
+//!
+//! [`Advertiser`]: crate::service::advertising::Advertiser
+//! [`Server`]: crate::server::Server
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use crate::Error;
+
+/// An IPv4 or IPv6 address prefix in CIDR notation, like `192.168.0.0/16` or `fd00::/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpPrefix {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpPrefix {
+    /// Creates a prefix matching all addresses whose first `prefix_len` bits are equal to those of
+    /// `addr`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix_len` is larger than 32 for IPv4 addresses, or larger than 128 for IPv6
+    /// addresses.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Self {
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        assert!(
+            prefix_len <= max,
+            "invalid prefix length {prefix_len} for {addr}"
+        );
+        Self { addr, prefix_len }
+    }
+
+    /// Returns the address this prefix was created from.
+    #[inline]
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// Returns the number of leading bits that have to match.
+    #[inline]
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Returns whether `ip` is covered by this prefix.
+    ///
+    /// IPv4 addresses never match IPv6 prefixes and vice versa, except for IPv4-mapped IPv6
+    /// addresses (`::ffff:a.b.c.d`), which are treated as the IPv4 address they map.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(prefix), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u32::from(prefix) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(prefix), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u128::from(prefix) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl From<Ipv4Addr> for IpPrefix {
+    /// Creates a prefix matching only `addr`.
+    fn from(addr: Ipv4Addr) -> Self {
+        Self::new(addr.into(), 32)
+    }
+}
+
+impl From<Ipv6Addr> for IpPrefix {
+    /// Creates a prefix matching only `addr`.
+    fn from(addr: Ipv6Addr) -> Self {
+        Self::new(addr.into(), 128)
+    }
+}
+
+impl From<IpAddr> for IpPrefix {
+    /// Creates a prefix matching only `addr`.
+    fn from(addr: IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(addr) => addr.into(),
+            IpAddr::V6(addr) => addr.into(),
+        }
+    }
+}
+
+impl FromStr for IpPrefix {
+    type Err = Error;
+
+    /// Parses a prefix in CIDR notation (`addr/len`).
+    ///
+    /// A plain address without `/len` matches just that address.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (s, None),
+        };
+        let addr = addr.parse::<IpAddr>().map_err(|_| Error::InvalidValue)?;
+        let Some(prefix_len) = prefix_len else {
+            return Ok(addr.into());
+        };
+        let prefix_len = prefix_len.parse::<u8>().map_err(|_| Error::InvalidValue)?;
+        if prefix_len > if addr.is_ipv4() { 32 } else { 128 } {
+            return Err(Error::InvalidValue);
+        }
+        Ok(Self::new(addr, prefix_len))
+    }
+}
+
+impl fmt::Display for IpPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// An access-control list of allowed and denied source address prefixes.
+///
+/// An address is allowed if it doesn't match any denied prefix, and either matches an allowed
+/// prefix or no allowed prefixes have been added. In other words, deny rules always take
+/// precedence, and an empty [`Acl`] allows everything.
+///
+/// # Example
+///
+/// ```
+/// # use uwuhi::acl::Acl;
+/// let mut acl = Acl::new();
+/// acl.allow("192.168.0.0/16".parse().unwrap());
+/// acl.deny("192.168.13.0/24".parse().unwrap());
+///
+/// assert!(acl.is_allowed("192.168.1.10".parse().unwrap()));
+/// assert!(!acl.is_allowed("192.168.13.10".parse().unwrap()));
+/// assert!(!acl.is_allowed("10.0.0.1".parse().unwrap()));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Acl {
+    allow: Vec<IpPrefix>,
+    deny: Vec<IpPrefix>,
+}
+
+impl Acl {
+    /// Creates an empty [`Acl`], which allows every address.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows addresses in `prefix`, unless they are also denied.
+    ///
+    /// Once any prefix is allowed, addresses outside of all allowed prefixes are denied.
+    pub fn allow(&mut self, prefix: IpPrefix) {
+        self.allow.push(prefix);
+    }
+
+    /// Denies addresses in `prefix`.
+    pub fn deny(&mut self, prefix: IpPrefix) {
+        self.deny.push(prefix);
+    }
+
+    /// Returns whether queries from `ip` should be answered.
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|prefix| prefix.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|prefix| prefix.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn prefixes() {
+        let prefix = "10.1.0.0/16".parse::<IpPrefix>().unwrap();
+        assert_eq!(prefix.to_string(), "10.1.0.0/16");
+        assert!(prefix.contains(ip("10.1.255.3")));
+        assert!(prefix.contains(ip("::ffff:10.1.0.1")));
+        assert!(!prefix.contains(ip("10.2.0.1")));
+        assert!(!prefix.contains(ip("::1")));
+
+        let prefix = "fd00::/8".parse::<IpPrefix>().unwrap();
+        assert!(prefix.contains(ip("fdab::1")));
+        assert!(!prefix.contains(ip("fe80::1")));
+
+        assert!("0.0.0.0/0"
+            .parse::<IpPrefix>()
+            .unwrap()
+            .contains(ip("1.2.3.4")));
+        assert_eq!("::1".parse::<IpPrefix>().unwrap().prefix_len(), 128);
+        assert!("10.0.0.0/33".parse::<IpPrefix>().is_err());
+        assert!("10.0.0.0/".parse::<IpPrefix>().is_err());
+        assert!("example/8".parse::<IpPrefix>().is_err());
+    }
+
+    #[test]
+    fn acl() {
+        let mut acl = Acl::new();
+        assert!(acl.is_allowed(ip("1.2.3.4")));
+        acl.deny("1.2.3.4".parse().unwrap());
+        assert!(!acl.is_allowed(ip("1.2.3.4")));
+        assert!(acl.is_allowed(ip("1.2.3.5")));
+
+        acl.allow("1.2.0.0/16".parse().unwrap());
+        acl.allow("fe80::/10".parse().unwrap());
+        assert!(!acl.is_allowed(ip("1.2.3.4")));
+        assert!(acl.is_allowed(ip("1.2.3.5")));
+        assert!(acl.is_allowed(ip("fe80::1")));
+        assert!(!acl.is_allowed(ip("1.3.0.1")));
+    }
+}
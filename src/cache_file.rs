@@ -0,0 +1,160 @@
+//! The versioned on-disk format of persistent caches.
+//!
+//! A cache file starts with a fixed header:
+//!
+//! - 8 bytes of magic (`uwuhi\0cf`),
+//! - a format version byte ([`VERSION`]),
+//! - a byte identifying the kind of cache ([`CacheKind`]),
+//! - the time the file was written, as a big-endian `u64` of seconds since the UNIX epoch.
+//!
+//! The header is followed by any number of entries, each a big-endian `u32` length followed by
+//! that many bytes. What the entries contain is up to the cache, but they are usually DNS messages
+//! whose record TTLs are the *remaining* TTLs at the time the file was written. When loading, the
+//! time that has passed since then (as returned by [`read`]) is subtracted from those TTLs, so
+//! that entries don't outlive the records they were created from.
+//!
+//! Files with a different magic, version or kind are rejected with [`Error::InvalidValue`], and so
+//! are entries longer than [`MAX_ENTRY_LEN`].
+
+use std::{
+    io::{self, Read, Write},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::Error;
+
+const MAGIC: &[u8; 8] = b"uwuhi\0cf";
+
+/// The current version of the format.
+///
+/// This must be bumped whenever the header or the contents of any kind of entry change in an
+/// incompatible way.
+pub(crate) const VERSION: u8 = 1;
+
+/// The longest entry that can be stored.
+///
+/// Entries are DNS messages (which are at most 64 KiB long) plus a few bytes of metadata, so any
+/// longer length prefix means that the file is corrupt. Checking this avoids allocating up to
+/// 4 GiB for a bogus entry.
+pub(crate) const MAX_ENTRY_LEN: usize = 1 << 17;
+
+/// Identifies which cache a file belongs to, so that they can't accidentally be mixed up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum CacheKind {
+    /// A [`DetailsCache`](crate::service::discovery::DetailsCache).
+    Details = 1,
+    /// A [`ResolverCache`](crate::resolver::cache::ResolverCache).
+    Resolver = 2,
+}
+
+/// Writes a cache file of the given `kind`, containing `entries`.
+pub(crate) fn write<'a>(
+    mut w: impl Write,
+    kind: CacheKind,
+    entries: impl IntoIterator<Item = &'a [u8]>,
+) -> Result<(), Error> {
+    let saved_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    w.write_all(MAGIC)?;
+    w.write_all(&[VERSION, kind as u8])?;
+    w.write_all(&saved_at.to_be_bytes())?;
+    for entry in entries {
+        if entry.len() > MAX_ENTRY_LEN {
+            return Err(Error::InvalidValue);
+        }
+        w.write_all(&(entry.len() as u32).to_be_bytes())?;
+        w.write_all(entry)?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Reads a cache file of the given `kind`.
+///
+/// Returns the time that has passed since the file was written, and its entries.
+pub(crate) fn read(mut r: impl Read, kind: CacheKind) -> Result<(Duration, Vec<Vec<u8>>), Error> {
+    let mut header = [0; 18];
+    r.read_exact(&mut header)?;
+    if &header[..8] != MAGIC || header[8] != VERSION || header[9] != kind as u8 {
+        log::debug!("incompatible cache file header: {:02x?}", header);
+        return Err(Error::InvalidValue);
+    }
+    let saved_at =
+        UNIX_EPOCH + Duration::from_secs(u64::from_be_bytes(header[10..].try_into().unwrap()));
+    // If the clock went backwards, treat the file as brand new rather than failing.
+    let age = SystemTime::now()
+        .duration_since(saved_at)
+        .unwrap_or_default();
+
+    let mut entries = Vec::new();
+    loop {
+        let mut len = [0; 4];
+        match r.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_ENTRY_LEN {
+            log::debug!("cache file entry too long ({} bytes)", len);
+            return Err(Error::InvalidValue);
+        }
+        let mut entry = vec![0; len];
+        r.read_exact(&mut entry)?;
+        entries.push(entry);
+    }
+    Ok((age, entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut file = Vec::new();
+        write(&mut file, CacheKind::Details, [&b"abc"[..], b"", b"de"]).unwrap();
+        let (age, entries) = read(&file[..], CacheKind::Details).unwrap();
+        assert!(age < Duration::from_secs(60));
+        assert_eq!(entries, [b"abc".to_vec(), Vec::new(), b"de".to_vec()]);
+
+        // Other versions are rejected.
+        let mut other = file.clone();
+        other[8] = VERSION + 1;
+        assert!(matches!(
+            read(&other[..], CacheKind::Details),
+            Err(Error::InvalidValue)
+        ));
+        assert!(read(&b"not a cache file"[..], CacheKind::Details).is_err());
+
+        // So are files of another kind.
+        assert!(matches!(
+            read(&file[..], CacheKind::Resolver),
+            Err(Error::InvalidValue)
+        ));
+
+        // Truncated entries are an error, not silently dropped.
+        assert!(read(&file[..file.len() - 1], CacheKind::Details).is_err());
+    }
+
+    #[test]
+    fn entry_length_limit() {
+        let long = vec![0; MAX_ENTRY_LEN + 1];
+        assert!(matches!(
+            write(Vec::new(), CacheKind::Details, [&long[..]]),
+            Err(Error::InvalidValue)
+        ));
+
+        // A huge length prefix is rejected without trying to read (or allocate) that much.
+        let mut file = Vec::new();
+        write(&mut file, CacheKind::Details, []).unwrap();
+        file.extend_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(
+            read(&file[..], CacheKind::Details),
+            Err(Error::InvalidValue)
+        ));
+    }
+}
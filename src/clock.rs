@@ -0,0 +1,220 @@
+//! Time sources for time-dependent components.
+//!
+//! Components that schedule retransmissions, enforce deadlines or wait between operations query
+//! the time through a [`Clock`] instead of calling [`Instant::now`] and [`thread::sleep`]
+//! directly. By default, the [`SystemClock`] is used, but tests can substitute a [`ManualClock`]
+//! to control the passage of time and avoid real sleeps.
+//!
+//! [`Backoff`] computes exponentially growing delays for retrying failed operations.
+//!
+//! [`thread::sleep`]: std::thread::sleep
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// A source of time.
+pub trait Clock: Send + Sync {
+    /// Returns the current point in time.
+    fn now(&self) -> Instant;
+
+    /// Blocks the calling thread for `duration`.
+    fn sleep(&self, duration: Duration);
+
+    /// Blocks the calling thread until `deadline` has passed.
+    ///
+    /// Returns immediately if `deadline` is in the past.
+    fn sleep_until(&self, deadline: Instant) {
+        let now = self.now();
+        if deadline > now {
+            self.sleep(deadline - now);
+        }
+    }
+
+    /// Returns the amount of time that has passed since `earlier`.
+    ///
+    /// Returns [`Duration::ZERO`] if `earlier` is in the future.
+    fn elapsed_since(&self, earlier: Instant) -> Duration {
+        self.now().saturating_duration_since(earlier)
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        (**self).sleep(duration)
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Box<C> {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        (**self).sleep(duration)
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        (**self).sleep(duration)
+    }
+}
+
+/// The operating system's monotonic clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// A [`Clock`] that only advances when told to.
+///
+/// [`Clock::sleep`] returns immediately after advancing the clock by the requested duration, so
+/// code that waits for timeouts runs without delay.
+///
+/// Clones of a [`ManualClock`] share the same time, so a test can keep a clone to advance the time
+/// observed by the component under test.
+#[derive(Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl ManualClock {
+    /// Creates a [`ManualClock`] starting at the current time.
+    pub fn new() -> Self {
+        Self::starting_at(Instant::now())
+    }
+
+    /// Creates a [`ManualClock`] starting at `start`.
+    pub fn starting_at(start: Instant) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(start)),
+        }
+    }
+
+    /// Moves the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for ManualClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ManualClock")
+            .field("now", &*self.now.lock().unwrap())
+            .finish()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+/// Exponentially growing delays between retries of a failing operation.
+///
+/// Each call to [`Backoff::next_delay`] returns twice the previous delay, up to a maximum. Call
+/// [`Backoff::reset`] once the operation succeeds.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    /// Creates a [`Backoff`] that starts at `initial` and never exceeds `max`.
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            next: initial.min(max),
+        }
+    }
+
+    /// Returns how long to wait before the next retry, and doubles the delay for the retry after.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = self.next.saturating_mul(2).min(self.max);
+        delay
+    }
+
+    /// Starts over at the initial delay.
+    pub fn reset(&mut self) {
+        self.next = self.initial.min(self.max);
+    }
+}
+
+impl Default for Backoff {
+    /// Creates a [`Backoff`] that starts at 100 milliseconds and grows up to 30 seconds.
+    ///
+    /// This is what the crate's long-running listeners use to recreate their sockets.
+    fn default() -> Self {
+        Self::new(Duration::from_millis(100), Duration::from_secs(30))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_clock() {
+        let clock = ManualClock::new();
+        let handle = clock.clone();
+        let start = clock.now();
+
+        clock.sleep(Duration::from_secs(5));
+        assert_eq!(handle.now() - start, Duration::from_secs(5));
+
+        handle.advance(Duration::from_secs(1));
+        assert_eq!(clock.elapsed_since(start), Duration::from_secs(6));
+
+        clock.sleep_until(start + Duration::from_secs(10));
+        assert_eq!(clock.elapsed_since(start), Duration::from_secs(10));
+        clock.sleep_until(start);
+        assert_eq!(clock.elapsed_since(start), Duration::from_secs(10));
+        assert_eq!(
+            clock.elapsed_since(clock.now() + Duration::from_secs(1)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn backoff() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(500));
+        let delays = (0..5).map(|_| backoff.next_delay()).collect::<Vec<_>>();
+        assert_eq!(delays, [100, 200, 400, 500, 500].map(Duration::from_millis));
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,1127 @@
+//! High-level DNS-SD API.
+//!
+//! This module provides a batteries-included interface similar to the ones offered by Bonjour and
+//! Avahi: [`register`] advertises a service instance on the local network, and [`browse`] keeps
+//! track of the instances of a service that are available. Both run in a background thread and
+//! report what happens via [`std::sync::mpsc`] channels. Dropping the returned handle stops the
+//! background thread.
+//!
+//! For more control, use the lower-level [`advertising`] and [`discovery`] modules instead.
+//!
+//! # System daemon
+//!
+//! When the `system-daemon` feature is enabled, [`register`] and [`browse`] first try to delegate
+//! to the mDNS daemon of the operating system (Avahi on Linux, Bonjour on macOS and Windows). This
+//! avoids running a second responder that competes with the system's own one. If no daemon is
+//! available, uwuhi's built-in implementation is used. [`Registration::backend`] and
+//! [`Browser::backend`] report which one is in use.
+//!
+//! [`advertising`]: crate::service::advertising
+//! [`discovery`]: crate::service::discovery
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    label,
+    name::{DomainName, Label},
+    packet::{
+        decoder::{MessageDecoder, ResourceRecord},
+        records::Record,
+        Class, Type,
+    },
+    service::{
+        advertising::Advertiser, discovery::SyncDiscoverer, InstanceDetails, Service,
+        ServiceInstance, TxtRecords,
+    },
+    Error, MDNS_BUFFER_SIZE,
+};
+
+#[cfg(feature = "system-daemon")]
+mod daemon;
+
+/// How often the background threads check whether they should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Number of probe queries that have to go unanswered before a name is considered unique
+/// (RFC 6762, section 8.1).
+const PROBE_COUNT: u32 = 3;
+
+/// Interval between probe queries, and time to wait for responses after the last one.
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Interval between browse queries.
+const BROWSE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Number of consecutive browse queries an instance has to be missing from before it is
+/// considered gone.
+const MISSED_ROUNDS_UNTIL_LOST: u32 = 2;
+
+/// Number of unanswered queries for an instance that have to be observed on the network before the
+/// instance is considered gone (RFC 6762, section 10.5).
+const POOF_QUERIES: u32 = 2;
+
+/// Time after the first unanswered query for an instance after which it is considered gone, if
+/// enough unanswered queries were observed.
+const POOF_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The implementation backing a [`Registration`] or [`Browser`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Backend {
+    /// uwuhi's own mDNS implementation.
+    Builtin,
+    /// The operating system's mDNS daemon.
+    SystemDaemon,
+}
+
+/// Events reported by a [`Registration`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RegistrationEvent {
+    /// Probing found that no other host uses the name, and the service instance is now being
+    /// advertised under the contained name.
+    Registered(ServiceInstance),
+    /// Another host claimed the name of the service instance (or of this host), so it was renamed
+    /// to the contained instance.
+    ///
+    /// The new name is probed for, and [`RegistrationEvent::Registered`] is sent again once it
+    /// turns out to be unique.
+    Conflict(ServiceInstance),
+    /// Advertising stopped because of an I/O error.
+    Failed(io::Error),
+}
+
+/// Handle to a service instance advertised via [`register`].
+///
+/// The instance is advertised until this handle is dropped. Stopping the registration withdraws
+/// the instance's records from the caches of other hosts.
+pub struct Registration {
+    instance: ServiceInstance,
+    backend: Backend,
+    events: mpsc::Receiver<RegistrationEvent>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Registration {
+    /// Returns the [`ServiceInstance`] that was passed to [`register`].
+    ///
+    /// The instance may be advertised under a different name if another host claimed this one
+    /// (see [`RegistrationEvent::Conflict`]).
+    #[inline]
+    pub fn instance(&self) -> &ServiceInstance {
+        &self.instance
+    }
+
+    /// Returns which implementation advertises the service instance.
+    #[inline]
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// Returns the channel on which [`RegistrationEvent`]s are delivered.
+    #[inline]
+    pub fn events(&self) -> &mpsc::Receiver<RegistrationEvent> {
+        &self.events
+    }
+
+    /// Stops advertising the service instance, and waits for the background thread to exit.
+    pub fn stop(mut self) {
+        self.stop_impl();
+    }
+
+    fn stop_impl(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.stop_impl();
+    }
+}
+
+/// Advertises `instance` on the local network.
+///
+/// The instance is advertised as reachable on `port` of this machine, with the metadata in `txt`.
+/// A host name for this machine is derived from its local IPv4 address.
+///
+/// Before the instance is announced, the built-in responder probes the network to make sure no
+/// other host uses the same name ([RFC 6762, section 8]). [`RegistrationEvent::Registered`] is
+/// sent once that succeeds. If another host claims the name, the instance is renamed and probed
+/// for again, which is reported as [`RegistrationEvent::Conflict`].
+///
+/// [RFC 6762, section 8]: https://datatracker.ietf.org/doc/html/rfc6762#section-8
+pub fn register(instance: ServiceInstance, port: u16, txt: TxtRecords) -> io::Result<Registration> {
+    let (sender, events) = mpsc::channel();
+    let stop = Arc::new(AtomicBool::new(false));
+
+    #[cfg(feature = "system-daemon")]
+    match daemon::register(&instance, port, &txt, &sender, &stop) {
+        Ok(thread) => {
+            return Ok(Registration {
+                instance,
+                backend: Backend::SystemDaemon,
+                events,
+                stop,
+                thread: Some(thread),
+            })
+        }
+        Err(e) => log::debug!(
+            "system mDNS daemon unavailable, using built-in responder: {}",
+            e
+        ),
+    }
+
+    let addr = local_ipv4()?;
+    let hostname = Label::new(format!("uwuhi-{}", addr).replace('.', "-"));
+    let host = DomainName::from_iter([hostname.clone(), label!("local")]);
+
+    let mut adv = Advertiser::new(hostname, IpAddr::V4(addr))?;
+    let mut details = InstanceDetails::new(host, port);
+    *details.txt_records_mut() = txt;
+    adv.add_instance(instance.clone(), details);
+
+    let sock = adv.create_socket()?;
+
+    let thread = {
+        let stop = stop.clone();
+        let instance = instance.clone();
+        let dest = SocketAddr::new(Ipv4Addr::new(224, 0, 0, 251).into(), 5353);
+        thread::spawn(move || {
+            if let Err(e) = advertise(adv, &instance, &sock, dest, &sender, &stop) {
+                log::error!("advertising failed: {}", e);
+                sender.send(RegistrationEvent::Failed(e)).ok();
+            }
+        })
+    };
+
+    Ok(Registration {
+        instance,
+        backend: Backend::Builtin,
+        events,
+        stop,
+        thread: Some(thread),
+    })
+}
+
+/// Whether the names of a registration are being probed for, or have been announced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RegistrationState {
+    /// `sent` probes were sent so far, and the next step is due at `next`.
+    Probing {
+        sent: u32,
+        next: Instant,
+    },
+    Announced,
+}
+
+/// Advertises `instance` via `adv`, multicasting to `dest`, until `stop` is set.
+///
+/// The names are probed for before they are announced, and again after they were renamed due to
+/// a conflict. Queries are only answered once probing has succeeded. When stopping, a goodbye
+/// packet withdraws the records again.
+fn advertise(
+    mut adv: Advertiser,
+    instance: &ServiceInstance,
+    sock: &UdpSocket,
+    dest: SocketAddr,
+    sender: &mpsc::Sender<RegistrationEvent>,
+    stop: &AtomicBool,
+) -> io::Result<()> {
+    let current_instance = |adv: &Advertiser| {
+        let name = adv.current_instance_name(instance).unwrap().clone();
+        ServiceInstance::from_service(name, instance.service().clone())
+    };
+    let mut names = (adv.current_hostname().clone(), current_instance(&adv));
+    let mut state = RegistrationState::Probing {
+        sent: 0,
+        next: Instant::now(),
+    };
+    let mut recv_buf = vec![0; adv.max_message_size()];
+    while !stop.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        let mut timeout = POLL_INTERVAL;
+        if let RegistrationState::Probing { sent, next } = &mut state {
+            if now >= *next && *sent < PROBE_COUNT {
+                log::debug!("probing for '{}'", names.1);
+                sock.send_to(adv.build_probe()?, dest)?;
+                *sent += 1;
+                *next = now + PROBE_INTERVAL;
+            } else if now >= *next {
+                // Nobody objected to the last probe in time, so the names are ours.
+                if let Some(announcement) = adv.announce_now()? {
+                    sock.send_to(announcement, dest)?;
+                }
+                // Goodbyes for names given up because of a conflict.
+                if let Some(announcement) = adv.build_announcement()? {
+                    sock.send_to(announcement, dest)?;
+                }
+                state = RegistrationState::Announced;
+                sender
+                    .send(RegistrationEvent::Registered(names.1.clone()))
+                    .ok();
+                continue;
+            }
+            timeout = timeout.min(next.saturating_duration_since(now));
+        }
+
+        sock.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        let (len, addr) = match sock.recv_from(&mut recv_buf) {
+            Ok(res) => res,
+            Err(e) => match Error::from(e) {
+                e if e.is_timeout() => continue,
+                e => return Err(e.into()),
+            },
+        };
+        match adv.handle_query(&recv_buf[..len], addr) {
+            // Names can't be defended before probing has finished.
+            Ok(Some((resp, dest))) if state == RegistrationState::Announced => {
+                sock.send_to(resp, dest)?;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                log::debug!("failed to handle packet: {}", e);
+            }
+        }
+
+        let current = (adv.current_hostname().clone(), current_instance(&adv));
+        if current != names {
+            log::info!("name conflict, renamed '{}' to '{}'", names.1, current.1);
+            names = current;
+            sender
+                .send(RegistrationEvent::Conflict(names.1.clone()))
+                .ok();
+            state = RegistrationState::Probing {
+                sent: 0,
+                next: Instant::now(),
+            };
+        }
+    }
+
+    if state == RegistrationState::Announced {
+        sock.send_to(adv.build_goodbye()?, dest)?;
+    }
+    Ok(())
+}
+
+/// Events reported by a [`Browser`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum BrowseEvent {
+    /// A new instance of the service was found.
+    Found(ServiceInstance),
+    /// The details of a previously found instance were resolved.
+    Resolved(ServiceInstance, InstanceDetails),
+    /// The details of a previously resolved instance have changed (eg. it moved to a different
+    /// host or port, or its TXT records were updated).
+    Updated(ServiceInstance, InstanceDetails),
+    /// A previously found instance has disappeared.
+    Lost(ServiceInstance),
+    /// Browsing stopped because of an I/O error.
+    Failed(io::Error),
+}
+
+/// Handle to a running service browser, created by [`browse`].
+///
+/// Browsing continues until this handle is dropped.
+pub struct Browser {
+    service: Service,
+    backend: Backend,
+    events: mpsc::Receiver<BrowseEvent>,
+    stop: Arc<AtomicBool>,
+    network_changed: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Browser {
+    /// Returns the [`Service`] being browsed for.
+    #[inline]
+    pub fn service(&self) -> &Service {
+        &self.service
+    }
+
+    /// Returns which implementation performs the browsing.
+    #[inline]
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// Returns the channel on which [`BrowseEvent`]s are delivered.
+    #[inline]
+    pub fn events(&self) -> &mpsc::Receiver<BrowseEvent> {
+        &self.events
+    }
+
+    /// Informs the browser that the host's network attachment has changed (for example, because
+    /// it joined a different Wi-Fi network, or an interface went up or down).
+    ///
+    /// The instances found so far may not be reachable anymore, so they are all reported via
+    /// [`BrowseEvent::Lost`], the cached instance details are discarded, and a new browse round
+    /// starts immediately. Instances that are still present are then reported via
+    /// [`BrowseEvent::Found`] again. Without this, instances from the previous network would only
+    /// be reported as lost after a few unanswered browse rounds.
+    ///
+    /// This library doesn't detect network changes by itself, so this has to be called by the
+    /// application (eg. in response to a notification from the operating system). It has no effect
+    /// when the [`Backend::SystemDaemon`] is used, since the daemon tracks network changes itself.
+    ///
+    /// Users of the lower-level [`SyncDiscoverer`] can get the same effect by calling
+    /// [`SyncDiscoverer::clear_details_cache`] and browsing again. The records cached by a
+    /// [`CachingResolver`] should be discarded as well, via [`ResolverCache::clear`].
+    ///
+    /// [`CachingResolver`]: crate::resolver::cache::CachingResolver
+    /// [`ResolverCache::clear`]: crate::resolver::cache::ResolverCache::clear
+    pub fn notify_network_change(&self) {
+        self.network_changed.store(true, Ordering::Relaxed);
+    }
+
+    /// Stops browsing, and waits for the background thread to exit.
+    pub fn stop(mut self) {
+        self.stop_impl();
+    }
+
+    fn stop_impl(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+impl Drop for Browser {
+    fn drop(&mut self) {
+        self.stop_impl();
+    }
+}
+
+/// Starts browsing the local network for instances of `service`.
+///
+/// New instances are reported via [`BrowseEvent::Found`], followed by [`BrowseEvent::Resolved`]
+/// once their host and port have been looked up. Instances that stop responding are reported via
+/// [`BrowseEvent::Lost`].
+///
+/// The built-in browser also watches the queries other hosts send for the service. If an instance
+/// doesn't answer several of them, it is reported as lost early, without waiting for the next
+/// browse rounds ("Passive Observation Of Failures", [RFC 6762, section 10.5]). When an instance
+/// announces records that differ from its resolved details, it is resolved again, and the new
+/// details are reported via [`BrowseEvent::Updated`].
+///
+/// [RFC 6762, section 10.5]: https://datatracker.ietf.org/doc/html/rfc6762#section-10.5
+pub fn browse(service: Service) -> io::Result<Browser> {
+    let (sender, events) = mpsc::channel();
+    let stop = Arc::new(AtomicBool::new(false));
+    let network_changed = Arc::new(AtomicBool::new(false));
+
+    #[cfg(feature = "system-daemon")]
+    match daemon::browse(&service, &sender, &stop) {
+        Ok(thread) => {
+            return Ok(Browser {
+                service,
+                backend: Backend::SystemDaemon,
+                events,
+                stop,
+                network_changed,
+                thread: Some(thread),
+            })
+        }
+        Err(e) => log::debug!(
+            "system mDNS daemon unavailable, using built-in browser: {}",
+            e
+        ),
+    }
+
+    let discoverer = SyncDiscoverer::new_multicast_v4()?;
+    let observer = match observer_socket() {
+        Ok(sock) => Some(sock),
+        Err(e) => {
+            log::debug!("cannot observe mDNS traffic, disabling POOF: {}", e);
+            None
+        }
+    };
+    let thread = {
+        let stop = stop.clone();
+        let network_changed = network_changed.clone();
+        let service = service.clone();
+        thread::spawn(move || {
+            if let Err(e) = run_browser(
+                discoverer,
+                observer,
+                &service,
+                &sender,
+                &stop,
+                &network_changed,
+                &SystemClock,
+            ) {
+                log::error!("browsing for {} failed: {}", service, e);
+                sender.send(BrowseEvent::Failed(e)).ok();
+            }
+        })
+    };
+
+    Ok(Browser {
+        service,
+        backend: Backend::Builtin,
+        events,
+        stop,
+        network_changed,
+        thread: Some(thread),
+    })
+}
+
+/// Creates a socket that receives the mDNS traffic on the network.
+fn observer_socket() -> io::Result<UdpSocket> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let sock = crate::net::MulticastSocketBuilder::mdns_v4().build()?;
+        sock.set_read_timeout(Some(POLL_INTERVAL))?;
+        Ok(sock)
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+fn run_browser(
+    mut discoverer: SyncDiscoverer,
+    mut observer: Option<UdpSocket>,
+    service: &Service,
+    sender: &mpsc::Sender<BrowseEvent>,
+    stop: &AtomicBool,
+    network_changed: &AtomicBool,
+    clock: &dyn Clock,
+) -> io::Result<()> {
+    let service_domain = DomainName::from_iter([
+        service.name().clone(),
+        service.transport().to_label(),
+        label!("local"),
+    ]);
+    let mut recv_buf = vec![0; MDNS_BUFFER_SIZE];
+    let mut tracker = InstanceTracker::default();
+    while !stop.load(Ordering::Relaxed) {
+        if network_changed.swap(false, Ordering::Relaxed) {
+            log::debug!("network changed, restarting browsing for {}", service);
+            discoverer.clear_details_cache();
+            // The multicast group membership of the observer socket belongs to the old network.
+            if observer.is_some() {
+                observer = observer_socket()
+                    .map_err(|e| log::debug!("cannot observe mDNS traffic, disabling POOF: {}", e))
+                    .ok();
+            }
+            for instance in tracker.clear() {
+                if sender.send(BrowseEvent::Lost(instance)).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        let round_start = clock.now();
+
+        let mut seen = BTreeSet::new();
+        discoverer.discover_instances(service, |instance| {
+            seen.insert(instance.clone());
+            if stop.load(Ordering::Relaxed) {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })?;
+
+        let (found, lost) = tracker.update(seen);
+        for instance in lost {
+            if sender.send(BrowseEvent::Lost(instance)).is_err() {
+                return Ok(());
+            }
+        }
+        for instance in found {
+            if sender.send(BrowseEvent::Found(instance.clone())).is_err() {
+                return Ok(());
+            }
+            match discoverer.load_instance_details(&instance) {
+                Ok(details) => {
+                    tracker.set_details(&instance, details.clone());
+                    if sender
+                        .send(BrowseEvent::Resolved(instance, details))
+                        .is_err()
+                    {
+                        return Ok(());
+                    }
+                }
+                Err(e) => {
+                    log::debug!("failed to resolve {}: {}", instance, e);
+                }
+            }
+        }
+
+        while clock.elapsed_since(round_start) < BROWSE_INTERVAL
+            && !stop.load(Ordering::Relaxed)
+            && !network_changed.load(Ordering::Relaxed)
+        {
+            let Some(sock) = &observer else {
+                clock.sleep(POLL_INTERVAL);
+                continue;
+            };
+
+            match sock.recv_from(&mut recv_buf) {
+                // Responses to queries from other ports are sent via unicast, so we can't tell
+                // whether they were answered.
+                Ok((len, addr)) if addr.port() == 5353 => {
+                    let packet = &recv_buf[..len];
+                    if let Err(e) = tracker.observe_packet(&service_domain, packet, clock.now()) {
+                        log::trace!("failed to decode packet from {}: {}", addr, e);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => match Error::from(e) {
+                    e if e.is_timeout() => {}
+                    e => return Err(e.into()),
+                },
+            }
+
+            for instance in tracker.expire_unanswered(clock.now()) {
+                log::debug!("{} did not answer queries, considering it lost", instance);
+                if sender.send(BrowseEvent::Lost(instance)).is_err() {
+                    return Ok(());
+                }
+            }
+
+            for instance in tracker.take_stale() {
+                let details = match discoverer.load_instance_details(&instance) {
+                    Ok(details) => details,
+                    Err(e) => {
+                        log::debug!("failed to re-resolve {}: {}", instance, e);
+                        continue;
+                    }
+                };
+                if tracker.set_details(&instance, details.clone())
+                    && sender
+                        .send(BrowseEvent::Updated(instance, details))
+                        .is_err()
+                {
+                    return Ok(());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Tracks which service instances are present across browse rounds.
+#[derive(Default)]
+struct InstanceTracker {
+    known: BTreeMap<ServiceInstance, InstanceState>,
+}
+
+#[derive(Default)]
+struct InstanceState {
+    /// Number of consecutive browse rounds the instance was missing from.
+    missed: u32,
+    /// When the first unanswered query for the instance was observed, and the number of
+    /// unanswered queries observed since then.
+    unanswered: Option<(Instant, u32)>,
+    /// The last resolved details of the instance.
+    details: Option<InstanceDetails>,
+    /// Whether records contradicting `details` were observed, so the instance has to be resolved
+    /// again.
+    stale: bool,
+}
+
+impl InstanceTracker {
+    /// Updates the tracker with the instances seen in a browse round, and returns the instances
+    /// that are new and the ones that are now considered lost.
+    fn update(
+        &mut self,
+        seen: BTreeSet<ServiceInstance>,
+    ) -> (Vec<ServiceInstance>, Vec<ServiceInstance>) {
+        let mut lost = Vec::new();
+        self.known.retain(|instance, state| {
+            if seen.contains(instance) {
+                state.missed = 0;
+                state.unanswered = None;
+                return true;
+            }
+            state.missed += 1;
+            if state.missed >= MISSED_ROUNDS_UNTIL_LOST {
+                lost.push(instance.clone());
+                false
+            } else {
+                true
+            }
+        });
+
+        let mut found = Vec::new();
+        for instance in seen {
+            if !self.known.contains_key(&instance) {
+                self.known
+                    .insert(instance.clone(), InstanceState::default());
+                found.push(instance);
+            }
+        }
+        (found, lost)
+    }
+
+    /// Forgets all instances, and returns them.
+    fn clear(&mut self) -> Vec<ServiceInstance> {
+        let known = std::mem::take(&mut self.known);
+        known.into_keys().collect()
+    }
+
+    /// Stores the resolved details of `instance`.
+    ///
+    /// Returns whether the details differ from the previously stored ones.
+    fn set_details(&mut self, instance: &ServiceInstance, details: InstanceDetails) -> bool {
+        let Some(state) = self.known.get_mut(instance) else {
+            return false;
+        };
+        state.stale = false;
+        let changed = state.details.as_ref().is_some_and(|old| *old != details);
+        state.details = Some(details);
+        changed
+    }
+
+    /// Returns the instances that need to be resolved again, and resets their stale flag.
+    fn take_stale(&mut self) -> Vec<ServiceInstance> {
+        let mut stale = Vec::new();
+        for (instance, state) in &mut self.known {
+            if state.stale {
+                state.stale = false;
+                stale.push(instance.clone());
+            }
+        }
+        stale
+    }
+
+    /// Processes an mDNS packet sent by another host.
+    ///
+    /// Queries for the instances of the service count as unanswered for every known instance they
+    /// don't list as a known answer, until a response containing the instance is observed.
+    ///
+    /// Responses containing cache-flush records that contradict the resolved details of an
+    /// instance mark the instance as stale (see [`InstanceTracker::take_stale`]).
+    fn observe_packet(
+        &mut self,
+        service_domain: &DomainName,
+        packet: &[u8],
+        now: Instant,
+    ) -> Result<(), Error> {
+        let mut dec = MessageDecoder::new(packet)?;
+        if dec.header().is_query() {
+            let mut asks_for_instances = false;
+            for q in dec.iter() {
+                let q = q?;
+                // Unicast responses can't be observed.
+                asks_for_instances |= !q.prefers_unicast()
+                    && q.qname() == service_domain
+                    && q.qtype().matches(Type::PTR)
+                    && q.qclass().matches(Class::IN);
+            }
+            if !asks_for_instances {
+                return Ok(());
+            }
+
+            let mut known_answers = BTreeSet::new();
+            let mut dec = dec.answers()?;
+            for rr in dec.iter() {
+                known_answers.extend(ptr_instance(&rr?, service_domain));
+            }
+            for (instance, state) in &mut self.known {
+                if !known_answers.contains(instance) {
+                    state.unanswered.get_or_insert((now, 0)).1 += 1;
+                }
+            }
+        } else {
+            let mut answered = Vec::new();
+            let mut dec = dec.answers()?;
+            for rr in dec.iter() {
+                let rr = rr?;
+                answered.extend(ptr_instance(&rr, service_domain));
+                self.check_stale(&rr);
+            }
+            let mut dec = dec.additional()?;
+            for rr in dec.iter() {
+                let rr = rr?;
+                answered.extend(ptr_instance(&rr, service_domain));
+                self.check_stale(&rr);
+            }
+            for instance in answered {
+                if let Some(state) = self.known.get_mut(&instance) {
+                    state.unanswered = None;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Marks instances as stale if `rr` is a cache-flush record that doesn't match their details.
+    fn check_stale(&mut self, rr: &ResourceRecord<'_>) {
+        if !rr.cache_flush() {
+            return;
+        }
+        let Some(Ok(record)) = rr.as_enum() else {
+            return;
+        };
+        for (instance, state) in &mut self.known {
+            let Some(details) = &state.details else {
+                continue;
+            };
+            let contradicts = match &record {
+                Record::SRV(srv) if rr.name() == &instance_domain(instance) => {
+                    !details.targets().iter().any(|target| {
+                        target.host() == srv.target()
+                            && target.port() == srv.port()
+                            && target.priority() == srv.priority()
+                            && target.weight() == srv.weight()
+                    })
+                }
+                Record::TXT(txt) if rr.name() == &instance_domain(instance) => {
+                    TxtRecords::from_txt(txt) != *details.txt_records()
+                }
+                Record::A(a) => details.targets().iter().any(|target| {
+                    target.host() == rr.name()
+                        && !target.addrs().is_empty()
+                        && !target.addrs().contains(&IpAddr::V4(a.addr()))
+                }),
+                Record::AAAA(aaaa) => details.targets().iter().any(|target| {
+                    target.host() == rr.name()
+                        && !target.addrs().is_empty()
+                        && !target.addrs().contains(&IpAddr::V6(aaaa.addr()))
+                }),
+                _ => false,
+            };
+            if contradicts {
+                log::debug!("{} changed: {}", instance, record);
+                state.stale = true;
+            }
+        }
+    }
+
+    /// Removes and returns the instances that failed to answer the queries observed by
+    /// [`InstanceTracker::observe_packet`].
+    fn expire_unanswered(&mut self, now: Instant) -> Vec<ServiceInstance> {
+        let mut lost = Vec::new();
+        self.known.retain(|instance, state| match state.unanswered {
+            Some((first, count))
+                if count >= POOF_QUERIES
+                    && now.saturating_duration_since(first) >= POOF_TIMEOUT =>
+            {
+                lost.push(instance.clone());
+                false
+            }
+            _ => true,
+        });
+        lost
+    }
+}
+
+/// Returns the fully qualified domain name of `instance` in the `.local` domain.
+fn instance_domain(instance: &ServiceInstance) -> DomainName {
+    DomainName::from_iter([
+        instance.instance_name().clone(),
+        instance.service_name().clone(),
+        instance.service_transport().to_label(),
+        label!("local"),
+    ])
+}
+
+/// Returns the service instance a `PTR` record for `service_domain` points to.
+fn ptr_instance(rr: &ResourceRecord<'_>, service_domain: &DomainName) -> Option<ServiceInstance> {
+    if rr.name() != service_domain {
+        return None;
+    }
+    match rr.as_enum() {
+        Some(Ok(Record::PTR(ptr))) => ServiceInstance::from_ptr(ptr).ok(),
+        _ => None,
+    }
+}
+
+/// Determines the local IPv4 address used to send mDNS traffic.
+fn local_ipv4() -> io::Result<Ipv4Addr> {
+    // Connecting a UDP socket doesn't send anything, but makes the OS pick a route and source
+    // address for it.
+    let sock = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    sock.connect((Ipv4Addr::new(224, 0, 0, 251), 5353))?;
+    match sock.local_addr()?.ip() {
+        IpAddr::V4(ip) if !ip.is_unspecified() => Ok(ip),
+        _ => Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "no local IPv4 address found",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        domain,
+        packet::{
+            encoder::{self, MessageEncoder},
+            records::{PTR, SRV},
+            Header, QType,
+        },
+        service::ServiceTransport,
+    };
+
+    use super::*;
+
+    fn instance(name: &str) -> ServiceInstance {
+        ServiceInstance::new(Label::new(name), Label::new("_http"), ServiceTransport::TCP)
+    }
+
+    #[test]
+    fn tracks_instances() {
+        let mut tracker = InstanceTracker::default();
+        let (a, b) = (instance("a"), instance("b"));
+
+        let (found, lost) = tracker.update([a.clone(), b.clone()].into());
+        assert_eq!(found, [a.clone(), b.clone()]);
+        assert!(lost.is_empty());
+
+        // `b` goes missing, but is only considered lost after several rounds.
+        let (found, lost) = tracker.update([a.clone()].into());
+        assert!(found.is_empty());
+        assert!(lost.is_empty());
+        let (_, lost) = tracker.update([a.clone()].into());
+        assert_eq!(lost, std::slice::from_ref(&b));
+
+        // Missing rounds are reset when the instance reappears.
+        let (found, _) = tracker.update([a.clone(), b.clone()].into());
+        assert_eq!(found, std::slice::from_ref(&b));
+        tracker.update([b].into());
+        let (_, lost) = tracker.update([a].into());
+        assert!(lost.is_empty());
+    }
+
+    /// Encodes a PTR query for `service`, listing `known` as known answers.
+    fn query(service: &DomainName, known: &[&ServiceInstance]) -> Vec<u8> {
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf);
+        enc.question(encoder::Question::new(service).ty(QType::PTR));
+        let mut enc = enc.answers();
+        for instance in known {
+            let ptr = Record::PTR(PTR::new(instance_domain(instance)));
+            enc.add_answer(encoder::ResourceRecord::new(service, &ptr).ttl(120));
+        }
+        let len = enc.finish().unwrap();
+        buf[..len].to_vec()
+    }
+
+    /// Encodes a response with a PTR record for `instance`.
+    fn response(service: &DomainName, instance: &ServiceInstance) -> Vec<u8> {
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf);
+        let mut header = Header::default();
+        header.set_response(true);
+        enc.set_header(header);
+        let mut enc = enc.answers();
+        let ptr = Record::PTR(PTR::new(instance_domain(instance)));
+        enc.add_answer(encoder::ResourceRecord::new(service, &ptr).ttl(120));
+        let len = enc.finish().unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn registration_probes_and_says_goodbye() {
+        // The "network" is a unicast socket that receives everything the advertiser multicasts,
+        // and plays another host that already uses the instance name.
+        let net = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        net.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let sock = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let adv_addr = sock.local_addr().unwrap();
+
+        let original = instance("Printer");
+        let mut adv = Advertiser::new(label!("host"), Ipv4Addr::LOCALHOST.into()).unwrap();
+        let details = InstanceDetails::new(domain!("host.local"), 631);
+        adv.add_instance(original.clone(), details);
+
+        let (sender, events) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let (original, stop) = (original.clone(), stop.clone());
+            let dest = net.local_addr().unwrap();
+            thread::spawn(move || advertise(adv, &original, &sock, dest, &sender, &stop).unwrap())
+        };
+
+        let mut buf = [0; MDNS_BUFFER_SIZE];
+        let len = net.recv(&mut buf).unwrap();
+        let dec = MessageDecoder::new(&buf[..len]).unwrap();
+        assert!(dec.header().is_query(), "expected a probe");
+        assert!(events.try_recv().is_err(), "registered before probing");
+
+        // Claim the name with a different `SRV` record.
+        let mut enc = MessageEncoder::new(&mut buf);
+        let mut header = Header::default();
+        header.set_response(true);
+        enc.set_header(header);
+        let mut enc = enc.answers();
+        let other = domain!("other.local");
+        let srv = Record::SRV(SRV::new(0, 0, 80, &other));
+        let domain = instance_domain(&original);
+        enc.add_answer(encoder::ResourceRecord::new(&domain, &srv).ttl(120));
+        let len = enc.finish().unwrap();
+        net.send_to(&buf[..len], adv_addr).unwrap();
+
+        let renamed = instance("Printer (2)");
+        let timeout = Duration::from_secs(5);
+        match events.recv_timeout(timeout).unwrap() {
+            RegistrationEvent::Conflict(instance) => assert_eq!(instance, renamed),
+            event => panic!("unexpected event {:?}", event),
+        }
+        match events.recv_timeout(timeout).unwrap() {
+            RegistrationEvent::Registered(instance) => assert_eq!(instance, renamed),
+            event => panic!("unexpected event {:?}", event),
+        }
+        stop.store(true, Ordering::Relaxed);
+        thread.join().unwrap();
+
+        // Everything sent after the conflict: 3 probes for the new name, the announcement, and
+        // finally the goodbye.
+        let renamed_domain = instance_domain(&renamed);
+        let mut probes = 0;
+        let mut goodbye = false;
+        while let Ok(len) = net.recv(&mut buf) {
+            let mut dec = MessageDecoder::new(&buf[..len]).unwrap();
+            if dec.header().is_query() {
+                let probed = dec.iter().any(|q| q.unwrap().qname() == &renamed_domain);
+                probes += usize::from(probed);
+                continue;
+            }
+            let mut answers = dec.answers().unwrap();
+            let ttls = answers
+                .iter()
+                .map(|rr| rr.unwrap().ttl())
+                .collect::<Vec<_>>();
+            if ttls.iter().all(|&ttl| ttl == 0) {
+                goodbye = true;
+                break;
+            }
+        }
+        assert_eq!(probes, 3);
+        assert!(goodbye, "no goodbye packet was sent");
+    }
+
+    #[test]
+    fn clears_instances() {
+        let mut tracker = InstanceTracker::default();
+        let (a, b) = (instance("a"), instance("b"));
+        tracker.update([a.clone(), b.clone()].into());
+        assert_eq!(tracker.clear(), [a.clone(), b]);
+        assert!(tracker.clear().is_empty());
+
+        // Instances are found again after being cleared.
+        let (found, lost) = tracker.update([a.clone()].into());
+        assert_eq!(found, [a]);
+        assert!(lost.is_empty());
+    }
+
+    #[test]
+    fn passive_observation_of_failures() {
+        let service = domain!("_http._tcp.local");
+        let mut tracker = InstanceTracker::default();
+        let (a, b) = (instance("a"), instance("b"));
+        tracker.update([a.clone(), b.clone()].into());
+
+        let start = Instant::now();
+        let secs = |s| start + Duration::from_secs(s);
+
+        // `a` is listed as a known answer, so only `b` is expected to answer.
+        tracker
+            .observe_packet(&service, &query(&service, &[&a]), secs(0))
+            .unwrap();
+        tracker
+            .observe_packet(&service, &query(&service, &[&a]), secs(2))
+            .unwrap();
+        assert!(tracker.expire_unanswered(secs(9)).is_empty());
+        assert_eq!(tracker.expire_unanswered(secs(10)), [b]);
+
+        // Responses reset the state.
+        tracker
+            .observe_packet(&service, &query(&service, &[]), secs(20))
+            .unwrap();
+        tracker
+            .observe_packet(&service, &query(&service, &[]), secs(21))
+            .unwrap();
+        tracker
+            .observe_packet(&service, &response(&service, &a), secs(22))
+            .unwrap();
+        assert!(tracker.expire_unanswered(secs(40)).is_empty());
+
+        // A single unanswered query isn't enough.
+        tracker
+            .observe_packet(&service, &query(&service, &[]), secs(40))
+            .unwrap();
+        assert!(tracker.expire_unanswered(secs(60)).is_empty());
+        tracker.update([a.clone()].into());
+
+        // Queries for other services are ignored.
+        let other = domain!("_ipp._tcp.local");
+        for t in 60..65 {
+            tracker
+                .observe_packet(&service, &query(&other, &[]), secs(t))
+                .unwrap();
+        }
+        assert!(tracker.expire_unanswered(secs(80)).is_empty());
+    }
+
+    #[test]
+    fn detects_changed_details() {
+        let service = domain!("_http._tcp.local");
+        let host = domain!("host.local");
+        let a = instance("a");
+        let mut tracker = InstanceTracker::default();
+        tracker.update([a.clone()].into());
+        assert!(!tracker.set_details(&a, InstanceDetails::new(host.clone(), 80)));
+
+        let srv_response = |port, cache_flush| {
+            let mut buf = [0; 512];
+            let mut enc = MessageEncoder::new(&mut buf);
+            let mut header = Header::default();
+            header.set_response(true);
+            enc.set_header(header);
+            let mut enc = enc.answers();
+            let srv = Record::SRV(SRV::new(0, 0, port, &host));
+            let class = if cache_flush {
+                Class(Class::IN.0 | 0x8000)
+            } else {
+                Class::IN
+            };
+            let name = instance_domain(&a);
+            enc.add_answer(encoder::ResourceRecord::new(&name, &srv).class(class));
+            let len = enc.finish().unwrap();
+            buf[..len].to_vec()
+        };
+        let now = Instant::now();
+
+        // Matching records and records without the cache-flush bit don't trigger re-resolution.
+        tracker
+            .observe_packet(&service, &srv_response(80, true), now)
+            .unwrap();
+        tracker
+            .observe_packet(&service, &srv_response(8080, false), now)
+            .unwrap();
+        assert!(tracker.take_stale().is_empty());
+
+        tracker
+            .observe_packet(&service, &srv_response(8080, true), now)
+            .unwrap();
+        assert_eq!(tracker.take_stale(), std::slice::from_ref(&a));
+        assert!(tracker.take_stale().is_empty());
+
+        assert!(tracker.set_details(&a, InstanceDetails::new(host.clone(), 8080)));
+        assert!(!tracker.set_details(&a, InstanceDetails::new(host.clone(), 8080)));
+    }
+}
@@ -0,0 +1,128 @@
+//! Registration and browsing via the operating system's mDNS daemon.
+//!
+//! Running a second mDNS responder next to the system's own one works, but the two will not know
+//! about each other's records (and might disagree about who owns a name). When a system daemon is
+//! available, it is preferable to ask it to do the work instead.
+//!
+//! Two daemons are supported: Avahi (via its D-Bus API) on Linux, and Bonjour (via the `dns_sd`
+//! library) on macOS and Windows.
+
+use std::{
+    io,
+    sync::{atomic::AtomicBool, mpsc, Arc},
+    thread::JoinHandle,
+};
+
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+use crate::name::Label;
+use crate::service::{Service, ServiceInstance, TxtRecords};
+
+use super::{BrowseEvent, RegistrationEvent};
+
+#[cfg(target_os = "linux")]
+mod avahi;
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+mod bonjour;
+
+#[cfg(target_os = "linux")]
+use avahi as imp;
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+use bonjour as imp;
+
+/// Asks the system daemon to advertise `instance`.
+///
+/// Returns an error if no daemon is available. Otherwise, the returned thread reports events via
+/// `sender` until `stop` is set.
+pub(super) fn register(
+    instance: &ServiceInstance,
+    port: u16,
+    txt: &TxtRecords,
+    sender: &mpsc::Sender<RegistrationEvent>,
+    stop: &Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+    {
+        imp::register(instance, port, txt, sender.clone(), stop.clone())
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+    {
+        let _ = (instance, port, txt, sender, stop);
+        Err(unsupported())
+    }
+}
+
+/// Asks the system daemon to browse for instances of `service`.
+///
+/// Returns an error if no daemon is available. Otherwise, the returned thread reports events via
+/// `sender` until `stop` is set.
+pub(super) fn browse(
+    service: &Service,
+    sender: &mpsc::Sender<BrowseEvent>,
+    stop: &Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+    {
+        imp::browse(service, sender.clone(), stop.clone())
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+    {
+        let _ = (service, sender, stop);
+        Err(unsupported())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "no system mDNS daemon is supported on this platform",
+    )
+}
+
+/// Returns the service type in the form used by the daemon APIs (eg. `_http._tcp`).
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+fn service_type(service: &Service) -> String {
+    service.to_string()
+}
+
+/// Parses a daemon-reported instance name and service type into a [`ServiceInstance`].
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+fn parse_instance(name: &str, ty: &str) -> Option<ServiceInstance> {
+    let ty = ty.strip_suffix('.').unwrap_or(ty);
+    let (service_name, transport) = ty.split_once('.')?;
+    if !service_name.starts_with('_') {
+        return None;
+    }
+    let transport = transport.parse().ok()?;
+    Some(ServiceInstance::new(
+        Label::try_new(name).ok()?,
+        Label::try_new(service_name).ok()?,
+        transport,
+    ))
+}
+
+#[cfg(all(
+    test,
+    any(target_os = "linux", target_os = "macos", target_os = "windows")
+))]
+mod tests {
+    use crate::{label, service::ServiceTransport};
+
+    use super::*;
+
+    #[test]
+    fn service_types() {
+        let instance =
+            ServiceInstance::new(label!("My Printer"), label!("_ipp"), ServiceTransport::TCP);
+        let ty = service_type(instance.service());
+        assert_eq!(ty, "_ipp._tcp");
+
+        assert_eq!(parse_instance("My Printer", &ty), Some(instance.clone()));
+        // Bonjour reports fully qualified types.
+        assert_eq!(parse_instance("My Printer", "_ipp._tcp."), Some(instance));
+        assert_eq!(parse_instance("My Printer", "ipp._tcp"), None);
+        assert_eq!(parse_instance("My Printer", "_ipp._sctp"), None);
+        assert_eq!(parse_instance("My Printer", "_ipp"), None);
+        assert_eq!(parse_instance("", "_ipp._tcp"), None);
+    }
+}
@@ -0,0 +1,387 @@
+//! Avahi backend, using its D-Bus API.
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use async_io::{block_on, Timer};
+use futures_lite::{future, Stream, StreamExt};
+use zbus::{message, Connection, MatchRule, MessageStream, Proxy};
+
+use crate::{
+    dnssd::{BrowseEvent, RegistrationEvent, POLL_INTERVAL},
+    name::DomainName,
+    packet::records::TXT,
+    service::{InstanceDetails, Service, ServiceInstance, ServiceTarget, TxtRecords},
+};
+
+const AVAHI: &str = "org.freedesktop.Avahi";
+const SERVER: &str = "org.freedesktop.Avahi.Server";
+const ENTRY_GROUP: &str = "org.freedesktop.Avahi.EntryGroup";
+const SERVICE_BROWSER: &str = "org.freedesktop.Avahi.ServiceBrowser";
+
+/// `AVAHI_IF_UNSPEC`: all interfaces.
+const IF_UNSPEC: i32 = -1;
+/// `AVAHI_PROTO_UNSPEC`: IPv4 and IPv6.
+const PROTO_UNSPEC: i32 = -1;
+
+/// `AVAHI_ENTRY_GROUP_ESTABLISHED`
+const STATE_ESTABLISHED: i32 = 2;
+/// `AVAHI_ENTRY_GROUP_COLLISION`
+const STATE_COLLISION: i32 = 3;
+/// `AVAHI_ENTRY_GROUP_FAILURE`
+const STATE_FAILURE: i32 = 4;
+
+pub(super) fn register(
+    instance: &ServiceInstance,
+    port: u16,
+    txt: &TxtRecords,
+    sender: mpsc::Sender<RegistrationEvent>,
+    stop: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    let name = instance.instance_name().to_string_lossy().into_owned();
+    let ty = super::service_type(instance.service());
+    let txt = encode_txt(txt);
+
+    let (conn, group, mut states) = block_on(async {
+        let conn = Connection::system().await?;
+        let server = Proxy::new(&conn, AVAHI, "/", SERVER).await?;
+        let path: zbus::zvariant::OwnedObjectPath = server.call("EntryGroupNew", &()).await?;
+        let group = Proxy::new(&conn, AVAHI, path, ENTRY_GROUP).await?;
+        // Subscribe before committing the group, so that no state change is missed.
+        let states = group.receive_signal("StateChanged").await?;
+        group
+            .call_method(
+                "AddService",
+                &(
+                    IF_UNSPEC,
+                    PROTO_UNSPEC,
+                    0u32,
+                    &name,
+                    &ty,
+                    "",
+                    "",
+                    port,
+                    &txt,
+                ),
+            )
+            .await?;
+        group.call_method("Commit", &()).await?;
+        zbus::Result::Ok((conn, group, states))
+    })
+    .map_err(dbus_error)?;
+
+    let instance = instance.clone();
+    Ok(thread::spawn(move || {
+        let res = block_on(async {
+            while let Some(msg) = next_or_stop(&mut states, &stop).await {
+                let (state, error): (i32, String) = msg.body().deserialize()?;
+                let Some(event) = registration_event(&instance, state, &error) else {
+                    continue;
+                };
+                let failed = matches!(event, RegistrationEvent::Failed(_));
+                if sender.send(event).is_err() || failed {
+                    break;
+                }
+            }
+            group.call_method("Free", &()).await?;
+            zbus::Result::Ok(())
+        });
+        if let Err(e) = res {
+            log::debug!("error while talking to Avahi: {}", e);
+        }
+        drop(conn);
+    }))
+}
+
+/// Converts `txt` to the list of entries expected by `AddService`.
+fn encode_txt(txt: &TxtRecords) -> Vec<Vec<u8>> {
+    txt.to_txt().entries().map(<[u8]>::to_vec).collect()
+}
+
+/// Returns the event to report when an entry group enters `state`.
+///
+/// `error` is the error message sent along with the state. Returns [`None`] for intermediate
+/// states that aren't reported.
+fn registration_event(
+    instance: &ServiceInstance,
+    state: i32,
+    error: &str,
+) -> Option<RegistrationEvent> {
+    Some(match state {
+        STATE_ESTABLISHED => RegistrationEvent::Registered(instance.clone()),
+        STATE_COLLISION => RegistrationEvent::Failed(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("service name '{}' is already in use", instance),
+        )),
+        STATE_FAILURE => RegistrationEvent::Failed(io::Error::other(format!(
+            "Avahi failed to register service: {}",
+            error
+        ))),
+        _ => return None,
+    })
+}
+
+pub(super) fn browse(
+    service: &Service,
+    sender: mpsc::Sender<BrowseEvent>,
+    stop: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    let ty = super::service_type(service);
+
+    let (conn, server, browser, mut signals) = block_on(async {
+        let conn = Connection::system().await?;
+        // Older Avahi versions start emitting signals as soon as the browser is created, so we
+        // have to subscribe to them before we know the browser's object path.
+        let rule = MatchRule::builder()
+            .msg_type(message::Type::Signal)
+            .sender(AVAHI)?
+            .interface(SERVICE_BROWSER)?
+            .build();
+        let signals = MessageStream::for_match_rule(rule, &conn, None).await?;
+        let server = Proxy::new(&conn, AVAHI, "/", SERVER).await?;
+        let path: zbus::zvariant::OwnedObjectPath = server
+            .call(
+                "ServiceBrowserNew",
+                &(IF_UNSPEC, PROTO_UNSPEC, &ty, "", 0u32),
+            )
+            .await?;
+        let browser = Proxy::new(&conn, AVAHI, path, SERVICE_BROWSER).await?;
+        zbus::Result::Ok((conn, server, browser, signals))
+    })
+    .map_err(dbus_error)?;
+
+    Ok(thread::spawn(move || {
+        let res = block_on(async {
+            // The same instance is reported once for every interface and protocol it is seen on.
+            let mut seen = BTreeMap::<ServiceInstance, u32>::new();
+            while let Some(msg) = next_or_stop(&mut signals, &stop).await {
+                let msg = msg?;
+                let header = msg.header();
+                if header.path().map(|p| p.as_str()) != Some(browser.path().as_str()) {
+                    continue;
+                }
+                let event = match header.member().map(|m| m.as_str()) {
+                    Some("ItemNew") => {
+                        let item: Item = msg.body().deserialize()?;
+                        let Some(instance) = super::parse_instance(&item.2, &item.3) else {
+                            continue;
+                        };
+                        let count = seen.entry(instance.clone()).or_default();
+                        *count += 1;
+                        if *count != 1 {
+                            continue;
+                        }
+                        if sender.send(BrowseEvent::Found(instance.clone())).is_err() {
+                            break;
+                        }
+                        match resolve(&server, &item).await {
+                            Ok(details) => BrowseEvent::Resolved(instance, details),
+                            Err(e) => {
+                                log::debug!("failed to resolve {}: {}", instance, e);
+                                continue;
+                            }
+                        }
+                    }
+                    Some("ItemRemove") => {
+                        let item: Item = msg.body().deserialize()?;
+                        let Some(instance) = super::parse_instance(&item.2, &item.3) else {
+                            continue;
+                        };
+                        let Some(count) = seen.get_mut(&instance) else {
+                            continue;
+                        };
+                        *count -= 1;
+                        if *count != 0 {
+                            continue;
+                        }
+                        seen.remove(&instance);
+                        BrowseEvent::Lost(instance)
+                    }
+                    Some("Failure") => {
+                        let (error,): (String,) = msg.body().deserialize()?;
+                        sender
+                            .send(BrowseEvent::Failed(io::Error::other(format!(
+                                "Avahi service browser failed: {}",
+                                error
+                            ))))
+                            .ok();
+                        break;
+                    }
+                    _ => continue,
+                };
+                if sender.send(event).is_err() {
+                    break;
+                }
+            }
+            browser.call_method("Free", &()).await?;
+            zbus::Result::Ok(())
+        });
+        if let Err(e) = res {
+            log::debug!("error while talking to Avahi: {}", e);
+        }
+        drop(conn);
+    }))
+}
+
+/// Arguments of the `ItemNew` and `ItemRemove` signals: interface, protocol, name, type, domain,
+/// flags.
+type Item = (i32, i32, String, String, String, u32);
+
+async fn resolve(server: &Proxy<'_>, item: &Item) -> zbus::Result<InstanceDetails> {
+    let (interface, protocol, name, ty, domain, _) = item;
+    #[allow(clippy::type_complexity)]
+    let reply: (
+        i32,
+        i32,
+        String,
+        String,
+        String,
+        String,
+        i32,
+        String,
+        u16,
+        Vec<Vec<u8>>,
+        u32,
+    ) = server
+        .call(
+            "ResolveService",
+            &(*interface, *protocol, name, ty, domain, PROTO_UNSPEC, 0u32),
+        )
+        .await?;
+    let (.., host, _, addr, port, txt, _) = reply;
+    resolved_details(&host, &addr, port, txt)
+}
+
+/// Converts the host name, address, port and TXT entries returned by `ResolveService`.
+fn resolved_details(
+    host: &str,
+    addr: &str,
+    port: u16,
+    txt: Vec<Vec<u8>>,
+) -> zbus::Result<InstanceDetails> {
+    let host = DomainName::from_str(host)
+        .map_err(|e| zbus::Error::Failure(format!("invalid host name '{}': {}", host, e)))?;
+    let mut target = ServiceTarget::new(host, port);
+    match addr.parse() {
+        Ok(addr) => target.add_addr(addr),
+        Err(e) => log::debug!("invalid address '{}' from Avahi: {}", addr, e),
+    }
+    let mut details = InstanceDetails::from_target(target);
+    if !txt.is_empty() {
+        *details.txt_records_mut() = TxtRecords::from_txt(&TXT::new(txt));
+    }
+    Ok(details)
+}
+
+/// Waits for the next item of `stream`, or returns `None` once `stop` is set.
+async fn next_or_stop<S: Stream + Unpin>(stream: &mut S, stop: &AtomicBool) -> Option<S::Item> {
+    loop {
+        if stop.load(Ordering::Relaxed) {
+            return None;
+        }
+        let timeout = async {
+            Timer::after(POLL_INTERVAL).await;
+            None
+        };
+        if let Some(item) = future::or(wrap_some(stream.next()), timeout).await {
+            return item;
+        }
+    }
+}
+
+async fn wrap_some<T>(fut: impl Future<Output = T>) -> Option<T> {
+    Some(fut.await)
+}
+
+fn dbus_error(e: zbus::Error) -> io::Error {
+    match e {
+        zbus::Error::InputOutput(e) => io::Error::new(e.kind(), e),
+        e => io::Error::other(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use crate::{label, service::ServiceTransport};
+
+    use super::*;
+
+    #[test]
+    fn txt_entries() {
+        assert_eq!(encode_txt(&TxtRecords::new()), [b""]);
+
+        let mut txt = TxtRecords::new();
+        txt.add_value("rp".into(), "queue");
+        txt.add_flag("color".into());
+        let entries = encode_txt(&txt);
+        assert_eq!(entries, [&b"rp=queue"[..], b"color"]);
+
+        let details = resolved_details("printer.local", "192.0.2.1", 631, entries).unwrap();
+        assert_eq!(details.txt_records(), &txt);
+        let details = resolved_details("printer.local", "192.0.2.1", 631, Vec::new()).unwrap();
+        assert!(details.txt_records().is_empty());
+    }
+
+    #[test]
+    fn resolved_target() {
+        let details = resolved_details("printer.local", "192.0.2.1", 631, Vec::new()).unwrap();
+        let [target] = details.targets() else {
+            panic!("expected a single target");
+        };
+        assert_eq!(
+            target.host(),
+            &DomainName::from_str("printer.local").unwrap()
+        );
+        assert_eq!(target.port(), 631);
+        assert_eq!(target.addrs(), [IpAddr::from(Ipv4Addr::new(192, 0, 2, 1))]);
+
+        // Invalid addresses are skipped, invalid host names are errors.
+        let details = resolved_details("printer.local", "bogus", 631, Vec::new()).unwrap();
+        assert!(details.targets()[0].addrs().is_empty());
+        assert!(resolved_details("printer..local", "192.0.2.1", 631, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn registration_events() {
+        let instance = ServiceInstance::new(label!("inst"), label!("_http"), ServiceTransport::TCP);
+        match registration_event(&instance, STATE_ESTABLISHED, "") {
+            Some(RegistrationEvent::Registered(registered)) => assert_eq!(registered, instance),
+            event => panic!("unexpected event {:?}", event),
+        }
+        match registration_event(&instance, STATE_COLLISION, "") {
+            Some(RegistrationEvent::Failed(e)) => assert_eq!(e.kind(), io::ErrorKind::AddrInUse),
+            event => panic!("unexpected event {:?}", event),
+        }
+        match registration_event(&instance, STATE_FAILURE, "Not permitted") {
+            Some(RegistrationEvent::Failed(e)) => {
+                assert_eq!(e.kind(), io::ErrorKind::Other);
+                assert!(e.to_string().contains("Not permitted"), "{e}");
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+        // `AVAHI_ENTRY_GROUP_UNCOMMITED` and `AVAHI_ENTRY_GROUP_REGISTERING`
+        assert!(registration_event(&instance, 0, "").is_none());
+        assert!(registration_event(&instance, 1, "").is_none());
+    }
+
+    #[test]
+    fn dbus_errors() {
+        let io = io::Error::from(io::ErrorKind::ConnectionRefused);
+        let e = dbus_error(zbus::Error::InputOutput(Arc::new(io)));
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+
+        let e = dbus_error(zbus::Error::Failure("oh no".into()));
+        assert_eq!(e.kind(), io::ErrorKind::Other);
+        assert!(e.to_string().contains("oh no"), "{e}");
+    }
+}
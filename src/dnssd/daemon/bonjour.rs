@@ -0,0 +1,615 @@
+//! Bonjour backend, using the `dns_sd` C API.
+
+#![allow(non_camel_case_types)]
+
+use std::{
+    collections::BTreeMap,
+    ffi::{c_char, c_void, CStr, CString},
+    io, ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    dnssd::{BrowseEvent, RegistrationEvent, POLL_INTERVAL},
+    name::DomainName,
+    packet::decoder::TxtEntries,
+    packet::records::TXT,
+    service::{InstanceDetails, Service, ServiceInstance, TxtRecords},
+};
+
+/// How long to wait for the daemon to resolve a service instance.
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+type DNSServiceRef = *mut c_void;
+type DNSServiceFlags = u32;
+type DNSServiceErrorType = i32;
+
+const K_DNS_SERVICE_ERR_NO_ERROR: DNSServiceErrorType = 0;
+const K_DNS_SERVICE_ERR_NAME_CONFLICT: DNSServiceErrorType = -65548;
+const K_DNS_SERVICE_ERR_SERVICE_NOT_RUNNING: DNSServiceErrorType = -65563;
+const K_DNS_SERVICE_FLAGS_ADD: DNSServiceFlags = 0x2;
+
+#[cfg(unix)]
+type dnssd_sock_t = std::ffi::c_int;
+#[cfg(windows)]
+type dnssd_sock_t = usize;
+
+type DNSServiceRegisterReply = extern "system" fn(
+    sd_ref: DNSServiceRef,
+    flags: DNSServiceFlags,
+    error_code: DNSServiceErrorType,
+    name: *const c_char,
+    regtype: *const c_char,
+    domain: *const c_char,
+    context: *mut c_void,
+);
+
+type DNSServiceBrowseReply = extern "system" fn(
+    sd_ref: DNSServiceRef,
+    flags: DNSServiceFlags,
+    interface_index: u32,
+    error_code: DNSServiceErrorType,
+    service_name: *const c_char,
+    regtype: *const c_char,
+    reply_domain: *const c_char,
+    context: *mut c_void,
+);
+
+type DNSServiceResolveReply = extern "system" fn(
+    sd_ref: DNSServiceRef,
+    flags: DNSServiceFlags,
+    interface_index: u32,
+    error_code: DNSServiceErrorType,
+    fullname: *const c_char,
+    hosttarget: *const c_char,
+    port: u16,
+    txt_len: u16,
+    txt_record: *const u8,
+    context: *mut c_void,
+);
+
+#[cfg_attr(windows, link(name = "dnssd"))]
+extern "system" {
+    fn DNSServiceRegister(
+        sd_ref: *mut DNSServiceRef,
+        flags: DNSServiceFlags,
+        interface_index: u32,
+        name: *const c_char,
+        regtype: *const c_char,
+        domain: *const c_char,
+        host: *const c_char,
+        port: u16,
+        txt_len: u16,
+        txt_record: *const c_void,
+        callback: DNSServiceRegisterReply,
+        context: *mut c_void,
+    ) -> DNSServiceErrorType;
+
+    fn DNSServiceBrowse(
+        sd_ref: *mut DNSServiceRef,
+        flags: DNSServiceFlags,
+        interface_index: u32,
+        regtype: *const c_char,
+        domain: *const c_char,
+        callback: DNSServiceBrowseReply,
+        context: *mut c_void,
+    ) -> DNSServiceErrorType;
+
+    fn DNSServiceResolve(
+        sd_ref: *mut DNSServiceRef,
+        flags: DNSServiceFlags,
+        interface_index: u32,
+        name: *const c_char,
+        regtype: *const c_char,
+        domain: *const c_char,
+        callback: DNSServiceResolveReply,
+        context: *mut c_void,
+    ) -> DNSServiceErrorType;
+
+    fn DNSServiceRefSockFD(sd_ref: DNSServiceRef) -> dnssd_sock_t;
+    fn DNSServiceProcessResult(sd_ref: DNSServiceRef) -> DNSServiceErrorType;
+    fn DNSServiceRefDeallocate(sd_ref: DNSServiceRef);
+}
+
+/// An owned `DNSServiceRef`, deallocated on drop.
+struct ServiceRef(DNSServiceRef);
+
+// The `dns_sd` API allows using a `DNSServiceRef` from any thread, as long as it isn't used
+// concurrently.
+unsafe impl Send for ServiceRef {}
+
+impl ServiceRef {
+    /// Creates a [`ServiceRef`] by calling `f`, which is expected to initialize it.
+    fn new(f: impl FnOnce(*mut DNSServiceRef) -> DNSServiceErrorType) -> io::Result<Self> {
+        let mut sd_ref = ptr::null_mut();
+        check(f(&mut sd_ref))?;
+        Ok(Self(sd_ref))
+    }
+
+    /// Waits up to `timeout` for a reply from the daemon, and invokes the corresponding callback.
+    ///
+    /// Returns `false` if no reply arrived in time.
+    fn process(&self, timeout: Duration) -> io::Result<bool> {
+        let fd = unsafe { DNSServiceRefSockFD(self.0) };
+        if !sys::wait_readable(fd, timeout)? {
+            return Ok(false);
+        }
+        check(unsafe { DNSServiceProcessResult(self.0) })?;
+        Ok(true)
+    }
+}
+
+impl Drop for ServiceRef {
+    fn drop(&mut self) {
+        unsafe { DNSServiceRefDeallocate(self.0) }
+    }
+}
+
+/// Heap-allocated context data passed to `dns_sd` callbacks.
+///
+/// The data is only accessed through a raw pointer, since the callbacks write to it while other
+/// references to it might exist. It must outlive the [`ServiceRef`] it is passed to.
+struct Context<T>(*mut T);
+
+unsafe impl<T: Send> Send for Context<T> {}
+
+impl<T> Context<T> {
+    fn new(value: T) -> Self {
+        Self(Box::into_raw(Box::new(value)))
+    }
+
+    fn as_ptr(&self) -> *mut c_void {
+        self.0.cast()
+    }
+
+    /// Accesses the context data. Must not be called while a callback might be running.
+    fn get(&mut self) -> &mut T {
+        unsafe { &mut *self.0 }
+    }
+}
+
+impl<T> Drop for Context<T> {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(self.0) });
+    }
+}
+
+fn check(err: DNSServiceErrorType) -> io::Result<()> {
+    match err {
+        K_DNS_SERVICE_ERR_NO_ERROR => Ok(()),
+        K_DNS_SERVICE_ERR_NAME_CONFLICT => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "service name is already in use",
+        )),
+        K_DNS_SERVICE_ERR_SERVICE_NOT_RUNNING => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "the Bonjour service is not running",
+        )),
+        err => Err(io::Error::other(format!("Bonjour error {}", err))),
+    }
+}
+
+fn cstring(s: impl Into<Vec<u8>>) -> io::Result<CString> {
+    CString::new(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// Converts a C string passed to a callback. `ptr` must be null or point to a valid C string.
+unsafe fn from_c(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(ptr).to_string_lossy().into_owned()
+}
+
+struct RegisterContext {
+    instance: ServiceInstance,
+    sender: mpsc::Sender<RegistrationEvent>,
+    failed: bool,
+}
+
+extern "system" fn register_reply(
+    _: DNSServiceRef,
+    _: DNSServiceFlags,
+    error_code: DNSServiceErrorType,
+    name: *const c_char,
+    _: *const c_char,
+    _: *const c_char,
+    context: *mut c_void,
+) {
+    let cx = unsafe { &mut *context.cast::<RegisterContext>() };
+    let event = match check(error_code) {
+        Ok(()) => {
+            // The daemon may have renamed the instance to resolve a conflict.
+            let name = unsafe { from_c(name) };
+            let instance = super::parse_instance(&name, &cx.instance.service().to_string())
+                .unwrap_or_else(|| cx.instance.clone());
+            RegistrationEvent::Registered(instance)
+        }
+        Err(e) => {
+            cx.failed = true;
+            RegistrationEvent::Failed(e)
+        }
+    };
+    cx.sender.send(event).ok();
+}
+
+pub(super) fn register(
+    instance: &ServiceInstance,
+    port: u16,
+    txt: &TxtRecords,
+    sender: mpsc::Sender<RegistrationEvent>,
+    stop: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    let name = cstring(instance.instance_name().as_bytes())?;
+    let ty = cstring(super::service_type(instance.service()))?;
+    let txt_record = encode_txt(txt)?;
+    // `encode_txt` ensures that this fits.
+    let txt_len = txt_record.len() as u16;
+
+    let mut cx = Context::new(RegisterContext {
+        instance: instance.clone(),
+        sender,
+        failed: false,
+    });
+    let sd_ref = ServiceRef::new(|sd_ref| unsafe {
+        DNSServiceRegister(
+            sd_ref,
+            0,
+            0,
+            name.as_ptr(),
+            ty.as_ptr(),
+            ptr::null(),
+            ptr::null(),
+            port.to_be(),
+            txt_len,
+            txt_record.as_ptr().cast(),
+            register_reply,
+            cx.as_ptr(),
+        )
+    })?;
+
+    Ok(thread::spawn(move || {
+        while !stop.load(Ordering::Relaxed) && !cx.get().failed {
+            if let Err(e) = sd_ref.process(POLL_INTERVAL) {
+                cx.get().sender.send(RegistrationEvent::Failed(e)).ok();
+                break;
+            }
+        }
+        drop(sd_ref);
+        drop(cx);
+    }))
+}
+
+/// Encodes `txt` in the wire format expected by `DNSServiceRegister`.
+fn encode_txt(txt: &TxtRecords) -> io::Result<Vec<u8>> {
+    let mut txt_record = Vec::new();
+    for entry in txt.to_txt().entries() {
+        let len = u8::try_from(entry.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "TXT entry too long"))?;
+        txt_record.push(len);
+        txt_record.extend_from_slice(entry);
+    }
+    if u16::try_from(txt_record.len()).is_err() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "TXT record too long",
+        ));
+    }
+    Ok(txt_record)
+}
+
+/// An instance reported by the browse callback.
+struct BrowseResult {
+    added: bool,
+    interface_index: u32,
+    name: String,
+    ty: String,
+    domain: String,
+}
+
+extern "system" fn browse_reply(
+    _: DNSServiceRef,
+    flags: DNSServiceFlags,
+    interface_index: u32,
+    error_code: DNSServiceErrorType,
+    service_name: *const c_char,
+    regtype: *const c_char,
+    reply_domain: *const c_char,
+    context: *mut c_void,
+) {
+    let results = unsafe { &mut *context.cast::<Vec<io::Result<BrowseResult>>>() };
+    results.push(check(error_code).map(|()| unsafe {
+        BrowseResult {
+            added: flags & K_DNS_SERVICE_FLAGS_ADD != 0,
+            interface_index,
+            name: from_c(service_name),
+            ty: from_c(regtype),
+            domain: from_c(reply_domain),
+        }
+    }));
+}
+
+pub(super) fn browse(
+    service: &Service,
+    sender: mpsc::Sender<BrowseEvent>,
+    stop: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    let ty = cstring(super::service_type(service))?;
+
+    let mut results = Context::new(Vec::<io::Result<BrowseResult>>::new());
+    let sd_ref = ServiceRef::new(|sd_ref| unsafe {
+        DNSServiceBrowse(
+            sd_ref,
+            0,
+            0,
+            ty.as_ptr(),
+            ptr::null(),
+            browse_reply,
+            results.as_ptr(),
+        )
+    })?;
+
+    Ok(thread::spawn(move || {
+        if let Err(e) = run_browser(&sd_ref, &mut results, &sender, &stop) {
+            log::error!("browsing via Bonjour failed: {}", e);
+            sender.send(BrowseEvent::Failed(e)).ok();
+        }
+        drop(sd_ref);
+        drop(results);
+    }))
+}
+
+fn run_browser(
+    sd_ref: &ServiceRef,
+    results: &mut Context<Vec<io::Result<BrowseResult>>>,
+    sender: &mpsc::Sender<BrowseEvent>,
+    stop: &AtomicBool,
+) -> io::Result<()> {
+    // The same instance is reported once for every interface it is seen on.
+    let mut seen = BTreeMap::<ServiceInstance, u32>::new();
+    while !stop.load(Ordering::Relaxed) {
+        sd_ref.process(POLL_INTERVAL)?;
+        for result in std::mem::take(results.get()) {
+            let result = result?;
+            let Some(instance) = super::parse_instance(&result.name, &result.ty) else {
+                continue;
+            };
+            let event = if result.added {
+                let count = seen.entry(instance.clone()).or_default();
+                *count += 1;
+                if *count != 1 {
+                    continue;
+                }
+                if sender.send(BrowseEvent::Found(instance.clone())).is_err() {
+                    return Ok(());
+                }
+                match resolve(&result) {
+                    Ok(details) => BrowseEvent::Resolved(instance, details),
+                    Err(e) => {
+                        log::debug!("failed to resolve {}: {}", instance, e);
+                        continue;
+                    }
+                }
+            } else {
+                let Some(count) = seen.get_mut(&instance) else {
+                    continue;
+                };
+                *count -= 1;
+                if *count != 0 {
+                    continue;
+                }
+                seen.remove(&instance);
+                BrowseEvent::Lost(instance)
+            };
+            if sender.send(event).is_err() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+extern "system" fn resolve_reply(
+    _: DNSServiceRef,
+    _: DNSServiceFlags,
+    _: u32,
+    error_code: DNSServiceErrorType,
+    _: *const c_char,
+    hosttarget: *const c_char,
+    port: u16,
+    txt_len: u16,
+    txt_record: *const u8,
+    context: *mut c_void,
+) {
+    let result = unsafe { &mut *context.cast::<Option<io::Result<InstanceDetails>>>() };
+    *result = Some(check(error_code).and_then(|()| {
+        let host = unsafe { from_c(hosttarget) };
+        let txt = if txt_record.is_null() {
+            &[][..]
+        } else {
+            unsafe { std::slice::from_raw_parts(txt_record, txt_len.into()) }
+        };
+        resolved_details(&host, port, txt)
+    }));
+}
+
+/// Converts the host name, port and TXT record passed to `resolve_reply`.
+///
+/// Like all ports in the `dns_sd` API, `port` is in network byte order.
+fn resolved_details(host: &str, port: u16, txt: &[u8]) -> io::Result<InstanceDetails> {
+    let host =
+        DomainName::from_str(host).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut details = InstanceDetails::new(host, u16::from_be(port));
+    let entries = TxtEntries::new(txt)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if !entries.is_empty() {
+        *details.txt_records_mut() = TxtRecords::from_txt(&TXT::new(entries));
+    }
+    Ok(details)
+}
+
+fn resolve(result: &BrowseResult) -> io::Result<InstanceDetails> {
+    let name = cstring(result.name.as_str())?;
+    let ty = cstring(result.ty.as_str())?;
+    let domain = cstring(result.domain.as_str())?;
+
+    let mut details = Context::new(None::<io::Result<InstanceDetails>>);
+    let sd_ref = ServiceRef::new(|sd_ref| unsafe {
+        DNSServiceResolve(
+            sd_ref,
+            0,
+            result.interface_index,
+            name.as_ptr(),
+            ty.as_ptr(),
+            domain.as_ptr(),
+            resolve_reply,
+            details.as_ptr(),
+        )
+    })?;
+
+    let deadline = Instant::now() + RESOLVE_TIMEOUT;
+    while details.get().is_none() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() || !sd_ref.process(remaining)? {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Bonjour did not resolve the service in time",
+            ));
+        }
+    }
+    drop(sd_ref);
+    details.get().take().unwrap()
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::{
+        ffi::{c_int, c_short, c_uint},
+        io,
+        time::Duration,
+    };
+
+    #[repr(C)]
+    struct pollfd {
+        fd: c_int,
+        events: c_short,
+        revents: c_short,
+    }
+
+    const POLLIN: c_short = 0x1;
+
+    extern "C" {
+        fn poll(fds: *mut pollfd, nfds: c_uint, timeout: c_int) -> c_int;
+    }
+
+    pub(super) fn wait_readable(fd: c_int, timeout: Duration) -> io::Result<bool> {
+        let mut fds = pollfd {
+            fd,
+            events: POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.as_millis().try_into().unwrap_or(c_int::MAX);
+        match unsafe { poll(&mut fds, 1, timeout) } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => Ok(false),
+            _ => Ok(true),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::{ffi::c_int, io, time::Duration};
+
+    #[repr(C)]
+    struct WSAPOLLFD {
+        fd: usize,
+        events: i16,
+        revents: i16,
+    }
+
+    const POLLRDNORM: i16 = 0x100;
+
+    #[link(name = "ws2_32")]
+    extern "system" {
+        fn WSAPoll(fds: *mut WSAPOLLFD, nfds: u32, timeout: c_int) -> c_int;
+    }
+
+    pub(super) fn wait_readable(fd: usize, timeout: Duration) -> io::Result<bool> {
+        let mut fds = WSAPOLLFD {
+            fd,
+            events: POLLRDNORM,
+            revents: 0,
+        };
+        let timeout = timeout.as_millis().try_into().unwrap_or(c_int::MAX);
+        match unsafe { WSAPoll(&mut fds, 1, timeout) } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => Ok(false),
+            _ => Ok(true),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn txt_record() {
+        assert_eq!(encode_txt(&TxtRecords::new()).unwrap(), [0]);
+
+        let mut txt = TxtRecords::new();
+        txt.add_value("rp".into(), "queue");
+        txt.add_flag("color".into());
+        let record = encode_txt(&txt).unwrap();
+        assert_eq!(record, b"\x08rp=queue\x05color");
+
+        let details = resolved_details("printer.local.", 631u16.to_be(), &record).unwrap();
+        assert_eq!(details.txt_records(), &txt);
+        let details = resolved_details("printer.local.", 631u16.to_be(), &[]).unwrap();
+        assert!(details.txt_records().is_empty());
+        // A length byte pointing past the end of the record.
+        let err = resolved_details("printer.local.", 631u16.to_be(), b"\x09rp").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut txt = TxtRecords::new();
+        txt.add_value("long".into(), vec![b'a'; 300]);
+        let err = encode_txt(&txt).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn port_byte_order() {
+        let port = 8080u16.to_be();
+        let details = resolved_details("printer.local.", port, &[]).unwrap();
+        assert_eq!(details.port(), 8080);
+        assert_eq!(
+            details.host(),
+            &DomainName::from_str("printer.local").unwrap()
+        );
+    }
+
+    #[test]
+    fn error_codes() {
+        assert!(check(K_DNS_SERVICE_ERR_NO_ERROR).is_ok());
+        let kind = |err| check(err).unwrap_err().kind();
+        assert_eq!(
+            kind(K_DNS_SERVICE_ERR_NAME_CONFLICT),
+            io::ErrorKind::AddrInUse
+        );
+        assert_eq!(
+            kind(K_DNS_SERVICE_ERR_SERVICE_NOT_RUNNING),
+            io::ErrorKind::NotFound
+        );
+        // `kDNSServiceErr_Unknown`
+        let e = check(-65537).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::Other);
+        assert!(e.to_string().contains("-65537"), "{e}");
+    }
+}
@@ -0,0 +1,542 @@
+//! DNS Stateful Operations ([RFC 8490]) sessions.
+//!
+//! A DSO session is a long-lived TCP (or TLS) connection to a DNS server, which is established by
+//! exchanging [`Keepalive`] TLVs. Once established, the server dictates how long the connection may
+//! stay idle and how often the client has to send traffic to keep it open. Sessions are the
+//! foundation for features like DNS Push Notifications.
+//!
+//! [`DsoSession`] implements the client side of the session state machine without performing any
+//! I/O. [`SyncDsoSession`] drives it over a blocking stream.
+//!
+//! [RFC 8490]: https://datatracker.ietf.org/doc/html/rfc8490
+
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    hex::Hex,
+    packet::{
+        decoder::MessageDecoder,
+        dso::{DsoDecoder, DsoEncoder, DsoType, Keepalive, RetryDelay, Tlv},
+        Header, Opcode, RCode,
+    },
+    Error,
+};
+
+/// Largest message that can be sent over a stream transport (limited by the 16-bit length
+/// prefix).
+const MAX_MESSAGE_SIZE: usize = u16::MAX as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// The connection is open, but no DSO session has been requested yet.
+    Connected,
+    /// A Keepalive request has been sent, and we're waiting for the response.
+    Establishing { id: u16 },
+    /// The server has accepted the session.
+    Established,
+    /// The server has asked us to close the connection, or rejected the session.
+    Closed,
+}
+
+/// Events produced by a [`DsoSession`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SessionEvent {
+    /// The server has accepted the session.
+    Established,
+    /// The server does not support DSO, or refused to establish a session.
+    ///
+    /// The connection may still be used for regular DNS messages.
+    Rejected(RCode),
+    /// The server changed the session timeouts.
+    TimeoutsChanged,
+    /// The server has asked the client to close the connection, and not to reconnect for the
+    /// given amount of time.
+    RetryDelay(Duration),
+    /// The session has been idle for longer than the inactivity timeout, and should be closed.
+    InactivityTimeout,
+    /// A non-DSO message was received over the connection.
+    Message(Vec<u8>),
+}
+
+/// Client-side state of a DSO session.
+///
+/// This type does not perform any I/O. Received messages are passed to
+/// [`DsoSession::handle_message`], and messages that need to be sent are retrieved via
+/// [`DsoSession::poll_transmit`]. The session relies on [`DsoSession::handle_timeout`] being
+/// called at the time returned by [`DsoSession::next_timeout`] to send keepalive traffic.
+///
+/// The inactivity timeout is measured from the last non-DSO message sent or received over the
+/// connection; keepalive traffic does not count as activity.
+pub struct DsoSession {
+    state: State,
+    inactivity_timeout: Option<Duration>,
+    keepalive_interval: Option<Duration>,
+    /// Time at which the last message of any kind was sent.
+    last_sent: Instant,
+    /// Time at which the last non-DSO message was sent or received.
+    last_activity: Instant,
+    next_id: u16,
+    transmit: VecDeque<Vec<u8>>,
+}
+
+impl DsoSession {
+    /// Creates the session state for a freshly opened connection.
+    pub fn new(now: Instant) -> Self {
+        Self {
+            state: State::Connected,
+            // Until the server tells us otherwise, the defaults from RFC 8490 apply.
+            inactivity_timeout: Some(Duration::from_secs(15)),
+            keepalive_interval: Some(Duration::from_secs(15)),
+            last_sent: now,
+            last_activity: now,
+            next_id: 1,
+            transmit: VecDeque::new(),
+        }
+    }
+
+    /// Returns whether the server has accepted the session.
+    #[inline]
+    pub fn is_established(&self) -> bool {
+        self.state == State::Established
+    }
+
+    /// Returns whether the connection should be closed.
+    #[inline]
+    pub fn is_closed(&self) -> bool {
+        self.state == State::Closed
+    }
+
+    /// Returns the current inactivity timeout (`None` means infinite).
+    #[inline]
+    pub fn inactivity_timeout(&self) -> Option<Duration> {
+        self.inactivity_timeout
+    }
+
+    /// Returns the current keepalive interval (`None` means infinite).
+    #[inline]
+    pub fn keepalive_interval(&self) -> Option<Duration> {
+        self.keepalive_interval
+    }
+
+    /// Queues a Keepalive request that asks the server to establish a session.
+    ///
+    /// `requested` contains the timeouts the client would like; the server has the final say.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if the session was already requested.
+    pub fn establish(&mut self, requested: Keepalive, now: Instant) {
+        assert_eq!(self.state, State::Connected, "session already requested");
+        let id = self.next_id();
+        self.queue_keepalive(id, &requested, now);
+        self.state = State::Establishing { id };
+    }
+
+    /// Returns the next message to send to the server, if any.
+    pub fn poll_transmit(&mut self) -> Option<Vec<u8>> {
+        self.transmit.pop_front()
+    }
+
+    /// Records that a non-DSO message was sent over the connection.
+    ///
+    /// Any traffic resets the keepalive interval, so this avoids sending unnecessary keepalive
+    /// messages.
+    pub fn on_message_sent(&mut self, now: Instant) {
+        self.last_sent = now;
+        self.last_activity = now;
+    }
+
+    /// Returns the time at which [`DsoSession::handle_timeout`] should be called next.
+    pub fn next_timeout(&self) -> Option<Instant> {
+        if self.state != State::Established {
+            return None;
+        }
+        let keepalive = self.keepalive_interval.map(|i| self.last_sent + i);
+        let inactivity = self.inactivity_timeout.map(|t| self.last_activity + t);
+        match (keepalive, inactivity) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Handles expired timers.
+    ///
+    /// Queues a keepalive message if the keepalive interval has elapsed, and returns
+    /// [`SessionEvent::InactivityTimeout`] if the session has been idle for too long.
+    pub fn handle_timeout(&mut self, now: Instant) -> Option<SessionEvent> {
+        if self.state != State::Established {
+            return None;
+        }
+        if let Some(timeout) = self.inactivity_timeout {
+            if now >= self.last_activity + timeout {
+                self.state = State::Closed;
+                return Some(SessionEvent::InactivityTimeout);
+            }
+        }
+        if let Some(interval) = self.keepalive_interval {
+            if now >= self.last_sent + interval {
+                let id = self.next_id();
+                let keepalive = Keepalive::new(self.inactivity_timeout, self.keepalive_interval);
+                self.queue_keepalive(id, &keepalive, now);
+            }
+        }
+        None
+    }
+
+    /// Handles a message received from the server.
+    pub fn handle_message(
+        &mut self,
+        msg: &[u8],
+        now: Instant,
+    ) -> Result<Option<SessionEvent>, Error> {
+        let header = *MessageDecoder::new(msg)?.header();
+        if header.opcode() != Opcode::DSO {
+            self.last_activity = now;
+            return Ok(Some(SessionEvent::Message(msg.to_vec())));
+        }
+
+        let mut dec = DsoDecoder::new(msg)?;
+        let primary = dec.next().transpose()?;
+
+        if header.is_response() {
+            return self.handle_response(&header, primary);
+        }
+
+        let Some(primary) = primary else {
+            return Err(Error::InvalidValue);
+        };
+        match primary.ty() {
+            DsoType::KEEPALIVE => {
+                if header.id() != 0 {
+                    // Keepalive requests may only be sent by clients.
+                    return Err(Error::InvalidValue);
+                }
+                self.apply_keepalive(&Keepalive::decode(&primary)?);
+                Ok(Some(SessionEvent::TimeoutsChanged))
+            }
+            DsoType::RETRY_DELAY => {
+                let retry = RetryDelay::decode(&primary)?;
+                self.state = State::Closed;
+                Ok(Some(SessionEvent::RetryDelay(retry.delay())))
+            }
+            ty => {
+                if header.id() != 0 {
+                    log::debug!("rejecting DSO request with unsupported type {}", ty);
+                    self.queue_response(header.id(), RCode::DSO_TYPE_NI, now);
+                } else {
+                    log::debug!("ignoring DSO message with unsupported type {}", ty);
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    fn handle_response(
+        &mut self,
+        header: &Header,
+        primary: Option<Tlv<'_>>,
+    ) -> Result<Option<SessionEvent>, Error> {
+        match self.state {
+            State::Establishing { id } if id == header.id() => {
+                if header.rcode() != RCode::NO_ERROR {
+                    self.state = State::Closed;
+                    return Ok(Some(SessionEvent::Rejected(header.rcode())));
+                }
+                // The response must contain the timeouts chosen by the server.
+                let Some(primary) = primary else {
+                    return Err(Error::InvalidValue);
+                };
+                self.apply_keepalive(&Keepalive::decode(&primary)?);
+                self.state = State::Established;
+                Ok(Some(SessionEvent::Established))
+            }
+            State::Established => {
+                // Response to a keepalive we sent; a primary TLV, if present, updates the timeouts.
+                match primary {
+                    Some(tlv) if tlv.ty() == DsoType::KEEPALIVE => {
+                        self.apply_keepalive(&Keepalive::decode(&tlv)?);
+                        Ok(None)
+                    }
+                    _ => Ok(None),
+                }
+            }
+            _ => {
+                log::debug!("ignoring unexpected DSO response (id={})", header.id());
+                Ok(None)
+            }
+        }
+    }
+
+    fn apply_keepalive(&mut self, keepalive: &Keepalive) {
+        self.inactivity_timeout = keepalive.inactivity_timeout();
+        self.keepalive_interval = keepalive.keepalive_interval();
+    }
+
+    fn next_id(&mut self) -> u16 {
+        let id = self.next_id;
+        // Message ID 0 is reserved for unidirectional messages.
+        self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+        id
+    }
+
+    fn queue_keepalive(&mut self, id: u16, keepalive: &Keepalive, now: Instant) {
+        let mut header = Header::default();
+        header.set_id(id);
+        let mut buf = [0; 64];
+        let mut enc = DsoEncoder::new(&mut buf, header);
+        enc.keepalive(keepalive);
+        let len = enc.finish().unwrap();
+        self.transmit.push_back(buf[..len].to_vec());
+        self.last_sent = now;
+    }
+
+    fn queue_response(&mut self, id: u16, rcode: RCode, now: Instant) {
+        let mut header = Header::default();
+        header.set_id(id);
+        header.set_response(true);
+        header.set_rcode(rcode);
+        let mut buf = [0; 12];
+        let len = DsoEncoder::new(&mut buf, header).finish().unwrap();
+        self.transmit.push_back(buf[..len].to_vec());
+        self.last_sent = now;
+    }
+}
+
+/// A DSO session over a blocking stream.
+///
+/// The stream is typically a [`TcpStream`], but any [`Read`] + [`Write`] implementation can be
+/// used (for example, a TLS stream). Messages are framed with a 2-byte length prefix.
+pub struct SyncDsoSession<S> {
+    stream: S,
+    session: DsoSession,
+}
+
+impl SyncDsoSession<TcpStream> {
+    /// Connects to a DNS server via TCP.
+    ///
+    /// The session is not established until [`SyncDsoSession::establish`] is called.
+    pub fn connect(addr: SocketAddr) -> Result<Self, Error> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream))
+    }
+
+    /// Receives the next event, sending keepalive traffic as required while waiting.
+    ///
+    /// Returns an [`Error::Io`] of kind [`io::ErrorKind::UnexpectedEof`] when the server closes the
+    /// connection.
+    pub fn next_event(&mut self) -> Result<SessionEvent, Error> {
+        loop {
+            self.flush_transmit()?;
+            let timeout = self
+                .session
+                .next_timeout()
+                .map(|at| at.saturating_duration_since(Instant::now()).max(MIN_WAIT));
+            self.stream.set_read_timeout(timeout)?;
+
+            match self.recv_event() {
+                Ok(Some(event)) => return Ok(event),
+                Ok(None) => {}
+                Err(e) if e.is_timeout() => {
+                    if let Some(event) = self.session.handle_timeout(Instant::now()) {
+                        return Ok(event);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Lower bound for read timeouts, since a zero timeout is rejected by the OS.
+const MIN_WAIT: Duration = Duration::from_millis(1);
+
+impl<S: Read + Write> SyncDsoSession<S> {
+    /// Wraps an already connected stream.
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            session: DsoSession::new(Instant::now()),
+        }
+    }
+
+    /// Returns a reference to the session state.
+    #[inline]
+    pub fn session(&self) -> &DsoSession {
+        &self.session
+    }
+
+    /// Returns a reference to the underlying stream.
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Requests a session with the given timeouts, and blocks until the server has responded.
+    ///
+    /// Non-DSO messages received while waiting for the response are discarded.
+    ///
+    /// Returns an [`Error::Io`] of kind [`io::ErrorKind::ConnectionRefused`] if the server rejects
+    /// the session.
+    pub fn establish(&mut self, requested: Keepalive) -> Result<(), Error> {
+        self.session.establish(requested, Instant::now());
+        self.flush_transmit()?;
+        loop {
+            match self.recv_event()? {
+                Some(SessionEvent::Established) => return Ok(()),
+                Some(SessionEvent::Rejected(rcode)) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionRefused,
+                        format!("server rejected DSO session ({})", rcode),
+                    )
+                    .into())
+                }
+                Some(SessionEvent::RetryDelay(delay)) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionRefused,
+                        format!("server asked to retry in {:?}", delay),
+                    )
+                    .into())
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Sends a DNS message over the session's connection.
+    pub fn send_message(&mut self, msg: &[u8]) -> Result<(), Error> {
+        self.write_frame(msg)?;
+        self.session.on_message_sent(Instant::now());
+        Ok(())
+    }
+
+    /// Receives a single message and processes it, without handling timeouts.
+    pub fn recv_event(&mut self) -> Result<Option<SessionEvent>, Error> {
+        let mut len = [0; 2];
+        self.stream.read_exact(&mut len)?;
+        let mut msg = vec![0; usize::from(u16::from_be_bytes(len))];
+        self.stream.read_exact(&mut msg)?;
+        log::trace!("DSO recv: {}", Hex(&msg));
+
+        let event = self.session.handle_message(&msg, Instant::now())?;
+        self.flush_transmit()?;
+        Ok(event)
+    }
+
+    fn flush_transmit(&mut self) -> io::Result<()> {
+        while let Some(msg) = self.session.poll_transmit() {
+            self.write_frame(&msg)?;
+        }
+        Ok(())
+    }
+
+    fn write_frame(&mut self, msg: &[u8]) -> io::Result<()> {
+        if msg.len() > MAX_MESSAGE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "message too large for stream transport",
+            ));
+        }
+        log::trace!("DSO send: {}", Hex(msg));
+        let mut frame = Vec::with_capacity(msg.len() + 2);
+        frame.extend_from_slice(&(msg.len() as u16).to_be_bytes());
+        frame.extend_from_slice(msg);
+        self.stream.write_all(&frame)?;
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server_response(request: &[u8], rcode: RCode, keepalive: Option<Keepalive>) -> Vec<u8> {
+        let dec = DsoDecoder::new(request).unwrap();
+        let mut header = Header::default();
+        header.set_id(dec.header().id());
+        header.set_response(true);
+        header.set_rcode(rcode);
+        let mut buf = [0; 64];
+        let mut enc = DsoEncoder::new(&mut buf, header);
+        if let Some(keepalive) = keepalive {
+            enc.keepalive(&keepalive);
+        }
+        let len = enc.finish().unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn establish_and_keepalive() {
+        let start = Instant::now();
+        let mut session = DsoSession::new(start);
+        session.establish(Keepalive::new(None, None), start);
+        let request = session.poll_transmit().unwrap();
+        assert!(session.poll_transmit().is_none());
+        assert_eq!(session.next_timeout(), None);
+
+        let granted = Keepalive::new(Some(Duration::from_secs(60)), Some(Duration::from_secs(20)));
+        let response = server_response(&request, RCode::NO_ERROR, Some(granted));
+        assert_eq!(
+            session.handle_message(&response, start).unwrap(),
+            Some(SessionEvent::Established)
+        );
+        assert!(session.is_established());
+        assert_eq!(session.keepalive_interval(), Some(Duration::from_secs(20)));
+        assert_eq!(
+            session.next_timeout(),
+            Some(start + Duration::from_secs(20))
+        );
+
+        // After the keepalive interval, a keepalive request is sent.
+        let later = start + Duration::from_secs(20);
+        assert_eq!(session.handle_timeout(later), None);
+        let keepalive = session.poll_transmit().unwrap();
+        let dec = DsoDecoder::new(&keepalive).unwrap();
+        assert_ne!(dec.header().id(), 0);
+
+        // Without any traffic from the server, the session eventually times out.
+        assert_eq!(
+            session.handle_timeout(later + Duration::from_secs(60)),
+            Some(SessionEvent::InactivityTimeout)
+        );
+        assert!(session.is_closed());
+    }
+
+    #[test]
+    fn rejected() {
+        let now = Instant::now();
+        let mut session = DsoSession::new(now);
+        session.establish(Keepalive::new(None, None), now);
+        let request = session.poll_transmit().unwrap();
+        let response = server_response(&request, RCode::DSO_TYPE_NI, None);
+        assert_eq!(
+            session.handle_message(&response, now).unwrap(),
+            Some(SessionEvent::Rejected(RCode::DSO_TYPE_NI))
+        );
+    }
+
+    #[test]
+    fn unknown_request() {
+        let now = Instant::now();
+        let mut session = DsoSession::new(now);
+        let mut header = Header::default();
+        header.set_id(99);
+        let mut buf = [0; 64];
+        let mut enc = DsoEncoder::new(&mut buf, header);
+        enc.tlv(Tlv::new(DsoType(0xf901), &[1, 2, 3]));
+        let len = enc.finish().unwrap();
+
+        assert_eq!(session.handle_message(&buf[..len], now).unwrap(), None);
+        let response = session.poll_transmit().unwrap();
+        let dec = DsoDecoder::new(&response).unwrap();
+        assert!(dec.header().is_response());
+        assert_eq!(dec.header().id(), 99);
+        assert_eq!(dec.header().rcode(), RCode::DSO_TYPE_NI);
+    }
+}
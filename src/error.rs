@@ -1,10 +1,16 @@
-use std::{fmt, io};
+use std::{fmt, io, sync::Arc};
 
-/// Non-I/O errors that may occur during message decoding.
+use crate::{packet::Warning, resolver::ResolveError};
+
+/// The error type used throughout this crate.
+///
+/// Message decoding and encoding only ever produce the packet-level variants (everything but
+/// [`Error::Io`], [`Error::Timeout`], [`Error::Validation`] and [`Error::Resolve`]). Resolvers,
+/// discoverers and advertisers additionally report socket errors and timeouts.
 ///
-/// The parts of the library that perform I/O use [`std::io::Error`] instead. This error type can be
-/// converted to [`std::io::Error`] via [`From`]/[`Into`].
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+/// This error type can be converted to [`std::io::Error`] via [`From`]/[`Into`], and an
+/// [`std::io::Error`] can be converted to it, so `?` works in both directions.
+#[derive(Debug, Clone)]
 #[non_exhaustive]
 pub enum Error {
     /// The end of the message was reached while more data was expected.
@@ -22,9 +28,48 @@ pub enum Error {
     InvalidEmptyLabel,
     /// A label exceeded the maximum allowable length of a label.
     LabelTooLong,
+    /// An I/O operation (typically on a socket) failed.
+    ///
+    /// The [`io::Error`] is reference-counted so that [`Error`] can remain [`Clone`].
+    Io(Arc<io::Error>),
+    /// No (usable) response was received in time.
+    Timeout,
+    /// A message was well-formed, but failed a consistency check (see [`packet::validate`]).
+    ///
+    /// [`packet::validate`]: crate::packet::validate
+    Validation(Box<Warning>),
+    /// A DNS server answered a query with an error (see [`ResolveError`]).
+    Resolve(Box<ResolveError>),
 }
 
 impl Error {
+    /// Returns whether this error indicates that an operation timed out.
+    ///
+    /// This is the case for [`Error::Timeout`], as well as for [`Error::Io`] errors of kind
+    /// [`io::ErrorKind::WouldBlock`] or [`io::ErrorKind::TimedOut`], which are returned by sockets
+    /// with a read timeout.
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            Error::Timeout => true,
+            Error::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ),
+            _ => false,
+        }
+    }
+
+    /// Returns whether this error is likely caused by a temporary network outage, like a network
+    /// interface going down or being removed.
+    ///
+    /// Long-running listeners recover from such errors by recreating their socket after a delay.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Io(e) => is_transient_io(e),
+            _ => false,
+        }
+    }
+
     fn description(&self) -> &str {
         match self {
             Error::Eof => "unexpected end of data",
@@ -33,17 +78,86 @@ impl Error {
             Error::Truncated => "packet truncated",
             Error::InvalidEmptyLabel => "invalid empty label",
             Error::LabelTooLong => "label too long",
+            Error::Io(_) => "I/O error",
+            Error::Timeout => "operation timed out",
+            Error::Validation(_) => "message failed validation",
+            Error::Resolve(_) => "query failed",
         }
     }
 }
 
+/// See [`Error::is_transient`].
+pub(crate) fn is_transient_io(e: &io::Error) -> bool {
+    // `ENODEV` is returned when the interface a socket is bound to disappears, but has no
+    // corresponding `io::ErrorKind`. Its value is the same on Linux, macOS and the BSDs.
+    #[cfg(unix)]
+    if e.raw_os_error() == Some(19) {
+        return true;
+    }
+    matches!(
+        e.kind(),
+        io::ErrorKind::NetworkDown
+            | io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::HostUnreachable
+            | io::ErrorKind::AddrNotAvailable
+    )
+}
+
+impl PartialEq for Error {
+    /// Compares two errors.
+    ///
+    /// [`Error::Io`] errors are considered equal if their [`io::ErrorKind`]s match.
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Error::Io(a), Error::Io(b)) => a.kind() == b.kind(),
+            (Error::Validation(a), Error::Validation(b)) => a == b,
+            (Error::Resolve(a), Error::Resolve(b)) => a == b,
+            (a, b) => std::mem::discriminant(a) == std::mem::discriminant(b),
+        }
+    }
+}
+
+impl Eq for Error {}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_str(self.description())
+        match self {
+            Error::Io(e) => e.fmt(f),
+            Error::Validation(w) => write!(f, "{}: {}", self.description(), w),
+            Error::Resolve(e) => write!(f, "{}: {}", self.description(), e),
+            _ => f.write_str(self.description()),
+        }
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(&**e),
+            Error::Validation(w) => Some(&**w),
+            Error::Resolve(e) => Some(&**e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(Arc::new(e))
+    }
+}
+
+impl From<Warning> for Error {
+    fn from(w: Warning) -> Self {
+        Error::Validation(Box::new(w))
+    }
+}
+
+impl From<ResolveError> for Error {
+    fn from(e: ResolveError) -> Self {
+        Error::Resolve(Box::new(e))
+    }
+}
 
 impl From<Error> for io::Error {
     fn from(e: Error) -> io::Error {
@@ -63,6 +177,109 @@ impl From<Error> for io::Error {
                 "domain name label exceeds maximum label length",
             ),
             Error::Truncated => io::ErrorKind::OutOfMemory.into(),
+            Error::Io(e) => Arc::try_unwrap(e).unwrap_or_else(|e| io::Error::new(e.kind(), e)),
+            Error::Timeout => io::ErrorKind::TimedOut.into(),
+            Error::Validation(w) => io::Error::new(io::ErrorKind::InvalidData, w),
+            Error::Resolve(e) => io::Error::other(e),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+
+    use crate::packet::MessagePart;
+
+    use super::*;
+
+    fn servfail() -> ResolveError {
+        // A response header with `RCODE=SERVFAIL` and no entries.
+        let msg = [0, 1, 0x80, 0x02, 0, 0, 0, 0, 0, 0, 0, 0];
+        ResolveError::from_response(&msg, "192.0.2.53:53".parse().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn io() {
+        let e = Error::from(io::Error::new(io::ErrorKind::ConnectionReset, "peer left"));
+        assert_eq!(e.to_string(), "peer left");
+        assert!(!e.is_timeout());
+        assert!(!e.is_transient());
+        assert!(Error::from(io::Error::from(io::ErrorKind::NetworkDown)).is_transient());
+
+        let source = e.source().unwrap().downcast_ref::<io::Error>().unwrap();
+        assert_eq!(source.kind(), io::ErrorKind::ConnectionReset);
+
+        // Converting back yields the original error, unless it is shared.
+        let back = io::Error::from(e.clone());
+        assert_eq!(back.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(back.to_string(), "peer left");
+        let back = io::Error::from(e);
+        assert_eq!(back.kind(), io::ErrorKind::ConnectionReset);
+
+        let e = Error::from(io::Error::from(io::ErrorKind::WouldBlock));
+        assert!(e.is_timeout());
+        assert!(!e.is_transient());
+        assert!(Error::from(io::Error::from(io::ErrorKind::TimedOut)).is_timeout());
+    }
+
+    #[test]
+    fn timeout() {
+        assert!(Error::Timeout.is_timeout());
+        assert!(!Error::Timeout.is_transient());
+        assert!(Error::Timeout.source().is_none());
+        assert_eq!(
+            io::Error::from(Error::Timeout).kind(),
+            io::ErrorKind::TimedOut
+        );
+        assert!(!Error::Eof.is_timeout());
+    }
+
+    #[test]
+    fn validation() {
+        let warning = Warning::TrailingData { len: 3 };
+        let e = Error::from(warning.clone());
+        assert!(e.to_string().ends_with(&warning.to_string()), "{e}");
+        let source = e.source().unwrap().downcast_ref::<Warning>().unwrap();
+        assert_eq!(source, &warning);
+        assert_eq!(io::Error::from(e).kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn resolve() {
+        let e = Error::from(servfail());
+        assert!(e.to_string().contains("192.0.2.53:53"), "{e}");
+        let source = e.source().unwrap().downcast_ref::<ResolveError>().unwrap();
+        assert_eq!(source, &servfail());
+    }
+
+    #[test]
+    fn equality() {
+        let io = |kind, msg| Error::from(io::Error::new(kind, msg));
+        assert_eq!(
+            io(io::ErrorKind::TimedOut, "a"),
+            io(io::ErrorKind::TimedOut, "b")
+        );
+        assert_ne!(
+            io(io::ErrorKind::TimedOut, "a"),
+            io(io::ErrorKind::WouldBlock, "a")
+        );
+        assert_ne!(io(io::ErrorKind::TimedOut, "a"), Error::Timeout);
+
+        let trailing = |len| Error::from(Warning::TrailingData { len });
+        assert_eq!(trailing(1), trailing(1));
+        assert_ne!(trailing(1), trailing(2));
+        let malformed = Error::from(Warning::Malformed {
+            part: MessagePart::Answer,
+            error: Error::Eof,
+        });
+        assert_ne!(trailing(1), malformed);
+
+        assert_eq!(Error::from(servfail()), Error::from(servfail()));
+        assert_ne!(Error::from(servfail()), Error::Timeout);
+
+        assert_eq!(Error::Eof, Error::Eof);
+        assert_eq!(Error::Timeout, Error::Timeout);
+        assert_ne!(Error::Eof, Error::Truncated);
+    }
+}
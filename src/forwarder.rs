@@ -0,0 +1,991 @@
+//! Forwarding DNS proxy.
+//!
+//! A forwarder accepts DNS queries from clients over UDP and TCP, relays them to one or more
+//! upstream (recursive) resolvers, and sends the upstream's answer back to the client. This is the
+//! building block for the DNS proxies that are typically found in home routers.
+//!
+//! Queries from UDP clients are relayed over UDP, and queries from TCP clients over TCP, so that
+//! responses too large for UDP reach TCP clients intact. Clients whose query can't be answered by
+//! any upstream server receive a `SERVFAIL` response.
+//!
+//! The records in upstream responses are kept in a [`ResolverCache`] until their TTL runs out, and
+//! queries for cached records are answered directly, with the TTLs lowered by the time the records
+//! spent in the cache. The retransmission of unanswered queries (see [`Forwarder::poll_timeouts`])
+//! is separate from the resolvers in [`crate::resolver`], since the forwarder has to keep track of
+//! many clients' queries at once instead of blocking on a single one.
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::Backoff,
+    error::is_transient_io,
+    hex::Hex,
+    packet::{
+        decoder::MessageDecoder,
+        encoder::{self, MessageEncoder, ResourceRecord},
+        rewrite::Rewriter,
+        Class, Opcode, RCode,
+    },
+    resolver::{cache::ResolverCache, stream::SyncStreamClient},
+    Error, DNS_BUFFER_SIZE,
+};
+
+/// Maximum size of a DNS message (the maximum size of a UDP datagram, and of a TCP-framed message).
+const MAX_MESSAGE_SIZE: usize = u16::MAX as usize;
+
+/// A query that has been forwarded to an upstream server, and is awaiting a response.
+struct Pending<C> {
+    client: C,
+    /// The ID used by the client, which will be restored in the response.
+    client_id: u16,
+    /// The query, as it was forwarded upstream (with the ID rewritten).
+    query: Vec<u8>,
+    /// Index of the upstream server the query was last sent to.
+    upstream: usize,
+    /// Number of times the query has been sent upstream.
+    attempts: u32,
+    last_sent: Instant,
+}
+
+/// What to do with a query, as decided by [`Forwarder::handle_query`].
+#[derive(Debug)]
+pub enum QueryAction<'a, C> {
+    /// Send the query to an upstream server.
+    Forward(SocketAddr, &'a [u8]),
+    /// Send a response from the cache back to the client.
+    Respond(C, Vec<u8>),
+}
+
+/// I/O-less forwarding logic.
+///
+/// The forwarder rewrites the IDs of forwarded queries, so that queries from different clients
+/// that happen to use the same ID don't get confused, and restores the client's ID in the response.
+/// Each client request is tagged with a value of type `C`, which is handed back together with the
+/// response, so that the caller knows where to send it.
+///
+/// The records from upstream responses are cached, and queries that can be answered from the
+/// cache aren't forwarded (see [`Forwarder::handle_query`]).
+///
+/// You probably want to use [`SyncForwarder`] instead.
+pub struct Forwarder<C> {
+    upstreams: Vec<SocketAddr>,
+    pending: HashMap<u16, Pending<C>>,
+    next_id: u16,
+    retransmit_timeout: Duration,
+    max_attempts: u32,
+    min_ttl: Option<u32>,
+    max_ttl: Option<u32>,
+    cache: ResolverCache,
+}
+
+impl<C> Forwarder<C> {
+    const DEFAULT_RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(500);
+    const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+
+    /// Creates a new forwarder that will relay queries to `upstream`.
+    pub fn new(upstream: SocketAddr) -> Self {
+        Self {
+            upstreams: vec![upstream],
+            pending: HashMap::new(),
+            next_id: 1,
+            retransmit_timeout: Self::DEFAULT_RETRANSMIT_TIMEOUT,
+            max_attempts: Self::DEFAULT_MAX_ATTEMPTS,
+            min_ttl: None,
+            max_ttl: None,
+            cache: ResolverCache::new(),
+        }
+    }
+
+    /// Adds another upstream server.
+    ///
+    /// When a forwarded query is not answered in time, it is retransmitted to the next upstream
+    /// server in the list.
+    pub fn add_upstream(&mut self, upstream: SocketAddr) {
+        self.upstreams.push(upstream);
+    }
+
+    /// Returns the list of upstream servers.
+    #[inline]
+    pub fn upstreams(&self) -> &[SocketAddr] {
+        &self.upstreams
+    }
+
+    /// Sets the time after which an unanswered query is retransmitted.
+    pub fn set_retransmit_timeout(&mut self, timeout: Duration) {
+        self.retransmit_timeout = timeout;
+    }
+
+    /// Sets the number of times a query is sent upstream before it is given up on.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `attempts` is 0.
+    pub fn set_max_attempts(&mut self, attempts: u32) {
+        assert_ne!(attempts, 0, "`max_attempts` must be at least 1");
+        self.max_attempts = attempts;
+    }
+
+    /// Sets the smallest TTL (in seconds) of the records in forwarded responses.
+    ///
+    /// Records with a lower TTL get their TTL raised to `min_ttl`, which makes clients cache them
+    /// for at least that long. TTLs of 0 are kept, since they mean that a record must not be
+    /// cached at all. [`None`] keeps all TTLs, which is the default.
+    ///
+    /// If `min_ttl` is larger than the [maximum TTL](Forwarder::set_max_ttl), the maximum wins.
+    pub fn set_min_ttl(&mut self, min_ttl: Option<u32>) {
+        self.min_ttl = min_ttl;
+    }
+
+    /// Sets the largest TTL (in seconds) of the records in forwarded responses.
+    ///
+    /// Records with a higher TTL get their TTL lowered to `max_ttl`, which bounds how long clients
+    /// cache them. [`None`] keeps all TTLs, which is the default.
+    pub fn set_max_ttl(&mut self, max_ttl: Option<u32>) {
+        self.max_ttl = max_ttl;
+    }
+
+    /// Returns a reference to the cache of upstream answers.
+    #[inline]
+    pub fn cache(&self) -> &ResolverCache {
+        &self.cache
+    }
+
+    /// Returns a mutable reference to the cache of upstream answers.
+    ///
+    /// This can be used to clear the cache, for example when the upstream servers change.
+    #[inline]
+    pub fn cache_mut(&mut self) -> &mut ResolverCache {
+        &mut self.cache
+    }
+
+    /// Returns the number of queries that are awaiting an upstream response.
+    #[inline]
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn allocate_id(&mut self) -> Option<u16> {
+        if self.pending.len() > usize::from(u16::MAX) {
+            return None;
+        }
+        loop {
+            let id = self.next_id;
+            self.next_id = self.next_id.wrapping_add(1);
+            if !self.pending.contains_key(&id) {
+                return Some(id);
+            }
+        }
+    }
+
+    /// Handles a query received from `client` over UDP.
+    ///
+    /// If the records the query asks for are cached, this returns the response to send back to
+    /// `client`. Otherwise, the query is forwarded, and this returns the upstream server to send
+    /// it to, and the message to send. Packets that aren't queries are ignored and result in
+    /// `Ok(None)`.
+    ///
+    /// Only queries with a single question whose records are in the cache are answered from it.
+    /// Cached absences of records aren't used, since the cache doesn't keep the response code and
+    /// the `SOA` record the client needs. Queries setting the `DO` bit are always forwarded, since
+    /// the cache doesn't keep DNSSEC signatures, and so are queries whose response wouldn't fit
+    /// into a UDP message.
+    pub fn handle_query(
+        &mut self,
+        client: C,
+        packet: &[u8],
+        now: Instant,
+    ) -> Result<Option<QueryAction<'_, C>>, Error> {
+        let dec = MessageDecoder::new(packet)?;
+        let h = dec.header();
+        if !h.is_query() || h.question_count() == 0 {
+            return Ok(None);
+        }
+        let client_id = h.id();
+
+        if let Some(response) = self.cached_response(packet, false, now) {
+            log::trace!("answering query {} from the cache", client_id);
+            return Ok(Some(QueryAction::Respond(client, response)));
+        }
+
+        let Some(id) = self.allocate_id() else {
+            log::warn!("too many pending queries, dropping query");
+            return Ok(None);
+        };
+        let mut query = packet.to_vec();
+        query[..2].copy_from_slice(&id.to_be_bytes());
+
+        log::trace!("forwarding query {} as {}: {}", client_id, id, Hex(&query));
+
+        let pending = self.pending.entry(id).or_insert(Pending {
+            client,
+            client_id,
+            query,
+            upstream: 0,
+            attempts: 1,
+            last_sent: now,
+        });
+        Ok(Some(QueryAction::Forward(
+            self.upstreams[0],
+            &pending.query,
+        )))
+    }
+
+    /// Answers the query `packet` from the cache, if possible.
+    ///
+    /// See [`Forwarder::handle_query`] for which queries are answered. `tcp` lifts the size limit
+    /// of UDP responses.
+    fn cached_response(&self, packet: &[u8], tcp: bool, now: Instant) -> Option<Vec<u8>> {
+        let mut dec = MessageDecoder::new(packet).ok()?;
+        let header = *dec.header();
+        if header.opcode() != Opcode::QUERY || header.question_count() != 1 {
+            return None;
+        }
+        let question = dec.next()?.ok()?;
+        let mut edns = None;
+        let mut dec = dec.additional().ok()?;
+        for rr in dec.iter() {
+            if let Some(opt) = rr.ok()?.edns_header() {
+                edns = Some((opt.udp_payload_size(), opt.dnssec_ok()));
+            }
+        }
+        if edns.is_some_and(|(_, dnssec_ok)| dnssec_ok) {
+            return None;
+        }
+
+        let class = Class::try_from(question.qclass()).ok()?;
+        let cached = self
+            .cache
+            .get(question.qname(), question.qtype(), class, now)?;
+        if cached.records().is_empty() {
+            return None;
+        }
+        let ttl = u32::try_from(cached.remaining_ttl(now).as_secs()).unwrap_or(u32::MAX);
+        if ttl == 0 {
+            return None;
+        }
+        let ttl = self.clamp_ttl(ttl);
+
+        let limit = match edns {
+            _ if tcp => MAX_MESSAGE_SIZE,
+            Some((payload_size, _)) => usize::from(payload_size).max(DNS_BUFFER_SIZE),
+            None => DNS_BUFFER_SIZE,
+        };
+        let mut buf = vec![0; limit];
+        let mut enc = MessageEncoder::response_to(&mut buf, &header, [&question]);
+        enc.modify_header(|h| {
+            h.set_authority(false);
+            h.set_recursion_available(true);
+        });
+        // Records found by following cached `CNAME`s are returned as if `qname` owned them.
+        for record in cached.records() {
+            enc.add_answer(
+                ResourceRecord::new(question.qname(), record)
+                    .class(class)
+                    .ttl(ttl),
+            );
+        }
+        let mut enc = enc.authority().additional();
+        if edns.is_some() {
+            enc.add_edns(DNS_BUFFER_SIZE as u16);
+        }
+        // If the response doesn't fit, the query is forwarded so that the upstream server can
+        // decide how to truncate it.
+        let len = enc.finish().ok()?;
+        buf.truncate(len);
+        Some(buf)
+    }
+
+    /// Handles a response received from an upstream server at `now`.
+    ///
+    /// If the response belongs to a pending query, the query is completed, and the client that
+    /// sent it is returned together with the response to send back to it. The records in the
+    /// response are cached.
+    pub fn handle_response(
+        &mut self,
+        from: SocketAddr,
+        packet: &[u8],
+        now: Instant,
+    ) -> Result<Option<(C, Vec<u8>)>, Error> {
+        let dec = MessageDecoder::new(packet)?;
+        let h = dec.header();
+        if !h.is_response() {
+            return Ok(None);
+        }
+        if !self.upstreams.contains(&from) {
+            log::debug!("ignoring response from unknown server {}", from);
+            return Ok(None);
+        }
+
+        let Some(pending) = self.pending.get(&h.id()) else {
+            log::debug!("ignoring response with unknown ID {} from {}", h.id(), from);
+            return Ok(None);
+        };
+        if !same_question(&pending.query, packet)? {
+            log::debug!(
+                "ignoring response from {} whose question doesn't match the query",
+                from
+            );
+            return Ok(None);
+        }
+
+        let pending = self.pending.remove(&h.id()).unwrap();
+        let response = self.client_response(pending.client_id, packet, now);
+        Ok(Some((pending.client, response)))
+    }
+
+    /// Caches the records in the upstream response received at `now`, and turns it into the
+    /// response for the client that sent the query with ID `client_id`.
+    fn client_response(&mut self, client_id: u16, response: &[u8], now: Instant) -> Vec<u8> {
+        if let Err(e) = self.cache.insert_response(response, now) {
+            log::debug!("failed to cache upstream response: {}", e);
+        }
+        let mut response = self.clamp_ttls(response);
+        response[..2].copy_from_slice(&client_id.to_be_bytes());
+        response
+    }
+
+    /// Applies the configured TTL bounds to `ttl`, like [`Forwarder::clamp_ttls`] does.
+    fn clamp_ttl(&self, ttl: u32) -> u32 {
+        let ttl = match self.min_ttl {
+            Some(min_ttl) if ttl != 0 => ttl.max(min_ttl),
+            _ => ttl,
+        };
+        match self.max_ttl {
+            Some(max_ttl) => ttl.min(max_ttl),
+            None => ttl,
+        }
+    }
+
+    /// Applies the configured TTL bounds to the records in `response`.
+    ///
+    /// If the response can't be rewritten, it is forwarded unchanged.
+    fn clamp_ttls(&self, response: &[u8]) -> Vec<u8> {
+        if self.min_ttl.is_none() && self.max_ttl.is_none() {
+            return response.to_vec();
+        }
+        let mut rewriter = Rewriter::new();
+        rewriter.set_min_ttl(self.min_ttl);
+        rewriter.set_max_ttl(self.max_ttl);
+        let mut buf = vec![0; MAX_MESSAGE_SIZE];
+        match rewriter.rewrite(response, &mut buf) {
+            Ok(len) => {
+                buf.truncate(len);
+                buf
+            }
+            Err(e) => {
+                log::debug!(
+                    "failed to clamp TTLs of response, forwarding it unchanged: {}",
+                    e
+                );
+                response.to_vec()
+            }
+        }
+    }
+
+    /// Retransmits and expires pending queries.
+    ///
+    /// `retransmit` is invoked with every query that should be resent, and the upstream server to
+    /// send it to. Queries that have exhausted their attempts are dropped, and their client is
+    /// passed to `expired`, together with a `SERVFAIL` response to send back to it.
+    ///
+    /// This should be called periodically, at least once per retransmit timeout.
+    pub fn poll_timeouts(
+        &mut self,
+        now: Instant,
+        mut retransmit: impl FnMut(SocketAddr, &[u8]),
+        mut expired: impl FnMut(C, &[u8]),
+    ) {
+        let mut expired_ids = Vec::new();
+        for (&id, pending) in &mut self.pending {
+            if now.saturating_duration_since(pending.last_sent) < self.retransmit_timeout {
+                continue;
+            }
+            if pending.attempts >= self.max_attempts {
+                expired_ids.push(id);
+                continue;
+            }
+
+            pending.attempts += 1;
+            pending.upstream = (pending.upstream + 1) % self.upstreams.len();
+            pending.last_sent = now;
+            retransmit(self.upstreams[pending.upstream], &pending.query);
+        }
+
+        for id in expired_ids {
+            let pending = self.pending.remove(&id).unwrap();
+            log::debug!("query {} timed out", pending.client_id);
+            match servfail(pending.client_id, &pending.query) {
+                Ok(response) => expired(pending.client, &response),
+                Err(e) => log::debug!("failed to create SERVFAIL response: {}", e),
+            }
+        }
+    }
+}
+
+/// Creates a `SERVFAIL` response with ID `client_id` to `query`.
+fn servfail(client_id: u16, query: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut buf = vec![0; MAX_MESSAGE_SIZE];
+    let len = encoder::error_response(&mut buf, query, RCode::SERV_FAIL)?;
+    buf.truncate(len);
+    buf[..2].copy_from_slice(&client_id.to_be_bytes());
+    Ok(buf)
+}
+
+/// Returns whether the first question in `query` and `response` are the same.
+fn same_question(query: &[u8], response: &[u8]) -> Result<bool, Error> {
+    let mut q = MessageDecoder::new(query)?;
+    let mut r = MessageDecoder::new(response)?;
+    match (q.next(), r.next()) {
+        (Some(q), Some(r)) => {
+            let (q, r) = (q?, r?);
+            Ok(q.qname() == r.qname() && q.qtype() == r.qtype() && q.qclass() == r.qclass())
+        }
+        // Some servers omit the question in error responses.
+        (Some(_), None) => Ok(true),
+        _ => Ok(false),
+    }
+}
+
+/// Sends `packet` to `dest`.
+///
+/// Transient errors (see [`Error::is_transient`]) are only logged, so that a temporary network
+/// outage (or a single unreachable client) doesn't bring down the forwarder.
+fn send_to(sock: &UdpSocket, packet: &[u8], dest: SocketAddr) -> io::Result<()> {
+    match sock.send_to(packet, dest) {
+        Ok(_) => Ok(()),
+        Err(e) if is_transient_io(&e) => {
+            log::warn!("failed to send to {}: {}", dest, e);
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// A simple, synchronous DNS forwarder listening on UDP and TCP.
+pub struct SyncForwarder {
+    fwd: Arc<Mutex<Forwarder<SocketAddr>>>,
+    udp: Arc<UdpSocket>,
+    tcp: TcpListener,
+    upstream_sock: Arc<UdpSocket>,
+}
+
+impl SyncForwarder {
+    /// Creates a forwarder that listens on `bind_addr` (UDP and TCP) and relays queries to
+    /// `upstream`.
+    ///
+    /// Typically, `bind_addr` will use port 53.
+    pub fn new(bind_addr: SocketAddr, upstream: SocketAddr) -> Result<Self, Error> {
+        let upstream_bind: SocketAddr = if upstream.is_ipv6() {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        };
+        let udp = UdpSocket::bind(bind_addr)?;
+        let tcp = TcpListener::bind(udp.local_addr()?)?;
+        Ok(Self {
+            fwd: Arc::new(Mutex::new(Forwarder::new(upstream))),
+            udp: Arc::new(udp),
+            tcp,
+            upstream_sock: Arc::new(UdpSocket::bind(upstream_bind)?),
+        })
+    }
+
+    /// Adds another upstream server.
+    ///
+    /// # Panics
+    ///
+    /// All upstream servers must match the address family of the first one passed to
+    /// [`SyncForwarder::new`], otherwise this method will panic.
+    pub fn add_upstream(&mut self, upstream: SocketAddr) {
+        let mut fwd = self.fwd.lock().unwrap();
+        assert_eq!(
+            fwd.upstreams()[0].is_ipv4(),
+            upstream.is_ipv4(),
+            "upstream families must match",
+        );
+        fwd.add_upstream(upstream);
+    }
+
+    /// Sets the time after which an unanswered query is retransmitted.
+    pub fn set_retransmit_timeout(&mut self, timeout: Duration) {
+        self.fwd.lock().unwrap().set_retransmit_timeout(timeout);
+    }
+
+    /// Sets the number of times a query is sent upstream before it is given up on.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `attempts` is 0.
+    pub fn set_max_attempts(&mut self, attempts: u32) {
+        self.fwd.lock().unwrap().set_max_attempts(attempts);
+    }
+
+    /// Sets the smallest TTL (in seconds) of the records in forwarded responses.
+    ///
+    /// See [`Forwarder::set_min_ttl`].
+    pub fn set_min_ttl(&mut self, min_ttl: Option<u32>) {
+        self.fwd.lock().unwrap().set_min_ttl(min_ttl);
+    }
+
+    /// Sets the largest TTL (in seconds) of the records in forwarded responses.
+    ///
+    /// See [`Forwarder::set_max_ttl`].
+    pub fn set_max_ttl(&mut self, max_ttl: Option<u32>) {
+        self.fwd.lock().unwrap().set_max_ttl(max_ttl);
+    }
+
+    /// Returns the local address the forwarder is listening on.
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.udp.local_addr()?)
+    }
+
+    /// Starts forwarding queries.
+    ///
+    /// This spawns background threads for TCP clients and upstream responses, and then blocks
+    /// forever serving UDP clients. It only returns when an error occurs.
+    ///
+    /// Transient socket errors (see [`Error::is_transient`]), like those caused by the network
+    /// going down, are logged, and receiving is retried after an increasing delay. The sockets
+    /// are unicast sockets, which don't lose any state when the network goes away, so they are
+    /// reused instead of being recreated.
+    pub fn listen_blocking(&mut self) -> Result<(), Error> {
+        let tcp = self.tcp.try_clone()?;
+        let fwd = self.fwd.clone();
+        thread::spawn(move || {
+            for conn in tcp.incoming() {
+                let conn = match conn {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        log::warn!("failed to accept TCP connection: {}", e);
+                        continue;
+                    }
+                };
+                let fwd = fwd.clone();
+                thread::spawn(move || {
+                    if let Err(e) = serve_tcp(conn, &fwd) {
+                        log::debug!("TCP connection error: {}", e);
+                    }
+                });
+            }
+        });
+
+        let fwd = self.fwd.clone();
+        let udp = self.udp.clone();
+        let upstream_sock = self.upstream_sock.clone();
+        thread::spawn(move || {
+            if let Err(e) = relay_responses(&fwd, &udp, &upstream_sock) {
+                log::error!("failed to receive upstream responses: {}", e);
+            }
+        });
+
+        let mut backoff = Backoff::default();
+        let mut recv_buf = [0; DNS_BUFFER_SIZE];
+        loop {
+            let (len, addr) = match self.udp.recv_from(&mut recv_buf) {
+                Ok(res) => res,
+                Err(e) if is_transient_io(&e) => {
+                    log::warn!("forwarder socket error: {}; retrying", e);
+                    thread::sleep(backoff.next_delay());
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            backoff.reset();
+            let packet = &recv_buf[..len];
+            log::trace!("recv from {}: {}", addr, Hex(packet));
+
+            let mut fwd = self.fwd.lock().unwrap();
+            match fwd.handle_query(addr, packet, Instant::now()) {
+                Ok(Some(QueryAction::Forward(upstream, query))) => {
+                    send_to(&self.upstream_sock, query, upstream)?
+                }
+                Ok(Some(QueryAction::Respond(client, response))) => {
+                    send_to(&self.udp, &response, client)?
+                }
+                Ok(None) => {}
+                Err(e) => log::debug!("failed to handle query from {}: {}", addr, e),
+            }
+        }
+    }
+}
+
+/// Receives upstream responses and relays them to the clients, and handles retransmissions.
+fn relay_responses(
+    fwd: &Mutex<Forwarder<SocketAddr>>,
+    udp: &UdpSocket,
+    upstream_sock: &UdpSocket,
+) -> io::Result<()> {
+    let tick = fwd.lock().unwrap().retransmit_timeout / 2;
+    upstream_sock.set_read_timeout(Some(tick))?;
+
+    let mut backoff = Backoff::default();
+    let mut recv_buf = vec![0; MAX_MESSAGE_SIZE];
+    loop {
+        match upstream_sock.recv_from(&mut recv_buf) {
+            Ok((len, addr)) => {
+                backoff.reset();
+                let packet = &recv_buf[..len];
+                log::trace!("upstream recv from {}: {}", addr, Hex(packet));
+
+                let res = fwd
+                    .lock()
+                    .unwrap()
+                    .handle_response(addr, packet, Instant::now());
+                match res {
+                    Ok(Some((client, response))) => send_to(udp, &response, client)?,
+                    Ok(None) => {}
+                    Err(e) => log::debug!("failed to handle response from {}: {}", addr, e),
+                }
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(e) if is_transient_io(&e) => {
+                log::warn!("upstream socket error: {}; retrying", e);
+                thread::sleep(backoff.next_delay());
+            }
+            Err(e) => return Err(e),
+        }
+
+        let mut retransmits = Vec::new();
+        let mut servfails = Vec::new();
+        fwd.lock().unwrap().poll_timeouts(
+            Instant::now(),
+            |upstream, query| retransmits.push((upstream, query.to_vec())),
+            |client, response| servfails.push((client, response.to_vec())),
+        );
+        for (upstream, query) in retransmits {
+            send_to(upstream_sock, &query, upstream)?;
+        }
+        for (client, response) in servfails {
+            send_to(udp, &response, client)?;
+        }
+    }
+}
+
+/// Serves a TCP client connection, using the 2-byte length prefix framing from RFC 1035.
+///
+/// Queries that can't be answered from the cache are relayed to the upstream servers over TCP as
+/// well, trying each server in turn until one responds. Each client connection uses its own
+/// upstream connections.
+fn serve_tcp(mut conn: TcpStream, fwd: &Mutex<Forwarder<SocketAddr>>) -> io::Result<()> {
+    let peer = conn.peer_addr()?;
+    let mut upstreams = fwd
+        .lock()
+        .unwrap()
+        .upstreams()
+        .iter()
+        .map(|&upstream| (upstream, SyncStreamClient::connect_tcp(upstream)))
+        .collect::<Vec<_>>();
+    let mut buf = vec![0; MAX_MESSAGE_SIZE];
+    loop {
+        let mut len = [0; 2];
+        match conn.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let packet = &mut buf[..usize::from(u16::from_be_bytes(len))];
+        conn.read_exact(packet)?;
+        log::trace!("TCP recv from {}: {}", peer, Hex(packet));
+
+        let client_id = match MessageDecoder::new(packet) {
+            Ok(dec) if dec.header().is_query() && dec.header().question_count() != 0 => {
+                dec.header().id()
+            }
+            Ok(_) => continue,
+            Err(e) => {
+                log::debug!("failed to handle query from {}: {}", peer, e);
+                continue;
+            }
+        };
+
+        let mut response = fwd
+            .lock()
+            .unwrap()
+            .cached_response(packet, true, Instant::now());
+        if response.is_none() {
+            for (upstream, client) in &mut upstreams {
+                match client.query(packet) {
+                    Ok(resp) => {
+                        log::trace!("TCP upstream recv from {}: {}", upstream, Hex(&resp));
+                        let mut fwd = fwd.lock().unwrap();
+                        response = Some(fwd.client_response(client_id, &resp, Instant::now()));
+                        break;
+                    }
+                    Err(e) => log::debug!("TCP query to {} failed: {}", upstream, e),
+                }
+            }
+        }
+        let response = match response {
+            Some(response) => response,
+            None => match servfail(client_id, packet) {
+                Ok(response) => response,
+                Err(e) => {
+                    log::debug!("failed to create SERVFAIL response: {}", e);
+                    continue;
+                }
+            },
+        };
+        conn.write_all(&(response.len() as u16).to_be_bytes())?;
+        conn.write_all(&response)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        name::DomainName,
+        packet::{
+            decoder::OwnedResourceRecord,
+            encoder::{MessageEncoder, Question},
+            records::{Record, A},
+            Header, Message, QType,
+        },
+    };
+
+    use super::*;
+
+    fn query(id: u16, name: &str) -> Vec<u8> {
+        let mut buf = [0; DNS_BUFFER_SIZE];
+        let mut header = Header::default();
+        header.set_id(id);
+        let name = DomainName::from_str(name).unwrap();
+        let mut enc = MessageEncoder::new(&mut buf);
+        enc.set_header(header);
+        enc.question(Question::new(&name).ty(QType::A));
+        let len = enc.finish().unwrap();
+        buf[..len].to_vec()
+    }
+
+    fn respond(query: &[u8]) -> Vec<u8> {
+        let mut response = query.to_vec();
+        response[2] |= 0x80; // QR
+        response
+    }
+
+    /// Encodes a response to `query` with an `A` record for every TTL in `ttls`.
+    fn respond_with(query: &[u8], ttls: &[u32]) -> Vec<u8> {
+        let mut msg = Message::decode(query).unwrap();
+        msg.header_mut().set_response(true);
+        let name = msg.questions()[0].qname().clone();
+        for (i, &ttl) in ttls.iter().enumerate() {
+            let a = Record::A(A::new([192, 0, 2, i as u8].into()));
+            let mut rr = OwnedResourceRecord::new(name.clone(), a);
+            rr.set_ttl(ttl);
+            msg.answers_mut().push(rr);
+        }
+        let mut buf = [0; DNS_BUFFER_SIZE];
+        let len = msg.encode(&mut buf).unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[track_caller]
+    fn forward<C: std::fmt::Debug>(
+        action: Result<Option<QueryAction<'_, C>>, Error>,
+    ) -> (SocketAddr, Vec<u8>) {
+        match action.unwrap() {
+            Some(QueryAction::Forward(to, query)) => (to, query.to_vec()),
+            action => panic!("expected the query to be forwarded, got {:?}", action),
+        }
+    }
+
+    #[test]
+    fn rewrites_ids() {
+        let upstream: SocketAddr = "127.0.0.1:53".parse().unwrap();
+        let mut fwd = Forwarder::new(upstream);
+        let now = Instant::now();
+
+        let (to, q1) = forward(fwd.handle_query("a", &query(7, "example.com"), now));
+        assert_eq!(to, upstream);
+        let (_, q2) = forward(fwd.handle_query("b", &query(7, "example.org"), now));
+        assert_ne!(q1[..2], q2[..2]);
+        assert_eq!(fwd.pending_count(), 2);
+
+        let (client, resp) = fwd
+            .handle_response(upstream, &respond(&q2), now)
+            .unwrap()
+            .unwrap();
+        assert_eq!(client, "b");
+        assert_eq!(MessageDecoder::new(&resp).unwrap().header().id(), 7);
+
+        // Responses from unknown servers and replayed responses are ignored.
+        let other: SocketAddr = "127.0.0.2:53".parse().unwrap();
+        assert!(fwd
+            .handle_response(other, &respond(&q1), now)
+            .unwrap()
+            .is_none());
+        assert!(fwd
+            .handle_response(upstream, &respond(&q2), now)
+            .unwrap()
+            .is_none());
+
+        // The question has to match.
+        let mut wrong = respond(&query(0, "example.net"));
+        wrong[..2].copy_from_slice(&q1[..2]);
+        assert!(fwd
+            .handle_response(upstream, &wrong, now)
+            .unwrap()
+            .is_none());
+        assert_eq!(fwd.pending_count(), 1);
+    }
+
+    #[test]
+    fn retransmits_and_expires() {
+        let a: SocketAddr = "127.0.0.1:53".parse().unwrap();
+        let b: SocketAddr = "127.0.0.2:53".parse().unwrap();
+        let mut fwd = Forwarder::new(a);
+        fwd.add_upstream(b);
+        fwd.set_max_attempts(2);
+        let start = Instant::now();
+        forward(fwd.handle_query((), &query(1, "example.com"), start));
+
+        let mut sent = Vec::new();
+        let mut expired = Vec::new();
+        fwd.poll_timeouts(
+            start,
+            |to, _| sent.push(to),
+            |_, resp| expired.push(resp.to_vec()),
+        );
+        assert!(sent.is_empty());
+
+        let later = start + Duration::from_secs(1);
+        fwd.poll_timeouts(
+            later,
+            |to, _| sent.push(to),
+            |_, resp| expired.push(resp.to_vec()),
+        );
+        assert_eq!(sent, [b]);
+        assert!(expired.is_empty());
+
+        fwd.poll_timeouts(
+            later + Duration::from_secs(1),
+            |to, _| sent.push(to),
+            |_, resp| expired.push(resp.to_vec()),
+        );
+        assert_eq!(fwd.pending_count(), 0);
+
+        // The client gets a `SERVFAIL` response with its own ID.
+        let [resp] = &expired[..] else {
+            panic!("expected one expired query, got {}", expired.len());
+        };
+        let resp = Message::decode(resp).unwrap();
+        assert!(resp.header().is_response());
+        assert_eq!(resp.header().id(), 1);
+        assert_eq!(resp.header().rcode(), RCode::SERV_FAIL);
+        assert_eq!(resp.questions().len(), 1);
+    }
+
+    #[test]
+    fn clamps_ttls() {
+        let upstream: SocketAddr = "127.0.0.1:53".parse().unwrap();
+        let mut fwd = Forwarder::new(upstream);
+        fwd.set_min_ttl(Some(30));
+        fwd.set_max_ttl(Some(3600));
+        let now = Instant::now();
+        let (_, q) = forward(fwd.handle_query((), &query(9, "example.com"), now));
+
+        let (_, resp) = fwd
+            .handle_response(upstream, &respond_with(&q, &[5, 0, 86400]), now)
+            .unwrap()
+            .unwrap();
+        let resp = Message::decode(&resp).unwrap();
+        assert_eq!(resp.header().id(), 9);
+        let ttls = resp.answers().iter().map(|rr| rr.ttl()).collect::<Vec<_>>();
+        assert_eq!(ttls, [30, 0, 3600]);
+    }
+
+    #[test]
+    fn answers_from_cache() {
+        let upstream: SocketAddr = "127.0.0.1:53".parse().unwrap();
+        let mut fwd = Forwarder::new(upstream);
+        fwd.set_max_ttl(Some(60));
+        let now = Instant::now();
+        let (_, q) = forward(fwd.handle_query("a", &query(1, "example.com"), now));
+        fwd.handle_response(upstream, &respond_with(&q, &[300, 100]), now)
+            .unwrap()
+            .unwrap();
+
+        // The records are cached for as long as the upstream said, not the clamped TTL, and the
+        // TTL decays while they're in the cache.
+        let later = now + Duration::from_secs(80);
+        let resp = match fwd.handle_query("b", &query(2, "EXAMPLE.com"), later) {
+            Ok(Some(QueryAction::Respond(client, resp))) => {
+                assert_eq!(client, "b");
+                resp
+            }
+            action => panic!("expected a cached response, got {:?}", action),
+        };
+        assert_eq!(fwd.pending_count(), 0);
+        let resp = Message::decode(&resp).unwrap();
+        assert_eq!(resp.header().id(), 2);
+        assert!(resp.header().is_response());
+        assert_eq!(resp.header().rcode(), RCode::NO_ERROR);
+        assert_eq!(resp.questions().len(), 1);
+        let ttls = resp.answers().iter().map(|rr| rr.ttl()).collect::<Vec<_>>();
+        assert_eq!(ttls, [20, 20]);
+
+        // Once the TTL has passed, the query is forwarded again.
+        let expired = now + Duration::from_secs(100);
+        forward(fwd.handle_query("c", &query(3, "example.com"), expired));
+
+        // Empty answers are not cached without a `SOA` record, and other names aren't affected.
+        let (_, q) = forward(fwd.handle_query("d", &query(4, "example.org"), now));
+        fwd.handle_response(upstream, &respond(&q), now)
+            .unwrap()
+            .unwrap();
+        forward(fwd.handle_query("e", &query(5, "example.org"), now));
+    }
+
+    #[test]
+    fn tcp_upstream() {
+        use crate::server::{SyncServer, Zone};
+
+        // 40 `A` records don't fit into a 512-byte UDP response.
+        let name = DomainName::from_str("big.example.com").unwrap();
+        let mut zone = Zone::new("example.com".parse().unwrap());
+        for i in 0..40 {
+            let a = Record::A(A::new(Ipv4Addr::new(192, 0, 2, i)));
+            zone.add(name.clone(), 300, a).unwrap();
+        }
+        let mut server = SyncServer::new((Ipv4Addr::LOCALHOST, 0).into(), zone).unwrap();
+        let upstream = server.local_addr().unwrap();
+        thread::spawn(move || server.listen_blocking());
+
+        let mut fwd = SyncForwarder::new((Ipv4Addr::LOCALHOST, 0).into(), upstream).unwrap();
+        let addr = fwd.local_addr().unwrap();
+        thread::spawn(move || fwd.listen_blocking());
+
+        let udp = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        udp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        udp.send_to(&query(3, "big.example.com"), addr).unwrap();
+        let mut buf = [0; DNS_BUFFER_SIZE];
+        let len = udp.recv(&mut buf).unwrap();
+        let dec = MessageDecoder::new(&buf[..len]).unwrap();
+        assert_eq!(dec.header().id(), 3);
+        assert!(dec.header().is_truncated());
+
+        let mut tcp = TcpStream::connect(addr).unwrap();
+        tcp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let q = query(4, "big.example.com");
+        tcp.write_all(&(q.len() as u16).to_be_bytes()).unwrap();
+        tcp.write_all(&q).unwrap();
+        let mut len = [0; 2];
+        tcp.read_exact(&mut len).unwrap();
+        let mut resp = vec![0; usize::from(u16::from_be_bytes(len))];
+        tcp.read_exact(&mut resp).unwrap();
+        let resp = Message::decode(&resp).unwrap();
+        assert_eq!(resp.header().id(), 4);
+        assert!(!resp.header().is_truncated());
+        assert_eq!(resp.answers().len(), 40);
+    }
+}
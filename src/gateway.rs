@@ -0,0 +1,537 @@
+//! mDNS-to-unicast DNS gateway.
+//!
+//! The gateway is a unicast DNS server that is authoritative for a configurable zone (for example,
+//! `local.example.com`). It answers queries for names in that zone by performing live mDNS
+//! resolution and DNS-SD browsing on the local network, and translating the results back into the
+//! zone. This makes services advertised via mDNS visible to clients that only speak unicast DNS.
+
+use std::{
+    net::{IpAddr, SocketAddr, UdpSocket},
+    ops::ControlFlow,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::Backoff,
+    domain,
+    error::is_transient_io,
+    hex::Hex,
+    label,
+    name::{DomainName, Label},
+    packet::{
+        decoder::{MessageDecoder, Question},
+        encoder::{self, MessageEncoder, ResourceRecord},
+        records::{Record, A, AAAA, PTR, SRV},
+        Class, Header, Opcode, QType, RCode, Type,
+    },
+    resolver::SyncResolver,
+    service::{discovery::SyncDiscoverer, Service, ServiceInstance, ServiceTransport},
+    Error, DNS_BUFFER_SIZE,
+};
+
+type Answers = Vec<(DomainName, Record<'static>)>;
+
+/// A synchronous gateway that answers unicast DNS queries using mDNS.
+pub struct SyncGateway {
+    sock: UdpSocket,
+    zone: DomainName,
+    local: DomainName,
+    resolver: SyncResolver,
+    discoverer: SyncDiscoverer,
+    ttl: u32,
+    min_ttl: Option<u32>,
+    max_ttl: Option<u32>,
+    response_buf: Vec<u8>,
+}
+
+impl SyncGateway {
+    const DEFAULT_TTL: u32 = 10;
+
+    /// Creates a gateway serving `zone` on the unicast address `bind_addr`.
+    ///
+    /// Queries for `<name>.<zone>` are translated to mDNS queries for `<name>.local`.
+    pub fn new(bind_addr: SocketAddr, zone: DomainName) -> Result<Self, Error> {
+        Ok(Self {
+            sock: UdpSocket::bind(bind_addr)?,
+            zone,
+            local: domain!("local"),
+            resolver: SyncResolver::new_multicast_v4()?,
+            discoverer: SyncDiscoverer::new_multicast_v4()?,
+            ttl: Self::DEFAULT_TTL,
+            min_ttl: None,
+            max_ttl: None,
+            response_buf: vec![0; DNS_BUFFER_SIZE],
+        })
+    }
+
+    /// Returns the local address the gateway is listening on.
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.sock.local_addr()?)
+    }
+
+    /// Sets the TTL of the records in the gateway's responses, in seconds.
+    ///
+    /// Since the answers are obtained via mDNS and may change at any time, this should be kept
+    /// short. The default is 10 seconds.
+    ///
+    /// Service instance details are served with a lower TTL if they were cached by the discoverer
+    /// and expire sooner.
+    pub fn set_ttl(&mut self, ttl: u32) {
+        self.ttl = ttl;
+    }
+
+    /// Sets the smallest TTL (in seconds) of the records in the gateway's responses.
+    ///
+    /// This raises the TTL of service instance details that expire sooner than the
+    /// [TTL](SyncGateway::set_ttl), so that clients don't query the gateway (and the gateway
+    /// doesn't query the local network) too often. A TTL of 0 is kept. [`None`] applies no lower
+    /// bound, which is the default.
+    ///
+    /// If `min_ttl` is larger than the [maximum TTL](SyncGateway::set_max_ttl), the maximum wins.
+    pub fn set_min_ttl(&mut self, min_ttl: Option<u32>) {
+        self.min_ttl = min_ttl;
+    }
+
+    /// Sets the largest TTL (in seconds) of the records in the gateway's responses.
+    ///
+    /// The TTL of every answer is capped at `max_ttl`, whatever the [TTL](SyncGateway::set_ttl)
+    /// is set to. Answers derived from mDNS should not be cached by one-shot resolvers for more
+    /// than 10 seconds ([RFC 6762, section 6.7]), which this can enforce. [`None`] applies no upper
+    /// bound, which is the default.
+    ///
+    /// [RFC 6762, section 6.7]: https://datatracker.ietf.org/doc/html/rfc6762#section-6.7
+    pub fn set_max_ttl(&mut self, max_ttl: Option<u32>) {
+        self.max_ttl = max_ttl;
+    }
+
+    /// Sets the time to wait for mDNS responses when browsing for services.
+    pub fn set_discovery_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        self.discoverer.set_discovery_timeout(timeout)
+    }
+
+    /// Sets the time to wait for mDNS responses when resolving host names.
+    pub fn set_resolve_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        self.resolver.set_timeout(timeout)
+    }
+
+    /// Starts listening for and responding to queries.
+    ///
+    /// This method will block forever and never return, except when an error occurs.
+    ///
+    /// Transient socket errors (see [`Error::is_transient`]), like those caused by the network
+    /// going down, are logged, and receiving is retried after an increasing delay. Unlike the
+    /// multicast sockets of the advertiser, the unicast socket doesn't lose any state when the
+    /// network goes away, so it is reused instead of being recreated.
+    pub fn listen_blocking(&mut self) -> Result<(), Error> {
+        let mut backoff = Backoff::default();
+        let mut recv_buf = [0; DNS_BUFFER_SIZE];
+        loop {
+            let (len, addr) = match self.sock.recv_from(&mut recv_buf) {
+                Ok(res) => res,
+                Err(e) if is_transient_io(&e) => {
+                    log::warn!("gateway socket error: {}; retrying", e);
+                    thread::sleep(backoff.next_delay());
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            backoff.reset();
+            let packet = &recv_buf[..len];
+            log::trace!("recv from {}: {}", addr, Hex(packet));
+
+            match self.handle_query(packet).map(|resp| resp.map(<[u8]>::len)) {
+                Ok(Some(len)) => match self.sock.send_to(&self.response_buf[..len], addr) {
+                    Ok(_) => {}
+                    Err(e) if is_transient_io(&e) => {
+                        log::warn!("failed to send response to {}: {}", addr, e);
+                    }
+                    Err(e) => return Err(e.into()),
+                },
+                Ok(None) => {}
+                Err(e) => {
+                    log::debug!("failed to handle query from {}: {}", addr, e);
+                }
+            }
+        }
+    }
+
+    /// Handles a unicast DNS query, and returns the response to send back (if any).
+    ///
+    /// This performs mDNS queries on the local network, and blocks until they have completed.
+    ///
+    /// Queries with an opcode other than `QUERY` are answered with `NOT_IMP`, those without a
+    /// question with `FORM_ERR`, and those whose mDNS lookup fails with `SERV_FAIL`. Responses
+    /// aren't answered, and an error is only returned if `packet` doesn't even have a valid header.
+    pub fn handle_query(&mut self, packet: &[u8]) -> Result<Option<&[u8]>, Error> {
+        let mut dec = MessageDecoder::new(packet)?;
+        let header = *dec.header();
+        if !header.is_query() {
+            return Ok(None);
+        }
+        if header.opcode() != Opcode::QUERY {
+            return self.error_response(packet, RCode::NOT_IMP);
+        }
+        // Like most servers, we only support a single question per query.
+        let question = match dec.next() {
+            Some(Ok(q)) => q,
+            Some(Err(e)) => {
+                log::debug!("failed to decode question: {}", e);
+                return self.error_response(packet, RCode::FORM_ERR);
+            }
+            None => return self.error_response(packet, RCode::FORM_ERR),
+        };
+        log::debug!("Q: {}", question);
+
+        let (rcode, answers, ttl) = if !question.qclass().matches(Class::IN) {
+            (RCode::REFUSED, Vec::new(), self.ttl)
+        } else {
+            match self.translate_zone(question.qname()) {
+                Some(name) => match self.lookup(&name, question.qtype()) {
+                    Ok(res) => res,
+                    Err(e) => {
+                        log::debug!("failed to look up {}: {}", name, e);
+                        return self.error_response(packet, RCode::SERV_FAIL);
+                    }
+                },
+                None => (RCode::REFUSED, Vec::new(), self.ttl),
+            }
+        };
+
+        let len = self.encode_response(&header, &question, rcode, &answers, ttl);
+        Ok(Some(&self.response_buf[..len]))
+    }
+
+    /// Writes a response to `query` with the error code `rcode` into the response buffer.
+    fn error_response(&mut self, query: &[u8], rcode: RCode) -> Result<Option<&[u8]>, Error> {
+        let len = encoder::error_response(&mut self.response_buf, query, rcode)?;
+        Ok(Some(&self.response_buf[..len]))
+    }
+
+    /// Looks up `name` via mDNS, returning the response code, the answers, and their TTL.
+    fn lookup(&mut self, name: &DomainName, qtype: QType) -> Result<(RCode, Answers, u32), Error> {
+        let labels = name.labels();
+        let mut answers = Vec::new();
+
+        // `_services._dns-sd._udp.local`
+        if labels.len() == 4 && labels[..3] == service_enumeration_labels()[..] {
+            if qtype.matches(Type::PTR) {
+                let mut types = Vec::new();
+                self.discoverer.discover_service_types(|service| {
+                    types.push(service.clone());
+                    ControlFlow::Continue(())
+                })?;
+                for service in types {
+                    let target = DomainName::from_iter([
+                        service.name().clone(),
+                        service.transport().to_label(),
+                    ]);
+                    answers.push((
+                        self.translate_local(name),
+                        Record::PTR(PTR::new(self.extend_zone(target))),
+                    ));
+                }
+            }
+            return Ok((RCode::NO_ERROR, answers, self.ttl));
+        }
+
+        // `_service._proto.local`
+        if let Some(service) = parse_service(labels) {
+            if qtype.matches(Type::PTR) {
+                let mut instances = Vec::new();
+                self.discoverer.discover_instances(&service, |instance| {
+                    instances.push(instance.clone());
+                    ControlFlow::Continue(())
+                })?;
+                for instance in instances {
+                    let target = DomainName::from_iter([
+                        instance.instance_name().clone(),
+                        instance.service_name().clone(),
+                        instance.service_transport().to_label(),
+                    ]);
+                    answers.push((
+                        self.translate_local(name),
+                        Record::PTR(PTR::new(self.extend_zone(target))),
+                    ));
+                }
+            }
+            return Ok((RCode::NO_ERROR, answers, self.ttl));
+        }
+
+        // `instance._service._proto.local`
+        if labels.len() == 4 {
+            if let Some(service) = parse_service(&labels[1..]) {
+                let instance = ServiceInstance::from_service(labels[0].clone(), service);
+                let details = match self.discoverer.load_instance_details(&instance) {
+                    Ok(details) => details,
+                    Err(e) if e.is_timeout() => return Ok((RCode::NX_DOMAIN, answers, self.ttl)),
+                    Err(e) => return Err(e),
+                };
+                // The details may come from the discoverer's cache, so don't hand them out for
+                // longer than they remain valid.
+                let ttl = match self.discoverer.cached_instance_details(&instance) {
+                    Some(cached) => {
+                        let remaining = cached.remaining_ttl(Instant::now()).as_secs();
+                        self.ttl.min(remaining.try_into().unwrap_or(u32::MAX))
+                    }
+                    None => self.ttl,
+                };
+                let owner = self.translate_local(name);
+                if qtype.matches(Type::SRV) {
+                    for target in details.targets() {
+                        let srv = SRV::new(
+                            target.priority(),
+                            target.weight(),
+                            target.port(),
+                            self.translate_local(target.host()),
+                        );
+                        answers.push((owner.clone(), Record::SRV(srv)));
+                    }
+                }
+                if qtype.matches(Type::TXT) {
+                    answers.push((owner, Record::TXT(details.txt_records().to_txt())));
+                }
+                return Ok((RCode::NO_ERROR, answers, ttl));
+            }
+        }
+
+        // Everything else is treated as a host name.
+        let ips = match self.resolver.resolve_domain(name) {
+            Ok(ips) => ips.collect::<Vec<_>>(),
+            Err(e) if e.is_timeout() => return Ok((RCode::NX_DOMAIN, answers, self.ttl)),
+            Err(e) => return Err(e),
+        };
+        let owner = self.translate_local(name);
+        for ip in ips {
+            let record = match ip {
+                IpAddr::V4(ip) => Record::A(A::new(ip)),
+                IpAddr::V6(ip) => Record::AAAA(AAAA::new(ip)),
+            };
+            if qtype.matches(record.record_type()) {
+                answers.push((owner.clone(), record));
+            }
+        }
+        Ok((RCode::NO_ERROR, answers, self.ttl))
+    }
+
+    fn encode_response(
+        &mut self,
+        query: &Header,
+        question: &Question,
+        rcode: RCode,
+        answers: &[(DomainName, Record<'static>)],
+        ttl: u32,
+    ) -> usize {
+        let ttl = self.clamp_ttl(ttl);
+        let mut enc = MessageEncoder::response_to(&mut self.response_buf, query, [question]);
+        enc.modify_header(|h| h.set_rcode(rcode));
+        for (name, record) in answers {
+            enc.add_answer(ResourceRecord::new(name, record).ttl(ttl));
+        }
+        // Truncated replies have the TC bit set, and should still get sent.
+        enc.finish().ok().unwrap_or(self.response_buf.len())
+    }
+
+    /// Applies the configured TTL bounds to `ttl`.
+    fn clamp_ttl(&self, mut ttl: u32) -> u32 {
+        if ttl == 0 {
+            return 0;
+        }
+        if let Some(min) = self.min_ttl {
+            ttl = ttl.max(min);
+        }
+        if let Some(max) = self.max_ttl {
+            ttl = ttl.min(max);
+        }
+        ttl
+    }
+
+    /// Translates a name in the gateway's zone to the corresponding `.local` name.
+    fn translate_zone(&self, name: &DomainName) -> Option<DomainName> {
+        let prefix = name.labels().strip_suffix(self.zone.labels())?;
+        let mut local = DomainName::from_iter(prefix);
+        local.extend(&self.local);
+        Some(local)
+    }
+
+    /// Translates a `.local` name to the corresponding name in the gateway's zone.
+    ///
+    /// Names outside of `.local` are returned unchanged.
+    fn translate_local(&self, name: &DomainName) -> DomainName {
+        match name.labels().strip_suffix(self.local.labels()) {
+            Some(prefix) => self.extend_zone(DomainName::from_iter(prefix)),
+            None => name.clone(),
+        }
+    }
+
+    fn extend_zone(&self, mut name: DomainName) -> DomainName {
+        name.extend(&self.zone);
+        name
+    }
+}
+
+fn service_enumeration_labels() -> [Label; 3] {
+    [label!("_services"), label!("_dns-sd"), label!("_udp")]
+}
+
+/// Parses `_service._proto.local`.
+fn parse_service(labels: &[Label]) -> Option<Service> {
+    let [name, transport, _local] = labels else {
+        return None;
+    };
+    if !name.as_bytes().starts_with(b"_") {
+        return None;
+    }
+    let transport = std::str::from_utf8(transport.as_bytes())
+        .ok()?
+        .parse::<ServiceTransport>()
+        .ok()?;
+    Some(Service::new(name.clone(), transport))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::encoder;
+
+    use super::*;
+
+    fn gateway() -> SyncGateway {
+        SyncGateway::new(
+            "127.0.0.1:0".parse().unwrap(),
+            DomainName::from_str("local.example.com").unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn translates_names() {
+        let gw = gateway();
+        let name = |s| DomainName::from_str(s).unwrap();
+
+        assert_eq!(
+            gw.translate_zone(&name("host.local.example.com")),
+            Some(name("host.local"))
+        );
+        assert_eq!(gw.translate_zone(&name("host.example.com")), None);
+        assert_eq!(
+            gw.translate_local(&name("_http._tcp.local")),
+            name("_http._tcp.local.example.com")
+        );
+        assert_eq!(
+            gw.translate_local(&name("example.org")),
+            name("example.org")
+        );
+    }
+
+    #[test]
+    fn error_responses() {
+        let mut gw = gateway();
+        let name = DomainName::from_str("host.local.example.com").unwrap();
+        let mut query = |opcode, question: bool| {
+            let mut header = Header::default();
+            header.set_id(42);
+            header.set_opcode(opcode);
+            let mut buf = [0; DNS_BUFFER_SIZE];
+            let mut enc = MessageEncoder::new(&mut buf);
+            enc.set_header(header);
+            if question {
+                enc.question(encoder::Question::new(&name).ty(QType::A));
+            }
+            let len = enc.finish().unwrap();
+            let resp = gw.handle_query(&buf[..len]).unwrap().map(|resp| {
+                let dec = MessageDecoder::new(resp).unwrap();
+                assert_eq!(dec.header().id(), 42);
+                assert!(dec.header().is_response());
+                dec.header().rcode()
+            });
+
+            // Responses are never answered.
+            buf[2] |= 0x80; // QR
+            assert!(gw.handle_query(&buf[..len]).unwrap().is_none());
+            resp
+        };
+
+        assert_eq!(query(Opcode::STATUS, true), Some(RCode::NOT_IMP));
+        assert_eq!(query(Opcode::QUERY, false), Some(RCode::FORM_ERR));
+    }
+
+    #[test]
+    fn clamps_ttls() {
+        let mut gw = gateway();
+        assert_eq!(gw.clamp_ttl(4), 4);
+        gw.set_min_ttl(Some(5));
+        gw.set_max_ttl(Some(60));
+        assert_eq!(gw.clamp_ttl(0), 0);
+        assert_eq!(gw.clamp_ttl(4), 5);
+        assert_eq!(gw.clamp_ttl(120), 60);
+        gw.set_min_ttl(Some(90));
+        assert_eq!(gw.clamp_ttl(4), 60);
+    }
+
+    #[test]
+    fn caps_ttl_of_cached_details() {
+        use crate::service::{discovery::DetailsCache, InstanceDetails};
+
+        let instance = ServiceInstance::new(label!("inst"), label!("_http"), ServiceTransport::TCP);
+        let details = InstanceDetails::new(DomainName::from_str("host.local").unwrap(), 80);
+        let query = |gw: &mut SyncGateway| {
+            let mut buf = [0; DNS_BUFFER_SIZE];
+            let name = DomainName::from_str("inst._http._tcp.local.example.com").unwrap();
+            let mut enc = MessageEncoder::new(&mut buf);
+            enc.set_header(Header::default());
+            enc.question(encoder::Question::new(&name).ty(QType::SRV));
+            let len = enc.finish().unwrap();
+
+            let resp = gw.handle_query(&buf[..len]).unwrap().unwrap();
+            let dec = MessageDecoder::new(resp).unwrap();
+            assert_eq!(dec.header().rcode(), RCode::NO_ERROR);
+            let mut answers = dec.answers().unwrap();
+            let ttls = answers
+                .iter()
+                .map(|rr| rr.unwrap().ttl())
+                .collect::<Vec<_>>();
+            assert_eq!(ttls.len(), 1);
+            ttls[0]
+        };
+
+        // The details are served from the discoverer's cache, so no mDNS queries are needed.
+        let mut gw = gateway();
+        let mut cache = DetailsCache::new();
+        let ttl = Duration::from_secs(300);
+        cache.insert(instance.clone(), details.clone(), ttl, Instant::now());
+        gw.discoverer.set_details_cache(cache);
+        assert_eq!(query(&mut gw), SyncGateway::DEFAULT_TTL);
+
+        // Details that expire sooner than the configured TTL are served with their remaining TTL.
+        let mut cache = DetailsCache::new();
+        let ttl = Duration::from_secs(4);
+        cache.insert(instance, details, ttl, Instant::now());
+        gw.discoverer.set_details_cache(cache);
+        let ttl = query(&mut gw);
+        assert!((1..=4).contains(&ttl), "{ttl}");
+
+        // ... unless the minimum TTL says otherwise.
+        gw.set_min_ttl(Some(8));
+        assert_eq!(query(&mut gw), 8);
+    }
+
+    #[test]
+    fn refuses_out_of_zone() {
+        let mut gw = gateway();
+        let mut buf = [0; DNS_BUFFER_SIZE];
+        let mut header = Header::default();
+        header.set_id(4321);
+        let name = DomainName::from_str("example.org").unwrap();
+        let mut enc = MessageEncoder::new(&mut buf);
+        enc.set_header(header);
+        enc.question(encoder::Question::new(&name).ty(QType::A));
+        let len = enc.finish().unwrap();
+
+        let resp = gw.handle_query(&buf[..len]).unwrap().unwrap();
+        let dec = MessageDecoder::new(resp).unwrap();
+        assert!(dec.header().is_response());
+        assert_eq!(dec.header().id(), 4321);
+        assert_eq!(dec.header().rcode(), RCode::REFUSED);
+        assert_eq!(dec.header().question_count(), 1);
+    }
+}
@@ -0,0 +1,384 @@
+//! Conversions between uwuhi and [`hickory_proto`] types.
+//!
+//! These allow mixing uwuhi's mDNS and DNS-SD components with an existing hickory-dns deployment.
+//! All conversions are implemented via [`From`] and [`TryFrom`]:
+//!
+//! - [`DomainName`] ⟷ [`Name`]
+//! - [`Record`] ⟷ [`RData`]
+//! - [`decoder::ResourceRecord`] ⟶ hickory's [`Record`](hickory_proto::rr::Record)
+//! - [`decoder::Question`] ⟶ [`Query`]
+//! - [`MessageDecoder`] ⟶ [`Message`]
+//! - [`packet::Message`] ⟷ [`Message`]
+//!
+//! Messages are converted by encoding them to the wire format and decoding the result, so that
+//! both libraries see exactly the same message.
+
+use std::{borrow::Cow, io};
+
+use hickory_proto::{
+    op::{Message, Query},
+    rr::{self, rdata, DNSClass, Name, RData, RecordType},
+    ProtoError,
+};
+
+use crate::{
+    name::{DomainName, Label},
+    packet::{
+        self,
+        decoder::{self, MessageDecoder},
+        records::{Record, A, AAAA, CNAME, HINFO, MX, NS, PTR, SOA, SRV, TXT},
+        section::Section,
+        Class, QClass, QType, Type,
+    },
+    Error,
+};
+
+impl TryFrom<&DomainName> for Name {
+    type Error = ProtoError;
+
+    fn try_from(name: &DomainName) -> Result<Self, Self::Error> {
+        Name::from_labels(name.labels().iter().map(Label::as_bytes))
+    }
+}
+
+impl TryFrom<DomainName> for Name {
+    type Error = ProtoError;
+
+    fn try_from(name: DomainName) -> Result<Self, Self::Error> {
+        Name::try_from(&name)
+    }
+}
+
+impl TryFrom<&Name> for DomainName {
+    type Error = Error;
+
+    /// Converts a hickory [`Name`] to a [`DomainName`].
+    ///
+    /// Relative names are treated as if they were fully qualified.
+    fn try_from(name: &Name) -> Result<Self, Self::Error> {
+        name.iter().map(Label::try_new).collect()
+    }
+}
+
+impl TryFrom<Name> for DomainName {
+    type Error = Error;
+
+    fn try_from(name: Name) -> Result<Self, Self::Error> {
+        DomainName::try_from(&name)
+    }
+}
+
+impl TryFrom<&Record<'_>> for RData {
+    type Error = ProtoError;
+
+    fn try_from(record: &Record<'_>) -> Result<Self, Self::Error> {
+        Ok(match record {
+            Record::A(a) => RData::A(rdata::A(a.addr())),
+            Record::AAAA(aaaa) => RData::AAAA(rdata::AAAA(aaaa.addr())),
+            Record::CNAME(cname) => RData::CNAME(rdata::CNAME(cname.cname().try_into()?)),
+            Record::MX(mx) => RData::MX(rdata::MX::new(mx.preference(), mx.exchange().try_into()?)),
+            Record::NS(ns) => RData::NS(rdata::NS(ns.nsdname().try_into()?)),
+            Record::PTR(ptr) => RData::PTR(rdata::PTR(ptr.ptrdname().try_into()?)),
+            Record::TXT(txt) => RData::TXT(rdata::TXT::from_bytes(txt.entries().collect())),
+            Record::HINFO(hinfo) => RData::HINFO(rdata::HINFO::from_bytes(
+                hinfo.cpu().into(),
+                hinfo.os().into(),
+            )),
+            Record::SRV(srv) => RData::SRV(rdata::SRV::new(
+                srv.priority(),
+                srv.weight(),
+                srv.port(),
+                srv.target().try_into()?,
+            )),
+            // hickory uses signed integers for some of the SOA fields, but RFC 1035 defines all
+            // of them as unsigned.
+            Record::SOA(soa) => RData::SOA(rdata::SOA::new(
+                soa.mname().try_into()?,
+                soa.rname().try_into()?,
+                soa.serial(),
+                soa.refresh() as i32,
+                soa.retry() as i32,
+                soa.expire() as i32,
+                soa.minimum_ttl(),
+            )),
+            // hickory only supports DNSSEC records with its `dnssec` features enabled, and its
+            // service parameter and EDNS types don't map cleanly onto uwuhi's raw ones.
+            Record::DNSKEY(_)
+            | Record::RRSIG(_)
+            | Record::NSEC(_)
+            | Record::SVCB(_)
+            | Record::HTTPS(_)
+            | Record::OPT(_) => RData::Unknown {
+                code: RecordType::from(record.record_type().0),
+                rdata: rdata::NULL::with(record.encode_to_vec()),
+            },
+        })
+    }
+}
+
+impl TryFrom<&RData> for Record<'static> {
+    type Error = Error;
+
+    /// Converts hickory record data to a [`Record`].
+    ///
+    /// Returns [`Error::InvalidValue`] if the record type is not supported by uwuhi.
+    fn try_from(rdata: &RData) -> Result<Self, Self::Error> {
+        let name = |name: &Name| DomainName::try_from(name).map(Cow::Owned);
+        Ok(match rdata {
+            RData::A(a) => Record::A(A::new(a.0)),
+            RData::AAAA(aaaa) => Record::AAAA(AAAA::new(aaaa.0)),
+            RData::CNAME(cname) => Record::CNAME(CNAME::new(name(&cname.0)?)),
+            RData::MX(mx) => Record::MX(MX::new(mx.preference(), name(mx.exchange())?)),
+            RData::NS(ns) => Record::NS(NS::new(name(&ns.0)?)),
+            RData::PTR(ptr) => Record::PTR(PTR::new(name(&ptr.0)?)),
+            RData::TXT(txt) if txt.txt_data().is_empty() => {
+                // uwuhi requires at least one entry; an empty string is equivalent (RFC 6763).
+                Record::TXT(TXT::new([&b""[..]]))
+            }
+            RData::TXT(txt) => {
+                Record::TXT(TXT::new(txt.txt_data().iter().map(|entry| entry.to_vec())))
+            }
+            RData::HINFO(hinfo) => {
+                Record::HINFO(HINFO::new(hinfo.cpu().to_vec(), hinfo.os().to_vec()))
+            }
+            RData::SRV(srv) => Record::SRV(SRV::new(
+                srv.priority(),
+                srv.weight(),
+                srv.port(),
+                name(srv.target())?,
+            )),
+            RData::SOA(soa) => Record::SOA(SOA::new(
+                name(soa.mname())?,
+                name(soa.rname())?,
+                soa.serial(),
+                soa.refresh() as u32,
+                soa.retry() as u32,
+                soa.expire() as u32,
+                soa.minimum(),
+            )),
+            _ => return Err(Error::InvalidValue),
+        })
+    }
+}
+
+impl TryFrom<&decoder::ResourceRecord<'_>> for rr::Record {
+    type Error = ProtoError;
+
+    /// Converts a decoded resource record to a hickory [`Record`](rr::Record).
+    ///
+    /// Record types that uwuhi does not support are converted to [`RData::Unknown`]. The mDNS
+    /// cache-flush bit is preserved.
+    fn try_from(rr: &decoder::ResourceRecord<'_>) -> Result<Self, Self::Error> {
+        let rdata = match rr.as_enum() {
+            Some(Ok(record)) => RData::try_from(&record)?,
+            Some(Err(e)) => return Err(ProtoError::from(e.to_string())),
+            None => RData::Unknown {
+                code: RecordType::from(rr.type_().0),
+                rdata: rdata::NULL::with(rr.rdata().to_vec()),
+            },
+        };
+        let mut record = rr::Record::from_rdata(rr.name().try_into()?, rr.ttl(), rdata);
+        record
+            .set_dns_class(DNSClass::from(rr.class().0))
+            .set_mdns_cache_flush(rr.cache_flush());
+        Ok(record)
+    }
+}
+
+impl TryFrom<&decoder::Question> for Query {
+    type Error = ProtoError;
+
+    fn try_from(q: &decoder::Question) -> Result<Self, Self::Error> {
+        let mut query = Query::query(q.qname().try_into()?, RecordType::from(q.qtype().0));
+        query.set_query_class(DNSClass::from(q.qclass().0));
+        Ok(query)
+    }
+}
+
+impl<S: Section> TryFrom<&MessageDecoder<'_, S>> for Message {
+    type Error = ProtoError;
+
+    /// Decodes the message as a hickory [`Message`].
+    ///
+    /// The whole message is converted, regardless of which section the decoder is in.
+    fn try_from(dec: &MessageDecoder<'_, S>) -> Result<Self, Self::Error> {
+        Message::from_vec(dec.message())
+    }
+}
+
+impl TryFrom<&packet::Message> for Message {
+    type Error = ProtoError;
+
+    /// Converts an owned uwuhi [`packet::Message`] to a hickory [`Message`].
+    fn try_from(msg: &packet::Message) -> Result<Self, Self::Error> {
+        let mut buf = vec![0; usize::from(u16::MAX)];
+        let len = msg
+            .encode(&mut buf)
+            .map_err(|e| ProtoError::from(e.to_string()))?;
+        Message::from_vec(&buf[..len])
+    }
+}
+
+impl TryFrom<&Message> for packet::Message {
+    type Error = Error;
+
+    /// Converts a hickory [`Message`] to an owned uwuhi [`packet::Message`].
+    ///
+    /// If hickory fails to encode the message, the [`ProtoError`] is returned as an
+    /// [`Error::Io`] of kind [`io::ErrorKind::InvalidData`].
+    fn try_from(msg: &Message) -> Result<Self, Self::Error> {
+        let bytes = msg
+            .to_vec()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        packet::Message::decode(&bytes)
+    }
+}
+
+impl From<RecordType> for Type {
+    fn from(ty: RecordType) -> Self {
+        Type(ty.into())
+    }
+}
+
+impl From<Type> for RecordType {
+    fn from(ty: Type) -> Self {
+        RecordType::from(ty.0)
+    }
+}
+
+impl From<RecordType> for QType {
+    fn from(ty: RecordType) -> Self {
+        QType(ty.into())
+    }
+}
+
+impl From<QType> for RecordType {
+    fn from(ty: QType) -> Self {
+        RecordType::from(ty.0)
+    }
+}
+
+impl From<DNSClass> for Class {
+    fn from(class: DNSClass) -> Self {
+        Class(class.into())
+    }
+}
+
+impl From<Class> for DNSClass {
+    fn from(class: Class) -> Self {
+        DNSClass::from(class.0)
+    }
+}
+
+impl From<DNSClass> for QClass {
+    fn from(class: DNSClass) -> Self {
+        QClass(class.into())
+    }
+}
+
+impl From<QClass> for DNSClass {
+    fn from(class: QClass) -> Self {
+        DNSClass::from(class.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hickory_proto::op;
+
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let name = DomainName::from_str("_http._tcp.local").unwrap();
+        let hickory = Name::try_from(&name).unwrap();
+        assert_eq!(hickory.to_string(), "_http._tcp.local.");
+        assert_eq!(DomainName::try_from(&hickory).unwrap(), name);
+
+        let srv = Record::SRV(SRV::new(0, 1, 80, &name));
+        let rdata = RData::try_from(&srv).unwrap();
+        let back = Record::try_from(&rdata).unwrap();
+        assert_eq!(back.to_string(), srv.to_string());
+
+        let txt = RData::TXT(rdata::TXT::from_bytes(Vec::new()));
+        let Record::TXT(txt) = Record::try_from(&txt).unwrap() else {
+            panic!("expected TXT record");
+        };
+        assert_eq!(txt.entries().collect::<Vec<_>>(), [b""]);
+    }
+
+    #[test]
+    fn query_to_hickory() {
+        use crate::packet::{decoder::Question, Header};
+
+        let name = DomainName::from_str("_http._tcp.local").unwrap();
+        let mut header = Header::default();
+        header.set_id(1234);
+        header.set_recursion_desired(true);
+        let mut msg = packet::Message::new(header);
+        msg.questions_mut()
+            .push(Question::new(name.clone(), QType::PTR));
+        let mut question = Question::new(name, QType::SRV);
+        question.set_qclass(QClass::ANY);
+        msg.questions_mut().push(question);
+
+        let hickory = Message::try_from(&msg).unwrap();
+        assert_eq!(hickory.id(), 1234);
+        assert!(hickory.recursion_desired());
+        assert_eq!(hickory.message_type(), op::MessageType::Query);
+        let queries = hickory.queries();
+        assert_eq!(queries.len(), 2);
+        assert_eq!(queries[0].name().to_string(), "_http._tcp.local.");
+        assert_eq!(queries[0].query_type(), RecordType::PTR);
+        assert_eq!(queries[0].query_class(), DNSClass::IN);
+        assert_eq!(queries[1].query_type(), RecordType::SRV);
+        assert_eq!(queries[1].query_class(), DNSClass::ANY);
+    }
+
+    #[test]
+    fn response_from_hickory() {
+        let name = Name::from_ascii("host.example.com.").unwrap();
+        let zone = Name::from_ascii("example.com.").unwrap();
+        let mut hickory = Message::new();
+        hickory
+            .set_id(4321)
+            .set_message_type(op::MessageType::Response)
+            .set_authoritative(true)
+            .set_response_code(op::ResponseCode::NXDomain)
+            .add_query(Query::query(name.clone(), RecordType::A))
+            .add_answer(rr::Record::from_rdata(
+                name.clone(),
+                60,
+                RData::A(rdata::A::new(192, 0, 2, 1)),
+            ))
+            .add_name_server(rr::Record::from_rdata(
+                zone.clone(),
+                3600,
+                RData::SOA(rdata::SOA::new(zone.clone(), zone, 1, 2, 3, 4, 5)),
+            ));
+
+        let msg = packet::Message::try_from(&hickory).unwrap();
+        assert_eq!(msg.header().id(), 4321);
+        assert!(msg.header().is_response());
+        assert!(msg.header().is_authority());
+        assert_eq!(msg.header().rcode(), packet::RCode::NX_DOMAIN);
+        assert_eq!(msg.questions().len(), 1);
+        assert_eq!(msg.questions()[0].qtype(), QType::A);
+        assert_eq!(msg.answers().len(), 1);
+        assert_eq!(msg.answers()[0].ttl(), 60);
+        assert_eq!(
+            msg.answers()[0].record(),
+            Some(&Record::A(A::new([192, 0, 2, 1].into())))
+        );
+        let Some(Record::SOA(soa)) = msg.authority()[0].record() else {
+            panic!("expected SOA record");
+        };
+        assert_eq!(soa.minimum_ttl(), 5);
+        assert!(msg.additional().is_empty());
+
+        // Converting back yields the same message. hickory only fills in the section counts of
+        // decoded messages, so compare the encoded form.
+        let back = Message::try_from(&msg).unwrap();
+        assert_eq!(back.to_vec().unwrap(), hickory.to_vec().unwrap());
+    }
+}
@@ -1,13 +1,34 @@
 //! Unicast and Multicast DNS and DNS Service Discovery implementation.
 
+#[macro_use]
+mod trace;
+
+pub mod acl;
+#[cfg(any(test, feature = "persistent-cache"))]
+mod cache_file;
+pub mod clock;
+pub mod dnssd;
+pub mod dso;
 mod error;
+pub mod forwarder;
+pub mod gateway;
 mod hex;
+#[cfg(feature = "hickory")]
+mod hickory;
 pub mod name;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod net;
 mod num;
 pub mod packet;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod reflector;
 pub mod resolver;
+pub mod server;
 pub mod service;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod tap;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 pub use error::Error;
 
@@ -24,3 +45,49 @@ pub const DNS_BUFFER_SIZE: usize = 512;
 /// This constant is the size of packet receive buffers and does not have to accomodate IP and UDP
 /// headers. It still does, because I cannot be bothered.
 pub const MDNS_BUFFER_SIZE: usize = 1500;
+
+/// Largest mDNS message size permitted on networks with jumbo frames.
+///
+/// mDNS messages may be up to 9000 Bytes large, as long as they fit in the link MTU (RFC 6762,
+/// section 17). Resolvers, discoverers and advertisers can be configured to use buffers of this size
+/// instead of [`MDNS_BUFFER_SIZE`].
+pub const MDNS_MAX_MESSAGE_SIZE: usize = 9000;
+
+#[doc(hidden)]
+pub mod __private {
+    //! Helpers shared with `uwuhi-async`. These are not part of the public API.
+
+    use std::net::SocketAddr;
+
+    use crate::{DNS_BUFFER_SIZE, MDNS_BUFFER_SIZE};
+
+    /// Returns the default maximum message size to use when talking to `server`.
+    ///
+    /// This is [`DNS_BUFFER_SIZE`] for unicast DNS servers, and [`MDNS_BUFFER_SIZE`] for multicast
+    /// protocols.
+    pub fn default_max_message_size(server: SocketAddr) -> usize {
+        if server.ip().is_multicast() {
+            MDNS_BUFFER_SIZE
+        } else {
+            DNS_BUFFER_SIZE
+        }
+    }
+
+    /// Validates a maximum message size passed to a `set_max_message_size` method.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is smaller than [`DNS_BUFFER_SIZE`] or doesn't fit in a UDP datagram.
+    pub fn checked_message_size(size: usize) -> usize {
+        assert!(
+            (DNS_BUFFER_SIZE..=usize::from(u16::MAX)).contains(&size),
+            "maximum message size must be between {} and {} bytes, got {}",
+            DNS_BUFFER_SIZE,
+            u16::MAX,
+            size,
+        );
+        size
+    }
+}
+
+pub(crate) use __private::{checked_message_size, default_max_message_size};
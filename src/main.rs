@@ -0,0 +1,420 @@
+//! The `uwuhi` command-line tool.
+//!
+//! Exposes the library's DNS, mDNS and DNS-SD functionality as subcommands. Run `uwuhi help` for
+//! usage information.
+
+use std::{
+    env,
+    fmt::Write as _,
+    io,
+    net::{IpAddr, SocketAddr},
+    ops::ControlFlow,
+    process,
+    time::Instant,
+};
+
+use uwuhi::{
+    dnssd::{self, BrowseEvent, RegistrationEvent},
+    name::{DomainName, Label},
+    net::MulticastSocketBuilder,
+    packet::{
+        decoder::{MessageDecoder, ResourceRecord},
+        records::TXT,
+    },
+    resolver::{hosts::HostsFile, ChainedResolver, SyncResolver},
+    service::{discovery::SyncDiscoverer, Service, ServiceInstance, TxtRecords},
+    tap::AnomalyDetector,
+    Error, MDNS_BUFFER_SIZE,
+};
+
+const USAGE: &str = "\
+usage: uwuhi <command> [args]
+
+commands:
+    resolve <name> [--server <addr>]
+        Resolve a host name. Names ending in `.local` are resolved via mDNS, other names via the
+        hosts file and the given DNS server (default: 8.8.8.8:53). If `name` is an IP address,
+        its host names are looked up via the DNS server instead.
+    browse [service]
+        Without arguments, list all service types on the local network. Otherwise, continuously
+        browse for instances of `service` (eg. `_http._tcp`).
+    advertise <service> <port> [--name <instance>] [--txt <key[=value]>]...
+        Advertise an instance of `service` running on `port` of this machine, until interrupted.
+    tap [--json]
+        Print every mDNS packet received on the local network, and warn about suspicious ones
+        (like responses to questions nobody asked).
+    help
+        Print this message.
+
+The log level can be controlled via the `RUST_LOG` environment variable.";
+
+/// Default DNS server to use for non-`.local` names.
+const DEFAULT_SERVER: &str = "8.8.8.8:53";
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let Some((command, args)) = args.split_first() else {
+        usage_error("no command given");
+    };
+    let res = match command.as_str() {
+        "resolve" => resolve(args),
+        "browse" => browse(args),
+        "advertise" => advertise(args),
+        "tap" => tap(args),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => usage_error(&format!("unknown command '{}'", command)),
+    };
+    if let Err(e) = res {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("error: {}\n\n{}", msg, USAGE);
+    process::exit(2);
+}
+
+fn resolve(args: &[String]) -> io::Result<()> {
+    let (name, server) = match args {
+        [name] => (name, DEFAULT_SERVER),
+        [name, flag, server] if flag == "--server" => (name, server.as_str()),
+        _ => usage_error("invalid arguments to `resolve`"),
+    };
+
+    if let Ok(addr) = name.parse::<IpAddr>() {
+        let server = parse_server(server)?;
+        let names = SyncResolver::new(server)?.resolve_addr(addr)?;
+        if names.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no host names found for '{}'", addr),
+            ));
+        }
+        for name in names {
+            println!("{}", name);
+        }
+        return Ok(());
+    }
+
+    let name = name.trim_end_matches('.');
+    let addrs = if name.ends_with(".local") {
+        SyncResolver::new_multicast_v4()?
+            .resolve(name)?
+            .collect::<Vec<_>>()
+    } else {
+        let server = parse_server(server)?;
+        let mut chain = ChainedResolver::new();
+        match HostsFile::load() {
+            Ok(hosts) => chain.push(hosts),
+            Err(e) => log::debug!("failed to load hosts file: {}", e),
+        }
+        chain.push(SyncResolver::new(server)?);
+        chain.resolve(name)?
+    };
+
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no addresses found for '{}'", name),
+        ));
+    }
+    for addr in addrs {
+        println!("{}", addr);
+    }
+    Ok(())
+}
+
+fn parse_server(server: &str) -> io::Result<SocketAddr> {
+    if let Ok(addr) = server.parse() {
+        return Ok(addr);
+    }
+    match server.parse::<IpAddr>() {
+        Ok(ip) => Ok(SocketAddr::new(ip, 53)),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid server address '{}'", server),
+        )),
+    }
+}
+
+fn browse(args: &[String]) -> io::Result<()> {
+    match args {
+        [] => list_service_types(),
+        [service] => browse_service(parse_service(service)?),
+        _ => usage_error("invalid arguments to `browse`"),
+    }
+}
+
+fn list_service_types() -> io::Result<()> {
+    let mut discoverer = SyncDiscoverer::new_multicast_v4()?;
+    discoverer.discover_service_types(|service| {
+        println!("{}", service);
+        ControlFlow::Continue(())
+    })?;
+    Ok(())
+}
+
+fn browse_service(service: Service) -> io::Result<()> {
+    let browser = dnssd::browse(service)?;
+    log::info!("browsing via {:?} backend", browser.backend());
+    for event in browser.events() {
+        match event {
+            BrowseEvent::Found(instance) => println!("+ {}", instance),
+            BrowseEvent::Resolved(instance, details) | BrowseEvent::Updated(instance, details) => {
+                print!("= {} at {}:{}", instance, details.host(), details.port());
+                if !details.txt_records().is_empty() {
+                    print!(" [{}]", details.txt_records());
+                }
+                println!();
+            }
+            BrowseEvent::Lost(instance) => println!("- {}", instance),
+            BrowseEvent::Failed(e) => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn advertise(args: &[String]) -> io::Result<()> {
+    let [service, port, flags @ ..] = args else {
+        usage_error("invalid arguments to `advertise`");
+    };
+    let service = parse_service(service)?;
+    let Ok(port) = port.parse::<u16>() else {
+        usage_error(&format!("invalid port '{}'", port));
+    };
+
+    let mut name = None;
+    let mut txt = Vec::new();
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        let Some(value) = flags.next() else {
+            usage_error(&format!("missing value for '{}'", flag));
+        };
+        match flag.as_str() {
+            "--name" => name = Some(value.clone()),
+            "--txt" => txt.push(value.as_bytes().to_vec()),
+            _ => usage_error(&format!("unknown flag '{}'", flag)),
+        }
+    }
+
+    let name = match name {
+        Some(name) => name,
+        None => format!("uwuhi on port {}", port),
+    };
+    let name = Label::try_new(&name).map_err(io::Error::from)?;
+    let txt = if txt.is_empty() {
+        TxtRecords::new()
+    } else {
+        TxtRecords::from_txt(&TXT::new(txt))
+    };
+
+    let registration = dnssd::register(ServiceInstance::from_service(name, service), port, txt)?;
+    log::info!("advertising via {:?} backend", registration.backend());
+    for event in registration.events() {
+        match event {
+            RegistrationEvent::Registered(instance) => println!("registered {}", instance),
+            RegistrationEvent::Failed(e) => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn parse_service(s: &str) -> io::Result<Service> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid service type '{}' (expected eg. `_http._tcp`)", s),
+        )
+    };
+    let s = s.trim_end_matches('.').trim_end_matches(".local");
+    let (name, transport) = s.split_once('.').ok_or_else(invalid)?;
+    if !name.starts_with('_') {
+        return Err(invalid());
+    }
+    let name = Label::try_new(name).map_err(|_| invalid())?;
+    let transport = transport.parse().map_err(|_| invalid())?;
+    Ok(Service::new(name, transport))
+}
+
+fn tap(args: &[String]) -> io::Result<()> {
+    let json = match args {
+        [] => false,
+        [flag] if flag == "--json" => true,
+        _ => usage_error("invalid arguments to `tap`"),
+    };
+
+    let sock = MulticastSocketBuilder::mdns_v4().build()?;
+    let mut detector = AnomalyDetector::new();
+    let mut buf = [0; MDNS_BUFFER_SIZE];
+    loop {
+        let (len, addr) = sock.recv_from(&mut buf)?;
+        match Packet::decode(&buf[..len]) {
+            Ok(packet) if json => println!("{}", packet.to_json(addr)),
+            Ok(packet) => print!("{}", packet.to_text(addr)),
+            Err(e) => log::warn!("failed to decode packet from {}: {}", addr, e),
+        }
+        let anomalies = detector.process(addr, &buf[..len], Instant::now());
+        for anomaly in anomalies.unwrap_or_default() {
+            match json {
+                true => println!(r#"{{"anomaly":{}}}"#, json_string(&anomaly.to_string())),
+                false => println!("anomaly: {}", anomaly),
+            }
+        }
+    }
+}
+
+/// A decoded packet, for output by the `tap` command.
+struct Packet {
+    id: u16,
+    response: bool,
+    questions: Vec<(DomainName, String, String)>,
+    /// Records from all sections, with the name of their section.
+    records: Vec<(&'static str, RecordInfo)>,
+}
+
+struct RecordInfo {
+    name: DomainName,
+    ty: String,
+    class: String,
+    ttl: u32,
+    data: String,
+}
+
+impl RecordInfo {
+    fn new(rr: &ResourceRecord<'_>) -> Self {
+        let data = match rr.as_enum() {
+            Some(Ok(record)) => record.to_string(),
+            Some(Err(e)) => format!("<{}>", e),
+            None => format!("{:02x?}", rr.rdata()),
+        };
+        Self {
+            name: rr.name().clone(),
+            ty: rr.type_().to_string(),
+            class: rr.class().to_string(),
+            ttl: rr.ttl(),
+            data,
+        }
+    }
+}
+
+impl Packet {
+    fn decode(msg: &[u8]) -> Result<Self, Error> {
+        let mut dec = MessageDecoder::new(msg)?;
+        let header = *dec.header();
+
+        let mut questions = Vec::new();
+        for q in dec.iter() {
+            let q = q?;
+            questions.push((
+                q.qname().clone(),
+                q.qtype().to_string(),
+                q.qclass().to_string(),
+            ));
+        }
+
+        let mut records = Vec::new();
+        let mut dec = dec.answers()?;
+        for rr in dec.iter() {
+            records.push(("answer", RecordInfo::new(&rr?)));
+        }
+        let mut dec = dec.authority()?;
+        for rr in dec.iter() {
+            records.push(("authority", RecordInfo::new(&rr?)));
+        }
+        let mut dec = dec.additional()?;
+        for rr in dec.iter() {
+            records.push(("additional", RecordInfo::new(&rr?)));
+        }
+
+        Ok(Self {
+            id: header.id(),
+            response: header.is_response(),
+            questions,
+            records,
+        })
+    }
+
+    fn to_text(&self, from: SocketAddr) -> String {
+        let kind = if self.response { "response" } else { "query" };
+        let mut out = format!("{} from {} (id {})\n", kind, from, self.id);
+        for (name, ty, class) in &self.questions {
+            writeln!(out, "  question\t{}\t{}\t{}", name, class, ty).unwrap();
+        }
+        for (section, rr) in &self.records {
+            writeln!(
+                out,
+                "  {}\t{}\t{}\t{}\t{}\t{}",
+                section, rr.name, rr.ttl, rr.class, rr.ty, rr.data
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    fn to_json(&self, from: SocketAddr) -> String {
+        let mut out = format!(
+            r#"{{"from":{},"id":{},"response":{},"questions":["#,
+            json_string(&from.to_string()),
+            self.id,
+            self.response,
+        );
+        for (i, (name, ty, class)) in self.questions.iter().enumerate() {
+            if i != 0 {
+                out.push(',');
+            }
+            write!(
+                out,
+                r#"{{"name":{},"type":{},"class":{}}}"#,
+                json_string(&name.to_string()),
+                json_string(ty),
+                json_string(class),
+            )
+            .unwrap();
+        }
+        out.push_str(r#"],"records":["#);
+        for (i, (section, rr)) in self.records.iter().enumerate() {
+            if i != 0 {
+                out.push(',');
+            }
+            write!(
+                out,
+                r#"{{"section":"{}","name":{},"type":{},"class":{},"ttl":{},"data":{}}}"#,
+                section,
+                json_string(&rr.name.to_string()),
+                json_string(&rr.ty),
+                json_string(&rr.class),
+                rr.ttl,
+                json_string(&rr.data),
+            )
+            .unwrap();
+        }
+        out.push_str("]}");
+        out
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -1,29 +1,92 @@
 //! Domain names and labels.
+//!
+//! Names and labels that are known at compile time can be created with the [`domain!`] and
+//! [`label!`] macros, which validate them during compilation and don't allocate.
+//!
+//! [`SmallDomainName`] stores a name in a fixed-size buffer instead, for code paths that must not
+//! allocate at all.
+//!
+//! [`domain!`]: crate::domain
+//! [`label!`]: crate::label
 
 use std::{
+    array,
     borrow::Cow,
+    cmp::Ordering,
     fmt::{self, Write},
-    slice,
+    hash::{Hash, Hasher},
+    iter,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ops, slice,
     str::FromStr,
     vec,
 };
 
-use crate::Error;
+use crate::{label, Error};
+
+mod small;
+
+pub use small::{SmallDomainName, SmallLabels};
 
 /// A `.`-separated component of a [`DomainName`].
 ///
 /// Labels consist of arbitrary bytes and have a maximum length of 63 bytes. This type can only
 /// represent non-empty labels, so the minimum length is 1 byte.
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+///
+/// Short labels (which includes almost every label found in practice) are stored inline, without
+/// a heap allocation.
+#[derive(Clone)]
 pub struct Label {
-    // Guaranteed to contain >0 and at most `Label::MAX_LEN` bytes.
-    bytes: Box<[u8]>,
+    // Guaranteed to contain >0 and at most `Label::MAX_LEN` bytes (except for `PLACEHOLDER`).
+    repr: LabelRepr,
+}
+
+/// Labels up to this length are stored inline.
+///
+/// This is the largest size that doesn't make [`Label`] larger than a boxed slice plus tag.
+const INLINE_LABEL_LEN: usize = 22;
+
+#[derive(Clone)]
+enum LabelRepr {
+    Inline {
+        len: u8,
+        bytes: [u8; INLINE_LABEL_LEN],
+    },
+    Heap(Box<[u8]>),
+    Static(&'static [u8]),
 }
 
 impl Label {
     /// The maximum length of a domain label.
     pub const MAX_LEN: usize = 0b0011_1111;
 
+    /// An (invalid) empty label used to fill the unused inline slots of a [`DomainName`].
+    const PLACEHOLDER: Self = Self {
+        repr: LabelRepr::Inline {
+            len: 0,
+            bytes: [0; INLINE_LABEL_LEN],
+        },
+    };
+
+    /// Creates a [`Label`] that refers to a `'static` string, without allocating.
+    ///
+    /// This is a `const fn`, so when called in a `const` context (like the [`label!`] macro does),
+    /// an invalid label results in a compile-time error.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `label` is empty or contains more than [`Self::MAX_LEN`] bytes.
+    ///
+    /// [`label!`]: crate::label
+    pub const fn from_static(label: &'static str) -> Self {
+        let label = label.as_bytes();
+        assert!(!label.is_empty(), "empty label");
+        assert!(label.len() <= Self::MAX_LEN, "label too long");
+        Self {
+            repr: LabelRepr::Static(label),
+        }
+    }
+
     /// Creates a [`Label`] from raw bytes or a string slice, panicking if the bytes are an invalid
     /// label.
     ///
@@ -54,15 +117,156 @@ impl Label {
             return Err(Error::LabelTooLong);
         }
 
-        Ok(Self {
-            bytes: label.into(),
-        })
+        let repr = if label.len() <= INLINE_LABEL_LEN {
+            let mut bytes = [0; INLINE_LABEL_LEN];
+            bytes[..label.len()].copy_from_slice(label);
+            LabelRepr::Inline {
+                len: label.len() as u8,
+                bytes,
+            }
+        } else {
+            LabelRepr::Heap(label.into())
+        };
+        Ok(Self { repr })
+    }
+
+    /// Creates a [`Label`] from its escaped text form, as used in zone files and printed by tools
+    /// like `dns-sd` and `avahi-browse`.
+    ///
+    /// A `\` followed by three decimal digits stands for the byte with that value (eg. `\032` for a
+    /// space), and a `\` followed by any other character stands for that character (eg. `\.` for a
+    /// literal dot, and `\\` for a backslash). All other characters are taken as-is.
+    ///
+    /// Returns [`Error::InvalidValue`] if `s` contains an incomplete or out-of-range escape.
+    pub fn from_escaped(s: &str) -> Result<Self, Error> {
+        let mut bytes = Vec::with_capacity(s.len());
+        let mut rest = s.as_bytes();
+        while let Some((&b, tail)) = rest.split_first() {
+            rest = tail;
+            if b != b'\\' {
+                bytes.push(b);
+                continue;
+            }
+            match rest {
+                [b'0'..=b'9', ..] => {
+                    let digits = rest.get(..3).ok_or(Error::InvalidValue)?;
+                    if !digits.iter().all(u8::is_ascii_digit) {
+                        return Err(Error::InvalidValue);
+                    }
+                    let value = digits
+                        .iter()
+                        .fold(0u32, |acc, d| acc * 10 + u32::from(d - b'0'));
+                    bytes.push(u8::try_from(value).map_err(|_| Error::InvalidValue)?);
+                    rest = &rest[3..];
+                }
+                // Multi-byte characters are copied over byte by byte, the escape has no effect.
+                [c, tail @ ..] => {
+                    bytes.push(*c);
+                    rest = tail;
+                }
+                [] => return Err(Error::InvalidValue),
+            }
+        }
+        Self::try_new(bytes)
+    }
+
+    /// Returns the contents of this label as text meant to be shown to users.
+    ///
+    /// DNS-SD instance names are free-form UTF-8 text ([RFC 6763, section 4.1.1]), which may
+    /// contain spaces, dots and punctuation. Unlike the [`Display`](fmt::Display) impl, this does
+    /// not escape anything, so "Anne's AirPort" is returned as-is. Invalid UTF-8 sequences are
+    /// replaced with `U+FFFD REPLACEMENT CHARACTER`.
+    ///
+    /// [RFC 6763, section 4.1.1]: https://datatracker.ietf.org/doc/html/rfc6763#section-4.1.1
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(self.as_bytes())
     }
 
     /// Returns the raw bytes of this label.
     #[inline]
     pub fn as_bytes(&self) -> &[u8] {
-        &self.bytes
+        match &self.repr {
+            LabelRepr::Inline { len, bytes } => &bytes[..usize::from(*len)],
+            LabelRepr::Heap(bytes) => bytes,
+            LabelRepr::Static(bytes) => bytes,
+        }
+    }
+
+    /// Compares two labels, ignoring ASCII case.
+    ///
+    /// DNS names are case-insensitive ([RFC 4343]), but only for ASCII letters. Bytes outside of
+    /// `A-Z` and `a-z` have to match exactly.
+    ///
+    /// [RFC 4343]: https://datatracker.ietf.org/doc/html/rfc4343
+    pub fn eq_ignore_ascii_case(&self, other: &Label) -> bool {
+        match (&self.repr, &other.repr) {
+            (
+                LabelRepr::Inline { len, bytes },
+                LabelRepr::Inline {
+                    len: other_len,
+                    bytes: other_bytes,
+                },
+            ) => {
+                // The unused bytes of inline labels are always zero, so the whole buffer can be
+                // compared with a fixed number of word operations, without any length-dependent
+                // branches.
+                let (a, b) = (inline_words(bytes), inline_words(other_bytes));
+                let diff = (0..a.len()).fold(0, |diff, i| {
+                    diff | fold_ascii_case(a[i]) ^ fold_ascii_case(b[i])
+                });
+                len == other_len && diff == 0
+            }
+            _ => {
+                let (a, b) = (self.as_bytes(), other.as_bytes());
+                a == b || a.eq_ignore_ascii_case(b)
+            }
+        }
+    }
+
+    /// Returns a copy of this label with all ASCII letters converted to lowercase.
+    ///
+    /// If the label doesn't contain any uppercase letters, this is just a [`Clone`].
+    pub fn to_ascii_lowercase(&self) -> Label {
+        let bytes = self.as_bytes();
+        if !bytes.iter().any(u8::is_ascii_uppercase) {
+            return self.clone();
+        }
+        let mut lower = [0; Self::MAX_LEN];
+        let lower = &mut lower[..bytes.len()];
+        lower.copy_from_slice(bytes);
+        for chunk in lower.chunks_mut(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            let word = fold_ascii_case(u64::from_ne_bytes(word)).to_ne_bytes();
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+        Label::new(lower)
+    }
+}
+
+impl PartialEq for Label {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl Eq for Label {}
+
+impl PartialOrd for Label {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Label {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_bytes().cmp(other.as_bytes())
+    }
+}
+
+impl Hash for Label {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_bytes().hash(state);
     }
 }
 
@@ -94,12 +298,25 @@ impl FromStr for Label {
 #[derive(PartialEq, Eq, Clone)]
 pub struct DomainName {
     // Does not include the trailing empty label.
-    labels: Vec<Label>,
+    labels: Labels,
 }
 
 impl DomainName {
     /// The empty root domain `.`.
-    pub const ROOT: Self = Self { labels: Vec::new() };
+    pub const ROOT: Self = Self {
+        labels: Labels::new(),
+    };
+
+    /// Creates a [`DomainName`] from a `'static` list of labels, without allocating.
+    ///
+    /// This is used by the [`domain!`] macro, which is usually more convenient.
+    ///
+    /// [`domain!`]: crate::domain
+    pub const fn from_static_labels(labels: &'static [Label]) -> Self {
+        Self {
+            labels: Labels::Static(labels),
+        }
+    }
 
     /// Parses a domain name as a string of `.`-separated labels.
     ///
@@ -107,16 +324,61 @@ impl DomainName {
     ///
     /// The [`FromStr`] implementation performs the same operation. This method is just a
     /// convenience function so that you don't have to import that trait.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Result<Self, Error> {
         s.parse()
     }
 
+    /// Returns the name used for reverse lookups of `addr`, in the `in-addr.arpa` or `ip6.arpa`
+    /// domain.
+    ///
+    /// See [`DomainName::arpa_v4`] and [`DomainName::arpa_v6`].
+    pub fn arpa(addr: IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(addr) => Self::arpa_v4(addr),
+            IpAddr::V6(addr) => Self::arpa_v6(addr),
+        }
+    }
+
+    /// Returns the name used for reverse lookups of an IPv4 address: its octets in reverse order,
+    /// followed by `in-addr.arpa` ([RFC 1035, section 3.5]).
+    ///
+    /// For example, the name for `192.0.2.1` is `1.2.0.192.in-addr.arpa`.
+    ///
+    /// [RFC 1035, section 3.5]: https://datatracker.ietf.org/doc/html/rfc1035#section-3.5
+    pub fn arpa_v4(addr: Ipv4Addr) -> Self {
+        let mut name = addr
+            .octets()
+            .iter()
+            .rev()
+            .map(|octet| Label::new(octet.to_string()))
+            .collect::<DomainName>();
+        name.extend([label!("in-addr"), label!("arpa")]);
+        name
+    }
+
+    /// Returns the name used for reverse lookups of an IPv6 address: its nibbles in reverse order,
+    /// as lowercase hex digits, followed by `ip6.arpa` ([RFC 3596, section 2.5]).
+    ///
+    /// [RFC 3596, section 2.5]: https://datatracker.ietf.org/doc/html/rfc3596#section-2.5
+    pub fn arpa_v6(addr: Ipv6Addr) -> Self {
+        let mut name = addr
+            .octets()
+            .iter()
+            .rev()
+            .flat_map(|octet| [octet & 0xf, octet >> 4])
+            .map(|nibble| Label::new(format!("{:x}", nibble)))
+            .collect::<DomainName>();
+        name.extend([label!("ip6"), label!("arpa")]);
+        name
+    }
+
     /// Returns the `.`-separated labels making up this domain name.
     ///
     /// The trailing empty label is not included.
     #[inline]
     pub fn labels(&self) -> &[Label] {
-        &self.labels
+        self.labels.as_slice()
     }
 
     /// Appends a [`Label`] to the end of this domain name.
@@ -124,6 +386,82 @@ impl DomainName {
     pub fn push_label(&mut self, label: Label) {
         self.labels.push(label);
     }
+
+    /// Removes all labels, turning this into the root domain while keeping allocated storage.
+    pub(crate) fn clear(&mut self) {
+        self.labels.clear();
+    }
+
+    /// Compares two domain names, ignoring ASCII case.
+    ///
+    /// This is how DNS names are compared ([RFC 4343]). The [`PartialEq`] implementation of
+    /// [`DomainName`], on the other hand, is case-sensitive.
+    ///
+    /// [RFC 4343]: https://datatracker.ietf.org/doc/html/rfc4343
+    pub fn eq_ignore_ascii_case(&self, other: &DomainName) -> bool {
+        self.labels().len() == other.labels().len()
+            && self
+                .labels()
+                .iter()
+                .zip(other.labels())
+                .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+
+    /// Feeds this name into `state`, ignoring ASCII case.
+    ///
+    /// Names that are equal according to [`DomainName::eq_ignore_ascii_case`] produce the same
+    /// hash, so this can be used to implement [`Hash`] for case-insensitive map keys without
+    /// having to lowercase (and possibly allocate) the name first.
+    pub fn hash_ignore_ascii_case<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.labels().len());
+        for label in self.labels() {
+            let bytes = label.as_bytes();
+            state.write_u8(bytes.len() as u8);
+            let mut chunks = bytes.chunks_exact(8);
+            for chunk in &mut chunks {
+                state.write_u64(fold_ascii_case(u64::from_ne_bytes(
+                    chunk.try_into().unwrap(),
+                )));
+            }
+            for byte in chunks.remainder() {
+                state.write_u8(byte.to_ascii_lowercase());
+            }
+        }
+    }
+
+    /// Returns a copy of this name with all ASCII letters converted to lowercase.
+    pub fn to_ascii_lowercase(&self) -> DomainName {
+        self.labels()
+            .iter()
+            .map(Label::to_ascii_lowercase)
+            .collect()
+    }
+}
+
+/// Splits the inline storage of a [`Label`] into (overlapping) words.
+#[inline]
+fn inline_words(bytes: &[u8; INLINE_LABEL_LEN]) -> [u64; 3] {
+    const LAST: usize = INLINE_LABEL_LEN - 8;
+    [0, 8, LAST].map(|start| u64::from_ne_bytes(bytes[start..start + 8].try_into().unwrap()))
+}
+
+/// Converts the ASCII uppercase letters among the 8 bytes packed into `word` to lowercase.
+///
+/// All other bytes (including non-ASCII ones) are left unchanged.
+#[inline]
+fn fold_ascii_case(word: u64) -> u64 {
+    const ONES: u64 = 0x0101_0101_0101_0101;
+    const HIGH: u64 = 0x8080_8080_8080_8080;
+
+    // Clearing the high bits ensures that the additions below never carry into the next byte.
+    let low = word & !HIGH;
+    // The high bit of each byte is set if the byte is >= b'A' or > b'Z', respectively.
+    let ge_a = low + ONES * u64::from(0x80 - b'A');
+    let gt_z = low + ONES * u64::from(0x80 - b'Z' - 1);
+    // Bytes that had the high bit set in `word` aren't ASCII.
+    let upper = ge_a & !gt_z & !word & HIGH;
+    // Setting bit 5 (0x20) converts an uppercase ASCII letter to lowercase.
+    word | (upper >> 2)
 }
 
 impl From<DomainName> for Cow<'_, DomainName> {
@@ -140,29 +478,29 @@ impl<'a> From<&'a DomainName> for Cow<'a, DomainName> {
 
 impl Extend<Label> for DomainName {
     fn extend<T: IntoIterator<Item = Label>>(&mut self, iter: T) {
-        self.labels.extend(iter)
+        for label in iter {
+            self.labels.push(label);
+        }
     }
 }
 
 impl<'a> Extend<&'a Label> for DomainName {
     fn extend<T: IntoIterator<Item = &'a Label>>(&mut self, iter: T) {
-        self.labels.extend(iter.into_iter().cloned())
+        self.extend(iter.into_iter().cloned());
     }
 }
 
 impl FromIterator<Label> for DomainName {
     fn from_iter<T: IntoIterator<Item = Label>>(iter: T) -> Self {
-        Self {
-            labels: Vec::from_iter(iter),
-        }
+        let mut name = Self::ROOT;
+        name.extend(iter);
+        name
     }
 }
 
 impl<'a> FromIterator<&'a Label> for DomainName {
     fn from_iter<T: IntoIterator<Item = &'a Label>>(iter: T) -> Self {
-        Self {
-            labels: Vec::from_iter(iter.into_iter().cloned()),
-        }
+        iter.into_iter().cloned().collect()
     }
 }
 
@@ -172,9 +510,14 @@ impl IntoIterator for DomainName {
 
     #[inline]
     fn into_iter(self) -> Self::IntoIter {
-        IntoIter {
-            inner: self.labels.into_iter(),
-        }
+        let inner = match self.labels {
+            Labels::Inline { len, labels } => {
+                IntoIterInner::Inline(labels.into_iter().take(usize::from(len)))
+            }
+            Labels::Heap(labels) => IntoIterInner::Heap(labels.into_iter()),
+            Labels::Static(labels) => IntoIterInner::Static(labels.iter()),
+        };
+        IntoIter { inner }
     }
 }
 
@@ -189,12 +532,18 @@ impl<'a> IntoIterator for &'a DomainName {
     }
 }
 
+impl Hash for DomainName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.labels().hash(state);
+    }
+}
+
 impl fmt::Debug for DomainName {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if self.labels.is_empty() {
+        if self.labels().is_empty() {
             return f.write_char('.');
         }
-        for label in &self.labels {
+        for label in self.labels() {
             label.fmt(f)?;
             f.write_char('.')?;
         }
@@ -204,10 +553,10 @@ impl fmt::Debug for DomainName {
 
 impl fmt::Display for DomainName {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if self.labels.is_empty() {
+        if self.labels().is_empty() {
             return f.write_char('.');
         }
-        for label in &self.labels {
+        for label in self.labels() {
             label.fmt(f)?;
             f.write_char('.')?;
         }
@@ -224,7 +573,7 @@ impl FromStr for DomainName {
             return Ok(Self::ROOT);
         }
 
-        let mut name = DomainName { labels: Vec::new() };
+        let mut name = DomainName::ROOT;
         for label in s.split_terminator('.') {
             name.labels.push(label.parse()?);
         }
@@ -232,9 +581,210 @@ impl FromStr for DomainName {
     }
 }
 
+/// Labels of a name are stored inline up to this number.
+///
+/// This covers DNS-SD instance names (`<instance>._service._proto.local`) and most host names.
+const INLINE_LABELS: usize = 4;
+
+/// A `SmallVec`-style list of [`Label`]s that avoids allocating for short names.
+#[derive(Clone)]
+enum Labels {
+    Inline {
+        len: u8,
+        /// Slots at `len..` contain [`Label::PLACEHOLDER`].
+        labels: [Label; INLINE_LABELS],
+    },
+    Heap(Vec<Label>),
+    Static(&'static [Label]),
+}
+
+impl Labels {
+    const fn new() -> Self {
+        Labels::Inline {
+            len: 0,
+            labels: [Label::PLACEHOLDER; INLINE_LABELS],
+        }
+    }
+
+    fn as_slice(&self) -> &[Label] {
+        match self {
+            Labels::Inline { len, labels } => &labels[..usize::from(*len)],
+            Labels::Heap(labels) => labels,
+            Labels::Static(labels) => labels,
+        }
+    }
+
+    fn clear(&mut self) {
+        match self {
+            Labels::Inline { len, labels } => {
+                labels[..usize::from(*len)].fill(Label::PLACEHOLDER);
+                *len = 0;
+            }
+            Labels::Heap(labels) => labels.clear(),
+            Labels::Static(_) => *self = Labels::new(),
+        }
+    }
+
+    fn push(&mut self, label: Label) {
+        match self {
+            Labels::Inline { len, labels } if usize::from(*len) < INLINE_LABELS => {
+                labels[usize::from(*len)] = label;
+                *len += 1;
+            }
+            Labels::Inline { labels, .. } => {
+                let mut vec = Vec::with_capacity(INLINE_LABELS * 2);
+                vec.extend(
+                    labels
+                        .iter_mut()
+                        .map(|l| std::mem::replace(l, Label::PLACEHOLDER)),
+                );
+                vec.push(label);
+                *self = Labels::Heap(vec);
+            }
+            Labels::Heap(labels) => labels.push(label),
+            Labels::Static(labels) => {
+                let mut vec = labels.to_vec();
+                vec.push(label);
+                *self = Labels::Heap(vec);
+            }
+        }
+    }
+}
+
+impl ops::Deref for Labels {
+    type Target = [Label];
+
+    fn deref(&self) -> &[Label] {
+        self.as_slice()
+    }
+}
+
+impl PartialEq for Labels {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for Labels {}
+
+/// Creates a [`Label`] from a string literal, validating it at compile time.
+///
+/// # Example
+///
+/// ```
+/// # use uwuhi::label;
+/// let label = label!("_http");
+/// assert_eq!(label.as_bytes(), b"_http");
+/// ```
+///
+/// Invalid labels fail to compile:
+///
+/// ```compile_fail
+/// # use uwuhi::label;
+/// let label = label!("");
+/// ```
+#[macro_export]
+macro_rules! label {
+    ($label:literal) => {{
+        const LABEL: $crate::name::Label = $crate::name::Label::from_static($label);
+        LABEL
+    }};
+}
+
+/// Creates a [`DomainName`] from a string literal, validating it at compile time.
+///
+/// The string uses the same syntax as [`DomainName::from_str`]. The resulting name refers to
+/// static data and does not allocate.
+///
+/// # Example
+///
+/// ```
+/// # use uwuhi::domain;
+/// let name = domain!("_http._tcp.local");
+/// assert_eq!(name.labels().len(), 3);
+/// assert_eq!(name.to_string(), "_http._tcp.local.");
+/// ```
+///
+/// Invalid names fail to compile:
+///
+/// ```compile_fail
+/// # use uwuhi::domain;
+/// let name = domain!("example..com");
+/// ```
+#[macro_export]
+macro_rules! domain {
+    ($name:literal) => {{
+        static LABELS: [$crate::name::Label; $crate::name::__private::count_labels($name)] =
+            $crate::name::__private::parse_labels($name);
+        $crate::name::DomainName::from_static_labels(&LABELS)
+    }};
+}
+
+#[doc(hidden)]
+pub mod __private {
+    use super::Label;
+
+    /// The maximum length of an encoded domain name.
+    const MAX_NAME_LEN: usize = 255;
+
+    pub const fn count_labels(name: &str) -> usize {
+        let name = name.as_bytes();
+        if name.is_empty() || (name.len() == 1 && name[0] == b'.') {
+            return 0;
+        }
+        let mut count = 0;
+        let mut i = 0;
+        while i < name.len() {
+            if name[i] == b'.' {
+                count += 1;
+            }
+            i += 1;
+        }
+        if name[name.len() - 1] != b'.' {
+            // No trailing dot, so the last label isn't terminated.
+            count += 1;
+        }
+        count
+    }
+
+    pub const fn parse_labels<const N: usize>(name: &'static str) -> [Label; N] {
+        let mut labels = [Label::PLACEHOLDER; N];
+        let mut rest = name.as_bytes();
+        let mut encoded_len = 1;
+        let mut i = 0;
+        while i < N {
+            let mut len = 0;
+            while len < rest.len() && rest[len] != b'.' {
+                len += 1;
+            }
+            let (label, tail) = rest.split_at(len);
+            rest = match tail {
+                [_, tail @ ..] => tail,
+                [] => tail,
+            };
+            let label = match std::str::from_utf8(label) {
+                Ok(label) => Label::from_static(label),
+                Err(_) => unreachable!(),
+            };
+            encoded_len += len + 1;
+            // `Label` has drop glue, so the placeholder can't be dropped by assigning over it.
+            std::mem::forget(std::mem::replace(&mut labels[i], label));
+            i += 1;
+        }
+        assert!(encoded_len <= MAX_NAME_LEN, "domain name too long");
+        labels
+    }
+}
+
 /// A by-value iterator over the [`Label`]s of a [`DomainName`].
 pub struct IntoIter {
-    inner: vec::IntoIter<Label>,
+    inner: IntoIterInner,
+}
+
+enum IntoIterInner {
+    Inline(iter::Take<array::IntoIter<Label, INLINE_LABELS>>),
+    Heap(vec::IntoIter<Label>),
+    Static(slice::Iter<'static, Label>),
 }
 
 impl Iterator for IntoIter {
@@ -242,7 +792,11 @@ impl Iterator for IntoIter {
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next()
+        match &mut self.inner {
+            IntoIterInner::Inline(it) => it.next(),
+            IntoIterInner::Heap(it) => it.next(),
+            IntoIterInner::Static(it) => it.next().cloned(),
+        }
     }
 }
 
@@ -278,6 +832,46 @@ mod tests {
         assert_eq!(format!(" {:?} ", Label::new("a")), r#" "a" "#);
     }
 
+    #[test]
+    fn presentation_text() {
+        let label = Label::from_escaped(r"Anne\032s\032AirPort\\\.\032").unwrap();
+        assert_eq!(label.as_bytes(), b"Anne s AirPort\\. ");
+        assert_eq!(
+            Label::from_escaped(r"Anne's Air\Port").unwrap().as_bytes(),
+            b"Anne's AirPort"
+        );
+        assert_eq!(
+            Label::from_escaped(r"\195\164").unwrap().to_string_lossy(),
+            "ä"
+        );
+        assert_eq!(Label::from_escaped(r"\").unwrap_err(), Error::InvalidValue);
+        assert_eq!(
+            Label::from_escaped(r"\03").unwrap_err(),
+            Error::InvalidValue
+        );
+        assert_eq!(
+            Label::from_escaped(r"\03x").unwrap_err(),
+            Error::InvalidValue
+        );
+        assert_eq!(
+            Label::from_escaped(r"\256").unwrap_err(),
+            Error::InvalidValue
+        );
+        assert_eq!(
+            Label::from_escaped("").unwrap_err(),
+            Error::InvalidEmptyLabel
+        );
+
+        assert_eq!(
+            Label::new("Anne's AirPort").to_string_lossy(),
+            "Anne's AirPort"
+        );
+        assert_eq!(
+            Label::new(b"caf\xc3\xa9 \xff").to_string_lossy(),
+            "café \u{fffd}"
+        );
+    }
+
     #[test]
     fn domain_name_string_conversion() {
         assert_eq!("..".parse::<DomainName>(), Err(Error::InvalidEmptyLabel));
@@ -287,4 +881,110 @@ mod tests {
         assert_eq!("com.".parse::<DomainName>().unwrap().labels().len(), 1);
         assert_eq!(DomainName::ROOT.labels().len(), 0);
     }
+
+    #[test]
+    fn arpa_names() {
+        assert_eq!(
+            DomainName::arpa(Ipv4Addr::new(192, 0, 2, 1).into()).to_string(),
+            "1.2.0.192.in-addr.arpa."
+        );
+        assert_eq!(
+            DomainName::arpa("2001:db8::567:89ab".parse().unwrap()).to_string(),
+            "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa."
+        );
+        assert_eq!(
+            DomainName::arpa(Ipv4Addr::UNSPECIFIED.into()).to_string(),
+            "0.0.0.0.in-addr.arpa."
+        );
+        assert_eq!(
+            DomainName::arpa(Ipv6Addr::LOCALHOST.into()).to_string(),
+            format!("1.{}ip6.arpa.", "0.".repeat(31))
+        );
+        // IPv4-mapped addresses are still IPv6 addresses and use `ip6.arpa`.
+        let mapped = DomainName::arpa(Ipv4Addr::new(192, 0, 2, 1).to_ipv6_mapped().into());
+        assert_eq!(
+            mapped.to_string(),
+            format!("1.0.2.0.0.0.0.c.f.f.f.f.{}ip6.arpa.", "0.".repeat(20))
+        );
+        assert_eq!(
+            DomainName::arpa_v4(Ipv4Addr::new(10, 20, 30, 40)),
+            DomainName::from_str("40.30.20.10.in-addr.arpa").unwrap()
+        );
+    }
+
+    #[test]
+    fn inline_storage() {
+        let long = "a".repeat(Label::MAX_LEN);
+        assert_eq!(Label::new(&long).as_bytes(), long.as_bytes());
+        assert_eq!(Label::new("abc"), Label::new("abc"));
+        assert!(Label::new("a") < Label::new(&long));
+
+        let short = DomainName::from_str("a.b.c").unwrap();
+        let spilled = DomainName::from_str("a.b.c.d.e.f").unwrap();
+        let mut built = short.clone();
+        built.extend(["d", "e", "f"].map(Label::new));
+        assert_eq!(built, spilled);
+        assert_ne!(built, short);
+        assert_eq!(spilled.to_string(), "a.b.c.d.e.f.");
+        assert_eq!(spilled.clone().into_iter().count(), 6);
+        assert_eq!(short.into_iter().collect::<DomainName>().labels().len(), 3);
+    }
+
+    #[test]
+    fn macros() {
+        assert_eq!(crate::domain!("."), DomainName::ROOT);
+        assert_eq!(crate::domain!(""), DomainName::ROOT);
+        let name = crate::domain!("a.b.c.d.e.");
+        assert_eq!(name, DomainName::from_str("a.b.c.d.e").unwrap());
+
+        let mut extended = crate::domain!("_http._tcp");
+        extended.push_label(crate::label!("local"));
+        assert_eq!(extended, DomainName::from_str("_http._tcp.local").unwrap());
+    }
+
+    #[test]
+    fn fold_case() {
+        // Exhaustively check every byte value in every position of the word.
+        for byte in 0..=u8::MAX {
+            for pos in 0..8 {
+                let mut word = [b'x'; 8];
+                word[pos] = byte;
+                let mut expected = word;
+                expected[pos] = byte.to_ascii_lowercase();
+                let folded = fold_ascii_case(u64::from_ne_bytes(word)).to_ne_bytes();
+                assert_eq!(folded, expected, "{byte:#x} at {pos}");
+            }
+        }
+    }
+
+    #[test]
+    fn case_insensitive() {
+        use std::hash::{BuildHasher, RandomState};
+
+        let a = DomainName::from_str("My-Printer-With-A-Long-Name._IPP._tcp.local").unwrap();
+        let b = DomainName::from_str("my-printer-with-a-long-name._ipp._TCP.LOCAL").unwrap();
+        let c = DomainName::from_str("my-printer-with-a-long-nam_._ipp._tcp.local").unwrap();
+        assert!(a.eq_ignore_ascii_case(&b));
+        assert!(!a.eq_ignore_ascii_case(&c));
+        assert!(!a.eq_ignore_ascii_case(&DomainName::from_str("_ipp._tcp.local").unwrap()));
+        assert!(!Label::new(b"\xc4").eq_ignore_ascii_case(&Label::new(b"\xe4")));
+        assert!(!Label::new("a").eq_ignore_ascii_case(&Label::new("a\0")));
+        assert!(crate::label!("_tcp").eq_ignore_ascii_case(&Label::new("_TCP")));
+        assert!(Label::new("@").eq_ignore_ascii_case(&Label::new("@")));
+        assert!(!Label::new("@").eq_ignore_ascii_case(&Label::new("`")));
+        assert_eq!(a.to_ascii_lowercase(), b.to_ascii_lowercase());
+        assert_eq!(
+            a.to_ascii_lowercase().to_string(),
+            "my-printer-with-a-long-name._ipp._tcp.local."
+        );
+
+        let state = RandomState::new();
+        let hash = |name: &DomainName| {
+            let mut hasher = state.build_hasher();
+            name.hash_ignore_ascii_case(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&a), hash(&b));
+        assert_ne!(hash(&a), hash(&c));
+    }
 }
@@ -0,0 +1,245 @@
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+
+use crate::Error;
+
+use super::{DomainName, Label};
+
+/// A domain name stored in a fixed-capacity, stack-allocated buffer.
+///
+/// Unlike [`DomainName`], this type never allocates, which makes it suitable for hot paths and for
+/// environments where per-query heap allocations are unacceptable. The labels are stored in wire
+/// format (each prefixed by its length, without the terminating empty label), so `N` is the
+/// maximum number of bytes the encoded name may take up, excluding the terminator. A capacity of
+/// 254 bytes is enough to hold any valid domain name.
+///
+/// Operations that would exceed the capacity fail with [`Error::Truncated`].
+///
+/// # Example
+///
+/// ```
+/// # use uwuhi::{domain, name::SmallDomainName};
+/// let name: SmallDomainName<64> = "_http._tcp.local".parse().unwrap();
+/// assert_eq!(name.label_count(), 3);
+/// assert_eq!(name, domain!("_http._tcp.local"));
+/// assert_eq!(name.to_domain_name(), domain!("_http._tcp.local"));
+/// ```
+#[derive(Clone, Copy)]
+pub struct SmallDomainName<const N: usize> {
+    /// Number of bytes of `buf` in use.
+    len: u8,
+    buf: [u8; N],
+}
+
+impl<const N: usize> SmallDomainName<N> {
+    /// The empty root domain `.`.
+    pub const ROOT: Self = {
+        assert!(
+            N <= 254,
+            "`SmallDomainName` capacity exceeds the maximum name length"
+        );
+        Self {
+            len: 0,
+            buf: [0; N],
+        }
+    };
+
+    /// Returns the number of bytes this name takes up in its buffer.
+    #[inline]
+    pub fn encoded_len(&self) -> usize {
+        usize::from(self.len)
+    }
+
+    /// Returns the number of labels in this name.
+    pub fn label_count(&self) -> usize {
+        self.labels().count()
+    }
+
+    /// Returns an iterator over the raw bytes of the labels making up this name.
+    ///
+    /// The trailing empty label is not included.
+    pub fn labels(&self) -> SmallLabels<'_> {
+        SmallLabels {
+            rest: &self.buf[..self.encoded_len()],
+        }
+    }
+
+    /// Appends a label to the end of this name.
+    ///
+    /// Returns [`Error::InvalidEmptyLabel`] or [`Error::LabelTooLong`] if `label` is not a valid
+    /// label, and [`Error::Truncated`] if it doesn't fit.
+    pub fn push_label(&mut self, label: impl AsRef<[u8]>) -> Result<(), Error> {
+        let label = label.as_ref();
+        if label.is_empty() {
+            return Err(Error::InvalidEmptyLabel);
+        }
+        if label.len() > Label::MAX_LEN {
+            return Err(Error::LabelTooLong);
+        }
+        let start = self.encoded_len();
+        let end = start + 1 + label.len();
+        let dest = self.buf.get_mut(start..end).ok_or(Error::Truncated)?;
+        dest[0] = label.len() as u8;
+        dest[1..].copy_from_slice(label);
+        self.len = end as u8;
+        Ok(())
+    }
+
+    /// Compares this name to a [`DomainName`], ignoring ASCII case.
+    pub fn eq_ignore_ascii_case(&self, name: &DomainName) -> bool {
+        let mut labels = self.labels();
+        name.labels().iter().all(|l| {
+            labels
+                .next()
+                .is_some_and(|o| o.eq_ignore_ascii_case(l.as_bytes()))
+        }) && labels.next().is_none()
+    }
+
+    /// Copies this name into a heap-allocated [`DomainName`].
+    pub fn to_domain_name(&self) -> DomainName {
+        self.labels().map(Label::new).collect()
+    }
+}
+
+impl<const N: usize> Default for SmallDomainName<N> {
+    fn default() -> Self {
+        Self::ROOT
+    }
+}
+
+impl<const N: usize> TryFrom<&DomainName> for SmallDomainName<N> {
+    type Error = Error;
+
+    /// Copies `name` into a [`SmallDomainName`].
+    ///
+    /// Returns [`Error::Truncated`] if `name` doesn't fit.
+    fn try_from(name: &DomainName) -> Result<Self, Error> {
+        let mut small = Self::ROOT;
+        for label in name.labels() {
+            small.push_label(label.as_bytes())?;
+        }
+        Ok(small)
+    }
+}
+
+impl<const N: usize> From<SmallDomainName<N>> for DomainName {
+    fn from(name: SmallDomainName<N>) -> Self {
+        name.to_domain_name()
+    }
+}
+
+impl<const N: usize> FromStr for SmallDomainName<N> {
+    type Err = Error;
+
+    /// Parses a domain name as a string of `.`-separated labels, like [`DomainName::from_str`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut name = Self::ROOT;
+        if s == "." {
+            return Ok(name);
+        }
+        for label in s.split_terminator('.') {
+            name.push_label(label)?;
+        }
+        Ok(name)
+    }
+}
+
+impl<const N: usize, const M: usize> PartialEq<SmallDomainName<M>> for SmallDomainName<N> {
+    fn eq(&self, other: &SmallDomainName<M>) -> bool {
+        self.buf[..self.encoded_len()] == other.buf[..other.encoded_len()]
+    }
+}
+
+impl<const N: usize> Eq for SmallDomainName<N> {}
+
+impl<const N: usize> PartialEq<DomainName> for SmallDomainName<N> {
+    fn eq(&self, other: &DomainName) -> bool {
+        self.labels().eq(other.labels().iter().map(Label::as_bytes))
+    }
+}
+
+impl<const N: usize> PartialEq<SmallDomainName<N>> for DomainName {
+    fn eq(&self, other: &SmallDomainName<N>) -> bool {
+        other == self
+    }
+}
+
+impl<const N: usize> Hash for SmallDomainName<N> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.buf[..self.encoded_len()].hash(state);
+    }
+}
+
+impl<const N: usize> fmt::Debug for SmallDomainName<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl<const N: usize> fmt::Display for SmallDomainName<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.len == 0 {
+            return f.write_str(".");
+        }
+        for label in self.labels() {
+            write!(f, "{}.", label.escape_ascii())?;
+        }
+        Ok(())
+    }
+}
+
+/// An iterator over the labels of a [`SmallDomainName`].
+#[derive(Debug, Clone)]
+pub struct SmallLabels<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for SmallLabels<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&len, rest) = self.rest.split_first()?;
+        let (label, rest) = rest.split_at(usize::from(len));
+        self.rest = rest;
+        Some(label)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain;
+
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        let name = domain!("My Printer._ipp._tcp.local");
+        let small = SmallDomainName::<32>::try_from(&name).unwrap();
+        assert_eq!(small.encoded_len(), 27);
+        assert_eq!(small.label_count(), 4);
+        assert_eq!(small, name);
+        assert_eq!(name, small);
+        assert_eq!(DomainName::from(small), name);
+        assert_eq!(small.to_string(), "My Printer._ipp._tcp.local.");
+        assert!(small.eq_ignore_ascii_case(&domain!("my printer._IPP._TCP.local")));
+        assert_ne!(small, domain!("My Printer._ipp._tcp"));
+
+        assert_eq!(
+            SmallDomainName::<26>::try_from(&name),
+            Err(Error::Truncated)
+        );
+        assert_eq!(
+            "a.b".parse::<SmallDomainName<4>>().unwrap().label_count(),
+            2
+        );
+        assert_eq!(".".parse::<SmallDomainName<4>>(), Ok(SmallDomainName::ROOT));
+        assert_eq!(SmallDomainName::<0>::ROOT.to_string(), ".");
+        assert_eq!(
+            "a..b".parse::<SmallDomainName<8>>(),
+            Err(Error::InvalidEmptyLabel)
+        );
+    }
+}
@@ -0,0 +1,403 @@
+//! Multicast socket configuration.
+//!
+//! Multicast DNS sockets need a number of (partially platform-specific) socket options to work
+//! correctly and to coexist with other mDNS responders on the same machine. This module provides
+//! [`MulticastSocketBuilder`], which takes care of these details.
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// The IPv4 multicast group used by mDNS.
+pub const MDNS_GROUP_V4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+/// The IPv6 multicast group used by mDNS.
+pub const MDNS_GROUP_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+/// The UDP port used by mDNS.
+pub const MDNS_PORT: u16 = 5353;
+
+/// Selects the network interface a multicast socket operates on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interface {
+    /// Let the operating system choose an interface.
+    #[default]
+    Default,
+    /// The IPv4 interface with this address.
+    ///
+    /// This can only be used with IPv4 sockets.
+    Addr(Ipv4Addr),
+    /// The interface with this OS-specific index.
+    Index(u32),
+}
+
+/// Creates multicast [`UdpSocket`]s.
+///
+/// The created sockets are bound to the wildcard address and the multicast port, with address (and,
+/// where needed, port) reuse enabled, so that they can coexist with other multicast DNS software
+/// running on the same machine.
+///
+/// # Platform notes
+///
+/// - On macOS and the BSDs, `SO_REUSEPORT` is required to share the port and is set in addition to
+///   `SO_REUSEADDR`.
+/// - On Linux, sockets only receive multicast packets for groups they joined themselves
+///   (`IP_MULTICAST_ALL` is disabled), rather than for every group joined by any socket on the
+///   system.
+/// - On Windows, another application (typically Bonjour or the system's own mDNS responder) may
+///   hold the port with `SO_EXCLUSIVEADDRUSE`, in which case [`MulticastSocketBuilder::build`]
+///   fails with [`io::ErrorKind::AddrInUse`].
+///
+/// # Example
+///
+/// ```no_run
+/// # use uwuhi::net::{Interface, MulticastSocketBuilder};
+/// let sock = MulticastSocketBuilder::mdns_v4()
+///     .interface(Interface::Addr("192.168.1.2".parse().unwrap()))
+///     .loopback(false)
+///     .build()?;
+/// # std::io::Result::Ok(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct MulticastSocketBuilder {
+    group: IpAddr,
+    port: u16,
+    interface: Interface,
+    join: bool,
+    ttl: u32,
+    loopback: bool,
+}
+
+impl MulticastSocketBuilder {
+    /// Creates a builder for sockets that use the multicast group `group` on `port`.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `group` is not a multicast address.
+    pub fn new(group: IpAddr, port: u16) -> Self {
+        assert!(group.is_multicast(), "{} is not a multicast address", group);
+        Self {
+            group,
+            port,
+            interface: Interface::Default,
+            join: true,
+            // RFC 6762 recommends sending all mDNS packets with an IP TTL of 255.
+            ttl: 255,
+            loopback: true,
+        }
+    }
+
+    /// Creates a builder for IPv4 mDNS sockets.
+    pub fn mdns_v4() -> Self {
+        Self::new(MDNS_GROUP_V4.into(), MDNS_PORT)
+    }
+
+    /// Creates a builder for IPv6 mDNS sockets.
+    pub fn mdns_v6() -> Self {
+        Self::new(MDNS_GROUP_V6.into(), MDNS_PORT)
+    }
+
+    /// Sets the interface to join the multicast group on and to send multicast packets from.
+    ///
+    /// By default, the operating system picks an interface.
+    pub fn interface(mut self, interface: Interface) -> Self {
+        self.interface = interface;
+        self
+    }
+
+    /// Sets whether to join the multicast group.
+    ///
+    /// Sockets that only *send* multicast packets don't need to join the group. By default, the
+    /// group is joined.
+    pub fn join(mut self, join: bool) -> Self {
+        self.join = join;
+        self
+    }
+
+    /// Sets the IP TTL (or IPv6 hop limit) of outgoing multicast packets.
+    ///
+    /// The default is 255, as recommended by RFC 6762.
+    pub fn ttl(mut self, ttl: u32) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets whether outgoing multicast packets are looped back to sockets on the same machine.
+    ///
+    /// This is enabled by default, which allows communicating with other mDNS software on the same
+    /// host.
+    pub fn loopback(mut self, loopback: bool) -> Self {
+        self.loopback = loopback;
+        self
+    }
+
+    /// Creates and configures the socket.
+    ///
+    /// The socket will be in blocking mode.
+    pub fn build(&self) -> io::Result<UdpSocket> {
+        let domain = match self.group {
+            IpAddr::V4(_) => Domain::IPV4,
+            IpAddr::V6(_) => Domain::IPV6,
+        };
+        let sock = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+        set_reuse(&sock)?;
+
+        match self.group {
+            IpAddr::V4(group) => {
+                bind(&sock, (Ipv4Addr::UNSPECIFIED, self.port).into())?;
+                #[cfg(target_os = "linux")]
+                sock.set_multicast_all_v4(false)?;
+                match self.interface {
+                    Interface::Default => {
+                        if self.join {
+                            sock.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+                        }
+                    }
+                    Interface::Addr(addr) => {
+                        if self.join {
+                            sock.join_multicast_v4(&group, &addr)?;
+                        }
+                        sock.set_multicast_if_v4(&addr)?;
+                    }
+                    Interface::Index(index) => {
+                        if self.join {
+                            join_v4_by_index(&sock, group, index)?;
+                        }
+                        set_multicast_if_v4_by_index(&sock, index)?;
+                    }
+                }
+                sock.set_multicast_ttl_v4(self.ttl)?;
+                sock.set_multicast_loop_v4(self.loopback)?;
+            }
+            IpAddr::V6(group) => {
+                sock.set_only_v6(true)?;
+                bind(&sock, (Ipv6Addr::UNSPECIFIED, self.port).into())?;
+                #[cfg(target_os = "linux")]
+                sock.set_multicast_all_v6(false)?;
+                let index = match self.interface {
+                    Interface::Default => 0,
+                    Interface::Index(index) => index,
+                    Interface::Addr(_) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "IPv6 multicast interfaces must be selected by index",
+                        ))
+                    }
+                };
+                if self.join {
+                    sock.join_multicast_v6(&group, index)?;
+                }
+                if index != 0 {
+                    sock.set_multicast_if_v6(index)?;
+                }
+                sock.set_multicast_hops_v6(self.ttl)?;
+                sock.set_multicast_loop_v6(self.loopback)?;
+            }
+        }
+
+        Ok(sock.into())
+    }
+}
+
+/// Allows multiple sockets to bind to the same multicast port.
+fn set_reuse(sock: &Socket) -> io::Result<()> {
+    sock.set_reuse_address(true)?;
+    // On the BSDs (including macOS), `SO_REUSEADDR` is not enough to share a multicast port.
+    // On Linux, `SO_REUSEPORT` load-balances unicast packets between sockets instead, which would
+    // cause unicast mDNS responses to end up at the wrong socket, so we don't set it there.
+    #[cfg(all(
+        unix,
+        not(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "solaris",
+            target_os = "illumos",
+            target_os = "cygwin",
+        ))
+    ))]
+    sock.set_reuse_port(true)?;
+    Ok(())
+}
+
+/// Binds `sock` to `addr`, turning the error Windows reports for exclusively-held ports into a
+/// more helpful one.
+fn bind(sock: &Socket, addr: SocketAddr) -> io::Result<()> {
+    match sock.bind(&addr.into()) {
+        // Windows reports `WSAEACCES` when another socket holds the port with
+        // `SO_EXCLUSIVEADDRUSE`, which `SO_REUSEADDR` can't override.
+        #[cfg(windows)]
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!(
+                "port {} is held exclusively by another application: {}",
+                addr.port(),
+                e
+            ),
+        )),
+        res => res,
+    }
+}
+
+#[cfg(not(any(
+    target_os = "aix",
+    target_os = "haiku",
+    target_os = "illumos",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "redox",
+    target_os = "solaris",
+    target_os = "nto",
+    target_os = "espidf",
+    target_os = "vita",
+    target_os = "cygwin",
+)))]
+fn join_v4_by_index(sock: &Socket, group: Ipv4Addr, index: u32) -> io::Result<()> {
+    sock.join_multicast_v4_n(&group, &socket2::InterfaceIndexOrAddress::Index(index))
+}
+
+#[cfg(any(
+    target_os = "aix",
+    target_os = "haiku",
+    target_os = "illumos",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "redox",
+    target_os = "solaris",
+    target_os = "nto",
+    target_os = "espidf",
+    target_os = "vita",
+    target_os = "cygwin",
+))]
+fn join_v4_by_index(_: &Socket, _: Ipv4Addr, _: u32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "joining IPv4 multicast groups by interface index is not supported on this platform",
+    ))
+}
+
+/// Selects the interface with index `index` for outgoing IPv4 multicast packets.
+///
+/// socket2 only supports selecting it by address, so this passes an `ip_mreqn` to
+/// `IP_MULTICAST_IF` directly.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_vendor = "apple",
+    target_os = "freebsd",
+))]
+fn set_multicast_if_v4_by_index(sock: &Socket, index: u32) -> io::Result<()> {
+    use std::{mem::size_of, os::fd::AsRawFd};
+
+    let mreqn = libc::ip_mreqn {
+        imr_multiaddr: libc::in_addr { s_addr: 0 },
+        imr_address: libc::in_addr { s_addr: 0 },
+        imr_ifindex: index
+            .try_into()
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?,
+    };
+    // SAFETY: `mreqn` is a valid `ip_mreqn`, and its size is passed along with it.
+    let res = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_MULTICAST_IF,
+            (&mreqn as *const libc::ip_mreqn).cast(),
+            size_of::<libc::ip_mreqn>() as libc::socklen_t,
+        )
+    };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Selects the interface with index `index` for outgoing IPv4 multicast packets.
+///
+/// Windows interprets addresses in `0.0.0.0/8` passed to `IP_MULTICAST_IF` as interface indices.
+#[cfg(windows)]
+fn set_multicast_if_v4_by_index(sock: &Socket, index: u32) -> io::Result<()> {
+    if index >= 1 << 24 {
+        return Err(io::ErrorKind::InvalidInput.into());
+    }
+    sock.set_multicast_if_v4(&Ipv4Addr::from(index))
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_vendor = "apple",
+    target_os = "freebsd",
+    windows,
+)))]
+fn set_multicast_if_v4_by_index(_: &Socket, _: u32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "selecting the IPv4 multicast interface by index is not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configures_v4_socket() {
+        let sock = MulticastSocketBuilder::new(MDNS_GROUP_V4.into(), 0)
+            .ttl(7)
+            .loopback(false)
+            .build()
+            .unwrap();
+        assert_eq!(sock.multicast_ttl_v4().unwrap(), 7);
+        assert!(!sock.multicast_loop_v4().unwrap());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn sets_v4_interface_by_index() {
+        let lo = unsafe { libc::if_nametoindex(c"lo".as_ptr()) };
+        assert_ne!(lo, 0);
+        let builder = |index| {
+            MulticastSocketBuilder::new(MDNS_GROUP_V4.into(), 0)
+                .interface(Interface::Index(index))
+                .join(false)
+                .build()
+        };
+        builder(lo).unwrap();
+        // Linux validates the index when setting `IP_MULTICAST_IF`, which shows that the option
+        // is set even though the group isn't joined.
+        let err = builder(999_999).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrNotAvailable);
+    }
+
+    #[test]
+    fn shares_port() {
+        let first = MulticastSocketBuilder::new(MDNS_GROUP_V4.into(), 0)
+            .join(false)
+            .build()
+            .unwrap();
+        let port = first.local_addr().unwrap().port();
+        let second = MulticastSocketBuilder::new(MDNS_GROUP_V4.into(), port)
+            .join(false)
+            .build()
+            .unwrap();
+        assert_eq!(second.local_addr().unwrap().port(), port);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn only_receives_joined_groups() {
+        let sock = MulticastSocketBuilder::new(MDNS_GROUP_V4.into(), 0)
+            .join(false)
+            .build()
+            .unwrap();
+        assert!(!Socket::from(sock).multicast_all_v4().unwrap());
+    }
+
+    #[test]
+    #[should_panic]
+    fn rejects_unicast_group() {
+        MulticastSocketBuilder::new(Ipv4Addr::LOCALHOST.into(), 0);
+    }
+}
@@ -2,17 +2,31 @@
 
 #[macro_use]
 mod macros;
+pub mod arena;
 pub mod decoder;
+pub mod dnssec;
+pub mod dso;
+pub mod edns;
 pub mod encoder;
+#[cfg(any(test, feature = "heapless"))]
+pub mod fixed;
+mod message;
 pub mod records;
+pub mod rewrite;
 pub mod section;
+pub mod sig0;
+mod validate;
 
 use core::fmt;
+use std::hash::{BuildHasher, Hasher, RandomState};
 
 use bitflags::bitflags;
 
 use crate::num::U16;
 
+pub use message::Message;
+pub use validate::{validate, MessagePart, Warning};
+
 ffi_enum! {
     /// DNS message operation codes.
     ///
@@ -43,6 +57,14 @@ ffi_enum! {
 
         NOTIFY = 4,
         UPDATE = 5,
+
+        /// DNS Stateful Operations ([RFC 8490]).
+        ///
+        /// Messages with this opcode are used on long-lived connections and are encoded and
+        /// decoded with the types in the [`dso`] module.
+        ///
+        /// [RFC 8490]: https://datatracker.ietf.org/doc/html/rfc8490
+        DSO = 6,
     }
 }
 
@@ -101,8 +123,10 @@ impl fmt::Display for RCode {
 ffi_enum! {
     /// Resource Record types.
     ///
-    /// These are mostly copied from [RFC 1035] and
-    /// <https://en.wikipedia.org/wiki/List_of_DNS_record_types>.
+    /// These are copied from the [IANA registry], which in turn references [RFC 1035] and many
+    /// later RFCs.
+    ///
+    /// [IANA registry]: https://www.iana.org/assignments/dns-parameters/dns-parameters.xhtml#dns-parameters-4
     ///
     /// [RFC 1035]: https://datatracker.ietf.org/doc/html/rfc1035
     pub enum Type: u16 {
@@ -124,15 +148,29 @@ ffi_enum! {
         TXT = 16,
         RP = 17,
         AFSDB = 18,
+        X25 = 19,
+        ISDN = 20,
+        RT = 21,
+        NSAP = 22,
+        NSAP_PTR = 23,
         SIG = 24,
         KEY = 25,
+        PX = 26,
+        GPOS = 27,
         AAAA = 28,
         LOC = 29,
+        NXT = 30,
+        EID = 31,
+        NIMLOC = 32,
         SRV = 33,
+        ATMA = 34,
         NAPTR = 35,
         KX = 36,
         CERT = 37,
+        A6 = 38,
         DNAME = 39,
+        SINK = 40,
+        OPT = 41,
         APL = 42,
         DS = 43,
         SSHFP = 44,
@@ -146,6 +184,9 @@ ffi_enum! {
         TLSA = 52,
         SMIMEA = 53,
         HIP = 55,
+        NINFO = 56,
+        RKEY = 57,
+        TALINK = 58,
         CDS = 59,
         CDNSKEY = 60,
         OPENPGPKEY = 61,
@@ -153,12 +194,34 @@ ffi_enum! {
         ZONEMD = 63,
         SVCB = 64,
         HTTPS = 65,
+        DSYNC = 66,
+        HHIT = 67,
+        BRID = 68,
+        SPF = 99,
+        UINFO = 100,
+        UID = 101,
+        GID = 102,
+        UNSPEC = 103,
+        NID = 104,
+        L32 = 105,
+        L64 = 106,
+        LP = 107,
         EUI48 = 108,
         EUI64 = 109,
+        NXNAME = 128,
         TKEY = 249,
         TSIG = 250,
         URI = 256,
         CAA = 257,
+        AVC = 258,
+        DOA = 259,
+        AMTRELAY = 260,
+        RESINFO = 261,
+        WALLET = 262,
+        CLA = 263,
+        IPN = 264,
+        TA = 32768,
+        DLV = 32769,
     }
 }
 
@@ -190,15 +253,29 @@ ffi_enum! {
         TXT = 16,
         RP = 17,
         AFSDB = 18,
+        X25 = 19,
+        ISDN = 20,
+        RT = 21,
+        NSAP = 22,
+        NSAP_PTR = 23,
         SIG = 24,
         KEY = 25,
+        PX = 26,
+        GPOS = 27,
         AAAA = 28,
         LOC = 29,
+        NXT = 30,
+        EID = 31,
+        NIMLOC = 32,
         SRV = 33,
+        ATMA = 34,
         NAPTR = 35,
         KX = 36,
         CERT = 37,
+        A6 = 38,
         DNAME = 39,
+        SINK = 40,
+        OPT = 41,
         APL = 42,
         DS = 43,
         SSHFP = 44,
@@ -212,6 +289,9 @@ ffi_enum! {
         TLSA = 52,
         SMIMEA = 53,
         HIP = 55,
+        NINFO = 56,
+        RKEY = 57,
+        TALINK = 58,
         CDS = 59,
         CDNSKEY = 60,
         OPENPGPKEY = 61,
@@ -219,14 +299,40 @@ ffi_enum! {
         ZONEMD = 63,
         SVCB = 64,
         HTTPS = 65,
+        DSYNC = 66,
+        HHIT = 67,
+        BRID = 68,
+        SPF = 99,
+        UINFO = 100,
+        UID = 101,
+        GID = 102,
+        UNSPEC = 103,
+        NID = 104,
+        L32 = 105,
+        L64 = 106,
+        LP = 107,
         EUI48 = 108,
         EUI64 = 109,
+        NXNAME = 128,
         TKEY = 249,
         TSIG = 250,
         URI = 256,
         CAA = 257,
+        AVC = 258,
+        DOA = 259,
+        AMTRELAY = 260,
+        RESINFO = 261,
+        WALLET = 262,
+        CLA = 263,
+        IPN = 264,
+        TA = 32768,
+        DLV = 32769,
 
         // QType-specific entries:
+        /// Incremental zone transfer ([RFC 1995]).
+        ///
+        /// [RFC 1995]: https://datatracker.ietf.org/doc/html/rfc1995
+        IXFR = 251,
         AXFR = 252,
         MAILB = 253,
         MAILA = 254,
@@ -245,7 +351,51 @@ impl QType {
             Self::MAILB => matches!(ty, Type::MB | Type::MG | Type::MR),
             Self::MAILA => false, // obsolete
             Self::ALL => true,
-            _ => self.0 == ty.0,
+            _ => *self == Self::from(ty),
+        }
+    }
+
+    /// Returns whether a record of type `ty` belongs in the answer to a query for `self`.
+    ///
+    /// This is like [`QType::matches`], except that `CNAME` records also answer queries for any
+    /// other type ([RFC 1034, section 3.6.2]): a name that owns a `CNAME` record can't own any
+    /// other data, so the alias is returned instead and the querier can look up its target.
+    ///
+    /// [RFC 1034, section 3.6.2]: https://datatracker.ietf.org/doc/html/rfc1034#section-3.6.2
+    pub fn is_answered_by(&self, ty: Type) -> bool {
+        self.matches(ty) || (ty == Type::CNAME && *self != Self::AXFR)
+    }
+
+    /// Returns whether `self` is one of the query-only types that don't correspond to a [`Type`]
+    /// (eg. [`QType::AXFR`] or [`QType::ALL`]).
+    pub fn is_meta(&self) -> bool {
+        matches!(
+            *self,
+            Self::IXFR | Self::AXFR | Self::MAILB | Self::MAILA | Self::ALL
+        )
+    }
+}
+
+impl From<Type> for QType {
+    /// Converts a record [`Type`] to the [`QType`] that queries for exactly that type.
+    #[inline]
+    fn from(ty: Type) -> Self {
+        Self(ty.0)
+    }
+}
+
+impl TryFrom<QType> for Type {
+    type Error = crate::Error;
+
+    /// Converts a [`QType`] to the record [`Type`] it queries for.
+    ///
+    /// Returns [`Error::InvalidValue`](crate::Error::InvalidValue) if the [`QType`] is a
+    /// query-only type (see [`QType::is_meta`]). Unknown values are converted unchanged.
+    fn try_from(qtype: QType) -> Result<Self, Self::Error> {
+        if qtype.is_meta() {
+            Err(crate::Error::InvalidValue)
+        } else {
+            Ok(Self(qtype.0))
         }
     }
 }
@@ -270,6 +420,10 @@ ffi_enum! {
         CH = 3,
         /// Hesiod (basically, an LDAP precursor).
         HS = 4,
+        /// Used in dynamic updates ([RFC 2136]) to delete specific records.
+        ///
+        /// [RFC 2136]: https://datatracker.ietf.org/doc/html/rfc2136
+        NONE = 254,
     }
 }
 
@@ -292,6 +446,10 @@ ffi_enum! {
         CH = 3,
         /// Hesiod (basically, an LDAP precursor).
         HS = 4,
+        /// Used in dynamic updates ([RFC 2136]) to delete specific records.
+        ///
+        /// [RFC 2136]: https://datatracker.ietf.org/doc/html/rfc2136
+        NONE = 254,
 
         /// Query is for all classes of resource.
         ANY = 255,
@@ -307,10 +465,30 @@ impl QClass {
     /// [`QClass::ANY`] matches any [`Class`]. Other [`QClass`]es only match their specific
     /// [`Class`].
     pub fn matches(&self, class: Class) -> bool {
-        if *self == Self::ANY {
-            true
+        *self == Self::ANY || *self == Self::from(class)
+    }
+}
+
+impl From<Class> for QClass {
+    /// Converts a record [`Class`] to the [`QClass`] that queries for exactly that class.
+    #[inline]
+    fn from(class: Class) -> Self {
+        Self(class.0)
+    }
+}
+
+impl TryFrom<QClass> for Class {
+    type Error = crate::Error;
+
+    /// Converts a [`QClass`] to the record [`Class`] it queries for.
+    ///
+    /// Returns [`Error::InvalidValue`](crate::Error::InvalidValue) for [`QClass::ANY`]. Unknown
+    /// values are converted unchanged.
+    fn try_from(qclass: QClass) -> Result<Self, Self::Error> {
+        if qclass == QClass::ANY {
+            Err(crate::Error::InvalidValue)
         } else {
-            self.0 == class.0
+            Ok(Self(qclass.0))
         }
     }
 }
@@ -403,6 +581,17 @@ impl Header {
         self.id = id.into();
     }
 
+    /// Returns a randomly chosen packet ID, for use in a new query.
+    ///
+    /// Random IDs make it harder for off-path attackers to inject forged responses, and prevent
+    /// responses to earlier queries from being mistaken for responses to later ones. The IDs are
+    /// not cryptographically secure.
+    pub fn random_id() -> u16 {
+        // `RandomState` is randomly seeded once per thread, and produces a different hasher every
+        // time, which makes it a decent source of non-cryptographic randomness.
+        RandomState::new().build_hasher().finish() as u16
+    }
+
     #[inline]
     pub fn is_query(&self) -> bool {
         !self.is_response()
@@ -451,6 +640,38 @@ impl Header {
         self.modify_flags(|f| f.set(HeaderFlags::AA, aa));
     }
 
+    /// Returns whether the LLMNR *Conflict* (`C`) bit is set.
+    ///
+    /// LLMNR ([RFC 4795]) reuses the position of the `AA` bit for this flag, so this is equivalent
+    /// to [`Header::is_authority`].
+    ///
+    /// [RFC 4795]: https://datatracker.ietf.org/doc/html/rfc4795
+    #[inline]
+    pub fn is_conflict(&self) -> bool {
+        self.is_authority()
+    }
+
+    #[inline]
+    pub fn set_conflict(&mut self, c: bool) {
+        self.set_authority(c);
+    }
+
+    /// Returns whether the LLMNR *Tentative* (`T`) bit is set.
+    ///
+    /// LLMNR ([RFC 4795]) reuses the position of the `RD` bit for this flag, so this is equivalent
+    /// to [`Header::is_recursion_desired`].
+    ///
+    /// [RFC 4795]: https://datatracker.ietf.org/doc/html/rfc4795
+    #[inline]
+    pub fn is_tentative(&self) -> bool {
+        self.is_recursion_desired()
+    }
+
+    #[inline]
+    pub fn set_tentative(&mut self, t: bool) {
+        self.set_recursion_desired(t);
+    }
+
     pub fn opcode(&self) -> Opcode {
         self.flags().opcode()
     }
@@ -526,6 +747,7 @@ impl fmt::Debug for Header {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Error;
 
     #[test]
     fn header() {
@@ -548,4 +770,55 @@ mod tests {
         h.set_rcode(RCode::NO_ERROR);
         assert_eq!(h.rcode(), RCode::NO_ERROR);
     }
+
+    #[test]
+    fn known_values() {
+        assert!(Type::SRV.is_known());
+        assert_eq!(Type::try_from(33), Ok(Type::SRV));
+        assert_eq!(Type::try_from(54), Err(Error::InvalidValue));
+        assert_eq!(Type::try_from(0), Err(Error::InvalidValue));
+        assert_eq!(u16::from(Type::DLV), 32769);
+        assert!(Type::iter().all(|ty| ty.is_known()));
+        assert_eq!(Type::iter().next(), Some(Type::A));
+
+        assert_eq!(QType::try_from(251), Ok(QType::IXFR));
+        assert_eq!(Class::try_from(254), Ok(Class::NONE));
+        assert_eq!(QClass::try_from(255), Ok(QClass::ANY));
+        assert!(Class::try_from(255).is_err());
+        assert_eq!(Opcode::try_from(3), Err(Error::InvalidValue));
+        assert_eq!(RCode::try_from(16), Ok(RCode::BAD_VERS));
+        assert_eq!(RCode::iter().filter(|&rc| rc == RCode::BAD_SIG).count(), 2);
+
+        // Every `Type` has a `QType` with the same name and value.
+        assert!(QType::iter()
+            .filter(|qty| !qty.is_meta())
+            .all(|qty| Type::try_from(qty).unwrap().is_known()));
+        for ty in Type::iter() {
+            let qty = QType::try_from(u16::from(ty)).unwrap();
+            assert_eq!(format!("{:?}", ty), format!("{:?}", qty));
+            assert_eq!(QType::from(ty), qty);
+            assert_eq!(Type::try_from(qty), Ok(ty));
+        }
+    }
+
+    #[test]
+    fn qtype_type_conversions() {
+        for qty in [QType::AXFR, QType::MAILB, QType::ALL] {
+            assert_eq!(Type::try_from(qty), Err(Error::InvalidValue));
+        }
+        assert!(QType::ALL.matches(Type::TXT));
+        assert!(QType::from(Type::TXT).matches(Type::TXT));
+        assert!(!QType::from(Type::TXT).matches(Type::A));
+        assert!(!QType::from(Type::TXT).matches(Type::CNAME));
+        assert!(QType::from(Type::TXT).is_answered_by(Type::CNAME));
+        assert!(QType::ALL.is_answered_by(Type::CNAME));
+        assert!(!QType::AXFR.is_answered_by(Type::CNAME));
+        assert!(!QType::from(Type::TXT).is_answered_by(Type::A));
+
+        assert_eq!(QClass::from(Class::IN), QClass::IN);
+        assert_eq!(Class::try_from(QClass::CH), Ok(Class::CH));
+        assert_eq!(Class::try_from(QClass::ANY), Err(Error::InvalidValue));
+        assert!(QClass::ANY.matches(Class::HS));
+        assert!(!QClass::IN.matches(Class::CH));
+    }
 }
@@ -0,0 +1,759 @@
+//! Arena-backed decoding of whole DNS messages.
+//!
+//! [`MessageDecoder`] copies every name it decodes into a [`DomainName`], and [`Record`]s copy
+//! their names and TXT entries as well. Both can allocate, and when processing a lot of traffic
+//! (like the mDNS [tap] or a [reflector] on a busy network does), this per-label heap churn ends up
+//! dominating CPU time.
+//!
+//! A [`DecodeArena`] instead decodes an entire message at once, and copies all of its names, labels,
+//! TXT entries and record data into a few flat buffers it owns. These buffers are cleared, but not
+//! freed, before the next message is decoded, so once they have grown to fit the largest message
+//! seen, decoding doesn't allocate anymore.
+//!
+//! [`MessageDecoder`]: super::decoder::MessageDecoder
+//! [`Record`]: super::records::Record
+//! [tap]: crate::tap
+//! [reflector]: crate::reflector
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ops::Range,
+    slice,
+};
+
+use crate::{
+    name::{DomainName, Label},
+    Error,
+};
+
+use super::{
+    decoder::{self, Reader},
+    edns::{EdnsHeader, EdnsOptions},
+    records, Class, Header, QClass, QType, Type,
+};
+
+/// Reusable storage for decoding DNS messages without per-name allocations.
+///
+/// # Example
+///
+/// ```
+/// # use uwuhi::packet::arena::DecodeArena;
+/// # fn process(packets: &[&[u8]]) -> Result<(), uwuhi::Error> {
+/// let mut arena = DecodeArena::new();
+/// for packet in packets {
+///     // Decoding a message invalidates the previous one, and reuses its memory.
+///     let msg = arena.decode(packet)?;
+///     for rr in msg.answers() {
+///         println!("{}", rr);
+///     }
+/// }
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Default)]
+pub struct DecodeArena {
+    /// Label, TXT entry and RDATA bytes.
+    bytes: Vec<u8>,
+    /// Labels and TXT entries, as spans of `bytes`.
+    strings: Vec<Span>,
+    questions: Vec<RawQuestion>,
+    records: Vec<RawRecord>,
+}
+
+/// A range of indices into one of the arena's buffers.
+#[derive(Debug, Clone, Copy)]
+struct Span {
+    start: u32,
+    end: u32,
+}
+
+impl Span {
+    fn range(self) -> Range<usize> {
+        self.start as usize..self.end as usize
+    }
+}
+
+#[derive(Debug)]
+struct RawQuestion {
+    /// Span of `strings`.
+    qname: Span,
+    qtype: QType,
+    qclass: QClass,
+    prefer_unicast: bool,
+}
+
+#[derive(Debug)]
+struct RawRecord {
+    /// Span of `strings`.
+    name: Span,
+    type_: Type,
+    class: Class,
+    cache_flush: bool,
+    ttl: u32,
+    /// Span of `bytes`.
+    rdata: Span,
+    /// For records pointing to another name (like PTR and SRV), the span of `strings` holding it.
+    target: Option<Span>,
+    /// For TXT records, the span of `strings` holding the entries.
+    txt: Option<Span>,
+}
+
+impl DecodeArena {
+    /// Creates an empty arena.
+    ///
+    /// The arena does not allocate until the first message is decoded.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes all sections of the DNS message in `msg`.
+    ///
+    /// Any data from the previously decoded message is discarded, and its memory is reused.
+    ///
+    /// Unlike [`MessageDecoder`], this validates the whole message upfront, and will return an
+    /// error if any question or resource record in it is malformed. The data of individual records
+    /// is not validated, except for the names and TXT entries that are made available via
+    /// [`ArenaRecord::target`] and [`ArenaRecord::txt_entries`].
+    ///
+    /// [`MessageDecoder`]: super::decoder::MessageDecoder
+    pub fn decode(&mut self, msg: &[u8]) -> Result<ArenaMessage<'_>, Error> {
+        self.bytes.clear();
+        self.strings.clear();
+        self.questions.clear();
+        self.records.clear();
+
+        let mut r = Reader::new(msg);
+        let header = r.read_obj::<Header>()?;
+        for _ in 0..header.question_count() {
+            let qname = self.push_name(&r)?;
+            let qtype = QType(r.read_u16()?);
+            let qclass = r.read_u16()?;
+            self.questions.push(RawQuestion {
+                qname,
+                qtype,
+                qclass: QClass(qclass & 0xff),
+                prefer_unicast: qclass & 0x8000 != 0,
+            });
+        }
+
+        let rr_count = usize::from(header.answer_count())
+            + usize::from(header.authoritative_count())
+            + usize::from(header.additional_count());
+        for _ in 0..rr_count {
+            let name = self.push_name(&r)?;
+            let (type_, class, cache_flush, ttl, rdata) = r.read_rr_fields()?;
+            let target = match type_ {
+                Type::CNAME | Type::NS | Type::PTR => Some(0),
+                Type::MX => Some(2),
+                Type::SRV => Some(6),
+                _ => None,
+            };
+            // Malformed record data is not an error here, the accessors just return `None`.
+            let target = target.and_then(|skip| {
+                let r = rdata.clone();
+                r.read_slice(skip).ok()?;
+                self.push_name(&r).ok()
+            });
+            let txt = match type_ {
+                Type::TXT => self.push_txt(&rdata).ok(),
+                _ => None,
+            };
+            let rdata = self.push_bytes(rdata.buf());
+            self.records.push(RawRecord {
+                name,
+                type_,
+                class,
+                cache_flush,
+                ttl,
+                rdata,
+                target,
+                txt,
+            });
+        }
+
+        Ok(ArenaMessage {
+            header,
+            arena: self,
+        })
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8]) -> Span {
+        let start = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(bytes);
+        Span {
+            start,
+            end: self.bytes.len() as u32,
+        }
+    }
+
+    fn push_string(&mut self, string: &[u8]) {
+        let span = self.push_bytes(string);
+        self.strings.push(span);
+    }
+
+    fn strings_since(&self, start: usize) -> Span {
+        Span {
+            start: start as u32,
+            end: self.strings.len() as u32,
+        }
+    }
+
+    fn push_name(&mut self, r: &Reader<'_>) -> Result<Span, Error> {
+        let start = self.strings.len();
+        r.walk_domain_name(|label| {
+            self.push_string(label);
+            Ok(())
+        })?;
+        Ok(self.strings_since(start))
+    }
+
+    fn push_txt(&mut self, rdata: &Reader<'_>) -> Result<Span, Error> {
+        let start = self.strings.len();
+        let r = rdata.clone();
+        while !r.buf().is_empty() {
+            self.push_string(r.read_character_string()?);
+        }
+        Ok(self.strings_since(start))
+    }
+
+    fn string(&self, index: usize) -> &[u8] {
+        &self.bytes[self.strings[index].range()]
+    }
+}
+
+/// A DNS message decoded into a [`DecodeArena`].
+#[derive(Clone, Copy)]
+pub struct ArenaMessage<'a> {
+    header: Header,
+    arena: &'a DecodeArena,
+}
+
+impl<'a> ArenaMessage<'a> {
+    /// Returns the message header.
+    #[inline]
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Returns an iterator over the entries in the *Question* section.
+    pub fn questions(&self) -> ArenaQuestions<'a> {
+        ArenaQuestions {
+            arena: self.arena,
+            iter: self.arena.questions.iter(),
+        }
+    }
+
+    /// Returns an iterator over the records in the *Answer* section.
+    pub fn answers(&self) -> ArenaRecords<'a> {
+        let end = usize::from(self.header.answer_count());
+        self.records(0..end)
+    }
+
+    /// Returns an iterator over the records in the *Authority* section.
+    pub fn authority(&self) -> ArenaRecords<'a> {
+        let start = usize::from(self.header.answer_count());
+        self.records(start..start + usize::from(self.header.authoritative_count()))
+    }
+
+    /// Returns an iterator over the records in the *Additional Records* section.
+    pub fn additional(&self) -> ArenaRecords<'a> {
+        let start = usize::from(self.header.answer_count())
+            + usize::from(self.header.authoritative_count());
+        self.records(start..self.arena.records.len())
+    }
+
+    /// Returns an iterator over the records in all sections (*Answer*, *Authority* and
+    /// *Additional Records*, in that order).
+    pub fn records_in_all_sections(&self) -> ArenaRecords<'a> {
+        self.records(0..self.arena.records.len())
+    }
+
+    fn records(&self, range: Range<usize>) -> ArenaRecords<'a> {
+        ArenaRecords {
+            arena: self.arena,
+            iter: self.arena.records[range].iter(),
+        }
+    }
+
+    /// Like [`MessageDecoder::format`], but for an already decoded message.
+    ///
+    /// [`MessageDecoder::format`]: decoder::MessageDecoder::format
+    pub(crate) fn format(&self, mut cb: impl FnMut(fmt::Arguments<'_>)) {
+        decoder::format_header(self.header(), &mut cb);
+        for q in self.questions() {
+            cb(format_args!("Q: {}", q));
+        }
+        for rr in self.answers() {
+            cb(format_args!("ANS: {}", rr));
+        }
+        for rr in self.authority() {
+            cb(format_args!("AUTH: {}", rr));
+        }
+        for rr in self.additional() {
+            cb(format_args!("ADDL: {}", rr));
+        }
+    }
+}
+
+/// An iterator over the questions of an [`ArenaMessage`].
+#[derive(Clone)]
+pub struct ArenaQuestions<'a> {
+    arena: &'a DecodeArena,
+    iter: slice::Iter<'a, RawQuestion>,
+}
+
+impl<'a> Iterator for ArenaQuestions<'a> {
+    type Item = ArenaQuestion<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let raw = self.iter.next()?;
+        Some(ArenaQuestion {
+            arena: self.arena,
+            raw,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl ExactSizeIterator for ArenaQuestions<'_> {}
+
+/// An iterator over the resource records in one or more sections of an [`ArenaMessage`].
+#[derive(Clone)]
+pub struct ArenaRecords<'a> {
+    arena: &'a DecodeArena,
+    iter: slice::Iter<'a, RawRecord>,
+}
+
+impl<'a> Iterator for ArenaRecords<'a> {
+    type Item = ArenaRecord<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let raw = self.iter.next()?;
+        Some(ArenaRecord {
+            arena: self.arena,
+            raw,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl ExactSizeIterator for ArenaRecords<'_> {}
+
+/// A question stored in a [`DecodeArena`].
+#[derive(Clone, Copy)]
+pub struct ArenaQuestion<'a> {
+    arena: &'a DecodeArena,
+    raw: &'a RawQuestion,
+}
+
+impl<'a> ArenaQuestion<'a> {
+    /// Returns the domain name that is being queried.
+    #[inline]
+    pub fn qname(&self) -> ArenaName<'a> {
+        ArenaName::new(self.arena, self.raw.qname)
+    }
+
+    /// Returns the resource record types the client is interested in.
+    #[inline]
+    pub fn qtype(&self) -> QType {
+        self.raw.qtype
+    }
+
+    /// Returns the record class that the client is interested in.
+    #[inline]
+    pub fn qclass(&self) -> QClass {
+        self.raw.qclass
+    }
+
+    /// Returns whether the mDNS "unicast-response" bit is set in this question.
+    #[inline]
+    pub fn prefer_unicast(&self) -> bool {
+        self.raw.prefer_unicast
+    }
+}
+
+impl fmt::Debug for ArenaQuestion<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArenaQuestion")
+            .field("qname", &self.qname())
+            .field("qtype", &self.qtype())
+            .field("qclass", &self.qclass())
+            .field("prefer_unicast", &self.prefer_unicast())
+            .finish()
+    }
+}
+
+impl fmt::Display for ArenaQuestion<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\t{}\t{}", self.qname(), self.qclass(), self.qtype())
+    }
+}
+
+/// A resource record stored in a [`DecodeArena`].
+#[derive(Clone, Copy)]
+pub struct ArenaRecord<'a> {
+    arena: &'a DecodeArena,
+    raw: &'a RawRecord,
+}
+
+impl<'a> ArenaRecord<'a> {
+    /// Returns the domain name this record belongs to.
+    #[inline]
+    pub fn name(&self) -> ArenaName<'a> {
+        ArenaName::new(self.arena, self.raw.name)
+    }
+
+    /// Returns the record type.
+    #[inline]
+    pub fn type_(&self) -> Type {
+        self.raw.type_
+    }
+
+    /// Returns the record class.
+    #[inline]
+    pub fn class(&self) -> Class {
+        self.raw.class
+    }
+
+    /// Returns whether the mDNS cache-flush bit is set in this record.
+    #[inline]
+    pub fn cache_flush(&self) -> bool {
+        self.raw.cache_flush
+    }
+
+    /// Returns the time-to-live of this record, in seconds.
+    #[inline]
+    pub fn ttl(&self) -> u32 {
+        self.raw.ttl
+    }
+
+    /// Returns the raw record data.
+    ///
+    /// Note that any domain names in the record data may contain compression pointers referring
+    /// to the original message, which is no longer available.
+    #[inline]
+    pub fn rdata(&self) -> &'a [u8] {
+        &self.arena.bytes[self.raw.rdata.range()]
+    }
+
+    /// If this is an [`A`] or [`AAAA`] record, returns the contained IP address.
+    ///
+    /// [`A`]: crate::packet::records::A
+    /// [`AAAA`]: crate::packet::records::AAAA
+    pub fn ip_addr(&self) -> Option<Result<IpAddr, Error>> {
+        let res = match self.type_() {
+            Type::A => <[u8; 4]>::try_from(self.rdata()).map(|o| Ipv4Addr::from(o).into()),
+            Type::AAAA => <[u8; 16]>::try_from(self.rdata()).map(|o| Ipv6Addr::from(o).into()),
+            _ => return None,
+        };
+        Some(res.map_err(|_| Error::InvalidValue))
+    }
+
+    /// If this is a [`CNAME`], [`NS`], [`PTR`], [`MX`], or [`SRV`] record, returns the domain name
+    /// it points to.
+    ///
+    /// Returns [`None`] for other record types, and when the record data is malformed.
+    ///
+    /// [`CNAME`]: crate::packet::records::CNAME
+    /// [`NS`]: crate::packet::records::NS
+    /// [`PTR`]: crate::packet::records::PTR
+    /// [`MX`]: crate::packet::records::MX
+    /// [`SRV`]: crate::packet::records::SRV
+    pub fn target(&self) -> Option<ArenaName<'a>> {
+        self.raw.target.map(|span| ArenaName::new(self.arena, span))
+    }
+
+    /// If this is a [`TXT`] record, returns an iterator over its entries.
+    ///
+    /// Returns [`None`] for other record types, and when the record data is malformed.
+    ///
+    /// [`TXT`]: crate::packet::records::TXT
+    pub fn txt_entries(&self) -> Option<ArenaStrings<'a>> {
+        self.raw.txt.map(|span| ArenaStrings {
+            arena: self.arena,
+            indices: span.range(),
+        })
+    }
+
+    /// If this is an EDNS(0) `OPT` record, returns an iterator over its options.
+    pub fn edns_options(&self) -> Option<EdnsOptions<'a>> {
+        match self.type_() {
+            Type::OPT => Some(EdnsOptions::new(self.rdata())),
+            _ => None,
+        }
+    }
+
+    /// If this is an EDNS(0) `OPT` record, returns the fields it stores in place of its CLASS and
+    /// TTL.
+    pub fn edns_header(&self) -> Option<EdnsHeader> {
+        match self.type_() {
+            Type::OPT => Some(EdnsHeader::new(
+                self.class().0 | if self.cache_flush() { 0x8000 } else { 0 },
+                self.ttl(),
+            )),
+            _ => None,
+        }
+    }
+
+    fn fmt_rdata(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rdata = self.rdata();
+        match self.type_() {
+            Type::A | Type::AAAA => {
+                if let Some(Ok(ip)) = self.ip_addr() {
+                    return write!(f, "{}", ip);
+                }
+            }
+            Type::CNAME | Type::NS | Type::PTR => {
+                if let Some(target) = self.target() {
+                    return write!(f, "{}", target);
+                }
+            }
+            Type::MX => {
+                if let Some(target) = self.target() {
+                    let preference = u16::from_be_bytes([rdata[0], rdata[1]]);
+                    return write!(f, "{} {}", preference, target);
+                }
+            }
+            Type::SRV => {
+                if let Some(target) = self.target() {
+                    let field = |i: usize| u16::from_be_bytes([rdata[i], rdata[i + 1]]);
+                    return write!(f, "{}\t{}\t{}\t{}", field(0), field(2), field(4), target);
+                }
+            }
+            Type::TXT => {
+                if let Some(entries) = self.txt_entries() {
+                    return records::fmt_txt_entries(entries, f);
+                }
+            }
+            Type::OPT => {
+                for (i, option) in EdnsOptions::new(rdata).enumerate() {
+                    if i != 0 {
+                        f.write_str(" ")?;
+                    }
+                    match option {
+                        Ok(option) => write!(f, "{}", option)?,
+                        Err(e) => write!(f, "{}", e)?,
+                    }
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+        write!(f, "{:02x?}", rdata)
+    }
+}
+
+impl fmt::Debug for ArenaRecord<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArenaRecord")
+            .field("name", &self.name())
+            .field("type_", &self.type_())
+            .field("class", &self.class())
+            .field("cache_flush", &self.cache_flush())
+            .field("ttl", &self.ttl())
+            .field("rdata", &self.rdata())
+            .finish()
+    }
+}
+
+/// Formats the record like [`decoder::ResourceRecord`] does.
+///
+/// Record data is only decoded for the record types that [`ArenaRecord`] has accessors for. Data
+/// of other types, or malformed data, is printed as raw bytes.
+impl fmt::Display for ArenaRecord<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t",
+            self.name(),
+            self.ttl(),
+            self.class(),
+            self.type_()
+        )?;
+        self.fmt_rdata(f)
+    }
+}
+
+/// A domain name stored in a [`DecodeArena`].
+#[derive(Clone, Copy)]
+pub struct ArenaName<'a> {
+    arena: &'a DecodeArena,
+    labels: Span,
+}
+
+impl<'a> ArenaName<'a> {
+    fn new(arena: &'a DecodeArena, labels: Span) -> Self {
+        Self { arena, labels }
+    }
+
+    /// Returns an iterator over the labels of this name.
+    ///
+    /// The terminating empty label is not included.
+    pub fn labels(&self) -> ArenaStrings<'a> {
+        ArenaStrings {
+            arena: self.arena,
+            indices: self.labels.range(),
+        }
+    }
+
+    /// Returns whether this name is `domain` or a subdomain of it.
+    ///
+    /// Labels are compared exactly, like [`DomainName`]'s [`PartialEq`] implementation does.
+    pub fn ends_with(&self, domain: &DomainName) -> bool {
+        let labels = self.labels();
+        labels.len() >= domain.labels().len()
+            && labels
+                .rev()
+                .zip(domain.labels().iter().rev())
+                .all(|(a, b)| a == b.as_bytes())
+    }
+
+    /// Compares this name to a [`DomainName`], ignoring ASCII case.
+    pub fn eq_ignore_ascii_case(&self, name: &DomainName) -> bool {
+        let labels = self.labels();
+        labels.len() == name.labels().len()
+            && labels
+                .zip(name.labels())
+                .all(|(a, b)| a.eq_ignore_ascii_case(b.as_bytes()))
+    }
+
+    /// Copies this name into an owned [`DomainName`].
+    pub fn to_domain_name(&self) -> DomainName {
+        self.labels().map(Label::new).collect()
+    }
+}
+
+impl fmt::Debug for ArenaName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, r#""{}""#, self)
+    }
+}
+
+impl fmt::Display for ArenaName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.labels.start == self.labels.end {
+            return f.write_str(".");
+        }
+        for label in self.labels() {
+            write!(f, "{}.", label.escape_ascii())?;
+        }
+        Ok(())
+    }
+}
+
+/// An iterator over the labels of an [`ArenaName`], or the entries of a TXT record.
+#[derive(Debug, Clone)]
+pub struct ArenaStrings<'a> {
+    arena: &'a DecodeArena,
+    indices: Range<usize>,
+}
+
+impl<'a> Iterator for ArenaStrings<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.indices.next()?;
+        Some(self.arena.string(index))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.indices.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for ArenaStrings<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let index = self.indices.next_back()?;
+        Some(self.arena.string(index))
+    }
+}
+
+impl ExactSizeIterator for ArenaStrings<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        domain, hex,
+        packet::{
+            decoder::MessageDecoder,
+            encoder::{MessageEncoder, Question, ResourceRecord},
+            records::{Record, PTR, SRV, TXT},
+        },
+    };
+
+    use super::*;
+
+    fn format(msg: &[u8]) -> (Vec<String>, Vec<String>) {
+        let mut decoded = Vec::new();
+        MessageDecoder::new(msg)
+            .unwrap()
+            .format(|args| decoded.push(args.to_string()))
+            .unwrap();
+        let mut arena = Vec::new();
+        DecodeArena::new()
+            .decode(msg)
+            .unwrap()
+            .format(|args| arena.push(args.to_string()));
+        (decoded, arena)
+    }
+
+    #[test]
+    fn matches_decoder() {
+        let service = domain!("_http._tcp.local");
+        let instance = domain!("a very long instance name._http._tcp.local");
+        let host = domain!("host.local");
+        let ptr = Record::PTR(PTR::new(&instance));
+        let srv = Record::SRV(SRV::new(1, 2, 8080, &host));
+        let txt = Record::TXT(TXT::new([b"path=/".to_vec(), b"flag".to_vec()]));
+
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf);
+        enc.question(Question::new(&service).ty(QType::PTR));
+        let mut enc = enc.answers();
+        enc.add_answer(ResourceRecord::new(&service, &ptr).ttl(4500));
+        let mut enc = enc.authority().additional();
+        enc.add_additional(ResourceRecord::new(&instance, &srv).ttl(120));
+        enc.add_additional(ResourceRecord::new(&instance, &txt).ttl(4500));
+        let len = enc.finish().unwrap();
+
+        let (decoded, arena) = format(&buf[..len]);
+        assert_eq!(decoded, arena);
+
+        // Uses name compression, both in owner names and record data.
+        let msg = hex::parse("303984000001000100000000095f7365727669636573075f646e732d7364045f756470056c6f63616c00000c0001c00c000c00010000000a000e065f6361636865045f746370c023");
+        let (decoded, arena) = format(&msg);
+        assert_eq!(decoded, arena);
+    }
+
+    #[test]
+    fn reuse() {
+        let name = domain!("example.com");
+        let target = domain!("host.example.com");
+        let ptr = Record::PTR(PTR::new(&target));
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf).answers();
+        enc.add_answer(ResourceRecord::new(&name, &ptr));
+        let len = enc.finish().unwrap();
+
+        let mut arena = DecodeArena::new();
+        for _ in 0..3 {
+            let msg = arena.decode(&buf[..len]).unwrap();
+            assert_eq!(msg.questions().len(), 0);
+            let rr = msg.answers().next().unwrap();
+            assert!(rr.name().eq_ignore_ascii_case(&domain!("EXAMPLE.com")));
+            assert!(rr.target().unwrap().ends_with(&name));
+            assert!(!rr.target().unwrap().ends_with(&domain!("host.com")));
+            assert_eq!(rr.target().unwrap().to_domain_name(), target);
+            assert_eq!(msg.authority().len() + msg.additional().len(), 0);
+        }
+        assert_eq!(arena.strings.len(), 5);
+
+        assert_eq!(arena.decode(&buf[..len - 1]).err(), Some(Error::Eof));
+    }
+}
@@ -1,18 +1,21 @@
 //! DNS packet decoder.
 
 use core::mem;
-use std::{any::TypeId, cell::Cell, cmp, fmt, marker::PhantomData, mem::size_of};
+use std::{
+    any::TypeId, cell::Cell, cmp, fmt, marker::PhantomData, mem::size_of, net::IpAddr, ops::Range,
+};
 
 use bytemuck::AnyBitPattern;
 
 use crate::{
-    name::{DomainName, Label},
+    name::{DomainName, Label, SmallDomainName},
     num::{U16, U32},
     Error,
 };
 
 use super::{
-    records::Record,
+    edns::{EdnsHeader, EdnsOptions},
+    records::{self, Record, RecordData},
     section::{self, Section},
     Class, Header, QClass, QType, Type,
 };
@@ -83,7 +86,7 @@ impl<'a> Reader<'a> {
     }
 
     pub(crate) fn read_u8(&self) -> Result<u8, Error> {
-        Ok(self.read_obj::<u8>()?)
+        self.read_obj::<u8>()
     }
 
     pub(crate) fn read_u16(&self) -> Result<u16, Error> {
@@ -103,6 +106,35 @@ impl<'a> Reader<'a> {
     /// Reads a `<domain-name>` value.
     pub(crate) fn read_domain_name(&self) -> Result<DomainName, Error> {
         let mut domain_name = DomainName::ROOT;
+        self.read_domain_name_into(&mut domain_name)?;
+        Ok(domain_name)
+    }
+
+    /// Reads a `<domain-name>` value into `name`, replacing its contents but reusing its storage.
+    pub(crate) fn read_domain_name_into(&self, name: &mut DomainName) -> Result<(), Error> {
+        name.clear();
+        self.walk_domain_name(|label| {
+            name.push_label(Label::try_new(label)?);
+            Ok(())
+        })
+    }
+
+    /// Reads a `<domain-name>` value without copying it out of the message.
+    pub(crate) fn read_name_ref(&self) -> Result<NameRef<'a>, Error> {
+        let name = NameRef {
+            msg: self.full_buf,
+            pos: self.pos.get(),
+        };
+        self.walk_domain_name(|_| Ok(()))?;
+        Ok(name)
+    }
+
+    /// Reads a `<domain-name>` value, following and validating compression pointers, and invokes
+    /// `on_label` for every label in the name.
+    pub(crate) fn walk_domain_name(
+        &self,
+        mut on_label: impl FnMut(&'a [u8]) -> Result<(), Error>,
+    ) -> Result<(), Error> {
         let mut min_pos = self.pos.get();
         let mut copy = self.clone();
         loop {
@@ -131,14 +163,14 @@ impl<'a> Reader<'a> {
                         break;
                     }
                     let label = copy.read_slice(length)?;
-                    domain_name.push_label(Label::try_new(label)?);
+                    on_label(label)?;
                 }
                 _ => return Err(Error::InvalidValue), // anything but 00 and 11 in MSb is reserved
             }
         }
 
         self.pos.set(cmp::max(self.pos.get(), copy.pos.get()));
-        Ok(domain_name)
+        Ok(())
     }
 
     fn read_question(&mut self) -> Result<Question, Error> {
@@ -155,8 +187,45 @@ impl<'a> Reader<'a> {
         })
     }
 
+    fn read_question_ref(&mut self) -> Result<QuestionRef<'a>, Error> {
+        let qname = self.read_name_ref()?;
+        let qtype = QType(self.read_u16()?);
+        let qclass = QClass(self.read_u16()? & 0xff);
+        Ok(QuestionRef {
+            qname,
+            qtype,
+            qclass,
+        })
+    }
+
+    fn read_resource_record_ref(&mut self) -> Result<ResourceRecordRef<'a>, Error> {
+        let name = self.read_name_ref()?;
+        let (type_, class, cache_flush, ttl, rdata) = self.read_rr_fields()?;
+        Ok(ResourceRecordRef {
+            name,
+            type_,
+            class,
+            cache_flush,
+            ttl,
+            rdata,
+        })
+    }
+
     fn read_resource_record(&mut self) -> Result<ResourceRecord<'a>, Error> {
         let name = self.read_domain_name()?;
+        let (type_, class, cache_flush, ttl, rdata) = self.read_rr_fields()?;
+        Ok(ResourceRecord {
+            name,
+            type_,
+            class,
+            cache_flush,
+            ttl,
+            rdata,
+        })
+    }
+
+    /// Reads the fields following the owner name of a resource record.
+    pub(crate) fn read_rr_fields(&mut self) -> Result<(Type, Class, bool, u32, Reader<'a>), Error> {
         let type_ = Type(self.read_u16()?);
         let mut cache_flush = false;
         let class = {
@@ -170,14 +239,7 @@ impl<'a> Reader<'a> {
         let ttl = self.read_u32()?;
         let rdlength = self.read_u16()?;
         let rdata = self.split_off(usize::from(rdlength))?;
-        Ok(ResourceRecord {
-            name,
-            type_,
-            class,
-            cache_flush,
-            ttl,
-            rdata,
-        })
+        Ok((type_, class, cache_flush, ttl, rdata))
     }
 }
 
@@ -193,6 +255,10 @@ impl<'a> Reader<'a> {
 /// currently decoding as the `S` type parameter. Initially (after calling [`MessageDecoder::new`]),
 /// the decoder is in the [`section::Question`] state, and is advanced by calling the appropriate
 /// methods.
+///
+/// Cloning a decoder is cheap, and yields an independent cursor at the same position. This can be
+/// used to look ahead (eg. at the questions) and later resume decoding from the saved position.
+/// [`MessageDecoder::rewind`] restarts decoding from the beginning of the message.
 pub struct MessageDecoder<'a, S: Section> {
     header: Header,
     q_remaining: u16,
@@ -204,6 +270,21 @@ pub struct MessageDecoder<'a, S: Section> {
     section: PhantomData<(S, *const ())>, // not Send/Sync
 }
 
+impl<S: Section> Clone for MessageDecoder<'_, S> {
+    fn clone(&self) -> Self {
+        Self {
+            header: self.header,
+            q_remaining: self.q_remaining,
+            ans_remaining: self.ans_remaining,
+            auth_remaining: self.auth_remaining,
+            addl_remaining: self.addl_remaining,
+            r: self.r.clone(),
+            has_errored: self.has_errored,
+            section: PhantomData,
+        }
+    }
+}
+
 impl<'a> MessageDecoder<'a, section::Question> {
     /// Creates a streaming message decoder that will read from `buf`.
     pub fn new(buf: &'a [u8]) -> Result<Self, Error> {
@@ -224,24 +305,7 @@ impl<'a> MessageDecoder<'a, section::Question> {
     pub(crate) fn format(self, mut cb: impl FnMut(fmt::Arguments<'_>)) -> Result<(), Error> {
         let mut msg = self;
 
-        let h = msg.header();
-        let dir = if h.is_query() { "query" } else { "response" };
-        let trunc = if h.is_truncated() { ", trunc" } else { "" };
-        let ra = if h.is_recursion_available() {
-            ", RA"
-        } else {
-            ""
-        };
-        let rd = if h.is_recursion_desired() { ", RD" } else { "" };
-        let aa = if h.is_authority() { ", AA" } else { "" };
-        cb(format_args!(
-            "{} (id={}, op={}, rcode={}{trunc}{ra}{rd}{aa})",
-            dir,
-            h.id(),
-            h.opcode(),
-            h.rcode(),
-        ));
-
+        format_header(msg.header(), &mut cb);
         for q in msg.iter() {
             let q = q?;
             cb(format_args!("Q: {}", q));
@@ -266,6 +330,26 @@ impl<'a> MessageDecoder<'a, section::Question> {
     }
 }
 
+/// Formats the first line of [`MessageDecoder::format`]'s output, describing the message header.
+pub(crate) fn format_header(h: &Header, mut cb: impl FnMut(fmt::Arguments<'_>)) {
+    let dir = if h.is_query() { "query" } else { "response" };
+    let trunc = if h.is_truncated() { ", trunc" } else { "" };
+    let ra = if h.is_recursion_available() {
+        ", RA"
+    } else {
+        ""
+    };
+    let rd = if h.is_recursion_desired() { ", RD" } else { "" };
+    let aa = if h.is_authority() { ", AA" } else { "" };
+    cb(format_args!(
+        "{} (id={}, op={}, rcode={}{trunc}{ra}{rd}{aa})",
+        dir,
+        h.id(),
+        h.opcode(),
+        h.rcode(),
+    ));
+}
+
 impl<'a, S: Section> MessageDecoder<'a, S> {
     /// Returns the message header.
     #[inline]
@@ -273,6 +357,72 @@ impl<'a, S: Section> MessageDecoder<'a, S> {
         &self.header
     }
 
+    /// Returns the complete encoded message this decoder reads from.
+    ///
+    /// The ranges returned by [`MessageDecoder::position`] and [`MessageDecoder::next_span`]
+    /// index into this buffer.
+    #[inline]
+    pub fn message(&self) -> &'a [u8] {
+        self.r.full_buf
+    }
+
+    /// Returns the offset of the next entry in [`MessageDecoder::message`].
+    ///
+    /// Taken before and after reading an entry, this yields the bytes the entry occupies.
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.r.pos.get()
+    }
+
+    /// Skips the next entry in the current section, and returns the range of bytes it occupies in
+    /// [`MessageDecoder::message`].
+    ///
+    /// The entry is validated like it would be by `next_borrowed`, but nothing is allocated.
+    ///
+    /// Note that the names in the entry may be compressed, pointing at earlier parts of the
+    /// message. Copying the bytes into another message only preserves their meaning if the
+    /// pointers stay valid there, for example when splicing a section into a copy of the message
+    /// prefix it was decoded from.
+    pub fn next_span(&mut self) -> Option<Result<Range<usize>, Error>> {
+        if self.has_errored || *self.remaining() == 0 {
+            return None;
+        }
+
+        let start = self.position();
+        let res = if TypeId::of::<S>() == TypeId::of::<section::Question>() {
+            self.r.read_question_ref().map(drop)
+        } else {
+            self.r.read_resource_record_ref().map(drop)
+        };
+        if let Err(e) = res {
+            self.has_errored = true;
+            return Some(Err(e));
+        }
+
+        *self.remaining() -= 1;
+
+        Some(Ok(start..self.position()))
+    }
+
+    /// Returns an iterator over the byte ranges of all remaining entries in the current section.
+    ///
+    /// See [`MessageDecoder::next_span`].
+    pub fn spans(&mut self) -> SpanIter<'_, 'a, S> {
+        SpanIter { dec: self }
+    }
+
+    /// Returns a new decoder positioned at the start of the *Question* section of the same message.
+    ///
+    /// `self` is left untouched, so it can continue decoding from its current position.
+    pub fn rewind(&self) -> MessageDecoder<'a, section::Question> {
+        MessageDecoder::new(self.r.full_buf).expect("header was already decoded successfully")
+    }
+
+    /// Returns the number of bytes in the message that have not been decoded yet.
+    pub(crate) fn remaining_len(&self) -> usize {
+        self.r.buf().len()
+    }
+
     fn remaining(&mut self) -> &mut u16 {
         if TypeId::of::<S>() == TypeId::of::<section::Question>() {
             &mut self.q_remaining
@@ -317,10 +467,29 @@ impl<'a, S: Section> MessageDecoder<'a, S> {
 
         Some(Ok(rr))
     }
+
+    fn next_rr_ref(&mut self) -> Option<Result<ResourceRecordRef<'a>, Error>> {
+        if self.has_errored || *self.remaining() == 0 {
+            return None;
+        }
+
+        let rr = match self.r.read_resource_record_ref() {
+            Ok(rr) => rr,
+            Err(e) => {
+                self.has_errored = true;
+                return Some(Err(e));
+            }
+        };
+
+        *self.remaining() -= 1;
+
+        Some(Ok(rr))
+    }
 }
 
 impl<'a> MessageDecoder<'a, section::Question> {
     /// Reads the next [`Question`] from the *Question* section.
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<Result<Question, Error>> {
         if self.has_errored || *self.remaining() == 0 {
             return None;
@@ -339,6 +508,25 @@ impl<'a> MessageDecoder<'a, section::Question> {
         Some(Ok(question))
     }
 
+    /// Reads the next [`QuestionRef`] from the *Question* section, without allocating.
+    pub fn next_borrowed(&mut self) -> Option<Result<QuestionRef<'a>, Error>> {
+        if self.has_errored || *self.remaining() == 0 {
+            return None;
+        }
+
+        let question = match self.r.read_question_ref() {
+            Ok(q) => q,
+            Err(e) => {
+                self.has_errored = true;
+                return Some(Err(e));
+            }
+        };
+
+        *self.remaining() -= 1;
+
+        Some(Ok(question))
+    }
+
     /// Returns an iterator over all [`Question`]s in the *Question* section of the message.
     pub fn iter(&mut self) -> QuestionIter<'_, 'a> {
         QuestionIter { dec: self }
@@ -370,10 +558,16 @@ impl<'a> MessageDecoder<'a, section::Question> {
 
 impl<'a> MessageDecoder<'a, section::Answer> {
     /// Reads the next [`ResourceRecord`] from the *Answer* section.
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<Result<ResourceRecord<'_>, Error>> {
         self.next_rr()
     }
 
+    /// Reads the next [`ResourceRecordRef`] from this section, without allocating.
+    pub fn next_borrowed(&mut self) -> Option<Result<ResourceRecordRef<'a>, Error>> {
+        self.next_rr_ref()
+    }
+
     /// Returns an iterator over all resource records in the *Answer* section.
     pub fn iter(&mut self) -> ResourceRecordIter<'_, 'a, section::Answer> {
         ResourceRecordIter { dec: self }
@@ -398,10 +592,16 @@ impl<'a> MessageDecoder<'a, section::Answer> {
 
 impl<'a> MessageDecoder<'a, section::Authority> {
     /// Reads the next [`ResourceRecord`] from the *Authority* section.
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<Result<ResourceRecord<'_>, Error>> {
         self.next_rr()
     }
 
+    /// Reads the next [`ResourceRecordRef`] from this section, without allocating.
+    pub fn next_borrowed(&mut self) -> Option<Result<ResourceRecordRef<'a>, Error>> {
+        self.next_rr_ref()
+    }
+
     /// Returns an iterator over all resource records in the *Authority* section.
     pub fn iter(&mut self) -> ResourceRecordIter<'_, 'a, section::Authority> {
         ResourceRecordIter { dec: self }
@@ -418,16 +618,37 @@ impl<'a> MessageDecoder<'a, section::Authority> {
 
 impl<'a> MessageDecoder<'a, section::Additional> {
     /// Reads the next [`ResourceRecord`] from the *Additional Records* section.
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<Result<ResourceRecord<'_>, Error>> {
         self.next_rr()
     }
 
+    /// Reads the next [`ResourceRecordRef`] from this section, without allocating.
+    pub fn next_borrowed(&mut self) -> Option<Result<ResourceRecordRef<'a>, Error>> {
+        self.next_rr_ref()
+    }
+
     /// Returns an iterator over all resource records in the *Additional Record* section.
     pub fn iter(&mut self) -> ResourceRecordIter<'_, 'a, section::Additional> {
         ResourceRecordIter { dec: self }
     }
 }
 
+/// Iterator over the byte ranges of the entries in a section of a DNS message.
+///
+/// Returned by [`MessageDecoder::spans`].
+pub struct SpanIter<'dec, 'data, S: Section> {
+    dec: &'dec mut MessageDecoder<'data, S>,
+}
+
+impl<'dec, 'data, S: Section> Iterator for SpanIter<'dec, 'data, S> {
+    type Item = Result<Range<usize>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.dec.next_span()
+    }
+}
+
 /// Iterator over Resource Records in a DNS message.
 pub struct ResourceRecordIter<'dec, 'data, S: Section> {
     dec: &'dec mut MessageDecoder<'data, S>,
@@ -492,6 +713,73 @@ impl<'a> ResourceRecord<'a> {
     pub fn as_enum(&self) -> Option<Result<Record<'_>, Error>> {
         Record::from_rr(self)
     }
+
+    /// Decodes the record data into `record`, reusing its allocations where possible.
+    ///
+    /// This is cheaper than [`ResourceRecord::as_enum`] when decoding many records of the same
+    /// type, since the same `record` can be reused for all of them. See
+    /// [`RecordData::decode_into`].
+    ///
+    /// Returns [`Error::InvalidValue`] if this record is not of type `T`.
+    pub fn decode_into<T: RecordData<'a>>(&self, record: &mut T) -> Result<(), Error> {
+        if self.type_ != T::TYPE {
+            return Err(Error::InvalidValue);
+        }
+        record.decode_into(&mut records::Decoder {
+            r: self.rdata.clone(),
+        })
+    }
+
+    /// If this is a `TXT` record, returns an iterator over its entries.
+    ///
+    /// Unlike decoding a [`TXT`](records::TXT) record, this does not allocate.
+    pub fn txt_entries(&self) -> Option<TxtEntries<'a>> {
+        match self.type_ {
+            Type::TXT => Some(TxtEntries::new(self.rdata.buf())),
+            _ => None,
+        }
+    }
+
+    /// If this is an EDNS(0) `OPT` record, returns an iterator over its options.
+    pub fn edns_options(&self) -> Option<EdnsOptions<'_>> {
+        match self.type_ {
+            Type::OPT => Some(EdnsOptions::new(self.rdata())),
+            _ => None,
+        }
+    }
+
+    /// If this is an EDNS(0) `OPT` record, returns the fields it stores in place of its CLASS and
+    /// TTL.
+    pub fn edns_header(&self) -> Option<EdnsHeader> {
+        match self.type_ {
+            // The payload size may have the top bit set, which was decoded as the cache-flush bit.
+            Type::OPT => Some(EdnsHeader::new(
+                self.class.0 | if self.cache_flush { 0x8000 } else { 0 },
+                self.ttl,
+            )),
+            _ => None,
+        }
+    }
+
+    /// Converts this record into an [`OwnedResourceRecord`] that no longer borrows from the
+    /// message buffer.
+    ///
+    /// Supported record types are decoded in the process, so this will return an error if the
+    /// record data is malformed.
+    pub fn into_owned(self) -> Result<OwnedResourceRecord, Error> {
+        let data = match self.as_enum() {
+            Some(res) => OwnedRData::Record(res?.into_owned()),
+            None => OwnedRData::Raw(self.rdata().into()),
+        };
+        Ok(OwnedResourceRecord {
+            name: self.name,
+            type_: self.type_,
+            class: self.class,
+            cache_flush: self.cache_flush,
+            ttl: self.ttl,
+            data,
+        })
+    }
 }
 
 impl<'a> fmt::Debug for ResourceRecord<'a> {
@@ -528,15 +816,482 @@ impl<'a> fmt::Display for ResourceRecord<'a> {
             Some(Err(e)) => {
                 write!(f, "{}", e)?;
             }
-            None => {
-                write!(f, "{:02x?}", self.rdata())?;
-            }
+            None => write!(f, "{:02x?}", self.rdata())?,
         }
 
         Ok(())
     }
 }
 
+/// A Resource Record that owns all of its data.
+///
+/// This is created from a [`ResourceRecord`] via [`ResourceRecord::into_owned`], and can be kept
+/// around after the message buffer is reused (for example, in a cache).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedResourceRecord {
+    name: DomainName,
+    type_: Type,
+    class: Class,
+    cache_flush: bool,
+    ttl: u32,
+    data: OwnedRData,
+}
+
+// Unsupported record types are rare, so the size difference doesn't matter much.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum OwnedRData {
+    Record(Record<'static>),
+    /// RDATA of an unsupported record type.
+    ///
+    /// [RFC 3597] forbids name compression in unknown record types, so this does not refer back
+    /// to the rest of the message.
+    ///
+    /// [RFC 3597]: https://datatracker.ietf.org/doc/html/rfc3597
+    Raw(Box<[u8]>),
+}
+
+impl OwnedResourceRecord {
+    /// Creates a record in the internet class ([`Class::IN`]) with a TTL of 0.
+    pub fn new(name: DomainName, record: Record<'_>) -> Self {
+        Self {
+            name,
+            type_: record.record_type(),
+            class: Class::IN,
+            cache_flush: false,
+            ttl: 0,
+            data: OwnedRData::Record(record.into_owned()),
+        }
+    }
+
+    /// Creates a record of a type that is unsupported by this library, from its raw record data.
+    ///
+    /// `rdata` must not contain compression pointers.
+    pub fn new_raw(name: DomainName, type_: Type, rdata: impl Into<Box<[u8]>>) -> Self {
+        Self {
+            name,
+            type_,
+            class: Class::IN,
+            cache_flush: false,
+            ttl: 0,
+            data: OwnedRData::Raw(rdata.into()),
+        }
+    }
+
+    /// Sets the record class.
+    #[inline]
+    pub fn set_class(&mut self, class: Class) {
+        self.class = class;
+    }
+
+    /// Sets the mDNS cache-flush bit.
+    #[inline]
+    pub fn set_cache_flush(&mut self, cache_flush: bool) {
+        self.cache_flush = cache_flush;
+    }
+
+    /// Sets the Time To Live, in seconds.
+    #[inline]
+    pub fn set_ttl(&mut self, ttl: u32) {
+        self.ttl = ttl;
+    }
+
+    #[inline]
+    pub fn name(&self) -> &DomainName {
+        &self.name
+    }
+
+    #[inline]
+    pub fn type_(&self) -> Type {
+        self.type_
+    }
+
+    #[inline]
+    pub fn class(&self) -> Class {
+        self.class
+    }
+
+    /// Returns whether the record's mDNS cache-flush bit is set.
+    #[inline]
+    pub fn cache_flush(&self) -> bool {
+        self.cache_flush
+    }
+
+    /// Returns the entry's Time To Live, in seconds.
+    #[inline]
+    pub fn ttl(&self) -> u32 {
+        self.ttl
+    }
+
+    /// Returns the decoded record data, or [`None`] if the record type is unsupported by this
+    /// library.
+    pub fn record(&self) -> Option<&Record<'static>> {
+        match &self.data {
+            OwnedRData::Record(record) => Some(record),
+            OwnedRData::Raw(_) => None,
+        }
+    }
+
+    /// Returns the raw record data of an unsupported record type.
+    ///
+    /// Returns [`None`] for supported record types, use [`OwnedResourceRecord::record`] instead.
+    pub fn raw_rdata(&self) -> Option<&[u8]> {
+        match &self.data {
+            OwnedRData::Record(_) => None,
+            OwnedRData::Raw(rdata) => Some(rdata),
+        }
+    }
+}
+
+impl fmt::Display for OwnedResourceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t",
+            self.name(),
+            self.ttl(),
+            self.class(),
+            self.type_()
+        )?;
+        match &self.data {
+            OwnedRData::Record(rr) => write!(f, "{}", rr),
+            OwnedRData::Raw(rdata) => write!(f, "{:02x?}", rdata),
+        }
+    }
+}
+
+/// A Resource Record that borrows all of its data from the message it was decoded from.
+///
+/// This is the allocation-free counterpart of [`ResourceRecord`], returned by the `next_borrowed`
+/// methods of [`MessageDecoder`].
+#[derive(Debug, Clone)]
+pub struct ResourceRecordRef<'a> {
+    name: NameRef<'a>,
+    type_: Type,
+    class: Class,
+    cache_flush: bool,
+    ttl: u32,
+    rdata: Reader<'a>,
+}
+
+impl<'a> ResourceRecordRef<'a> {
+    #[inline]
+    pub fn name(&self) -> NameRef<'a> {
+        self.name
+    }
+
+    #[inline]
+    pub fn type_(&self) -> Type {
+        self.type_
+    }
+
+    #[inline]
+    pub fn class(&self) -> Class {
+        self.class
+    }
+
+    /// Returns whether the record's mDNS cache-flush bit is set.
+    #[inline]
+    pub fn cache_flush(&self) -> bool {
+        self.cache_flush
+    }
+
+    /// Returns the entry's Time To Live, in seconds.
+    #[inline]
+    pub fn ttl(&self) -> u32 {
+        self.ttl
+    }
+
+    /// Returns the raw record data.
+    #[inline]
+    pub fn rdata(&self) -> &'a [u8] {
+        self.rdata.buf()
+    }
+
+    /// If this is an [`A`] or [`AAAA`] record, returns the contained IP address.
+    ///
+    /// [`A`]: crate::packet::records::A
+    /// [`AAAA`]: crate::packet::records::AAAA
+    pub fn ip_addr(&self) -> Option<Result<IpAddr, Error>> {
+        let res = match self.type_ {
+            Type::A => <[u8; 4]>::try_from(self.rdata()).map(IpAddr::from),
+            Type::AAAA => <[u8; 16]>::try_from(self.rdata()).map(IpAddr::from),
+            _ => return None,
+        };
+        Some(res.map_err(|_| Error::InvalidValue))
+    }
+
+    /// If this is a [`CNAME`], [`NS`], [`PTR`], [`MX`], or [`SRV`] record, returns the domain name
+    /// it points to.
+    ///
+    /// [`CNAME`]: crate::packet::records::CNAME
+    /// [`NS`]: crate::packet::records::NS
+    /// [`PTR`]: crate::packet::records::PTR
+    /// [`MX`]: crate::packet::records::MX
+    /// [`SRV`]: crate::packet::records::SRV
+    pub fn target(&self) -> Option<Result<NameRef<'a>, Error>> {
+        let skip = match self.type_ {
+            Type::CNAME | Type::NS | Type::PTR => 0,
+            Type::MX => 2,
+            Type::SRV => 6,
+            _ => return None,
+        };
+        let r = self.rdata.clone();
+        Some(r.read_slice(skip).and_then(|_| r.read_name_ref()))
+    }
+
+    /// If this is a [`TXT`] record, returns an iterator over its entries.
+    ///
+    /// [`TXT`]: crate::packet::records::TXT
+    pub fn txt_entries(&self) -> Option<TxtEntries<'a>> {
+        match self.type_ {
+            Type::TXT => Some(TxtEntries::new(self.rdata())),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> fmt::Display for ResourceRecordRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{:02x?}",
+            self.name(),
+            self.ttl(),
+            self.class(),
+            self.type_(),
+            self.rdata(),
+        )
+    }
+}
+
+/// A question that borrows its name from the message it was decoded from.
+///
+/// This is the allocation-free counterpart of [`Question`], returned by
+/// [`MessageDecoder::next_borrowed`].
+#[derive(Debug, Clone, Copy)]
+pub struct QuestionRef<'a> {
+    qname: NameRef<'a>,
+    qtype: QType,
+    qclass: QClass,
+}
+
+impl<'a> QuestionRef<'a> {
+    /// Returns the domain name that is being queried.
+    #[inline]
+    pub fn qname(&self) -> NameRef<'a> {
+        self.qname
+    }
+
+    /// Returns the resource record types the client is interested in.
+    #[inline]
+    pub fn qtype(&self) -> QType {
+        self.qtype
+    }
+
+    /// Returns the record class that the client is interested in.
+    #[inline]
+    pub fn qclass(&self) -> QClass {
+        self.qclass
+    }
+}
+
+impl<'a> fmt::Display for QuestionRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\t{}\t{}", self.qname(), self.qclass(), self.qtype())
+    }
+}
+
+/// A domain name that is stored (possibly compressed) inside of a DNS message.
+///
+/// Unlike [`DomainName`], this type does not allocate. Its labels are read from the message on
+/// demand, following any compression pointers. The name has already been validated when the
+/// [`NameRef`] was created, so iterating over its labels cannot fail.
+#[derive(Clone, Copy)]
+pub struct NameRef<'a> {
+    msg: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> NameRef<'a> {
+    /// Refers to the name at `pos` in `msg`, which must be a valid (possibly compressed) name.
+    pub(crate) fn new(msg: &'a [u8], pos: usize) -> Self {
+        Self { msg, pos }
+    }
+
+    /// Returns an iterator over the labels of this name.
+    ///
+    /// The terminating empty label is not included.
+    pub fn labels(&self) -> NameLabels<'a> {
+        NameLabels {
+            msg: self.msg,
+            pos: self.pos,
+        }
+    }
+
+    /// Compares this name to a [`DomainName`], ignoring ASCII case.
+    pub fn eq_ignore_ascii_case(&self, name: &DomainName) -> bool {
+        let mut labels = self.labels();
+        name.labels().iter().all(|l| {
+            labels
+                .next()
+                .is_some_and(|o| o.eq_ignore_ascii_case(l.as_bytes()))
+        }) && labels.next().is_none()
+    }
+
+    /// Writes this name in uncompressed wire format into `buf`, and returns the number of bytes
+    /// written.
+    ///
+    /// Returns [`Error::Truncated`] if `buf` is too small.
+    pub fn write_uncompressed(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut pos = 0;
+        for label in self.labels() {
+            let end = pos + 1 + label.len();
+            let dest = buf.get_mut(pos..end).ok_or(Error::Truncated)?;
+            dest[0] = label.len() as u8;
+            dest[1..].copy_from_slice(label);
+            pos = end;
+        }
+        *buf.get_mut(pos).ok_or(Error::Truncated)? = 0;
+        Ok(pos + 1)
+    }
+
+    /// Copies this name into an owned [`DomainName`].
+    pub fn to_domain_name(&self) -> DomainName {
+        self.labels().map(Label::new).collect()
+    }
+
+    /// Copies this name in uncompressed wire format into a [`FixedVec`].
+    ///
+    /// Returns [`Error::Truncated`] if the name doesn't fit. This is like
+    /// [`NameRef::write_uncompressed`], but returns the buffer instead of writing into one.
+    ///
+    /// [`FixedVec`]: super::fixed::FixedVec
+    #[cfg(any(test, feature = "heapless"))]
+    pub fn to_fixed_bytes<const N: usize>(&self) -> Result<super::fixed::FixedVec<u8, N>, Error> {
+        let mut buf = super::fixed::FixedVec::new();
+        for label in self.labels() {
+            buf.push(label.len() as u8)?;
+            buf.extend_from_slice(label)?;
+        }
+        buf.push(0)?;
+        Ok(buf)
+    }
+
+    /// Copies this name into a stack-allocated [`SmallDomainName`].
+    ///
+    /// Returns [`Error::Truncated`] if the name doesn't fit.
+    pub fn to_small_domain_name<const N: usize>(&self) -> Result<SmallDomainName<N>, Error> {
+        let mut name = SmallDomainName::ROOT;
+        for label in self.labels() {
+            name.push_label(label)?;
+        }
+        Ok(name)
+    }
+}
+
+impl<'a> fmt::Debug for NameRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, r#""{}""#, self)
+    }
+}
+
+impl<'a> fmt::Display for NameRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut empty = true;
+        for label in self.labels() {
+            write!(f, "{}.", label.escape_ascii())?;
+            empty = false;
+        }
+        if empty {
+            f.write_str(".")?;
+        }
+        Ok(())
+    }
+}
+
+/// An iterator over the labels of a [`NameRef`].
+#[derive(Debug, Clone)]
+pub struct NameLabels<'a> {
+    msg: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Iterator for NameLabels<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let length = *self.msg.get(self.pos)?;
+            if length & 0b1100_0000 == 0b1100_0000 {
+                let low = *self.msg.get(self.pos + 1)?;
+                self.pos = usize::from(u16::from_be_bytes([length & 0b0011_1111, low]));
+                continue;
+            }
+            if length == 0 {
+                self.msg = &[];
+                return None;
+            }
+            let start = self.pos + 1;
+            let end = start + usize::from(length);
+            let label = self.msg.get(start..end)?;
+            self.pos = end;
+            return Some(label);
+        }
+    }
+}
+
+/// An iterator over the *character strings* in the RDATA of a [`TXT`] record.
+///
+/// This reads the entries directly from the message without allocating.
+///
+/// [`TXT`]: crate::packet::records::TXT
+#[derive(Debug, Clone)]
+pub struct TxtEntries<'a> {
+    r: Reader<'a>,
+    has_errored: bool,
+}
+
+impl<'a> TxtEntries<'a> {
+    /// Creates an iterator over the entries in the RDATA of a TXT record.
+    pub fn new(rdata: &'a [u8]) -> Self {
+        Self {
+            r: Reader::new(rdata),
+            has_errored: false,
+        }
+    }
+
+    /// Collects the remaining entries into a [`FixedVec`] holding up to `N` of them.
+    ///
+    /// Returns [`Error::Truncated`] if there are more than `N` entries, or the error encountered
+    /// while decoding an entry.
+    ///
+    /// [`FixedVec`]: super::fixed::FixedVec
+    #[cfg(any(test, feature = "heapless"))]
+    pub fn collect_fixed<const N: usize>(
+        self,
+    ) -> Result<super::fixed::FixedVec<&'a [u8], N>, Error> {
+        let mut entries = super::fixed::FixedVec::new();
+        for entry in self {
+            entries.push(entry?)?;
+        }
+        Ok(entries)
+    }
+}
+
+impl<'a> Iterator for TxtEntries<'a> {
+    type Item = Result<&'a [u8], Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.has_errored || self.r.buf().is_empty() {
+            return None;
+        }
+        let res = self.r.read_character_string();
+        self.has_errored = res.is_err();
+        Some(res)
+    }
+}
+
 /// An iterator over [`Question`]s in the *Question* section of a DNS message.
 pub struct QuestionIter<'dec, 'data> {
     dec: &'dec mut MessageDecoder<'data, section::Question>,
@@ -551,16 +1306,38 @@ impl<'dec, 'data> Iterator for QuestionIter<'dec, 'data> {
 }
 
 /// A question from a DNS query message.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Question {
     qname: DomainName,
     qtype: QType,
     qclass: QClass,
-    #[expect(dead_code)]
     prefer_unicast: bool,
 }
 
 impl Question {
+    /// Creates a question for the `qtype` records of `qname`, in the internet class
+    /// ([`QClass::IN`]).
+    pub fn new(qname: DomainName, qtype: QType) -> Self {
+        Self {
+            qname,
+            qtype,
+            qclass: QClass::IN,
+            prefer_unicast: false,
+        }
+    }
+
+    /// Sets the record class that is queried.
+    #[inline]
+    pub fn set_qclass(&mut self, qclass: QClass) {
+        self.qclass = qclass;
+    }
+
+    /// Sets the mDNS "unicast-response" bit.
+    #[inline]
+    pub fn set_prefer_unicast(&mut self, prefer_unicast: bool) {
+        self.prefer_unicast = prefer_unicast;
+    }
+
     /// Returns the domain name that is being queried.
     #[inline]
     pub fn qname(&self) -> &DomainName {
@@ -578,6 +1355,15 @@ impl Question {
     pub fn qclass(&self) -> QClass {
         self.qclass
     }
+
+    /// Returns whether the mDNS "unicast-response" bit is set, indicating that the querier would
+    /// like to receive a unicast response ([RFC 6762, section 5.4]).
+    ///
+    /// [RFC 6762, section 5.4]: https://datatracker.ietf.org/doc/html/rfc6762#section-5.4
+    #[inline]
+    pub fn prefers_unicast(&self) -> bool {
+        self.prefer_unicast
+    }
 }
 
 impl fmt::Display for Question {
@@ -676,6 +1462,65 @@ mod tests {
         assert_eq!(r.read_domain_name(), Err(Error::PointerLoop));
     }
 
+    #[test]
+    fn decode_borrowed() {
+        let packet = hex::parse("303984000001000100000000095f7365727669636573075f646e732d7364045f756470056c6f63616c00000c0001c00c000c00010000000a000e065f6361636865045f746370c023");
+        let mut dec = MessageDecoder::new(&packet).unwrap();
+        let q = dec.next_borrowed().unwrap().unwrap();
+        assert_eq!(q.to_string(), "_services._dns-sd._udp.local.\tIN\tPTR");
+        assert!(dec.next_borrowed().is_none());
+
+        let mut dec = dec.answers().unwrap();
+        let rr = dec.next_borrowed().unwrap().unwrap();
+        assert!(rr
+            .name()
+            .eq_ignore_ascii_case(&DomainName::from_str("_SERVICES._dns-sd._udp.local").unwrap()));
+        assert_eq!(rr.ttl(), 10);
+        let target = rr.target().unwrap().unwrap();
+        assert_eq!(target.to_string(), "_cache._tcp.local.");
+        let small = target.to_small_domain_name::<32>().unwrap();
+        assert_eq!(small.to_string(), "_cache._tcp.local.");
+        assert_eq!(target.to_small_domain_name::<8>(), Err(Error::Truncated));
+        assert_eq!(target.to_domain_name().to_string(), "_cache._tcp.local.");
+        assert!(rr.ip_addr().is_none());
+        assert!(rr.txt_entries().is_none());
+
+        let mut buf = [0; 64];
+        let len = target.write_uncompressed(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"\x06_cache\x04_tcp\x05local\x00");
+        assert_eq!(
+            target.write_uncompressed(&mut buf[..4]),
+            Err(Error::Truncated)
+        );
+    }
+
+    #[test]
+    fn into_owned() {
+        let packet = hex::parse("303984000001000100000000095f7365727669636573075f646e732d7364045f756470056c6f63616c00000c0001c00c000c00010000000a000e065f6361636865045f746370c023");
+        let rr = {
+            let packet = packet.clone();
+            let mut dec = MessageDecoder::new(&packet).unwrap().answers().unwrap();
+            let rr = dec.next().unwrap().unwrap();
+            rr.into_owned().unwrap()
+        };
+        assert_eq!(rr.ttl(), 10);
+        assert!(rr.raw_rdata().is_none());
+        match rr.record() {
+            Some(Record::PTR(ptr)) => assert_eq!(ptr.ptrdname().to_string(), "_cache._tcp.local."),
+            other => panic!("unexpected record {:?}", other),
+        }
+    }
+
+    #[test]
+    fn txt_entries() {
+        let entries = TxtEntries::new(b"\x03a=b\x00\x01c").collect::<Result<Vec<_>, _>>();
+        assert_eq!(entries.unwrap(), [&b"a=b"[..], b"", b"c"]);
+
+        let mut entries = TxtEntries::new(b"\x05abc");
+        assert_eq!(entries.next(), Some(Err(Error::Eof)));
+        assert_eq!(entries.next(), None);
+    }
+
     #[test]
     fn decode_dns_query() {
         check_decode("303901000002000000000000076578616d706c6503636f6d0000010001076578616d706c6503636f6d00001c0001", expect![[r#"
@@ -704,4 +1549,65 @@ mod tests {
             ANS: _services._dns-sd._udp.local.	10	IN	PTR	_cache._tcp.local.
         "#]]);
     }
+
+    #[test]
+    fn rewind() {
+        let packet = hex::parse("303981800001000100000000076578616d706c6503636f6d0000060001c00c0006000100000e10002c026e73056963616e6e036f726700036e6f6303646e73c02c7886aa5a00001c2000000e100012750000000e10");
+        let mut dec = MessageDecoder::new(&packet).unwrap();
+
+        // Peek at the question without consuming it.
+        let mut peek = dec.clone();
+        assert_eq!(peek.next().unwrap().unwrap().qtype(), QType::SOA);
+        assert!(peek.next().is_none());
+        assert_eq!(dec.next().unwrap().unwrap().qtype(), QType::SOA);
+
+        let mut ans = dec.answers().unwrap();
+        let saved = ans.clone();
+        assert_eq!(ans.next().unwrap().unwrap().type_(), Type::SOA);
+        assert!(ans.next().is_none());
+        let mut ans = saved;
+        assert_eq!(ans.next().unwrap().unwrap().type_(), Type::SOA);
+
+        let mut dec = ans.rewind();
+        assert_eq!(dec.iter().count(), 1);
+        assert_eq!(dec.answers().unwrap().iter().count(), 1);
+    }
+    #[test]
+    fn spans() {
+        let packet = hex::parse("303981800001000100000000076578616d706c6503636f6d0000060001c00c0006000100000e10002c026e73056963616e6e036f726700036e6f6303646e73c02c7886aa5a00001c2000000e100012750000000e10");
+        let mut dec = MessageDecoder::new(&packet).unwrap();
+        assert_eq!(dec.position(), 12);
+        let spans = dec.spans().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0], 12..29);
+        assert!(dec.next().is_none());
+
+        let mut ans = dec.answers().unwrap();
+        let saved = ans.clone();
+        let span = ans.next_span().unwrap().unwrap();
+        assert_eq!(span, 29..packet.len());
+        assert!(ans.next_span().is_none());
+
+        // The span covers exactly what `next` decodes.
+        let mut ans = saved;
+        let rr = ans.next().unwrap().unwrap();
+        assert_eq!(rr.type_(), Type::SOA);
+        assert_eq!(ans.position(), span.end);
+        assert_eq!(&ans.message()[span][..2], &[0xc0, 0x0c]);
+
+        // Splicing the question back into a copy of the header yields a valid query.
+        let mut query = packet[..spans[0].end].to_vec();
+        query[6..8].copy_from_slice(&[0, 0]);
+        let mut dec = MessageDecoder::new(&query).unwrap();
+        assert_eq!(dec.next().unwrap().unwrap().qtype(), QType::SOA);
+        assert_eq!(dec.additional().unwrap().next_span(), None);
+
+        // Errors are reported once, then iteration stops.
+        let mut dec = MessageDecoder::new(&packet[..40])
+            .unwrap()
+            .answers()
+            .unwrap();
+        assert_eq!(dec.next_span(), Some(Err(Error::Eof)));
+        assert_eq!(dec.next_span(), None);
+    }
 }
@@ -0,0 +1,117 @@
+//! Shared DNSSEC primitives.
+//!
+//! These types are used both for signing zones ([`Zone::sign`]) and for SIG(0) transaction
+//! signatures ([`sig0`]). The actual cryptography is left to implementors of [`Signer`].
+//!
+//! [`Zone::sign`]: crate::server::Zone::sign
+//! [`sig0`]: super::sig0
+
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{name::DomainName, Error};
+
+ffi_enum! {
+    /// DNSSEC algorithm numbers, as used in `SIG`, `KEY`, `RRSIG` and `DNSKEY` records.
+    pub enum Algorithm: u8 {
+        RSAMD5 = 1,
+        DH = 2,
+        DSA = 3,
+        RSASHA1 = 5,
+        DSA_NSEC3_SHA1 = 6,
+        RSASHA1_NSEC3_SHA1 = 7,
+        RSASHA256 = 8,
+        RSASHA512 = 10,
+        ECC_GOST = 12,
+        ECDSAP256SHA256 = 13,
+        ECDSAP384SHA384 = 14,
+        ED25519 = 15,
+        ED448 = 16,
+        INDIRECT = 252,
+        PRIVATEDNS = 253,
+        PRIVATEOID = 254,
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Creates DNSSEC and SIG(0) signatures with a private key.
+pub trait Signer {
+    /// Returns the algorithm of the signing key.
+    fn algorithm(&self) -> Algorithm;
+
+    /// Returns the key tag of the signing key, as computed from its `KEY` or `DNSKEY` record.
+    fn key_tag(&self) -> u16;
+
+    /// Returns the name under which the public key is published.
+    ///
+    /// For zone signing keys, this is the zone apex, which holds the `DNSKEY` record. SIG(0) keys
+    /// are published as a `KEY` record.
+    fn signer_name(&self) -> &DomainName;
+
+    /// Signs `data`, returning the signature in the wire format of [`Signer::algorithm`].
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// The validity period of an `RRSIG` or SIG(0) signature.
+///
+/// Times are stored as seconds since the UNIX epoch, modulo 2<sup>32</sup>, and compared using
+/// serial number arithmetic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Validity {
+    inception: u32,
+    expiration: u32,
+}
+
+impl Validity {
+    /// Clock skew to tolerate when creating signatures with [`Validity::starting_at`].
+    const FUDGE: Duration = Duration::from_secs(300);
+
+    /// Creates a validity period from raw inception and expiration times.
+    pub fn new(inception: u32, expiration: u32) -> Self {
+        Self {
+            inception,
+            expiration,
+        }
+    }
+
+    /// Creates a validity period starting at `now` and lasting for `lifetime`.
+    ///
+    /// To tolerate clocks that are slightly behind, the inception time is set to 5 minutes before
+    /// `now`.
+    pub fn starting_at(now: SystemTime, lifetime: Duration) -> Self {
+        let now = unix_time(now);
+        Self {
+            inception: now.wrapping_sub(Self::FUDGE.as_secs() as u32),
+            expiration: now.wrapping_add(lifetime.as_secs().try_into().unwrap_or(u32::MAX)),
+        }
+    }
+
+    /// Returns the time the signature becomes valid.
+    pub fn inception(&self) -> u32 {
+        self.inception
+    }
+
+    /// Returns the time the signature stops being valid.
+    pub fn expiration(&self) -> u32 {
+        self.expiration
+    }
+
+    /// Returns whether `time` lies within this validity period.
+    pub fn contains(&self, time: SystemTime) -> bool {
+        let time = unix_time(time);
+        time.wrapping_sub(self.inception) as i32 >= 0
+            && self.expiration.wrapping_sub(time) as i32 >= 0
+    }
+}
+
+fn unix_time(time: SystemTime) -> u32 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32)
+}
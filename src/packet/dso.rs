@@ -0,0 +1,350 @@
+//! DNS Stateful Operations ([RFC 8490]) message format.
+//!
+//! DSO messages use [`Opcode::DSO`] and carry no questions or resource records. Instead, the
+//! message body is a sequence of TLVs (type-length-value triples). The first TLV is the *Primary
+//! TLV* and determines the meaning of the message; any further TLVs are *Additional TLVs*.
+//!
+//! Session management on top of these messages is implemented in [`crate::dso`].
+//!
+//! [RFC 8490]: https://datatracker.ietf.org/doc/html/rfc8490
+
+use std::{fmt, time::Duration};
+
+use crate::Error;
+
+use super::{decoder::Reader, encoder::Writer, Header, Opcode};
+
+ffi_enum! {
+    /// DSO TLV types.
+    pub enum DsoType: u16 {
+        /// Establishes a session and negotiates its timeouts (see [`Keepalive`]).
+        KEEPALIVE = 1,
+        /// Instructs the client to close the session and wait before reconnecting (see
+        /// [`RetryDelay`]).
+        RETRY_DELAY = 2,
+        /// Padding for use with encrypted transports. Its content is ignored.
+        ENCRYPTION_PADDING = 3,
+        SUBSCRIBE = 0x40,
+        PUSH = 0x41,
+        UNSUBSCRIBE = 0x42,
+        RECONFIRM = 0x43,
+    }
+}
+
+impl fmt::Display for DsoType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A raw DSO TLV.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tlv<'a> {
+    ty: DsoType,
+    data: &'a [u8],
+}
+
+impl<'a> Tlv<'a> {
+    /// Creates a TLV of type `ty` containing `data`.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `data` is longer than 65535 bytes.
+    pub fn new(ty: DsoType, data: &'a [u8]) -> Self {
+        assert!(data.len() <= usize::from(u16::MAX), "TLV data too long");
+        Self { ty, data }
+    }
+
+    /// Returns the type of this TLV.
+    #[inline]
+    pub fn ty(&self) -> DsoType {
+        self.ty
+    }
+
+    /// Returns the TLV's data.
+    #[inline]
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+/// A Keepalive TLV, establishing a DSO session and negotiating its timeouts.
+///
+/// Both timeouts are transmitted in milliseconds. A value of `None` means "infinite".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    inactivity_timeout: u32,
+    keepalive_interval: u32,
+}
+
+impl Keepalive {
+    const INFINITE: u32 = u32::MAX;
+
+    /// Creates a Keepalive TLV with the given timeouts.
+    ///
+    /// Timeouts are rounded down to whole milliseconds, and saturate at the largest finite value.
+    pub fn new(inactivity_timeout: Option<Duration>, keepalive_interval: Option<Duration>) -> Self {
+        Self {
+            inactivity_timeout: to_millis(inactivity_timeout),
+            keepalive_interval: to_millis(keepalive_interval),
+        }
+    }
+
+    /// Decodes a Keepalive TLV.
+    ///
+    /// Returns [`Error::InvalidValue`] if `tlv` is not a Keepalive TLV.
+    pub fn decode(tlv: &Tlv<'_>) -> Result<Self, Error> {
+        if tlv.ty != DsoType::KEEPALIVE {
+            return Err(Error::InvalidValue);
+        }
+        let r = Reader::new(tlv.data);
+        let this = Self {
+            inactivity_timeout: r.read_u32()?,
+            keepalive_interval: r.read_u32()?,
+        };
+        if !r.buf().is_empty() {
+            return Err(Error::InvalidValue);
+        }
+        Ok(this)
+    }
+
+    /// Returns the time after which an idle session should be closed by the client.
+    pub fn inactivity_timeout(&self) -> Option<Duration> {
+        from_millis(self.inactivity_timeout)
+    }
+
+    /// Returns the interval at which the client has to send traffic to keep the session alive.
+    pub fn keepalive_interval(&self) -> Option<Duration> {
+        from_millis(self.keepalive_interval)
+    }
+}
+
+/// A Retry Delay TLV, instructing the client to close the session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryDelay {
+    delay: u32,
+}
+
+impl RetryDelay {
+    /// Creates a Retry Delay TLV.
+    ///
+    /// The delay is rounded down to whole milliseconds.
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay: delay.as_millis().try_into().unwrap_or(u32::MAX),
+        }
+    }
+
+    /// Decodes a Retry Delay TLV.
+    ///
+    /// Returns [`Error::InvalidValue`] if `tlv` is not a Retry Delay TLV.
+    pub fn decode(tlv: &Tlv<'_>) -> Result<Self, Error> {
+        if tlv.ty != DsoType::RETRY_DELAY {
+            return Err(Error::InvalidValue);
+        }
+        let r = Reader::new(tlv.data);
+        let delay = r.read_u32()?;
+        if !r.buf().is_empty() {
+            return Err(Error::InvalidValue);
+        }
+        Ok(Self { delay })
+    }
+
+    /// Returns the time the client should wait before reconnecting to the server.
+    pub fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay.into())
+    }
+}
+
+fn to_millis(d: Option<Duration>) -> u32 {
+    match d {
+        Some(d) => d.as_millis().try_into().unwrap_or(Keepalive::INFINITE - 1),
+        None => Keepalive::INFINITE,
+    }
+}
+
+fn from_millis(ms: u32) -> Option<Duration> {
+    match ms {
+        Keepalive::INFINITE => None,
+        ms => Some(Duration::from_millis(ms.into())),
+    }
+}
+
+/// Encodes a DSO message.
+pub struct DsoEncoder<'a> {
+    w: Writer<'a>,
+}
+
+impl<'a> DsoEncoder<'a> {
+    /// Creates a DSO message encoder that will write into `buf`.
+    ///
+    /// The message's opcode is set to [`Opcode::DSO`] and all section counts are set to zero;
+    /// other fields are taken from `header`.
+    pub fn new(buf: &'a mut [u8], mut header: Header) -> Self {
+        header.set_opcode(Opcode::DSO);
+        header.set_qdcount(0);
+        header.set_ancount(0);
+        header.set_nscount(0);
+        header.set_arcount(0);
+        let mut w = Writer::new(buf);
+        w.write_obj(header);
+        Self { w }
+    }
+
+    /// Appends a raw TLV to the message.
+    pub fn tlv(&mut self, tlv: Tlv<'_>) {
+        self.w.write_u16(tlv.ty.0);
+        self.w.write_u16(tlv.data.len() as u16);
+        self.w.write_slice(tlv.data);
+    }
+
+    /// Appends a [`Keepalive`] TLV to the message.
+    pub fn keepalive(&mut self, keepalive: &Keepalive) {
+        self.w.write_u16(DsoType::KEEPALIVE.0);
+        self.w.write_u16(8);
+        self.w.write_u32(keepalive.inactivity_timeout);
+        self.w.write_u32(keepalive.keepalive_interval);
+    }
+
+    /// Appends a [`RetryDelay`] TLV to the message.
+    pub fn retry_delay(&mut self, retry_delay: &RetryDelay) {
+        self.w.write_u16(DsoType::RETRY_DELAY.0);
+        self.w.write_u16(4);
+        self.w.write_u32(retry_delay.delay);
+    }
+
+    /// Appends an Encryption Padding TLV with `len` bytes of padding to the message.
+    pub fn padding(&mut self, len: u16) {
+        self.w.write_u16(DsoType::ENCRYPTION_PADDING.0);
+        self.w.write_u16(len);
+        for _ in 0..len {
+            self.w.write_u8(0);
+        }
+    }
+
+    /// Finishes encoding and returns the length of the message in bytes.
+    ///
+    /// Returns [`Error::Truncated`] if the buffer was too small to fit the message.
+    pub fn finish(self) -> Result<usize, Error> {
+        if self.w.is_truncated() {
+            Err(Error::Truncated)
+        } else {
+            Ok(self.w.pos)
+        }
+    }
+}
+
+/// Decodes a DSO message.
+///
+/// This is an [`Iterator`] over the message's TLVs. The first TLV yielded is the Primary TLV.
+/// Responses are permitted to omit the Primary TLV, so a response may start with an Additional TLV
+/// or contain no TLVs at all.
+pub struct DsoDecoder<'a> {
+    header: Header,
+    r: Reader<'a>,
+    has_errored: bool,
+}
+
+impl<'a> DsoDecoder<'a> {
+    /// Creates a decoder for the DSO message in `buf`.
+    ///
+    /// Returns [`Error::InvalidValue`] if the message does not use [`Opcode::DSO`], or if any of its
+    /// section counts are non-zero.
+    pub fn new(buf: &'a [u8]) -> Result<Self, Error> {
+        let r = Reader::new(buf);
+        let header = r.read_obj::<Header>()?;
+        if header.opcode() != Opcode::DSO
+            || header.question_count() != 0
+            || header.answer_count() != 0
+            || header.authoritative_count() != 0
+            || header.additional_count() != 0
+        {
+            return Err(Error::InvalidValue);
+        }
+        Ok(Self {
+            header,
+            r,
+            has_errored: false,
+        })
+    }
+
+    /// Returns the message header.
+    #[inline]
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    fn read_tlv(&mut self) -> Result<Tlv<'a>, Error> {
+        let ty = DsoType(self.r.read_u16()?);
+        let len = self.r.read_u16()?;
+        let data = self.r.read_slice(len.into())?;
+        Ok(Tlv { ty, data })
+    }
+}
+
+impl<'a> Iterator for DsoDecoder<'a> {
+    type Item = Result<Tlv<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.has_errored || self.r.buf().is_empty() {
+            return None;
+        }
+        let res = self.read_tlv();
+        self.has_errored = res.is_err();
+        Some(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut buf = [0; 64];
+        let mut header = Header::default();
+        header.set_id(77);
+        let keepalive = Keepalive::new(Some(Duration::from_secs(15)), None);
+        let mut enc = DsoEncoder::new(&mut buf, header);
+        enc.keepalive(&keepalive);
+        enc.padding(3);
+        let len = enc.finish().unwrap();
+        assert_eq!(len, 12 + 12 + 7);
+
+        let mut dec = DsoDecoder::new(&buf[..len]).unwrap();
+        assert_eq!(dec.header().id(), 77);
+        assert_eq!(dec.header().opcode(), Opcode::DSO);
+        let primary = dec.next().unwrap().unwrap();
+        assert_eq!(Keepalive::decode(&primary).unwrap(), keepalive);
+        assert_eq!(
+            keepalive.inactivity_timeout(),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(keepalive.keepalive_interval(), None);
+        let padding = dec.next().unwrap().unwrap();
+        assert_eq!(padding.ty(), DsoType::ENCRYPTION_PADDING);
+        assert_eq!(padding.data(), [0, 0, 0]);
+        assert!(dec.next().is_none());
+    }
+
+    #[test]
+    fn rejects_non_dso() {
+        let buf = [0; 12];
+        assert_eq!(DsoDecoder::new(&buf).err(), Some(Error::InvalidValue));
+
+        let mut buf = [0; 12 + 3];
+        DsoEncoder::new(&mut buf, Header::default());
+        buf[12..].copy_from_slice(&[0, 2, 0]);
+        let mut dec = DsoDecoder::new(&buf).unwrap();
+        assert_eq!(dec.next(), Some(Err(Error::Eof)));
+        assert_eq!(dec.next(), None);
+    }
+
+    #[test]
+    fn encoder_truncation() {
+        let mut buf = [0; 16];
+        let mut enc = DsoEncoder::new(&mut buf, Header::default());
+        enc.retry_delay(&RetryDelay::new(Duration::from_secs(1)));
+        assert_eq!(enc.finish(), Err(Error::Truncated));
+    }
+}
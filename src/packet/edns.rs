@@ -0,0 +1,1064 @@
+//! EDNS(0) ([RFC 6891]) options.
+//!
+//! EDNS(0) extends DNS messages with an `OPT` pseudo-record in the *Additional Records* section. Its
+//! RDATA is a sequence of options, each consisting of an option code, a length, and option data.
+//!
+//! `OPT` records are added to messages with [`MessageEncoder::edns`], and their options can be
+//! read from a decoded record via [`ResourceRecord::edns_options`]. The fields that `OPT` records
+//! store in place of their CLASS and TTL are returned by [`ResourceRecord::edns_header`].
+//!
+//! [RFC 6891]: https://datatracker.ietf.org/doc/html/rfc6891
+//! [`MessageEncoder::edns`]: super::encoder::MessageEncoder::edns
+//! [`ResourceRecord::edns_options`]: super::decoder::ResourceRecord::edns_options
+//! [`ResourceRecord::edns_header`]: super::decoder::ResourceRecord::edns_header
+
+use std::{fmt, time::Duration};
+
+use crate::Error;
+
+use super::{decoder::Reader, encoder::Writer, RCode};
+
+ffi_enum! {
+    /// EDNS(0) option codes.
+    pub enum OptionCode: u16 {
+        /// Long-Lived Queries.
+        LLQ = 1,
+        /// Requests (and grants) a lease for a dynamic update (see [`UpdateLease`]).
+        UL = 2,
+        /// Name Server Identifier.
+        NSID = 3,
+        /// Identifies the owner of a sleep proxy registration (see [`Owner`]).
+        OWNER = 4,
+        DAU = 5,
+        DHU = 6,
+        N3U = 7,
+        CLIENT_SUBNET = 8,
+        EXPIRE = 9,
+        COOKIE = 10,
+        /// Negotiates TCP connection idle timeouts (see [`TcpKeepalive`]).
+        TCP_KEEPALIVE = 11,
+        PADDING = 12,
+        CHAIN = 13,
+        KEY_TAG = 14,
+        /// Extended DNS Error (see [`ExtendedError`]).
+        EXTENDED_ERROR = 15,
+        CLIENT_TAG = 16,
+        SERVER_TAG = 17,
+        REPORT_CHANNEL = 18,
+        ZONEVERSION = 19,
+    }
+}
+
+impl fmt::Display for OptionCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+ffi_enum! {
+    /// Extended DNS Error codes, carried in [`ExtendedError`] options.
+    ///
+    /// See [RFC 8914, section 4] for the meaning of each code.
+    ///
+    /// [RFC 8914, section 4]: https://datatracker.ietf.org/doc/html/rfc8914#section-4
+    pub enum ExtendedErrorCode: u16 {
+        OTHER = 0,
+        UNSUPPORTED_DNSKEY_ALGORITHM = 1,
+        UNSUPPORTED_DS_DIGEST_TYPE = 2,
+        STALE_ANSWER = 3,
+        FORGED_ANSWER = 4,
+        DNSSEC_INDETERMINATE = 5,
+        DNSSEC_BOGUS = 6,
+        SIGNATURE_EXPIRED = 7,
+        SIGNATURE_NOT_YET_VALID = 8,
+        DNSKEY_MISSING = 9,
+        RRSIGS_MISSING = 10,
+        NO_ZONE_KEY_BIT_SET = 11,
+        NSEC_MISSING = 12,
+        CACHED_ERROR = 13,
+        NOT_READY = 14,
+        BLOCKED = 15,
+        CENSORED = 16,
+        FILTERED = 17,
+        PROHIBITED = 18,
+        STALE_NXDOMAIN_ANSWER = 19,
+        NOT_AUTHORITATIVE = 20,
+        NOT_SUPPORTED = 21,
+        NO_REACHABLE_AUTHORITY = 22,
+        NETWORK_ERROR = 23,
+        INVALID_DATA = 24,
+    }
+}
+
+impl ExtendedErrorCode {
+    /// Returns the human-readable name of this code, as listed in the IANA registry.
+    pub fn name(&self) -> Option<&'static str> {
+        Some(match *self {
+            Self::OTHER => "Other Error",
+            Self::UNSUPPORTED_DNSKEY_ALGORITHM => "Unsupported DNSKEY Algorithm",
+            Self::UNSUPPORTED_DS_DIGEST_TYPE => "Unsupported DS Digest Type",
+            Self::STALE_ANSWER => "Stale Answer",
+            Self::FORGED_ANSWER => "Forged Answer",
+            Self::DNSSEC_INDETERMINATE => "DNSSEC Indeterminate",
+            Self::DNSSEC_BOGUS => "DNSSEC Bogus",
+            Self::SIGNATURE_EXPIRED => "Signature Expired",
+            Self::SIGNATURE_NOT_YET_VALID => "Signature Not Yet Valid",
+            Self::DNSKEY_MISSING => "DNSKEY Missing",
+            Self::RRSIGS_MISSING => "RRSIGs Missing",
+            Self::NO_ZONE_KEY_BIT_SET => "No Zone Key Bit Set",
+            Self::NSEC_MISSING => "NSEC Missing",
+            Self::CACHED_ERROR => "Cached Error",
+            Self::NOT_READY => "Not Ready",
+            Self::BLOCKED => "Blocked",
+            Self::CENSORED => "Censored",
+            Self::FILTERED => "Filtered",
+            Self::PROHIBITED => "Prohibited",
+            Self::STALE_NXDOMAIN_ANSWER => "Stale NXDOMAIN Answer",
+            Self::NOT_AUTHORITATIVE => "Not Authoritative",
+            Self::NOT_SUPPORTED => "Not Supported",
+            Self::NO_REACHABLE_AUTHORITY => "No Reachable Authority",
+            Self::NETWORK_ERROR => "Network Error",
+            Self::INVALID_DATA => "Invalid Data",
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for ExtendedErrorCode {
+    /// Writes the human-readable name of the code, or its numeric value if it is unknown.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "Extended Error {}", self.0),
+        }
+    }
+}
+
+/// A raw EDNS(0) option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EdnsOption<'a> {
+    code: OptionCode,
+    data: &'a [u8],
+}
+
+impl<'a> EdnsOption<'a> {
+    /// Creates an option with code `code` containing `data`.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `data` is longer than 65535 bytes.
+    pub fn new(code: OptionCode, data: &'a [u8]) -> Self {
+        assert!(data.len() <= usize::from(u16::MAX), "option data too long");
+        Self { code, data }
+    }
+
+    /// Returns the option code.
+    #[inline]
+    pub fn code(&self) -> OptionCode {
+        self.code
+    }
+
+    /// Returns the option's data.
+    #[inline]
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+impl fmt::Display for EdnsOption<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.code {
+            OptionCode::OWNER => match Owner::decode(self) {
+                Ok(owner) => owner.fmt(f),
+                Err(e) => write!(f, "{}({})", self.code, e),
+            },
+            OptionCode::UL => match UpdateLease::decode(self) {
+                Ok(lease) => lease.fmt(f),
+                Err(e) => write!(f, "{}({})", self.code, e),
+            },
+            OptionCode::TCP_KEEPALIVE => match TcpKeepalive::decode(self) {
+                Ok(keepalive) => keepalive.fmt(f),
+                Err(e) => write!(f, "{}({})", self.code, e),
+            },
+            OptionCode::EXTENDED_ERROR => match ExtendedError::decode(self) {
+                Ok(error) => write!(f, "{}({})", self.code, error),
+                Err(e) => write!(f, "{}({})", self.code, e),
+            },
+            _ => write!(f, "{}({:02x?})", self.code, self.data),
+        }
+    }
+}
+
+/// An EDNS(0) `OWNER` option, used by sleep proxy clients to identify themselves.
+///
+/// When a host hands its records to a sleep proxy before going to sleep, it includes this option
+/// so that the proxy knows which machine the records belong to, and how to wake it up (via a
+/// Wake-on-LAN "magic packet" to the wakeup MAC address, optionally with a password).
+///
+/// See [draft-cheshire-edns0-owner-option].
+///
+/// [draft-cheshire-edns0-owner-option]: https://datatracker.ietf.org/doc/html/draft-cheshire-edns0-owner-option
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Owner {
+    version: u8,
+    seq: u8,
+    primary_mac: [u8; 6],
+    wakeup_mac: Option<[u8; 6]>,
+    password: Option<([u8; 6], u8)>,
+}
+
+impl Owner {
+    /// Creates an `OWNER` option (version 0) for the machine with the MAC address `primary_mac`.
+    ///
+    /// `seq` is incremented by the client every time it goes to sleep, so that the sleep proxy can
+    /// discard stale registrations.
+    pub fn new(seq: u8, primary_mac: [u8; 6]) -> Self {
+        Self {
+            version: 0,
+            seq,
+            primary_mac,
+            wakeup_mac: None,
+            password: None,
+        }
+    }
+
+    /// Sets the MAC address that the wakeup packet should be sent to, if it differs from the
+    /// primary MAC address.
+    pub fn set_wakeup_mac(&mut self, wakeup_mac: [u8; 6]) {
+        self.wakeup_mac = Some(wakeup_mac);
+    }
+
+    /// Sets the password to include in the wakeup packet.
+    ///
+    /// The option format requires a wakeup MAC address to be present when a password is included,
+    /// so if none was set, the primary MAC address is used.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `password` is not 4 or 6 bytes long.
+    pub fn set_password(&mut self, password: &[u8]) {
+        assert!(
+            matches!(password.len(), 4 | 6),
+            "wakeup password must be 4 or 6 bytes long",
+        );
+        let mut buf = [0; 6];
+        buf[..password.len()].copy_from_slice(password);
+        self.wakeup_mac.get_or_insert(self.primary_mac);
+        self.password = Some((buf, password.len() as u8));
+    }
+
+    /// Decodes an `OWNER` option.
+    ///
+    /// Returns [`Error::InvalidValue`] if `option` is not an `OWNER` option, or if it has an
+    /// invalid length.
+    pub fn decode(option: &EdnsOption<'_>) -> Result<Self, Error> {
+        if option.code != OptionCode::OWNER {
+            return Err(Error::InvalidValue);
+        }
+        let r = Reader::new(option.data);
+        let mut this = Self {
+            version: r.read_u8()?,
+            seq: r.read_u8()?,
+            primary_mac: *r.read_array()?,
+            wakeup_mac: None,
+            password: None,
+        };
+        match r.buf().len() {
+            0 => {}
+            6 | 10 | 12 => {
+                this.wakeup_mac = Some(*r.read_array()?);
+                let password = r.buf();
+                if !password.is_empty() {
+                    this.set_password(password);
+                }
+            }
+            _ => return Err(Error::InvalidValue),
+        }
+        Ok(this)
+    }
+
+    /// Returns the version of the option format.
+    ///
+    /// Only version 0 is currently defined.
+    #[inline]
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Returns the sequence number of the registration.
+    #[inline]
+    pub fn seq(&self) -> u8 {
+        self.seq
+    }
+
+    /// Returns the MAC address of the interface the sleeping machine is registered on.
+    #[inline]
+    pub fn primary_mac(&self) -> [u8; 6] {
+        self.primary_mac
+    }
+
+    /// Returns the MAC address to send the wakeup packet to.
+    ///
+    /// If no separate wakeup MAC address was specified, this is the primary MAC address.
+    #[inline]
+    pub fn wakeup_mac(&self) -> [u8; 6] {
+        self.wakeup_mac.unwrap_or(self.primary_mac)
+    }
+
+    /// Returns the password to include in the wakeup packet, if any.
+    pub fn password(&self) -> Option<&[u8]> {
+        self.password
+            .as_ref()
+            .map(|(buf, len)| &buf[..usize::from(*len)])
+    }
+
+    /// Returns the length of the encoded option data.
+    fn data_len(&self) -> u16 {
+        match (self.wakeup_mac, &self.password) {
+            (Some(_), Some((_, len))) => 14 + u16::from(*len),
+            (Some(_), None) => 14,
+            (None, _) => 8,
+        }
+    }
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "OWNER(v{}, seq={}, primary={}",
+            self.version,
+            self.seq,
+            Mac(self.primary_mac)
+        )?;
+        if let Some(mac) = self.wakeup_mac {
+            write!(f, ", wakeup={}", Mac(mac))?;
+        }
+        if self.password.is_some() {
+            f.write_str(", password")?;
+        }
+        f.write_str(")")
+    }
+}
+
+/// An EDNS(0) `edns-tcp-keepalive` option, used to negotiate how long idle TCP connections are
+/// kept open.
+///
+/// Clients include this option without a timeout in queries sent over TCP, to signal that they'd
+/// like to reuse the connection for further queries. Servers answer with the idle timeout they're
+/// going to apply to the connection. The option must not be used over UDP.
+///
+/// The timeout is transmitted in units of 100 milliseconds. See [RFC 7828].
+///
+/// [RFC 7828]: https://datatracker.ietf.org/doc/html/rfc7828
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpKeepalive {
+    /// Timeout in units of 100 ms.
+    timeout: Option<u16>,
+}
+
+impl TcpKeepalive {
+    /// Creates an `edns-tcp-keepalive` option carrying `timeout`.
+    ///
+    /// Queries must not specify a timeout. Timeouts are rounded down to multiples of 100 ms, and
+    /// saturate at about 1.8 hours.
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            timeout: timeout.map(|t| (t.as_millis() / 100).try_into().unwrap_or(u16::MAX)),
+        }
+    }
+
+    /// Decodes an `edns-tcp-keepalive` option.
+    ///
+    /// Returns [`Error::InvalidValue`] if `option` is not an `edns-tcp-keepalive` option, or if
+    /// it has an invalid length.
+    pub fn decode(option: &EdnsOption<'_>) -> Result<Self, Error> {
+        if option.code != OptionCode::TCP_KEEPALIVE {
+            return Err(Error::InvalidValue);
+        }
+        let timeout = match *option.data {
+            [] => None,
+            [a, b] => Some(u16::from_be_bytes([a, b])),
+            _ => return Err(Error::InvalidValue),
+        };
+        Ok(Self { timeout })
+    }
+
+    /// Returns the idle timeout, if one is specified.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+            .map(|t| Duration::from_millis(u64::from(t) * 100))
+    }
+}
+
+impl fmt::Display for TcpKeepalive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.timeout() {
+            Some(timeout) => write!(f, "TCP_KEEPALIVE({:?})", timeout),
+            None => f.write_str("TCP_KEEPALIVE"),
+        }
+    }
+}
+
+/// An EDNS(0) `UPDATE-LEASE` option, attached to DNS UPDATE messages that register records.
+///
+/// Clients use it to request how long the server should keep the registered records around, and
+/// servers echo the lease they actually granted in the response. Clients have to refresh the
+/// registration before the lease runs out, or the records are removed. The optional `KEY-LEASE`
+/// applies to the `KEY` record used to authenticate the update, and defaults to the lease.
+///
+/// Leases are transmitted in seconds. See [draft-ietf-dnssd-update-lease].
+///
+/// [draft-ietf-dnssd-update-lease]: https://datatracker.ietf.org/doc/html/draft-ietf-dnssd-update-lease
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpdateLease {
+    lease: u32,
+    key_lease: Option<u32>,
+}
+
+impl UpdateLease {
+    /// Creates an `UPDATE-LEASE` option requesting or granting `lease`, and optionally a different
+    /// `key_lease`.
+    ///
+    /// Leases are rounded down to whole seconds, and saturate at about 136 years.
+    pub fn new(lease: Duration, key_lease: Option<Duration>) -> Self {
+        let secs = |d: Duration| d.as_secs().try_into().unwrap_or(u32::MAX);
+        Self {
+            lease: secs(lease),
+            key_lease: key_lease.map(secs),
+        }
+    }
+
+    /// Decodes an `UPDATE-LEASE` option.
+    ///
+    /// Returns [`Error::InvalidValue`] if `option` is not an `UPDATE-LEASE` option, or if it has
+    /// an invalid length.
+    pub fn decode(option: &EdnsOption<'_>) -> Result<Self, Error> {
+        if option.code != OptionCode::UL {
+            return Err(Error::InvalidValue);
+        }
+        let (lease, key_lease) = match *option.data {
+            [a, b, c, d] => (u32::from_be_bytes([a, b, c, d]), None),
+            [a, b, c, d, e, f, g, h] => (
+                u32::from_be_bytes([a, b, c, d]),
+                Some(u32::from_be_bytes([e, f, g, h])),
+            ),
+            _ => return Err(Error::InvalidValue),
+        };
+        Ok(Self { lease, key_lease })
+    }
+
+    /// Returns the lease of the registered records.
+    pub fn lease(&self) -> Duration {
+        Duration::from_secs(self.lease.into())
+    }
+
+    /// Returns the lease of the `KEY` record.
+    ///
+    /// If the option doesn't specify one, this is the same as [`UpdateLease::lease`].
+    pub fn key_lease(&self) -> Duration {
+        Duration::from_secs(self.key_lease.unwrap_or(self.lease).into())
+    }
+}
+
+impl fmt::Display for UpdateLease {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "UPDATE_LEASE({}s", self.lease)?;
+        if let Some(key_lease) = self.key_lease {
+            write!(f, ", key {}s", key_lease)?;
+        }
+        f.write_str(")")
+    }
+}
+
+/// An EDNS(0) Extended DNS Error option, describing why a query failed (or why a response is
+/// degraded).
+///
+/// Resolvers attach these to responses to provide more detail than the `RCODE` allows, for
+/// example to tell clients that a name was blocked by a filtering policy, or that DNSSEC
+/// validation failed. A response may contain several of them.
+///
+/// See [RFC 8914].
+///
+/// [RFC 8914]: https://datatracker.ietf.org/doc/html/rfc8914
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedError {
+    code: ExtendedErrorCode,
+    extra_text: String,
+}
+
+impl ExtendedError {
+    /// Creates an Extended DNS Error option with the given info code and explanatory text.
+    ///
+    /// `extra_text` is meant for human consumption and may be empty.
+    pub fn new(code: ExtendedErrorCode, extra_text: impl Into<String>) -> Self {
+        Self {
+            code,
+            extra_text: extra_text.into(),
+        }
+    }
+
+    /// Decodes an Extended DNS Error option.
+    ///
+    /// Returns [`Error::InvalidValue`] if `option` is not an Extended DNS Error option. Extra text
+    /// that isn't valid UTF-8 is decoded lossily.
+    pub fn decode(option: &EdnsOption<'_>) -> Result<Self, Error> {
+        if option.code != OptionCode::EXTENDED_ERROR {
+            return Err(Error::InvalidValue);
+        }
+        let r = Reader::new(option.data);
+        let code = ExtendedErrorCode(r.read_u16()?);
+        // Some implementations NUL-terminate the text, even though the RFC says not to.
+        let text = r.buf().strip_suffix(&[0]).unwrap_or(r.buf());
+        Ok(Self {
+            code,
+            extra_text: String::from_utf8_lossy(text).into_owned(),
+        })
+    }
+
+    /// Returns the info code identifying the error.
+    #[inline]
+    pub fn code(&self) -> ExtendedErrorCode {
+        self.code
+    }
+
+    /// Returns the explanatory text attached to the error, which may be empty.
+    #[inline]
+    pub fn extra_text(&self) -> &str {
+        &self.extra_text
+    }
+}
+
+impl fmt::Display for ExtendedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.code.fmt(f)?;
+        if !self.extra_text.is_empty() {
+            write!(f, ": {}", self.extra_text.escape_debug())?;
+        }
+        Ok(())
+    }
+}
+
+struct Mac([u8; 6]);
+
+impl fmt::Display for Mac {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+/// The `DO` bit in the TTL field of an `OPT` record.
+pub(crate) const DNSSEC_OK: u32 = 0x8000;
+
+/// The fields an `OPT` record stores in place of its CLASS and TTL.
+///
+/// Returned by [`ResourceRecord::edns_header`].
+///
+/// [`ResourceRecord::edns_header`]: super::decoder::ResourceRecord::edns_header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EdnsHeader {
+    udp_payload_size: u16,
+    ttl: u32,
+}
+
+impl EdnsHeader {
+    /// Creates an [`EdnsHeader`] from the raw CLASS and TTL fields of an `OPT` record.
+    pub(crate) fn new(class: u16, ttl: u32) -> Self {
+        Self {
+            udp_payload_size: class,
+            ttl,
+        }
+    }
+
+    /// Returns the largest UDP payload size the sender can receive, in bytes.
+    #[inline]
+    pub fn udp_payload_size(&self) -> u16 {
+        self.udp_payload_size
+    }
+
+    /// Returns the upper 8 bits of the message's 12-bit RCODE.
+    ///
+    /// The lower 4 bits are stored in the message [`Header`][super::Header]. Use
+    /// [`EdnsHeader::full_rcode`] to combine both.
+    #[inline]
+    pub fn extended_rcode(&self) -> u8 {
+        (self.ttl >> 24) as u8
+    }
+
+    /// Combines the extended RCODE bits with the `rcode` from the message header.
+    pub fn full_rcode(&self, rcode: RCode) -> u16 {
+        u16::from(self.extended_rcode()) << 4 | u16::from(rcode.0 & 0xf)
+    }
+
+    /// Returns the EDNS version the sender implements.
+    ///
+    /// Only version 0 is currently defined.
+    #[inline]
+    pub fn version(&self) -> u8 {
+        (self.ttl >> 16) as u8
+    }
+
+    /// Returns the raw 16-bit flags field, which includes the `DO` flag.
+    #[inline]
+    pub fn flags(&self) -> u16 {
+        self.ttl as u16
+    }
+
+    /// Returns whether the `DO` (*DNSSEC OK*) flag is set.
+    #[inline]
+    pub fn dnssec_ok(&self) -> bool {
+        self.ttl & DNSSEC_OK != 0
+    }
+}
+
+/// Appends options to an `OPT` record, created by [`MessageEncoder::edns`].
+///
+/// The record's RDATA length is filled in when this is dropped.
+///
+/// [`MessageEncoder::edns`]: super::encoder::MessageEncoder::edns
+pub struct OptEncoder<'e, 'a> {
+    w: &'e mut Writer<'a>,
+    rdlength_pos: usize,
+    ttl: u32,
+}
+
+impl<'e, 'a> OptEncoder<'e, 'a> {
+    /// Writes the fixed part of an `OPT` record, with the RDATA left empty.
+    pub(crate) fn new(w: &'e mut Writer<'a>, udp_payload_size: u16) -> Self {
+        w.write_u8(0); // root domain
+        w.write_u16(super::Type::OPT.0);
+        // The CLASS field holds the payload size, TTL holds extended RCODE, version and flags.
+        w.write_u16(udp_payload_size);
+        w.write_u32(0);
+        let rdlength_pos = w.pos;
+        w.write_u16(0);
+        Self {
+            w,
+            rdlength_pos,
+            ttl: 0,
+        }
+    }
+
+    fn set_ttl(&mut self, ttl: u32) {
+        self.ttl = ttl;
+        let end = self.w.pos;
+        self.w.pos = self.rdlength_pos - 4;
+        self.w.write_u32(ttl);
+        self.w.pos = end;
+    }
+
+    /// Sets the `DO` (*DNSSEC OK*) flag.
+    ///
+    /// In queries, this requests DNSSEC records to be included in the response. Responses to such
+    /// queries set it too.
+    pub fn set_dnssec_ok(&mut self, dnssec_ok: bool) {
+        let ttl = self.ttl & !DNSSEC_OK;
+        self.set_ttl(if dnssec_ok { ttl | DNSSEC_OK } else { ttl });
+    }
+
+    /// Sets the upper 8 bits of the message's 12-bit RCODE.
+    ///
+    /// The lower 4 bits have to be set in the message [`Header`][super::Header].
+    pub fn set_extended_rcode(&mut self, extended_rcode: u8) {
+        self.set_ttl(self.ttl & 0x00ff_ffff | u32::from(extended_rcode) << 24);
+    }
+
+    /// Sets the EDNS version. Defaults to 0, the only version currently defined.
+    pub fn set_version(&mut self, version: u8) {
+        self.set_ttl(self.ttl & 0xff00_ffff | u32::from(version) << 16);
+    }
+
+    /// Appends a raw option.
+    pub fn option(&mut self, option: EdnsOption<'_>) {
+        self.w.write_u16(option.code.0);
+        self.w.write_u16(option.data.len() as u16);
+        self.w.write_slice(option.data);
+    }
+
+    /// Appends an [`Owner`] option.
+    pub fn owner(&mut self, owner: &Owner) {
+        self.w.write_u16(OptionCode::OWNER.0);
+        self.w.write_u16(owner.data_len());
+        self.w.write_u8(owner.version);
+        self.w.write_u8(owner.seq);
+        self.w.write_slice(&owner.primary_mac);
+        if let Some(mac) = owner.wakeup_mac {
+            self.w.write_slice(&mac);
+            if let Some(password) = owner.password() {
+                self.w.write_slice(password);
+            }
+        }
+    }
+
+    /// Appends an [`ExtendedError`] option.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if the extra text is too long to fit in an option.
+    pub fn extended_error(&mut self, error: &ExtendedError) {
+        let len = u16::try_from(error.extra_text.len() + 2).expect("extra text too long");
+        self.w.write_u16(OptionCode::EXTENDED_ERROR.0);
+        self.w.write_u16(len);
+        self.w.write_u16(error.code.0);
+        self.w.write_slice(error.extra_text.as_bytes());
+    }
+
+    /// Appends an [`UpdateLease`] option.
+    pub fn update_lease(&mut self, lease: &UpdateLease) {
+        self.w.write_u16(OptionCode::UL.0);
+        match lease.key_lease {
+            Some(key_lease) => {
+                self.w.write_u16(8);
+                self.w.write_u32(lease.lease);
+                self.w.write_u32(key_lease);
+            }
+            None => {
+                self.w.write_u16(4);
+                self.w.write_u32(lease.lease);
+            }
+        }
+    }
+
+    /// Appends a [`TcpKeepalive`] option.
+    pub fn tcp_keepalive(&mut self, keepalive: &TcpKeepalive) {
+        self.w.write_u16(OptionCode::TCP_KEEPALIVE.0);
+        match keepalive.timeout {
+            Some(timeout) => {
+                self.w.write_u16(2);
+                self.w.write_u16(timeout);
+            }
+            None => self.w.write_u16(0),
+        }
+    }
+}
+
+impl Drop for OptEncoder<'_, '_> {
+    fn drop(&mut self) {
+        let end = self.w.pos;
+        let rdlength = end - self.rdlength_pos - 2;
+        self.w.pos = self.rdlength_pos;
+        self.w
+            .write_u16(rdlength.try_into().expect("RDATA length overflows u16"));
+        self.w.pos = end;
+    }
+}
+
+/// An iterator over the options in the RDATA of an `OPT` record.
+///
+/// Returned by [`ResourceRecord::edns_options`].
+///
+/// [`ResourceRecord::edns_options`]: super::decoder::ResourceRecord::edns_options
+#[derive(Debug, Clone)]
+pub struct EdnsOptions<'a> {
+    r: Reader<'a>,
+    has_errored: bool,
+}
+
+impl<'a> EdnsOptions<'a> {
+    /// Creates an iterator over the options encoded in `rdata`.
+    pub fn new(rdata: &'a [u8]) -> Self {
+        Self {
+            r: Reader::new(rdata),
+            has_errored: false,
+        }
+    }
+
+    fn read_option(&mut self) -> Result<EdnsOption<'a>, Error> {
+        let code = OptionCode(self.r.read_u16()?);
+        let len = self.r.read_u16()?;
+        let data = self.r.read_slice(len.into())?;
+        Ok(EdnsOption { code, data })
+    }
+}
+
+impl<'a> Iterator for EdnsOptions<'a> {
+    type Item = Result<EdnsOption<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.has_errored || self.r.buf().is_empty() {
+            return None;
+        }
+        let res = self.read_option();
+        self.has_errored = res.is_err();
+        Some(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::{
+        decoder::MessageDecoder,
+        encoder::MessageEncoder,
+        records::{Record, OPT},
+        Type,
+    };
+
+    use super::*;
+
+    #[test]
+    fn opt_record() {
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf)
+            .answers()
+            .authority()
+            .additional();
+        // Payload sizes above 32767 set the bit that mDNS uses as the cache-flush bit.
+        let mut opt = enc.edns(0x9000);
+        opt.set_dnssec_ok(true);
+        opt.set_extended_rcode(RCode::BAD_VERS.0 >> 4);
+        opt.set_version(1);
+        opt.set_dnssec_ok(false);
+        opt.set_dnssec_ok(true);
+        opt.tcp_keepalive(&TcpKeepalive::new(None));
+        drop(opt);
+        let len = enc.finish().unwrap();
+
+        let dec = MessageDecoder::new(&buf[..len]).unwrap();
+        let mut dec = dec.additional().unwrap();
+        let rr = dec.next().unwrap().unwrap();
+        let header = rr.edns_header().unwrap();
+        assert_eq!(header.udp_payload_size(), 0x9000);
+        assert_eq!(header.extended_rcode(), 1);
+        assert_eq!(header.full_rcode(RCode::NO_ERROR), 16);
+        assert_eq!(header.version(), 1);
+        assert!(header.dnssec_ok());
+        assert_eq!(header.flags(), 0x8000);
+
+        let Some(Ok(Record::OPT(record))) = rr.as_enum() else {
+            panic!("expected OPT record, got {:?}", rr.as_enum());
+        };
+        let mut expected = OPT::new();
+        expected.push_option(EdnsOption::new(OptionCode::TCP_KEEPALIVE, &[]));
+        assert_eq!(record, expected);
+        assert_eq!(record.to_string(), "TCP_KEEPALIVE");
+        assert_eq!(
+            rr.into_owned().unwrap().record(),
+            Some(&Record::OPT(expected))
+        );
+
+        // Malformed options are rejected when decoding the record.
+        let mut enc = MessageEncoder::new(&mut buf)
+            .answers()
+            .authority()
+            .additional();
+        enc.edns(1232)
+            .option(EdnsOption::new(OptionCode::PADDING, &[0; 2]));
+        let len = enc.finish().unwrap();
+        // Claim that the option's data is longer than the RDATA.
+        buf[len - 3] = 9;
+        let dec = MessageDecoder::new(&buf[..len]).unwrap();
+        let mut dec = dec.additional().unwrap();
+        let rr = dec.next().unwrap().unwrap();
+        assert_eq!(rr.edns_header().unwrap().udp_payload_size(), 1232);
+        assert!(matches!(rr.as_enum(), Some(Err(Error::Eof))));
+    }
+
+    #[test]
+    fn owner_roundtrip() {
+        let mac = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+        let wakeup = [0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb];
+        let mut owners = [
+            Owner::new(1, mac),
+            Owner::new(2, mac),
+            Owner::new(3, mac),
+            Owner::new(4, mac),
+        ];
+        owners[1].set_wakeup_mac(wakeup);
+        owners[2].set_wakeup_mac(wakeup);
+        owners[2].set_password(b"abcd");
+        owners[3].set_password(b"abcdef");
+        assert_eq!(owners[0].wakeup_mac(), mac);
+        assert_eq!(owners[3].wakeup_mac(), mac);
+
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf)
+            .answers()
+            .authority()
+            .additional();
+        let mut opt = enc.edns(1440);
+        for owner in &owners {
+            opt.owner(owner);
+        }
+        opt.option(EdnsOption::new(OptionCode::PADDING, &[0; 3]));
+        drop(opt);
+        let len = enc.finish().unwrap();
+
+        let dec = MessageDecoder::new(&buf[..len]).unwrap();
+        let mut dec = dec.additional().unwrap();
+        let rr = dec.next().unwrap().unwrap();
+        assert_eq!(rr.type_(), Type::OPT);
+        assert_eq!(rr.class().0, 1440);
+        let options = rr
+            .edns_options()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(options.len(), 5);
+        for (option, owner) in options.iter().zip(&owners) {
+            assert_eq!(Owner::decode(option).unwrap(), *owner);
+        }
+        assert_eq!(options[0].data().len(), 8);
+        assert_eq!(options[1].data().len(), 14);
+        assert_eq!(options[2].data().len(), 18);
+        assert_eq!(options[3].data().len(), 20);
+        let owner = Owner::decode(&options[3]).unwrap();
+        assert_eq!(owner.password(), Some(&b"abcdef"[..]));
+        assert_eq!(
+            options[2].to_string(),
+            "OWNER(v0, seq=3, primary=00:11:22:33:44:55, wakeup=66:77:88:99:aa:bb, password)"
+        );
+        assert_eq!(options[4].code(), OptionCode::PADDING);
+        assert_eq!(options[4].to_string(), "PADDING([00, 00, 00])");
+        assert!(Owner::decode(&options[4]).is_err());
+    }
+
+    #[test]
+    fn invalid_owner() {
+        let option = EdnsOption::new(OptionCode::OWNER, &[0; 9]);
+        assert_eq!(Owner::decode(&option), Err(Error::InvalidValue));
+        let option = EdnsOption::new(OptionCode::OWNER, &[0; 4]);
+        assert_eq!(Owner::decode(&option), Err(Error::Eof));
+
+        let mut options = EdnsOptions::new(&[0, 4, 0, 8, 0]);
+        assert_eq!(options.next(), Some(Err(Error::Eof)));
+        assert_eq!(options.next(), None);
+    }
+
+    #[test]
+    fn tcp_keepalive() {
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf)
+            .answers()
+            .authority()
+            .additional();
+        let mut opt = enc.edns(1232);
+        opt.tcp_keepalive(&TcpKeepalive::new(None));
+        opt.tcp_keepalive(&TcpKeepalive::new(Some(Duration::from_millis(12345))));
+        opt.tcp_keepalive(&TcpKeepalive::new(Some(Duration::from_secs(86400))));
+        drop(opt);
+        let len = enc.finish().unwrap();
+
+        let dec = MessageDecoder::new(&buf[..len]).unwrap();
+        let mut dec = dec.additional().unwrap();
+        let rr = dec.next().unwrap().unwrap();
+        let options = rr
+            .edns_options()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(options[0].data(), &[]);
+        assert_eq!(options[1].data(), &[0, 123]);
+        let timeouts = options
+            .iter()
+            .map(|option| TcpKeepalive::decode(option).unwrap().timeout())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            timeouts,
+            [
+                None,
+                Some(Duration::from_millis(12300)),
+                Some(Duration::from_millis(6553500)),
+            ]
+        );
+        assert_eq!(options[0].to_string(), "TCP_KEEPALIVE");
+        assert_eq!(options[1].to_string(), "TCP_KEEPALIVE(12.3s)");
+
+        let option = EdnsOption::new(OptionCode::TCP_KEEPALIVE, &[0]);
+        assert_eq!(TcpKeepalive::decode(&option), Err(Error::InvalidValue));
+        assert_eq!(option.to_string(), "TCP_KEEPALIVE(invalid value)");
+    }
+
+    #[test]
+    fn update_lease() {
+        let leases = [
+            UpdateLease::new(Duration::from_secs(7200), None),
+            UpdateLease::new(
+                Duration::from_millis(3_600_900),
+                Some(Duration::from_secs(86400)),
+            ),
+        ];
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf)
+            .answers()
+            .authority()
+            .additional();
+        let mut opt = enc.edns(1232);
+        for lease in &leases {
+            opt.update_lease(lease);
+        }
+        drop(opt);
+        let len = enc.finish().unwrap();
+
+        let dec = MessageDecoder::new(&buf[..len]).unwrap();
+        let mut dec = dec.additional().unwrap();
+        let rr = dec.next().unwrap().unwrap();
+        let options = rr
+            .edns_options()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(options[0].data(), &[0, 0, 0x1c, 0x20]);
+        assert_eq!(options[1].data().len(), 8);
+        for (option, lease) in options.iter().zip(&leases) {
+            assert_eq!(UpdateLease::decode(option).unwrap(), *lease);
+        }
+        assert_eq!(leases[0].key_lease(), Duration::from_secs(7200));
+        assert_eq!(leases[1].lease(), Duration::from_secs(3600));
+        assert_eq!(leases[1].key_lease(), Duration::from_secs(86400));
+        assert_eq!(options[0].to_string(), "UPDATE_LEASE(7200s)");
+        assert_eq!(options[1].to_string(), "UPDATE_LEASE(3600s, key 86400s)");
+
+        let option = EdnsOption::new(OptionCode::UL, &[0; 6]);
+        assert_eq!(UpdateLease::decode(&option), Err(Error::InvalidValue));
+    }
+
+    #[test]
+    fn extended_error() {
+        let errors = [
+            ExtendedError::new(ExtendedErrorCode::BLOCKED, "blocked by policy"),
+            ExtendedError::new(ExtendedErrorCode::DNSSEC_BOGUS, ""),
+            ExtendedError::new(ExtendedErrorCode(1000), "\"odd\""),
+        ];
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf)
+            .answers()
+            .authority()
+            .additional();
+        let mut opt = enc.edns(1232);
+        for error in &errors {
+            opt.extended_error(error);
+        }
+        drop(opt);
+        let len = enc.finish().unwrap();
+
+        let dec = MessageDecoder::new(&buf[..len]).unwrap();
+        let mut dec = dec.additional().unwrap();
+        let rr = dec.next().unwrap().unwrap();
+        let options = rr
+            .edns_options()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        for (option, error) in options.iter().zip(&errors) {
+            assert_eq!(ExtendedError::decode(option).unwrap(), *error);
+        }
+        assert_eq!(options[1].data(), &[0, 6]);
+        assert_eq!(
+            options[0].to_string(),
+            "EXTENDED_ERROR(Blocked: blocked by policy)"
+        );
+        assert_eq!(options[1].to_string(), "EXTENDED_ERROR(DNSSEC Bogus)");
+        assert_eq!(
+            options[2].to_string(),
+            "EXTENDED_ERROR(Extended Error 1000: \\\"odd\\\")"
+        );
+
+        let option = EdnsOption::new(OptionCode::EXTENDED_ERROR, b"\0\x11filtered\0");
+        let error = ExtendedError::decode(&option).unwrap();
+        assert_eq!(error.code(), ExtendedErrorCode::FILTERED);
+        assert_eq!(error.extra_text(), "filtered");
+        let option = EdnsOption::new(OptionCode::EXTENDED_ERROR, &[0]);
+        assert_eq!(ExtendedError::decode(&option), Err(Error::Eof));
+    }
+}
@@ -1,22 +1,39 @@
 //! DNS packet encoder.
 
 use core::marker::PhantomData;
-use std::mem::{align_of, size_of};
+use std::{
+    any::TypeId,
+    fmt,
+    mem::{align_of, size_of},
+};
 
 use bytemuck::{NoUninit, Zeroable};
 
-use crate::{name::DomainName, Error};
+use crate::{
+    name::{DomainName, Label},
+    Error,
+};
 
 use super::{
+    decoder::{self, NameRef},
+    edns::OptEncoder,
     records::{Encoder, Record},
     section::{self, Section},
-    Class, Header, QClass, QType,
+    Class, Header, QClass, QType, RCode, Type,
 };
 
+/// The largest message offset a compression pointer can refer to.
+const MAX_POINTER: usize = 0x3fff;
+
 pub(crate) struct Writer<'a> {
     buf: &'a mut [u8],
     pub(crate) pos: usize,
     trunc: bool,
+    /// If set, no data is written and only `pos` is advanced, to measure the encoded size of
+    /// something.
+    measure: bool,
+    /// Offsets of all names (and their suffixes) written so far, if name compression is enabled.
+    names: Option<Vec<u16>>,
 }
 
 impl<'a> Writer<'a> {
@@ -25,6 +42,30 @@ impl<'a> Writer<'a> {
             buf,
             pos: 0,
             trunc: false,
+            measure: false,
+            names: None,
+        }
+    }
+
+    /// Creates a [`Writer`] that compresses names written with [`Writer::write_compressed_name`].
+    ///
+    /// `buf` must hold a whole DNS message, starting with the header, since compression pointers
+    /// are offsets from the start of the message.
+    fn compressing(buf: &'a mut [u8]) -> Self {
+        Self {
+            names: Some(Vec::new()),
+            ..Self::new(buf)
+        }
+    }
+
+    /// Creates a [`Writer`] that discards all data, but keeps track of how much was written.
+    pub(crate) fn measuring() -> Writer<'static> {
+        Writer {
+            buf: &mut [],
+            pos: 0,
+            trunc: false,
+            measure: true,
+            names: None,
         }
     }
 
@@ -35,7 +76,16 @@ impl<'a> Writer<'a> {
         with(h);
     }
 
+    pub(crate) fn is_truncated(&self) -> bool {
+        self.trunc
+    }
+
     pub(crate) fn write_slice(&mut self, data: &[u8]) {
+        if self.measure {
+            self.pos += data.len();
+            return;
+        }
+
         let buf = &mut self.buf[self.pos..];
         if data.len() > buf.len() {
             self.trunc = true;
@@ -72,6 +122,59 @@ impl<'a> Writer<'a> {
         self.write_u8(0);
     }
 
+    /// Writes a `<domain-name>` value, replacing the longest suffix that was already written to
+    /// the message with a compression pointer ([RFC 1035, section 4.1.4]).
+    ///
+    /// Only owner names, question names, and the names in the RDATA of the record types defined
+    /// in RFC 1035 may be compressed ([RFC 3597, section 4]). If this writer doesn't compress
+    /// names, this is the same as [`Writer::write_domain_name`].
+    ///
+    /// Labels are compared byte by byte, so names that only differ in case are not merged. This
+    /// preserves the case of mDNS names, which is shown to users.
+    ///
+    /// [RFC 1035, section 4.1.4]: https://datatracker.ietf.org/doc/html/rfc1035#section-4.1.4
+    /// [RFC 3597, section 4]: https://datatracker.ietf.org/doc/html/rfc3597#section-4
+    pub(crate) fn write_compressed_name(&mut self, name: &DomainName) {
+        let Some(known) = self.names.as_ref().map(Vec::len) else {
+            return self.write_domain_name(name);
+        };
+
+        let labels = name.labels();
+        let (literal, pointer) = (0..labels.len())
+            .find_map(|i| Some((i, self.find_name(&labels[i..])?)))
+            .unwrap_or((labels.len(), 0));
+        for label in &labels[..literal] {
+            if self.pos <= MAX_POINTER {
+                let pos = self.pos as u16;
+                self.names.as_mut().unwrap().push(pos);
+            }
+            self.write_u8(label.as_bytes().len() as u8);
+            self.write_slice(label.as_bytes());
+        }
+        if literal == labels.len() {
+            self.write_u8(0);
+        } else {
+            self.write_u16(0xc000 | pointer);
+        }
+
+        if self.trunc {
+            // Don't let later names point at partially written ones.
+            self.names.as_mut().unwrap().truncate(known);
+        }
+    }
+
+    /// Returns the offset of a name consisting of exactly `labels` that was written earlier.
+    fn find_name(&self, labels: &[Label]) -> Option<u16> {
+        let msg = &self.buf[..self.pos];
+        self.names.as_ref()?.iter().copied().find(|&offset| {
+            let mut written = NameRef::new(msg, offset.into()).labels();
+            labels
+                .iter()
+                .all(|label| written.next() == Some(label.as_bytes()))
+                && written.next().is_none()
+        })
+    }
+
     pub(crate) fn write_character_string(&mut self, string: &[u8]) {
         assert!(string.len() <= 255);
         self.write_u8(string.len() as u8);
@@ -114,6 +217,13 @@ impl<'a, S: Section> MessageEncoder<'a, S> {
         self.inner.w.modify_header(|h| *h = header);
     }
 
+    /// Changes individual fields of the message header.
+    ///
+    /// This is useful after [`MessageEncoder::response_to`], which pre-fills the header.
+    pub fn modify_header(&mut self, with: impl FnOnce(&mut Header)) {
+        self.inner.w.modify_header(with);
+    }
+
     /// Finishes encoding the packet, and returns the number of bytes that were written to the
     /// buffer.
     ///
@@ -144,7 +254,7 @@ impl<'a> MessageEncoder<'a, section::Question> {
     /// `buf` must be large enough to fit at least the message header (`size_of::<Header>()`),
     /// otherwise this function will panic.
     pub fn new(buf: &'a mut [u8]) -> Self {
-        let mut w = Writer::new(buf);
+        let mut w = Writer::compressing(buf);
         w.write_obj(Header::zeroed());
         Self {
             inner: EncoderInner {
@@ -160,9 +270,7 @@ impl<'a> MessageEncoder<'a, section::Question> {
 
     /// Adds a question to the *Question* section.
     pub fn question(&mut self, question: Question<'_>) {
-        self.inner.w.write_domain_name(question.name);
-        self.inner.w.write_u16(question.ty.0);
-        self.inner.w.write_u16(question.class.0);
+        write_question(&mut self.inner.w, &question);
         self.inner.qdcount += 1;
     }
 
@@ -177,35 +285,202 @@ impl<'a> MessageEncoder<'a, section::Question> {
 }
 
 impl<'a, S: Section> MessageEncoder<'a, S> {
+    /// Returns the number of bytes written to the buffer so far, including the header.
+    ///
+    /// If the message has been truncated, this is the size of the buffer.
+    #[inline]
+    pub fn bytes_written(&self) -> usize {
+        self.inner.w.pos
+    }
+
+    /// Returns the number of bytes left in the buffer.
+    #[inline]
+    pub fn bytes_remaining(&self) -> usize {
+        self.inner.w.buf.len() - self.inner.w.pos
+    }
+
+    /// Returns the number of bytes `question` would take up if added to this message.
+    ///
+    /// This does not account for name compression, so the question may end up taking less space.
+    pub fn question_len(&self, question: &Question<'_>) -> usize {
+        question.encoded_len()
+    }
+
+    /// Returns the number of bytes `rr` would take up if added to this message.
+    ///
+    /// This can be used to decide whether a record still fits into the message before adding it,
+    /// instead of truncating the message. Name compression is not accounted for, so the record
+    /// may end up taking less space.
+    pub fn record_len(&self, rr: &ResourceRecord<'_>) -> usize {
+        rr.encoded_len()
+    }
+
+    /// Returns whether `rr` can be added to this message without truncating it.
+    pub fn fits(&self, rr: &ResourceRecord<'_>) -> bool {
+        !self.inner.w.trunc && self.record_len(rr) <= self.bytes_remaining()
+    }
+
     fn write_rr(&mut self, rr: ResourceRecord<'_>) {
+        write_rr(&mut self.inner.w, &rr);
+    }
+
+    /// Returns the record count of the current section.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the encoder is in the *Question* section.
+    fn record_count(&mut self) -> &mut u16 {
+        if TypeId::of::<S>() == TypeId::of::<section::Answer>() {
+            &mut self.inner.ancount
+        } else if TypeId::of::<S>() == TypeId::of::<section::Authority>() {
+            &mut self.inner.nscount
+        } else if TypeId::of::<S>() == TypeId::of::<section::Additional>() {
+            &mut self.inner.arcount
+        } else {
+            unreachable!("cannot add records to the question section")
+        }
+    }
+
+    /// Adds `rr` to the current section, for code that is generic over the section.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the encoder is in the *Question* section.
+    pub(crate) fn add_record(&mut self, rr: ResourceRecord<'_>) {
+        self.write_rr(rr);
+        *self.record_count() += 1;
+    }
+
+    /// Adds a record with raw RDATA to the current section.
+    ///
+    /// `class` and `ttl` are written as-is, so this can also be used for `OPT` records and records
+    /// with the mDNS cache-flush bit set. `rdata` must not contain compression pointers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the encoder is in the *Question* section.
+    pub(crate) fn add_raw(
+        &mut self,
+        name: &DomainName,
+        type_: Type,
+        class: u16,
+        ttl: u32,
+        rdata: &[u8],
+    ) {
         let w = &mut self.inner.w;
-        w.write_domain_name(rr.name);
-        w.write_u16(rr.rdata.record_type().0);
-        w.write_u16(rr.class.0);
-        w.write_u32(rr.ttl);
-        // a little inscrutable seek dance :3
-        let lenpos = w.pos;
-        w.write_u16(0); // dummy length
-        let before_rdata = w.pos;
-        let mut enc = Encoder {
-            w: Writer {
-                buf: &mut *w.buf,
-                pos: w.pos,
-                trunc: w.trunc,
-            },
-        };
-        rr.rdata.encode(&mut enc);
-        w.pos = enc.w.pos;
-        w.trunc = enc.w.trunc;
-        let rdata_len = w.pos - before_rdata;
-        let finished_pos = w.pos;
-        w.pos = lenpos;
-        w.write_u16(rdata_len.try_into().expect("RDATA length overflows u16"));
-        w.pos = finished_pos;
+        w.write_compressed_name(name);
+        w.write_u16(type_.0);
+        w.write_u16(class);
+        w.write_u32(ttl);
+        w.write_u16(rdata.len().try_into().expect("RDATA length overflows u16"));
+        w.write_slice(rdata);
+        *self.record_count() += 1;
     }
 }
 
+/// Returns the number of bytes an `OPT` record takes up when `build` adds its options.
+pub(crate) fn edns_len(build: impl FnOnce(&mut OptEncoder<'_, '_>)) -> usize {
+    let mut w = Writer::measuring();
+    build(&mut OptEncoder::new(&mut w, 0));
+    w.pos
+}
+
+fn write_question(w: &mut Writer<'_>, question: &Question<'_>) {
+    w.write_compressed_name(question.name);
+    w.write_u16(question.ty.0);
+    w.write_u16(question.class.0 | if question.prefer_unicast { 0x8000 } else { 0 });
+}
+
+fn write_rr(w: &mut Writer<'_>, rr: &ResourceRecord<'_>) {
+    w.write_compressed_name(rr.name);
+    w.write_u16(rr.rdata.record_type().0);
+    w.write_u16(rr.class.0);
+    w.write_u32(rr.ttl);
+    // a little inscrutable seek dance :3
+    let lenpos = w.pos;
+    w.write_u16(0); // dummy length
+    let before_rdata = w.pos;
+    let mut enc = Encoder {
+        w: Writer {
+            buf: &mut *w.buf,
+            pos: w.pos,
+            trunc: w.trunc,
+            measure: w.measure,
+            names: w.names.take(),
+        },
+    };
+    rr.rdata.encode(&mut enc);
+    w.pos = enc.w.pos;
+    w.trunc = enc.w.trunc;
+    w.names = enc.w.names;
+    let rdata_len = w.pos - before_rdata;
+    let finished_pos = w.pos;
+    w.pos = lenpos;
+    w.write_u16(rdata_len.try_into().expect("RDATA length overflows u16"));
+    w.pos = finished_pos;
+}
+
+/// Encodes an error response to `query` into `buf`, and returns its length.
+///
+/// This is meant for queries that can't be answered at all, for example because they are
+/// malformed (`FORM_ERR`), use an unsupported opcode (`NOT_IMP`), or are not allowed
+/// (`REFUSED`). Like [`MessageEncoder::response_to`], the response copies the ID, opcode and
+/// *recursion desired* flag of the query. The first question is copied too, if it can be decoded.
+/// The *authoritative answer* flag is not set.
+///
+/// Returns an error if not even the header of `query` can be decoded, since the querier would
+/// have no way to match a response to its query.
+///
+/// Note that mDNS responders must never send error responses ([RFC 6762, section 18.11]).
+///
+/// [RFC 6762, section 18.11]: https://datatracker.ietf.org/doc/html/rfc6762#section-18.11
+pub fn error_response(buf: &mut [u8], query: &[u8], rcode: RCode) -> Result<usize, Error> {
+    let mut dec = decoder::MessageDecoder::new(query)?;
+    let header = *dec.header();
+    let question = dec.next().and_then(Result::ok);
+    let mut enc = MessageEncoder::response_to(buf, &header, &question);
+    enc.modify_header(|h| {
+        h.set_authority(false);
+        h.set_rcode(rcode);
+    });
+    enc.finish()
+}
+
 impl<'a> MessageEncoder<'a, section::Answer> {
+    /// Creates an encoder for a response to the query with the header `query`, writing to `buf`.
+    ///
+    /// The response copies the ID, opcode and *recursion desired* flag of the query, and has the
+    /// *response* and *authoritative answer* flags set. All of `questions` are copied to the
+    /// *Question* section (without the mDNS "unicast-response" bit), and the returned encoder is
+    /// in the *Answer* section.
+    ///
+    /// Use [`MessageEncoder::modify_header`] to set the response code, or to clear the
+    /// *authoritative answer* flag when the response doesn't come from an authoritative source.
+    ///
+    /// # Panics
+    ///
+    /// `buf` must be large enough to fit at least the message header (`size_of::<Header>()`),
+    /// otherwise this function will panic.
+    pub fn response_to<'q>(
+        buf: &'a mut [u8],
+        query: &Header,
+        questions: impl IntoIterator<Item = &'q decoder::Question>,
+    ) -> Self {
+        let mut header = Header::default();
+        header.set_id(query.id());
+        header.set_opcode(query.opcode());
+        header.set_recursion_desired(query.is_recursion_desired());
+        header.set_response(true);
+        header.set_authority(true);
+
+        let mut enc = MessageEncoder::new(buf);
+        enc.set_header(header);
+        for q in questions {
+            enc.question(Question::new(q.qname()).ty(q.qtype()).class(q.qclass()));
+        }
+        enc.answers()
+    }
+
     pub fn add_answer(&mut self, rr: ResourceRecord<'_>) {
         self.write_rr(rr);
         self.inner.ancount += 1;
@@ -242,12 +517,178 @@ impl<'a> MessageEncoder<'a, section::Additional> {
         self.write_rr(rr);
         self.inner.arcount += 1;
     }
+
+    /// Adds all of `rrset` to the *Additional Records* section if the message stays within `limit`
+    /// bytes, and returns whether it did.
+    ///
+    /// Unlike [`MessageEncoder::fits`], this accounts for name compression. If the records don't
+    /// fit, none of them are added and the message is left as it was, so that responses can
+    /// include as much additional data as there is room for without being truncated ([RFC 2181,
+    /// section 9]). `limit` can be used to leave room for records added afterwards, like the
+    /// `OPT` record.
+    ///
+    /// [RFC 2181, section 9]: https://datatracker.ietf.org/doc/html/rfc2181#section-9
+    pub fn try_add_additional(&mut self, rrset: &[ResourceRecord<'_>], limit: usize) -> bool {
+        let w = &mut self.inner.w;
+        if w.trunc {
+            return false;
+        }
+        let (pos, names) = (w.pos, w.names.as_ref().map_or(0, Vec::len));
+        for rr in rrset {
+            write_rr(w, rr);
+        }
+        if w.trunc || w.pos > limit {
+            w.pos = pos;
+            w.trunc = false;
+            if let Some(known) = &mut w.names {
+                known.truncate(names);
+            }
+            return false;
+        }
+        self.inner.arcount += rrset.len() as u16;
+        true
+    }
+
+    /// Adds an EDNS(0) `OPT` pseudo-record ([RFC 6891]) to the *Additional Records* section.
+    ///
+    /// The record advertises that the sender can receive UDP messages of up to
+    /// `udp_payload_size` bytes. No options, extended flags or extended RCODE bits are set.
+    ///
+    /// [RFC 6891]: https://datatracker.ietf.org/doc/html/rfc6891
+    pub fn add_edns(&mut self, udp_payload_size: u16) {
+        self.edns(udp_payload_size);
+    }
+
+    /// Adds an EDNS(0) `OPT` pseudo-record to the *Additional Records* section, and returns an
+    /// [`OptEncoder`] that can be used to add options to it.
+    ///
+    /// See [`MessageEncoder::add_edns`] for details.
+    pub fn edns(&mut self, udp_payload_size: u16) -> OptEncoder<'_, 'a> {
+        self.inner.arcount += 1;
+        OptEncoder::new(&mut self.inner.w, udp_payload_size)
+    }
+}
+
+/// Builds queries carrying a list of known answers, as used by mDNS ([RFC 6762, section 7.1]).
+///
+/// A [`MessageEncoder`] requires all questions to be added before moving on to the *Answer*
+/// section. [`QueryBuilder`] instead collects questions and known answers in any order, and writes
+/// them into the right sections when encoding. If the known answers don't fit into a single
+/// message, [`QueryBuilder::encode_packets`] spreads them over several messages, as described in
+/// [RFC 6762, section 7.2].
+///
+/// [RFC 6762, section 7.1]: https://datatracker.ietf.org/doc/html/rfc6762#section-7.1
+/// [RFC 6762, section 7.2]: https://datatracker.ietf.org/doc/html/rfc6762#section-7.2
+#[derive(Clone, Default)]
+pub struct QueryBuilder<'a> {
+    header: Header,
+    questions: Vec<Question<'a>>,
+    known_answers: Vec<ResourceRecord<'a>>,
 }
 
+impl<'a> QueryBuilder<'a> {
+    /// Creates an empty query with a default [`Header`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the message header.
+    ///
+    /// As with [`MessageEncoder::set_header`], the section counts and the truncation flag are
+    /// overwritten when encoding.
+    pub fn set_header(&mut self, header: Header) {
+        self.header = header;
+    }
+
+    /// Adds a question to the query.
+    pub fn question(&mut self, question: Question<'a>) {
+        self.questions.push(question);
+    }
+
+    /// Adds a record the querier already knows about to the *Answer* section.
+    ///
+    /// Responders will not send records that are listed as known answers, unless the TTL given
+    /// here is less than half of the record's real TTL.
+    pub fn known_answer(&mut self, rr: ResourceRecord<'a>) {
+        self.known_answers.push(rr);
+    }
+
+    /// Encodes the query into a single message in `buf`, and returns its length.
+    ///
+    /// If the message doesn't fit into `buf`, this returns [`Error::Truncated`] (like
+    /// [`MessageEncoder::finish`]).
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut enc = MessageEncoder::new(buf);
+        enc.set_header(self.header);
+        for question in &self.questions {
+            enc.question(*question);
+        }
+        let mut enc = enc.answers();
+        for rr in &self.known_answers {
+            enc.add_answer(*rr);
+        }
+        enc.finish()
+    }
+
+    /// Encodes the query into one or more messages of at most `buf.len()` bytes, and passes each
+    /// of them to `on_packet`.
+    ///
+    /// The first message contains all questions, followed by as many known answers as fit. The
+    /// remaining known answers are sent in subsequent messages without any questions. Every
+    /// message but the last has the truncation flag set, which tells responders to wait for the
+    /// rest of the known answers before responding.
+    ///
+    /// Returns [`Error::Truncated`] if the questions, or a single known answer, don't fit into
+    /// `buf`. In that case, `on_packet` may already have been called for earlier messages.
+    pub fn encode_packets(
+        &self,
+        buf: &mut [u8],
+        mut on_packet: impl FnMut(&[u8]),
+    ) -> Result<(), Error> {
+        let mut known_answers = &self.known_answers[..];
+        let mut first = true;
+        loop {
+            let mut enc = MessageEncoder::new(&mut *buf);
+            enc.set_header(self.header);
+            if first {
+                for question in &self.questions {
+                    enc.question(*question);
+                }
+            }
+            let mut enc = enc.answers();
+            let mut added = 0;
+            for rr in known_answers {
+                if !enc.fits(rr) {
+                    break;
+                }
+                enc.add_answer(*rr);
+                added += 1;
+            }
+            if added == 0 && !first {
+                return Err(Error::Truncated);
+            }
+            let len = enc.finish()?;
+
+            known_answers = &known_answers[added..];
+            if !known_answers.is_empty() {
+                let header: &mut Header = bytemuck::from_bytes_mut(&mut buf[..size_of::<Header>()]);
+                header.set_truncated(true);
+            }
+            on_packet(&buf[..len]);
+            if known_answers.is_empty() {
+                return Ok(());
+            }
+            first = false;
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
 pub struct Question<'a> {
     name: &'a DomainName,
     class: QClass,
     ty: QType,
+    prefer_unicast: bool,
 }
 
 impl<'a> Question<'a> {
@@ -259,6 +700,7 @@ impl<'a> Question<'a> {
             name,
             class: QClass::IN,
             ty: QType::ALL,
+            prefer_unicast: false,
         }
     }
 
@@ -276,8 +718,28 @@ impl<'a> Question<'a> {
     pub fn ty(self, ty: QType) -> Self {
         Self { ty, ..self }
     }
+
+    /// Sets the mDNS "unicast-response" bit, asking responders to reply via unicast instead of
+    /// multicast ([RFC 6762, section 5.4]).
+    ///
+    /// [RFC 6762, section 5.4]: https://datatracker.ietf.org/doc/html/rfc6762#section-5.4
+    #[inline]
+    pub fn prefer_unicast(self, prefer_unicast: bool) -> Self {
+        Self {
+            prefer_unicast,
+            ..self
+        }
+    }
+
+    /// Returns the number of bytes this question takes up when encoded on its own.
+    pub fn encoded_len(&self) -> usize {
+        let mut w = Writer::measuring();
+        write_question(&mut w, self);
+        w.pos
+    }
 }
 
+#[derive(Clone, Copy)]
 pub struct ResourceRecord<'a> {
     name: &'a DomainName,
     class: Class,
@@ -304,4 +766,318 @@ impl<'a> ResourceRecord<'a> {
     pub fn ttl(self, ttl: u32) -> Self {
         Self { ttl, ..self }
     }
+
+    /// Returns the owner name, record type, and TTL of this record.
+    ///
+    /// This is mostly useful for logging, and for matching outgoing records against the ones found
+    /// in a received message.
+    #[inline]
+    pub fn owner_type_ttl(&self) -> (&'a DomainName, Type, u32) {
+        (self.name, self.rdata.record_type(), self.ttl)
+    }
+
+    /// Returns the number of bytes this record takes up when encoded on its own.
+    pub fn encoded_len(&self) -> usize {
+        let mut w = Writer::measuring();
+        write_rr(&mut w, self);
+        w.pos
+    }
+}
+
+impl<'a> fmt::Debug for ResourceRecord<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResourceRecord")
+            .field("name", &self.name)
+            .field("type_", &self.rdata.record_type())
+            .field("class", &self.class)
+            .field("ttl", &self.ttl)
+            .field("rdata", &self.rdata)
+            .finish()
+    }
+}
+
+/// Formats the record like [`decoder::ResourceRecord`] does, so that outgoing and incoming records
+/// can be logged the same way.
+///
+/// [`decoder::ResourceRecord`]: super::decoder::ResourceRecord
+impl<'a> fmt::Display for ResourceRecord<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, type_, ttl) = self.owner_type_ttl();
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}",
+            name, ttl, self.class, type_, self.rdata
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        domain,
+        packet::{
+            decoder::MessageDecoder,
+            records::{A, AAAA, CNAME, PTR, SRV},
+            Message, Opcode,
+        },
+    };
+
+    use super::*;
+
+    #[test]
+    fn encoded_len() {
+        let name = domain!("example.com");
+        let target = domain!("host.local");
+        let srv = Record::SRV(SRV::new(0, 0, 80, &target));
+        let q = Question::new(&name).ty(QType::SRV);
+        let rr = ResourceRecord::new(&name, &srv);
+
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf);
+        assert_eq!(enc.bytes_written(), size_of::<Header>());
+        let before = enc.bytes_written();
+        enc.question(q);
+        assert_eq!(enc.bytes_written() - before, q.encoded_len());
+        assert_eq!(q.encoded_len(), 13 + 4);
+
+        let mut enc = enc.answers();
+        let before = enc.bytes_written();
+        assert!(enc.fits(&rr));
+        enc.add_answer(rr);
+        assert_eq!(rr.encoded_len(), 13 + 10 + 6 + 12);
+        assert_eq!(enc.record_len(&rr), rr.encoded_len());
+        // The owner name is compressed to a pointer at the question.
+        assert_eq!(enc.bytes_written() - before, 2 + 10 + 6 + 12);
+
+        // Fill the buffer up until the record no longer fits.
+        while enc.fits(&rr) {
+            enc.add_answer(rr);
+        }
+        assert!(enc.bytes_remaining() < rr.encoded_len());
+        assert!(enc.finish().is_ok());
+    }
+
+    #[test]
+    fn display_matches_decoder() {
+        let name = domain!("example.com");
+        let target = domain!("host.local");
+        let srv = Record::SRV(SRV::new(0, 5, 80, &target));
+        let rr = ResourceRecord::new(&name, &srv).ttl(120);
+        assert_eq!(rr.owner_type_ttl(), (&name, Type::SRV, 120));
+
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf).answers();
+        enc.add_answer(rr);
+        let len = enc.finish().unwrap();
+
+        let mut dec = MessageDecoder::new(&buf[..len]).unwrap().answers().unwrap();
+        let decoded = dec.next().unwrap().unwrap();
+        assert_eq!(rr.to_string(), decoded.to_string());
+        assert_eq!(
+            rr.to_string(),
+            "example.com.\t120\tIN\tSRV\t0\t5\t80\thost.local."
+        );
+    }
+
+    #[test]
+    fn known_answer_packets() {
+        let service = domain!("_http._tcp.local");
+        let instances = (0..20)
+            .map(|i| format!("instance {i}._http._tcp.local").parse().unwrap())
+            .collect::<Vec<DomainName>>();
+        let ptrs = instances
+            .iter()
+            .map(|instance| Record::PTR(PTR::new(instance)))
+            .collect::<Vec<_>>();
+
+        let mut query = QueryBuilder::new();
+        for ptr in &ptrs {
+            query.known_answer(ResourceRecord::new(&service, ptr).ttl(4500));
+        }
+        query.question(Question::new(&service).ty(QType::PTR));
+
+        let mut buf = [0; 1500];
+        let len = query.encode(&mut buf).unwrap();
+        let dec = MessageDecoder::new(&buf[..len]).unwrap();
+        assert_eq!(dec.header().question_count(), 1);
+        assert_eq!(dec.header().answer_count(), 20);
+        assert!(!dec.header().is_truncated());
+
+        let mut buf = [0; 512];
+        assert_eq!(query.encode(&mut buf), Err(Error::Truncated));
+        let mut packets = Vec::new();
+        query
+            .encode_packets(&mut buf, |packet| {
+                let dec = MessageDecoder::new(packet).unwrap();
+                let h = dec.header();
+                packets.push((h.question_count(), h.answer_count(), h.is_truncated()));
+            })
+            .unwrap();
+        assert_eq!(packets, [(1, 17, true), (0, 3, false)]);
+
+        let mut buf = [0; 20];
+        assert_eq!(
+            query.encode_packets(&mut buf, |_| {}),
+            Err(Error::Truncated)
+        );
+    }
+
+    #[test]
+    fn name_compression() {
+        let service = domain!("_http._tcp.local");
+        let instance = domain!("My Printer._http._tcp.local");
+        let host = domain!("printer.local");
+        let upper = domain!("PRINTER.local");
+        let ptr = Record::PTR(PTR::new(&instance));
+        let srv = Record::SRV(SRV::new(0, 0, 80, &host));
+        let cname = Record::CNAME(CNAME::new(&upper));
+
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf);
+        enc.question(Question::new(&service).ty(QType::PTR));
+        let mut enc = enc.answers();
+        enc.add_answer(ResourceRecord::new(&service, &ptr));
+        let mut enc = enc.authority().additional();
+        enc.add_additional(ResourceRecord::new(&instance, &srv));
+        enc.add_additional(ResourceRecord::new(&host, &cname));
+        let len = enc.finish().unwrap();
+
+        let uncompressed = 12
+            + Question::new(&service).encoded_len()
+            + ResourceRecord::new(&service, &ptr).encoded_len()
+            + ResourceRecord::new(&instance, &srv).encoded_len()
+            + ResourceRecord::new(&host, &cname).encoded_len();
+        assert_eq!(len, 122);
+        assert_eq!(uncompressed, 191);
+
+        let packet = &buf[..len];
+        let mut dec = MessageDecoder::new(packet).unwrap();
+        assert_eq!(dec.next().unwrap().unwrap().qname(), &service);
+        let mut dec = dec.answers().unwrap();
+        let rr = dec.next().unwrap().unwrap();
+        assert_eq!(rr.name(), &service);
+        assert_eq!(rr.as_enum().unwrap().unwrap(), ptr);
+        // PTR RDATA: "My Printer" followed by a pointer at the question name.
+        assert_eq!(rr.rdata(), b"\x0aMy Printer\xc0\x0c");
+
+        let mut dec = dec.additional().unwrap();
+        let rr = dec.next().unwrap().unwrap();
+        assert_eq!(rr.name(), &instance);
+        assert_eq!(rr.as_enum().unwrap().unwrap(), srv);
+        // SRV targets are never compressed (RFC 2782).
+        assert_eq!(&rr.rdata()[6..], b"\x07printer\x05local\x00");
+        let rr = dec.next().unwrap().unwrap();
+        assert_eq!(rr.name(), &host);
+        // Names only differing in case are not merged, but their common suffix is.
+        assert_eq!(rr.as_enum().unwrap().unwrap(), cname);
+        assert_eq!(rr.rdata().len(), 1 + 7 + 2);
+
+        // Record data encoded on its own is never compressed.
+        assert_eq!(
+            ptr.encode_to_vec(),
+            b"\x0aMy Printer\x05_http\x04_tcp\x05local\x00"
+        );
+    }
+
+    #[test]
+    fn response_to() {
+        let name = domain!("example.com");
+        let mut query = [0; 512];
+        let mut enc = MessageEncoder::new(&mut query);
+        let mut header = Header::default();
+        header.set_id(1234);
+        header.set_recursion_desired(true);
+        enc.set_header(header);
+        enc.question(Question::new(&name).ty(QType::A).prefer_unicast(true));
+        let len = enc.finish().unwrap();
+
+        let mut dec = MessageDecoder::new(&query[..len]).unwrap();
+        let header = *dec.header();
+        let questions = dec.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::response_to(&mut buf, &header, &questions);
+        enc.modify_header(|h| h.set_rcode(RCode::NX_DOMAIN));
+        let len = enc.finish().unwrap();
+
+        let mut dec = MessageDecoder::new(&buf[..len]).unwrap();
+        let h = dec.header();
+        assert_eq!(h.id(), 1234);
+        assert!(h.is_response());
+        assert!(h.is_authority());
+        assert!(h.is_recursion_desired());
+        assert_eq!(h.rcode(), RCode::NX_DOMAIN);
+        assert_eq!(h.question_count(), 1);
+        let q = dec.next().unwrap().unwrap();
+        assert_eq!(q.qname(), &name);
+        assert_eq!(q.qtype(), QType::A);
+        assert!(!q.prefers_unicast());
+    }
+
+    #[test]
+    fn error_response() {
+        let name = domain!("example.com");
+        let mut query = [0; 512];
+        let mut enc = MessageEncoder::new(&mut query);
+        let mut header = Header::default();
+        header.set_id(4321);
+        header.set_opcode(Opcode::STATUS);
+        enc.set_header(header);
+        enc.question(Question::new(&name).ty(QType::MX));
+        let len = enc.finish().unwrap();
+
+        let mut buf = [0; 512];
+        let resp = super::error_response(&mut buf, &query[..len], RCode::NOT_IMP).unwrap();
+        let mut dec = MessageDecoder::new(&buf[..resp]).unwrap();
+        let h = dec.header();
+        assert_eq!(h.id(), 4321);
+        assert_eq!(h.opcode(), Opcode::STATUS);
+        assert!(h.is_response());
+        assert!(!h.is_authority());
+        assert_eq!(h.rcode(), RCode::NOT_IMP);
+        let q = dec.next().unwrap().unwrap();
+        assert_eq!(q.qname(), &name);
+        assert_eq!(q.qtype(), QType::MX);
+
+        // A garbled question is left out.
+        query[len - 1] = 0xff;
+        query[size_of::<Header>()] = 0xc0;
+        let resp = super::error_response(&mut buf, &query[..len], RCode::FORM_ERR).unwrap();
+        let dec = MessageDecoder::new(&buf[..resp]).unwrap();
+        assert_eq!(dec.header().id(), 4321);
+        assert_eq!(dec.header().rcode(), RCode::FORM_ERR);
+        assert_eq!(dec.header().question_count(), 0);
+
+        assert!(super::error_response(&mut buf, &query[..4], RCode::FORM_ERR).is_err());
+    }
+    #[test]
+    fn additional_budget() {
+        let name = domain!("host.example.com");
+        let a = Record::A(A::new([10, 0, 0, 1].into()));
+        let aaaa = Record::AAAA(AAAA::new([0xfe80, 0, 0, 0, 0, 0, 0, 1].into()));
+        let rr = |record| ResourceRecord::new(&name, record).ttl(120);
+
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf);
+        enc.question(Question::new(&name).ty(QType::A));
+        let mut enc = enc.answers();
+        enc.add_answer(rr(&a));
+        let mut enc = enc.authority().additional();
+        let before = enc.bytes_written();
+        // The compressed record takes 28 bytes, much less than its uncompressed length.
+        assert_eq!(enc.record_len(&rr(&aaaa)), 44);
+        assert!(!enc.try_add_additional(&[rr(&aaaa)], before + 27));
+        assert_eq!(enc.bytes_written(), before);
+        assert!(!enc.try_add_additional(&[rr(&aaaa), rr(&a)], before + 28));
+        assert_eq!(enc.bytes_written(), before);
+        assert!(enc.try_add_additional(&[rr(&aaaa)], before + 28));
+        assert_eq!(enc.bytes_written(), before + 28);
+        // Running out of buffer space is handled the same way.
+        assert!(!enc.try_add_additional(&[rr(&a); 30], usize::MAX));
+        let len = enc.finish().unwrap();
+
+        let msg = Message::decode(&buf[..len]).unwrap();
+        assert!(!msg.header().is_truncated());
+        assert_eq!(msg.additional().len(), 1);
+        assert_eq!(msg.additional()[0].type_(), Type::AAAA);
+    }
 }
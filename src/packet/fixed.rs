@@ -0,0 +1,204 @@
+//! Fixed-capacity buffers for decoding messages without a heap.
+//!
+//! Together with the borrowing decoder API ([`MessageDecoder::next_borrowed`], [`NameRef`] and
+//! [`TxtEntries`]) and [`SmallDomainName`], this allows processing DNS messages on targets without
+//! an allocator. [`FixedVec`] is a `heapless::Vec`-style vector that stores up to `N` elements
+//! inline, and fails instead of growing.
+//!
+//! This module requires the `heapless` Cargo feature.
+//!
+//! # Example
+//!
+//! ```
+//! # use uwuhi::packet::{decoder::TxtEntries, fixed::FixedVec};
+//! // The RDATA of a `TXT` record with two entries.
+//! let rdata = b"\x09txtvers=1\x06path=/";
+//! let entries: FixedVec<&[u8], 8> = TxtEntries::new(rdata).collect_fixed()?;
+//! assert_eq!(entries[..], [&b"txtvers=1"[..], b"path=/"]);
+//! # Ok::<_, uwuhi::Error>(())
+//! ```
+//!
+//! [`MessageDecoder::next_borrowed`]: super::decoder::MessageDecoder::next_borrowed
+//! [`NameRef`]: super::decoder::NameRef
+//! [`TxtEntries`]: super::decoder::TxtEntries
+//! [`SmallDomainName`]: crate::name::SmallDomainName
+
+use std::{fmt, ops::Deref};
+
+use crate::Error;
+
+/// A vector with a fixed capacity of `N` elements, stored inline.
+///
+/// Operations that would exceed the capacity fail with [`Error::Truncated`] and leave the vector
+/// unchanged.
+#[derive(Clone, Copy)]
+pub struct FixedVec<T, const N: usize> {
+    len: usize,
+    buf: [T; N],
+}
+
+impl<T: Copy + Default, const N: usize> FixedVec<T, N> {
+    /// Creates an empty vector.
+    pub fn new() -> Self {
+        Self {
+            len: 0,
+            buf: [T::default(); N],
+        }
+    }
+
+    /// Appends `item` to the end of the vector.
+    ///
+    /// Returns [`Error::Truncated`] if the vector is full.
+    pub fn push(&mut self, item: T) -> Result<(), Error> {
+        let slot = self.buf.get_mut(self.len).ok_or(Error::Truncated)?;
+        *slot = item;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Appends all of `items` to the end of the vector.
+    ///
+    /// Returns [`Error::Truncated`] if they don't all fit, in which case none of them are
+    /// appended.
+    pub fn extend_from_slice(&mut self, items: &[T]) -> Result<(), Error> {
+        let end = self.len + items.len();
+        let dest = self.buf.get_mut(self.len..end).ok_or(Error::Truncated)?;
+        dest.copy_from_slice(items);
+        self.len = end;
+        Ok(())
+    }
+
+    /// Removes the last element and returns it, or returns [`None`] if the vector is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        self.len = self.len.checked_sub(1)?;
+        Some(self.buf[self.len])
+    }
+
+    /// Removes all elements.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+impl<T, const N: usize> FixedVec<T, N> {
+    /// Returns the maximum number of elements the vector can hold.
+    #[inline]
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Returns whether the vector is full.
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Returns the elements as a slice.
+    #[inline]
+    pub fn as_slice(&self) -> &[T] {
+        &self.buf[..self.len]
+    }
+}
+
+impl<T: Copy + Default, const N: usize> Default for FixedVec<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Deref for FixedVec<T, N> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T: PartialEq, const N: usize> PartialEq for FixedVec<T, N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl<T: Eq, const N: usize> Eq for FixedVec<T, N> {}
+
+impl<T: fmt::Debug, const N: usize> fmt::Debug for FixedVec<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_slice().fmt(f)
+    }
+}
+
+impl<T: Copy + Default, const N: usize> TryFrom<&[T]> for FixedVec<T, N> {
+    type Error = Error;
+
+    /// Copies `items` into a [`FixedVec`], failing with [`Error::Truncated`] if they don't fit.
+    fn try_from(items: &[T]) -> Result<Self, Error> {
+        let mut vec = Self::new();
+        vec.extend_from_slice(items)?;
+        Ok(vec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        name::DomainName,
+        packet::{
+            decoder::{MessageDecoder, TxtEntries},
+            encoder::{MessageEncoder, ResourceRecord},
+            records::{Record, TXT},
+        },
+    };
+
+    use super::*;
+
+    #[test]
+    fn capacity() {
+        let mut vec = FixedVec::<u8, 3>::new();
+        assert!(vec.is_empty());
+        vec.extend_from_slice(&[1, 2]).unwrap();
+        assert_eq!(vec.extend_from_slice(&[3, 4]), Err(Error::Truncated));
+        assert_eq!(vec[..], [1, 2]);
+        vec.push(3).unwrap();
+        assert!(vec.is_full());
+        assert_eq!(vec.push(4), Err(Error::Truncated));
+        assert_eq!(vec.pop(), Some(3));
+        assert_eq!(vec, FixedVec::try_from(&[1, 2][..]).unwrap());
+        vec.clear();
+        assert_eq!(vec.pop(), None);
+        assert_eq!(vec.capacity(), 3);
+    }
+
+    #[test]
+    fn decode_without_heap() {
+        let name = DomainName::from_str("printer._ipp._tcp.local").unwrap();
+        let txt = Record::TXT(TXT::new([&b"txtvers=1"[..], b"rp=ipp/print", b"note="]));
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf).answers();
+        enc.add_answer(ResourceRecord::new(&name, &txt).ttl(120));
+        let len = enc.finish().unwrap();
+
+        let mut dec = MessageDecoder::new(&buf[..len]).unwrap().answers().unwrap();
+        let rr = dec.next_borrowed().unwrap().unwrap();
+        let owner = rr.name().to_fixed_bytes::<64>().unwrap();
+        assert_eq!(owner[..], *b"\x07printer\x04_ipp\x04_tcp\x05local\x00");
+        assert_eq!(
+            rr.name().to_fixed_bytes::<8>().unwrap_err(),
+            Error::Truncated
+        );
+
+        let entries = rr.txt_entries().unwrap().collect_fixed::<4>().unwrap();
+        assert_eq!(entries[..], [&b"txtvers=1"[..], b"rp=ipp/print", b"note="]);
+        assert_eq!(
+            rr.txt_entries().unwrap().collect_fixed::<2>().unwrap_err(),
+            Error::Truncated
+        );
+        // Malformed entries are reported.
+        assert_eq!(
+            TxtEntries::new(b"\x05abc")
+                .collect_fixed::<4>()
+                .unwrap_err(),
+            Error::Eof
+        );
+    }
+}
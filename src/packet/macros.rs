@@ -11,7 +11,7 @@ macro_rules! ffi_enum {
         }
     ) => {
         $( #[$attrs] )*
-        #[derive(Clone, Copy, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
+        #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, bytemuck::Pod, bytemuck::Zeroable)]
         #[repr(transparent)]
         $v struct $name(pub(crate) $native);
 
@@ -20,6 +20,43 @@ macro_rules! ffi_enum {
                 $( #[$variant_attrs] )*
                 $v const $variant: Self = Self($value);
             )+
+
+            /// Returns whether `self` is one of the named values of this type.
+            #[allow(unreachable_patterns)]
+            $v fn is_known(&self) -> bool {
+                matches!(*self, $( Self::$variant )|+)
+            }
+
+            /// Returns an iterator over all named values of this type, in declaration order.
+            ///
+            /// Values with several names are yielded once per name.
+            $v fn iter() -> impl Iterator<Item = Self> {
+                [$( Self::$variant ),+].into_iter()
+            }
+        }
+
+        impl TryFrom<$native> for $name {
+            type Error = crate::Error;
+
+            /// Converts a raw value to this type.
+            ///
+            /// Returns [`Error::InvalidValue`](crate::Error::InvalidValue) if the value is
+            /// reserved or unassigned (ie. if it isn't one of the named values).
+            fn try_from(value: $native) -> Result<Self, Self::Error> {
+                let this = Self(value);
+                if this.is_known() {
+                    Ok(this)
+                } else {
+                    Err(crate::Error::InvalidValue)
+                }
+            }
+        }
+
+        impl From<$name> for $native {
+            #[inline]
+            fn from(value: $name) -> $native {
+                value.0
+            }
         }
 
         #[allow(unreachable_patterns)]
@@ -0,0 +1,282 @@
+use std::{fmt, mem::size_of};
+
+use crate::Error;
+
+use super::{
+    decoder::{format_header, MessageDecoder, OwnedResourceRecord, Question},
+    encoder::{self, MessageEncoder, ResourceRecord},
+    section::Section,
+    Class, Header,
+};
+
+/// A complete DNS message that owns all of its data.
+///
+/// [`MessageDecoder`] and [`MessageEncoder`] process messages one entry at a time without
+/// allocating, which is what the resolvers and responders in this library use. [`Message`] instead
+/// decodes or encodes the whole message at once, which is more convenient for tools that inspect
+/// or construct messages.
+#[derive(Debug, Clone, Default)]
+pub struct Message {
+    header: Header,
+    questions: Vec<Question>,
+    answers: Vec<OwnedResourceRecord>,
+    authority: Vec<OwnedResourceRecord>,
+    additional: Vec<OwnedResourceRecord>,
+}
+
+impl Message {
+    /// Creates an empty message with the given header.
+    ///
+    /// The section counts in `header` are ignored, they are computed from the contents of the
+    /// message when encoding it.
+    pub fn new(header: Header) -> Self {
+        Self {
+            header,
+            ..Self::default()
+        }
+    }
+
+    /// Decodes a complete message.
+    pub fn decode(packet: &[u8]) -> Result<Self, Error> {
+        let mut dec = MessageDecoder::new(packet)?;
+        let header = *dec.header();
+        let questions = dec.iter().collect::<Result<_, _>>()?;
+        let mut dec = dec.answers()?;
+        let answers = dec
+            .iter()
+            .map(|rr| rr?.into_owned())
+            .collect::<Result<_, _>>()?;
+        let mut dec = dec.authority()?;
+        let authority = dec
+            .iter()
+            .map(|rr| rr?.into_owned())
+            .collect::<Result<_, _>>()?;
+        let mut dec = dec.additional()?;
+        let additional = dec
+            .iter()
+            .map(|rr| rr?.into_owned())
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            header,
+            questions,
+            answers,
+            authority,
+            additional,
+        })
+    }
+
+    /// Encodes this message into `buf`, and returns the number of bytes written.
+    ///
+    /// If the message doesn't fit into `buf`, this returns [`Error::Truncated`], like
+    /// [`MessageEncoder::finish`].
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut enc = MessageEncoder::new(&mut *buf);
+        enc.set_header(self.header);
+        for q in &self.questions {
+            enc.question(
+                encoder::Question::new(q.qname())
+                    .ty(q.qtype())
+                    .class(q.qclass())
+                    .prefer_unicast(q.prefers_unicast()),
+            );
+        }
+        let mut enc = enc.answers();
+        encode_records(&self.answers, &mut enc);
+        let mut enc = enc.authority();
+        encode_records(&self.authority, &mut enc);
+        let mut enc = enc.additional();
+        encode_records(&self.additional, &mut enc);
+        let len = enc.finish()?;
+
+        // The encoder clears the TC bit unless it truncated the message itself.
+        if self.header.is_truncated() {
+            let h: &mut Header = bytemuck::from_bytes_mut(&mut buf[..size_of::<Header>()]);
+            h.set_truncated(true);
+        }
+        Ok(len)
+    }
+
+    /// Returns the message header.
+    ///
+    /// The section counts are the ones of the decoded message. They are not updated when entries
+    /// are added or removed.
+    #[inline]
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Returns a mutable reference to the message header.
+    #[inline]
+    pub fn header_mut(&mut self) -> &mut Header {
+        &mut self.header
+    }
+
+    /// Returns the entries of the *Question* section.
+    #[inline]
+    pub fn questions(&self) -> &[Question] {
+        &self.questions
+    }
+
+    /// Returns a mutable reference to the entries of the *Question* section.
+    #[inline]
+    pub fn questions_mut(&mut self) -> &mut Vec<Question> {
+        &mut self.questions
+    }
+
+    /// Returns the records in the *Answer* section.
+    #[inline]
+    pub fn answers(&self) -> &[OwnedResourceRecord] {
+        &self.answers
+    }
+
+    /// Returns a mutable reference to the records in the *Answer* section.
+    #[inline]
+    pub fn answers_mut(&mut self) -> &mut Vec<OwnedResourceRecord> {
+        &mut self.answers
+    }
+
+    /// Returns the records in the *Authority* section.
+    #[inline]
+    pub fn authority(&self) -> &[OwnedResourceRecord] {
+        &self.authority
+    }
+
+    /// Returns a mutable reference to the records in the *Authority* section.
+    #[inline]
+    pub fn authority_mut(&mut self) -> &mut Vec<OwnedResourceRecord> {
+        &mut self.authority
+    }
+
+    /// Returns the records in the *Additional Records* section.
+    #[inline]
+    pub fn additional(&self) -> &[OwnedResourceRecord] {
+        &self.additional
+    }
+
+    /// Returns a mutable reference to the records in the *Additional Records* section.
+    #[inline]
+    pub fn additional_mut(&mut self) -> &mut Vec<OwnedResourceRecord> {
+        &mut self.additional
+    }
+}
+
+fn encode_records<S: Section>(records: &[OwnedResourceRecord], enc: &mut MessageEncoder<'_, S>) {
+    for rr in records {
+        // The top bit of the class is the cache-flush bit in mDNS, and part of the payload size
+        // in `OPT` records. Either way, it has to be written back as it was.
+        let class = rr.class().0 | if rr.cache_flush() { 0x8000 } else { 0 };
+        match rr.record() {
+            Some(record) => enc.add_record(
+                ResourceRecord::new(rr.name(), record)
+                    .class(Class(class))
+                    .ttl(rr.ttl()),
+            ),
+            None => {
+                let rdata = rr.raw_rdata().unwrap_or_default();
+                enc.add_raw(rr.name(), rr.type_(), class, rr.ttl(), rdata);
+            }
+        }
+    }
+}
+
+/// Formats the message like [`MessageDecoder`]s are logged, with one line per entry.
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut res = Ok(());
+        format_header(&self.header, |args| res = res.and(writeln!(f, "{}", args)));
+        res?;
+        for q in &self.questions {
+            writeln!(f, "Q: {}", q)?;
+        }
+        for rr in &self.answers {
+            writeln!(f, "ANS: {}", rr)?;
+        }
+        for rr in &self.authority {
+            writeln!(f, "AUTH: {}", rr)?;
+        }
+        for rr in &self.additional {
+            writeln!(f, "ADDL: {}", rr)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    use crate::{
+        domain, hex,
+        name::DomainName,
+        packet::{
+            records::{Record, A, OPT},
+            QType, RCode, Type,
+        },
+    };
+
+    use super::*;
+
+    #[test]
+    fn decode_encode() {
+        let packet = hex::parse("303981800001000100000000076578616d706c6503636f6d0000060001c00c0006000100000e10002c026e73056963616e6e036f726700036e6f6303646e73c02c7886aa5a00001c2000000e100012750000000e10");
+        let msg = Message::decode(&packet).unwrap();
+        assert_eq!(msg.questions().len(), 1);
+        assert_eq!(msg.answers()[0].type_(), Type::SOA);
+        expect![[r#"
+            response (id=12345, op=QUERY, rcode=NO_ERROR, RA, RD)
+            Q: example.com.	IN	SOA
+            ANS: example.com.	3600	IN	SOA	ns.icann.org.	noc.dns.icann.org.	2022091354	7200	3600	1209600	3600
+        "#]]
+        .assert_eq(&msg.to_string());
+
+        let mut buf = [0; 512];
+        let len = msg.encode(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &packet[..]);
+    }
+
+    #[test]
+    fn build() {
+        let name = domain!("host.local");
+        let mut header = Header::default();
+        header.set_id(7);
+        header.set_response(true);
+        header.set_rcode(RCode::NX_DOMAIN);
+        let mut msg = Message::new(header);
+
+        let mut question = Question::new(name.clone(), QType::A);
+        question.set_prefer_unicast(true);
+        msg.questions_mut().push(question);
+        let mut a = OwnedResourceRecord::new(name.clone(), Record::A(A::new([10, 0, 0, 1].into())));
+        a.set_cache_flush(true);
+        a.set_ttl(120);
+        msg.answers_mut().push(a);
+        let mut raw = OwnedResourceRecord::new_raw(name.clone(), Type(0xff00), *b"raw");
+        raw.set_ttl(5);
+        msg.authority_mut().push(raw);
+        // A payload size above 32767 is stored as class + cache-flush bit.
+        let mut opt = OwnedResourceRecord::new(DomainName::ROOT, Record::OPT(OPT::new()));
+        opt.set_class(Class(0x1000));
+        opt.set_cache_flush(true);
+        msg.additional_mut().push(opt);
+
+        let mut buf = [0; 512];
+        let len = msg.encode(&mut buf).unwrap();
+        let decoded = Message::decode(&buf[..len]).unwrap();
+        assert_eq!(decoded.header().id(), 7);
+        assert_eq!(decoded.header().rcode(), RCode::NX_DOMAIN);
+        assert_eq!(decoded.header().answer_count(), 1);
+        assert_eq!(decoded.questions(), msg.questions());
+        assert_eq!(decoded.answers(), msg.answers());
+        assert_eq!(decoded.authority(), msg.authority());
+        assert_eq!(decoded.additional(), msg.additional());
+
+        let mut dec = MessageDecoder::new(&buf[..len])
+            .unwrap()
+            .additional()
+            .unwrap();
+        let opt = dec.next().unwrap().unwrap();
+        assert_eq!(opt.edns_header().unwrap().udp_payload_size(), 0x9000);
+
+        assert_eq!(msg.encode(&mut buf[..30]), Err(Error::Truncated));
+    }
+}
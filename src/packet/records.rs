@@ -13,12 +13,15 @@ use std::{
     fmt::{self, Write},
     marker::PhantomData,
     net::{Ipv4Addr, Ipv6Addr},
+    ops::{Deref, DerefMut},
 };
 
-use crate::{name::DomainName, Error};
+use crate::{hex::Hex, name::DomainName, Error};
 
 use super::{
     decoder::{self, Reader},
+    dnssec::{Algorithm, Validity},
+    edns::{EdnsOption, EdnsOptions},
     encoder::Writer,
     Type,
 };
@@ -47,6 +50,30 @@ pub trait RecordData<'a>: Sized {
 
     /// Attempts to decode an instance of this resource record from an RDATA field.
     fn decode(r: &mut Decoder<'a>) -> Result<Self, Error>;
+
+    /// Decodes a resource record from an RDATA field into `self`, reusing the memory allocated by
+    /// `self` where possible.
+    ///
+    /// This allows decoding many records of the same type without allocating for each of them.
+    /// If an error is returned, `self` is left in an unspecified (but valid) state.
+    ///
+    /// By default, this decodes a new instance with [`RecordData::decode`] and replaces `self`
+    /// with it.
+    fn decode_into(&mut self, r: &mut Decoder<'a>) -> Result<(), Error> {
+        *self = Self::decode(r)?;
+        Ok(())
+    }
+}
+
+/// Decodes a domain name into `name`, reusing its storage if it is owned.
+fn decode_name_into<'a>(name: &mut Cow<'a, DomainName>, dec: &Decoder<'a>) -> Result<(), Error> {
+    match name {
+        Cow::Owned(name) => dec.r.read_domain_name_into(name),
+        Cow::Borrowed(_) => {
+            *name = Cow::Owned(dec.r.read_domain_name()?);
+            Ok(())
+        }
+    }
 }
 
 macro_rules! records {
@@ -55,7 +82,7 @@ macro_rules! records {
     ) => {
         /// Enumeration of all supported Resource Record types.
         #[non_exhaustive]
-        #[derive(Debug)]
+        #[derive(Debug, PartialEq, Eq, Hash, Clone)]
         pub enum Record<'a> {
             $( $record($record<'a>), )+
         }
@@ -77,6 +104,27 @@ macro_rules! records {
                 }
             }
 
+            /// Converts this record into one that owns all of its data, so that it no longer
+            /// borrows from the message it was decoded from.
+            pub fn into_owned(self) -> Record<'static> {
+                match self {
+                    $( Record::$record(r) => Record::$record(r.into_owned()), )+
+                }
+            }
+
+            /// Encodes the record data of this record.
+            pub(crate) fn encode_to_vec(&self) -> Vec<u8> {
+                let mut enc = Encoder {
+                    w: Writer::measuring(),
+                };
+                self.encode(&mut enc);
+                let mut buf = vec![0; enc.w.pos];
+                self.encode(&mut Encoder {
+                    w: Writer::new(&mut buf),
+                });
+                buf
+            }
+
             pub fn record_type(&self) -> Type {
                 match self {
                     $( Record::$record(_) => Type::$record, )+
@@ -94,7 +142,7 @@ macro_rules! records {
     };
 }
 
-records!(A, AAAA, CNAME, MX, NS, PTR, TXT, SRV, SOA);
+records!(A, AAAA, CNAME, MX, NS, PTR, TXT, HINFO, SRV, SOA, DNSKEY, RRSIG, NSEC, SVCB, HTTPS, OPT);
 
 /// A record storing an IPv4 address.
 ///
@@ -104,7 +152,7 @@ records!(A, AAAA, CNAME, MX, NS, PTR, TXT, SRV, SOA);
 ///
 /// Also see [`AAAA`] for the IPv6 equivalent. Both [`A`] and [`AAAA`] records can be present for a
 /// domain, making it reachable via both IPv4 and IPv6.
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct A<'a> {
     addr: Ipv4Addr,
     _p: PhantomData<&'a [u8]>,
@@ -140,6 +188,14 @@ impl<'a> A<'a> {
     pub fn addr(&self) -> Ipv4Addr {
         self.addr
     }
+
+    /// Converts this record into one that owns all of its data.
+    pub fn into_owned(self) -> A<'static> {
+        A {
+            addr: self.addr,
+            _p: PhantomData,
+        }
+    }
 }
 
 impl<'a> fmt::Display for A<'a> {
@@ -154,7 +210,7 @@ impl<'a> fmt::Display for A<'a> {
 ///
 /// Also see [`A`] for the IPv4 equivalent. Both [`A`] and [`AAAA`] records can be present for a
 /// domain, making it reachable via both IPv4 and IPv6.
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct AAAA<'a> {
     addr: Ipv6Addr,
     _p: PhantomData<&'a [u8]>,
@@ -190,6 +246,14 @@ impl<'a> AAAA<'a> {
     pub fn addr(&self) -> Ipv6Addr {
         self.addr
     }
+
+    /// Converts this record into one that owns all of its data.
+    pub fn into_owned(self) -> AAAA<'static> {
+        AAAA {
+            addr: self.addr,
+            _p: PhantomData,
+        }
+    }
 }
 
 impl<'a> fmt::Display for AAAA<'a> {
@@ -210,7 +274,7 @@ impl<'a> fmt::Display for AAAA<'a> {
 /// resource record types. For instace, a domain that has a [`CNAME`] record is not allowed to be
 /// listed as a mail server in an [`MX`] record, nor as an authoritative name server in an [`NS`]
 /// record. The canonical name should be used instead.
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct CNAME<'a> {
     name: Cow<'a, DomainName>,
     _p: PhantomData<&'a ()>,
@@ -220,7 +284,7 @@ impl<'a> RecordData<'a> for CNAME<'a> {
     const TYPE: Type = Type::CNAME;
 
     fn encode(&self, enc: &mut Encoder<'_>) {
-        enc.w.write_domain_name(&self.name);
+        enc.w.write_compressed_name(&self.name);
     }
 
     fn decode(dec: &mut Decoder<'a>) -> Result<Self, Error> {
@@ -229,6 +293,10 @@ impl<'a> RecordData<'a> for CNAME<'a> {
             _p: PhantomData,
         })
     }
+
+    fn decode_into(&mut self, dec: &mut Decoder<'a>) -> Result<(), Error> {
+        decode_name_into(&mut self.name, dec)
+    }
 }
 
 impl<'a> CNAME<'a> {
@@ -245,6 +313,14 @@ impl<'a> CNAME<'a> {
     pub fn cname(&self) -> &DomainName {
         &self.name
     }
+
+    /// Converts this record into one that owns all of its data.
+    pub fn into_owned(self) -> CNAME<'static> {
+        CNAME {
+            name: Cow::Owned(self.name.into_owned()),
+            _p: PhantomData,
+        }
+    }
 }
 
 impl<'a> fmt::Display for CNAME<'a> {
@@ -256,7 +332,7 @@ impl<'a> fmt::Display for CNAME<'a> {
 /// A **M**ail e**X**changer record specifies the mail server in charge of a domain.
 ///
 /// A domain can have multiple [`MX`] records pointing to different mail servers for load balancing.
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct MX<'a> {
     preference: u16,
     exchange: Cow<'a, DomainName>,
@@ -268,7 +344,7 @@ impl<'a> RecordData<'a> for MX<'a> {
 
     fn encode(&self, enc: &mut Encoder<'_>) {
         enc.w.write_u16(self.preference);
-        enc.w.write_domain_name(&self.exchange);
+        enc.w.write_compressed_name(&self.exchange);
     }
 
     fn decode(dec: &mut Decoder<'a>) -> Result<Self, Error> {
@@ -278,6 +354,11 @@ impl<'a> RecordData<'a> for MX<'a> {
             _p: PhantomData,
         })
     }
+
+    fn decode_into(&mut self, dec: &mut Decoder<'a>) -> Result<(), Error> {
+        self.preference = dec.r.read_u16()?;
+        decode_name_into(&mut self.exchange, dec)
+    }
 }
 
 impl<'a> MX<'a> {
@@ -306,6 +387,15 @@ impl<'a> MX<'a> {
     pub fn exchange(&self) -> &DomainName {
         &self.exchange
     }
+
+    /// Converts this record into one that owns all of its data.
+    pub fn into_owned(self) -> MX<'static> {
+        MX {
+            preference: self.preference,
+            exchange: Cow::Owned(self.exchange.into_owned()),
+            _p: PhantomData,
+        }
+    }
 }
 
 impl<'a> fmt::Display for MX<'a> {
@@ -320,7 +410,7 @@ impl<'a> fmt::Display for MX<'a> {
 /// contact.
 ///
 /// Several [`NS`] records can be used by the same domain name to increase redundancy.
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct NS<'a> {
     nsdname: Cow<'a, DomainName>,
     _p: PhantomData<&'a ()>,
@@ -330,7 +420,7 @@ impl<'a> RecordData<'a> for NS<'a> {
     const TYPE: Type = Type::NS;
 
     fn encode(&self, enc: &mut Encoder<'_>) {
-        enc.w.write_domain_name(&self.nsdname);
+        enc.w.write_compressed_name(&self.nsdname);
     }
 
     fn decode(dec: &mut Decoder<'a>) -> Result<Self, Error> {
@@ -339,6 +429,10 @@ impl<'a> RecordData<'a> for NS<'a> {
             _p: PhantomData,
         })
     }
+
+    fn decode_into(&mut self, dec: &mut Decoder<'a>) -> Result<(), Error> {
+        decode_name_into(&mut self.nsdname, dec)
+    }
 }
 
 impl<'a> NS<'a> {
@@ -354,6 +448,14 @@ impl<'a> NS<'a> {
     pub fn nsdname(&self) -> &DomainName {
         &self.nsdname
     }
+
+    /// Converts this record into one that owns all of its data.
+    pub fn into_owned(self) -> NS<'static> {
+        NS {
+            nsdname: Cow::Owned(self.nsdname.into_owned()),
+            _p: PhantomData,
+        }
+    }
 }
 
 impl<'a> fmt::Display for NS<'a> {
@@ -366,7 +468,7 @@ impl<'a> fmt::Display for NS<'a> {
 ///
 /// This record type is used by *reverse DNS*, in which [`PTR`] records are not associated with the
 /// human-readable domain name, but with the `in-addr.arpa` namespace. It is also used for DNS-SD.
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct PTR<'a> {
     ptrdname: Cow<'a, DomainName>,
     _p: PhantomData<&'a ()>,
@@ -376,7 +478,7 @@ impl<'a> RecordData<'a> for PTR<'a> {
     const TYPE: Type = Type::PTR;
 
     fn encode(&self, enc: &mut Encoder<'_>) {
-        enc.w.write_domain_name(&self.ptrdname);
+        enc.w.write_compressed_name(&self.ptrdname);
     }
 
     fn decode(dec: &mut Decoder<'a>) -> Result<Self, Error> {
@@ -385,6 +487,10 @@ impl<'a> RecordData<'a> for PTR<'a> {
             _p: PhantomData,
         })
     }
+
+    fn decode_into(&mut self, dec: &mut Decoder<'a>) -> Result<(), Error> {
+        decode_name_into(&mut self.ptrdname, dec)
+    }
 }
 
 impl<'a> PTR<'a> {
@@ -400,6 +506,14 @@ impl<'a> PTR<'a> {
     pub fn ptrdname(&self) -> &DomainName {
         &self.ptrdname
     }
+
+    /// Converts this record into one that owns all of its data.
+    pub fn into_owned(self) -> PTR<'static> {
+        PTR {
+            ptrdname: Cow::Owned(self.ptrdname.into_owned()),
+            _p: PhantomData,
+        }
+    }
 }
 
 impl<'a> fmt::Display for PTR<'a> {
@@ -417,7 +531,7 @@ impl<'a> fmt::Display for PTR<'a> {
 /// A domain may have multiple [`TXT`] records, and each [`TXT`] record can store multiple blobs of
 /// data (but must contain at least one entry). Typically, information pertaining to a service must
 /// be stored as several entries in a single [`TXT`] record.
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct TXT<'a> {
     entries: Vec<Cow<'a, [u8]>>,
 }
@@ -441,6 +555,14 @@ impl<'a> RecordData<'a> for TXT<'a> {
 
         Ok(Self { entries })
     }
+
+    fn decode_into(&mut self, dec: &mut Decoder<'a>) -> Result<(), Error> {
+        self.entries.clear();
+        while !dec.r.buf().is_empty() {
+            self.entries.push(dec.r.read_character_string()?.into());
+        }
+        Ok(())
+    }
 }
 
 impl<'a> TXT<'a> {
@@ -468,24 +590,123 @@ impl<'a> TXT<'a> {
     pub fn entries(&self) -> impl Iterator<Item = &'_ [u8]> {
         self.entries.iter().map(|cow| &**cow)
     }
+
+    /// Returns an iterator over the *character string* values in the raw RDATA of a [`TXT`]
+    /// record, without decoding it into a [`TXT`].
+    ///
+    /// Unlike [`RecordData::decode`], this doesn't allocate. Also see
+    /// [`decoder::ResourceRecord::txt_entries`].
+    pub fn entries_iter(rdata: &'a [u8]) -> decoder::TxtEntries<'a> {
+        decoder::TxtEntries::new(rdata)
+    }
+
+    /// Converts this record into one that owns all of its data.
+    pub fn into_owned(self) -> TXT<'static> {
+        TXT {
+            entries: self
+                .entries
+                .into_iter()
+                .map(|e| Cow::Owned(e.into_owned()))
+                .collect(),
+        }
+    }
 }
 
 impl<'a> fmt::Display for TXT<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for (i, entry) in self.entries().enumerate() {
-            if i != 0 {
-                f.write_char('\t')?;
-            }
+        fmt_txt_entries(self.entries(), f)
+    }
+}
 
-            for &byte in entry {
-                if byte.is_ascii_graphic() {
-                    f.write_char(byte as char)?;
-                } else {
-                    f.write_char('�')?;
-                }
+/// Formats TXT record entries the way [`TXT`]'s `Display` implementation does.
+pub(crate) fn fmt_txt_entries<'e>(
+    entries: impl Iterator<Item = &'e [u8]>,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    for (i, entry) in entries.enumerate() {
+        if i != 0 {
+            f.write_char('\t')?;
+        }
+
+        for &byte in entry {
+            if byte.is_ascii_graphic() {
+                f.write_char(byte as char)?;
+            } else {
+                f.write_char('�')?;
             }
         }
-        Ok(())
+    }
+    Ok(())
+}
+
+/// A host information record, describing the CPU and operating system of a host.
+///
+/// [`HINFO`] records are rarely published today. Instead, servers that don't want to answer
+/// queries for type `ANY` respond with a synthesized [`HINFO`] record with the CPU set to
+/// `RFC8482` ([RFC 8482, section 4.2]).
+///
+/// [RFC 8482, section 4.2]: https://datatracker.ietf.org/doc/html/rfc8482#section-4.2
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct HINFO<'a> {
+    cpu: Cow<'a, [u8]>,
+    os: Cow<'a, [u8]>,
+}
+
+impl<'a> RecordData<'a> for HINFO<'a> {
+    const TYPE: Type = Type::HINFO;
+
+    fn encode(&self, enc: &mut Encoder<'_>) {
+        enc.w.write_character_string(&self.cpu);
+        enc.w.write_character_string(&self.os);
+    }
+
+    fn decode(dec: &mut Decoder<'a>) -> Result<Self, Error> {
+        Ok(Self {
+            cpu: dec.r.read_character_string()?.into(),
+            os: dec.r.read_character_string()?.into(),
+        })
+    }
+}
+
+impl<'a> HINFO<'a> {
+    /// Creates a new [`HINFO`] record.
+    pub fn new(cpu: impl Into<Cow<'a, [u8]>>, os: impl Into<Cow<'a, [u8]>>) -> Self {
+        Self {
+            cpu: cpu.into(),
+            os: os.into(),
+        }
+    }
+
+    /// Creates the [`HINFO`] record used to answer queries for type `ANY` minimally, as
+    /// recommended by [RFC 8482, section 4.2].
+    ///
+    /// [RFC 8482, section 4.2]: https://datatracker.ietf.org/doc/html/rfc8482#section-4.2
+    pub fn rfc8482() -> HINFO<'static> {
+        HINFO::new(&b"RFC8482"[..], &b""[..])
+    }
+
+    /// Returns the CPU type of the host.
+    pub fn cpu(&self) -> &[u8] {
+        &self.cpu
+    }
+
+    /// Returns the operating system of the host.
+    pub fn os(&self) -> &[u8] {
+        &self.os
+    }
+
+    /// Converts this record into one that owns all of its data.
+    pub fn into_owned(self) -> HINFO<'static> {
+        HINFO {
+            cpu: Cow::Owned(self.cpu.into_owned()),
+            os: Cow::Owned(self.os.into_owned()),
+        }
+    }
+}
+
+impl<'a> fmt::Display for HINFO<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_txt_entries([self.cpu(), self.os()].into_iter(), f)
     }
 }
 
@@ -495,7 +716,7 @@ impl<'a> fmt::Display for TXT<'a> {
 /// `service` is an identifier of the service offered, `_proto` is either `_tcp` for services served
 /// over TCP or `_udp` for all other services, and `name` is the domain name advertising the
 /// service (which may be different from the domain name *hosting* the service).
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct SRV<'a> {
     priority: u16,
     weight: u16,
@@ -523,6 +744,13 @@ impl<'a> RecordData<'a> for SRV<'a> {
             _p: PhantomData,
         })
     }
+
+    fn decode_into(&mut self, dec: &mut Decoder<'a>) -> Result<(), Error> {
+        self.priority = dec.r.read_u16()?;
+        self.weight = dec.r.read_u16()?;
+        self.port = dec.r.read_u16()?;
+        decode_name_into(&mut self.target, dec)
+    }
 }
 
 impl<'a> SRV<'a> {
@@ -568,6 +796,17 @@ impl<'a> SRV<'a> {
     pub fn target(&self) -> &DomainName {
         &self.target
     }
+
+    /// Converts this record into one that owns all of its data.
+    pub fn into_owned(self) -> SRV<'static> {
+        SRV {
+            priority: self.priority,
+            weight: self.weight,
+            port: self.port,
+            target: Cow::Owned(self.target.into_owned()),
+            _p: PhantomData,
+        }
+    }
 }
 
 impl<'a> fmt::Display for SRV<'a> {
@@ -581,7 +820,7 @@ impl<'a> fmt::Display for SRV<'a> {
 }
 
 /// Record containing administrative information about a DNS zone.
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct SOA<'a> {
     mname: Cow<'a, DomainName>,
     rname: Cow<'a, DomainName>,
@@ -597,8 +836,8 @@ impl<'a> RecordData<'a> for SOA<'a> {
     const TYPE: Type = Type::SOA;
 
     fn encode(&self, enc: &mut Encoder<'_>) {
-        enc.w.write_domain_name(&self.mname);
-        enc.w.write_domain_name(&self.rname);
+        enc.w.write_compressed_name(&self.mname);
+        enc.w.write_compressed_name(&self.rname);
         enc.w.write_u32(self.serial);
         enc.w.write_u32(self.refresh);
         enc.w.write_u32(self.retry);
@@ -698,6 +937,20 @@ impl<'a> SOA<'a> {
     pub fn minimum_ttl(&self) -> u32 {
         self.minimum_ttl
     }
+
+    /// Converts this record into one that owns all of its data.
+    pub fn into_owned(self) -> SOA<'static> {
+        SOA {
+            mname: Cow::Owned(self.mname.into_owned()),
+            rname: Cow::Owned(self.rname.into_owned()),
+            serial: self.serial,
+            refresh: self.refresh,
+            retry: self.retry,
+            expire: self.expire,
+            minimum_ttl: self.minimum_ttl,
+            _p: PhantomData,
+        }
+    }
 }
 
 impl<'a> fmt::Display for SOA<'a> {
@@ -716,6 +969,808 @@ impl<'a> fmt::Display for SOA<'a> {
     }
 }
 
+/// A DNSSEC public key, published at the apex of a signed zone.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct DNSKEY<'a> {
+    flags: u16,
+    protocol: u8,
+    algorithm: Algorithm,
+    public_key: Cow<'a, [u8]>,
+}
+
+impl<'a> RecordData<'a> for DNSKEY<'a> {
+    const TYPE: Type = Type::DNSKEY;
+
+    fn encode(&self, enc: &mut Encoder<'_>) {
+        enc.w.write_u16(self.flags);
+        enc.w.write_u8(self.protocol);
+        enc.w.write_u8(self.algorithm.0);
+        enc.w.write_slice(&self.public_key);
+    }
+
+    fn decode(dec: &mut Decoder<'a>) -> Result<Self, Error> {
+        Ok(Self {
+            flags: dec.r.read_u16()?,
+            protocol: dec.r.read_u8()?,
+            algorithm: Algorithm(dec.r.read_u8()?),
+            public_key: dec.r.read_slice(dec.r.buf().len())?.into(),
+        })
+    }
+}
+
+impl<'a> DNSKEY<'a> {
+    /// Flag indicating that the key is a DNSSEC zone key.
+    pub const ZONE_KEY: u16 = 0x0100;
+    /// Flag indicating that the key is a *Secure Entry Point*, typically a key-signing key.
+    pub const SECURE_ENTRY_POINT: u16 = 0x0001;
+
+    /// Creates a new [`DNSKEY`] record.
+    ///
+    /// The protocol field is always set to 3, as required by [RFC 4034].
+    ///
+    /// [RFC 4034]: https://datatracker.ietf.org/doc/html/rfc4034
+    pub fn new(flags: u16, algorithm: Algorithm, public_key: impl Into<Cow<'a, [u8]>>) -> Self {
+        Self {
+            flags,
+            protocol: 3,
+            algorithm,
+            public_key: public_key.into(),
+        }
+    }
+
+    /// Returns the key's flags (see [`DNSKEY::ZONE_KEY`] and [`DNSKEY::SECURE_ENTRY_POINT`]).
+    #[inline]
+    pub fn flags(&self) -> u16 {
+        self.flags
+    }
+
+    /// Returns the protocol field, which must be 3.
+    #[inline]
+    pub fn protocol(&self) -> u8 {
+        self.protocol
+    }
+
+    /// Returns the algorithm the key is used with.
+    #[inline]
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Returns the public key, in the algorithm-specific wire format.
+    #[inline]
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    /// Computes the key tag identifying this key in `RRSIG` records ([RFC 4034, Appendix B]).
+    ///
+    /// [RFC 4034, Appendix B]: https://datatracker.ietf.org/doc/html/rfc4034#appendix-B
+    pub fn key_tag(&self) -> u16 {
+        let rdata = Record::DNSKEY(self.clone()).encode_to_vec();
+        let mut acc = 0u32;
+        for (i, &byte) in rdata.iter().enumerate() {
+            acc += if i % 2 == 0 {
+                u32::from(byte) << 8
+            } else {
+                u32::from(byte)
+            };
+        }
+        acc += acc >> 16;
+        acc as u16
+    }
+
+    /// Converts this record into one that owns all of its data.
+    pub fn into_owned(self) -> DNSKEY<'static> {
+        DNSKEY {
+            flags: self.flags,
+            protocol: self.protocol,
+            algorithm: self.algorithm,
+            public_key: Cow::Owned(self.public_key.into_owned()),
+        }
+    }
+}
+
+impl<'a> fmt::Display for DNSKEY<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}",
+            self.flags,
+            self.protocol,
+            self.algorithm,
+            Hex(&self.public_key)
+        )
+    }
+}
+
+/// A DNSSEC signature over an RRset.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct RRSIG<'a> {
+    type_covered: Type,
+    algorithm: Algorithm,
+    labels: u8,
+    original_ttl: u32,
+    expiration: u32,
+    inception: u32,
+    key_tag: u16,
+    signer_name: Cow<'a, DomainName>,
+    signature: Cow<'a, [u8]>,
+}
+
+impl<'a> RecordData<'a> for RRSIG<'a> {
+    const TYPE: Type = Type::RRSIG;
+
+    fn encode(&self, enc: &mut Encoder<'_>) {
+        let w = &mut enc.w;
+        w.write_u16(self.type_covered.0);
+        w.write_u8(self.algorithm.0);
+        w.write_u8(self.labels);
+        w.write_u32(self.original_ttl);
+        w.write_u32(self.expiration);
+        w.write_u32(self.inception);
+        w.write_u16(self.key_tag);
+        w.write_domain_name(&self.signer_name);
+        w.write_slice(&self.signature);
+    }
+
+    fn decode(dec: &mut Decoder<'a>) -> Result<Self, Error> {
+        Ok(Self {
+            type_covered: Type(dec.r.read_u16()?),
+            algorithm: Algorithm(dec.r.read_u8()?),
+            labels: dec.r.read_u8()?,
+            original_ttl: dec.r.read_u32()?,
+            expiration: dec.r.read_u32()?,
+            inception: dec.r.read_u32()?,
+            key_tag: dec.r.read_u16()?,
+            signer_name: dec.r.read_domain_name()?.into(),
+            signature: dec.r.read_slice(dec.r.buf().len())?.into(),
+        })
+    }
+}
+
+impl<'a> RRSIG<'a> {
+    /// Creates a new [`RRSIG`] record.
+    ///
+    /// `labels` is the number of labels in the owner name of the signed RRset, not counting a
+    /// leading `*` label.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        type_covered: Type,
+        algorithm: Algorithm,
+        labels: u8,
+        original_ttl: u32,
+        validity: Validity,
+        key_tag: u16,
+        signer_name: impl Into<Cow<'a, DomainName>>,
+        signature: impl Into<Cow<'a, [u8]>>,
+    ) -> Self {
+        Self {
+            type_covered,
+            algorithm,
+            labels,
+            original_ttl,
+            expiration: validity.expiration(),
+            inception: validity.inception(),
+            key_tag,
+            signer_name: signer_name.into(),
+            signature: signature.into(),
+        }
+    }
+
+    /// Returns the type of the RRset covered by this signature.
+    #[inline]
+    pub fn type_covered(&self) -> Type {
+        self.type_covered
+    }
+
+    /// Returns the algorithm used to create the signature.
+    #[inline]
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Returns the number of labels in the owner name of the signed RRset.
+    #[inline]
+    pub fn labels(&self) -> u8 {
+        self.labels
+    }
+
+    /// Returns the TTL of the signed RRset, as it appears in the authoritative zone.
+    #[inline]
+    pub fn original_ttl(&self) -> u32 {
+        self.original_ttl
+    }
+
+    /// Returns the period during which the signature is valid.
+    #[inline]
+    pub fn validity(&self) -> Validity {
+        Validity::new(self.inception, self.expiration)
+    }
+
+    /// Returns the key tag of the signing key's [`DNSKEY`] record.
+    #[inline]
+    pub fn key_tag(&self) -> u16 {
+        self.key_tag
+    }
+
+    /// Returns the name of the zone containing the signing key.
+    #[inline]
+    pub fn signer_name(&self) -> &DomainName {
+        &self.signer_name
+    }
+
+    /// Returns the raw signature.
+    #[inline]
+    pub fn signature(&self) -> &[u8] {
+        &self.signature
+    }
+
+    /// Converts this record into one that owns all of its data.
+    pub fn into_owned(self) -> RRSIG<'static> {
+        RRSIG {
+            type_covered: self.type_covered,
+            algorithm: self.algorithm,
+            labels: self.labels,
+            original_ttl: self.original_ttl,
+            expiration: self.expiration,
+            inception: self.inception,
+            key_tag: self.key_tag,
+            signer_name: Cow::Owned(self.signer_name.into_owned()),
+            signature: Cow::Owned(self.signature.into_owned()),
+        }
+    }
+}
+
+impl<'a> fmt::Display for RRSIG<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.type_covered,
+            self.algorithm,
+            self.labels,
+            self.original_ttl,
+            self.expiration,
+            self.inception,
+            self.key_tag,
+            self.signer_name,
+            Hex(&self.signature),
+        )
+    }
+}
+
+/// Authenticated denial of existence: links an owner name to the next name in a signed zone.
+///
+/// Every name in a signed zone owns an [`NSEC`] record listing the record types present at the
+/// name, and pointing to the next name in canonical order. A signed [`NSEC`] record proves that
+/// no names exist between its owner and [`NSEC::next_domain_name`], and that no other types exist
+/// at its owner.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct NSEC<'a> {
+    next_domain_name: Cow<'a, DomainName>,
+    /// Sorted and deduplicated.
+    types: Vec<Type>,
+}
+
+impl<'a> RecordData<'a> for NSEC<'a> {
+    const TYPE: Type = Type::NSEC;
+
+    fn encode(&self, enc: &mut Encoder<'_>) {
+        enc.w.write_domain_name(&self.next_domain_name);
+        let mut types = self.types.iter().peekable();
+        while let Some(&first) = types.peek() {
+            let window = first.0 >> 8;
+            let mut bitmap = [0u8; 32];
+            let mut len = 0;
+            while let Some(ty) = types.next_if(|ty| ty.0 >> 8 == window) {
+                let bit = usize::from(ty.0 as u8);
+                bitmap[bit / 8] |= 0x80 >> (bit % 8);
+                len = bit / 8 + 1;
+            }
+            enc.w.write_u8(window as u8);
+            enc.w.write_u8(len as u8);
+            enc.w.write_slice(&bitmap[..len]);
+        }
+    }
+
+    fn decode(dec: &mut Decoder<'a>) -> Result<Self, Error> {
+        let next_domain_name = dec.r.read_domain_name()?.into();
+        let mut types = Vec::new();
+        let mut last_window = None;
+        while !dec.r.buf().is_empty() {
+            let window = dec.r.read_u8()?;
+            let len = dec.r.read_u8()?;
+            if last_window.is_some_and(|last| window <= last) || !(1..=32).contains(&len) {
+                return Err(Error::InvalidValue);
+            }
+            last_window = Some(window);
+            let bitmap = dec.r.read_slice(usize::from(len))?;
+            for (i, &byte) in bitmap.iter().enumerate() {
+                for bit in 0..8 {
+                    if byte & (0x80 >> bit) != 0 {
+                        types.push(Type(u16::from(window) << 8 | (i * 8 + bit) as u16));
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            next_domain_name,
+            types,
+        })
+    }
+}
+
+impl<'a> NSEC<'a> {
+    /// Creates an [`NSEC`] record from the next owner name in the zone and the types present at
+    /// the owner of this record.
+    pub fn new(
+        next_domain_name: impl Into<Cow<'a, DomainName>>,
+        types: impl IntoIterator<Item = Type>,
+    ) -> Self {
+        let mut types = types.into_iter().collect::<Vec<_>>();
+        types.sort_by_key(|ty| ty.0);
+        types.dedup();
+        Self {
+            next_domain_name: next_domain_name.into(),
+            types,
+        }
+    }
+
+    /// Returns the next owner name in the zone, in canonical order.
+    ///
+    /// The last [`NSEC`] record in a zone points back to the zone apex.
+    #[inline]
+    pub fn next_domain_name(&self) -> &DomainName {
+        &self.next_domain_name
+    }
+
+    /// Returns the record types present at the owner name, in ascending order.
+    #[inline]
+    pub fn types(&self) -> &[Type] {
+        &self.types
+    }
+
+    /// Returns whether `ty` is present at the owner name.
+    pub fn has_type(&self, ty: Type) -> bool {
+        self.types.binary_search_by_key(&ty.0, |ty| ty.0).is_ok()
+    }
+
+    /// Converts this record into one that owns all of its data.
+    pub fn into_owned(self) -> NSEC<'static> {
+        NSEC {
+            next_domain_name: Cow::Owned(self.next_domain_name.into_owned()),
+            types: self.types,
+        }
+    }
+}
+
+impl<'a> fmt::Display for NSEC<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.next_domain_name.fmt(f)?;
+        for ty in &self.types {
+            write!(f, " {}", ty)?;
+        }
+        Ok(())
+    }
+}
+
+ffi_enum! {
+    /// Keys of the service parameters in [`SVCB`] and [`HTTPS`] records.
+    ///
+    /// These are copied from the [IANA registry].
+    ///
+    /// [IANA registry]: https://www.iana.org/assignments/dns-svcb/dns-svcb.xhtml
+    pub enum SvcParamKey: u16 {
+        /// Keys that clients must understand to use the record.
+        MANDATORY = 0,
+        /// Application-Layer Protocol Negotiation (ALPN) protocol IDs supported by the endpoint.
+        ALPN = 1,
+        /// The endpoint doesn't support the protocol's default ALPN protocol ID.
+        NO_DEFAULT_ALPN = 2,
+        /// The port the endpoint listens on.
+        PORT = 3,
+        /// IPv4 addresses of the endpoint, which clients may use before resolving them.
+        IPV4HINT = 4,
+        /// An Encrypted ClientHello configuration list.
+        ECH = 5,
+        /// IPv6 addresses of the endpoint, which clients may use before resolving them.
+        IPV6HINT = 6,
+    }
+}
+
+impl fmt::Display for SvcParamKey {
+    /// Formats the key in presentation format (for example, `no-default-alpn` or `key65000`).
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::MANDATORY => f.write_str("mandatory"),
+            Self::ALPN => f.write_str("alpn"),
+            Self::NO_DEFAULT_ALPN => f.write_str("no-default-alpn"),
+            Self::PORT => f.write_str("port"),
+            Self::IPV4HINT => f.write_str("ipv4hint"),
+            Self::ECH => f.write_str("ech"),
+            Self::IPV6HINT => f.write_str("ipv6hint"),
+            _ => write!(f, "key{}", self.0),
+        }
+    }
+}
+
+/// Service binding: describes an alternative endpoint of a service, and the parameters needed to
+/// connect to it ([RFC 9460]).
+///
+/// An [`SVCB`] record is either in *AliasMode* ([`SVCB::priority`] is 0), in which case it points
+/// to another name owning the [`SVCB`] records for the service, or in *ServiceMode*, in which case
+/// [`SVCB::target`] is an endpoint of the service. Clients should try ServiceMode records in order
+/// of ascending priority. The parameters of the endpoint, like the supported ALPN protocols and
+/// the port, are stored as a list of key/value pairs (see [`SvcParamKey`]).
+///
+/// See [`HTTPS`] for the variant of this record used for HTTPS origins.
+///
+/// [RFC 9460]: https://datatracker.ietf.org/doc/html/rfc9460
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct SVCB<'a> {
+    priority: u16,
+    target: Cow<'a, DomainName>,
+    /// The parameters in wire format, with keys in strictly ascending order.
+    params: Cow<'a, [u8]>,
+}
+
+impl<'a> RecordData<'a> for SVCB<'a> {
+    const TYPE: Type = Type::SVCB;
+
+    fn encode(&self, enc: &mut Encoder<'_>) {
+        enc.w.write_u16(self.priority);
+        enc.w.write_domain_name(&self.target);
+        enc.w.write_slice(&self.params);
+    }
+
+    fn decode(dec: &mut Decoder<'a>) -> Result<Self, Error> {
+        let priority = dec.r.read_u16()?;
+        let target = dec.r.read_domain_name()?.into();
+        let params = dec.r.read_slice(dec.r.buf().len())?;
+
+        // Validate the parameters, so that they can be iterated over infallibly later.
+        let r = Reader::new(params);
+        let mut last_key = None;
+        while !r.buf().is_empty() {
+            let key = r.read_u16()?;
+            if last_key.is_some_and(|last| key <= last) {
+                return Err(Error::InvalidValue);
+            }
+            last_key = Some(key);
+            let len = r.read_u16()?;
+            r.read_slice(usize::from(len))?;
+        }
+
+        Ok(Self {
+            priority,
+            target,
+            params: params.into(),
+        })
+    }
+}
+
+impl<'a> SVCB<'a> {
+    /// Creates an [`SVCB`] record without any parameters.
+    ///
+    /// A `priority` of 0 creates an AliasMode record. A `target` of [`DomainName::ROOT`] refers to
+    /// the owner name of the record (in ServiceMode), or indicates that the service doesn't exist
+    /// (in AliasMode).
+    pub fn new(priority: u16, target: impl Into<Cow<'a, DomainName>>) -> Self {
+        Self {
+            priority,
+            target: target.into(),
+            params: Cow::Borrowed(&[]),
+        }
+    }
+
+    /// Returns the priority of this record, or 0 if it is an AliasMode record.
+    ///
+    /// Lower values are preferred.
+    #[inline]
+    pub fn priority(&self) -> u16 {
+        self.priority
+    }
+
+    /// Returns whether this is an AliasMode record.
+    #[inline]
+    pub fn is_alias(&self) -> bool {
+        self.priority == 0
+    }
+
+    /// Returns the target name of this record.
+    ///
+    /// [`DomainName::ROOT`] has a special meaning, see [`SVCB::new`].
+    #[inline]
+    pub fn target(&self) -> &DomainName {
+        &self.target
+    }
+
+    /// Sets the value of the parameter `key`, replacing any existing value.
+    ///
+    /// See [`SvcParamKey`] for the format of the values.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `value` is longer than 65535 bytes.
+    pub fn set_param(&mut self, key: SvcParamKey, value: &[u8]) {
+        let len = u16::try_from(value.len()).expect("SvcParam value too long");
+        let mut params = Vec::with_capacity(self.params.len() + 4 + value.len());
+        let mut inserted = false;
+        for (k, v) in self.params() {
+            if k.0 > key.0 && !inserted {
+                push_param(&mut params, key, len, value);
+                inserted = true;
+            }
+            if k != key {
+                push_param(&mut params, k, v.len() as u16, v);
+            }
+        }
+        if !inserted {
+            push_param(&mut params, key, len, value);
+        }
+        self.params = params.into();
+    }
+
+    /// Returns an iterator over the parameters of this record, in ascending order of their keys.
+    pub fn params(&self) -> impl Iterator<Item = (SvcParamKey, &[u8])> + '_ {
+        let r = Reader::new(&self.params);
+        std::iter::from_fn(move || {
+            if r.buf().is_empty() {
+                return None;
+            }
+            // `decode` and `set_param` ensure that this is well-formed.
+            let key = r.read_u16().ok()?;
+            let len = r.read_u16().ok()?;
+            Some((SvcParamKey(key), r.read_slice(usize::from(len)).ok()?))
+        })
+    }
+
+    /// Returns the value of the parameter `key`, if this record has it.
+    pub fn param(&self, key: SvcParamKey) -> Option<&[u8]> {
+        self.params().find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    /// Returns the keys that clients must understand in order to use this record.
+    pub fn mandatory(&self) -> impl Iterator<Item = SvcParamKey> + '_ {
+        self.param(SvcParamKey::MANDATORY)
+            .unwrap_or_default()
+            .chunks_exact(2)
+            .map(|key| SvcParamKey(u16::from_be_bytes([key[0], key[1]])))
+    }
+
+    /// Returns the ALPN protocol IDs (like `h2` or `h3`) supported by the endpoint.
+    ///
+    /// Unless [`SVCB::no_default_alpn`] returns `true`, the default protocol of the service (like
+    /// `http/1.1` for [`HTTPS`]) is supported as well.
+    pub fn alpn(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        let r = Reader::new(self.param(SvcParamKey::ALPN).unwrap_or_default());
+        std::iter::from_fn(move || r.read_character_string().ok())
+    }
+
+    /// Returns whether the endpoint doesn't support the default protocol of the service.
+    pub fn no_default_alpn(&self) -> bool {
+        self.param(SvcParamKey::NO_DEFAULT_ALPN).is_some()
+    }
+
+    /// Returns the port the endpoint listens on, if it differs from the default port of the
+    /// service.
+    pub fn port(&self) -> Option<u16> {
+        match self.param(SvcParamKey::PORT)? {
+            &[hi, lo] => Some(u16::from_be_bytes([hi, lo])),
+            _ => None,
+        }
+    }
+
+    /// Returns the IPv4 address hints of the endpoint.
+    pub fn ipv4_hints(&self) -> impl Iterator<Item = Ipv4Addr> + '_ {
+        self.param(SvcParamKey::IPV4HINT)
+            .unwrap_or_default()
+            .chunks_exact(4)
+            .map(|addr| Ipv4Addr::from(<[u8; 4]>::try_from(addr).unwrap()))
+    }
+
+    /// Returns the IPv6 address hints of the endpoint.
+    pub fn ipv6_hints(&self) -> impl Iterator<Item = Ipv6Addr> + '_ {
+        self.param(SvcParamKey::IPV6HINT)
+            .unwrap_or_default()
+            .chunks_exact(16)
+            .map(|addr| Ipv6Addr::from(<[u8; 16]>::try_from(addr).unwrap()))
+    }
+
+    /// Returns the Encrypted ClientHello configuration list of the endpoint, if any.
+    pub fn ech(&self) -> Option<&[u8]> {
+        self.param(SvcParamKey::ECH)
+    }
+
+    /// Converts this record into one that owns all of its data.
+    pub fn into_owned(self) -> SVCB<'static> {
+        SVCB {
+            priority: self.priority,
+            target: Cow::Owned(self.target.into_owned()),
+            params: Cow::Owned(self.params.into_owned()),
+        }
+    }
+}
+
+fn push_param(params: &mut Vec<u8>, key: SvcParamKey, len: u16, value: &[u8]) {
+    params.extend_from_slice(&key.0.to_be_bytes());
+    params.extend_from_slice(&len.to_be_bytes());
+    params.extend_from_slice(value);
+}
+
+impl<'a> fmt::Display for SVCB<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\t{}", self.priority, self.target)?;
+        for (key, value) in self.params() {
+            write!(f, "\t{}", key)?;
+            match key {
+                SvcParamKey::NO_DEFAULT_ALPN if value.is_empty() => continue,
+                SvcParamKey::MANDATORY => {
+                    let keys = self.mandatory().map(|key| key.to_string());
+                    write!(f, "={}", keys.collect::<Vec<_>>().join(","))?
+                }
+                SvcParamKey::ALPN => {
+                    f.write_char('=')?;
+                    for (i, id) in self.alpn().enumerate() {
+                        if i != 0 {
+                            f.write_char(',')?;
+                        }
+                        fmt_txt_entries([id].into_iter(), f)?;
+                    }
+                }
+                SvcParamKey::PORT if value.len() == 2 => write!(f, "={}", self.port().unwrap())?,
+                SvcParamKey::IPV4HINT => {
+                    let addrs = self.ipv4_hints().map(|addr| addr.to_string());
+                    write!(f, "={}", addrs.collect::<Vec<_>>().join(","))?
+                }
+                SvcParamKey::IPV6HINT => {
+                    let addrs = self.ipv6_hints().map(|addr| addr.to_string());
+                    write!(f, "={}", addrs.collect::<Vec<_>>().join(","))?
+                }
+                _ => write!(f, "={}", Hex(value))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Service binding for HTTPS origins ([RFC 9460, section 9]).
+///
+/// This has the same format as [`SVCB`], which it dereferences to. [`HTTPS`] records are owned by
+/// the host name of the origin for the default port (443), and by `_<port>._https.<host>` for
+/// other ports. The default ALPN protocol is `http/1.1`.
+///
+/// [RFC 9460, section 9]: https://datatracker.ietf.org/doc/html/rfc9460#section-9
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct HTTPS<'a>(SVCB<'a>);
+
+impl<'a> RecordData<'a> for HTTPS<'a> {
+    const TYPE: Type = Type::HTTPS;
+
+    fn encode(&self, enc: &mut Encoder<'_>) {
+        self.0.encode(enc)
+    }
+
+    fn decode(dec: &mut Decoder<'a>) -> Result<Self, Error> {
+        SVCB::decode(dec).map(Self)
+    }
+}
+
+impl<'a> HTTPS<'a> {
+    /// Creates an [`HTTPS`] record without any parameters.
+    ///
+    /// See [`SVCB::new`].
+    pub fn new(priority: u16, target: impl Into<Cow<'a, DomainName>>) -> Self {
+        Self(SVCB::new(priority, target))
+    }
+
+    /// Converts this record into one that owns all of its data.
+    pub fn into_owned(self) -> HTTPS<'static> {
+        HTTPS(self.0.into_owned())
+    }
+}
+
+impl<'a> From<SVCB<'a>> for HTTPS<'a> {
+    fn from(svcb: SVCB<'a>) -> Self {
+        Self(svcb)
+    }
+}
+
+impl<'a> Deref for HTTPS<'a> {
+    type Target = SVCB<'a>;
+
+    fn deref(&self) -> &SVCB<'a> {
+        &self.0
+    }
+}
+
+impl<'a> DerefMut for HTTPS<'a> {
+    fn deref_mut(&mut self) -> &mut SVCB<'a> {
+        &mut self.0
+    }
+}
+
+impl<'a> fmt::Display for HTTPS<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The RDATA of an EDNS(0) `OPT` pseudo-record ([RFC 6891]).
+///
+/// This only holds the record's options. The UDP payload size, extended RCODE, version and flags
+/// are stored in the CLASS and TTL fields of the record, and can be read with
+/// [`ResourceRecord::edns_header`]. To add an `OPT` record to a message, use
+/// [`MessageEncoder::edns`], which takes care of those fields.
+///
+/// [RFC 6891]: https://datatracker.ietf.org/doc/html/rfc6891
+/// [`ResourceRecord::edns_header`]: super::decoder::ResourceRecord::edns_header
+/// [`MessageEncoder::edns`]: super::encoder::MessageEncoder::edns
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Default)]
+pub struct OPT<'a> {
+    options: Cow<'a, [u8]>,
+}
+
+impl<'a> RecordData<'a> for OPT<'a> {
+    const TYPE: Type = Type::OPT;
+
+    fn encode(&self, enc: &mut Encoder<'_>) {
+        enc.w.write_slice(&self.options);
+    }
+
+    fn decode(dec: &mut Decoder<'a>) -> Result<Self, Error> {
+        let options = dec.r.read_slice(dec.r.buf().len())?;
+        for option in EdnsOptions::new(options) {
+            option?;
+        }
+        Ok(Self {
+            options: options.into(),
+        })
+    }
+}
+
+impl<'a> OPT<'a> {
+    /// Creates an [`OPT`] record without any options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends an option to this record.
+    pub fn push_option(&mut self, option: EdnsOption<'_>) {
+        let options = self.options.to_mut();
+        options.extend_from_slice(&option.code().0.to_be_bytes());
+        options.extend_from_slice(&(option.data().len() as u16).to_be_bytes());
+        options.extend_from_slice(option.data());
+    }
+
+    /// Returns an iterator over the options in this record.
+    pub fn options(&self) -> EdnsOptions<'_> {
+        EdnsOptions::new(&self.options)
+    }
+
+    /// Converts this record into one that owns all of its data.
+    pub fn into_owned(self) -> OPT<'static> {
+        OPT {
+            options: Cow::Owned(self.options.into_owned()),
+        }
+    }
+}
+
+impl<'a> fmt::Display for OPT<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, option) in self.options().enumerate() {
+            if i != 0 {
+                f.write_str(" ")?;
+            }
+            match option {
+                Ok(option) => write!(f, "{}", option)?,
+                Err(e) => write!(f, "{}", e)?,
+            }
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 #[allow(const_item_mutation)]
 mod tests {
@@ -737,25 +1792,131 @@ mod tests {
 
     const BUF: [u8; 256] = [0; 256];
 
+    fn encode<'a, R: RecordData<'a>>(rr: &R, buf: &'a mut [u8]) -> &'a [u8] {
+        let mut enc = Encoder {
+            w: Writer::new(buf),
+        };
+        rr.encode(&mut enc);
+        let pos = enc.w.pos;
+        &buf[..pos]
+    }
+
+    #[test]
+    fn decode_into() {
+        let mut bufs = [[0; 64]; 3];
+        let [a, b, c] = &mut bufs;
+        let a = encode(&SRV::new(1, 2, 80, domain("one.local")), a);
+        let b = encode(
+            &SRV::new(3, 4, 443, domain("a.much.longer.name.than.before.local")),
+            b,
+        );
+        let c = encode(&TXT::new([&b"x=1"[..], b"y"]), c);
+
+        let mut srv = SRV::new(0, 0, 0, Cow::Owned(DomainName::ROOT));
+        for (data, expected) in [
+            (a, SRV::new(1, 2, 80, domain("one.local"))),
+            (
+                b,
+                SRV::new(3, 4, 443, domain("a.much.longer.name.than.before.local")),
+            ),
+            (a, SRV::new(1, 2, 80, domain("one.local"))),
+        ] {
+            srv.decode_into(&mut Decoder {
+                r: Reader::new(data),
+            })
+            .unwrap();
+            assert_eq!(srv, expected);
+        }
+
+        let mut txt = TXT::new([&b"old"[..], b"entries", b"here"]);
+        txt.decode_into(&mut Decoder { r: Reader::new(c) }).unwrap();
+        assert_eq!(txt, TXT::new([&b"x=1"[..], b"y"]));
+        let entries = TXT::entries_iter(c).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(entries, [&b"x=1"[..], b"y"]);
+
+        let mut ptr = PTR::new(domain("a.b"));
+        assert_eq!(
+            ptr.decode_into(&mut Decoder {
+                r: Reader::new(&a[6..7])
+            }),
+            Err(Error::Eof)
+        );
+    }
+
     fn domain(s: &str) -> DomainName {
         s.parse().unwrap()
     }
 
+    #[test]
+    fn svcb() {
+        let mut svcb = SVCB::new(1, domain("svc.example.com"));
+        svcb.set_param(SvcParamKey::PORT, &8443u16.to_be_bytes());
+        svcb.set_param(SvcParamKey::ALPN, b"\x02h2\x02h3");
+        svcb.set_param(SvcParamKey::IPV4HINT, &[192, 0, 2, 1, 192, 0, 2, 2]);
+        svcb.set_param(SvcParamKey::MANDATORY, &[0, 1]);
+        svcb.set_param(SvcParamKey::PORT, &443u16.to_be_bytes());
+        assert_eq!(
+            svcb.params().map(|(key, _)| key).collect::<Vec<_>>(),
+            [
+                SvcParamKey::MANDATORY,
+                SvcParamKey::ALPN,
+                SvcParamKey::PORT,
+                SvcParamKey::IPV4HINT
+            ],
+        );
+        assert_eq!(svcb.port(), Some(443));
+        assert_eq!(svcb.alpn().collect::<Vec<_>>(), [&b"h2"[..], b"h3"]);
+        assert_eq!(svcb.mandatory().collect::<Vec<_>>(), [SvcParamKey::ALPN]);
+        assert_eq!(svcb.ipv4_hints().count(), 2);
+        assert!(!svcb.no_default_alpn());
+        assert_eq!(svcb.ech(), None);
+        assert_eq!(
+            svcb.to_string(),
+            "1\tsvc.example.com.\tmandatory=alpn\talpn=h2,h3\tport=443\tipv4hint=192.0.2.1,192.0.2.2",
+        );
+        roundtrip(svcb.clone(), &mut BUF);
+
+        let mut https = HTTPS::new(0, domain("alias.example.com"));
+        assert!(https.is_alias());
+        https.set_param(SvcParamKey::NO_DEFAULT_ALPN, &[]);
+        assert!(https.no_default_alpn());
+        roundtrip(https, &mut BUF);
+
+        // Keys must be in strictly ascending order.
+        let mut buf = BUF;
+        let data = encode(&svcb, &mut buf);
+        let mut swapped = data.to_vec();
+        let params = data.len() - (4 + 2) - (4 + 6) - (4 + 2) - (4 + 8);
+        swapped[params..params + 2].copy_from_slice(&7u16.to_be_bytes());
+        assert_eq!(
+            SVCB::decode(&mut Decoder {
+                r: Reader::new(&swapped)
+            }),
+            Err(Error::InvalidValue),
+        );
+        assert_eq!(
+            SVCB::decode(&mut Decoder {
+                r: Reader::new(&data[..data.len() - 1])
+            }),
+            Err(Error::Eof),
+        );
+    }
+
     #[test]
     fn test_roundtrip() {
         roundtrip(A::new(Ipv4Addr::new(9, 4, 78, 210)), &mut BUF);
         roundtrip(AAAA::new(Ipv6Addr::LOCALHOST), &mut BUF);
-        roundtrip(CNAME::new(&domain("a.b.c")), &mut BUF);
-        roundtrip(MX::new(123, &domain("a.b.c")), &mut BUF);
-        roundtrip(NS::new(&domain("a.b.c")), &mut BUF);
-        roundtrip(PTR::new(&domain("a.b.c")), &mut BUF);
+        roundtrip(CNAME::new(domain("a.b.c")), &mut BUF);
+        roundtrip(MX::new(123, domain("a.b.c")), &mut BUF);
+        roundtrip(NS::new(domain("a.b.c")), &mut BUF);
+        roundtrip(PTR::new(domain("a.b.c")), &mut BUF);
         roundtrip(TXT::new([&b"abc"[..]]), &mut BUF);
         roundtrip(TXT::new([&b"abc"[..], &[], &b"def"[..]]), &mut BUF);
-        roundtrip(SRV::new(123, 456, 8080, &domain("a.b.c")), &mut BUF);
+        roundtrip(SRV::new(123, 456, 8080, domain("a.b.c")), &mut BUF);
         roundtrip(
             SOA::new(
-                &domain("m.name"),
-                &domain("r.name"),
+                domain("m.name"),
+                domain("r.name"),
                 999999,
                 888888,
                 777777,
@@ -764,6 +1925,60 @@ mod tests {
             ),
             &mut BUF,
         );
+        roundtrip(
+            DNSKEY::new(DNSKEY::ZONE_KEY, Algorithm::ED25519, &[1, 2, 3][..]),
+            &mut BUF,
+        );
+        roundtrip(
+            RRSIG::new(
+                Type::A,
+                Algorithm::ED25519,
+                2,
+                3600,
+                Validity::new(100, 200),
+                12345,
+                domain("a.b"),
+                &[4, 5, 6][..],
+            ),
+            &mut BUF,
+        );
+        roundtrip(HINFO::new(&b"x86_64"[..], &b"Linux"[..]), &mut BUF);
+        roundtrip(HINFO::rfc8482(), &mut BUF);
+        roundtrip(NSEC::new(domain("a.b.c"), []), &mut BUF);
+        roundtrip(
+            NSEC::new(
+                domain("a.b.c"),
+                [
+                    Type::A,
+                    Type::MX,
+                    Type::RRSIG,
+                    Type::NSEC,
+                    Type::CAA,
+                    Type::DLV,
+                ],
+            ),
+            &mut BUF,
+        );
+    }
+
+    #[test]
+    fn test_nsec_bitmap() {
+        // Example from RFC 4034, section 4.3.
+        let nsec = NSEC::new(
+            domain("host.example.com"),
+            [Type::A, Type::MX, Type::RRSIG, Type::NSEC, Type(1234)],
+        );
+        let rdata = Record::NSEC(nsec).encode_to_vec();
+        assert_eq!(
+            &rdata[18..],
+            &[
+                0x00, 0x06, 0x40, 0x01, 0x00, 0x00, 0x00, 0x03, //
+                0x04, 0x1b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+                0x00, 0x00, 0x00, 0x00, 0x20,
+            ]
+        );
     }
 
     #[test]
@@ -772,4 +1987,21 @@ mod tests {
             rec
         }
     }
+
+    #[test]
+    fn test_record_eq_hash() {
+        use std::collections::HashSet;
+
+        let name = domain("a.b.c");
+        let borrowed = Record::SRV(SRV::new(0, 0, 80, &name));
+        let owned = Record::SRV(SRV::new(0, 0, 80, name.clone()));
+        assert_eq!(borrowed, owned);
+        assert_ne!(borrowed, Record::SRV(SRV::new(0, 0, 81, &name)));
+        assert_ne!(borrowed, Record::PTR(PTR::new(&name)));
+
+        let set = [borrowed, owned, Record::PTR(PTR::new(&name))]
+            .into_iter()
+            .collect::<HashSet<_>>();
+        assert_eq!(set.len(), 2);
+    }
 }
@@ -0,0 +1,347 @@
+//! Re-encoding of DNS messages with transformations applied.
+//!
+//! Forwarding a message to another network is not always as simple as copying its bytes. An mDNS
+//! reflector might have to drop EDNS(0) options that only make sense on the original link, and a
+//! gateway translating between `.local` and a unicast zone has to rename every record it passes
+//! on. [`Rewriter`] decodes a message and encodes it again with those changes applied.
+//!
+//! Note that rewriting names or TTLs invalidates any DNSSEC signatures in the message.
+
+use std::{borrow::Cow, mem::size_of};
+
+use crate::{name::DomainName, Error};
+
+use super::{
+    decoder::{self, MessageDecoder},
+    encoder::{MessageEncoder, Question, ResourceRecord},
+    records::{Record, CNAME, MX, NS, PTR, SOA, SRV},
+    section::Section,
+    Class, Header, Type,
+};
+
+/// Re-encodes DNS messages, transforming their contents along the way.
+///
+/// A new [`Rewriter`] copies messages unchanged (apart from re-doing name compression). The
+/// `set_*` methods enable the individual transformations.
+#[derive(Debug, Clone, Default)]
+pub struct Rewriter {
+    strip_edns: bool,
+    min_ttl: Option<u32>,
+    max_ttl: Option<u32>,
+    clear_cache_flush: bool,
+    suffix: Option<(DomainName, DomainName)>,
+}
+
+impl Rewriter {
+    /// Creates a [`Rewriter`] that doesn't apply any transformations.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether EDNS(0) `OPT` records are removed from messages.
+    pub fn set_strip_edns(&mut self, strip_edns: bool) {
+        self.strip_edns = strip_edns;
+    }
+
+    /// Sets the smallest TTL (in seconds) that records in rewritten messages may have.
+    ///
+    /// Records with a lower TTL get their TTL raised to `min_ttl`. TTLs of 0 (mDNS goodbye
+    /// packets) are kept as-is. [`None`] keeps all TTLs, which is the default.
+    ///
+    /// If `min_ttl` is larger than the [maximum TTL](Rewriter::set_max_ttl), the maximum wins.
+    pub fn set_min_ttl(&mut self, min_ttl: Option<u32>) {
+        self.min_ttl = min_ttl;
+    }
+
+    /// Sets the largest TTL (in seconds) that records in rewritten messages may have.
+    ///
+    /// Records with a higher TTL get their TTL lowered to `max_ttl`. TTLs of 0 (mDNS goodbye
+    /// packets) are kept as-is. [`None`] keeps all TTLs, which is the default.
+    pub fn set_max_ttl(&mut self, max_ttl: Option<u32>) {
+        self.max_ttl = max_ttl;
+    }
+
+    /// Sets whether the mDNS cache-flush bit is cleared in all records.
+    ///
+    /// Records that are forwarded from another network should not cause caches to flush the
+    /// records they already have from the local network.
+    pub fn set_clear_cache_flush(&mut self, clear_cache_flush: bool) {
+        self.clear_cache_flush = clear_cache_flush;
+    }
+
+    /// Replaces the domain suffix `from` with `to` in all names.
+    ///
+    /// This applies to the questions, to the owner names of all records, and to the names in the
+    /// record data of `CNAME`, `NS`, `PTR`, `MX`, `SOA` and `SRV` records. Suffixes are matched
+    /// ignoring ASCII case.
+    pub fn set_suffix_translation(&mut self, from: DomainName, to: DomainName) {
+        self.suffix = Some((from, to));
+    }
+
+    /// Rewrites `packet` into `buf`, and returns the length of the rewritten message.
+    ///
+    /// The header is copied, except for the section counts. If the rewritten message doesn't fit
+    /// into `buf`, [`Error::Truncated`] is returned.
+    pub fn rewrite(&self, packet: &[u8], buf: &mut [u8]) -> Result<usize, Error> {
+        let mut dec = MessageDecoder::new(packet)?;
+        let header = *dec.header();
+
+        let mut enc = MessageEncoder::new(&mut *buf);
+        enc.set_header(header);
+        for q in dec.iter() {
+            let q = q?;
+            let name = self.translate(q.qname());
+            enc.question(
+                Question::new(&name)
+                    .ty(q.qtype())
+                    .class(q.qclass())
+                    .prefer_unicast(q.prefers_unicast()),
+            );
+        }
+
+        let mut dec = dec.answers()?;
+        let mut enc = enc.answers();
+        self.copy_records(dec.iter(), &mut enc)?;
+        let mut dec = dec.authority()?;
+        let mut enc = enc.authority();
+        self.copy_records(dec.iter(), &mut enc)?;
+        let mut dec = dec.additional()?;
+        let mut enc = enc.additional();
+        self.copy_records(dec.iter(), &mut enc)?;
+        let len = enc.finish()?;
+
+        // The encoder only sets the TC bit when *it* truncates the message, but mDNS queries use
+        // it to announce more known answers.
+        if header.is_truncated() {
+            let h: &mut Header = bytemuck::from_bytes_mut(&mut buf[..size_of::<Header>()]);
+            h.set_truncated(true);
+        }
+        Ok(len)
+    }
+
+    fn copy_records<'a, S: Section>(
+        &self,
+        records: impl Iterator<Item = Result<decoder::ResourceRecord<'a>, Error>>,
+        enc: &mut MessageEncoder<'_, S>,
+    ) -> Result<(), Error> {
+        for rr in records {
+            let rr = rr?;
+            if let Some(header) = rr.edns_header() {
+                if !self.strip_edns {
+                    let size = header.udp_payload_size();
+                    enc.add_raw(rr.name(), Type::OPT, size, rr.ttl(), rr.rdata());
+                }
+                continue;
+            }
+
+            let name = self.translate(rr.name());
+            let ttl = self.clamp_ttl(rr.ttl());
+            let mut class = rr.class().0;
+            if rr.cache_flush() && !self.clear_cache_flush {
+                class |= 0x8000;
+            }
+            match rr.as_enum() {
+                Some(record) => {
+                    let record = self.translate_record(record?);
+                    enc.add_record(
+                        ResourceRecord::new(&name, &record)
+                            .class(Class(class))
+                            .ttl(ttl),
+                    );
+                }
+                // Unknown record types can't contain compressed names (RFC 3597), so their data
+                // can be copied verbatim.
+                None => enc.add_raw(&name, rr.type_(), class, ttl, rr.rdata()),
+            }
+        }
+        Ok(())
+    }
+
+    fn clamp_ttl(&self, mut ttl: u32) -> u32 {
+        if ttl == 0 {
+            return 0;
+        }
+        if let Some(min) = self.min_ttl {
+            ttl = ttl.max(min);
+        }
+        if let Some(max) = self.max_ttl {
+            ttl = ttl.min(max);
+        }
+        ttl
+    }
+
+    fn translate<'n>(&self, name: &'n DomainName) -> Cow<'n, DomainName> {
+        let Some((from, to)) = &self.suffix else {
+            return Cow::Borrowed(name);
+        };
+        let labels = name.labels();
+        let Some(split) = labels.len().checked_sub(from.labels().len()) else {
+            return Cow::Borrowed(name);
+        };
+        let matches = labels[split..]
+            .iter()
+            .zip(from.labels())
+            .all(|(a, b)| a.eq_ignore_ascii_case(b));
+        if !matches {
+            return Cow::Borrowed(name);
+        }
+        let mut translated = DomainName::from_iter(&labels[..split]);
+        translated.extend(to);
+        Cow::Owned(translated)
+    }
+
+    fn translate_record<'r>(&self, record: Record<'r>) -> Record<'r> {
+        if self.suffix.is_none() {
+            return record;
+        }
+        let t = |name: &DomainName| self.translate(name).into_owned();
+        match record {
+            Record::CNAME(r) => Record::CNAME(CNAME::new(t(r.cname()))),
+            Record::NS(r) => Record::NS(NS::new(t(r.nsdname()))),
+            Record::PTR(r) => Record::PTR(PTR::new(t(r.ptrdname()))),
+            Record::MX(r) => Record::MX(MX::new(r.preference(), t(r.exchange()))),
+            Record::SRV(r) => {
+                Record::SRV(SRV::new(r.priority(), r.weight(), r.port(), t(r.target())))
+            }
+            Record::SOA(r) => Record::SOA(SOA::new(
+                t(r.mname()),
+                t(r.rname()),
+                r.serial(),
+                r.refresh(),
+                r.retry(),
+                r.expire(),
+                r.minimum_ttl(),
+            )),
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write;
+
+    use expect_test::{expect, Expect};
+
+    use crate::{
+        domain,
+        packet::{records::A, QType},
+    };
+
+    use super::*;
+
+    fn packet(truncated: bool) -> Vec<u8> {
+        let service = domain!("_http._tcp.local");
+        let instance = domain!("printer._http._tcp.LOCAL");
+        let host = domain!("printer.local");
+        let ptr = Record::PTR(PTR::new(&instance));
+        let srv = Record::SRV(SRV::new(0, 0, 631, &host));
+        let a = Record::A(A::new([192, 168, 1, 20].into()));
+
+        let mut buf = vec![0; 512];
+        let mut enc = MessageEncoder::new(&mut buf);
+        let mut header = Header::default();
+        header.set_response(true);
+        enc.set_header(header);
+        enc.question(Question::new(&service).ty(QType::PTR).prefer_unicast(true));
+        let mut enc = enc.answers();
+        enc.add_answer(ResourceRecord::new(&service, &ptr).ttl(4500));
+        let mut enc = enc.authority().additional();
+        let cache_flush = Class(Class::IN.0 | 0x8000);
+        enc.add_additional(
+            ResourceRecord::new(&instance, &srv)
+                .class(cache_flush)
+                .ttl(120),
+        );
+        enc.add_additional(ResourceRecord::new(&host, &a).class(cache_flush).ttl(0));
+        enc.add_raw(&host, Type(0xff00), 1, 120, &[1, 2, 3]);
+        enc.edns(1440).set_dnssec_ok(true);
+        let len = enc.finish().unwrap();
+        buf.truncate(len);
+        let h: &mut Header = bytemuck::from_bytes_mut(&mut buf[..size_of::<Header>()]);
+        h.set_truncated(truncated);
+        buf
+    }
+
+    fn check(rewriter: &Rewriter, packet: &[u8], expect: Expect) {
+        let mut buf = [0; 512];
+        let len = rewriter.rewrite(packet, &mut buf).unwrap();
+        let dec = MessageDecoder::new(&buf[..len]).unwrap();
+        let mut out = String::new();
+        dec.format(|args| writeln!(out, "{}", args).unwrap())
+            .unwrap();
+        expect.assert_eq(&out);
+    }
+
+    fn cache_flush_bits(packet: &[u8]) -> Vec<bool> {
+        let mut dec = MessageDecoder::new(packet).unwrap().additional().unwrap();
+        dec.iter().map(|rr| rr.unwrap().cache_flush()).collect()
+    }
+
+    #[test]
+    fn unchanged() {
+        let packet = packet(false);
+        let mut buf = [0; 512];
+        let len = Rewriter::new().rewrite(&packet, &mut buf).unwrap();
+        assert_eq!(&buf[..len], &packet[..]);
+    }
+
+    #[test]
+    fn transformations() {
+        let packet = packet(true);
+        let mut rewriter = Rewriter::new();
+        rewriter.set_strip_edns(true);
+        rewriter.set_max_ttl(Some(60));
+        rewriter.set_clear_cache_flush(true);
+        rewriter.set_suffix_translation(domain!("local"), domain!("lan.example.com"));
+        check(
+            &rewriter,
+            &packet,
+            expect![[r#"
+                response (id=0, op=QUERY, rcode=NO_ERROR, trunc)
+                Q: _http._tcp.lan.example.com.	IN	PTR
+                ANS: _http._tcp.lan.example.com.	60	IN	PTR	printer._http._tcp.lan.example.com.
+                ADDL: printer._http._tcp.lan.example.com.	60	IN	SRV	0	0	631	printer.lan.example.com.
+                ADDL: printer.lan.example.com.	0	IN	A	192.168.1.20
+                ADDL: printer.lan.example.com.	60	IN	(unknown Type: 0xff00)	[01, 02, 03]
+            "#]],
+        );
+
+        let mut buf = [0; 512];
+        let len = rewriter.rewrite(&packet, &mut buf).unwrap();
+        let mut dec = MessageDecoder::new(&buf[..len]).unwrap();
+        assert!(dec.header().is_truncated());
+        assert!(dec.next().unwrap().unwrap().prefers_unicast());
+        assert_eq!(cache_flush_bits(&buf[..len]), [false, false, false]);
+
+        // Raising TTLs keeps the goodbye record's TTL of 0.
+        let mut rewriter = Rewriter::new();
+        rewriter.set_min_ttl(Some(300));
+        rewriter.set_max_ttl(Some(3600));
+        let len = rewriter.rewrite(&packet, &mut buf).unwrap();
+        let dec = MessageDecoder::new(&buf[..len]).unwrap().answers().unwrap();
+        let mut dec = dec.authority().unwrap().additional().unwrap();
+        let ttls = dec.iter().map(|rr| rr.unwrap().ttl()).collect::<Vec<_>>();
+        assert_eq!(ttls[..3], [300, 0, 300]);
+
+        // Only the cache-flush bits.
+        let mut rewriter = Rewriter::new();
+        rewriter.set_clear_cache_flush(true);
+        assert_eq!(cache_flush_bits(&packet), [true, true, false, false]);
+        let len = rewriter.rewrite(&packet, &mut buf).unwrap();
+        assert_eq!(cache_flush_bits(&buf[..len]), [false, false, false, false]);
+        let mut dec = MessageDecoder::new(&buf[..len])
+            .unwrap()
+            .additional()
+            .unwrap();
+        let opt = dec.iter().last().unwrap().unwrap();
+        let header = opt.edns_header().unwrap();
+        assert_eq!(header.udp_payload_size(), 1440);
+        assert!(header.dnssec_ok());
+
+        assert_eq!(
+            rewriter.rewrite(&packet, &mut buf[..40]),
+            Err(Error::Truncated)
+        );
+    }
+}
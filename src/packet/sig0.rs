@@ -0,0 +1,391 @@
+//! SIG(0) transaction signatures ([RFC 2931]).
+//!
+//! SIG(0) authenticates a whole DNS message (typically a dynamic update and its response) with a
+//! public-key signature, carried in a `SIG` record appended to the *Additional Records* section.
+//! Unlike TSIG, no shared secret is needed: the signer's public key is published as a `KEY` record
+//! under the signer's name.
+//!
+//! This module only implements the message format. The cryptography is supplied by the user through
+//! the [`Signer`] and [`Verifier`] traits, using the DNSSEC algorithm identified by [`Algorithm`].
+
+//!
+//! [RFC 2931]: https://datatracker.ietf.org/doc/html/rfc2931
+
+use std::{mem::size_of, time::SystemTime};
+
+use crate::{name::DomainName, Error};
+
+use super::{
+    decoder::{MessageDecoder, Reader},
+    dnssec::{Algorithm, Signer, Validity},
+    encoder::Writer,
+    Header, QClass, Type,
+};
+
+/// Checks SIG(0) signatures against known public keys.
+pub trait Verifier {
+    /// Returns whether `sig` is a valid signature of `data`.
+    ///
+    /// The key to use is identified by [`Sig0::signer_name`], [`Sig0::algorithm`] and
+    /// [`Sig0::key_tag`]. If no such key is known, this should return `false`.
+    fn verify(&self, sig: &Sig0<'_>, data: &[u8]) -> bool;
+}
+
+/// The RDATA of a SIG(0) record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sig0<'a> {
+    algorithm: Algorithm,
+    validity: Validity,
+    key_tag: u16,
+    signer_name: DomainName,
+    signature: &'a [u8],
+}
+
+impl<'a> Sig0<'a> {
+    /// Decodes a `SIG` record's RDATA.
+    ///
+    /// Returns `None` if the record has a non-zero *type covered* field, ie. if it is a `SIG`
+    /// record covering an RRset rather than a SIG(0) transaction signature.
+    fn decode(r: &Reader<'a>) -> Result<Option<Self>, Error> {
+        let type_covered = r.read_u16()?;
+        let algorithm = Algorithm(r.read_u8()?);
+        let _labels = r.read_u8()?;
+        let _original_ttl = r.read_u32()?;
+        let expiration = r.read_u32()?;
+        let inception = r.read_u32()?;
+        let key_tag = r.read_u16()?;
+        let signer_name = r.read_domain_name()?;
+        let signature = r.buf();
+        if type_covered != 0 {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            algorithm,
+            validity: Validity::new(inception, expiration),
+            key_tag,
+            signer_name,
+            signature,
+        }))
+    }
+
+    /// Encodes the RDATA, minus the signature.
+    ///
+    /// This is the part of the RDATA that is covered by the signature itself.
+    fn unsigned_rdata(
+        algorithm: Algorithm,
+        validity: Validity,
+        key_tag: u16,
+        signer_name: &DomainName,
+    ) -> Vec<u8> {
+        let write = |w: &mut Writer<'_>| {
+            w.write_u16(0); // type covered
+            w.write_u8(algorithm.0);
+            w.write_u8(0); // labels
+            w.write_u32(0); // original TTL
+            w.write_u32(validity.expiration());
+            w.write_u32(validity.inception());
+            w.write_u16(key_tag);
+            w.write_domain_name(signer_name);
+        };
+        let mut w = Writer::measuring();
+        write(&mut w);
+        let mut rdata = vec![0; w.pos];
+        write(&mut Writer::new(&mut rdata));
+        rdata
+    }
+
+    /// Returns the algorithm of the signing key.
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Returns the period during which the signature is valid.
+    pub fn validity(&self) -> Validity {
+        self.validity
+    }
+
+    /// Returns the key tag of the signing key.
+    pub fn key_tag(&self) -> u16 {
+        self.key_tag
+    }
+
+    /// Returns the name of the `KEY` record holding the signer's public key.
+    pub fn signer_name(&self) -> &DomainName {
+        &self.signer_name
+    }
+
+    /// Returns the raw signature.
+    pub fn signature(&self) -> &'a [u8] {
+        self.signature
+    }
+}
+
+/// Assembles the data covered by a SIG(0) signature.
+///
+/// `message` is the message without the `SIG` record (but with its ARCOUNT still including it).
+fn signed_data(unsigned_rdata: &[u8], request: Option<&[u8]>, message: &[u8]) -> Vec<u8> {
+    let mut data =
+        Vec::with_capacity(unsigned_rdata.len() + request.map_or(0, |r| r.len()) + message.len());
+    data.extend_from_slice(unsigned_rdata);
+    data.extend_from_slice(request.unwrap_or_default());
+    let start = data.len();
+    data.extend_from_slice(message);
+    let header: &mut Header = bytemuck::from_bytes_mut(&mut data[start..][..size_of::<Header>()]);
+    header.set_arcount(header.additional_count() - 1);
+    data
+}
+
+/// Signs the message in `buf[..len]` by appending a SIG(0) record to it.
+///
+/// When signing a response to a signed request, the complete request (including its SIG(0) record)
+/// must be passed as `request`, so that the signature binds the response to it.
+///
+/// Returns the length of the signed message. If `buf` is too small to hold the signature,
+/// [`Error::Truncated`] is returned and `buf` is left unchanged.
+///
+/// # Panics
+///
+/// This function will panic if `len` is larger than `buf.len()`.
+pub fn sign<S: Signer + ?Sized>(
+    buf: &mut [u8],
+    len: usize,
+    request: Option<&[u8]>,
+    signer: &S,
+    validity: Validity,
+) -> Result<usize, Error> {
+    let (message, rest) = buf.split_at_mut(len);
+    if message.len() < size_of::<Header>() {
+        return Err(Error::Eof);
+    }
+    let header: &mut Header = bytemuck::from_bytes_mut(&mut message[..size_of::<Header>()]);
+    let arcount = header
+        .additional_count()
+        .checked_add(1)
+        .ok_or(Error::InvalidValue)?;
+    header.set_arcount(arcount);
+
+    let unsigned_rdata = Sig0::unsigned_rdata(
+        signer.algorithm(),
+        validity,
+        signer.key_tag(),
+        signer.signer_name(),
+    );
+    let signature = signer.sign(&signed_data(&unsigned_rdata, request, message));
+
+    let res = signature.and_then(|signature| {
+        let rdlength = u16::try_from(unsigned_rdata.len() + signature.len())
+            .map_err(|_| Error::InvalidValue)?;
+        let mut w = Writer::new(rest);
+        w.write_u8(0); // root domain
+        w.write_u16(Type::SIG.0);
+        w.write_u16(QClass::ANY.0);
+        w.write_u32(0);
+        w.write_u16(rdlength);
+        w.write_slice(&unsigned_rdata);
+        w.write_slice(&signature);
+        if w.is_truncated() {
+            Err(Error::Truncated)
+        } else {
+            Ok(len + w.pos)
+        }
+    });
+    if res.is_err() {
+        let header: &mut Header = bytemuck::from_bytes_mut(&mut buf[..size_of::<Header>()]);
+        header.set_arcount(arcount - 1);
+    }
+    res
+}
+
+/// Verifies the SIG(0) signature of `message`.
+///
+/// `request` has to be set to the complete request when verifying the response to a signed request
+/// (see [`sign`]).
+///
+/// Returns `Ok(None)` if the message is not signed, ie. if the last record in its *Additional
+/// Records* section is not a SIG(0) record. If the message is signed, but the signature is invalid
+/// or not valid at time `now`, [`Error::InvalidValue`] is returned.
+pub fn verify<'a, V: Verifier + ?Sized>(
+    message: &'a [u8],
+    request: Option<&[u8]>,
+    verifier: &V,
+    now: SystemTime,
+) -> Result<Option<Sig0<'a>>, Error> {
+    let mut dec = MessageDecoder::new(message)?;
+    if dec.header().additional_count() == 0 {
+        return Ok(None);
+    }
+    for q in dec.iter() {
+        q?;
+    }
+    let mut dec = dec.answers()?;
+    for rr in dec.iter() {
+        rr?;
+    }
+    let mut dec = dec.authority()?;
+    for rr in dec.iter() {
+        rr?;
+    }
+    let mut dec = dec.additional()?;
+    let mut last = None;
+    loop {
+        let start = message.len() - dec.remaining_len();
+        match dec.iter().next() {
+            Some(rr) => last = Some((start, rr?)),
+            None => break,
+        }
+    }
+
+    let Some((start, rr)) = last else {
+        return Ok(None);
+    };
+    if rr.type_() != Type::SIG {
+        return Ok(None);
+    }
+    let Some(sig) = Sig0::decode(&rr.rdata)? else {
+        return Ok(None);
+    };
+    if !rr.name().labels().is_empty() || rr.class().0 != QClass::ANY.0 {
+        return Err(Error::InvalidValue);
+    }
+
+    let unsigned_rdata =
+        Sig0::unsigned_rdata(sig.algorithm, sig.validity, sig.key_tag, &sig.signer_name);
+    let data = signed_data(&unsigned_rdata, request, &message[..start]);
+    if !sig.validity.contains(now) || !verifier.verify(&sig, &data) {
+        return Err(Error::InvalidValue);
+    }
+    Ok(Some(sig))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::encoder::{MessageEncoder, Question};
+
+    use std::time::Duration;
+
+    use super::*;
+
+    /// A "signature" scheme where the signature is a checksum of the key and the data.
+    struct Checksum {
+        name: DomainName,
+        key: u8,
+    }
+
+    impl Checksum {
+        fn checksum(key: u8, data: &[u8]) -> Vec<u8> {
+            let sum = data
+                .iter()
+                .fold(u32::from(key), |acc, &b| acc.rotate_left(5) ^ u32::from(b));
+            sum.to_be_bytes().to_vec()
+        }
+    }
+
+    impl Signer for Checksum {
+        fn algorithm(&self) -> Algorithm {
+            Algorithm::PRIVATEDNS
+        }
+
+        fn key_tag(&self) -> u16 {
+            u16::from(self.key)
+        }
+
+        fn signer_name(&self) -> &DomainName {
+            &self.name
+        }
+
+        fn sign(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+            Ok(Self::checksum(self.key, data))
+        }
+    }
+
+    impl Verifier for Checksum {
+        fn verify(&self, sig: &Sig0<'_>, data: &[u8]) -> bool {
+            sig.signer_name() == &self.name
+                && sig.key_tag() == u16::from(self.key)
+                && sig.signature() == Self::checksum(self.key, data)
+        }
+    }
+
+    fn message(buf: &mut [u8]) -> usize {
+        let name = DomainName::from_str("example.com.").unwrap();
+        let mut enc = MessageEncoder::new(buf);
+        enc.question(Question::new(&name));
+        enc.finish().unwrap()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let key = Checksum {
+            name: DomainName::from_str("key.example.com.").unwrap(),
+            key: 42,
+        };
+        let now = SystemTime::now();
+        let validity = Validity::starting_at(now, Duration::from_secs(60));
+
+        let mut request = [0; 512];
+        let len = message(&mut request);
+        assert_eq!(verify(&request[..len], None, &key, now), Ok(None));
+        let len = sign(&mut request, len, None, &key, validity).unwrap();
+        let request = &request[..len];
+        let sig = verify(request, None, &key, now).unwrap().unwrap();
+        assert_eq!(sig.algorithm(), Algorithm::PRIVATEDNS);
+        assert_eq!(sig.key_tag(), 42);
+        assert_eq!(sig.signer_name(), &key.name);
+        assert_eq!(sig.validity(), validity);
+        assert_eq!(
+            MessageDecoder::new(request)
+                .unwrap()
+                .header()
+                .additional_count(),
+            1
+        );
+
+        // Expired or not yet valid.
+        let later = now + Duration::from_secs(61);
+        assert_eq!(verify(request, None, &key, later), Err(Error::InvalidValue));
+        let earlier = now - Duration::from_secs(301);
+        assert_eq!(
+            verify(request, None, &key, earlier),
+            Err(Error::InvalidValue)
+        );
+
+        // Wrong key.
+        let other = Checksum {
+            name: key.name.clone(),
+            key: 43,
+        };
+        assert_eq!(verify(request, None, &other, now), Err(Error::InvalidValue));
+
+        // Tampered message.
+        let mut tampered = request.to_vec();
+        tampered[1] ^= 1;
+        assert_eq!(verify(&tampered, None, &key, now), Err(Error::InvalidValue));
+
+        // The response signature covers the request.
+        let mut response = [0; 512];
+        let len = message(&mut response);
+        let len = sign(&mut response, len, Some(request), &key, validity).unwrap();
+        let response = &response[..len];
+        assert!(verify(response, Some(request), &key, now)
+            .unwrap()
+            .is_some());
+        assert_eq!(verify(response, None, &key, now), Err(Error::InvalidValue));
+    }
+
+    #[test]
+    fn sign_truncated() {
+        let key = Checksum {
+            name: DomainName::from_str("key.example.com.").unwrap(),
+            key: 1,
+        };
+        let mut buf = [0; 40];
+        let len = message(&mut buf);
+        let orig = buf;
+        let validity = Validity::new(0, 100);
+        assert_eq!(
+            sign(&mut buf, len, None, &key, validity),
+            Err(Error::Truncated)
+        );
+        assert_eq!(buf[..len], orig[..len]);
+    }
+}
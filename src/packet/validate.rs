@@ -0,0 +1,483 @@
+//! Semantic message validation.
+//!
+//! The [`MessageDecoder`] only checks that a message is well-formed on the wire. [`validate`] goes
+//! further and checks the records in a message against each other, to find mistakes that are
+//! legal to encode but indicate a buggy (or malicious) peer.
+
+use std::{collections::BTreeMap, fmt};
+
+use crate::{label, name::DomainName, Error};
+
+use super::{
+    decoder::{MessageDecoder, ResourceRecord},
+    records::Record,
+    Type,
+};
+
+/// The maximum length of an uncompressed domain name on the wire ([RFC 1035 § 2.3.4]).
+///
+/// [RFC 1035 § 2.3.4]: https://datatracker.ietf.org/doc/html/rfc1035#section-2.3.4
+const MAX_NAME_LEN: usize = 255;
+
+/// Identifies the part of a DNS message a [`Warning`] refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessagePart {
+    Header,
+    Question,
+    Answer,
+    Authority,
+    Additional,
+}
+
+impl fmt::Display for MessagePart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MessagePart::Header => "header",
+            MessagePart::Question => "question section",
+            MessagePart::Answer => "answer section",
+            MessagePart::Authority => "authority section",
+            MessagePart::Additional => "additional section",
+        })
+    }
+}
+
+/// A problem found by [`validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Warning {
+    /// The message could not be decoded past this point.
+    Malformed { part: MessagePart, error: Error },
+    /// The message ended before the number of entries announced in the header was reached.
+    CountMismatch {
+        part: MessagePart,
+        expected: u16,
+        actual: u16,
+    },
+    /// There are bytes left after the last entry of the message.
+    TrailingData { len: usize },
+    /// A domain name exceeds the maximum length of 255 bytes.
+    NameTooLong { part: MessagePart, name: DomainName },
+    /// A name owns a `CNAME` record in addition to other data ([RFC 1034 § 3.6.2]).
+    ///
+    /// [RFC 1034 § 3.6.2]: https://datatracker.ietf.org/doc/html/rfc1034#section-3.6.2
+    CnameAndOtherData { name: DomainName },
+    /// A name owns more than one `CNAME` record ([RFC 2181 § 10.1]).
+    ///
+    /// [RFC 2181 § 10.1]: https://datatracker.ietf.org/doc/html/rfc2181#section-10.1
+    MultipleCnames { name: DomainName },
+    /// A DNS-SD `PTR` record points to a service instance, but the response contains no `SRV`
+    /// record for it ([RFC 6763 § 12.1]).
+    ///
+    /// [RFC 6763 § 12.1]: https://datatracker.ietf.org/doc/html/rfc6763#section-12.1
+    MissingSrv { instance: DomainName },
+    /// A DNS-SD `PTR` record points to a service instance, but the response contains no `TXT`
+    /// record for it ([RFC 6763 § 12.1]).
+    ///
+    /// [RFC 6763 § 12.1]: https://datatracker.ietf.org/doc/html/rfc6763#section-12.1
+    MissingTxt { instance: DomainName },
+    /// An `SRV` record's target has no address records in the response ([RFC 6763 § 12.2]).
+    ///
+    /// [RFC 6763 § 12.2]: https://datatracker.ietf.org/doc/html/rfc6763#section-12.2
+    MissingAddress { target: DomainName },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::Malformed { part, error } => write!(f, "malformed {}: {}", part, error),
+            Warning::CountMismatch {
+                part,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "header announces {} entries in {}, but message only contains {}",
+                expected, part, actual
+            ),
+            Warning::TrailingData { len } => {
+                write!(f, "{} bytes of trailing data after the last record", len)
+            }
+            Warning::NameTooLong { part, name } => {
+                write!(f, "domain name in {} is too long: {}", part, name)
+            }
+            Warning::CnameAndOtherData { name } => {
+                write!(f, "{} has a CNAME record and other data", name)
+            }
+            Warning::MultipleCnames { name } => write!(f, "{} has multiple CNAME records", name),
+            Warning::MissingSrv { instance } => {
+                write!(f, "no SRV record for service instance {}", instance)
+            }
+            Warning::MissingTxt { instance } => {
+                write!(f, "no TXT record for service instance {}", instance)
+            }
+            Warning::MissingAddress { target } => {
+                write!(f, "no address records for SRV target {}", target)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Warning {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Warning::Malformed { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Checks a DNS message for consistency.
+///
+/// In addition to the structural checks performed by the [`MessageDecoder`], this checks that:
+///
+/// - the entry counts in the header match the message contents, and no data follows the last
+///   record,
+/// - domain names do not exceed the maximum length,
+/// - names with a `CNAME` record own no other data,
+/// - for responses, DNS-SD service instances referenced by `PTR` records come with `SRV` and `TXT`
+///   records, and `SRV` targets come with address records.
+///
+/// The DNS-SD checks are recommendations rather than hard requirements, so they may produce
+/// warnings for responses from perfectly compliant (but less helpful) servers.
+///
+/// Returns the list of problems found, which is empty if the message passed all checks. Decoding
+/// errors are reported as [`Warning::Malformed`], and stop the validation of the remaining message.
+pub fn validate(msg: &[u8]) -> Vec<Warning> {
+    let mut v = Validator {
+        warnings: Vec::new(),
+        records: Vec::new(),
+    };
+    match v.decode(msg) {
+        Ok(is_response) => v.check_records(is_response),
+        Err(w) => v.warnings.push(w),
+    }
+    v.warnings
+}
+
+/// The parts of a resource record the cross-record checks look at.
+struct Entry {
+    name: DomainName,
+    ty: Type,
+    /// The domain name a `PTR` or `SRV` record points to.
+    target: Option<DomainName>,
+}
+
+struct Validator {
+    warnings: Vec<Warning>,
+    records: Vec<Entry>,
+}
+
+impl Validator {
+    /// Decodes the whole message, performing the per-entry checks and collecting the records.
+    fn decode(&mut self, msg: &[u8]) -> Result<bool, Warning> {
+        let mut dec = MessageDecoder::new(msg).map_err(|error| Warning::Malformed {
+            part: MessagePart::Header,
+            error,
+        })?;
+        let h = *dec.header();
+
+        // The sections all have different decoder types, so this can't be a function.
+        macro_rules! section {
+            ($dec:ident, $part:ident, $expected:expr, |$item:ident| $body:expr) => {{
+                let mut count = 0;
+                loop {
+                    let at_end = $dec.remaining_len() == 0;
+                    match $dec.next() {
+                        None => break,
+                        Some(Ok($item)) => $body,
+                        Some(Err(e)) => {
+                            return Err(decode_error(
+                                MessagePart::$part,
+                                e,
+                                at_end,
+                                $expected,
+                                count,
+                            ))
+                        }
+                    }
+                    count += 1;
+                }
+            }};
+        }
+
+        section!(dec, Question, h.question_count(), |q| {
+            self.check_name(MessagePart::Question, q.qname());
+        });
+        let mut dec = dec.answers().unwrap();
+        section!(dec, Answer, h.answer_count(), |rr| {
+            self.record(MessagePart::Answer, &rr);
+        });
+        let mut dec = dec.authority().unwrap();
+        section!(dec, Authority, h.authoritative_count(), |rr| {
+            self.record(MessagePart::Authority, &rr);
+        });
+        let mut dec = dec.additional().unwrap();
+        section!(dec, Additional, h.additional_count(), |rr| {
+            self.record(MessagePart::Additional, &rr);
+        });
+
+        let len = dec.remaining_len();
+        if len != 0 {
+            self.warnings.push(Warning::TrailingData { len });
+        }
+        Ok(h.is_response())
+    }
+
+    fn record(&mut self, part: MessagePart, rr: &ResourceRecord<'_>) {
+        self.check_name(part, rr.name());
+        let target = match rr.as_enum() {
+            Some(Ok(Record::PTR(ptr))) => Some(ptr.ptrdname().clone()),
+            Some(Ok(Record::SRV(srv))) => Some(srv.target().clone()),
+            Some(Err(error)) => {
+                self.warnings.push(Warning::Malformed { part, error });
+                None
+            }
+            _ => None,
+        };
+        if let Some(target) = &target {
+            self.check_name(part, target);
+        }
+        self.records.push(Entry {
+            name: rr.name().clone(),
+            ty: rr.type_(),
+            target,
+        });
+    }
+
+    fn check_name(&mut self, part: MessagePart, name: &DomainName) {
+        let len = name
+            .labels()
+            .iter()
+            .map(|label| label.as_bytes().len() + 1)
+            .sum::<usize>()
+            + 1;
+        if len > MAX_NAME_LEN {
+            self.warnings.push(Warning::NameTooLong {
+                part,
+                name: name.clone(),
+            });
+        }
+    }
+
+    fn check_records(&mut self, is_response: bool) {
+        // (CNAME records, other records) per owner name.
+        let mut owners = BTreeMap::<String, (&DomainName, usize, usize)>::new();
+        for rr in &self.records {
+            // DNSSEC records are allowed next to a CNAME (RFC 4035 § 2.5).
+            if rr.ty == Type::RRSIG || rr.ty == Type::NSEC {
+                continue;
+            }
+            let entry = owners.entry(key(&rr.name)).or_insert((&rr.name, 0, 0));
+            if rr.ty == Type::CNAME {
+                entry.1 += 1;
+            } else {
+                entry.2 += 1;
+            }
+        }
+        for (name, cnames, others) in owners.into_values() {
+            if cnames > 1 {
+                self.warnings
+                    .push(Warning::MultipleCnames { name: name.clone() });
+            }
+            if cnames > 0 && others > 0 {
+                self.warnings
+                    .push(Warning::CnameAndOtherData { name: name.clone() });
+            }
+        }
+
+        if is_response {
+            self.check_dnssd();
+        }
+    }
+
+    fn check_dnssd(&mut self) {
+        let has = |name: &DomainName, types: &[Type]| {
+            self.records
+                .iter()
+                .any(|rr| types.contains(&rr.ty) && rr.name.eq_ignore_ascii_case(name))
+        };
+
+        let mut warnings = Vec::new();
+        for rr in &self.records {
+            match (rr.ty, &rr.target) {
+                (Type::PTR, Some(instance)) if is_service_instance(instance, &rr.name) => {
+                    if !has(instance, &[Type::SRV]) {
+                        warnings.push(Warning::MissingSrv {
+                            instance: instance.clone(),
+                        });
+                    }
+                    if !has(instance, &[Type::TXT]) {
+                        warnings.push(Warning::MissingTxt {
+                            instance: instance.clone(),
+                        });
+                    }
+                }
+                // A target of `.` means that the service is decidedly not available (RFC 2782).
+                (Type::SRV, Some(target))
+                    if !target.labels().is_empty() && !has(target, &[Type::A, Type::AAAA]) =>
+                {
+                    warnings.push(Warning::MissingAddress {
+                        target: target.clone(),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        // Multiple PTR records may point at the same instance; only report each problem once.
+        for w in warnings {
+            if !self.warnings.contains(&w) {
+                self.warnings.push(w);
+            }
+        }
+    }
+}
+
+fn decode_error(
+    part: MessagePart,
+    error: Error,
+    at_end: bool,
+    expected: u16,
+    actual: u16,
+) -> Warning {
+    if error == Error::Eof && at_end {
+        Warning::CountMismatch {
+            part,
+            expected,
+            actual,
+        }
+    } else {
+        Warning::Malformed { part, error }
+    }
+}
+
+/// Returns whether `instance` is a DNS-SD service instance name of the service type `service`
+/// (`<Instance>.<_service>.<_proto>.<domain>`).
+fn is_service_instance(instance: &DomainName, service: &DomainName) -> bool {
+    let labels = service.labels();
+    let is_service_type = labels.len() >= 2
+        && labels[0].as_bytes().starts_with(b"_")
+        && (labels[1].eq_ignore_ascii_case(&label!("_tcp"))
+            || labels[1].eq_ignore_ascii_case(&label!("_udp")));
+    is_service_type
+        && instance.labels().len() == labels.len() + 1
+        && instance.labels()[1..]
+            .iter()
+            .zip(labels)
+            .all(|(a, b)| a.eq_ignore_ascii_case(b))
+}
+
+/// Case-insensitive map key for a domain name.
+fn key(name: &DomainName) -> String {
+    name.to_string().to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::packet::{
+        encoder::{self, MessageEncoder},
+        records::{A, CNAME, PTR, SRV, TXT},
+        Header,
+    };
+
+    use super::*;
+
+    fn response(
+        answers: &[(&DomainName, Record<'_>)],
+        additional: &[(&DomainName, Record<'_>)],
+    ) -> Vec<u8> {
+        let mut buf = [0; 1500];
+        let mut enc = MessageEncoder::new(&mut buf);
+        let mut header = Header::default();
+        header.set_response(true);
+        enc.set_header(header);
+        let mut enc = enc.answers();
+        for (name, rdata) in answers {
+            enc.add_answer(encoder::ResourceRecord::new(name, rdata));
+        }
+        let mut enc = enc.authority().additional();
+        for (name, rdata) in additional {
+            enc.add_additional(encoder::ResourceRecord::new(name, rdata));
+        }
+        let len = enc.finish().unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn dnssd() {
+        let service = DomainName::from_str("_http._tcp.local").unwrap();
+        let instance = DomainName::from_str("web._http._tcp.local").unwrap();
+        let host = DomainName::from_str("host.local").unwrap();
+
+        let msg = response(
+            &[(&service, Record::PTR(PTR::new(&instance)))],
+            &[(&instance, Record::SRV(SRV::new(0, 0, 80, &host)))],
+        );
+        assert_eq!(
+            validate(&msg),
+            [
+                Warning::MissingTxt {
+                    instance: instance.clone()
+                },
+                Warning::MissingAddress {
+                    target: host.clone()
+                },
+            ]
+        );
+
+        let msg = response(
+            &[(&service, Record::PTR(PTR::new(&instance)))],
+            &[
+                (&instance, Record::SRV(SRV::new(0, 0, 80, &host))),
+                (&instance, Record::TXT(TXT::new([&b""[..]]))),
+                (&host, Record::A(A::new(Ipv4Addr::LOCALHOST))),
+            ],
+        );
+        assert_eq!(validate(&msg), []);
+    }
+
+    #[test]
+    fn cname() {
+        let name = DomainName::from_str("example.com").unwrap();
+        let other = DomainName::from_str("other.example.com").unwrap();
+        let msg = response(
+            &[
+                (&name, Record::CNAME(CNAME::new(&other))),
+                (&name, Record::A(A::new(Ipv4Addr::LOCALHOST))),
+            ],
+            &[],
+        );
+        assert_eq!(validate(&msg), [Warning::CnameAndOtherData { name }]);
+    }
+
+    #[test]
+    fn counts() {
+        let name = DomainName::from_str("example.com").unwrap();
+        let mut msg = response(&[(&name, Record::A(A::new(Ipv4Addr::LOCALHOST)))], &[]);
+
+        msg.push(0);
+        assert_eq!(validate(&msg), [Warning::TrailingData { len: 1 }]);
+        msg.pop();
+
+        // Bump ANCOUNT.
+        msg[7] += 1;
+        assert_eq!(
+            validate(&msg),
+            [Warning::CountMismatch {
+                part: MessagePart::Answer,
+                expected: 2,
+                actual: 1,
+            }]
+        );
+
+        assert_eq!(
+            validate(&msg[..4]),
+            [Warning::Malformed {
+                part: MessagePart::Header,
+                error: Error::Eof,
+            }]
+        );
+    }
+}
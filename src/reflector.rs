@@ -0,0 +1,402 @@
+//! mDNS reflector.
+//!
+//! mDNS traffic is limited to a single link, so services on one network segment (for example, a
+//! separate VLAN for IoT devices) are invisible to clients on another. A reflector listens on
+//! several interfaces and re-multicasts the mDNS queries and responses it receives on one
+//! interface to the others, making services discoverable across segments.
+
+use std::{
+    collections::{hash_map::DefaultHasher, VecDeque},
+    hash::{Hash, Hasher},
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::Backoff,
+    error::is_transient_io,
+    hex::Hex,
+    name::DomainName,
+    net::{self, MulticastSocketBuilder, MDNS_GROUP_V4, MDNS_PORT},
+    packet::arena::{ArenaName, DecodeArena},
+    Error, MDNS_BUFFER_SIZE,
+};
+
+/// Identifies an interface added to a [`Reflector`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InterfaceId(usize);
+
+struct Interface {
+    addr: Ipv4Addr,
+    prefix_len: u8,
+}
+
+impl Interface {
+    fn contains(&self, ip: Ipv4Addr) -> bool {
+        let mask = u32::MAX
+            .checked_shl(32 - u32::from(self.prefix_len))
+            .unwrap_or(0);
+        u32::from(self.addr) & mask == u32::from(ip) & mask
+    }
+}
+
+/// I/O-less mDNS reflection logic.
+///
+/// The reflector decides which interfaces a received packet should be forwarded to. It prevents
+/// loops by ignoring packets sent from the reflector's own addresses, and by suppressing packets
+/// that it has reflected recently.
+///
+/// You probably want to use [`SyncReflector`] instead.
+pub struct Reflector {
+    interfaces: Vec<Interface>,
+    /// `(from, to)` pairs of interfaces between which packets are reflected.
+    routes: Vec<(InterfaceId, InterfaceId)>,
+    /// If non-empty, only packets mentioning a name in one of these domains are reflected.
+    filters: Vec<DomainName>,
+    /// Hashes of recently reflected packets.
+    recent: VecDeque<(u64, Instant)>,
+    suppression_window: Duration,
+    /// Reused for decoding packets when `filters` is non-empty.
+    arena: DecodeArena,
+}
+
+impl Reflector {
+    const DEFAULT_SUPPRESSION_WINDOW: Duration = Duration::from_secs(1);
+
+    /// Creates a new reflector without any interfaces.
+    pub fn new() -> Self {
+        Self {
+            interfaces: Vec::new(),
+            routes: Vec::new(),
+            filters: Vec::new(),
+            recent: VecDeque::new(),
+            suppression_window: Self::DEFAULT_SUPPRESSION_WINDOW,
+            arena: DecodeArena::new(),
+        }
+    }
+
+    /// Adds a network interface, identified by its local IPv4 address and network prefix length.
+    ///
+    /// Incoming packets are associated with an interface by matching their source address against
+    /// the interface's network.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix_len` is larger than 32.
+    pub fn add_interface(&mut self, addr: Ipv4Addr, prefix_len: u8) -> InterfaceId {
+        assert!(prefix_len <= 32, "invalid IPv4 prefix length {prefix_len}");
+        self.interfaces.push(Interface { addr, prefix_len });
+        InterfaceId(self.interfaces.len() - 1)
+    }
+
+    /// Returns the local address of an interface.
+    pub fn interface_addr(&self, id: InterfaceId) -> Ipv4Addr {
+        self.interfaces[id.0].addr
+    }
+
+    /// Returns an iterator over all interfaces added to this reflector.
+    pub fn interfaces(&self) -> impl Iterator<Item = InterfaceId> {
+        (0..self.interfaces.len()).map(InterfaceId)
+    }
+
+    /// Reflects packets received on `from` to `to` (but not the other way around).
+    pub fn reflect_one_way(&mut self, from: InterfaceId, to: InterfaceId) {
+        assert_ne!(from, to, "cannot reflect an interface to itself");
+        if !self.routes.contains(&(from, to)) {
+            self.routes.push((from, to));
+        }
+    }
+
+    /// Reflects packets between the interfaces `a` and `b`, in both directions.
+    pub fn reflect_between(&mut self, a: InterfaceId, b: InterfaceId) {
+        self.reflect_one_way(a, b);
+        self.reflect_one_way(b, a);
+    }
+
+    /// Only reflect packets that refer to names in `domain` (for example,
+    /// `_airplay._tcp.local.`).
+    ///
+    /// May be called multiple times to allow several domains. If no filter is configured, all
+    /// packets are reflected.
+    pub fn add_filter(&mut self, domain: DomainName) {
+        self.filters.push(domain);
+    }
+
+    /// Sets the time window during which a reflected packet will not be reflected again.
+    pub fn set_suppression_window(&mut self, window: Duration) {
+        self.suppression_window = window;
+    }
+
+    /// Determines the interface a packet from `source` was received on.
+    pub fn interface_for(&self, source: Ipv4Addr) -> Option<InterfaceId> {
+        self.interfaces
+            .iter()
+            .position(|iface| iface.contains(source))
+            .map(InterfaceId)
+    }
+
+    /// Handles a packet received from `source`, and invokes `reflect` for every interface the
+    /// packet should be re-multicast on.
+    pub fn handle_packet(
+        &mut self,
+        source: SocketAddr,
+        packet: &[u8],
+        now: Instant,
+        mut reflect: impl FnMut(InterfaceId),
+    ) -> Result<(), Error> {
+        let IpAddr::V4(source_ip) = source.ip() else {
+            return Ok(());
+        };
+        if self.interfaces.iter().any(|iface| iface.addr == source_ip) {
+            // Sent by us (either reflected, or from a local responder).
+            return Ok(());
+        }
+        let Some(from) = self.interface_for(source_ip) else {
+            log::trace!(
+                "ignoring packet from {}, which is on no known interface",
+                source
+            );
+            return Ok(());
+        };
+
+        while let Some(&(_, time)) = self.recent.front() {
+            if now.saturating_duration_since(time) < self.suppression_window {
+                break;
+            }
+            self.recent.pop_front();
+        }
+        let hash = {
+            let mut hasher = DefaultHasher::new();
+            packet.hash(&mut hasher);
+            hasher.finish()
+        };
+        if self.recent.iter().any(|&(h, _)| h == hash) {
+            log::trace!("suppressing recently reflected packet from {}", source);
+            return Ok(());
+        }
+
+        if !self.filters.is_empty() && !self.matches_filter(packet)? {
+            return Ok(());
+        }
+
+        let mut reflected = false;
+        for &(_, to) in self.routes.iter().filter(|(f, _)| *f == from) {
+            reflected = true;
+            reflect(to);
+        }
+        if reflected {
+            self.recent.push_back((hash, now));
+        }
+        Ok(())
+    }
+
+    fn matches_filter(&mut self, packet: &[u8]) -> Result<bool, Error> {
+        let msg = self.arena.decode(packet)?;
+        let matches =
+            |name: ArenaName<'_>| self.filters.iter().any(|filter| name.ends_with(filter));
+        Ok(msg.questions().any(|q| matches(q.qname()))
+            || msg.records_in_all_sections().any(|rr| matches(rr.name())))
+    }
+}
+
+impl Default for Reflector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A simple, synchronous IPv4 mDNS reflector.
+pub struct SyncReflector {
+    refl: Reflector,
+    recv_sock: UdpSocket,
+    /// One socket per interface, used for sending reflected packets out of that interface.
+    send_socks: Vec<UdpSocket>,
+}
+
+impl SyncReflector {
+    /// Creates a reflector that will reflect packets between all of the given interfaces.
+    ///
+    /// Every interface is specified by its local IPv4 address and network prefix length.
+    pub fn new(interfaces: &[(Ipv4Addr, u8)]) -> Result<Self, Error> {
+        let mut refl = Reflector::new();
+        let ids = interfaces
+            .iter()
+            .map(|&(addr, prefix_len)| refl.add_interface(addr, prefix_len))
+            .collect::<Vec<_>>();
+        for (i, &a) in ids.iter().enumerate() {
+            for &b in &ids[i + 1..] {
+                refl.reflect_between(a, b);
+            }
+        }
+        Self::from_reflector(refl)
+    }
+
+    /// Creates a reflector from a preconfigured I/O-less [`Reflector`].
+    pub fn from_reflector(refl: Reflector) -> Result<Self, Error> {
+        let (recv_sock, send_socks) = Self::create_sockets(&refl)?;
+        Ok(Self {
+            refl,
+            recv_sock,
+            send_socks,
+        })
+    }
+
+    /// Creates the receiving socket, and one sending socket per interface of `refl`.
+    fn create_sockets(refl: &Reflector) -> io::Result<(UdpSocket, Vec<UdpSocket>)> {
+        let recv_sock = MulticastSocketBuilder::mdns_v4().join(false).build()?;
+        let mut send_socks = Vec::new();
+        for id in refl.interfaces() {
+            let addr = refl.interface_addr(id);
+            recv_sock.join_multicast_v4(&MDNS_GROUP_V4, &addr)?;
+
+            let sock = MulticastSocketBuilder::mdns_v4()
+                .interface(net::Interface::Addr(addr))
+                .join(false)
+                .loopback(false)
+                .build()?;
+            send_socks.push(sock);
+        }
+        Ok((recv_sock, send_socks))
+    }
+
+    /// Returns a reference to the I/O-less reflector logic.
+    pub fn reflector_mut(&mut self) -> &mut Reflector {
+        &mut self.refl
+    }
+
+    /// Starts reflecting packets.
+    ///
+    /// This method will block forever and never return, except when an error occurs.
+    ///
+    /// Transient socket errors (see [`Error::is_transient`]), like those caused by an interface
+    /// going down, are logged, and the sockets are recreated after an increasing delay.
+    pub fn listen_blocking(&mut self) -> Result<(), Error> {
+        let mut backoff = Backoff::default();
+        let mut recv_buf = [0; MDNS_BUFFER_SIZE];
+        loop {
+            let (len, addr) = match self.recv_sock.recv_from(&mut recv_buf) {
+                Ok(res) => res,
+                Err(e) => {
+                    self.recover(e, &mut backoff)?;
+                    continue;
+                }
+            };
+            backoff.reset();
+            let packet = &recv_buf[..len];
+            log::trace!("raw recv from {}: {}", addr, Hex(packet));
+
+            let mut targets = Vec::new();
+            let res = self
+                .refl
+                .handle_packet(addr, packet, Instant::now(), |to| targets.push(to));
+            if let Err(e) = res {
+                log::debug!("failed to handle packet from {}: {}", addr, e);
+                continue;
+            }
+            for to in targets {
+                log::trace!("reflecting packet from {} to {:?}", addr, to);
+                if let Err(e) = self.send_socks[to.0].send_to(packet, (MDNS_GROUP_V4, MDNS_PORT)) {
+                    self.recover(e, &mut backoff)?;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Recreates the sockets if `error` is transient, and returns `error` otherwise.
+    ///
+    /// All sockets are recreated, since the receiving socket loses its multicast group membership
+    /// on an interface that went away.
+    fn recover(&mut self, error: io::Error, backoff: &mut Backoff) -> io::Result<()> {
+        if !is_transient_io(&error) {
+            return Err(error);
+        }
+        log::warn!("reflector socket error: {}; recreating sockets", error);
+        loop {
+            thread::sleep(backoff.next_delay());
+            match Self::create_sockets(&self.refl) {
+                Ok((recv_sock, send_socks)) => {
+                    self.recv_sock = recv_sock;
+                    self.send_socks = send_socks;
+                    return Ok(());
+                }
+                Err(e) if is_transient_io(&e) => {
+                    log::warn!("failed to recreate reflector sockets: {}; retrying", e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::{
+        encoder::{MessageEncoder, Question},
+        QType,
+    };
+
+    use super::*;
+
+    fn query(name: &str) -> Vec<u8> {
+        let mut buf = [0; MDNS_BUFFER_SIZE];
+        let name = DomainName::from_str(name).unwrap();
+        let mut enc = MessageEncoder::new(&mut buf);
+        enc.question(Question::new(&name).ty(QType::PTR));
+        let len = enc.finish().unwrap();
+        buf[..len].to_vec()
+    }
+
+    fn reflect(refl: &mut Reflector, from: &str, packet: &[u8], now: Instant) -> Vec<InterfaceId> {
+        let mut targets = Vec::new();
+        refl.handle_packet(from.parse().unwrap(), packet, now, |to| targets.push(to))
+            .unwrap();
+        targets
+    }
+
+    #[test]
+    fn reflects_between_interfaces() {
+        let mut refl = Reflector::new();
+        let lan = refl.add_interface(Ipv4Addr::new(192, 168, 1, 1), 24);
+        let iot = refl.add_interface(Ipv4Addr::new(10, 0, 0, 1), 8);
+        let _guest = refl.add_interface(Ipv4Addr::new(172, 16, 0, 1), 12);
+        refl.reflect_one_way(lan, iot);
+        let now = Instant::now();
+
+        let packet = query("_http._tcp.local");
+        assert_eq!(reflect(&mut refl, "192.168.1.7:5353", &packet, now), [iot]);
+        // Suppressed when it arrives again (eg. from another reflector).
+        assert_eq!(reflect(&mut refl, "192.168.1.8:5353", &packet, now), []);
+        let later = now + Duration::from_secs(2);
+        assert_eq!(
+            reflect(&mut refl, "192.168.1.7:5353", &packet, later),
+            [iot]
+        );
+
+        // One-way route, and unrouted interfaces.
+        let packet = query("_ipp._tcp.local");
+        assert_eq!(reflect(&mut refl, "10.1.2.3:5353", &packet, now), []);
+        assert_eq!(reflect(&mut refl, "172.16.3.3:5353", &packet, now), []);
+
+        // Our own packets are never reflected.
+        let packet = query("_ssh._tcp.local");
+        assert_eq!(reflect(&mut refl, "192.168.1.1:5353", &packet, now), []);
+    }
+
+    #[test]
+    fn filters() {
+        let mut refl = Reflector::new();
+        let a = refl.add_interface(Ipv4Addr::new(192, 168, 1, 1), 24);
+        let b = refl.add_interface(Ipv4Addr::new(192, 168, 2, 1), 24);
+        refl.reflect_between(a, b);
+        refl.add_filter(DomainName::from_str("_airplay._tcp.local").unwrap());
+        let now = Instant::now();
+
+        let packet = query("_airplay._tcp.local");
+        assert_eq!(reflect(&mut refl, "192.168.2.50:5353", &packet, now), [a]);
+        let packet = query("_ssh._tcp.local");
+        assert_eq!(reflect(&mut refl, "192.168.2.50:5353", &packet, now), []);
+    }
+}
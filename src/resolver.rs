@@ -1,63 +1,422 @@
 //! DNS name resolution.
 
 use std::{
-    io,
-    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
-    time::Duration,
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 use crate::{
+    clock::{Clock, SystemClock},
     hex::Hex,
+    label,
     name::DomainName,
     packet::{
         decoder::MessageDecoder,
+        edns::{ExtendedError, OptionCode, TcpKeepalive},
         encoder::{MessageEncoder, Question},
         records::Record,
-        Header, QType,
+        Header, QType, RCode,
     },
     Error,
 };
 
-use crate::{DNS_BUFFER_SIZE, MDNS_BUFFER_SIZE};
+use crate::{checked_message_size, default_max_message_size, DNS_BUFFER_SIZE, MDNS_BUFFER_SIZE};
+
+pub mod addr_select;
+pub mod cache;
+mod happy_eyeballs;
+pub mod hosts;
+mod lookup;
+pub mod probe;
+mod query_log;
+pub mod recursive;
+pub mod stream;
+pub mod svcb;
+
+pub use happy_eyeballs::{connect_happy_eyeballs, sort_happy_eyeballs, CONNECTION_ATTEMPT_DELAY};
+pub use lookup::RecordLookup;
+pub use query_log::{QueryLog, QueryLogEntry};
+
+use stream::SyncStreamClient;
+
+/// A source of host name to IP address mappings.
+///
+/// This is implemented by [`SyncResolver`] (for unicast DNS, mDNS, and LLMNR), by
+/// [`recursive::RecursiveResolver`], by [`cache::CachingResolver`] and by [`hosts::HostsFile`],
+/// and allows combining several of them into a [`ChainedResolver`].
+pub trait Resolve {
+    /// Resolves `name` to a list of IP addresses.
+    ///
+    /// An empty list indicates that the name is unknown to this resolver. Errors indicate that the
+    /// resolver was unable to determine whether the name exists (for example, because a query
+    /// timed out).
+    fn resolve_name(&mut self, name: &DomainName) -> Result<Vec<IpAddr>, Error>;
+
+    /// Looks up the records of type `qtype` owned by `name` (or by its canonical name, if `name`
+    /// is an alias).
+    ///
+    /// An empty list indicates that no such records are known to this resolver. The default
+    /// implementation always returns an empty list, which is appropriate for sources that only
+    /// map names to addresses.
+    fn resolve_records(
+        &mut self,
+        name: &DomainName,
+        qtype: QType,
+    ) -> Result<Vec<Record<'static>>, Error> {
+        let _ = (name, qtype);
+        Ok(Vec::new())
+    }
+}
+
+impl<R: Resolve + ?Sized> Resolve for Box<R> {
+    fn resolve_name(&mut self, name: &DomainName) -> Result<Vec<IpAddr>, Error> {
+        (**self).resolve_name(name)
+    }
+
+    fn resolve_records(
+        &mut self,
+        name: &DomainName,
+        qtype: QType,
+    ) -> Result<Vec<Record<'static>>, Error> {
+        (**self).resolve_records(name, qtype)
+    }
+}
+
+/// Tries a list of [`Resolve`] implementations in order, similar to `nsswitch.conf`.
+///
+/// # Example
+///
+/// ```no_run
+/// # use uwuhi::resolver::{ChainedResolver, SyncResolver, hosts::HostsFile};
+/// # fn main() -> Result<(), uwuhi::Error> {
+/// let mut resolver = ChainedResolver::new();
+/// resolver.push(HostsFile::load()?);
+/// resolver.push(SyncResolver::new_multicast_v4()?);
+/// resolver.push(SyncResolver::new("1.1.1.1:53".parse().unwrap())?);
+///
+/// for ip in resolver.resolve("example.com")? {
+///     println!("{}", ip);
+/// }
+/// # Ok(()) }
+/// ```
+#[derive(Default)]
+pub struct ChainedResolver {
+    resolvers: Vec<Box<dyn Resolve + Send>>,
+}
+
+impl ChainedResolver {
+    /// Creates an empty resolver chain.
+    ///
+    /// Resolvers have to be added via [`ChainedResolver::push`] before any name can be resolved.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `resolver` to the end of the chain.
+    pub fn push(&mut self, resolver: impl Resolve + Send + 'static) {
+        self.resolvers.push(Box::new(resolver));
+    }
+
+    /// Resolves `hostname` by trying every resolver in the chain.
+    ///
+    /// See [`ChainedResolver::resolve_name`] for details.
+    pub fn resolve(&mut self, hostname: &str) -> Result<Vec<IpAddr>, Error> {
+        let name = DomainName::from_str(hostname)?;
+        self.resolve_name(&name)
+    }
+}
+
+impl Resolve for ChainedResolver {
+    /// Resolves `name` by trying every resolver in the chain.
+    ///
+    /// The addresses returned by the first resolver that finds any are returned. If no resolver
+    /// knows the name, the last error encountered is returned, or an empty list if there was none.
+    fn resolve_name(&mut self, name: &DomainName) -> Result<Vec<IpAddr>, Error> {
+        let mut error = None;
+        for resolver in &mut self.resolvers {
+            match resolver.resolve_name(name) {
+                Ok(ips) if !ips.is_empty() => return Ok(ips),
+                Ok(_) => {}
+                Err(e) => {
+                    log::debug!("failed to resolve '{}': {}", name, e);
+                    error = Some(e);
+                }
+            }
+        }
+        match error {
+            Some(e) => Err(e),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Looks up records by trying every resolver in the chain.
+    ///
+    /// Like [`ChainedResolver::resolve_name`], this returns the records found by the first
+    /// resolver that finds any.
+    fn resolve_records(
+        &mut self,
+        name: &DomainName,
+        qtype: QType,
+    ) -> Result<Vec<Record<'static>>, Error> {
+        let mut error = None;
+        for resolver in &mut self.resolvers {
+            match resolver.resolve_records(name, qtype) {
+                Ok(records) if !records.is_empty() => return Ok(records),
+                Ok(_) => {}
+                Err(e) => {
+                    log::debug!("failed to look up {:?} records of '{}': {}", qtype, name, e);
+                    error = Some(e);
+                }
+            }
+        }
+        match error {
+            Some(e) => Err(e),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Resolves `host` via `resolver`, and returns its addresses combined with `port`, best first.
+///
+/// `host` may also be an IPv4 or IPv6 address literal, in which case no resolution takes place.
+///
+/// The addresses are ordered according to [RFC 6724] (see [`addr_select`]), using the source
+/// addresses the operating system picks for them. Addresses that aren't reachable from this host
+/// are placed last. Returns an empty list if `resolver` doesn't know `host`.
+///
+/// [RFC 6724]: https://datatracker.ietf.org/doc/html/rfc6724
+pub fn resolve_socket_addrs<R: Resolve + ?Sized>(
+    resolver: &mut R,
+    host: &str,
+    port: u16,
+) -> Result<Vec<SocketAddr>, Error> {
+    let mut ips = match host.parse::<IpAddr>() {
+        Ok(ip) => vec![ip],
+        Err(_) => resolver.resolve_name(&DomainName::from_str(host)?)?,
+    };
+    let sources = addr_select::source_addrs(&ips);
+    addr_select::sort_destinations(&mut ips, &sources);
+    Ok(ips
+        .into_iter()
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect())
+}
+
+/// Describes an error response received from a DNS server.
+///
+/// This is returned (wrapped in [`Error::Resolve`]) by [`SyncResolver`] when every server it
+/// contacted answered with a failure, like `SERVFAIL` or `REFUSED`. If the server included
+/// Extended DNS Errors ([RFC 8914]) in its response, they are available via
+/// [`ResolveError::extended_errors`], and explain the failure in more detail (for example, that
+/// the name was blocked, or that DNSSEC validation failed).
+///
+/// [RFC 8914]: https://datatracker.ietf.org/doc/html/rfc8914
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolveError {
+    server: SocketAddr,
+    rcode: RCode,
+    extended_errors: Vec<ExtendedError>,
+}
+
+impl ResolveError {
+    /// Inspects a response from `server`, and returns a [`ResolveError`] if it indicates failure.
+    ///
+    /// `NXDOMAIN` responses are not considered failures, since they are a definitive answer.
+    /// Messages that aren't responses or can't be decoded yield [`None`].
+    pub fn from_response(msg: &[u8], server: SocketAddr) -> Option<Self> {
+        let dec = MessageDecoder::new(msg).ok()?;
+        let header = *dec.header();
+        if !header.is_response() || matches!(header.rcode(), RCode::NO_ERROR | RCode::NX_DOMAIN) {
+            return None;
+        }
+
+        let mut extended_errors = Vec::new();
+        if let Ok(mut dec) = dec.additional() {
+            for rr in dec.iter().flatten() {
+                for option in rr.edns_options().into_iter().flatten().flatten() {
+                    if option.code() == OptionCode::EXTENDED_ERROR {
+                        extended_errors.extend(ExtendedError::decode(&option).ok());
+                    }
+                }
+            }
+        }
+        Some(Self {
+            server,
+            rcode: header.rcode(),
+            extended_errors,
+        })
+    }
+
+    /// Returns the address of the server that sent the error response.
+    #[inline]
+    pub fn server(&self) -> SocketAddr {
+        self.server
+    }
+
+    /// Returns the response code of the error response.
+    #[inline]
+    pub fn rcode(&self) -> RCode {
+        self.rcode
+    }
+
+    /// Returns the Extended DNS Errors included in the response.
+    #[inline]
+    pub fn extended_errors(&self) -> &[ExtendedError] {
+        &self.extended_errors
+    }
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} responded with {}", self.server, self.rcode)?;
+        for (i, error) in self.extended_errors.iter().enumerate() {
+            f.write_str(if i == 0 { " (" } else { ", " })?;
+            error.fmt(f)?;
+        }
+        if !self.extended_errors.is_empty() {
+            f.write_str(")")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+/// The protocol spoken by a resolver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Protocol {
+    /// Unicast DNS.
+    Dns,
+    /// Multicast DNS.
+    Mdns,
+    /// Link-Local Multicast Name Resolution.
+    Llmnr,
+}
+
+impl Protocol {
+    fn for_server(server: SocketAddr) -> Self {
+        if !server.ip().is_multicast() {
+            Self::Dns
+        } else if server.port() == LLMNR_PORT {
+            Self::Llmnr
+        } else {
+            Self::Mdns
+        }
+    }
+}
+
+/// The UDP port used by LLMNR.
+const LLMNR_PORT: u16 = 5355;
 
 /// A simple, synchronous, non-recursive (m)DNS stub resolver.
 pub struct SyncResolver {
     servers: Vec<SocketAddr>,
     sock: UdpSocket,
     ip_buf: Vec<IpAddr>,
-    is_multicast: bool,
+    protocol: Protocol,
+    max_message_size: usize,
+    llmnr_fallback: Option<Box<SyncResolver>>,
+    query_log: Option<QueryLog>,
+    /// TCP connections to the servers, used to retry truncated responses.
+    tcp_clients: Vec<(SocketAddr, SyncStreamClient<TcpStream>)>,
+    timeout: Duration,
+    /// Shared with the LLMNR fallback resolver.
+    clock: Arc<dyn Clock>,
 }
 
 impl SyncResolver {
     const DEFAULT_TIMEOUT: Duration = Duration::from_millis(500);
 
     /// Creates a new DNS resolver that will contact the given server.
-    pub fn new(sock: SocketAddr) -> io::Result<Self> {
-        let bind_addr: SocketAddr = if sock.is_ipv6() {
+    pub fn new(server: SocketAddr) -> Result<Self, Error> {
+        let bind_addr: SocketAddr = if server.is_ipv6() {
             (Ipv6Addr::UNSPECIFIED, 0).into()
         } else {
             (Ipv4Addr::UNSPECIFIED, 0).into()
         };
+        Self::with_socket(UdpSocket::bind(bind_addr)?, server)
+    }
+
+    /// Creates a new DNS resolver that will use `sock` to contact the given server.
+    ///
+    /// This allows using a socket that was configured in ways this library does not support
+    /// directly (eg. bound to a specific interface or port). `sock` must use the same address
+    /// family as `server`.
+    ///
+    /// Note that the socket's read timeout will be overwritten (see [`SyncResolver::set_timeout`]).
+    pub fn with_socket(sock: UdpSocket, server: SocketAddr) -> Result<Self, Error> {
         let mut this = Self {
-            servers: vec![sock],
-            sock: UdpSocket::bind(bind_addr)?,
+            servers: vec![server],
+            sock,
             ip_buf: Vec::new(),
-            is_multicast: bind_addr.ip().is_multicast(),
+            protocol: Protocol::for_server(server),
+            max_message_size: default_max_message_size(server),
+            llmnr_fallback: None,
+            query_log: None,
+            tcp_clients: Vec::new(),
+            timeout: Self::DEFAULT_TIMEOUT,
+            clock: Arc::new(SystemClock),
         };
         this.set_timeout(Self::DEFAULT_TIMEOUT)?;
         Ok(this)
     }
 
     /// Creates a new mDNS resolver that will use IPv4.
-    pub fn new_multicast_v4() -> io::Result<Self> {
+    pub fn new_multicast_v4() -> Result<Self, Error> {
         Self::new("224.0.0.251:5353".parse().unwrap())
     }
 
     /// Creates a new mDNS resolver that will use IPv6.
-    pub fn new_multicast_v6() -> io::Result<Self> {
+    pub fn new_multicast_v6() -> Result<Self, Error> {
         Self::new("[ff02::fb]:5353".parse().unwrap())
     }
 
+    /// Creates a new LLMNR resolver that will use IPv4.
+    ///
+    /// Link-Local Multicast Name Resolution ([RFC 4795]) is mostly used by Windows machines to
+    /// resolve single-label host names on the local network.
+    ///
+    /// [RFC 4795]: https://datatracker.ietf.org/doc/html/rfc4795
+    pub fn new_llmnr_v4() -> Result<Self, Error> {
+        Self::new("224.0.0.252:5355".parse().unwrap())
+    }
+
+    /// Creates a new LLMNR resolver that will use IPv6.
+    pub fn new_llmnr_v6() -> Result<Self, Error> {
+        Self::new("[ff02::1:3]:5355".parse().unwrap())
+    }
+
+    /// Enables LLMNR as a fallback for single-label names on an mDNS resolver.
+    ///
+    /// When enabled, single-label names like `printer` are first resolved as `printer.local` via
+    /// mDNS, and if that fails, the name is resolved via LLMNR (using the same address family as
+    /// the mDNS resolver).
+    ///
+    /// # Panics
+    ///
+    /// This method will panic when called on a resolver that doesn't use mDNS.
+    pub fn enable_llmnr_fallback(&mut self) -> Result<(), Error> {
+        assert_eq!(
+            self.protocol,
+            Protocol::Mdns,
+            "LLMNR fallback can only be enabled on mDNS resolvers",
+        );
+        let mut llmnr = if self.servers[0].is_ipv4() {
+            Self::new_llmnr_v4()?
+        } else {
+            Self::new_llmnr_v6()?
+        };
+        llmnr.set_timeout(self.timeout)?;
+        llmnr.clock = self.clock.clone();
+        if let Some(log) = &self.query_log {
+            llmnr.enable_query_log(log.capacity());
+        }
+        self.llmnr_fallback = Some(Box::new(llmnr));
+        Ok(())
+    }
+
     /// Adds another server to be contacted by this resolver.
     ///
     /// Calling [`SyncResolver::resolve`] or [`SyncResolver::resolve_domain`] will send a query to
@@ -71,8 +430,9 @@ impl SyncResolver {
     ///
     /// This method will also panic when called on a multicast resolver.
     pub fn add_server(&mut self, server: SocketAddr) {
-        assert!(
-            !self.is_multicast,
+        assert_eq!(
+            self.protocol,
+            Protocol::Dns,
             "cannot add_server to a multicast DNS resolver",
         );
         assert_eq!(
@@ -83,86 +443,660 @@ impl SyncResolver {
         self.servers.push(server);
     }
 
+    /// Sets the largest DNS message this resolver can receive, in bytes.
+    ///
+    /// Defaults to [`DNS_BUFFER_SIZE`] for unicast DNS, and to [`MDNS_BUFFER_SIZE`] for mDNS and
+    /// LLMNR. [`MDNS_MAX_MESSAGE_SIZE`] can be used for mDNS on networks with jumbo frames.
+    ///
+    /// When using unicast DNS with a size larger than [`DNS_BUFFER_SIZE`], the resolver advertises
+    /// the size to servers via an EDNS(0) `OPT` record, allowing them to send larger responses.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is smaller than [`DNS_BUFFER_SIZE`], which every DNS client must support,
+    /// or larger than 65535, the maximum size of a UDP payload.
+    ///
+    /// [`MDNS_MAX_MESSAGE_SIZE`]: crate::MDNS_MAX_MESSAGE_SIZE
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = checked_message_size(size);
+        if let Some(llmnr) = &mut self.llmnr_fallback {
+            llmnr.set_max_message_size(size);
+        }
+    }
+
     /// Sets the timeout after which to abort a resolution attempt.
     ///
-    /// This is the timeout for individual receive operations, not for the whole query. Packets that
-    /// don't match the query that was sent will be ignored, but still reset the timeout.
-    pub fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+    /// This is how long to wait for responses after sending a query, not the timeout for the whole
+    /// query: if no response at all arrives before the timeout passes, the query is sent once
+    /// more, and responses are awaited for the same amount of time before giving up.
+    ///
+    /// The timeout also applies to the TCP connections used for truncated responses; open
+    /// connections are closed.
+    pub fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
         self.sock.set_read_timeout(Some(timeout))?;
+        self.timeout = timeout;
+        self.tcp_clients.clear();
+        if let Some(llmnr) = &mut self.llmnr_fallback {
+            llmnr.set_timeout(timeout)?;
+        }
         Ok(())
     }
 
+    /// Sets the [`Clock`] used to time retransmissions and the timeout (see
+    /// [`SyncResolver::set_timeout`]), and to measure round-trip times.
+    ///
+    /// By default, the [`SystemClock`] is used.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Arc::new(clock);
+        if let Some(llmnr) = &mut self.llmnr_fallback {
+            llmnr.clock = self.clock.clone();
+        }
+    }
+
+    /// Starts recording the last `capacity` queries sent by this resolver, along with the
+    /// responses they received, in a [`QueryLog`].
+    ///
+    /// Every response received is logged, as well as every server that didn't respond before the
+    /// timeout. Calling this again discards the existing log.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `capacity` is 0.
+    pub fn enable_query_log(&mut self, capacity: usize) {
+        self.query_log = Some(QueryLog::new(capacity));
+        if let Some(llmnr) = &mut self.llmnr_fallback {
+            llmnr.enable_query_log(capacity);
+        }
+    }
+
+    /// Returns the [`QueryLog`], if it was enabled via [`SyncResolver::enable_query_log`].
+    #[inline]
+    pub fn query_log(&self) -> Option<&QueryLog> {
+        self.query_log.as_ref()
+    }
+
     /// Attempts to resolve `hostname` using the configured DNS servers.
     ///
-    /// If the query times out, an error of type [`io::ErrorKind::WouldBlock`] or
-    /// [`io::ErrorKind::TimedOut`] will be returned.
+    /// If the query times out, [`Error::Timeout`] will be returned. If every server answered with
+    /// an error (or the query timed out after some did), [`Error::Resolve`] is returned instead.
     ///
     /// The resolver does not perform recursive resolution (it is a "stub resolver"). It does set
     /// the `RD` bit in the query, which instructs the server to perform recursion.
-    pub fn resolve(&mut self, hostname: &str) -> io::Result<impl Iterator<Item = IpAddr> + '_> {
-        let name = DomainName::from_str(&hostname)?;
+    ///
+    /// If a unicast DNS server sends a truncated response (with the `TC` bit set), the query is
+    /// repeated over TCP to the same server, to get the complete answer.
+    pub fn resolve(&mut self, hostname: &str) -> Result<impl Iterator<Item = IpAddr> + '_, Error> {
+        let name = DomainName::from_str(hostname)?;
         self.resolve_domain(&name)
     }
 
     /// Attempts to resolve a [`DomainName`] using the configured DNS servers.
     ///
-    /// If the query times out, an error of type [`io::ErrorKind::WouldBlock`] or
-    /// [`io::ErrorKind::TimedOut`] will be returned.
+    /// If the query times out, [`Error::Timeout`] will be returned. If every server answered with
+    /// an error (or the query timed out after some did), [`Error::Resolve`] is returned instead.
     ///
     /// The resolver does not perform recursive resolution (it is a "stub resolver"). It does set
     /// the `RD` bit in the query, which instructs the server to perform recursion.
+    ///
+    /// If a unicast DNS server sends a truncated response (with the `TC` bit set), the query is
+    /// repeated over TCP to the same server, to get the complete answer.
     pub fn resolve_domain(
         &mut self,
         name: &DomainName,
-    ) -> io::Result<impl Iterator<Item = IpAddr> + '_> {
+    ) -> Result<impl Iterator<Item = IpAddr> + '_, Error> {
+        self.resolve_domain_with(name, &mut |_| {})
+    }
+
+    /// Like [`SyncResolver::resolve_domain`], but passes every response that is used to
+    /// `on_response`.
+    pub(crate) fn resolve_domain_with(
+        &mut self,
+        name: &DomainName,
+        on_response: &mut dyn FnMut(&[u8]),
+    ) -> Result<impl Iterator<Item = IpAddr> + '_, Error> {
+        if self.llmnr_fallback.is_some() && name.labels().len() == 1 {
+            let mut local = name.clone();
+            local.push_label(label!("local"));
+            match self.resolve_impl(&local, on_response) {
+                Ok(()) => {}
+                Err(e) if e.is_timeout() => {
+                    log::debug!("mDNS resolution of '{}' timed out, trying LLMNR", local);
+                    let llmnr = self.llmnr_fallback.as_mut().unwrap();
+                    let res = llmnr
+                        .resolve_domain_with(name, on_response)
+                        .map(|ips| ips.collect::<Vec<_>>());
+                    if let (Some(log), Some(llmnr_log)) =
+                        (&mut self.query_log, &mut llmnr.query_log)
+                    {
+                        log.append(llmnr_log);
+                    }
+                    self.ip_buf = res?;
+                }
+                Err(e) => return Err(e),
+            }
+        } else {
+            self.resolve_impl(name, on_response)?;
+        }
+
+        Ok(self.ip_buf.iter().copied())
+    }
+
+    fn resolve_impl(
+        &mut self,
+        name: &DomainName,
+        on_response: &mut dyn FnMut(&[u8]),
+    ) -> Result<(), Error> {
         self.ip_buf.clear();
 
         let mut send_buf = [0; MDNS_BUFFER_SIZE];
-        let data = encode_query(&mut send_buf, name);
+        let mut queries = Vec::new();
+        match self.protocol {
+            Protocol::Llmnr => {
+                // LLMNR queries must contain exactly one question (RFC 4795 §2.1.1), so `A` and
+                // `AAAA` records are queried separately.
+                for qtype in [QType::A, QType::AAAA] {
+                    let id = Header::random_id();
+                    queries.push(encode_llmnr_query(&mut send_buf, id, name, qtype).to_vec());
+                }
+            }
+            Protocol::Dns if self.max_message_size > DNS_BUFFER_SIZE => queries.push(
+                encode_edns_query(&mut send_buf, name, self.max_message_size as u16).to_vec(),
+            ),
+            Protocol::Dns | Protocol::Mdns => {
+                queries.push(encode_query(&mut send_buf, name).to_vec())
+            }
+        }
 
-        log::trace!("resolving '{}', raw query: {}", name, Hex(data));
+        trace_span!("resolve", %name, protocol = ?self.protocol, servers = ?self.servers);
 
-        // FIXME: retransmit
-        for addr in &self.servers {
-            self.sock.send_to(data, addr)?;
+        let mut sent_at = self.clock.now();
+        for data in &queries {
+            log::trace!("resolving '{}', raw query: {}", name, Hex(data));
+            for addr in &self.servers {
+                self.sock.send_to(data, addr)?;
+            }
         }
+        let mut retransmitted = false;
 
+        // Servers that answered with an error, and the last such error.
+        let mut failed = Vec::new();
+        let mut error = None;
+        // Servers we've received any response from, for the query log.
+        let mut responded = Vec::new();
+        // The LLMNR queries that have been answered.
+        let mut answered = Vec::new();
+        let mut recv_buf = vec![0; self.max_message_size];
         loop {
-            let mut recv_buf = [0; DNS_BUFFER_SIZE];
-            let (b, addr) = self.sock.recv_from(&mut recv_buf)?;
+            let (b, addr) = match self.recv_until(&mut recv_buf, sent_at + self.timeout) {
+                Ok(res) => res,
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    if let Some(log) = &mut self.query_log {
+                        for server in self.servers.iter().filter(|s| !responded.contains(*s)) {
+                            for data in &queries {
+                                log.push(QueryLogEntry::new(*server, data, None));
+                            }
+                        }
+                    }
+                    if !retransmitted && answered.is_empty() && failed.is_empty() {
+                        // Nothing has been received, so the queries (or the responses) may have
+                        // been lost. Try once more.
+                        log::debug!("no response for '{}', retransmitting", name);
+                        retransmitted = true;
+                        sent_at = self.clock.now();
+                        for data in &queries {
+                            for addr in &self.servers {
+                                self.sock.send_to(data, addr)?;
+                            }
+                        }
+                        continue;
+                    }
+                    if !self.ip_buf.is_empty() {
+                        // Only one of the LLMNR queries was answered.
+                        return Ok(());
+                    }
+                    return Err(error.map_or(Error::Timeout, Error::from));
+                }
+                Err(e) => return Err(e.into()),
+            };
             let recv = &recv_buf[..b];
-            log::trace!("recv from {}: {}", addr, Hex(recv));
+            let rtt = self.clock.elapsed_since(sent_at);
+            log::trace!("recv from {} after {:?}: {}", addr, rtt, Hex(recv));
+            trace_event!(server = %addr, rtt_ms = rtt.as_millis() as u64, len = b, "received response");
+
+            // mDNS responders may set the ID of responses to 0, other responses have to carry the ID
+            // of one of our queries.
+            let index = if self.protocol == Protocol::Mdns {
+                0
+            } else {
+                match queries.iter().position(|q| is_response_to(recv, q)) {
+                    Some(index) => index,
+                    None => continue,
+                }
+            };
+            let data = &queries[index];
+            if let Some(log) = &mut self.query_log {
+                log.push(QueryLogEntry::new(addr, data, Some((recv, rtt))));
+                if !responded.contains(&addr) {
+                    responded.push(addr);
+                }
+            }
+
+            if self.protocol == Protocol::Llmnr && is_tentative_response(recv) {
+                log::debug!("ignoring tentative LLMNR response from {}", addr);
+                continue;
+            }
+            let mut tcp_buf = Vec::new();
+            let recv = self.complete_response(addr, data, recv, &mut tcp_buf);
+            on_response(recv);
 
             match decode_answer(recv, &mut self.ip_buf) {
                 Ok(()) => {
-                    if !self.ip_buf.is_empty() {
-                        // We return once any answer contains IP addresses.
-                        return Ok(self.ip_buf.iter().copied());
+                    if !answered.contains(&index) {
+                        answered.push(index);
+                    }
+                    // We return once any answer contains IP addresses (for LLMNR, once both
+                    // queries have been answered).
+                    if !self.ip_buf.is_empty() && answered.len() == queries.len() {
+                        return Ok(());
                     }
                 }
                 Err(e) => {
                     log::warn!("failed to decode response from {}: {:?}", addr, e);
                 }
             }
+
+            if self.protocol == Protocol::Dns {
+                if let Some(e) = ResolveError::from_response(recv, addr) {
+                    log::debug!("{}", e);
+                    if !failed.contains(&addr) {
+                        failed.push(addr);
+                    }
+                    if failed.len() == self.servers.len() {
+                        return Err(e.into());
+                    }
+                    error = Some(e);
+                }
+            }
+        }
+    }
+
+    /// Looks up the host names that `addr` belongs to, via a reverse (`PTR`) query for
+    /// [`DomainName::arpa`].
+    ///
+    /// On mDNS and LLMNR resolvers, every host that claims `addr` may respond, so responses are
+    /// collected until the timeout passes and the host names from all of them are returned. If
+    /// none arrive, an error of kind [`io::ErrorKind::TimedOut`] is returned.
+    ///
+    /// Unicast DNS resolvers return the names from the first response that contains any. If every
+    /// server responded without any names, an empty list is returned, unless one of them answered
+    /// with an error, in which case the returned [`io::Error`] wraps the [`ResolveError`].
+    pub fn resolve_addr(&mut self, addr: IpAddr) -> io::Result<Vec<DomainName>> {
+        Ok(self.resolve_hostname(addr)?)
+    }
+
+    /// Looks up the host names claiming `addr` on the local network, like `gethostbyaddr`.
+    ///
+    /// This is meant for mDNS resolvers (see [`SyncResolver::new_multicast_v4`]): the reverse
+    /// (`PTR`) query is multicast, and the `.local` names of every responder claiming `addr` are
+    /// collected until the timeout passes. If none respond, [`Error::Timeout`] is returned.
+    ///
+    /// It works with any resolver, and behaves like [`SyncResolver::resolve_addr`], except that
+    /// errors are returned as [`Error`].
+    pub fn resolve_hostname(&mut self, addr: IpAddr) -> Result<Vec<DomainName>, Error> {
+        let records = self.query(&DomainName::arpa(addr), QType::PTR)?;
+        let mut names: Vec<DomainName> = Vec::new();
+        for record in records {
+            if let Record::PTR(ptr) = record {
+                let target = ptr.ptrdname();
+                if !names.iter().any(|n| n.eq_ignore_ascii_case(target)) {
+                    names.push(target.clone());
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    /// Queries the configured servers for records of type `qtype` owned by `name`, and returns
+    /// the records from the *Answer* section of the responses.
+    ///
+    /// This can be used to look up any type of record (eg. `SRV`, `TXT` or `NS` records).
+    /// `CNAME` records are followed as described in [`decode_records_answer`], and records of
+    /// types this library doesn't support are skipped.
+    ///
+    /// On mDNS and LLMNR resolvers, every host may respond, so responses are collected until the
+    /// timeout passes and the records from all of them are returned. If none arrive,
+    /// [`Error::Timeout`] is returned.
+    ///
+    /// Unicast DNS resolvers return the records from the first response that contains any. If
+    /// every server responded without any matching records, an empty list is returned, unless one
+    /// of them answered with an error, which is returned as [`Error::Resolve`]. Truncated responses
+    /// are retried over TCP, like in [`SyncResolver::resolve`].
+    pub fn query(
+        &mut self,
+        name: &DomainName,
+        qtype: QType,
+    ) -> Result<Vec<Record<'static>>, Error> {
+        self.lookup_with(name, qtype, &mut |_| {})
+    }
+
+    /// Like [`SyncResolver::query`], but passes every response that is used to `on_response`.
+    pub(crate) fn lookup_with(
+        &mut self,
+        name: &DomainName,
+        qtype: QType,
+        on_response: &mut dyn FnMut(&[u8]),
+    ) -> Result<Vec<Record<'static>>, Error> {
+        let mut lookup = RecordLookup::new(name, qtype, &self.servers)?;
+        log::trace!(
+            "resolving {:?} of '{}', raw query: {}",
+            qtype,
+            name,
+            Hex(lookup.query())
+        );
+        let mut sent_at = self.clock.now();
+        for addr in &self.servers {
+            self.sock.send_to(lookup.query(), addr)?;
+        }
+
+        // Servers we've received any response from, for the query log.
+        let mut responded = Vec::new();
+        let mut recv_buf = vec![0; self.max_message_size];
+        loop {
+            let (b, addr) = match self.recv_until(&mut recv_buf, sent_at + self.timeout) {
+                Ok(res) => res,
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    if let Some(log) = &mut self.query_log {
+                        for server in self.servers.iter().filter(|s| !responded.contains(*s)) {
+                            log.push(QueryLogEntry::new(*server, lookup.query(), None));
+                        }
+                    }
+                    match lookup.handle_timeout() {
+                        Some(res) => return res,
+                        None => {
+                            log::debug!(
+                                "no response for {:?} of '{}', retransmitting",
+                                qtype,
+                                name
+                            );
+                            sent_at = self.clock.now();
+                            for addr in &self.servers {
+                                self.sock.send_to(lookup.query(), addr)?;
+                            }
+                            continue;
+                        }
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let recv = &recv_buf[..b];
+            let rtt = self.clock.elapsed_since(sent_at);
+            log::trace!("recv from {} after {:?}: {}", addr, rtt, Hex(recv));
+            if let Some(log) = &mut self.query_log {
+                log.push(QueryLogEntry::new(addr, lookup.query(), Some((recv, rtt))));
+                if !responded.contains(&addr) {
+                    responded.push(addr);
+                }
+            }
+
+            if !lookup.accepts(recv) {
+                continue;
+            }
+            let mut tcp_buf = Vec::new();
+            let recv = self.complete_response(addr, lookup.query(), recv, &mut tcp_buf);
+            on_response(recv);
+            if let Some(res) = lookup.handle_response(recv, addr) {
+                return res;
+            }
+        }
+    }
+
+    /// Receives a message, waiting until `deadline` (according to the resolver's clock) at most.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::TimedOut`] if `deadline` has already passed.
+    fn recv_until(&self, buf: &mut [u8], deadline: Instant) -> io::Result<(usize, SocketAddr)> {
+        let wait = deadline.saturating_duration_since(self.clock.now());
+        if wait.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.sock.set_read_timeout(Some(wait))?;
+        self.sock.recv_from(buf)
+    }
+
+    /// If `recv` is a truncated response from the unicast DNS server `server`, repeats `query`
+    /// over TCP ([RFC 7766]) and returns the complete response.
+    ///
+    /// Returns `recv` itself if it isn't truncated, or if the TCP query fails.
+    ///
+    /// The connection to each server is kept open for later truncated responses, for as long as
+    /// the server announces in its `edns-tcp-keepalive` option ([RFC 7828]).
+    ///
+    /// [RFC 7766]: https://datatracker.ietf.org/doc/html/rfc7766
+    /// [RFC 7828]: https://datatracker.ietf.org/doc/html/rfc7828
+    fn complete_response<'b>(
+        &mut self,
+        server: SocketAddr,
+        query: &[u8],
+        recv: &'b [u8],
+        tcp_buf: &'b mut Vec<u8>,
+    ) -> &'b [u8] {
+        let truncated = MessageDecoder::new(recv).is_ok_and(|dec| dec.header().is_truncated());
+        if self.protocol != Protocol::Dns || !truncated {
+            return recv;
+        }
+
+        log::debug!("response from {} is truncated, retrying over TCP", server);
+        let index = match self
+            .tcp_clients
+            .iter()
+            .position(|(addr, _)| *addr == server)
+        {
+            Some(index) => index,
+            None => {
+                let timeout = self.timeout;
+                let client = SyncStreamClient::new(move || {
+                    let stream = TcpStream::connect_timeout(&server, timeout)?;
+                    stream.set_nodelay(true)?;
+                    stream.set_read_timeout(Some(timeout))?;
+                    Ok(stream)
+                });
+                self.tcp_clients.push((server, client));
+                self.tcp_clients.len() - 1
+            }
+        };
+        let client = &mut self.tcp_clients[index].1;
+        let sent_at = self.clock.now();
+        match tcp_query(query).and_then(|query| client.query(&query)) {
+            Ok(resp) => {
+                let rtt = self.clock.elapsed_since(sent_at);
+                log::trace!("TCP recv from {} after {:?}: {}", server, rtt, Hex(&resp));
+                if let Some(log) = &mut self.query_log {
+                    log.push(QueryLogEntry::new(server, query, Some((&resp, rtt))));
+                }
+                *tcp_buf = resp;
+                tcp_buf
+            }
+            Err(e) => {
+                log::debug!(
+                    "TCP query to {} failed, using truncated response: {}",
+                    server,
+                    e
+                );
+                recv
+            }
         }
     }
 }
 
-/// Writes a DNS query asking for IPv4 and IPv6 addresses of `name` into `buf`.
+impl Resolve for SyncResolver {
+    fn resolve_name(&mut self, name: &DomainName) -> Result<Vec<IpAddr>, Error> {
+        Ok(self.resolve_domain(name)?.collect())
+    }
+
+    /// Queries the configured servers for records of type `qtype` owned by `name`.
+    ///
+    /// This is the same as [`SyncResolver::query`].
+    fn resolve_records(
+        &mut self,
+        name: &DomainName,
+        qtype: QType,
+    ) -> Result<Vec<Record<'static>>, Error> {
+        self.query(name, qtype)
+    }
+}
+
+/// Writes a DNS query with a random ID, asking for IPv4 and IPv6 addresses of `name`, into `buf`.
 ///
 /// The given buffer must be large enough to fit the query, or this method will panic.
 pub fn encode_query<'a>(buf: &'a mut [u8], name: &DomainName) -> &'a [u8] {
     let mut header = Header::default();
     header.set_recursion_desired(true);
-    header.set_id(12345);
+    header.set_id(Header::random_id());
+    let mut enc = MessageEncoder::new(buf);
+    enc.set_header(header);
+    enc.question(Question::new(name).ty(QType::A));
+    enc.question(Question::new(name).ty(QType::AAAA));
+    let bytes = enc.finish().unwrap();
+    &buf[..bytes]
+}
+
+/// Writes a DNS query with a random ID, asking for IPv4 and IPv6 addresses of `name`, into `buf`,
+/// advertising an EDNS(0) UDP payload size of `udp_payload_size` bytes.
+///
+/// The given buffer must be large enough to fit the query, or this method will panic.
+pub fn encode_edns_query<'a>(
+    buf: &'a mut [u8],
+    name: &DomainName,
+    udp_payload_size: u16,
+) -> &'a [u8] {
+    let mut header = Header::default();
+    header.set_recursion_desired(true);
+    header.set_id(Header::random_id());
+    let mut enc = MessageEncoder::new(buf);
+    enc.set_header(header);
+    enc.question(Question::new(name).ty(QType::A));
+    enc.question(Question::new(name).ty(QType::AAAA));
+    let mut enc = enc.answers().authority().additional();
+    enc.add_edns(udp_payload_size);
+    let bytes = enc.finish().unwrap();
+    &buf[..bytes]
+}
+
+/// Writes a DNS query with ID `id` to be sent over TCP, asking for IPv4 and IPv6 addresses of
+/// `name`, into `buf`.
+///
+/// The query includes an `edns-tcp-keepalive` option, asking the server to keep the connection open
+/// for further queries. The idle timeout the server applies can be read from its response with
+/// [`decode_tcp_keepalive`].
+///
+/// The given buffer must be large enough to fit the query, or this method will panic.
+pub fn encode_tcp_query<'a>(buf: &'a mut [u8], id: u16, name: &DomainName) -> &'a [u8] {
+    let mut header = Header::default();
+    header.set_recursion_desired(true);
+    header.set_id(id);
+    let mut enc = MessageEncoder::new(buf);
+    enc.set_header(header);
+    enc.question(Question::new(name).ty(QType::A));
+    enc.question(Question::new(name).ty(QType::AAAA));
+    let mut enc = enc.answers().authority().additional();
+    enc.edns(u16::MAX).tcp_keepalive(&TcpKeepalive::new(None));
+    let bytes = enc.finish().unwrap();
+    &buf[..bytes]
+}
+
+/// Re-encodes the UDP query `query` for TCP, with an `edns-tcp-keepalive` option.
+///
+/// Only the header and the questions are kept, which is all that the resolver's queries contain
+/// besides the `OPT` record advertising the UDP payload size.
+fn tcp_query(query: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut dec = MessageDecoder::new(query)?;
+    let mut buf = vec![0; MDNS_BUFFER_SIZE];
+    let mut enc = MessageEncoder::new(&mut buf);
+    enc.set_header(*dec.header());
+    for question in dec.iter() {
+        let question = question?;
+        enc.question(
+            Question::new(question.qname())
+                .ty(question.qtype())
+                .class(question.qclass()),
+        );
+    }
+    let mut enc = enc.answers().authority().additional();
+    enc.edns(u16::MAX).tcp_keepalive(&TcpKeepalive::new(None));
+    let len = enc.finish()?;
+    buf.truncate(len);
+    Ok(buf)
+}
+
+/// Returns the TCP idle timeout announced in the `edns-tcp-keepalive` option of a response.
+///
+/// Returns `Ok(None)` if the server did not include the option. Clients should close the
+/// connection once it has been idle for the returned duration.
+pub fn decode_tcp_keepalive(msg: &[u8]) -> Result<Option<Duration>, Error> {
+    let mut dec = MessageDecoder::new(msg)?.additional()?;
+    for rr in dec.iter() {
+        let rr = rr?;
+        for option in rr.edns_options().into_iter().flatten() {
+            let option = option?;
+            if option.code() == OptionCode::TCP_KEEPALIVE {
+                return Ok(TcpKeepalive::decode(&option)?.timeout());
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Writes an LLMNR query with ID `id`, asking for records of type `qtype` owned by `name`, into
+/// `buf`.
+///
+/// Unlike [`encode_query`], this does not set the `RD` bit, since LLMNR uses that bit as the
+/// *Tentative* flag (see [`Header::is_tentative`]). LLMNR queries may only contain a single
+/// question ([RFC 4795 §2.1.1]), so IPv4 and IPv6 addresses have to be queried separately.
+///
+/// The given buffer must be large enough to fit the query, or this method will panic.
+///
+/// [RFC 4795 §2.1.1]: https://datatracker.ietf.org/doc/html/rfc4795#section-2.1.1
+pub fn encode_llmnr_query<'a>(
+    buf: &'a mut [u8],
+    id: u16,
+    name: &DomainName,
+    qtype: QType,
+) -> &'a [u8] {
+    let mut header = Header::default();
+    header.set_id(id);
     let mut enc = MessageEncoder::new(buf);
     enc.set_header(header);
-    enc.question(Question::new(&name).ty(QType::A));
-    enc.question(Question::new(&name).ty(QType::AAAA));
+    enc.question(Question::new(name).ty(qtype));
     let bytes = enc.finish().unwrap();
     &buf[..bytes]
 }
 
+/// Returns whether `msg` is a response carrying the same ID as `query`.
+pub fn is_response_to(msg: &[u8], query: &[u8]) -> bool {
+    match (MessageDecoder::new(msg), MessageDecoder::new(query)) {
+        (Ok(msg), Ok(query)) => {
+            msg.header().is_response() && msg.header().id() == query.header().id()
+        }
+        _ => false,
+    }
+}
+
+/// Returns whether `msg` is an LLMNR response with the *Tentative* bit set.
+///
+/// Tentative responses are sent by hosts that haven't finished verifying that their name is
+/// unique, and should not be used.
+pub fn is_tentative_response(msg: &[u8]) -> bool {
+    match MessageDecoder::new(msg) {
+        Ok(dec) => dec.header().is_response() && dec.header().is_tentative(),
+        Err(_) => false,
+    }
+}
+
 /// Decodes an answer packet from a DNS resolver, adding any contained IP addresses to `ip_buf`.
 pub fn decode_answer(msg: &[u8], ip_buf: &mut Vec<IpAddr>) -> Result<(), Error> {
     let dec = MessageDecoder::new(msg)?;
@@ -185,3 +1119,488 @@ pub fn decode_answer(msg: &[u8], ip_buf: &mut Vec<IpAddr>) -> Result<(), Error>
 
     Ok(())
 }
+
+/// Decodes the records of type `qtype` owned by `name` in the answer section of `msg`, adding
+/// those not yet in `records` to it.
+///
+/// `CNAME` records in the answer section are followed, so that records owned by the canonical
+/// name of `name` are decoded as well. Records of unsupported types are skipped.
+pub fn decode_records_answer(
+    msg: &[u8],
+    name: &DomainName,
+    qtype: QType,
+    records: &mut Vec<Record<'static>>,
+) -> Result<(), Error> {
+    let dec = MessageDecoder::new(msg)?;
+    if !dec.header().is_response() {
+        return Ok(());
+    }
+
+    let mut owners = vec![name.clone()];
+    for res in dec.answers()?.iter() {
+        let ans = res?;
+        if !owners
+            .iter()
+            .any(|owner| owner.eq_ignore_ascii_case(ans.name()))
+        {
+            continue;
+        }
+        let Some(record) = ans.as_enum().transpose()? else {
+            continue;
+        };
+        if let Record::CNAME(cname) = &record {
+            owners.push(cname.cname().clone());
+        }
+        if qtype.matches(ans.type_()) && !records.contains(&record) {
+            records.push(record.into_owned());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::Type;
+
+    use super::*;
+
+    #[test]
+    fn llmnr_query_header() {
+        let name = DomainName::from_str("printer").unwrap();
+        let mut buf = [0; DNS_BUFFER_SIZE];
+        let query = encode_llmnr_query(&mut buf, 42, &name, QType::AAAA);
+        let mut dec = MessageDecoder::new(query).unwrap();
+        assert!(dec.header().is_query());
+        assert!(!dec.header().is_tentative());
+        assert_eq!(dec.header().id(), 42);
+        assert_eq!(dec.header().question_count(), 1);
+        assert_eq!(dec.next().unwrap().unwrap().qtype(), QType::AAAA);
+
+        let mut response = query.to_vec();
+        response[2] |= 0x80; // QR
+        assert!(!is_tentative_response(&response));
+        response[2] |= 0x01; // T
+        assert!(is_tentative_response(&response));
+    }
+
+    #[test]
+    fn llmnr_separate_queries() {
+        use crate::packet::{
+            encoder::ResourceRecord,
+            records::{A, AAAA},
+        };
+
+        let responder = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = responder.local_addr().unwrap();
+        let thread = std::thread::spawn(move || {
+            let mut qtypes = Vec::new();
+            for _ in 0..2 {
+                let mut buf = [0; MDNS_BUFFER_SIZE];
+                let (len, client) = responder.recv_from(&mut buf).unwrap();
+                let mut dec = MessageDecoder::new(&buf[..len]).unwrap();
+                let header = *dec.header();
+                assert_eq!(header.question_count(), 1);
+                let question = dec.next().unwrap().unwrap();
+                let record = match question.qtype() {
+                    QType::A => Record::A(A::new(Ipv4Addr::new(192, 0, 2, 1))),
+                    _ => Record::AAAA(AAAA::new(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))),
+                };
+                qtypes.push(question.qtype());
+
+                let mut resp = [0; MDNS_BUFFER_SIZE];
+                let mut enc = MessageEncoder::response_to(&mut resp, &header, [&question]);
+                enc.add_answer(ResourceRecord::new(question.qname(), &record).ttl(30));
+                let len = enc.finish().unwrap();
+                responder.send_to(&resp[..len], client).unwrap();
+            }
+            qtypes
+        });
+
+        let mut resolver = SyncResolver::new(addr).unwrap();
+        resolver.protocol = Protocol::Llmnr;
+        resolver.set_timeout(Duration::from_secs(5)).unwrap();
+        let name = DomainName::from_str("printer").unwrap();
+        let mut ips = resolver.resolve_domain(&name).unwrap().collect::<Vec<_>>();
+        ips.sort();
+        assert_eq!(
+            ips,
+            [
+                "192.0.2.1".parse::<IpAddr>().unwrap(),
+                "2001:db8::1".parse::<IpAddr>().unwrap(),
+            ]
+        );
+        assert_eq!(thread.join().unwrap(), [QType::A, QType::AAAA]);
+    }
+
+    #[test]
+    fn caller_provided_socket() {
+        use crate::packet::{encoder::ResourceRecord, records::A};
+
+        let responder = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = responder.local_addr().unwrap();
+        let thread = std::thread::spawn(move || {
+            let mut buf = [0; MDNS_BUFFER_SIZE];
+            let (len, client) = responder.recv_from(&mut buf).unwrap();
+            let mut dec = MessageDecoder::new(&buf[..len]).unwrap();
+            let header = *dec.header();
+            let question = dec.next().unwrap().unwrap();
+            let record = Record::A(A::new(Ipv4Addr::new(192, 0, 2, 1)));
+            let mut resp = [0; MDNS_BUFFER_SIZE];
+            let mut enc = MessageEncoder::response_to(&mut resp, &header, [&question]);
+            enc.add_answer(ResourceRecord::new(question.qname(), &record).ttl(30));
+            let len = enc.finish().unwrap();
+            responder.send_to(&resp[..len], client).unwrap();
+            client
+        });
+
+        let sock = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let local = sock.local_addr().unwrap();
+        let handle = sock.try_clone().unwrap();
+        let mut resolver = SyncResolver::with_socket(sock, addr).unwrap();
+        // The read timeout of the socket is overwritten.
+        assert_eq!(
+            handle.read_timeout().unwrap(),
+            Some(SyncResolver::DEFAULT_TIMEOUT)
+        );
+        resolver.set_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(handle.read_timeout().unwrap(), Some(Duration::from_secs(5)));
+
+        let name = DomainName::from_str("example.com").unwrap();
+        let ips = resolver.resolve_domain(&name).unwrap().collect::<Vec<_>>();
+        assert_eq!(ips, ["192.0.2.1".parse::<IpAddr>().unwrap()]);
+        // The query was sent from the provided socket.
+        assert_eq!(thread.join().unwrap(), local);
+    }
+
+    #[test]
+    fn retransmission_clock() {
+        use crate::{
+            clock::ManualClock,
+            packet::{encoder::ResourceRecord, records::A},
+        };
+
+        // Ignores the first query, but makes the timeout pass and wakes up the resolver with a
+        // response to another query. Answers the retransmitted query.
+        let clock = ManualClock::new();
+        let responder = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = responder.local_addr().unwrap();
+        let thread = std::thread::spawn({
+            let clock = clock.clone();
+            move || {
+                let mut buf = [0; MDNS_BUFFER_SIZE];
+                let (len, client) = responder.recv_from(&mut buf).unwrap();
+                let first = buf[..len].to_vec();
+                clock.advance(Duration::from_secs(3600));
+                let mut stray = first.clone();
+                stray[0] ^= 0xff;
+                stray[2] |= 0x80; // QR
+                responder.send_to(&stray, client).unwrap();
+
+                let (len, _) = responder.recv_from(&mut buf).unwrap();
+                assert_eq!(buf[..len], first);
+                let mut dec = MessageDecoder::new(&first).unwrap();
+                let header = *dec.header();
+                let question = dec.next().unwrap().unwrap();
+                let record = Record::A(A::new(Ipv4Addr::new(192, 0, 2, 1)));
+                let mut resp = [0; MDNS_BUFFER_SIZE];
+                let mut enc = MessageEncoder::response_to(&mut resp, &header, [&question]);
+                enc.add_answer(ResourceRecord::new(question.qname(), &record).ttl(30));
+                let len = enc.finish().unwrap();
+                clock.advance(Duration::from_millis(20));
+                responder.send_to(&resp[..len], client).unwrap();
+            }
+        });
+
+        // With the system clock, this would wait for an hour before retransmitting.
+        let mut resolver = SyncResolver::new(addr).unwrap();
+        resolver.set_timeout(Duration::from_secs(3600)).unwrap();
+        resolver.set_clock(clock);
+        resolver.enable_query_log(4);
+        let name = DomainName::from_str("example.com").unwrap();
+        let ips = resolver.resolve_domain(&name).unwrap().collect::<Vec<_>>();
+        assert_eq!(ips, ["192.0.2.1".parse::<IpAddr>().unwrap()]);
+        thread.join().unwrap();
+
+        // The round-trip time is measured from the retransmission.
+        let log = resolver.query_log().unwrap();
+        let rtts = log.iter().map(|entry| entry.rtt()).collect::<Vec<_>>();
+        assert_eq!(rtts, [None, Some(Duration::from_millis(20))]);
+    }
+
+    #[test]
+    fn ignores_mismatched_ids() {
+        use crate::packet::{encoder::ResourceRecord, records::A};
+
+        let responder = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = responder.local_addr().unwrap();
+        let thread = std::thread::spawn(move || {
+            let mut buf = [0; MDNS_BUFFER_SIZE];
+            let (len, client) = responder.recv_from(&mut buf).unwrap();
+            let mut dec = MessageDecoder::new(&buf[..len]).unwrap();
+            let header = *dec.header();
+            let question = dec.next().unwrap().unwrap();
+
+            // Responds to a different query first, then to the actual one.
+            for (id, ip) in [
+                (header.id().wrapping_add(1), Ipv4Addr::new(192, 0, 2, 66)),
+                (header.id(), Ipv4Addr::new(192, 0, 2, 1)),
+            ] {
+                let mut header = header;
+                header.set_id(id);
+                let record = Record::A(A::new(ip));
+                let mut resp = [0; MDNS_BUFFER_SIZE];
+                let mut enc = MessageEncoder::response_to(&mut resp, &header, [&question]);
+                enc.add_answer(ResourceRecord::new(question.qname(), &record).ttl(30));
+                let len = enc.finish().unwrap();
+                responder.send_to(&resp[..len], client).unwrap();
+            }
+        });
+
+        let mut resolver = SyncResolver::new(addr).unwrap();
+        resolver.set_timeout(Duration::from_secs(5)).unwrap();
+        let name = DomainName::from_str("example.com").unwrap();
+        let ips = resolver.resolve_domain(&name).unwrap().collect::<Vec<_>>();
+        assert_eq!(ips, ["192.0.2.1".parse::<IpAddr>().unwrap()]);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn edns_query() {
+        let name = DomainName::from_str("example.com").unwrap();
+        let mut buf = [0; DNS_BUFFER_SIZE];
+        let query = encode_edns_query(&mut buf, &name, 4096);
+        let dec = MessageDecoder::new(query).unwrap();
+        assert_eq!(dec.header().question_count(), 2);
+        assert_eq!(dec.header().additional_count(), 1);
+
+        let mut dec = dec.additional().unwrap();
+        let opt = dec.next().unwrap().unwrap();
+        assert_eq!(opt.name(), &DomainName::ROOT);
+        assert_eq!(opt.type_(), Type::OPT);
+        assert_eq!(opt.class().0, 4096);
+        assert!(opt.rdata().is_empty());
+        assert!(dec.next().is_none());
+    }
+
+    #[test]
+    fn chain_order() {
+        let hosts = |s| hosts::HostsFile::parse(s);
+        let name = DomainName::from_str("nas").unwrap();
+
+        let mut chain = ChainedResolver::new();
+        assert!(chain.resolve_name(&name).unwrap().is_empty());
+
+        chain.push(hosts("10.0.0.1 printer"));
+        chain.push(hosts("10.0.0.2 nas"));
+        chain.push(hosts("10.0.0.3 nas"));
+        assert_eq!(
+            chain.resolve_name(&name).unwrap(),
+            ["10.0.0.2".parse::<IpAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn protocol_detection() {
+        assert_eq!(
+            Protocol::for_server("8.8.8.8:53".parse().unwrap()),
+            Protocol::Dns
+        );
+        assert_eq!(
+            Protocol::for_server("224.0.0.251:5353".parse().unwrap()),
+            Protocol::Mdns
+        );
+        assert_eq!(
+            Protocol::for_server("[ff02::1:3]:5355".parse().unwrap()),
+            Protocol::Llmnr
+        );
+    }
+
+    #[test]
+    fn error_response() {
+        use crate::{
+            acl::Acl,
+            packet::edns::ExtendedErrorCode,
+            server::{SyncServer, Zone},
+        };
+
+        let mut server = SyncServer::new(
+            (Ipv4Addr::LOCALHOST, 0).into(),
+            Zone::new("example.com".parse().unwrap()),
+        )
+        .unwrap();
+        let mut acl = Acl::new();
+        acl.deny("127.0.0.0/8".parse().unwrap());
+        server.set_acl(acl);
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || server.listen_blocking());
+
+        let mut resolver = SyncResolver::new(addr).unwrap();
+        resolver.set_max_message_size(1232);
+        resolver.set_timeout(Duration::from_secs(5)).unwrap();
+        let Err(Error::Resolve(e)) = resolver.resolve("www.example.com").map(|_| ()) else {
+            panic!("expected an error response");
+        };
+        assert_eq!(e.server(), addr);
+        assert_eq!(e.rcode(), RCode::REFUSED);
+        assert_eq!(e.extended_errors().len(), 1);
+        assert_eq!(e.extended_errors()[0].code(), ExtendedErrorCode::PROHIBITED);
+        assert_eq!(
+            Error::from(*e).to_string(),
+            format!("query failed: {addr} responded with REFUSED (Prohibited)")
+        );
+    }
+
+    #[test]
+    fn tcp_fallback() {
+        use crate::{
+            packet::records::A,
+            server::{SyncServer, Zone},
+        };
+
+        // 40 `A` records don't fit into a 512-byte UDP response.
+        let name = DomainName::from_str("big.example.com").unwrap();
+        let mut zone = Zone::new("example.com".parse().unwrap());
+        for i in 0..40 {
+            let a = Record::A(A::new(Ipv4Addr::new(192, 0, 2, i)));
+            zone.add(name.clone(), 300, a).unwrap();
+        }
+        let mut server = SyncServer::new((Ipv4Addr::LOCALHOST, 0).into(), zone).unwrap();
+        server.set_tcp_idle_timeout(Duration::from_secs(30));
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || server.listen_blocking());
+
+        let mut resolver = SyncResolver::new(addr).unwrap();
+        resolver.set_timeout(Duration::from_secs(5)).unwrap();
+        resolver.enable_query_log(4);
+        assert_eq!(resolver.resolve_domain(&name).unwrap().count(), 40);
+        let local_addr = |resolver: &SyncResolver| {
+            let client = &resolver.tcp_clients[0].1;
+            client.get_ref().unwrap().local_addr().unwrap()
+        };
+        let conn = local_addr(&resolver);
+        let records = resolver.resolve_records(&name, QType::A).unwrap();
+        assert_eq!(records.len(), 40);
+
+        // Both TCP queries use the same connection, which is kept open for as long as the server
+        // announced.
+        assert_eq!(resolver.tcp_clients.len(), 1);
+        assert_eq!(local_addr(&resolver), conn);
+        assert_eq!(
+            resolver.tcp_clients[0].1.pipeline().idle_timeout(),
+            Some(Duration::from_secs(30))
+        );
+
+        let truncated = resolver
+            .query_log()
+            .unwrap()
+            .iter()
+            .map(|entry| {
+                let response = entry.response().unwrap();
+                *MessageDecoder::new(response).unwrap().header()
+            })
+            .map(|header| header.is_truncated())
+            .collect::<Vec<_>>();
+        assert_eq!(truncated, [true, false, true, false]);
+    }
+
+    #[test]
+    fn query() {
+        use crate::{
+            packet::records::{SRV, TXT},
+            server::{SyncServer, Zone},
+        };
+
+        let mut zone = Zone::new("example.com".parse().unwrap());
+        let name = DomainName::from_str("_ldap._tcp.example.com").unwrap();
+        let target = DomainName::from_str("dc1.example.com").unwrap();
+        let srv = Record::SRV(SRV::new(0, 100, 389, target.clone()));
+        let txt = Record::TXT(TXT::new([&b"site=main"[..]]));
+        zone.add(name.clone(), 300, srv.clone()).unwrap();
+        zone.add(name.clone(), 300, txt.clone()).unwrap();
+        let mut server = SyncServer::new((Ipv4Addr::LOCALHOST, 0).into(), zone).unwrap();
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || server.listen_blocking());
+
+        let mut resolver = SyncResolver::new(addr).unwrap();
+        resolver.set_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(resolver.query(&name, QType::SRV).unwrap(), [srv]);
+        assert_eq!(resolver.query(&name, QType::TXT).unwrap(), [txt]);
+        assert!(resolver.query(&target, QType::TXT).unwrap().is_empty());
+    }
+
+    #[test]
+    fn ptr_answer() {
+        use crate::packet::{encoder::ResourceRecord, records::PTR};
+
+        let addr = IpAddr::from(Ipv4Addr::new(192, 0, 2, 1));
+        let name = DomainName::arpa(addr);
+        let other = DomainName::arpa(Ipv4Addr::new(192, 0, 2, 2).into());
+        let upper = DomainName::from_str(&name.to_string().to_uppercase()).unwrap();
+        let printer = DomainName::from_str("printer.example.com").unwrap();
+        let alias = DomainName::from_str("alias.example.com").unwrap();
+        let answers = [
+            (upper, printer.clone()),
+            (
+                other,
+                DomainName::from_str("unrelated.example.com").unwrap(),
+            ),
+            (name.clone(), alias.clone()),
+            (name, DomainName::from_str("PRINTER.example.com").unwrap()),
+        ];
+
+        let responder = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let server = responder.local_addr().unwrap();
+        let thread = std::thread::spawn(move || {
+            let mut buf = [0; MDNS_BUFFER_SIZE];
+            let (len, client) = responder.recv_from(&mut buf).unwrap();
+            let mut dec = MessageDecoder::new(&buf[..len]).unwrap();
+            let header = *dec.header();
+            let question = dec.next().unwrap().unwrap();
+            assert_eq!(question.qtype(), QType::PTR);
+
+            let mut resp = [0; MDNS_BUFFER_SIZE];
+            let mut enc = MessageEncoder::response_to(&mut resp, &header, [&question]);
+            for (owner, target) in &answers {
+                let ptr = Record::PTR(PTR::new(target.clone()));
+                enc.add_answer(ResourceRecord::new(owner, &ptr).ttl(300));
+            }
+            let len = enc.finish().unwrap();
+            responder.send_to(&resp[..len], client).unwrap();
+        });
+
+        // Owner names are compared case-insensitively, and duplicate targets are skipped.
+        let mut resolver = SyncResolver::new(server).unwrap();
+        resolver.set_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(resolver.resolve_hostname(addr).unwrap(), [printer, alias]);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn reverse_lookup() {
+        use crate::{
+            packet::records::PTR,
+            server::{SyncServer, Zone},
+        };
+
+        let mut zone = Zone::new("2.0.192.in-addr.arpa".parse().unwrap());
+        let host = DomainName::from_str("printer.example.com").unwrap();
+        let ptr = Record::PTR(PTR::new(host.clone()));
+        zone.add("1.2.0.192.in-addr.arpa".parse().unwrap(), 300, ptr)
+            .unwrap();
+        let mut server = SyncServer::new((Ipv4Addr::LOCALHOST, 0).into(), zone).unwrap();
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || server.listen_blocking());
+
+        let mut resolver = SyncResolver::new(addr).unwrap();
+        resolver.set_timeout(Duration::from_secs(5)).unwrap();
+        let names = resolver
+            .resolve_addr(Ipv4Addr::new(192, 0, 2, 1).into())
+            .unwrap();
+        assert_eq!(names, [host]);
+        let names = resolver
+            .resolve_addr(Ipv4Addr::new(192, 0, 2, 2).into())
+            .unwrap();
+        assert!(names.is_empty());
+    }
+}
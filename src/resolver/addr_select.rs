@@ -0,0 +1,305 @@
+//! Destination and source address selection ([RFC 6724]).
+//!
+//! Host names often resolve to several addresses, and not all of them are equally good: an IPv6
+//! address is useless on a host without IPv6 connectivity, and a link-local address is only
+//! reachable from the local link. RFC 6724 defines an order in which the addresses should be
+//! tried, based on the source addresses the local host would use to reach them.
+//!
+//! [`sort_destinations`] implements that order, given the addresses of the local interfaces.
+//! [`source_addrs`] determines suitable local addresses by asking the operating system which
+//! source address it would use for each destination. [`resolve_socket_addrs`] uses both to order
+//! the addresses it returns.
+//!
+//! The rules that depend on information not available here (deprecated, home and temporary
+//! addresses, and the outgoing interface) are not implemented. The default policy table from
+//! [RFC 6724, section 2.1] is used.
+//!
+//! [RFC 6724]: https://datatracker.ietf.org/doc/html/rfc6724
+//! [RFC 6724, section 2.1]: https://datatracker.ietf.org/doc/html/rfc6724#section-2.1
+//! [`resolve_socket_addrs`]: super::resolve_socket_addrs
+
+use std::{
+    cmp::Reverse,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+};
+
+/// The default policy table: prefix, prefix length, precedence and label.
+///
+/// IPv4 addresses are looked up as IPv4-mapped IPv6 addresses. More specific prefixes come before
+/// the prefixes containing them, so the first match is the longest one.
+const POLICY_TABLE: &[(u128, u8, u8, u8)] = &[
+    (0x0000_0000_0000_0000_0000_0000_0000_0001, 128, 50, 0), // ::1/128
+    (0x0000_0000_0000_0000_0000_ffff_0000_0000, 96, 35, 4),  // ::ffff:0:0/96
+    (0x2002_0000_0000_0000_0000_0000_0000_0000, 16, 30, 2),  // 2002::/16
+    (0x2001_0000_0000_0000_0000_0000_0000_0000, 32, 5, 5),   // 2001::/32
+    (0xfc00_0000_0000_0000_0000_0000_0000_0000, 7, 3, 13),   // fc00::/7
+    (0x0000_0000_0000_0000_0000_0000_0000_0000, 96, 1, 3),   // ::/96
+    (0xfec0_0000_0000_0000_0000_0000_0000_0000, 10, 1, 11),  // fec0::/10
+    (0x3ffe_0000_0000_0000_0000_0000_0000_0000, 16, 1, 12),  // 3ffe::/16
+    (0x0000_0000_0000_0000_0000_0000_0000_0000, 0, 40, 1),   // ::/0
+];
+
+// Address scopes, as defined in RFC 4291 and RFC 6724, section 3.
+const SCOPE_LINK_LOCAL: u8 = 0x2;
+const SCOPE_SITE_LOCAL: u8 = 0x5;
+const SCOPE_GLOBAL: u8 = 0xe;
+
+/// Sorts `destinations` from most to least preferred, according to the destination address
+/// selection rules of [RFC 6724, section 6].
+///
+/// `sources` are the addresses of the local interfaces. Destinations that can't be reached from
+/// any of them (for example, IPv6 addresses on a host without IPv6 addresses) are moved to the
+/// end. Addresses that are equally preferred keep their relative order.
+///
+/// [RFC 6724, section 6]: https://datatracker.ietf.org/doc/html/rfc6724#section-6
+pub fn sort_destinations(destinations: &mut [IpAddr], sources: &[IpAddr]) {
+    destinations.sort_by_cached_key(|&destination| {
+        let destination = destination.to_canonical();
+        let source = select_source(destination, sources);
+        let scope_d = scope(destination);
+        let (precedence_d, label_d) = policy(destination);
+        (
+            // Rule 1: Avoid unusable destinations.
+            source.is_none(),
+            // Rule 2: Prefer matching scope.
+            source.is_none_or(|source| scope(source) != scope_d),
+            // Rule 5: Prefer matching label.
+            source.is_none_or(|source| policy(source).1 != label_d),
+            // Rule 6: Prefer higher precedence.
+            Reverse(precedence_d),
+            // Rule 8: Prefer smaller scope.
+            scope_d,
+            // Rule 9: Use longest matching prefix. Addresses of different families never get here,
+            // since they differ in precedence.
+            Reverse(source.map_or(0, |source| common_prefix_len(source, destination))),
+        )
+    });
+}
+
+/// Removes the destinations that can't be reached from any of `sources`.
+///
+/// A destination is considered reachable if [`select_source`] finds a source address for it.
+pub fn retain_usable(destinations: &mut Vec<IpAddr>, sources: &[IpAddr]) {
+    destinations.retain(|&destination| select_source(destination, sources).is_some());
+}
+
+/// Selects the address from `sources` that should be used to communicate with `destination`,
+/// according to the source address selection rules of [RFC 6724, section 5].
+///
+/// Only addresses of the same family as `destination` are considered. Returns [`None`] if there
+/// are none.
+///
+/// [RFC 6724, section 5]: https://datatracker.ietf.org/doc/html/rfc6724#section-5
+pub fn select_source(destination: IpAddr, sources: &[IpAddr]) -> Option<IpAddr> {
+    let destination = destination.to_canonical();
+    let scope_d = scope(destination);
+    let label_d = policy(destination).1;
+    sources
+        .iter()
+        .map(|source| source.to_canonical())
+        .filter(|source| source.is_ipv4() == destination.is_ipv4())
+        .min_by_key(|&source| {
+            let scope_s = scope(source);
+            (
+                // Rule 1: Prefer same address.
+                source != destination,
+                // Rule 2: Prefer appropriate scope: the smallest scope that is at least as large
+                // as the destination's, or else the largest one.
+                if scope_s >= scope_d {
+                    (false, scope_s)
+                } else {
+                    (true, u8::MAX - scope_s)
+                },
+                // Rule 6: Prefer matching label.
+                policy(source).1 != label_d,
+                // Rule 8: Use longest matching prefix.
+                Reverse(common_prefix_len(source, destination)),
+            )
+        })
+}
+
+/// Returns the source addresses the operating system would use to reach each of `destinations`.
+///
+/// This works by "connecting" a UDP socket to each destination, which selects a route and a
+/// source address without sending any packets. Destinations without a route are skipped, and
+/// duplicate source addresses are only returned once. The result can be passed as the `sources`
+/// of [`sort_destinations`].
+pub fn source_addrs(destinations: &[IpAddr]) -> Vec<IpAddr> {
+    let mut sources = Vec::new();
+    for &destination in destinations {
+        let destination = destination.to_canonical();
+        let unspecified: IpAddr = match destination {
+            IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let source = UdpSocket::bind((unspecified, 0))
+            .and_then(|sock| {
+                // The port doesn't matter, but 0 is rejected by some platforms.
+                sock.connect(SocketAddr::new(destination, 9))?;
+                sock.local_addr()
+            })
+            .map(|addr| addr.ip());
+        match source {
+            Ok(source) => {
+                if !sources.contains(&source) {
+                    sources.push(source);
+                }
+            }
+            Err(e) => log::trace!("no source address for {}: {}", destination, e),
+        }
+    }
+    sources
+}
+
+/// Returns the precedence and label of `ip` in the default policy table.
+fn policy(ip: IpAddr) -> (u8, u8) {
+    let bits = u128::from(to_ipv6(ip));
+    POLICY_TABLE
+        .iter()
+        .find(|&&(prefix, len, ..)| {
+            let mask = u128::MAX.checked_shl(128 - u32::from(len)).unwrap_or(0);
+            bits & mask == prefix
+        })
+        .map(|&(.., precedence, label)| (precedence, label))
+        .expect("policy table contains ::/0")
+}
+
+/// Returns the scope of `ip` ([RFC 6724, section 3.1]).
+///
+/// [RFC 6724, section 3.1]: https://datatracker.ietf.org/doc/html/rfc6724#section-3.1
+fn scope(ip: IpAddr) -> u8 {
+    match ip {
+        IpAddr::V4(ip) if ip.is_loopback() || ip.is_link_local() => SCOPE_LINK_LOCAL,
+        IpAddr::V4(_) => SCOPE_GLOBAL,
+        IpAddr::V6(ip) if ip.is_multicast() => ip.octets()[1] & 0x0f,
+        IpAddr::V6(ip) if ip.is_loopback() || ip.segments()[0] & 0xffc0 == 0xfe80 => {
+            SCOPE_LINK_LOCAL
+        }
+        IpAddr::V6(ip) if ip.segments()[0] & 0xffc0 == 0xfec0 => SCOPE_SITE_LOCAL,
+        IpAddr::V6(_) => SCOPE_GLOBAL,
+    }
+}
+
+/// Returns the number of leading bits `a` and `b` have in common.
+///
+/// For IPv6 addresses, only the first 64 bits (the usual prefix length) are compared, so that the
+/// interface identifiers don't influence the result.
+fn common_prefix_len(a: IpAddr, b: IpAddr) -> u32 {
+    match (a, b) {
+        (IpAddr::V4(a), IpAddr::V4(b)) => (u32::from(a) ^ u32::from(b)).leading_zeros(),
+        (IpAddr::V6(a), IpAddr::V6(b)) => (u128::from(a) ^ u128::from(b)).leading_zeros().min(64),
+        _ => 0,
+    }
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ips(s: &str) -> Vec<IpAddr> {
+        s.split_whitespace().map(|ip| ip.parse().unwrap()).collect()
+    }
+
+    fn sorted(destinations: &str, sources: &str) -> Vec<IpAddr> {
+        let mut destinations = ips(destinations);
+        sort_destinations(&mut destinations, &ips(sources));
+        destinations
+    }
+
+    /// The examples from RFC 6724, section 10.2.
+    #[test]
+    fn rfc_examples() {
+        // Prefer matching scope.
+        assert_eq!(
+            sorted(
+                "2001:db8:1::1 198.51.100.121",
+                "2001:db8:1::2 fe80::1 169.254.13.78"
+            ),
+            ips("2001:db8:1::1 198.51.100.121"),
+        );
+        assert_eq!(
+            sorted("2001:db8:1::1 198.51.100.121", "fe80::1 198.51.100.117"),
+            ips("198.51.100.121 2001:db8:1::1"),
+        );
+        // Prefer higher precedence.
+        assert_eq!(
+            sorted("2001:db8:1::1 10.1.2.3", "2001:db8:1::2 fe80::1 10.1.2.4"),
+            ips("2001:db8:1::1 10.1.2.3"),
+        );
+        // Prefer smaller scope.
+        assert_eq!(
+            sorted("2001:db8:1::1 fe80::1", "2001:db8:1::2 fe80::2"),
+            ips("fe80::1 2001:db8:1::1"),
+        );
+        // Longest matching prefix.
+        assert_eq!(
+            sorted(
+                "2001:db8:1::1 2001:db8:3ffe::1",
+                "2001:db8:1::2 2001:db8:3f44::2 fe80::2"
+            ),
+            ips("2001:db8:1::1 2001:db8:3ffe::1"),
+        );
+        // Prefer matching label.
+        assert_eq!(
+            sorted(
+                "2002:c633:6401::1 2001:db8:1::1",
+                "2002:c633:6401::2 fe80::2"
+            ),
+            ips("2002:c633:6401::1 2001:db8:1::1"),
+        );
+        assert_eq!(
+            sorted(
+                "2002:c633:6401::1 2001:db8:1::1",
+                "2002:c633:6401::2 2001:db8:1::2 fe80::2"
+            ),
+            ips("2001:db8:1::1 2002:c633:6401::1"),
+        );
+        // Prefer higher precedence (global unicast vs. ULA).
+        assert_eq!(
+            sorted("2001:db8:1::1 fd00::1", "2001:db8:1::2 fd00::2"),
+            ips("2001:db8:1::1 fd00::1"),
+        );
+    }
+
+    #[test]
+    fn unusable() {
+        assert_eq!(
+            sorted("2001:db8::1 192.0.2.1 2001:db8::2", "192.0.2.100"),
+            ips("192.0.2.1 2001:db8::1 2001:db8::2"),
+        );
+        let mut destinations = ips("2001:db8::1 192.0.2.1 ::ffff:192.0.2.2");
+        retain_usable(&mut destinations, &ips("192.0.2.100"));
+        assert_eq!(destinations, ips("192.0.2.1 ::ffff:192.0.2.2"));
+    }
+
+    #[test]
+    fn sources() {
+        let sources = ips("2001:db8:1::2 fe80::1 169.254.13.78 198.51.100.117");
+        let select = |destination: &str| select_source(destination.parse().unwrap(), &sources);
+        assert_eq!(select("2001:db8:1::1"), Some(ips("2001:db8:1::2")[0]));
+        assert_eq!(select("fe80::2"), Some(ips("fe80::1")[0]));
+        assert_eq!(select("198.51.100.1"), Some(ips("198.51.100.117")[0]));
+        assert_eq!(select("169.254.1.1"), Some(ips("169.254.13.78")[0]));
+        assert_eq!(select("fe80::1"), Some(ips("fe80::1")[0]));
+        assert_eq!(select_source(ips("::1")[0], &ips("192.0.2.1")), None);
+    }
+
+    #[test]
+    fn policy_table() {
+        assert_eq!(policy(ips("::1")[0]), (50, 0));
+        assert_eq!(policy(ips("192.0.2.1")[0]), (35, 4));
+        assert_eq!(policy(ips("2001:db8::1")[0]), (40, 1));
+        assert_eq!(policy(ips("2001::1")[0]), (5, 5));
+        assert_eq!(policy(ips("fd12::1")[0]), (3, 13));
+        assert_eq!(scope(ips("ff02::1")[0]), SCOPE_LINK_LOCAL);
+        assert_eq!(scope(ips("127.0.0.1")[0]), SCOPE_LINK_LOCAL);
+        assert_eq!(scope(ips("10.0.0.1")[0]), SCOPE_GLOBAL);
+    }
+}
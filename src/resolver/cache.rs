@@ -0,0 +1,764 @@
+//! Caching of resolver answers.
+//!
+//! [`ResolverCache`] stores the records from DNS responses until their TTL runs out, and
+//! [`CachingResolver`] uses it to answer repeated queries of a [`SyncResolver`] without contacting
+//! the network again.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    name::DomainName,
+    packet::{decoder::MessageDecoder, records::Record, Class, QType, RCode, Type},
+    Error,
+};
+
+use super::{Resolve, SyncResolver};
+
+/// The longest chain of `CNAME` records that is followed when looking up cached records.
+const MAX_CNAME_CHAIN: usize = 8;
+
+/// A cache of resource records, keyed by their owner name, type and class.
+///
+/// Records are inserted from complete DNS responses via [`ResolverCache::insert_response`], and
+/// each set of records with the same owner name, type and class is kept until the lowest TTL among
+/// them has passed. Owner names are compared ignoring ASCII case.
+///
+/// Empty answers are cached as well, if the response contains the `SOA` record of the zone, as
+/// described in [RFC 2308]. Those entries expire after the `SOA`'s negative caching TTL.
+///
+/// With the `persistent-cache` feature, the cache can be saved to disk with `ResolverCache::save`,
+/// so that later runs of the program can reuse it.
+///
+/// [RFC 2308]: https://datatracker.ietf.org/doc/html/rfc2308
+#[derive(Debug, Clone, Default)]
+pub struct ResolverCache {
+    entries: HashMap<(DomainName, Type, Class), CacheEntry>,
+}
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    records: Vec<Record<'static>>,
+    received_at: Instant,
+    ttl: Duration,
+}
+
+impl CacheEntry {
+    fn expires_at(&self) -> Instant {
+        self.received_at + self.ttl
+    }
+}
+
+/// A set of records returned by [`ResolverCache::get`], along with when they were received.
+///
+/// When passing cached records on (for example, in DNS responses), use
+/// [`CachedRecords::remaining_ttl`] rather than [`CachedRecords::original_ttl`], so that
+/// downstream caches don't keep them for longer than the records' owner intended.
+#[derive(Debug, Clone, Copy)]
+pub struct CachedRecords<'a> {
+    records: &'a [Record<'static>],
+    received_at: Instant,
+    ttl: Duration,
+    expires_at: Instant,
+}
+
+impl<'a> CachedRecords<'a> {
+    /// Returns the cached records.
+    ///
+    /// An empty list means that the records are known not to exist.
+    #[inline]
+    pub fn records(&self) -> &'a [Record<'static>] {
+        self.records
+    }
+
+    /// Returns the point in time at which the records were received.
+    #[inline]
+    pub fn received_at(&self) -> Instant {
+        self.received_at
+    }
+
+    /// Returns the TTL the records had when they were received.
+    #[inline]
+    pub fn original_ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Returns the part of the TTL that is left at `now`.
+    ///
+    /// If the records were found by following cached `CNAME` records, this is limited by the
+    /// remaining TTL of those as well. Returns [`Duration::ZERO`] once the records have expired.
+    pub fn remaining_ttl(&self, now: Instant) -> Duration {
+        self.expires_at.saturating_duration_since(now)
+    }
+}
+
+impl ResolverCache {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the cached records of type `qtype` and class `class` owned by `name`, unless they
+    /// have expired at `now`.
+    ///
+    /// Cached `CNAME` records are followed, like [`decode_records_answer`] follows them in a
+    /// response. An empty list of records means that they are known not to exist, while [`None`]
+    /// means that nothing is known about them.
+    ///
+    /// Queries for more than one record type (like [`QType::ALL`]) are never answered from the
+    /// cache, since it can't tell whether it knows *all* matching records.
+    ///
+    /// [`decode_records_answer`]: super::decode_records_answer
+    pub fn get(
+        &self,
+        name: &DomainName,
+        qtype: QType,
+        class: Class,
+        now: Instant,
+    ) -> Option<CachedRecords<'_>> {
+        let ty = Type::try_from(qtype).ok()?;
+        let mut name = name.to_ascii_lowercase();
+        // The chain of `CNAME`s is only valid as long as all of its links are.
+        let mut chain_expires_at = None::<Instant>;
+        for _ in 0..MAX_CNAME_CHAIN {
+            if let Some(entry) = self.fresh(&name, ty, class, now) {
+                let expires_at = entry.expires_at();
+                return Some(CachedRecords {
+                    records: &entry.records,
+                    received_at: entry.received_at,
+                    ttl: entry.ttl,
+                    expires_at: chain_expires_at.map_or(expires_at, |at| at.min(expires_at)),
+                });
+            }
+            let entry = self.fresh(&name, Type::CNAME, class, now)?;
+            match entry.records.first() {
+                Some(Record::CNAME(cname)) => name = cname.cname().to_ascii_lowercase(),
+                _ => return None,
+            }
+            let expires_at = entry.expires_at();
+            chain_expires_at = Some(chain_expires_at.map_or(expires_at, |at| at.min(expires_at)));
+        }
+        None
+    }
+
+    fn fresh(
+        &self,
+        name: &DomainName,
+        ty: Type,
+        class: Class,
+        now: Instant,
+    ) -> Option<&CacheEntry> {
+        self.entries
+            .get(&(name.clone(), ty, class))
+            .filter(|entry| entry.expires_at() > now)
+    }
+
+    /// Caches the records in the *Answer* section of the response `msg`, which was received at
+    /// `now`.
+    ///
+    /// Each set of records replaces any cached records with the same owner name, type and class.
+    /// Sets containing a record with a TTL of 0 (like mDNS goodbye packets) are removed from the
+    /// cache instead. Records of unsupported types are skipped, and so are truncated responses,
+    /// since they might not contain every record of a set.
+    ///
+    /// Expired entries are removed.
+    pub fn insert_response(&mut self, msg: &[u8], now: Instant) -> Result<(), Error> {
+        let mut dec = MessageDecoder::new(msg)?;
+        let header = *dec.header();
+        if !header.is_response() || header.is_truncated() {
+            return Ok(());
+        }
+        self.remove_expired(now);
+
+        let questions = dec.iter().collect::<Result<Vec<_>, _>>()?;
+        let mut dec = dec.answers()?;
+        let mut rrsets: HashMap<(DomainName, Type, Class), (Vec<Record<'static>>, u32)> =
+            HashMap::new();
+        for rr in dec.iter() {
+            let rr = rr?;
+            let Some(record) = rr.as_enum().transpose()? else {
+                continue;
+            };
+            let key = (rr.name().to_ascii_lowercase(), rr.type_(), rr.class());
+            let (records, ttl) = rrsets.entry(key).or_insert((Vec::new(), u32::MAX));
+            *ttl = (*ttl).min(rr.ttl());
+            if !records.contains(&record) {
+                records.push(record.into_owned());
+            }
+        }
+
+        // Cache the absence of answers to the questions (RFC 2308). This needs the `SOA` record
+        // from the authority section, which determines how long the absence may be cached.
+        if header.rcode() == RCode::NO_ERROR || header.rcode() == RCode::NX_DOMAIN {
+            let mut dec = dec.authority()?;
+            let mut soa_ttl = None;
+            for rr in dec.iter() {
+                let rr = rr?;
+                if let Some(Record::SOA(soa)) = rr.as_enum().transpose()? {
+                    soa_ttl = Some(rr.ttl().min(soa.minimum_ttl()));
+                }
+            }
+            if let Some(soa_ttl) = soa_ttl {
+                let mut negative = Vec::new();
+                for q in &questions {
+                    let (Ok(ty), Ok(class)) =
+                        (Type::try_from(q.qtype()), Class::try_from(q.qclass()))
+                    else {
+                        continue;
+                    };
+                    let name = canonical_name(&rrsets, q.qname(), class);
+                    if !rrsets.contains_key(&(name.clone(), ty, class)) {
+                        negative.push((name, ty, class));
+                    }
+                }
+                for key in negative {
+                    rrsets.insert(key, (Vec::new(), soa_ttl));
+                }
+            }
+        }
+
+        for (key, (records, ttl)) in rrsets {
+            if ttl == 0 {
+                self.entries.remove(&key);
+            } else {
+                self.entries.insert(
+                    key,
+                    CacheEntry {
+                        records,
+                        received_at: now,
+                        ttl: Duration::from_secs(ttl.into()),
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes all entries that have expired at `now`.
+    pub fn remove_expired(&mut self, now: Instant) {
+        self.entries.retain(|_, entry| entry.expires_at() > now);
+    }
+
+    /// Removes all entries.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns the number of cached record sets, including expired ones that haven't been removed
+    /// yet.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Writes the record sets that haven't expired at `now` to `writer`.
+    ///
+    /// Each set is stored along with its remaining TTL, and the wall-clock time of the save is
+    /// recorded, so that [`ResolverCache::load`] can tell how much of the TTL is left by the time
+    /// the cache is loaded again (typically by a later invocation of the same program). Cached
+    /// negative answers are saved as well.
+    ///
+    /// The format is versioned; files written by incompatible versions of this library are
+    /// rejected when loading.
+    #[cfg(any(test, feature = "persistent-cache"))]
+    pub fn save(&self, writer: impl std::io::Write, now: Instant) -> Result<(), Error> {
+        let mut entries = Vec::new();
+        for ((name, ty, class), entry) in &self.entries {
+            let ttl = entry.expires_at().saturating_duration_since(now).as_secs();
+            if ttl == 0 {
+                continue;
+            }
+            let ttl = u32::try_from(ttl).unwrap_or(u32::MAX);
+            entries.push(encode_cached_rrset(name, *ty, *class, &entry.records, ttl)?);
+        }
+        crate::cache_file::write(
+            writer,
+            crate::cache_file::CacheKind::Resolver,
+            entries.iter().map(Vec::as_slice),
+        )
+    }
+
+    /// Loads a cache previously written by [`ResolverCache::save`] from `reader`.
+    ///
+    /// Record sets are treated as if they were received at `now`, with their TTLs reduced by the
+    /// (wall-clock) time that has passed since the cache was saved. Sets that have expired in the
+    /// meantime are dropped.
+    ///
+    /// Returns [`Error::InvalidValue`] if `reader` does not contain a resolver cache in a format
+    /// understood by this version of the library. Callers should usually just start with an empty
+    /// cache in that case.
+    #[cfg(any(test, feature = "persistent-cache"))]
+    pub fn load(reader: impl std::io::Read, now: Instant) -> Result<Self, Error> {
+        use crate::packet::Message;
+
+        let (age, entries) =
+            crate::cache_file::read(reader, crate::cache_file::CacheKind::Resolver)?;
+        let mut cache = Self::new();
+        for entry in entries {
+            if entry.len() < 4 {
+                return Err(Error::Eof);
+            }
+            let (ttl, msg) = entry.split_at(4);
+            let ttl = u32::from_be_bytes(ttl.try_into().unwrap());
+            let ttl = Duration::from_secs(ttl.into()).saturating_sub(age);
+            let msg = Message::decode(msg)?;
+            let question = msg.questions().first().ok_or(Error::InvalidValue)?;
+            let ty = Type::try_from(question.qtype())?;
+            let class = Class::try_from(question.qclass())?;
+            if ttl.is_zero() {
+                continue;
+            }
+            let records = msg
+                .answers()
+                .iter()
+                .filter_map(|rr| rr.record().cloned())
+                .collect();
+            cache.entries.insert(
+                (question.qname().to_ascii_lowercase(), ty, class),
+                CacheEntry {
+                    records,
+                    received_at: now,
+                    ttl,
+                },
+            );
+        }
+        Ok(cache)
+    }
+}
+
+/// Encodes a cached record set the way [`ResolverCache::save`] stores it: as its remaining TTL
+/// (a big-endian `u32`), followed by a DNS response whose question identifies the set, and whose
+/// answers are its records.
+///
+/// The TTL is stored separately since negative entries don't contain any records to carry it.
+#[cfg(any(test, feature = "persistent-cache"))]
+fn encode_cached_rrset(
+    name: &DomainName,
+    ty: Type,
+    class: Class,
+    records: &[Record<'static>],
+    ttl: u32,
+) -> Result<Vec<u8>, Error> {
+    use crate::packet::{
+        decoder::{OwnedResourceRecord, Question},
+        Header, Message,
+    };
+
+    let mut header = Header::default();
+    header.set_response(true);
+    let mut msg = Message::new(header);
+    let mut question = Question::new(name.clone(), ty.into());
+    question.set_qclass(class.into());
+    msg.questions_mut().push(question);
+    for record in records {
+        let mut rr = OwnedResourceRecord::new(name.clone(), record.clone());
+        rr.set_class(class);
+        rr.set_ttl(ttl);
+        msg.answers_mut().push(rr);
+    }
+
+    let mut buf = vec![0; 4 + usize::from(u16::MAX)];
+    buf[..4].copy_from_slice(&ttl.to_be_bytes());
+    let len = msg.encode(&mut buf[4..])?;
+    buf.truncate(4 + len);
+    Ok(buf)
+}
+
+/// Follows the `CNAME` records in `rrsets`, starting at `name`, and returns the lowercased name at
+/// the end of the chain.
+fn canonical_name(
+    rrsets: &HashMap<(DomainName, Type, Class), (Vec<Record<'static>>, u32)>,
+    name: &DomainName,
+    class: Class,
+) -> DomainName {
+    let mut name = name.to_ascii_lowercase();
+    for _ in 0..MAX_CNAME_CHAIN {
+        match rrsets
+            .get(&(name.clone(), Type::CNAME, class))
+            .and_then(|(records, _)| records.first())
+        {
+            Some(Record::CNAME(cname)) => name = cname.cname().to_ascii_lowercase(),
+            _ => break,
+        }
+    }
+    name
+}
+
+/// A [`SyncResolver`] that caches the answers it receives in a [`ResolverCache`].
+///
+/// Queries are answered from the cache while the records from a previous response are still
+/// fresh, and are only sent to the network once they have expired.
+pub struct CachingResolver {
+    resolver: SyncResolver,
+    cache: ResolverCache,
+    clock: Box<dyn Clock>,
+}
+
+impl CachingResolver {
+    /// Creates a [`CachingResolver`] that sends queries via `resolver`.
+    pub fn new(resolver: SyncResolver) -> Self {
+        Self {
+            resolver,
+            cache: ResolverCache::new(),
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    /// Sets the [`Clock`] used to determine whether cached records have expired.
+    ///
+    /// By default, the [`SystemClock`] is used.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Box::new(clock);
+    }
+
+    /// Returns a reference to the wrapped [`SyncResolver`].
+    #[inline]
+    pub fn resolver(&self) -> &SyncResolver {
+        &self.resolver
+    }
+
+    /// Returns a mutable reference to the wrapped [`SyncResolver`].
+    ///
+    /// Queries made directly through the [`SyncResolver`] bypass the cache.
+    #[inline]
+    pub fn resolver_mut(&mut self) -> &mut SyncResolver {
+        &mut self.resolver
+    }
+
+    /// Returns a reference to the [`ResolverCache`].
+    #[inline]
+    pub fn cache(&self) -> &ResolverCache {
+        &self.cache
+    }
+
+    /// Returns a mutable reference to the [`ResolverCache`].
+    #[inline]
+    pub fn cache_mut(&mut self) -> &mut ResolverCache {
+        &mut self.cache
+    }
+
+    /// Returns the wrapped [`SyncResolver`], discarding the cache.
+    pub fn into_inner(self) -> SyncResolver {
+        self.resolver
+    }
+
+    /// Resolves `hostname` like [`SyncResolver::resolve`], answering from the cache if possible.
+    pub fn resolve(&mut self, hostname: &str) -> Result<Vec<IpAddr>, Error> {
+        let name = DomainName::from_str(hostname)?;
+        self.resolve_domain(&name)
+    }
+
+    /// Resolves `name` like [`SyncResolver::resolve_domain`], answering from the cache if
+    /// possible.
+    ///
+    /// The cache is used if it contains fresh `A` or `AAAA` records of `name`. Since the resolver
+    /// asks for both in the same query, and returns the first response with any addresses, this
+    /// matches what an uncached query would return.
+    pub fn resolve_domain(&mut self, name: &DomainName) -> Result<Vec<IpAddr>, Error> {
+        let now = self.clock.now();
+        let a = self.cache.get(name, QType::A, Class::IN, now);
+        let aaaa = self.cache.get(name, QType::AAAA, Class::IN, now);
+        if a.is_some() || aaaa.is_some() {
+            log::trace!("answering query for '{}' from cache", name);
+            let ips = a
+                .into_iter()
+                .chain(aaaa)
+                .flat_map(|cached| cached.records())
+                .filter_map(|record| match record {
+                    Record::A(a) => Some(IpAddr::V4(a.addr().octets().into())),
+                    Record::AAAA(a) => Some(IpAddr::V6(a.addr().octets().into())),
+                    _ => None,
+                })
+                .collect();
+            return Ok(ips);
+        }
+
+        let cache = &mut self.cache;
+        let ips = self.resolver.resolve_domain_with(name, &mut |msg| {
+            if let Err(e) = cache.insert_response(msg, now) {
+                log::debug!("failed to cache response: {}", e);
+            }
+        })?;
+        Ok(ips.collect())
+    }
+
+    /// Looks up the records of type `qtype` owned by `name` like [`SyncResolver::query`],
+    /// answering from the cache if possible.
+    pub fn query(
+        &mut self,
+        name: &DomainName,
+        qtype: QType,
+    ) -> Result<Vec<Record<'static>>, Error> {
+        let now = self.clock.now();
+        if let Some(cached) = self.cache.get(name, qtype, Class::IN, now) {
+            log::trace!("answering {:?} query for '{}' from cache", qtype, name);
+            return Ok(cached.records().to_vec());
+        }
+
+        let cache = &mut self.cache;
+        self.resolver.lookup_with(name, qtype, &mut |msg| {
+            if let Err(e) = cache.insert_response(msg, now) {
+                log::debug!("failed to cache response: {}", e);
+            }
+        })
+    }
+}
+
+impl Resolve for CachingResolver {
+    fn resolve_name(&mut self, name: &DomainName) -> Result<Vec<IpAddr>, Error> {
+        self.resolve_domain(name)
+    }
+
+    fn resolve_records(
+        &mut self,
+        name: &DomainName,
+        qtype: QType,
+    ) -> Result<Vec<Record<'static>>, Error> {
+        self.query(name, qtype)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::{
+        clock::ManualClock,
+        domain,
+        packet::{
+            decoder::{OwnedResourceRecord, Question},
+            records::{A, CNAME, SOA},
+            Header, Message,
+        },
+        server::{SyncServer, Zone},
+    };
+
+    use super::*;
+
+    fn response(
+        questions: &[(&DomainName, QType)],
+        answers: &[(&DomainName, u32, Record<'_>)],
+        soa: Option<u32>,
+    ) -> Vec<u8> {
+        let mut header = Header::default();
+        header.set_response(true);
+        let mut msg = Message::new(header);
+        for (name, qtype) in questions {
+            msg.questions_mut()
+                .push(Question::new((*name).clone(), *qtype));
+        }
+        for (name, ttl, record) in answers {
+            let mut rr = OwnedResourceRecord::new((*name).clone(), record.clone().into_owned());
+            rr.set_ttl(*ttl);
+            msg.answers_mut().push(rr);
+        }
+        if let Some(ttl) = soa {
+            let soa = SOA::new(
+                domain!("ns.example.com"),
+                domain!("admin.example.com"),
+                1,
+                0,
+                0,
+                0,
+                30,
+            );
+            let mut rr = OwnedResourceRecord::new(domain!("example.com"), Record::SOA(soa));
+            rr.set_ttl(ttl);
+            msg.authority_mut().push(rr);
+        }
+        let mut buf = [0; 512];
+        let len = msg.encode(&mut buf).unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn expiry() {
+        let alias = domain!("WWW.example.com");
+        let host = domain!("host.example.com");
+        let a1 = Record::A(A::new(Ipv4Addr::new(192, 0, 2, 1)));
+        let a2 = Record::A(A::new(Ipv4Addr::new(192, 0, 2, 2)));
+        let cname = Record::CNAME(CNAME::new(host.clone()));
+        let msg = response(
+            &[(&alias, QType::A), (&alias, QType::AAAA)],
+            &[
+                (&alias, 300, cname),
+                (&host, 60, a1.clone()),
+                (&host, 120, a2.clone()),
+            ],
+            Some(3600),
+        );
+
+        let now = Instant::now();
+        let mut cache = ResolverCache::new();
+        cache.insert_response(&msg, now).unwrap();
+        // The `CNAME`, the `A` records, and the missing `AAAA` records of the canonical name.
+        assert_eq!(cache.len(), 3);
+
+        let get = |cache: &ResolverCache, qtype, secs| {
+            cache
+                .get(
+                    &domain!("www.example.com"),
+                    qtype,
+                    Class::IN,
+                    now + Duration::from_secs(secs),
+                )
+                .map(|cached| cached.records().len())
+        };
+        assert_eq!(get(&cache, QType::A, 0), Some(2));
+        assert_eq!(get(&cache, QType::CNAME, 0), Some(1));
+        // The negative TTL is the `SOA` minimum.
+        assert_eq!(get(&cache, QType::AAAA, 29), Some(0));
+        assert_eq!(get(&cache, QType::AAAA, 30), None);
+        // The lowest TTL of the set applies.
+        assert_eq!(get(&cache, QType::A, 59), Some(2));
+        assert_eq!(get(&cache, QType::A, 60), None);
+        assert_eq!(get(&cache, QType::ALL, 0), None);
+        assert_eq!(get(&cache, QType::MX, 0), None);
+
+        // A TTL of 0 removes the set.
+        let goodbye = response(&[], &[(&host, 0, a1)], None);
+        cache.insert_response(&goodbye, now).unwrap();
+        assert_eq!(get(&cache, QType::A, 0), None);
+
+        cache.remove_expired(now + Duration::from_secs(300));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn decayed_ttls() {
+        let alias = domain!("www.example.com");
+        let host = domain!("host.example.com");
+        let a = Record::A(A::new(Ipv4Addr::new(192, 0, 2, 1)));
+        let cname = Record::CNAME(CNAME::new(host.clone()));
+        let msg = response(
+            &[(&alias, QType::A)],
+            &[(&alias, 30, cname), (&host, 120, a.clone())],
+            None,
+        );
+
+        let received_at = Instant::now();
+        let mut cache = ResolverCache::new();
+        cache.insert_response(&msg, received_at).unwrap();
+
+        let now = received_at + Duration::from_secs(10);
+        let cached = cache.get(&host, QType::A, Class::IN, now).unwrap();
+        assert_eq!(cached.records(), [a]);
+        assert_eq!(cached.received_at(), received_at);
+        assert_eq!(cached.original_ttl(), Duration::from_secs(120));
+        assert_eq!(cached.remaining_ttl(now), Duration::from_secs(110));
+        assert_eq!(
+            cached.remaining_ttl(received_at + Duration::from_secs(200)),
+            Duration::ZERO
+        );
+
+        // The `CNAME` expires first, which limits how long the answer for the alias is valid.
+        let cached = cache.get(&alias, QType::A, Class::IN, now).unwrap();
+        assert_eq!(cached.original_ttl(), Duration::from_secs(120));
+        assert_eq!(cached.remaining_ttl(now), Duration::from_secs(20));
+    }
+
+    #[test]
+    fn persistent_resolver_cache() {
+        let name = domain!("Host.example.com");
+        let a = Record::A(A::new(Ipv4Addr::new(192, 0, 2, 1)));
+        let msg = response(
+            &[(&name, QType::A), (&name, QType::AAAA)],
+            &[(&name, 120, a.clone())],
+            Some(3600),
+        );
+
+        let start = Instant::now();
+        let mut cache = ResolverCache::new();
+        cache.insert_response(&msg, start).unwrap();
+        assert_eq!(cache.len(), 2);
+
+        let mut file = Vec::new();
+        let now = start + Duration::from_secs(10);
+        cache.save(&mut file, now).unwrap();
+
+        let loaded = ResolverCache::load(&file[..], now).unwrap();
+        assert_eq!(loaded.len(), 2);
+        let get = |qtype, secs| {
+            loaded
+                .get(&name, qtype, Class::IN, now + Duration::from_secs(secs))
+                .map(|cached| cached.records().to_vec())
+        };
+        // The saved TTLs were the remaining ones, so the sets expire at the same time as before
+        // (give or take the few seconds the test might take).
+        assert_eq!(get(QType::A, 100), Some(vec![a]));
+        assert_eq!(get(QType::A, 111), None);
+        // The negative answer survives as well.
+        assert_eq!(get(QType::AAAA, 10), Some(Vec::new()));
+        assert_eq!(get(QType::AAAA, 21), None);
+
+        // Sets that expired before the save are dropped.
+        let mut file = Vec::new();
+        cache
+            .save(&mut file, start + Duration::from_secs(60))
+            .unwrap();
+        let loaded = ResolverCache::load(&file[..], now).unwrap();
+        assert_eq!(loaded.len(), 1);
+
+        // Other kinds of data are rejected.
+        assert!(ResolverCache::load(&b"uwuhi"[..], now).is_err());
+    }
+
+    #[test]
+    fn caching_resolver() {
+        let apex = domain!("example.com");
+        let name = domain!("host.example.com");
+        let mut zone = Zone::new(apex.clone());
+        let soa = SOA::new(
+            domain!("ns.example.com"),
+            domain!("admin.example.com"),
+            1,
+            0,
+            0,
+            0,
+            30,
+        );
+        zone.add(apex, 3600, Record::SOA(soa)).unwrap();
+        let a = Record::A(A::new(Ipv4Addr::new(192, 0, 2, 1)));
+        zone.add(name.clone(), 60, a).unwrap();
+        let mut server = SyncServer::new((Ipv4Addr::LOCALHOST, 0).into(), zone).unwrap();
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || server.listen_blocking());
+
+        let mut resolver = SyncResolver::new(addr).unwrap();
+        resolver.set_timeout(Duration::from_secs(5)).unwrap();
+        resolver.enable_query_log(8);
+        let clock = ManualClock::new();
+        let mut resolver = CachingResolver::new(resolver);
+        resolver.set_clock(clock.clone());
+        let queries = |resolver: &CachingResolver| resolver.resolver().query_log().unwrap().len();
+
+        let ips = resolver.resolve("host.example.com").unwrap();
+        assert_eq!(ips, [IpAddr::from([192, 0, 2, 1])]);
+        assert_eq!(queries(&resolver), 1);
+        assert_eq!(resolver.resolve("HOST.example.com").unwrap(), ips);
+        assert_eq!(resolver.query(&name, QType::A).unwrap().len(), 1);
+        assert_eq!(queries(&resolver), 1);
+
+        // The server only answers the first question of the address query, so `AAAA` records need
+        // another query. The empty answer is cached as well.
+        for _ in 0..2 {
+            assert!(resolver.query(&name, QType::AAAA).unwrap().is_empty());
+            assert_eq!(queries(&resolver), 2);
+        }
+
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(resolver.query(&name, QType::A).unwrap().len(), 1);
+        assert_eq!(queries(&resolver), 3);
+        // The negative answer expired after 30 seconds.
+        assert!(resolver.query(&name, QType::AAAA).unwrap().is_empty());
+        assert_eq!(queries(&resolver), 4);
+    }
+}
@@ -0,0 +1,188 @@
+//! Dual-stack TCP connection establishment ("Happy Eyeballs", [RFC 8305]).
+//!
+//! [RFC 8305]: https://datatracker.ietf.org/doc/html/rfc8305
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr, TcpStream},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use crate::Error;
+
+use super::{resolve_socket_addrs, Resolve};
+
+/// Time to wait for a connection attempt to succeed before starting the next one in parallel.
+///
+/// This is the *Connection Attempt Delay* recommended by [RFC 8305, section 5].
+///
+/// [RFC 8305, section 5]: https://datatracker.ietf.org/doc/html/rfc8305#section-5
+pub const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Resolves `host` via `resolver` and opens a TCP connection to `port` on one of its addresses.
+///
+/// `host` may also be an IPv4 or IPv6 address literal, in which case no resolution takes place.
+///
+/// The addresses are first sorted by [`resolve_socket_addrs`], and connection attempts are then
+/// made in the order given by [`sort_happy_eyeballs`], which alternates between IPv6 and IPv4
+/// addresses. Whenever an attempt hasn't succeeded after [`CONNECTION_ATTEMPT_DELAY`] (or fails),
+/// the next one is started while the earlier ones keep running. The first connection to be
+/// established is returned, and all others are closed. This avoids long delays when one address
+/// family is broken, without doubling the number of connections to the server when both work.
+///
+/// If every attempt fails, the error from the last one is returned.
+///
+/// # Example
+///
+/// ```no_run
+/// # use uwuhi::resolver::{connect_happy_eyeballs, SyncResolver};
+/// # fn main() -> Result<(), uwuhi::Error> {
+/// let mut resolver = SyncResolver::new("1.1.1.1:53".parse().unwrap())?;
+/// let stream = connect_happy_eyeballs(&mut resolver, "example.com", 80)?;
+/// println!("connected to {}", stream.peer_addr()?);
+/// # Ok(()) }
+/// ```
+pub fn connect_happy_eyeballs<R: Resolve + ?Sized>(
+    resolver: &mut R,
+    host: &str,
+    port: u16,
+) -> Result<TcpStream, Error> {
+    let addrs = resolve_socket_addrs(resolver, host, port)?;
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no addresses found for '{}'", host),
+        )
+        .into());
+    }
+
+    let ips = addrs.iter().map(|addr| addr.ip()).collect::<Vec<_>>();
+    let addrs = sort_happy_eyeballs(&ips)
+        .into_iter()
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect::<Vec<_>>();
+    race(&addrs, CONNECTION_ATTEMPT_DELAY, TcpStream::connect)
+}
+
+/// Orders `ips` for connection attempts, by interleaving IPv6 and IPv4 addresses
+/// ([RFC 8305, section 4]).
+///
+/// IPv6 addresses come first, and the relative order of the addresses of each family is preserved.
+///
+/// [RFC 8305, section 4]: https://datatracker.ietf.org/doc/html/rfc8305#section-4
+pub fn sort_happy_eyeballs(ips: &[IpAddr]) -> Vec<IpAddr> {
+    let mut v6 = ips.iter().filter(|ip| ip.is_ipv6());
+    let mut v4 = ips.iter().filter(|ip| ip.is_ipv4());
+    let mut sorted = Vec::with_capacity(ips.len());
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => return sorted,
+            (a, b) => sorted.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// Starts a connection attempt to each of `addrs` in turn, `delay` apart, and returns the first
+/// stream to be connected.
+fn race<S: Send + 'static>(
+    addrs: &[SocketAddr],
+    delay: Duration,
+    connect: fn(SocketAddr) -> io::Result<S>,
+) -> Result<S, Error> {
+    let (sender, receiver) = mpsc::channel();
+    let mut addrs = addrs.iter().copied();
+    let mut pending = 0;
+    let mut error = None;
+    let mut start_next = true;
+    loop {
+        if start_next {
+            if let Some(addr) = addrs.next() {
+                log::trace!("connecting to {}", addr);
+                let sender = sender.clone();
+                // Streams that lose the race are closed when `send` fails.
+                thread::spawn(move || sender.send((addr, connect(addr))));
+                pending += 1;
+            }
+        }
+        if pending == 0 {
+            return Err(error.unwrap_or(Error::Timeout));
+        }
+
+        // Wait for the next attempt to finish, or until it's time to start another one.
+        let res = if addrs.len() == 0 {
+            receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            receiver.recv_timeout(delay)
+        };
+        match res {
+            Ok((addr, Ok(stream))) => {
+                log::debug!("connected to {}", addr);
+                return Ok(stream);
+            }
+            Ok((addr, Err(e))) => {
+                log::debug!("failed to connect to {}: {}", addr, e);
+                pending -= 1;
+                error = Some(e.into());
+                start_next = true;
+            }
+            Err(RecvTimeoutError::Timeout) => start_next = true,
+            Err(RecvTimeoutError::Disconnected) => unreachable!("`sender` is still alive"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use crate::resolver::hosts::HostsFile;
+
+    use super::*;
+
+    fn ips(s: &str) -> Vec<IpAddr> {
+        s.split_whitespace().map(|ip| ip.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn interleave() {
+        assert_eq!(
+            sort_happy_eyeballs(&ips("10.0.0.1 10.0.0.2 10.0.0.3 ::1 ::2")),
+            ips("::1 10.0.0.1 ::2 10.0.0.2 10.0.0.3"),
+        );
+        assert_eq!(sort_happy_eyeballs(&ips("::1 ::2")), ips("::1 ::2"));
+        assert!(sort_happy_eyeballs(&[]).is_empty());
+    }
+
+    #[test]
+    fn stalled_attempt() {
+        let stall = |addr: SocketAddr| {
+            if addr.port() == 1 {
+                // Never completes while the test is running.
+                thread::sleep(Duration::from_secs(60));
+            }
+            Ok(addr)
+        };
+        let addrs = ["[::1]:1", "127.0.0.1:2"].map(|s| s.parse().unwrap());
+        let addr = race(&addrs, Duration::from_millis(10), stall).unwrap();
+        assert_eq!(addr.port(), 2);
+
+        let refuse = |_| Err(io::Error::from(io::ErrorKind::ConnectionRefused));
+        let err = race::<()>(&addrs, Duration::from_secs(60), refuse).unwrap_err();
+        assert!(matches!(err, Error::Io(e) if e.kind() == io::ErrorKind::ConnectionRefused));
+    }
+
+    #[test]
+    fn connect_localhost() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut hosts = HostsFile::parse("127.0.0.1 host\n::1 host");
+
+        let stream = connect_happy_eyeballs(&mut hosts, "host", port).unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), listener.local_addr().unwrap());
+        let stream = connect_happy_eyeballs(&mut hosts, "127.0.0.1", port).unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), listener.local_addr().unwrap());
+        assert!(connect_happy_eyeballs(&mut hosts, "unknown", port).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+//! Static host name resolution via a `hosts` file.
+
+use std::{fs, net::IpAddr, path::Path};
+
+use crate::{name::DomainName, Error};
+
+use super::Resolve;
+
+/// Static host name to address mappings, as found in `/etc/hosts`.
+#[derive(Debug, Clone, Default)]
+pub struct HostsFile {
+    entries: Vec<(DomainName, IpAddr)>,
+}
+
+impl HostsFile {
+    /// Creates an empty [`HostsFile`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the system's `hosts` file.
+    ///
+    /// This is `/etc/hosts` on Unix-like systems, and
+    /// `%SystemRoot%\System32\drivers\etc\hosts` on Windows.
+    pub fn load() -> Result<Self, Error> {
+        #[cfg(windows)]
+        let path = {
+            let root = std::env::var_os("SystemRoot").unwrap_or_else(|| r"C:\Windows".into());
+            Path::new(&root).join(r"System32\drivers\etc\hosts")
+        };
+        #[cfg(not(windows))]
+        let path = Path::new("/etc/hosts");
+
+        Self::load_from(path)
+    }
+
+    /// Loads a `hosts` file from `path`.
+    pub fn load_from(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let contents = fs::read_to_string(path)?;
+        Ok(Self::parse(&contents))
+    }
+
+    /// Parses the contents of a `hosts` file.
+    ///
+    /// Each line consists of an IP address followed by one or more host names, separated by
+    /// whitespace. Everything after a `#` is a comment. Malformed lines and names are skipped.
+    pub fn parse(contents: &str) -> Self {
+        let mut this = Self::new();
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap();
+            let mut fields = line.split_whitespace();
+            let Some(addr) = fields.next() else {
+                continue;
+            };
+            let Ok(addr) = addr.parse::<IpAddr>() else {
+                log::debug!("skipping invalid hosts file line '{}'", line);
+                continue;
+            };
+            for name in fields {
+                match DomainName::from_str(name) {
+                    Ok(name) => this.add(name, addr),
+                    Err(e) => log::debug!("skipping invalid host name '{}': {}", name, e),
+                }
+            }
+        }
+        this
+    }
+
+    /// Adds a mapping from `name` to `addr`.
+    pub fn add(&mut self, name: DomainName, addr: IpAddr) {
+        self.entries.push((name, addr));
+    }
+
+    /// Returns an iterator over all addresses `name` maps to.
+    ///
+    /// Names are compared case-insensitively.
+    pub fn lookup<'a>(&'a self, name: &'a DomainName) -> impl Iterator<Item = IpAddr> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, addr)| *addr)
+    }
+}
+
+impl Resolve for HostsFile {
+    fn resolve_name(&mut self, name: &DomainName) -> Result<Vec<IpAddr>, Error> {
+        Ok(self.lookup(name).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let hosts = HostsFile::parse(
+            "# comment\n\
+             127.0.0.1 localhost\n\
+             ::1\tlocalhost ip6-localhost # trailing comment\n\
+             not-an-ip example.com\n\
+             \n\
+             192.168.1.5  NAS.lan nas\n",
+        );
+        let lookup = |name| {
+            hosts
+                .lookup(&DomainName::from_str(name).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            lookup("localhost"),
+            [
+                "127.0.0.1".parse::<IpAddr>().unwrap(),
+                "::1".parse().unwrap()
+            ]
+        );
+        assert_eq!(lookup("ip6-localhost"), ["::1".parse::<IpAddr>().unwrap()]);
+        assert_eq!(
+            lookup("nas.LAN"),
+            ["192.168.1.5".parse::<IpAddr>().unwrap()]
+        );
+        assert!(lookup("example.com").is_empty());
+    }
+}
@@ -0,0 +1,304 @@
+//! Response handling for record queries, shared by the sync and async resolvers.
+
+use std::{mem, net::SocketAddr};
+
+use crate::{
+    name::DomainName,
+    packet::{
+        decoder::MessageDecoder,
+        encoder::{MessageEncoder, Question},
+        records::Record,
+        Header, QType,
+    },
+    Error, MDNS_BUFFER_SIZE,
+};
+
+use super::{decode_records_answer, is_tentative_response, Protocol, ResolveError};
+
+/// The state of a query for the records of one type owned by one name.
+///
+/// This type does not perform any I/O. It encodes the query, decides which received messages are
+/// responses to it, and collects the records from them until the query is finished. Resolvers send
+/// [`RecordLookup::query`] to every server, pass each received message that
+/// [`RecordLookup::accepts`] to [`RecordLookup::handle_response`], and call
+/// [`RecordLookup::handle_timeout`] when nothing arrives in time.
+///
+/// This implements [`SyncResolver::query`], and the async version of it.
+///
+/// [`SyncResolver::query`]: super::SyncResolver::query
+#[derive(Debug)]
+pub struct RecordLookup {
+    name: DomainName,
+    qtype: QType,
+    protocol: Protocol,
+    server_count: usize,
+    id: u16,
+    query: Vec<u8>,
+    records: Vec<Record<'static>>,
+    error: Option<ResolveError>,
+    /// Unicast servers that sent a response.
+    answered: Vec<SocketAddr>,
+    retransmitted: bool,
+}
+
+impl RecordLookup {
+    /// Creates a lookup of the records of type `qtype` owned by `name`, which will be sent to
+    /// `servers`.
+    ///
+    /// Whether unicast DNS, mDNS or LLMNR is used is determined from the first server, like in
+    /// [`SyncResolver::with_socket`](super::SyncResolver::with_socket).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `servers` is empty.
+    pub fn new(name: &DomainName, qtype: QType, servers: &[SocketAddr]) -> Result<Self, Error> {
+        let protocol = Protocol::for_server(servers[0]);
+        let mut header = Header::default();
+        header.set_id(Header::random_id());
+        // LLMNR uses the `RD` bit as the *Tentative* flag.
+        header.set_recursion_desired(protocol != Protocol::Llmnr);
+        let mut buf = [0; MDNS_BUFFER_SIZE];
+        let mut enc = MessageEncoder::new(&mut buf);
+        enc.set_header(header);
+        enc.question(Question::new(name).ty(qtype));
+        let len = enc.finish()?;
+
+        Ok(Self {
+            name: name.clone(),
+            qtype,
+            protocol,
+            server_count: servers.len(),
+            id: header.id(),
+            query: buf[..len].to_vec(),
+            records: Vec::new(),
+            error: None,
+            answered: Vec::new(),
+            retransmitted: false,
+        })
+    }
+
+    /// Returns the encoded query, which has to be sent to every server.
+    pub fn query(&self) -> &[u8] {
+        &self.query
+    }
+
+    /// Returns whether `msg` is a response to this query that should be used.
+    ///
+    /// Unicast DNS and LLMNR responses have to carry the ID of the query (mDNS responders may
+    /// set it to 0). Tentative LLMNR responses are ignored.
+    pub fn accepts(&self, msg: &[u8]) -> bool {
+        let header = match MessageDecoder::new(msg) {
+            Ok(dec) => *dec.header(),
+            Err(_) => return false,
+        };
+        if !header.is_response() || (header.id() != self.id && self.protocol != Protocol::Mdns) {
+            return false;
+        }
+        if self.protocol == Protocol::Llmnr && is_tentative_response(msg) {
+            log::debug!("ignoring tentative LLMNR response");
+            return false;
+        }
+        true
+    }
+
+    /// Handles a response that was received from `source`, and has been checked with
+    /// [`RecordLookup::accepts`].
+    ///
+    /// Returns the result of the query once it is finished. Unicast DNS queries are finished by
+    /// the first response that contains any matching records, or once every server has responded.
+    /// mDNS and LLMNR queries collect responses until [`RecordLookup::handle_timeout`] finishes
+    /// them.
+    pub fn handle_response(
+        &mut self,
+        msg: &[u8],
+        source: SocketAddr,
+    ) -> Option<Result<Vec<Record<'static>>, Error>> {
+        let found = self.records.len();
+        if let Err(e) = decode_records_answer(msg, &self.name, self.qtype, &mut self.records) {
+            log::warn!("failed to decode response from {}: {:?}", source, e);
+        }
+        if self.protocol != Protocol::Dns {
+            return None;
+        }
+
+        if self.records.len() > found {
+            return Some(Ok(mem::take(&mut self.records)));
+        }
+        if let Some(e) = ResolveError::from_response(msg, source) {
+            log::debug!("{}", e);
+            self.error = Some(e);
+        }
+        if !self.answered.contains(&source) {
+            self.answered.push(source);
+        }
+        if self.answered.len() < self.server_count {
+            return None;
+        }
+        Some(match self.error.take() {
+            Some(e) => Err(e.into()),
+            None => Ok(mem::take(&mut self.records)),
+        })
+    }
+
+    /// Handles the receive timeout passing without a response.
+    ///
+    /// If nothing has been received yet, this returns `None` the first time it is called, and
+    /// [`RecordLookup::query`] should be sent to every server again. Otherwise, the query is
+    /// finished: the collected records are returned, or [`Error::Timeout`] (or the
+    /// [`ResolveError`] a unicast server answered with) if there are none.
+    pub fn handle_timeout(&mut self) -> Option<Result<Vec<Record<'static>>, Error>> {
+        if !self.retransmitted && self.records.is_empty() && self.answered.is_empty() {
+            self.retransmitted = true;
+            return None;
+        }
+        if self.records.is_empty() {
+            return Some(Err(self.error.take().map_or(Error::Timeout, Error::from)));
+        }
+        Some(Ok(mem::take(&mut self.records)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::packet::{
+        encoder::ResourceRecord,
+        records::{A, SRV},
+        RCode,
+    };
+
+    use super::*;
+
+    fn name() -> DomainName {
+        DomainName::from_str("_ldap._tcp.example.com").unwrap()
+    }
+
+    fn srv(port: u16) -> Record<'static> {
+        let target = DomainName::from_str("dc1.example.com").unwrap();
+        Record::SRV(SRV::new(0, 100, port, target))
+    }
+
+    /// Encodes a response to `query` with the given answers.
+    fn response(query: &[u8], rcode: RCode, answers: &[Record<'_>]) -> Vec<u8> {
+        let mut dec = MessageDecoder::new(query).unwrap();
+        let header = *dec.header();
+        let question = dec.next().unwrap().unwrap();
+        let mut buf = [0; MDNS_BUFFER_SIZE];
+        let mut enc = MessageEncoder::response_to(&mut buf, &header, [&question]);
+        enc.modify_header(|h| h.set_rcode(rcode));
+        for answer in answers {
+            enc.add_answer(ResourceRecord::new(question.qname(), answer).ttl(300));
+        }
+        let len = enc.finish().unwrap();
+        buf[..len].to_vec()
+    }
+
+    fn with_id(msg: &[u8], id: u16) -> Vec<u8> {
+        let mut msg = msg.to_vec();
+        msg[..2].copy_from_slice(&id.to_be_bytes());
+        msg
+    }
+
+    #[test]
+    fn unicast() {
+        let servers = [
+            "192.0.2.1:53".parse().unwrap(),
+            "192.0.2.2:53".parse().unwrap(),
+        ];
+        let mut lookup = RecordLookup::new(&name(), QType::SRV, &servers).unwrap();
+        let query = lookup.query().to_vec();
+        let dec = MessageDecoder::new(&query).unwrap();
+        assert!(dec.header().is_query());
+        assert!(dec.header().is_recursion_desired());
+
+        // Queries and responses to other queries are not accepted.
+        let resp = response(&query, RCode::NO_ERROR, &[srv(389)]);
+        assert!(!lookup.accepts(&query));
+        assert!(!lookup.accepts(&with_id(&resp, lookup.id.wrapping_add(1))));
+        assert!(lookup.accepts(&resp));
+
+        // An empty response from one server doesn't finish the query, but the first one with
+        // records does.
+        let empty = response(&query, RCode::NO_ERROR, &[]);
+        assert!(lookup.handle_response(&empty, servers[0]).is_none());
+        let records = lookup.handle_response(&resp, servers[1]).unwrap().unwrap();
+        assert_eq!(records, [srv(389)]);
+    }
+
+    #[test]
+    fn unicast_errors() {
+        let servers = [
+            "192.0.2.1:53".parse().unwrap(),
+            "192.0.2.2:53".parse().unwrap(),
+        ];
+        let mut lookup = RecordLookup::new(&name(), QType::SRV, &servers).unwrap();
+        let query = lookup.query().to_vec();
+
+        let refused = response(&query, RCode::REFUSED, &[]);
+        let empty = response(&query, RCode::NO_ERROR, &[]);
+        assert!(lookup.handle_response(&refused, servers[0]).is_none());
+        // Repeated responses from the same server don't count twice.
+        assert!(lookup.handle_response(&refused, servers[0]).is_none());
+        match lookup.handle_response(&empty, servers[1]) {
+            Some(Err(Error::Resolve(e))) => assert_eq!(e.rcode(), RCode::REFUSED),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // Timeouts after some servers responded don't cause a retransmission.
+        let mut lookup = RecordLookup::new(&name(), QType::SRV, &servers).unwrap();
+        let refused = response(lookup.query(), RCode::REFUSED, &[]);
+        assert!(lookup.handle_response(&refused, servers[0]).is_none());
+        assert!(matches!(
+            lookup.handle_timeout(),
+            Some(Err(Error::Resolve(_)))
+        ));
+    }
+
+    #[test]
+    fn retransmits_once() {
+        let servers = ["192.0.2.1:53".parse().unwrap()];
+        let mut lookup = RecordLookup::new(&name(), QType::SRV, &servers).unwrap();
+        assert!(lookup.handle_timeout().is_none());
+        assert!(matches!(lookup.handle_timeout(), Some(Err(Error::Timeout))));
+    }
+
+    #[test]
+    fn multicast() {
+        let servers = ["224.0.0.251:5353".parse().unwrap()];
+        let mut lookup = RecordLookup::new(&name(), QType::SRV, &servers).unwrap();
+        let query = lookup.query().to_vec();
+        let peer = (Ipv4Addr::new(192, 0, 2, 1), 5353).into();
+
+        // mDNS responses may carry any ID, and are collected until the timeout passes.
+        let first = with_id(&response(&query, RCode::NO_ERROR, &[srv(389)]), 0);
+        let second = response(&query, RCode::NO_ERROR, &[srv(389), srv(636)]);
+        assert!(lookup.accepts(&first));
+        assert!(lookup.handle_response(&first, peer).is_none());
+        assert!(lookup.handle_response(&second, peer).is_none());
+        let records = lookup.handle_timeout().unwrap().unwrap();
+        assert_eq!(records, [srv(389), srv(636)]);
+    }
+
+    #[test]
+    fn llmnr() {
+        let servers = ["224.0.0.252:5355".parse().unwrap()];
+        let name = DomainName::from_str("printer").unwrap();
+        let mut lookup = RecordLookup::new(&name, QType::A, &servers).unwrap();
+        let query = lookup.query().to_vec();
+        let dec = MessageDecoder::new(&query).unwrap();
+        assert!(!dec.header().is_tentative());
+
+        let a = Record::A(A::new(Ipv4Addr::new(192, 0, 2, 1)));
+        let resp = response(&query, RCode::NO_ERROR, std::slice::from_ref(&a));
+        let mut tentative = resp.clone();
+        tentative[2] |= 0x01; // T
+        assert!(!lookup.accepts(&tentative));
+        assert!(!lookup.accepts(&with_id(&resp, lookup.id.wrapping_add(1))));
+        assert!(lookup.accepts(&resp));
+
+        let peer = (Ipv4Addr::new(192, 0, 2, 1), 5355).into();
+        assert!(lookup.handle_response(&resp, peer).is_none());
+        assert_eq!(lookup.handle_timeout().unwrap().unwrap(), [a]);
+    }
+}
@@ -0,0 +1,325 @@
+//! Latency and loss measurement for DNS servers and mDNS responders.
+//!
+//! A [`Probe`] sends a series of identical queries to a unicast DNS server or to the mDNS
+//! multicast group, and records which of them were answered, by whom, and how quickly. This helps
+//! telling apart a misbehaving library from a lossy network or an overloaded responder.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use uwuhi::resolver::probe::Probe;
+//! let mut probe = Probe::new("224.0.0.251:5353".parse()?, "printer.local".parse()?);
+//! probe.set_count(20);
+//! for responder in probe.run()?.responders() {
+//!     println!(
+//!         "{}: {:.0}% loss, median {:?}, p95 {:?}",
+//!         responder.addr(),
+//!         responder.loss() * 100.0,
+//!         responder.percentile(50.0),
+//!         responder.percentile(95.0),
+//!     );
+//! }
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    hex::Hex,
+    name::DomainName,
+    packet::{
+        decoder::MessageDecoder,
+        encoder::{MessageEncoder, Question},
+        Header, QType,
+    },
+    Error, MDNS_BUFFER_SIZE,
+};
+
+/// Sends repeated queries to a server or responder and measures how they are answered.
+pub struct Probe {
+    target: SocketAddr,
+    name: DomainName,
+    qtype: QType,
+    count: u32,
+    interval: Duration,
+    timeout: Duration,
+}
+
+impl Probe {
+    /// Creates a probe that queries `target` for the `A` records of `name`.
+    ///
+    /// If `target` is a multicast address (like the mDNS group `224.0.0.251:5353`), every host
+    /// that responds is measured separately.
+    ///
+    /// By default, 10 queries are sent, 200 ms apart, and responses are awaited for 1 second.
+    pub fn new(target: SocketAddr, name: DomainName) -> Self {
+        Self {
+            target,
+            name,
+            qtype: QType::A,
+            count: 10,
+            interval: Duration::from_millis(200),
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    /// Sets the record type to query.
+    pub fn set_qtype(&mut self, qtype: QType) {
+        self.qtype = qtype;
+    }
+
+    /// Sets the number of queries to send.
+    pub fn set_count(&mut self, count: u32) {
+        self.count = count;
+    }
+
+    /// Sets the time between sending two consecutive queries.
+    ///
+    /// If the wait for responses to a query takes longer, the next query is sent right after.
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Sets how long to wait for responses to each query.
+    ///
+    /// Responses arriving later are counted as lost. For multicast targets, the probe always waits
+    /// this long, since any number of responders may answer.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `timeout` is zero.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        assert!(!timeout.is_zero(), "probe timeout must not be zero");
+        self.timeout = timeout;
+    }
+
+    /// Sends the queries, blocking until all of them have been answered or timed out.
+    pub fn run(&mut self) -> Result<ProbeReport, Error> {
+        let bind_addr: SocketAddr = match self.target {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let sock = UdpSocket::bind(bind_addr)?;
+        let multicast = self.target.ip().is_multicast();
+
+        let mut rtts: BTreeMap<SocketAddr, Vec<Duration>> = BTreeMap::new();
+        if !multicast {
+            rtts.insert(self.target, Vec::new());
+        }
+        let mut query_buf = [0; 512];
+        let mut recv_buf = [0; MDNS_BUFFER_SIZE];
+        for _ in 0..self.count {
+            let id = Header::random_id();
+            let mut header = Header::default();
+            header.set_id(id);
+            header.set_recursion_desired(!multicast);
+            let mut enc = MessageEncoder::new(&mut query_buf);
+            enc.set_header(header);
+            enc.question(Question::new(&self.name).ty(self.qtype));
+            let len = enc.finish()?;
+
+            let sent_at = Instant::now();
+            sock.send_to(&query_buf[..len], self.target)?;
+            let mut answered = Vec::new();
+            loop {
+                let remaining = self.timeout.saturating_sub(sent_at.elapsed());
+                if remaining.is_zero() {
+                    break;
+                }
+                sock.set_read_timeout(Some(remaining))?;
+                let (len, from) = match sock.recv_from(&mut recv_buf) {
+                    Ok(res) => res,
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                        ) =>
+                    {
+                        break;
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                let rtt = sent_at.elapsed();
+                let msg = &recv_buf[..len];
+                log::trace!("probe response from {} after {:?}: {}", from, rtt, Hex(msg));
+                let Ok(dec) = MessageDecoder::new(msg) else {
+                    continue;
+                };
+                // mDNS responders may answer with ID 0.
+                let id_matches = dec.header().id() == id || (multicast && dec.header().id() == 0);
+                if !dec.header().is_response() || !id_matches || answered.contains(&from) {
+                    continue;
+                }
+                if !multicast && from != self.target {
+                    continue;
+                }
+                answered.push(from);
+                rtts.entry(from).or_default().push(rtt);
+                if !multicast {
+                    break;
+                }
+            }
+
+            let next = sent_at + self.interval;
+            let now = Instant::now();
+            if next > now {
+                thread::sleep(next - now);
+            }
+        }
+
+        Ok(ProbeReport {
+            sent: self.count,
+            responders: rtts
+                .into_iter()
+                .map(|(addr, mut rtts)| {
+                    rtts.sort();
+                    ResponderStats {
+                        addr,
+                        sent: self.count,
+                        rtts,
+                    }
+                })
+                .collect(),
+        })
+    }
+}
+
+/// The results of running a [`Probe`].
+#[derive(Debug, Clone)]
+pub struct ProbeReport {
+    sent: u32,
+    responders: Vec<ResponderStats>,
+}
+
+impl ProbeReport {
+    /// Returns the number of queries that were sent.
+    #[inline]
+    pub fn sent(&self) -> u32 {
+        self.sent
+    }
+
+    /// Returns the statistics of every host that responded, ordered by address.
+    ///
+    /// For unicast targets, this always contains exactly one entry for the target, even if it
+    /// never responded.
+    #[inline]
+    pub fn responders(&self) -> &[ResponderStats] {
+        &self.responders
+    }
+}
+
+/// Response statistics of a single server or responder.
+#[derive(Debug, Clone)]
+pub struct ResponderStats {
+    addr: SocketAddr,
+    sent: u32,
+    /// Sorted in ascending order.
+    rtts: Vec<Duration>,
+}
+
+impl ResponderStats {
+    /// Returns the address responses were received from.
+    #[inline]
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the number of queries this host responded to.
+    #[inline]
+    pub fn received(&self) -> u32 {
+        self.rtts.len() as u32
+    }
+
+    /// Returns the fraction of queries that this host did not respond to, between 0.0 and 1.0.
+    pub fn loss(&self) -> f64 {
+        if self.sent == 0 {
+            return 0.0;
+        }
+        1.0 - f64::from(self.received()) / f64::from(self.sent)
+    }
+
+    /// Returns the round-trip time below which `p` percent of the responses arrived, or [`None`]
+    /// if there were no responses.
+    ///
+    /// Uses the nearest-rank method, so `percentile(0.0)` is the fastest and `percentile(100.0)`
+    /// the slowest response.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `p` is not between 0 and 100.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        assert!((0.0..=100.0).contains(&p), "invalid percentile {p}");
+        let rank = (p / 100.0 * self.rtts.len() as f64).ceil() as usize;
+        self.rtts.get(rank.saturating_sub(1)).copied()
+    }
+
+    /// Returns the round-trip times of all responses, in ascending order.
+    #[inline]
+    pub fn rtts(&self) -> &[Duration] {
+        &self.rtts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        packet::records::{Record, A},
+        server::{SyncServer, Zone},
+    };
+
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        let stats = ResponderStats {
+            addr: (Ipv4Addr::LOCALHOST, 53).into(),
+            sent: 5,
+            rtts: (1..=4).map(Duration::from_millis).collect(),
+        };
+        assert_eq!(stats.received(), 4);
+        assert!((stats.loss() - 0.2).abs() < 1e-9);
+        assert_eq!(stats.percentile(0.0), Some(Duration::from_millis(1)));
+        assert_eq!(stats.percentile(50.0), Some(Duration::from_millis(2)));
+        assert_eq!(stats.percentile(51.0), Some(Duration::from_millis(3)));
+        assert_eq!(stats.percentile(100.0), Some(Duration::from_millis(4)));
+    }
+
+    #[test]
+    fn probe_server() {
+        let mut zone = Zone::new("example.com".parse().unwrap());
+        let a = Record::A(A::new(Ipv4Addr::new(10, 0, 0, 1)));
+        zone.add("www.example.com".parse().unwrap(), 300, a)
+            .unwrap();
+        let mut server = SyncServer::new((Ipv4Addr::LOCALHOST, 0).into(), zone).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.listen_blocking());
+
+        let mut probe = Probe::new(addr, "www.example.com".parse().unwrap());
+        probe.set_count(3);
+        probe.set_interval(Duration::ZERO);
+        probe.set_timeout(Duration::from_secs(5));
+        let report = probe.run().unwrap();
+        assert_eq!(report.sent(), 3);
+        let [stats] = report.responders() else {
+            panic!("unexpected responders: {:?}", report.responders());
+        };
+        assert_eq!(stats.addr(), addr);
+        assert_eq!(stats.received(), 3);
+        assert_eq!(stats.loss(), 0.0);
+
+        // Nobody answers on this socket.
+        let silent = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut probe = Probe::new(silent.local_addr().unwrap(), "a.b".parse().unwrap());
+        probe.set_count(2);
+        probe.set_timeout(Duration::from_millis(50));
+        let report = probe.run().unwrap();
+        assert_eq!(report.responders()[0].loss(), 1.0);
+        assert_eq!(report.responders()[0].percentile(50.0), None);
+    }
+}
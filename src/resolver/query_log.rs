@@ -0,0 +1,209 @@
+//! A bounded log of recent queries and their responses.
+
+use std::{collections::VecDeque, fmt, net::SocketAddr, time::Duration};
+
+use crate::packet::decoder::MessageDecoder;
+
+/// A ring buffer holding the most recent queries sent by a [`SyncResolver`], along with the
+/// responses they received.
+///
+/// This is meant for diagnostics, like showing recent DNS activity in a debugging UI, without
+/// having to capture packets. Enable it with [`SyncResolver::enable_query_log`].
+///
+/// [`SyncResolver`]: super::SyncResolver
+/// [`SyncResolver::enable_query_log`]: super::SyncResolver::enable_query_log
+#[derive(Debug, Clone)]
+pub struct QueryLog {
+    entries: VecDeque<QueryLogEntry>,
+    capacity: usize,
+}
+
+impl QueryLog {
+    /// Creates an empty log that retains up to `capacity` entries.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `capacity` is 0.
+    pub fn new(capacity: usize) -> Self {
+        assert_ne!(capacity, 0, "query log capacity must not be 0");
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Returns the maximum number of entries retained by this log.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of entries in the log.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether the log is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns an iterator over the logged entries, from oldest to newest.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &QueryLogEntry> + ExactSizeIterator {
+        self.entries.iter()
+    }
+
+    /// Removes all entries from the log.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Appends an entry, evicting the oldest one if the log is full.
+    pub(crate) fn push(&mut self, entry: QueryLogEntry) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Moves all entries of `other` to the end of this log.
+    pub(crate) fn append(&mut self, other: &mut QueryLog) {
+        for entry in other.entries.drain(..) {
+            self.push(entry);
+        }
+    }
+}
+
+/// A query sent to a server, and the response it received (if any).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryLogEntry {
+    server: SocketAddr,
+    query: Vec<u8>,
+    response: Option<Vec<u8>>,
+    rtt: Option<Duration>,
+}
+
+impl QueryLogEntry {
+    pub(crate) fn new(
+        server: SocketAddr,
+        query: &[u8],
+        response: Option<(&[u8], Duration)>,
+    ) -> Self {
+        Self {
+            server,
+            query: query.to_vec(),
+            response: response.map(|(response, _)| response.to_vec()),
+            rtt: response.map(|(_, rtt)| rtt),
+        }
+    }
+
+    /// Returns the address of the server the query was sent to (and the response received from).
+    #[inline]
+    pub fn server(&self) -> SocketAddr {
+        self.server
+    }
+
+    /// Returns the raw query message.
+    #[inline]
+    pub fn query(&self) -> &[u8] {
+        &self.query
+    }
+
+    /// Returns the raw response message, or [`None`] if the server didn't respond in time.
+    #[inline]
+    pub fn response(&self) -> Option<&[u8]> {
+        self.response.as_deref()
+    }
+
+    /// Returns the time between sending the query and receiving the response.
+    #[inline]
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+}
+
+impl fmt::Display for QueryLogEntry {
+    /// Writes a one-line summary of the entry, like
+    /// `example.com. A via 1.1.1.1:53: NO_ERROR, 1 answers in 12ms`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match MessageDecoder::new(&self.query).map(|mut dec| dec.next()) {
+            Ok(Some(Ok(q))) => write!(f, "{} {}", q.qname(), q.qtype())?,
+            _ => f.write_str("<malformed query>")?,
+        }
+        write!(f, " via {}: ", self.server)?;
+        let (Some(response), Some(rtt)) = (&self.response, self.rtt) else {
+            return f.write_str("no response");
+        };
+        match MessageDecoder::new(response) {
+            Ok(dec) => write!(
+                f,
+                "{}, {} answers",
+                dec.header().rcode(),
+                dec.header().answer_count()
+            )?,
+            Err(e) => write!(f, "malformed response ({})", e)?,
+        }
+        write!(f, " in {:?}", rtt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::{
+        packet::records::{Record, A},
+        resolver::SyncResolver,
+        server::{SyncServer, Zone},
+    };
+
+    use super::*;
+
+    #[test]
+    fn ring() {
+        let server = SocketAddr::from((Ipv4Addr::LOCALHOST, 53));
+        let mut log = QueryLog::new(2);
+        for i in 0..3u8 {
+            log.push(QueryLogEntry::new(server, &[i], None));
+        }
+        assert_eq!(log.len(), 2);
+        let queries = log.iter().map(|e| e.query()[0]).collect::<Vec<_>>();
+        assert_eq!(queries, [1, 2]);
+        assert_eq!(
+            log.iter().next().unwrap().to_string(),
+            "<malformed query> via 127.0.0.1:53: no response"
+        );
+    }
+
+    #[test]
+    fn resolver_log() {
+        let mut zone = Zone::new("example.com".parse().unwrap());
+        let a = Record::A(A::new(Ipv4Addr::new(10, 0, 0, 1)));
+        zone.add("www.example.com".parse().unwrap(), 300, a)
+            .unwrap();
+        let mut server = SyncServer::new((Ipv4Addr::LOCALHOST, 0).into(), zone).unwrap();
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || server.listen_blocking());
+
+        let mut resolver = SyncResolver::new(addr).unwrap();
+        resolver.set_timeout(Duration::from_secs(5)).unwrap();
+        assert!(resolver.query_log().is_none());
+        resolver.enable_query_log(8);
+        let ips = resolver
+            .resolve("www.example.com")
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(ips, [Ipv4Addr::new(10, 0, 0, 1)]);
+
+        let log = resolver.query_log().unwrap();
+        assert_eq!(log.len(), 1);
+        let entry = log.iter().next().unwrap();
+        assert_eq!(entry.server(), addr);
+        assert!(entry.rtt().is_some());
+        let summary = entry.to_string();
+        let expected = format!("www.example.com. A via {addr}: NO_ERROR, 1 answers in ");
+        assert!(summary.starts_with(&expected), "{summary}");
+    }
+}
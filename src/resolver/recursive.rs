@@ -0,0 +1,1005 @@
+//! Iterative resolution starting at the DNS root.
+//!
+//! Unlike [`SyncResolver`], which asks a recursive server to do the work, [`RecursiveResolver`]
+//! contacts the authoritative servers itself: it starts at the root servers and follows referrals
+//! down the delegation chain until it reaches a server that can answer the query.
+//!
+//! The root servers are bootstrapped from a set of [`RootHints`]. Before the first query, the
+//! resolver sends a *priming query* ([RFC 8109]) for the root's `NS` records to the hinted servers,
+//! and uses the (validated) response as its list of root servers from then on.
+//!
+//! By default, the resolver performs QNAME minimization ([RFC 9156]): each server is only sent the
+//! part of the queried name it needs to know about to refer the resolver to the next zone.
+//!
+//! [`SyncResolver`]: super::SyncResolver
+//! [RFC 8109]: https://datatracker.ietf.org/doc/html/rfc8109
+//! [RFC 9156]: https://datatracker.ietf.org/doc/html/rfc9156
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use crate::{
+    hex::Hex,
+    name::DomainName,
+    packet::{
+        decoder::{MessageDecoder, OwnedResourceRecord},
+        encoder::{MessageEncoder, Question},
+        records::Record,
+        Header, QType, RCode, Type,
+    },
+    Error,
+};
+
+use super::Resolve;
+
+/// UDP payload size advertised in our queries.
+const EDNS_PAYLOAD_SIZE: u16 = 1232;
+
+/// Maximum number of referrals to follow for a single query.
+const MAX_REFERRALS: usize = 16;
+
+/// Maximum number of `CNAME` records to follow for a single query.
+const MAX_CNAMES: usize = 8;
+
+/// Maximum number of minimized queries to send for a single query (`MAX_MINIMISE_COUNT` in
+/// RFC 9156).
+const MAX_MINIMIZE_COUNT: usize = 10;
+
+/// Number of minimized queries that reveal only a single additional label (`MINIMISE_ONE_LAB` in
+/// RFC 9156).
+const MINIMIZE_ONE_LABEL: usize = 4;
+
+/// Maximum nesting depth of lookups for the addresses of name servers without glue.
+const MAX_DEPTH: usize = 4;
+
+/// The root servers of the public DNS, as listed at <https://www.iana.org/domains/root/servers>.
+const IANA_ROOT_SERVERS: &[(&str, Ipv4Addr, Ipv6Addr)] = &[
+    (
+        "a.root-servers.net",
+        Ipv4Addr::new(198, 41, 0, 4),
+        Ipv6Addr::new(0x2001, 0x503, 0xba3e, 0, 0, 0, 0x2, 0x30),
+    ),
+    (
+        "b.root-servers.net",
+        Ipv4Addr::new(170, 247, 170, 2),
+        Ipv6Addr::new(0x2801, 0x1b8, 0x10, 0, 0, 0, 0, 0xb),
+    ),
+    (
+        "c.root-servers.net",
+        Ipv4Addr::new(192, 33, 4, 12),
+        Ipv6Addr::new(0x2001, 0x500, 0x2, 0, 0, 0, 0, 0xc),
+    ),
+    (
+        "d.root-servers.net",
+        Ipv4Addr::new(199, 7, 91, 13),
+        Ipv6Addr::new(0x2001, 0x500, 0x2d, 0, 0, 0, 0, 0xd),
+    ),
+    (
+        "e.root-servers.net",
+        Ipv4Addr::new(192, 203, 230, 10),
+        Ipv6Addr::new(0x2001, 0x500, 0xa8, 0, 0, 0, 0, 0xe),
+    ),
+    (
+        "f.root-servers.net",
+        Ipv4Addr::new(192, 5, 5, 241),
+        Ipv6Addr::new(0x2001, 0x500, 0x2f, 0, 0, 0, 0, 0xf),
+    ),
+    (
+        "g.root-servers.net",
+        Ipv4Addr::new(192, 112, 36, 4),
+        Ipv6Addr::new(0x2001, 0x500, 0x12, 0, 0, 0, 0, 0xd0d),
+    ),
+    (
+        "h.root-servers.net",
+        Ipv4Addr::new(198, 97, 190, 53),
+        Ipv6Addr::new(0x2001, 0x500, 0x1, 0, 0, 0, 0, 0x53),
+    ),
+    (
+        "i.root-servers.net",
+        Ipv4Addr::new(192, 36, 148, 17),
+        Ipv6Addr::new(0x2001, 0x7fe, 0, 0, 0, 0, 0, 0x53),
+    ),
+    (
+        "j.root-servers.net",
+        Ipv4Addr::new(192, 58, 128, 30),
+        Ipv6Addr::new(0x2001, 0x503, 0xc27, 0, 0, 0, 0x2, 0x30),
+    ),
+    (
+        "k.root-servers.net",
+        Ipv4Addr::new(193, 0, 14, 129),
+        Ipv6Addr::new(0x2001, 0x7fd, 0, 0, 0, 0, 0, 0x1),
+    ),
+    (
+        "l.root-servers.net",
+        Ipv4Addr::new(199, 7, 83, 42),
+        Ipv6Addr::new(0x2001, 0x500, 0x9f, 0, 0, 0, 0, 0x42),
+    ),
+    (
+        "m.root-servers.net",
+        Ipv4Addr::new(202, 12, 27, 33),
+        Ipv6Addr::new(0x2001, 0xdc3, 0, 0, 0, 0, 0, 0x35),
+    ),
+];
+
+/// A list of root server names and addresses, used to find the actual root servers.
+///
+/// [`RootHints::iana`] returns the hints for the public DNS root. Private DNS roots (for example in
+/// air-gapped networks or test environments) can be configured by building a custom list with
+/// [`RootHints::add`], or by loading a hints file in the format of IANA's `named.root` with
+/// [`RootHints::parse`].
+#[derive(Debug, Clone, Default)]
+pub struct RootHints {
+    servers: Vec<(DomainName, Vec<IpAddr>)>,
+}
+
+impl RootHints {
+    /// Creates an empty list of root hints.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the built-in hints for the 13 root servers of the public DNS.
+    pub fn iana() -> Self {
+        let mut this = Self::new();
+        for &(name, v4, v6) in IANA_ROOT_SERVERS {
+            let name = DomainName::from_str(name).unwrap();
+            this.add(name.clone(), v4.into());
+            this.add(name, v6.into());
+        }
+        this
+    }
+
+    /// Parses a root hints file, like IANA's `named.root`.
+    ///
+    /// The file is in zone file format. Only `A` and `AAAA` records are used; the name servers are
+    /// identified by the owner names of those records. Everything after a `;` is a comment.
+    /// Malformed lines are skipped.
+    pub fn parse(contents: &str) -> Self {
+        let mut this = Self::new();
+        for line in contents.lines() {
+            let line = line.split(';').next().unwrap();
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            // <name> [<ttl>] [<class>] <type> <data>
+            let Some(ty) = fields
+                .iter()
+                .position(|f| f.eq_ignore_ascii_case("A") || f.eq_ignore_ascii_case("AAAA"))
+            else {
+                continue;
+            };
+            let (Some(name), Some(addr)) = (fields.first(), fields.get(ty + 1)) else {
+                continue;
+            };
+            match (DomainName::from_str(name), addr.parse::<IpAddr>()) {
+                (Ok(name), Ok(addr)) if ty > 0 => this.add(name, addr),
+                _ => log::debug!("skipping invalid root hints line '{}'", line),
+            }
+        }
+        this
+    }
+
+    /// Adds `addr` as an address of the root server `name`.
+    pub fn add(&mut self, name: DomainName, addr: IpAddr) {
+        match self
+            .servers
+            .iter_mut()
+            .find(|(n, _)| n.eq_ignore_ascii_case(&name))
+        {
+            Some((_, addrs)) => {
+                if !addrs.contains(&addr) {
+                    addrs.push(addr);
+                }
+            }
+            None => self.servers.push((name, vec![addr])),
+        }
+    }
+
+    /// Returns whether no root server addresses are known.
+    pub fn is_empty(&self) -> bool {
+        self.servers.is_empty()
+    }
+
+    /// Returns an iterator over the root server names and their addresses.
+    pub fn servers(&self) -> impl Iterator<Item = (&DomainName, &[IpAddr])> {
+        self.servers
+            .iter()
+            .map(|(name, addrs)| (name, addrs.as_slice()))
+    }
+
+    /// Returns an iterator over the addresses of all root servers.
+    pub fn addrs(&self) -> impl Iterator<Item = IpAddr> + '_ {
+        self.servers
+            .iter()
+            .flat_map(|(_, addrs)| addrs.iter().copied())
+    }
+
+    fn addrs_of(&self, name: &DomainName) -> Option<&[IpAddr]> {
+        self.servers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, addrs)| addrs.as_slice())
+    }
+
+    /// Builds the list of root servers from the response to a priming query.
+    ///
+    /// The name servers are taken from the root `NS` records in the *Answer* section. Their
+    /// addresses are taken from the `A` and `AAAA` records in the *Additional* section, which must
+    /// belong to one of those name servers; any other records are ignored. Name servers without
+    /// any such glue keep the addresses from `self`, if there are any.
+    fn primed_by(&self, response: &Response) -> Result<Self, Error> {
+        let mut primed = Self::new();
+        for rr in &response.answers {
+            let Some(Record::NS(ns)) = rr.record() else {
+                continue;
+            };
+            if !rr.name().labels().is_empty() {
+                log::debug!("ignoring non-root NS record in priming response: {:?}", rr);
+                continue;
+            }
+            let name = ns.nsdname();
+            for addr in response.glue(name) {
+                primed.add(name.clone(), addr);
+            }
+            if primed.addrs_of(name).is_none() {
+                for &addr in self.addrs_of(name).unwrap_or(&[]) {
+                    primed.add(name.clone(), addr);
+                }
+            }
+        }
+
+        if primed.is_empty() {
+            log::debug!("priming response contains no usable root servers");
+            return Err(Error::InvalidValue);
+        }
+        Ok(primed)
+    }
+}
+
+/// A synchronous recursive DNS resolver that performs iterative resolution starting at the root.
+///
+/// This is useful on hosts that should not depend on any upstream recursive server. Most
+/// applications should use a [`SyncResolver`] pointed at a recursive server instead, which will
+/// answer most queries from its cache.
+///
+/// [`SyncResolver`]: super::SyncResolver
+///
+/// # Example
+///
+/// ```no_run
+/// # use uwuhi::resolver::recursive::RecursiveResolver;
+/// # fn main() -> Result<(), uwuhi::Error> {
+/// let mut resolver = RecursiveResolver::new();
+/// for ip in resolver.resolve("example.com")? {
+///     println!("{}", ip);
+/// }
+/// # Ok(()) }
+/// ```
+pub struct RecursiveResolver {
+    hints: RootHints,
+    roots: Option<RootHints>,
+    port: u16,
+    timeout: Duration,
+    qname_minimization: bool,
+    sock_v4: Option<UdpSocket>,
+    sock_v6: Option<UdpSocket>,
+}
+
+impl RecursiveResolver {
+    const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Creates a resolver that starts at the root servers of the public DNS.
+    pub fn new() -> Self {
+        Self::with_hints(RootHints::iana())
+    }
+
+    /// Creates a resolver that finds the root servers using `hints`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `hints` is empty.
+    pub fn with_hints(hints: RootHints) -> Self {
+        assert!(!hints.is_empty(), "root hints must not be empty");
+        Self {
+            hints,
+            roots: None,
+            port: 53,
+            timeout: Self::DEFAULT_TIMEOUT,
+            qname_minimization: true,
+            sock_v4: None,
+            sock_v6: None,
+        }
+    }
+
+    /// Sets the UDP port that name servers are contacted on.
+    ///
+    /// This is 53 by default, and only needs to be changed in test environments where the servers
+    /// can't listen on the standard DNS port.
+    pub fn set_port(&mut self, port: u16) {
+        self.port = port;
+    }
+
+    /// Sets the time to wait for a response from each contacted name server.
+    pub fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        self.timeout = timeout;
+        for sock in self.sock_v4.iter().chain(&self.sock_v6) {
+            sock.set_read_timeout(Some(timeout))?;
+        }
+        Ok(())
+    }
+
+    /// Enables or disables QNAME minimization ([RFC 9156]).
+    ///
+    /// When enabled (the default), the resolver only sends as much of the queried name to each
+    /// name server as is needed to find the next delegation, instead of revealing the full name to
+    /// the root and top-level domain servers. This costs a few extra queries for names with many
+    /// labels. If a server fails to answer a minimized query, the full name is sent instead.
+    ///
+    /// [RFC 9156]: https://datatracker.ietf.org/doc/html/rfc9156
+    pub fn set_qname_minimization(&mut self, enable: bool) {
+        self.qname_minimization = enable;
+    }
+
+    /// Returns the root servers in use, if the resolver has been primed.
+    pub fn root_servers(&self) -> Option<&RootHints> {
+        self.roots.as_ref()
+    }
+
+    /// Sends a priming query for the root `NS` records to the hinted root servers, and uses the
+    /// validated response as the list of root servers.
+    ///
+    /// This is done automatically before the first query is resolved, but can be called explicitly
+    /// to refresh the list of root servers.
+    pub fn prime(&mut self) -> Result<&RootHints, Error> {
+        let servers = self.hints.addrs().collect::<Vec<_>>();
+        let response = self.query(&servers, &DomainName::ROOT, QType::NS)?;
+        let roots = self.hints.primed_by(&response)?;
+        log::debug!("primed root servers: {:?}", roots);
+        Ok(self.roots.insert(roots))
+    }
+
+    /// Resolves `hostname` to its IPv4 and IPv6 addresses.
+    pub fn resolve(&mut self, hostname: &str) -> Result<Vec<IpAddr>, Error> {
+        let name = DomainName::from_str(hostname)?;
+        self.resolve_domain(&name)
+    }
+
+    /// Resolves a [`DomainName`] to its IPv4 and IPv6 addresses.
+    ///
+    /// An empty list is returned if the name or its addresses don't exist.
+    pub fn resolve_domain(&mut self, name: &DomainName) -> Result<Vec<IpAddr>, Error> {
+        let mut addrs = self.lookup(name, QType::A, 0)?;
+        addrs.extend(self.lookup(name, QType::AAAA, 0)?);
+        Ok(addrs)
+    }
+
+    /// Iteratively resolves the addresses of `name`, starting at the root servers.
+    fn lookup(
+        &mut self,
+        name: &DomainName,
+        qtype: QType,
+        depth: usize,
+    ) -> Result<Vec<IpAddr>, Error> {
+        if depth > MAX_DEPTH {
+            log::debug!(
+                "giving up on '{}': name server lookups nested too deeply",
+                name
+            );
+            return Err(Error::InvalidValue);
+        }
+        if self.roots.is_none() {
+            self.prime()?;
+        }
+
+        let roots = self.roots.as_ref().unwrap();
+        let mut servers = roots.addrs().collect::<Vec<_>>();
+        let mut zone = DomainName::ROOT;
+        let mut qname = name.clone();
+        let mut cnames = 0;
+        let mut referrals = 0;
+        let mut minimizer = self.qname_minimization.then(Minimizer::default);
+        while referrals < MAX_REFERRALS {
+            if let Some(sname) = minimizer.as_mut().and_then(|m| m.next(&qname, &zone)) {
+                // Only reveal the next label(s) to the servers of `zone` (RFC 9156). Queries for
+                // `A` records are less likely to trip up broken servers than ones for `NS`.
+                let response = match self.query(&servers, &sname, QType::A) {
+                    Ok(response) => response,
+                    Err(e) => {
+                        log::debug!(
+                            "minimized query for '{}' failed ({}), sending full query name",
+                            sname,
+                            e
+                        );
+                        minimizer = None;
+                        continue;
+                    }
+                };
+                if response.rcode == RCode::NX_DOMAIN {
+                    // Nothing exists below a nonexistent name (RFC 8020).
+                    return Ok(Vec::new());
+                }
+                if let Some((cut, nsdnames)) = response.referral(&sname, &zone) {
+                    servers = self.follow_referral(&response, &zone, &cut, &nsdnames, depth)?;
+                    zone = cut;
+                    referrals += 1;
+                }
+                continue;
+            }
+
+            let response = self.query(&servers, &qname, qtype)?;
+            if response.rcode == RCode::NX_DOMAIN {
+                return Ok(Vec::new());
+            }
+
+            // Follow `CNAME`s contained in the answer itself.
+            let mut target = qname.clone();
+            while let Some(cname) = response.cname(&target) {
+                cnames += 1;
+                if cnames > MAX_CNAMES {
+                    log::debug!("giving up on '{}': too many CNAMEs", name);
+                    return Err(Error::InvalidValue);
+                }
+                target = cname.clone();
+            }
+            let addrs = response.addrs(&target, qtype);
+            if !addrs.is_empty() {
+                return Ok(addrs);
+            }
+            if !target.eq_ignore_ascii_case(&qname) {
+                // The target lives elsewhere; start over at the root.
+                log::trace!("following CNAME from '{}' to '{}'", qname, target);
+                qname = target;
+                zone = DomainName::ROOT;
+                servers = self.roots.as_ref().unwrap().addrs().collect();
+                minimizer = self.qname_minimization.then(Minimizer::default);
+                continue;
+            }
+
+            let Some((cut, nsdnames)) = response.referral(&qname, &zone) else {
+                // No data, or the server is misbehaving.
+                return Ok(Vec::new());
+            };
+            servers = self.follow_referral(&response, &zone, &cut, &nsdnames, depth)?;
+            zone = cut;
+            referrals += 1;
+        }
+
+        log::debug!("giving up on '{}': too many referrals", name);
+        Err(Error::InvalidValue)
+    }
+
+    /// Returns the addresses of the name servers `nsdnames` a server for `zone` referred us to.
+    fn follow_referral(
+        &mut self,
+        response: &Response,
+        zone: &DomainName,
+        cut: &DomainName,
+        nsdnames: &[DomainName],
+        depth: usize,
+    ) -> Result<Vec<IpAddr>, Error> {
+        log::trace!("referral from '{}' to '{}'", zone, cut);
+
+        let mut next = Vec::new();
+        for nsdname in nsdnames {
+            // Only trust glue from the zone the responding server is authoritative for.
+            if is_subdomain(nsdname, zone) {
+                next.extend(response.glue(nsdname));
+            }
+        }
+        if next.is_empty() {
+            for nsdname in nsdnames {
+                match self.lookup(nsdname, QType::A, depth + 1) {
+                    Ok(addrs) => next.extend(addrs),
+                    Err(e) => log::debug!("failed to resolve name server '{}': {}", nsdname, e),
+                }
+                if !next.is_empty() {
+                    break;
+                }
+            }
+        }
+        if next.is_empty() {
+            log::debug!("no reachable name servers for '{}'", cut);
+            return Err(Error::Timeout);
+        }
+        Ok(next)
+    }
+
+    /// Sends a non-recursive query to each of `servers` in turn, until one of them responds.
+    fn query(
+        &mut self,
+        servers: &[IpAddr],
+        qname: &DomainName,
+        qtype: QType,
+    ) -> Result<Response, Error> {
+        let id = Header::random_id();
+        let mut header = Header::default();
+        header.set_id(id);
+        let mut send_buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut send_buf);
+        enc.set_header(header);
+        enc.question(Question::new(qname).ty(qtype));
+        let mut enc = enc.answers().authority().additional();
+        enc.add_edns(EDNS_PAYLOAD_SIZE);
+        let len = enc.finish()?;
+        let query = &send_buf[..len];
+
+        let mut error = Error::Timeout;
+        let mut recv_buf = [0; EDNS_PAYLOAD_SIZE as usize];
+        for &ip in servers {
+            let server = SocketAddr::new(ip, self.port);
+            log::trace!("querying {} for '{}' {}", server, qname, qtype);
+            let sock = match self.socket(ip) {
+                Ok(sock) => sock,
+                Err(e) => {
+                    log::debug!("cannot contact {}: {}", server, e);
+                    error = e;
+                    continue;
+                }
+            };
+            if let Err(e) = sock.send_to(query, server) {
+                log::debug!("failed to send query to {}: {}", server, e);
+                error = e.into();
+                continue;
+            }
+
+            loop {
+                let (len, addr) = match sock.recv_from(&mut recv_buf) {
+                    Ok(res) => res,
+                    Err(e)
+                        if e.kind() == io::ErrorKind::WouldBlock
+                            || e.kind() == io::ErrorKind::TimedOut =>
+                    {
+                        log::debug!("query to {} timed out", server);
+                        error = Error::Timeout;
+                        break;
+                    }
+                    Err(e) => {
+                        error = e.into();
+                        break;
+                    }
+                };
+                let recv = &recv_buf[..len];
+                log::trace!("recv from {}: {}", addr, Hex(recv));
+                if addr != server {
+                    continue;
+                }
+                match Response::decode(recv, id, qname, qtype) {
+                    Ok(Some(response))
+                        if response.rcode == RCode::NO_ERROR
+                            || response.rcode == RCode::NX_DOMAIN =>
+                    {
+                        return Ok(response);
+                    }
+                    Ok(Some(response)) => {
+                        log::debug!("{} responded with {:?}", server, response.rcode);
+                        error = Error::InvalidValue;
+                        break;
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        log::debug!("failed to decode response from {}: {}", server, e);
+                        error = e;
+                        break;
+                    }
+                }
+            }
+        }
+        Err(error)
+    }
+
+    fn socket(&mut self, ip: IpAddr) -> Result<&UdpSocket, Error> {
+        let (slot, bind_addr): (_, SocketAddr) = match ip {
+            IpAddr::V4(_) => (&mut self.sock_v4, (Ipv4Addr::UNSPECIFIED, 0).into()),
+            IpAddr::V6(_) => (&mut self.sock_v6, (Ipv6Addr::UNSPECIFIED, 0).into()),
+        };
+        if let Some(sock) = slot {
+            return Ok(sock);
+        }
+        let sock = UdpSocket::bind(bind_addr)?;
+        sock.set_read_timeout(Some(self.timeout))?;
+        Ok(slot.insert(sock))
+    }
+}
+
+impl Default for RecursiveResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolve for RecursiveResolver {
+    fn resolve_name(&mut self, name: &DomainName) -> Result<Vec<IpAddr>, Error> {
+        self.resolve_domain(name)
+    }
+}
+
+/// Tracks the state of QNAME minimization for a single lookup.
+#[derive(Default)]
+struct Minimizer {
+    /// Number of labels sent in the last minimized query.
+    labels: usize,
+    /// Number of minimized queries sent so far.
+    queries: usize,
+}
+
+impl Minimizer {
+    /// Returns the name to send in the next minimized query to the servers of `zone`, or [`None`]
+    /// if the full `qname` should be sent.
+    fn next(&mut self, qname: &DomainName, zone: &DomainName) -> Option<DomainName> {
+        let total = qname.labels().len();
+        // Every referral resets the count to just below the new zone cut.
+        let current = self.labels.max(zone.labels().len());
+        let remaining = total.saturating_sub(current);
+        if remaining <= 1 || self.queries >= MAX_MINIMIZE_COUNT {
+            return None;
+        }
+
+        let step = if self.queries < MINIMIZE_ONE_LABEL {
+            1
+        } else {
+            // Spread the remaining labels over the remaining queries.
+            (remaining / (MAX_MINIMIZE_COUNT - self.queries)).clamp(1, remaining - 1)
+        };
+        self.labels = current + step;
+        self.queries += 1;
+        Some(DomainName::from_iter(
+            &qname.labels()[total - self.labels..],
+        ))
+    }
+}
+
+/// The relevant parts of a response from an authoritative server.
+struct Response {
+    rcode: RCode,
+    answers: Vec<OwnedResourceRecord>,
+    authority: Vec<OwnedResourceRecord>,
+    additional: Vec<OwnedResourceRecord>,
+}
+
+impl Response {
+    /// Decodes `msg`, returning [`None`] if it isn't a response to the query with ID `id`.
+    fn decode(
+        msg: &[u8],
+        id: u16,
+        qname: &DomainName,
+        qtype: QType,
+    ) -> Result<Option<Self>, Error> {
+        let mut dec = MessageDecoder::new(msg)?;
+        let header = *dec.header();
+        if !header.is_response() || header.id() != id {
+            return Ok(None);
+        }
+        match dec.next() {
+            Some(q) => {
+                let q = q?;
+                if q.qtype() != qtype || !q.qname().eq_ignore_ascii_case(qname) {
+                    return Ok(None);
+                }
+            }
+            None => return Ok(None),
+        }
+
+        let mut dec = dec.answers()?;
+        let answers = dec
+            .iter()
+            .map(|rr| rr?.into_owned())
+            .collect::<Result<_, _>>()?;
+        let mut dec = dec.authority()?;
+        let authority = dec
+            .iter()
+            .map(|rr| rr?.into_owned())
+            .collect::<Result<_, _>>()?;
+        let mut dec = dec.additional()?;
+        let additional = dec
+            .iter()
+            .map(|rr| rr?.into_owned())
+            .collect::<Result<_, _>>()?;
+        Ok(Some(Self {
+            rcode: header.rcode(),
+            answers,
+            authority,
+            additional,
+        }))
+    }
+
+    /// Returns the target of the `CNAME` record owned by `name` in the *Answer* section.
+    fn cname(&self, name: &DomainName) -> Option<&DomainName> {
+        self.answers.iter().find_map(|rr| match rr.record() {
+            Some(Record::CNAME(cname)) if rr.name().eq_ignore_ascii_case(name) => {
+                Some(cname.cname())
+            }
+            _ => None,
+        })
+    }
+
+    /// Returns the addresses of `name` in the *Answer* section.
+    fn addrs(&self, name: &DomainName, qtype: QType) -> Vec<IpAddr> {
+        self.answers
+            .iter()
+            .filter(|rr| qtype.matches(rr.type_()) && rr.name().eq_ignore_ascii_case(name))
+            .filter_map(record_addr)
+            .collect()
+    }
+
+    /// Returns the glue addresses of the name server `nsdname` in the *Additional* section.
+    fn glue<'a>(&'a self, nsdname: &'a DomainName) -> impl Iterator<Item = IpAddr> + 'a {
+        self.additional
+            .iter()
+            .filter(move |rr| rr.name().eq_ignore_ascii_case(nsdname))
+            .filter_map(record_addr)
+    }
+
+    /// If this is a referral for `qname` to a zone below `zone`, returns the zone cut and the
+    /// names of its name servers.
+    fn referral(
+        &self,
+        qname: &DomainName,
+        zone: &DomainName,
+    ) -> Option<(DomainName, Vec<DomainName>)> {
+        let mut cut: Option<DomainName> = None;
+        let mut nsdnames = Vec::new();
+        for rr in &self.authority {
+            let Some(Record::NS(ns)) = rr.record() else {
+                continue;
+            };
+            let owner = rr.name();
+            // The cut has to be strictly below the current zone, and at or above `qname`.
+            if owner.labels().len() <= zone.labels().len()
+                || !is_subdomain(owner, zone)
+                || !is_subdomain(qname, owner)
+            {
+                log::debug!("ignoring out-of-bailiwick referral to '{}'", owner);
+                continue;
+            }
+            match &cut {
+                Some(cut) if !cut.eq_ignore_ascii_case(owner) => continue,
+                Some(_) => {}
+                None => cut = Some(owner.clone()),
+            }
+            nsdnames.push(ns.nsdname().clone());
+        }
+        cut.map(|cut| (cut, nsdnames))
+    }
+}
+
+fn record_addr(rr: &OwnedResourceRecord) -> Option<IpAddr> {
+    match (rr.type_(), rr.record()) {
+        (Type::A, Some(Record::A(a))) => Some(IpAddr::V4(a.addr().octets().into())),
+        (Type::AAAA, Some(Record::AAAA(a))) => Some(IpAddr::V6(a.addr().octets().into())),
+        _ => None,
+    }
+}
+
+/// Returns whether `name` is equal to or below `parent`, ignoring ASCII case.
+fn is_subdomain(name: &DomainName, parent: &DomainName) -> bool {
+    let (name, parent) = (name.labels(), parent.labels());
+    name.len() >= parent.len()
+        && name[name.len() - parent.len()..]
+            .iter()
+            .zip(parent)
+            .all(|(a, b)| a.eq_ignore_ascii_case(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::{
+        packet::{
+            encoder::ResourceRecord,
+            records::{A, AAAA, NS, SOA},
+        },
+        server::{SyncServer, Zone},
+    };
+
+    use super::*;
+
+    fn domain(s: &str) -> DomainName {
+        match s {
+            "." => DomainName::ROOT,
+            _ => s.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn parse_hints() {
+        let hints = RootHints::parse(
+            "; root hints\n\
+             .                        3600000      NS    A.ROOT-SERVERS.NET.\n\
+             A.ROOT-SERVERS.NET.      3600000      A     198.41.0.4\n\
+             A.ROOT-SERVERS.NET.      3600000      AAAA  2001:503:ba3e::2:30\n\
+             b.root-servers.net.      IN           A     not-an-ip\n\
+             b.root-servers.net.      IN           A     170.247.170.2 ; comment\n",
+        );
+        let servers = hints
+            .servers()
+            .map(|(name, addrs)| format!("{} {:?}", name, addrs))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            servers,
+            [
+                "A.ROOT-SERVERS.NET. [198.41.0.4, 2001:503:ba3e::2:30]",
+                "b.root-servers.net. [170.247.170.2]",
+            ]
+        );
+        assert_eq!(RootHints::iana().addrs().count(), 26);
+    }
+
+    #[test]
+    fn priming_glue() {
+        let root = DomainName::ROOT;
+        let a_root = domain("a.root-servers.net");
+        let b_root = domain("b.root-servers.net");
+        let c_root = domain("c.root-servers.net");
+        let evil = domain("evil.example");
+
+        let mut buf = [0; 512];
+        let mut header = Header::default();
+        header.set_id(1);
+        header.set_response(true);
+        let mut enc = MessageEncoder::new(&mut buf);
+        enc.set_header(header);
+        enc.question(Question::new(&root).ty(QType::NS));
+        let mut enc = enc.answers();
+        let ns = |name: &DomainName| Record::NS(NS::new(name.clone()));
+        for name in [&a_root, &b_root, &c_root] {
+            enc.add_answer(ResourceRecord::new(&root, &ns(name)));
+        }
+        let mut enc = enc.authority().additional();
+        let a = |ip| Record::A(A::new(Ipv4Addr::new(10, 0, 0, ip)));
+        let aaaa = Record::AAAA(AAAA::new(Ipv6Addr::LOCALHOST));
+        enc.add_additional(ResourceRecord::new(&a_root, &a(1)));
+        enc.add_additional(ResourceRecord::new(&a_root, &aaaa));
+        enc.add_additional(ResourceRecord::new(&evil, &a(2)));
+        let len = enc.finish().unwrap();
+
+        let response = Response::decode(&buf[..len], 1, &root, QType::NS)
+            .unwrap()
+            .unwrap();
+        let mut hints = RootHints::new();
+        hints.add(domain("B.root-servers.net"), "10.0.0.3".parse().unwrap());
+        hints.add(evil, "10.0.0.4".parse().unwrap());
+        let primed = hints.primed_by(&response).unwrap();
+        assert_eq!(
+            primed.addrs().collect::<Vec<_>>(),
+            [
+                "10.0.0.1".parse::<IpAddr>().unwrap(),
+                "::1".parse().unwrap(),
+                "10.0.0.3".parse().unwrap(),
+            ]
+        );
+
+        assert!(Response::decode(&buf[..len], 2, &root, QType::NS)
+            .unwrap()
+            .is_none());
+        assert!(RootHints::new()
+            .primed_by(&Response {
+                rcode: RCode::NO_ERROR,
+                answers: Vec::new(),
+                authority: Vec::new(),
+                additional: Vec::new(),
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn minimized_names() {
+        let qname = domain("a.b.c.d.e.f.g.h.i.j.k.l");
+        let mut minimizer = Minimizer::default();
+        let mut names = Vec::new();
+        while let Some(name) = minimizer.next(&qname, &DomainName::ROOT) {
+            names.push(name.to_string());
+        }
+        assert_eq!(
+            names,
+            [
+                "l.",
+                "k.l.",
+                "j.k.l.",
+                "i.j.k.l.",
+                "h.i.j.k.l.",
+                "g.h.i.j.k.l.",
+                "f.g.h.i.j.k.l.",
+                "e.f.g.h.i.j.k.l.",
+                "c.d.e.f.g.h.i.j.k.l.",
+                "b.c.d.e.f.g.h.i.j.k.l.",
+            ]
+        );
+
+        // A referral skips ahead to the new zone cut.
+        let mut minimizer = Minimizer::default();
+        let zone = domain("h.i.j.k.l");
+        assert_eq!(
+            minimizer.next(&qname, &zone).unwrap(),
+            domain("g.h.i.j.k.l")
+        );
+        assert!(minimizer
+            .next(&domain("www.example.com"), &domain("example.com"))
+            .is_none());
+    }
+
+    fn zone(apex: &str, records: &[(&str, Record<'static>)]) -> Zone {
+        let apex = domain(apex);
+        let mut zone = Zone::new(apex.clone());
+        let soa = SOA::new(
+            domain("ns.invalid"),
+            domain("admin.invalid"),
+            1,
+            3600,
+            600,
+            86400,
+            60,
+        );
+        zone.add(apex, 3600, Record::SOA(soa)).unwrap();
+        for (name, record) in records {
+            zone.add(domain(name), 3600, record.clone()).unwrap();
+        }
+        zone
+    }
+
+    /// Runs a server for `zone` on `ip` in the background, returning its port.
+    fn serve(ip: Ipv4Addr, port: u16, zone: Zone) -> u16 {
+        let mut server = SyncServer::new((ip, port).into(), zone).unwrap();
+        let port = server.local_addr().unwrap().port();
+        thread::spawn(move || server.listen_blocking());
+        port
+    }
+
+    #[test]
+    fn iterate_from_root() {
+        let ns = |name| Record::NS(NS::new(domain(name)));
+        let a = |ip: [u8; 4]| Record::A(A::new(ip.into()));
+        let root = zone(
+            ".",
+            &[
+                (".", ns("a.root-servers.test")),
+                ("a.root-servers.test", a([127, 0, 0, 1])),
+                ("test", ns("ns.test")),
+                ("ns.test", a([127, 0, 0, 2])),
+            ],
+        );
+        let tld = zone(
+            "test",
+            &[
+                ("test", ns("ns.test")),
+                ("example.test", ns("ns.example.test")),
+                ("ns.example.test", a([127, 0, 0, 3])),
+            ],
+        );
+        let example = zone(
+            "example.test",
+            &[
+                ("example.test", ns("ns.example.test")),
+                ("www.example.test", a([192, 0, 2, 1])),
+                (
+                    "alias.example.test",
+                    Record::CNAME(crate::packet::records::CNAME::new(domain(
+                        "www.example.test",
+                    ))),
+                ),
+            ],
+        );
+        let port = serve(Ipv4Addr::new(127, 0, 0, 1), 0, root);
+        serve(Ipv4Addr::new(127, 0, 0, 2), port, tld);
+        serve(Ipv4Addr::new(127, 0, 0, 3), port, example);
+
+        let mut hints = RootHints::new();
+        hints.add(domain("a.root-servers.test"), Ipv4Addr::LOCALHOST.into());
+        let mut resolver = RecursiveResolver::with_hints(hints);
+        resolver.set_port(port);
+
+        let expected = ["192.0.2.1".parse::<IpAddr>().unwrap()];
+        for minimize in [true, false] {
+            resolver.set_qname_minimization(minimize);
+            assert_eq!(resolver.resolve("www.example.test").unwrap(), expected);
+            assert_eq!(resolver.resolve("alias.example.test").unwrap(), expected);
+            assert!(resolver.resolve("nope.example.test").unwrap().is_empty());
+            assert!(resolver.resolve("a.b.nope.test").unwrap().is_empty());
+        }
+        assert_eq!(
+            resolver.root_servers().unwrap().addrs().collect::<Vec<_>>(),
+            [IpAddr::from(Ipv4Addr::LOCALHOST)]
+        );
+    }
+}
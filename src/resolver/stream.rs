@@ -0,0 +1,683 @@
+//! Query pipelining over stream transports (TCP and DNS-over-TLS).
+//!
+//! Opening a new connection for every query is slow, particularly when TLS is involved. [RFC 7766]
+//! allows clients to keep a connection open, to send several queries over it without waiting for
+//! the previous responses, and requires servers to be prepared to answer them out of order.
+//! Responses are matched to their queries by message ID and question.
+//!
+//! [`Pipeline`] implements the client side of this without performing any I/O: it assigns message
+//! IDs, frames outgoing queries with their 2-byte length prefix, reassembles responses from the
+//! received bytes, and tracks how long the connection has been idle. [`SyncStreamClient`] drives
+//! it over a blocking stream, reconnecting when the connection is closed or has been idle for too
+//! long. Servers announce how long they keep idle connections open via the `edns-tcp-keepalive`
+//! option ([RFC 7828]), which [`SyncStreamClient`] uses as its idle timeout.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use uwuhi::resolver::{stream::SyncStreamClient, Resolve};
+//! let mut client = SyncStreamClient::connect_tcp("1.1.1.1:53".parse()?);
+//! // Both queries (`A` and `AAAA`) are sent before waiting for the responses.
+//! let addrs = client.resolve_name(&"example.com".parse()?)?;
+//! // This reuses the same connection.
+//! let more = client.resolve_name(&"example.org".parse()?)?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [RFC 7766]: https://datatracker.ietf.org/doc/html/rfc7766
+//! [RFC 7828]: https://datatracker.ietf.org/doc/html/rfc7828
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    hex::Hex,
+    name::DomainName,
+    packet::{
+        decoder::MessageDecoder,
+        edns::TcpKeepalive,
+        encoder::{MessageEncoder, Question},
+        records::Record,
+        Header, QClass, QType,
+    },
+    Error,
+};
+
+use super::{decode_records_answer, decode_tcp_keepalive, Resolve};
+
+/// Largest message that can be sent over a stream transport (limited by the 16-bit length
+/// prefix).
+const MAX_MESSAGE_SIZE: usize = u16::MAX as usize;
+
+/// A query that was sent, but not answered yet.
+struct Pending {
+    question: Option<(DomainName, QType, QClass)>,
+    /// The framed query, kept around so that it can be resent on a new connection.
+    frame: Vec<u8>,
+}
+
+/// Client-side state of a connection that carries pipelined DNS queries.
+///
+/// This type does not perform any I/O. Queries are submitted with [`Pipeline::send_query`], and
+/// the bytes that need to be written to the connection are retrieved via
+/// [`Pipeline::poll_transmit`]. Bytes read from the connection are passed to
+/// [`Pipeline::handle_data`], and the responses are then available via
+/// [`Pipeline::take_response`], in whatever order the server sent them.
+///
+/// A response is only accepted if its message ID belongs to an outstanding query, and its question
+/// matches that of the query. Anything else is logged and dropped.
+pub struct Pipeline {
+    outstanding: BTreeMap<u16, Pending>,
+    responses: BTreeMap<u16, Vec<u8>>,
+    transmit: VecDeque<Vec<u8>>,
+    recv_buf: Vec<u8>,
+    idle_timeout: Option<Duration>,
+    /// Time at which the connection last became idle, or any message was exchanged.
+    last_activity: Instant,
+}
+
+impl Pipeline {
+    /// The default idle timeout.
+    ///
+    /// Servers usually close idle connections after a few seconds. Closing them first avoids
+    /// sending a query into a connection that's about to be closed.
+    pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Creates the pipeline state for a freshly opened connection.
+    pub fn new(now: Instant) -> Self {
+        Self {
+            outstanding: BTreeMap::new(),
+            responses: BTreeMap::new(),
+            transmit: VecDeque::new(),
+            recv_buf: Vec::new(),
+            idle_timeout: Some(Self::DEFAULT_IDLE_TIMEOUT),
+            last_activity: now,
+        }
+    }
+
+    /// Sets how long the connection may stay idle before it should be closed.
+    ///
+    /// The connection is idle while no queries are outstanding. `None` keeps it open indefinitely.
+    /// Defaults to [`Pipeline::DEFAULT_IDLE_TIMEOUT`].
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_timeout = timeout;
+    }
+
+    /// Returns the idle timeout.
+    #[inline]
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// Returns the number of queries that have been sent, but not answered yet.
+    #[inline]
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    /// Returns whether the query with message ID `id` is still waiting for its response.
+    pub fn is_outstanding(&self, id: u16) -> bool {
+        self.outstanding.contains_key(&id)
+    }
+
+    /// Queues the DNS query `msg` for transmission.
+    ///
+    /// The message ID of `msg` is replaced with a random ID that is not used by any other
+    /// outstanding query. Returns that ID, which identifies the response.
+    ///
+    /// Returns [`Error::Truncated`] if `msg` is too large for a stream transport.
+    pub fn send_query(&mut self, msg: &[u8], now: Instant) -> Result<u16, Error> {
+        if msg.len() > MAX_MESSAGE_SIZE {
+            return Err(Error::Truncated);
+        }
+        let question = match MessageDecoder::new(msg)?.iter().next() {
+            Some(q) => {
+                let q = q?;
+                Some((q.qname().clone(), q.qtype(), q.qclass()))
+            }
+            None => None,
+        };
+        if self.outstanding.len() + self.responses.len() > usize::from(u16::MAX) {
+            return Err(Error::InvalidValue);
+        }
+        let id = loop {
+            let id = Header::random_id();
+            if !self.outstanding.contains_key(&id) && !self.responses.contains_key(&id) {
+                break id;
+            }
+        };
+
+        let mut frame = Vec::with_capacity(msg.len() + 2);
+        frame.extend_from_slice(&(msg.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&id.to_be_bytes());
+        frame.extend_from_slice(&msg[2..]);
+        self.transmit.push_back(frame.clone());
+        self.outstanding.insert(id, Pending { question, frame });
+        self.last_activity = now;
+        Ok(id)
+    }
+
+    /// Returns the next chunk of bytes to write to the connection, if any.
+    pub fn poll_transmit(&mut self) -> Option<Vec<u8>> {
+        self.transmit.pop_front()
+    }
+
+    /// Processes bytes read from the connection.
+    ///
+    /// `data` does not have to contain whole messages; partial messages are buffered until the
+    /// rest arrives.
+    pub fn handle_data(&mut self, data: &[u8], now: Instant) {
+        self.recv_buf.extend_from_slice(data);
+        let mut pos = 0;
+        while let Some(len) = self.recv_buf.get(pos..pos + 2) {
+            let len = usize::from(u16::from_be_bytes([len[0], len[1]]));
+            let Some(msg) = self.recv_buf.get(pos + 2..pos + 2 + len) else {
+                break;
+            };
+            let msg = msg.to_vec();
+            pos += 2 + len;
+            self.handle_message(msg, now);
+        }
+        self.recv_buf.drain(..pos);
+    }
+
+    fn handle_message(&mut self, msg: Vec<u8>, now: Instant) {
+        log::trace!("stream recv: {}", Hex(&msg));
+        let mut dec = match MessageDecoder::new(&msg) {
+            Ok(dec) => dec,
+            Err(e) => {
+                log::debug!("dropping malformed message: {}", e);
+                return;
+            }
+        };
+        let id = dec.header().id();
+        let Some(pending) = self.outstanding.get(&id) else {
+            log::debug!("dropping response with unknown ID {}", id);
+            return;
+        };
+        if !dec.header().is_response() {
+            log::debug!("dropping non-response message with ID {}", id);
+            return;
+        }
+        let question = match dec.iter().next() {
+            Some(Ok(q)) => Some((q.qname().clone(), q.qtype(), q.qclass())),
+            Some(Err(e)) => {
+                log::debug!("dropping response with malformed question: {}", e);
+                return;
+            }
+            None => None,
+        };
+        // Some servers omit the question from error responses, so only compare it if it's there.
+        if let (Some((name, qtype, qclass)), Some(expected)) = (&question, &pending.question) {
+            if !name.eq_ignore_ascii_case(&expected.0)
+                || *qtype != expected.1
+                || *qclass != expected.2
+            {
+                log::debug!("dropping response with ID {} for the wrong question", id);
+                return;
+            }
+        }
+
+        self.outstanding.remove(&id);
+        self.responses.insert(id, msg);
+        self.last_activity = now;
+    }
+
+    /// Returns the response to the query with message ID `id`, if it has arrived.
+    pub fn take_response(&mut self, id: u16) -> Option<Vec<u8>> {
+        self.responses.remove(&id)
+    }
+
+    /// Stops waiting for the response to the query with message ID `id`.
+    ///
+    /// If it arrives later, it is dropped.
+    pub fn cancel(&mut self, id: u16, now: Instant) {
+        self.responses.remove(&id);
+        if self.outstanding.remove(&id).is_some() && self.outstanding.is_empty() {
+            self.last_activity = now;
+        }
+    }
+
+    /// Returns the time at which the connection will have been idle for too long.
+    ///
+    /// Returns [`None`] while queries are outstanding, or if there is no idle timeout.
+    pub fn next_timeout(&self) -> Option<Instant> {
+        if !self.outstanding.is_empty() {
+            return None;
+        }
+        self.idle_timeout
+            .map(|timeout| self.last_activity + timeout)
+    }
+
+    /// Returns whether the connection has been idle for too long at `now`, and should be closed.
+    pub fn is_idle(&self, now: Instant) -> bool {
+        self.next_timeout().is_some_and(|at| now >= at)
+    }
+
+    /// Prepares the pipeline for a new connection, after the old one was closed.
+    ///
+    /// Partially received messages are discarded, and all outstanding queries are queued for
+    /// transmission again, since their responses will never arrive over the old connection.
+    pub fn reset(&mut self, now: Instant) {
+        self.recv_buf.clear();
+        self.transmit = self
+            .outstanding
+            .values()
+            .map(|pending| pending.frame.clone())
+            .collect();
+        self.last_activity = now;
+    }
+}
+
+/// Opens connections for a [`SyncStreamClient`].
+///
+/// This is implemented for closures returning a connected stream, so that any transport (for
+/// example, TLS) can be used.
+pub trait Connect<S>: Send {
+    /// Opens a new connection to the server.
+    fn connect(&mut self) -> io::Result<S>;
+}
+
+impl<S, F: FnMut() -> io::Result<S> + Send> Connect<S> for F {
+    fn connect(&mut self) -> io::Result<S> {
+        self()
+    }
+}
+
+/// A DNS client that pipelines queries over a blocking stream, and reuses the connection.
+///
+/// The connection is opened lazily when the first query is sent, and opened again when the server
+/// closes it or when it has been idle for longer than the [idle timeout]. Queries that were
+/// outstanding when the server closed the connection are resent once over the new connection.
+///
+/// Use [`SyncStreamClient::send_query`] and [`SyncStreamClient::recv_response`] to have several
+/// queries in flight at once, or [`SyncStreamClient::query`] for a single query. The
+/// [`Resolve`] implementation sends the `A` and `AAAA` queries for a name together.
+///
+/// When a response carries an `edns-tcp-keepalive` option, the timeout the server announced in it
+/// replaces the idle timeout. The queries sent by the [`Resolve`] implementation ask for that
+/// option; queries passed to [`SyncStreamClient::send_query`] have to include it themselves (see
+/// [`encode_tcp_query`](super::encode_tcp_query)).
+///
+/// [idle timeout]: Pipeline::set_idle_timeout
+pub struct SyncStreamClient<S> {
+    connector: Box<dyn Connect<S>>,
+    stream: Option<S>,
+    pipeline: Pipeline,
+}
+
+impl SyncStreamClient<TcpStream> {
+    /// The default time to wait for a response over TCP.
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Creates a client that sends queries to the DNS server at `addr` via TCP.
+    ///
+    /// The connection is opened when the first query is sent. Reading from the connection times
+    /// out after [`SyncStreamClient::DEFAULT_TIMEOUT`].
+    pub fn connect_tcp(addr: SocketAddr) -> Self {
+        Self::new(move || {
+            let stream = TcpStream::connect_timeout(&addr, Self::DEFAULT_TIMEOUT)?;
+            stream.set_nodelay(true)?;
+            stream.set_read_timeout(Some(Self::DEFAULT_TIMEOUT))?;
+            Ok(stream)
+        })
+    }
+}
+
+impl<S: Read + Write> SyncStreamClient<S> {
+    /// Creates a client that opens connections with `connector`.
+    ///
+    /// Timeouts are up to the streams returned by `connector`: if reading from a stream never
+    /// times out, waiting for a response that never arrives blocks forever.
+    pub fn new(connector: impl Connect<S> + 'static) -> Self {
+        Self {
+            connector: Box::new(connector),
+            stream: None,
+            pipeline: Pipeline::new(Instant::now()),
+        }
+    }
+
+    /// Returns a reference to the pipeline state.
+    #[inline]
+    pub fn pipeline(&self) -> &Pipeline {
+        &self.pipeline
+    }
+
+    /// Sets how long the connection may stay idle before it is closed.
+    ///
+    /// See [`Pipeline::set_idle_timeout`].
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.pipeline.set_idle_timeout(timeout);
+    }
+
+    /// Returns a reference to the current connection, if one is open.
+    #[inline]
+    pub fn get_ref(&self) -> Option<&S> {
+        self.stream.as_ref()
+    }
+
+    /// Sends the DNS query `msg`, and returns the message ID that identifies its response.
+    ///
+    /// The message ID in `msg` is replaced (see [`Pipeline::send_query`]).
+    pub fn send_query(&mut self, msg: &[u8]) -> Result<u16, Error> {
+        let now = Instant::now();
+        if self.pipeline.is_idle(now) && self.stream.take().is_some() {
+            log::debug!("closing idle connection");
+        }
+        let id = self.pipeline.send_query(msg, now)?;
+        if let Err(e) = self.flush_transmit() {
+            self.pipeline.cancel(id, Instant::now());
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    /// Waits for the response to the query with message ID `id`.
+    ///
+    /// Responses to other queries that arrive in the meantime are kept until they are asked for.
+    /// If the stream times out, the query is cancelled and [`Error::Timeout`] is returned.
+    pub fn recv_response(&mut self, id: u16) -> Result<Vec<u8>, Error> {
+        let mut reconnected = false;
+        let mut buf = vec![0; 4096];
+        loop {
+            if let Some(msg) = self.pipeline.take_response(id) {
+                match decode_tcp_keepalive(&msg) {
+                    Ok(Some(timeout)) => {
+                        log::trace!("server keeps the connection open for {:?}", timeout);
+                        self.pipeline.set_idle_timeout(Some(timeout));
+                    }
+                    Ok(None) => {}
+                    Err(e) => log::debug!("invalid edns-tcp-keepalive option: {}", e),
+                }
+                return Ok(msg);
+            }
+            if !self.pipeline.is_outstanding(id) {
+                // Cancelled, or never sent.
+                return Err(Error::InvalidValue);
+            }
+
+            let res = match &mut self.stream {
+                Some(stream) => stream.read(&mut buf),
+                None => Ok(0),
+            };
+            match res {
+                Ok(0) if !reconnected => {
+                    log::debug!("connection closed, reconnecting");
+                    reconnected = true;
+                    self.stream = None;
+                    self.pipeline.reset(Instant::now());
+                    if let Err(e) = self.flush_transmit() {
+                        self.pipeline.cancel(id, Instant::now());
+                        return Err(e);
+                    }
+                }
+                Ok(0) => {
+                    self.stream = None;
+                    self.pipeline.cancel(id, Instant::now());
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                }
+                Ok(n) => self.pipeline.handle_data(&buf[..n], Instant::now()),
+                Err(e) => {
+                    self.pipeline.cancel(id, Instant::now());
+                    let e = Error::from(e);
+                    return Err(if e.is_timeout() { Error::Timeout } else { e });
+                }
+            }
+        }
+    }
+
+    /// Sends the DNS query `msg` and waits for its response.
+    pub fn query(&mut self, msg: &[u8]) -> Result<Vec<u8>, Error> {
+        let id = self.send_query(msg)?;
+        self.recv_response(id)
+    }
+
+    /// Encodes a query for `name` and `qtype` with an `edns-tcp-keepalive` option, and sends it.
+    fn send_question(&mut self, name: &DomainName, qtype: QType) -> Result<u16, Error> {
+        let mut header = Header::default();
+        header.set_recursion_desired(true);
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf);
+        enc.set_header(header);
+        enc.question(Question::new(name).ty(qtype));
+        let mut enc = enc.answers().authority().additional();
+        enc.edns(u16::MAX).tcp_keepalive(&TcpKeepalive::new(None));
+        let len = enc.finish()?;
+        self.send_query(&buf[..len])
+    }
+
+    fn flush_transmit(&mut self) -> Result<(), Error> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => {
+                let stream = self.connector.connect()?;
+                self.stream.insert(stream)
+            }
+        };
+        while let Some(frame) = self.pipeline.poll_transmit() {
+            log::trace!("stream send: {}", Hex(&frame[2..]));
+            if let Err(e) = stream.write_all(&frame).and_then(|()| stream.flush()) {
+                self.stream = None;
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> Resolve for SyncStreamClient<S> {
+    /// Resolves `name` by sending its `A` and `AAAA` queries at once, over the same connection.
+    fn resolve_name(&mut self, name: &DomainName) -> Result<Vec<IpAddr>, Error> {
+        let a = self.send_question(name, QType::A)?;
+        let aaaa = match self.send_question(name, QType::AAAA) {
+            Ok(id) => id,
+            Err(e) => {
+                self.pipeline.cancel(a, Instant::now());
+                return Err(e);
+            }
+        };
+
+        let mut records = Vec::new();
+        let mut error = None;
+        for (id, qtype) in [(a, QType::A), (aaaa, QType::AAAA)] {
+            match self.recv_response(id) {
+                Ok(msg) => decode_records_answer(&msg, name, qtype, &mut records)?,
+                Err(e) => {
+                    log::debug!("{:?} query for '{}' failed: {}", qtype, name, e);
+                    error = Some(e);
+                }
+            }
+        }
+
+        let addrs: Vec<IpAddr> = records
+            .iter()
+            .filter_map(|record| match record {
+                Record::A(a) => Some(IpAddr::V4(a.addr())),
+                Record::AAAA(aaaa) => Some(IpAddr::V6(aaaa.addr())),
+                _ => None,
+            })
+            .collect();
+        match error {
+            Some(e) if addrs.is_empty() => Err(e),
+            _ => Ok(addrs),
+        }
+    }
+
+    fn resolve_records(
+        &mut self,
+        name: &DomainName,
+        qtype: QType,
+    ) -> Result<Vec<Record<'static>>, Error> {
+        let id = self.send_question(name, qtype)?;
+        let msg = self.recv_response(id)?;
+        let mut records = Vec::new();
+        decode_records_answer(&msg, name, qtype, &mut records)?;
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, TcpListener},
+        thread,
+    };
+
+    use crate::packet::{encoder, records::A};
+
+    use super::*;
+
+    fn query(name: &str, qtype: QType) -> Vec<u8> {
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf);
+        enc.question(Question::new(&name.parse().unwrap()).ty(qtype));
+        let len = enc.finish().unwrap();
+        buf[..len].to_vec()
+    }
+
+    fn response(id: u16, name: &str, qtype: QType, addr: Option<Ipv4Addr>) -> Vec<u8> {
+        let name: DomainName = name.parse().unwrap();
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf);
+        let mut header = Header::default();
+        header.set_id(id);
+        header.set_response(true);
+        enc.set_header(header);
+        enc.question(Question::new(&name).ty(qtype));
+        let mut enc = enc.answers();
+        if let Some(addr) = addr {
+            enc.add_answer(encoder::ResourceRecord::new(&name, &Record::A(A::new(addr))).ttl(60));
+        }
+        let len = enc.finish().unwrap();
+        buf[..len].to_vec()
+    }
+
+    fn frame(msg: &[u8]) -> Vec<u8> {
+        let mut frame = (msg.len() as u16).to_be_bytes().to_vec();
+        frame.extend_from_slice(msg);
+        frame
+    }
+
+    #[test]
+    fn out_of_order() {
+        let now = Instant::now();
+        let mut pipeline = Pipeline::new(now);
+        let first = pipeline
+            .send_query(&query("a.example", QType::A), now)
+            .unwrap();
+        let second = pipeline
+            .send_query(&query("b.example", QType::A), now)
+            .unwrap();
+        assert_ne!(first, second);
+        assert_eq!(pipeline.outstanding(), 2);
+        let sent = pipeline.poll_transmit().unwrap();
+        assert_eq!(&sent[2..4], &first.to_be_bytes());
+        assert!(pipeline.poll_transmit().is_some());
+        assert!(pipeline.poll_transmit().is_none());
+        assert_eq!(pipeline.next_timeout(), None);
+
+        // The second response arrives first, split across reads, and together with a response
+        // to the right ID but the wrong question.
+        let mut data = frame(&response(first, "other.example", QType::A, None));
+        data.extend(frame(&response(second, "b.example", QType::A, None)));
+        data.extend(frame(&response(first, "a.example", QType::A, None)));
+        let (start, end) = data.split_at(7);
+        pipeline.handle_data(start, now);
+        assert_eq!(pipeline.outstanding(), 2);
+        pipeline.handle_data(end, now);
+        assert_eq!(pipeline.outstanding(), 0);
+
+        let msg = pipeline.take_response(first).unwrap();
+        let mut dec = MessageDecoder::new(&msg).unwrap();
+        assert_eq!(
+            dec.iter().next().unwrap().unwrap().qname().to_string(),
+            "a.example."
+        );
+        assert!(pipeline.take_response(second).is_some());
+        assert!(pipeline.take_response(first).is_none());
+
+        // Without outstanding queries, the idle timeout applies.
+        assert!(!pipeline.is_idle(now));
+        assert!(pipeline.is_idle(now + Pipeline::DEFAULT_IDLE_TIMEOUT));
+    }
+
+    #[test]
+    fn reset_resends() {
+        let now = Instant::now();
+        let mut pipeline = Pipeline::new(now);
+        let id = pipeline
+            .send_query(&query("a.example", QType::A), now)
+            .unwrap();
+        let sent = pipeline.poll_transmit().unwrap();
+        pipeline.handle_data(&[0, 40, 1, 2], now);
+
+        pipeline.reset(now);
+        assert_eq!(pipeline.poll_transmit(), Some(sent));
+        pipeline.handle_data(&frame(&response(id, "a.example", QType::A, None)), now);
+        assert!(pipeline.take_response(id).is_some());
+
+        let id = pipeline
+            .send_query(&query("a.example", QType::A), now)
+            .unwrap();
+        pipeline.cancel(id, now);
+        pipeline.reset(now);
+        assert_eq!(pipeline.poll_transmit(), None);
+    }
+
+    #[test]
+    fn client() {
+        // Accepts one connection, waits for two queries, and answers them in reverse order. Then
+        // closes the connection after one more query, and answers it on the next connection.
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let read_query = |conn: &mut TcpStream| {
+                let mut len = [0; 2];
+                conn.read_exact(&mut len).unwrap();
+                let mut msg = vec![0; usize::from(u16::from_be_bytes(len))];
+                conn.read_exact(&mut msg).unwrap();
+                let mut dec = MessageDecoder::new(&msg).unwrap();
+                let id = dec.header().id();
+                let q = dec.iter().next().unwrap().unwrap();
+                (id, q.qname().to_string(), q.qtype())
+            };
+            let answer = |conn: &mut TcpStream, (id, name, qtype): (u16, String, QType)| {
+                let addr = (qtype == QType::A).then_some(Ipv4Addr::new(192, 0, 2, 1));
+                conn.write_all(&frame(&response(id, &name, qtype, addr)))
+                    .unwrap();
+            };
+
+            let (mut conn, _) = listener.accept().unwrap();
+            let first = read_query(&mut conn);
+            let second = read_query(&mut conn);
+            answer(&mut conn, second);
+            answer(&mut conn, first);
+
+            read_query(&mut conn);
+            drop(conn);
+            let (mut conn, _) = listener.accept().unwrap();
+            let resent = read_query(&mut conn);
+            answer(&mut conn, resent);
+        });
+
+        let mut client = SyncStreamClient::connect_tcp(addr);
+        let addrs = client.resolve_name(&"a.example".parse().unwrap()).unwrap();
+        assert_eq!(addrs, [IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]);
+
+        let response = client.query(&query("b.example", QType::A)).unwrap();
+        let mut records = Vec::new();
+        decode_records_answer(
+            &response,
+            &"b.example".parse().unwrap(),
+            QType::A,
+            &mut records,
+        )
+        .unwrap();
+        assert_eq!(records.len(), 1);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,450 @@
+//! Service binding resolution for HTTPS origins ([RFC 9460]).
+//!
+//! `HTTPS` records tell clients how to connect to an origin before they connect to it: which
+//! server to use (possibly a different one than the origin host), on which port, with which ALPN
+//! protocols, and which addresses to try. [`resolve_https`] performs the client side of this:
+//! it follows AliasMode records, orders the ServiceMode records by priority, resolves their
+//! targets, and falls back to plain `A`/`AAAA` resolution of the origin when no usable record
+//! exists.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use uwuhi::resolver::{svcb::resolve_https, SyncResolver};
+//! let mut resolver = SyncResolver::new("1.1.1.1:53".parse()?)?;
+//! for candidate in resolve_https(&mut resolver, &"example.com".parse()?, 443)? {
+//!     println!("{} port {}: {:?}", candidate.target(), candidate.port(), candidate.addrs());
+//! }
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [RFC 9460]: https://datatracker.ietf.org/doc/html/rfc9460
+
+use std::net::{IpAddr, SocketAddr};
+
+use crate::{
+    name::{DomainName, Label},
+    packet::{
+        records::{Record, SvcParamKey, SVCB},
+        QType,
+    },
+    Error,
+};
+
+use super::{addr_select, Resolve};
+
+/// Maximum number of AliasMode records to follow before giving up on the service binding.
+const MAX_ALIAS_CHAIN: usize = 8;
+
+/// The service parameter keys whose meaning this module understands.
+///
+/// Records listing any other key as mandatory are skipped, as required by RFC 9460.
+const SUPPORTED_KEYS: &[SvcParamKey] = &[
+    SvcParamKey::MANDATORY,
+    SvcParamKey::ALPN,
+    SvcParamKey::NO_DEFAULT_ALPN,
+    SvcParamKey::PORT,
+    SvcParamKey::IPV4HINT,
+    SvcParamKey::ECH,
+    SvcParamKey::IPV6HINT,
+];
+
+/// An endpoint a client may connect to, as returned by [`resolve_https`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionCandidate {
+    target: DomainName,
+    port: u16,
+    alpn: Vec<Vec<u8>>,
+    no_default_alpn: bool,
+    ech: Option<Vec<u8>>,
+    addrs: Vec<IpAddr>,
+    from_record: bool,
+}
+
+impl ConnectionCandidate {
+    /// Returns the name of the server to connect to.
+    ///
+    /// This is the name whose addresses are in [`ConnectionCandidate::addrs`]. TLS certificates
+    /// are still validated against the origin name, not against this one.
+    pub fn target(&self) -> &DomainName {
+        &self.target
+    }
+
+    /// Returns the port to connect to.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Returns the ALPN protocol identifiers advertised by the service, in order of preference.
+    ///
+    /// Unless [`ConnectionCandidate::no_default_alpn`] returns `true`, the default protocol of the
+    /// scheme (`http/1.1` for HTTPS) is supported as well.
+    pub fn alpn(&self) -> &[Vec<u8>] {
+        &self.alpn
+    }
+
+    /// Returns whether the service does *not* support the default protocol of the scheme.
+    pub fn no_default_alpn(&self) -> bool {
+        self.no_default_alpn
+    }
+
+    /// Returns the Encrypted Client Hello configuration list of the service, if it has one.
+    pub fn ech(&self) -> Option<&[u8]> {
+        self.ech.as_deref()
+    }
+
+    /// Returns the addresses of the target, sorted in the order they should be tried.
+    ///
+    /// These are the resolved addresses of the target if it had any, and the address hints from
+    /// the record otherwise.
+    pub fn addrs(&self) -> &[IpAddr] {
+        &self.addrs
+    }
+
+    /// Returns the socket addresses to connect to, in the order they should be tried.
+    pub fn socket_addrs(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.addrs
+            .iter()
+            .map(move |ip| SocketAddr::new(*ip, self.port))
+    }
+
+    /// Returns whether this candidate was derived from an `HTTPS` record.
+    ///
+    /// If this is `false`, the origin had no usable `HTTPS` records, and this candidate is the
+    /// origin host itself, on the port that was passed to [`resolve_https`].
+    pub fn from_record(&self) -> bool {
+        self.from_record
+    }
+}
+
+/// Resolves the connection candidates for the HTTPS origin `host`:`port`.
+///
+/// The `HTTPS` records of the origin are looked up via [`Resolve::resolve_records`], following
+/// AliasMode records, and the ServiceMode records are turned into candidates in order of their
+/// priority. The addresses of each target are resolved via [`Resolve::resolve_name`], and the
+/// address hints of the record are used if that yields none. Records that list an unsupported
+/// parameter as mandatory, or whose target has no addresses, are skipped.
+///
+/// If the origin has no usable `HTTPS` records, a single candidate for `host` and `port` is
+/// returned, as if no service binding existed. The returned list is only empty if `host` has no
+/// addresses either.
+///
+/// Errors while looking up the `HTTPS` records are logged and treated like the absence of
+/// records; only errors while resolving the fallback are returned.
+pub fn resolve_https<R: Resolve + ?Sized>(
+    resolver: &mut R,
+    host: &DomainName,
+    port: u16,
+) -> Result<Vec<ConnectionCandidate>, Error> {
+    let mut candidates = Vec::new();
+    for (owner, svcb) in service_records(resolver, host, port) {
+        let Some(candidate) = candidate(resolver, &owner, &svcb, port) else {
+            continue;
+        };
+        candidates.push(candidate);
+    }
+
+    if candidates.is_empty() {
+        let mut addrs = resolver.resolve_name(host)?;
+        if !addrs.is_empty() {
+            sort_addrs(&mut addrs);
+            candidates.push(ConnectionCandidate {
+                target: host.clone(),
+                port,
+                alpn: Vec::new(),
+                no_default_alpn: false,
+                ech: None,
+                addrs,
+                from_record: false,
+            });
+        }
+    }
+    Ok(candidates)
+}
+
+/// Returns the name owning the `HTTPS` records of the origin `host`:`port`.
+///
+/// Port 443 uses the host name itself, other ports are prefixed by "port prefix naming"
+/// (`_8443._https.example.com`).
+fn query_name(host: &DomainName, port: u16) -> DomainName {
+    if port == 443 {
+        return host.clone();
+    }
+    [Label::new(format!("_{port}")), Label::new("_https")]
+        .iter()
+        .chain(host.labels())
+        .collect()
+}
+
+/// Looks up the ServiceMode records of the origin, following AliasMode records.
+///
+/// Returns the name that owns the records, along with the records in order of their priority.
+fn service_records<R: Resolve + ?Sized>(
+    resolver: &mut R,
+    host: &DomainName,
+    port: u16,
+) -> Vec<(DomainName, SVCB<'static>)> {
+    let mut owner = query_name(host, port);
+    for _ in 0..MAX_ALIAS_CHAIN {
+        let records = match resolver.resolve_records(&owner, QType::HTTPS) {
+            Ok(records) => records,
+            Err(e) => {
+                log::debug!("failed to look up HTTPS records of '{}': {}", owner, e);
+                return Vec::new();
+            }
+        };
+        let mut records: Vec<_> = records
+            .into_iter()
+            .filter_map(|record| match record {
+                Record::HTTPS(https) => Some(SVCB::clone(&https)),
+                _ => None,
+            })
+            .collect();
+
+        // AliasMode records take precedence; ServiceMode records next to one are ignored.
+        if let Some(alias) = records.iter().find(|svcb| svcb.is_alias()) {
+            if alias.target().labels().is_empty() {
+                // An alias to the root name says that the service is not available via SVCB.
+                return Vec::new();
+            }
+            log::trace!(
+                "following HTTPS alias from '{}' to '{}'",
+                owner,
+                alias.target()
+            );
+            owner = alias.target().clone();
+            continue;
+        }
+
+        records.sort_by_key(|svcb| svcb.priority());
+        return records
+            .into_iter()
+            .map(|svcb| (owner.clone(), svcb))
+            .collect();
+    }
+
+    log::debug!("HTTPS alias chain of '{}' is too long", host);
+    Vec::new()
+}
+
+/// Turns a ServiceMode record owned by `owner` into a candidate.
+///
+/// Returns [`None`] if the record is not usable.
+fn candidate<R: Resolve + ?Sized>(
+    resolver: &mut R,
+    owner: &DomainName,
+    svcb: &SVCB<'_>,
+    port: u16,
+) -> Option<ConnectionCandidate> {
+    if let Some(key) = svcb.mandatory().find(|key| !SUPPORTED_KEYS.contains(key)) {
+        log::debug!(
+            "skipping HTTPS record of '{}' with mandatory key {}",
+            owner,
+            key
+        );
+        return None;
+    }
+
+    // In ServiceMode, a target of "." refers to the owner of the record.
+    let target = if svcb.target().labels().is_empty() {
+        owner.clone()
+    } else {
+        svcb.target().clone()
+    };
+    let mut addrs = match resolver.resolve_name(&target) {
+        Ok(addrs) => addrs,
+        Err(e) => {
+            log::debug!("failed to resolve HTTPS target '{}': {}", target, e);
+            Vec::new()
+        }
+    };
+    if addrs.is_empty() {
+        addrs.extend(svcb.ipv6_hints().map(IpAddr::V6));
+        addrs.extend(svcb.ipv4_hints().map(IpAddr::V4));
+    }
+    if addrs.is_empty() {
+        log::debug!("HTTPS target '{}' has no addresses", target);
+        return None;
+    }
+    sort_addrs(&mut addrs);
+
+    Some(ConnectionCandidate {
+        target,
+        port: svcb.port().unwrap_or(port),
+        alpn: svcb.alpn().map(<[u8]>::to_vec).collect(),
+        no_default_alpn: svcb.no_default_alpn(),
+        ech: svcb.ech().map(<[u8]>::to_vec),
+        addrs,
+        from_record: true,
+    })
+}
+
+fn sort_addrs(addrs: &mut [IpAddr]) {
+    let sources = addr_select::source_addrs(addrs);
+    addr_select::sort_destinations(addrs, &sources);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use crate::packet::records::HTTPS;
+
+    use super::*;
+
+    /// A resolver answering from a fixed set of records.
+    #[derive(Default)]
+    struct Zone {
+        https: Vec<(DomainName, HTTPS<'static>)>,
+        addrs: Vec<(DomainName, IpAddr)>,
+    }
+
+    impl Zone {
+        fn https(mut self, owner: &str, https: HTTPS<'static>) -> Self {
+            self.https.push((owner.parse().unwrap(), https));
+            self
+        }
+
+        fn addr(mut self, owner: &str, addr: IpAddr) -> Self {
+            self.addrs.push((owner.parse().unwrap(), addr));
+            self
+        }
+    }
+
+    impl Resolve for Zone {
+        fn resolve_name(&mut self, name: &DomainName) -> Result<Vec<IpAddr>, Error> {
+            Ok(self
+                .addrs
+                .iter()
+                .filter(|(owner, _)| owner.eq_ignore_ascii_case(name))
+                .map(|(_, addr)| *addr)
+                .collect())
+        }
+
+        fn resolve_records(
+            &mut self,
+            name: &DomainName,
+            qtype: QType,
+        ) -> Result<Vec<Record<'static>>, Error> {
+            assert_eq!(qtype, QType::HTTPS);
+            Ok(self
+                .https
+                .iter()
+                .filter(|(owner, _)| owner.eq_ignore_ascii_case(name))
+                .map(|(_, https)| Record::HTTPS(https.clone()))
+                .collect())
+        }
+    }
+
+    fn name(s: &str) -> DomainName {
+        s.parse().unwrap()
+    }
+
+    const V4: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const V4_2: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    #[test]
+    fn fallback() {
+        let mut zone = Zone::default().addr("example.com", V4);
+        let candidates = resolve_https(&mut zone, &name("example.com"), 443).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].target(), &name("example.com"));
+        assert_eq!(candidates[0].port(), 443);
+        assert_eq!(candidates[0].addrs(), &[V4]);
+        assert!(!candidates[0].from_record());
+
+        let mut zone = Zone::default();
+        let candidates = resolve_https(&mut zone, &name("example.com"), 443).unwrap();
+        assert!(candidates.is_empty());
+    }
+
+    #[test]
+    fn service_mode() {
+        let mut h3 = HTTPS::new(1, name("svc.example.net"));
+        h3.set_param(SvcParamKey::ALPN, b"\x02h3");
+        h3.set_param(SvcParamKey::PORT, &8443u16.to_be_bytes());
+        let mut h2 = HTTPS::new(2, name("."));
+        h2.set_param(SvcParamKey::ALPN, b"\x02h2");
+        h2.set_param(SvcParamKey::IPV4HINT, &[192, 0, 2, 2]);
+
+        let mut zone = Zone::default()
+            .https("example.com", h2)
+            .https("example.com", h3)
+            .addr("svc.example.net", V4)
+            .addr("example.com", V4);
+        let candidates = resolve_https(&mut zone, &name("example.com"), 443).unwrap();
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0].target(), &name("svc.example.net"));
+        assert_eq!(candidates[0].port(), 8443);
+        assert_eq!(candidates[0].alpn(), &[b"h3".to_vec()]);
+        assert_eq!(
+            candidates[0].socket_addrs().collect::<Vec<_>>(),
+            ["192.0.2.1:8443".parse().unwrap()]
+        );
+        // "." refers to the owner, and its resolved addresses take precedence over the hints.
+        assert_eq!(candidates[1].target(), &name("example.com"));
+        assert_eq!(candidates[1].port(), 443);
+        assert_eq!(candidates[1].alpn(), &[b"h2".to_vec()]);
+        assert_eq!(candidates[1].addrs(), &[V4]);
+        assert!(candidates.iter().all(|c| c.from_record()));
+    }
+
+    #[test]
+    fn alias_mode() {
+        let mut svc = HTTPS::new(1, name("."));
+        svc.set_param(SvcParamKey::IPV4HINT, &[192, 0, 2, 2]);
+        svc.set_param(
+            SvcParamKey::IPV6HINT,
+            &Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).octets(),
+        );
+        let mut zone = Zone::default()
+            .https(
+                "_8080._https.example.com",
+                HTTPS::new(0, name("pool.example.net")),
+            )
+            .https(
+                "_8080._https.example.com",
+                HTTPS::new(1, name("ignored.example")),
+            )
+            .https("pool.example.net", svc);
+        let candidates = resolve_https(&mut zone, &name("example.com"), 8080).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].target(), &name("pool.example.net"));
+        assert_eq!(candidates[0].port(), 8080);
+        assert_eq!(candidates[0].addrs().len(), 2);
+        assert!(candidates[0].addrs().contains(&V4_2));
+
+        // An alias to "." disables SVCB for the origin.
+        let mut zone = Zone::default()
+            .https("example.com", HTTPS::new(0, name(".")))
+            .https("example.com", HTTPS::new(1, name("svc.example.net")))
+            .addr("svc.example.net", V4_2)
+            .addr("example.com", V4);
+        let candidates = resolve_https(&mut zone, &name("example.com"), 443).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].addrs(), &[V4]);
+        assert!(!candidates[0].from_record());
+
+        // Alias loops end in the fallback.
+        let mut zone = Zone::default()
+            .https("a.example", HTTPS::new(0, name("b.example")))
+            .https("b.example", HTTPS::new(0, name("a.example")))
+            .addr("a.example", V4);
+        let candidates = resolve_https(&mut zone, &name("a.example"), 443).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert!(!candidates[0].from_record());
+    }
+
+    #[test]
+    fn unsupported_mandatory_key() {
+        let mut svc = HTTPS::new(1, name("."));
+        svc.set_param(SvcParamKey::MANDATORY, &65000u16.to_be_bytes());
+        svc.set_param(SvcParamKey(65000), b"");
+        let mut zone = Zone::default()
+            .https("example.com", svc)
+            .addr("example.com", V4);
+        let candidates = resolve_https(&mut zone, &name("example.com"), 443).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert!(!candidates[0].from_record());
+    }
+}
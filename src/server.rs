@@ -0,0 +1,2788 @@
+//! Authoritative DNS server.
+//!
+//! A [`Zone`] holds the resource records of a DNS zone, and can be signed ahead of time with
+//! [`Zone::sign`], which adds the `NSEC` chain and `RRSIG` records required by DNSSEC. [`Server`]
+//! answers queries for a zone without performing any I/O, and [`SyncServer`] runs a [`Server`] on
+//! a UDP socket and a TCP listener.
+//!
+//! Besides serving the static contents of a zone, a server can synthesize answers at query time
+//! via [`Policy`] hooks, registered for the whole zone or for individual names. This allows things
+//! like round-robin or location-dependent answers.
+//!
+//! DNSSEC records are only included in responses to queries that set the `DO` bit in their `OPT`
+//! record ([RFC 3225]). Negative responses then carry the `NSEC` records proving that the name or
+//! type does not exist.
+//!
+//! Clients on different networks can be given different answers for the same name by adding
+//! *views* (also known as split-horizon DNS) via [`Server::add_view`]. Each view is a [`Zone`]
+//! layered on top of the server's main zone, and selected by the client's source address.
+//!
+//! Servers can act as the primary or a secondary server for their zone: primaries announce zone
+//! changes to their secondaries via `NOTIFY` messages ([RFC 1996]), and secondaries respond to
+//! them by fetching the updated zone (see the [`transfer`] module).
+//!
+//! TCP connections are kept open for further queries until they have been idle for a while. Clients
+//! can learn the idle timeout via the `edns-tcp-keepalive` option ([RFC 7828]).
+//!
+//! To keep servers from being abused for reflection attacks, responses over UDP can be rate limited
+//! (see the [`rrl`] module).
+//!
+//! [RFC 1996]: https://datatracker.ietf.org/doc/html/rfc1996
+//! [RFC 3225]: https://datatracker.ietf.org/doc/html/rfc3225
+//! [RFC 7828]: https://datatracker.ietf.org/doc/html/rfc7828
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    acl::Acl,
+    clock::{Clock, SystemClock},
+    domain,
+    hex::Hex,
+    label,
+    name::DomainName,
+    packet::{
+        decoder::{self, MessageDecoder},
+        dnssec::{Signer, Validity},
+        edns::{ExtendedError, ExtendedErrorCode, OptEncoder, OptionCode, TcpKeepalive},
+        encoder::{self, MessageEncoder, Question, ResourceRecord},
+        records::{Record, CNAME, HINFO, MX, NS, NSEC, PTR, RRSIG, SOA, SRV, TXT},
+        section, Class, Header, Opcode, QClass, QType, RCode, Type,
+    },
+    resolver::ResolveError,
+    Error, DNS_BUFFER_SIZE,
+};
+
+pub mod rrl;
+pub mod transfer;
+
+use rrl::{RateLimitAction, RateLimiter};
+
+/// UDP payload size advertised in our `OPT` records.
+///
+/// This is the value recommended by DNS Flag Day 2020, which avoids IP fragmentation on virtually
+/// all networks.
+const EDNS_PAYLOAD_SIZE: u16 = 1232;
+
+/// Default time after which idle TCP connections are closed.
+const DEFAULT_TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for a secondary to acknowledge a `NOTIFY`, and for a primary to answer
+/// during a zone refresh.
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often to send a `NOTIFY` before giving up on a secondary.
+const NOTIFY_ATTEMPTS: usize = 3;
+
+/// A domain name, ordered according to the canonical DNS name order ([RFC 4034, section 6.1]).
+///
+/// All names in a [`Zone`] are lowercase, so this only has to compare the labels from right to
+/// left.
+///
+/// [RFC 4034, section 6.1]: https://datatracker.ietf.org/doc/html/rfc4034#section-6.1
+#[derive(Clone, PartialEq, Eq)]
+struct CanonicalName(DomainName);
+
+impl PartialOrd for CanonicalName {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CanonicalName {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .labels()
+            .iter()
+            .rev()
+            .cmp(other.0.labels().iter().rev())
+    }
+}
+
+struct RRset {
+    ty: Type,
+    ttl: u32,
+    records: Vec<Record<'static>>,
+}
+
+/// The resource records of a DNS zone.
+///
+/// All names and records are stored in lowercase, and in the internet class ([`Class::IN`]).
+pub struct Zone {
+    apex: DomainName,
+    names: BTreeMap<CanonicalName, Vec<RRset>>,
+}
+
+impl Zone {
+    /// Creates an empty zone rooted at `apex`.
+    ///
+    /// The zone needs at least an [`SOA`] record at the apex to be served.
+    pub fn new(apex: DomainName) -> Self {
+        Self {
+            apex: apex.to_ascii_lowercase(),
+            names: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the name of the zone apex.
+    #[inline]
+    pub fn apex(&self) -> &DomainName {
+        &self.apex
+    }
+
+    /// Returns the serial number from the zone's `SOA` record, if it has one.
+    pub fn serial(&self) -> Option<u32> {
+        self.soa().map(|(_, soa)| soa.serial())
+    }
+
+    /// Adds a record to the zone.
+    ///
+    /// All records of an RRset share the same TTL, so this sets the TTL of every record with the
+    /// same name and type as `record`.
+    ///
+    /// Returns [`Error::InvalidValue`] if `name` is not inside the zone.
+    pub fn add(
+        &mut self,
+        name: DomainName,
+        ttl: u32,
+        record: Record<'static>,
+    ) -> Result<(), Error> {
+        let name = name.to_ascii_lowercase();
+        if !is_subdomain(&name, &self.apex) {
+            return Err(Error::InvalidValue);
+        }
+        let record = lowercase_record(record);
+        let rrsets = self.names.entry(CanonicalName(name)).or_default();
+        let ty = record.record_type();
+        match rrsets.iter_mut().find(|rrset| rrset.ty == ty) {
+            Some(rrset) => {
+                rrset.ttl = ttl;
+                if !rrset.records.contains(&record) {
+                    rrset.records.push(record);
+                }
+            }
+            None => rrsets.push(RRset {
+                ty,
+                ttl,
+                records: vec![record],
+            }),
+        }
+        Ok(())
+    }
+
+    /// Returns an iterator over all records in the zone, as `(name, ttl, record)` triples.
+    ///
+    /// Names are yielded in canonical order.
+    pub fn records(&self) -> impl Iterator<Item = (&DomainName, u32, &Record<'static>)> {
+        self.names.iter().flat_map(|(name, rrsets)| {
+            rrsets.iter().flat_map(move |rrset| {
+                rrset
+                    .records
+                    .iter()
+                    .map(move |record| (&name.0, rrset.ttl, record))
+            })
+        })
+    }
+
+    /// Signs the zone with DNSSEC.
+    ///
+    /// This builds the `NSEC` chain linking all names in the zone, and signs every authoritative
+    /// RRset with `signer`, adding the resulting `RRSIG` records. `NSEC` and `RRSIG` records from an
+    /// earlier signing are replaced. Names below delegation points (glue) are not signed, and only
+    /// the `DS` and `NSEC` RRsets at delegation points are.
+    ///
+    /// The zone should contain the signer's `DNSKEY` record at the apex, and `signer` has to use
+    /// the apex as its [`Signer::signer_name`]. The zone must also contain an [`SOA`] record, whose
+    /// minimum TTL is used as the TTL of the `NSEC` records.
+    ///
+    /// Returns [`Error::InvalidValue`] if the zone has no [`SOA`] record, or if the signer name does
+    /// not match the apex. Errors returned by the [`Signer`] are passed through.
+    pub fn sign<S: Signer + ?Sized>(
+        &mut self,
+        signer: &S,
+        validity: Validity,
+    ) -> Result<(), Error> {
+        if signer.signer_name().to_ascii_lowercase() != self.apex {
+            return Err(Error::InvalidValue);
+        }
+        let nsec_ttl = self.negative_ttl().ok_or(Error::InvalidValue)?;
+
+        for rrsets in self.names.values_mut() {
+            rrsets.retain(|rrset| rrset.ty != Type::NSEC && rrset.ty != Type::RRSIG);
+        }
+        self.names.retain(|_, rrsets| !rrsets.is_empty());
+
+        // Build the NSEC chain over all authoritative names and delegation points.
+        let chain = self
+            .names
+            .keys()
+            .filter(|name| !self.is_occluded(&name.0))
+            .cloned()
+            .collect::<Vec<_>>();
+        for (i, name) in chain.iter().enumerate() {
+            let next = chain.get(i + 1).unwrap_or(&chain[0]);
+            let delegation = self.is_delegation(&name.0);
+            let rrsets = self.names.get_mut(name).unwrap();
+            let types = rrsets
+                .iter()
+                .map(|rrset| rrset.ty)
+                .filter(|&ty| !delegation || ty == Type::NS || ty == Type::DS)
+                .chain([Type::NSEC, Type::RRSIG]);
+            let nsec = NSEC::new(next.0.clone(), types);
+            rrsets.push(RRset {
+                ty: Type::NSEC,
+                ttl: nsec_ttl,
+                records: vec![Record::NSEC(nsec)],
+            });
+        }
+
+        for name in &chain {
+            let delegation = self.is_delegation(&name.0);
+            let mut signatures = Vec::new();
+            for rrset in &self.names[name] {
+                if delegation && rrset.ty != Type::DS && rrset.ty != Type::NSEC {
+                    continue;
+                }
+                signatures.push(Record::RRSIG(sign_rrset(&name.0, rrset, signer, validity)?));
+            }
+            // All signatures at a name are stored in a single RRset, but they are served with the
+            // TTL of the RRset they cover (see `Zone::push_rrset`).
+            self.names.get_mut(name).unwrap().push(RRset {
+                ty: Type::RRSIG,
+                ttl: nsec_ttl,
+                records: signatures,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn soa(&self) -> Option<(&RRset, &SOA<'static>)> {
+        let rrset = self.rrset(&self.apex, Type::SOA)?;
+        match rrset.records.first() {
+            Some(Record::SOA(soa)) => Some((rrset, soa)),
+            _ => None,
+        }
+    }
+
+    /// Returns the TTL to use for negative answers ([RFC 9077]).
+    ///
+    /// [RFC 9077]: https://datatracker.ietf.org/doc/html/rfc9077
+    fn negative_ttl(&self) -> Option<u32> {
+        self.soa()
+            .map(|(rrset, soa)| rrset.ttl.min(soa.minimum_ttl()))
+    }
+
+    fn rrsets(&self, name: &DomainName) -> Option<&[RRset]> {
+        self.names
+            .get(&CanonicalName(name.clone()))
+            .map(Vec::as_slice)
+    }
+
+    fn rrset(&self, name: &DomainName, ty: Type) -> Option<&RRset> {
+        self.rrsets(name)?.iter().find(|rrset| rrset.ty == ty)
+    }
+
+    /// Returns whether `name` is a delegation point, ie. owns an `NS` RRset but is not the apex.
+    fn is_delegation(&self, name: &DomainName) -> bool {
+        *name != self.apex && self.rrset(name, Type::NS).is_some()
+    }
+
+    /// Returns the delegation point at or above `name`, if any.
+    fn delegation_of(&self, name: &DomainName) -> Option<DomainName> {
+        let labels = name.labels();
+        let apex_len = self.apex.labels().len();
+        (apex_len + 1..=labels.len())
+            .map(|len| DomainName::from_iter(&labels[labels.len() - len..]))
+            .find(|ancestor| self.is_delegation(ancestor))
+    }
+
+    /// Returns whether `name` is below a delegation point, and thus not authoritative data.
+    fn is_occluded(&self, name: &DomainName) -> bool {
+        self.delegation_of(name)
+            .is_some_and(|cut| cut.labels().len() < name.labels().len())
+    }
+
+    /// Returns whether `name` exists, either because it owns records, or because names below it
+    /// do (an *empty non-terminal*).
+    fn exists(&self, name: &DomainName) -> bool {
+        let key = CanonicalName(name.clone());
+        self.names
+            .range(&key..)
+            .next()
+            .is_some_and(|(next, _)| is_subdomain(&next.0, name))
+    }
+
+    /// Returns the last name with an `NSEC` record that sorts before `name`.
+    ///
+    /// Its `NSEC` record proves that `name` does not exist.
+    fn covering_nsec(&self, name: &DomainName) -> Option<&DomainName> {
+        self.names
+            .range(..CanonicalName(name.clone()))
+            .rev()
+            .find(|(_, rrsets)| rrsets.iter().any(|rrset| rrset.ty == Type::NSEC))
+            .map(|(name, _)| &name.0)
+    }
+
+    /// Looks up the answer to a query for `qname` and `qtype`.
+    fn lookup(&self, qname: &DomainName, qtype: QType, dnssec_ok: bool) -> Answer<'_> {
+        let mut answer = Answer {
+            rcode: RCode::NO_ERROR,
+            authoritative: true,
+            class: Class::IN,
+            answers: Vec::new(),
+            authority: Vec::new(),
+            additional: Vec::new(),
+        };
+        let qname = qname.to_ascii_lowercase();
+
+        if let Some(cut) = self.delegation_of(&qname) {
+            // `DS` records live on the parent side of the delegation, and are answered normally.
+            if cut != qname || qtype != QType::DS {
+                answer.authoritative = false;
+                self.push_rrset(&mut answer.authority, &cut, Type::NS, dnssec_ok);
+                if dnssec_ok && !self.push_rrset(&mut answer.authority, &cut, Type::DS, true) {
+                    self.push_rrset(&mut answer.authority, &cut, Type::NSEC, true);
+                }
+                return answer;
+            }
+        }
+
+        let Some(rrsets) = self.rrsets(&qname) else {
+            if !self.exists(&qname) {
+                answer.rcode = RCode::NX_DOMAIN;
+            }
+            self.push_denial(&mut answer, &qname, dnssec_ok);
+            return answer;
+        };
+
+        let cname = rrsets.iter().find(|rrset| rrset.ty == Type::CNAME);
+        let matching = match cname {
+            Some(_) if !qtype.matches(Type::CNAME) => vec![Type::CNAME],
+            _ => rrsets
+                .iter()
+                .map(|rrset| rrset.ty)
+                .filter(|&ty| qtype.matches(ty))
+                .filter(|&ty| dnssec_ok || QType::from(ty) == qtype || !is_dnssec_type(ty))
+                .collect(),
+        };
+        if matching.is_empty() {
+            self.push_denial(&mut answer, &qname, dnssec_ok);
+        }
+        for ty in matching {
+            self.push_rrset(
+                &mut answer.answers,
+                &qname,
+                ty,
+                dnssec_ok && ty != Type::RRSIG,
+            );
+        }
+        answer
+    }
+
+    /// Returns the contents of the zone for a zone transfer: the `SOA` record, all other records,
+    /// and the `SOA` record again.
+    fn transfer(&self) -> Answer<'_> {
+        let mut answer = Answer {
+            rcode: RCode::NO_ERROR,
+            authoritative: true,
+            class: Class::IN,
+            answers: Vec::new(),
+            authority: Vec::new(),
+            additional: Vec::new(),
+        };
+        let Some((soa, _)) = self.soa() else {
+            answer.rcode = RCode::SERV_FAIL;
+            return answer;
+        };
+        let soa = (&self.apex, soa.ttl, &soa.records[0]);
+        answer.answers.push(soa);
+        answer.answers.extend(
+            self.records()
+                .filter(|(_, _, record)| record.record_type() != Type::SOA),
+        );
+        answer.answers.push(soa);
+        answer
+    }
+
+    /// Adds the `SOA` record and, if requested, the `NSEC` records proving the non-existence of
+    /// `qname` (or the queried type) to the *Authority* section.
+    fn push_denial<'a>(&'a self, answer: &mut Answer<'a>, qname: &DomainName, dnssec_ok: bool) {
+        self.push_rrset(&mut answer.authority, &self.apex, Type::SOA, dnssec_ok);
+        if let Some(ttl) = self.negative_ttl() {
+            // Negative answers are cached for the SOA's minimum TTL.
+            for (_, rr_ttl, _) in &mut answer.authority {
+                *rr_ttl = (*rr_ttl).min(ttl);
+            }
+        }
+        if !dnssec_ok {
+            return;
+        }
+
+        if self.rrset(qname, Type::NSEC).is_some() {
+            // The name exists, but doesn't have the queried type.
+            self.push_rrset(&mut answer.authority, qname, Type::NSEC, true);
+            return;
+        }
+
+        let covering = self.covering_nsec(qname);
+        if answer.rcode == RCode::NX_DOMAIN {
+            // Also prove that there is no wildcard at the closest encloser.
+            let mut encloser = qname.clone();
+            while !self.exists(&encloser) && encloser != self.apex {
+                encloser = DomainName::from_iter(&encloser.labels()[1..]);
+            }
+            let mut wildcard = DomainName::from_iter([label!("*")]);
+            wildcard.extend(encloser.labels());
+            let wildcard_covering = self.covering_nsec(&wildcard);
+            if wildcard_covering != covering {
+                if let Some(name) = wildcard_covering {
+                    self.push_rrset(&mut answer.authority, name, Type::NSEC, true);
+                }
+            }
+        }
+        if let Some(name) = covering {
+            self.push_rrset(&mut answer.authority, name, Type::NSEC, true);
+        }
+    }
+
+    /// Adds the addresses of the hosts named by the `NS`, `MX` and `SRV` records in the *Answer* and
+    /// *Authority* sections to the *Additional* section ([RFC 1034, section 3.7]), so that
+    /// resolvers don't have to look them up separately.
+    ///
+    /// For DNS-SD `PTR` answers, the `SRV` and `TXT` records of the service instances, and the
+    /// addresses of their targets, are added as well ([RFC 6763, section 12.1]). The records are
+    /// added in order of usefulness (`SRV` before `TXT` before addresses), since the ones at the
+    /// end are left out if the response gets too large.
+    ///
+    /// Addresses below a delegation point aren't authoritative data, and are only included as glue
+    /// for `NS` records. Glue is never signed, other records are signed if `dnssec_ok` is set.
+    ///
+    /// [RFC 1034, section 3.7]: https://datatracker.ietf.org/doc/html/rfc1034#section-3.7
+    /// [RFC 6763, section 12.1]: https://datatracker.ietf.org/doc/html/rfc6763#section-12.1
+    fn push_additional<'a>(&'a self, answer: &mut Answer<'a>, dnssec_ok: bool) {
+        let mut instances: Vec<DomainName> = Vec::new();
+        for (_, _, record) in &answer.answers {
+            if let Record::PTR(ptr) = record {
+                let instance = ptr.ptrdname().to_ascii_lowercase();
+                if !instances.contains(&instance) && !self.is_occluded(&instance) {
+                    instances.push(instance);
+                }
+            }
+        }
+        for ty in [Type::SRV, Type::TXT] {
+            for instance in &instances {
+                self.push_rrset(&mut answer.additional, instance, ty, dnssec_ok);
+            }
+        }
+
+        let mut targets: Vec<(DomainName, bool)> = Vec::new();
+        let sections = answer.answers.iter().chain(&answer.authority);
+        for (_, _, record) in sections.chain(&answer.additional) {
+            let (target, ns) = match record {
+                Record::NS(ns) => (ns.nsdname(), true),
+                Record::MX(mx) => (mx.exchange(), false),
+                Record::SRV(srv) => (srv.target(), false),
+                _ => continue,
+            };
+            let target = target.to_ascii_lowercase();
+            match targets.iter_mut().find(|(name, _)| *name == target) {
+                Some((_, glue)) => *glue |= ns,
+                None => targets.push((target, ns)),
+            }
+        }
+        for (target, glue) in targets {
+            let occluded = self.is_occluded(&target);
+            if occluded && !glue {
+                continue;
+            }
+            for ty in [Type::A, Type::AAAA] {
+                self.push_rrset(&mut answer.additional, &target, ty, dnssec_ok && !occluded);
+            }
+        }
+    }
+
+    /// Adds the RRset of type `ty` at `name` to `section`, along with its signatures if `signed` is
+    /// set.
+    ///
+    /// Returns whether the RRset exists.
+    fn push_rrset<'a>(
+        &'a self,
+        section: &mut Vec<(&'a DomainName, u32, &'a Record<'static>)>,
+        name: &DomainName,
+        ty: Type,
+        signed: bool,
+    ) -> bool {
+        let Some((owner, rrsets)) = self.names.get_key_value(&CanonicalName(name.clone())) else {
+            return false;
+        };
+        let Some(rrset) = rrsets.iter().find(|rrset| rrset.ty == ty) else {
+            return false;
+        };
+        section.extend(
+            rrset
+                .records
+                .iter()
+                .map(|record| (&owner.0, rrset.ttl, record)),
+        );
+        if signed {
+            let signatures = rrsets
+                .iter()
+                .filter(|rrset| rrset.ty == Type::RRSIG)
+                .flat_map(|rrset| &rrset.records)
+                .filter(|record| matches!(record, Record::RRSIG(sig) if sig.type_covered() == ty));
+            section.extend(signatures.map(|record| (&owner.0, rrset.ttl, record)));
+        }
+        true
+    }
+}
+
+/// Returns the type of the RRset `record` belongs to, which is the covered type for signatures.
+fn rrset_type(record: &Record<'_>) -> Type {
+    match record {
+        Record::RRSIG(sig) => sig.type_covered(),
+        _ => record.record_type(),
+    }
+}
+
+/// Signs `rrset`, owned by `name` ([RFC 4034, section 3.1.8.1]).
+///
+/// [RFC 4034, section 3.1.8.1]: https://datatracker.ietf.org/doc/html/rfc4034#section-3.1.8.1
+fn sign_rrset<S: Signer + ?Sized>(
+    name: &DomainName,
+    rrset: &RRset,
+    signer: &S,
+    validity: Validity,
+) -> Result<RRSIG<'static>, Error> {
+    let labels = name.labels();
+    let wildcard = labels.first().is_some_and(|l| l.as_bytes() == b"*");
+    let rrsig = |signature| {
+        RRSIG::new(
+            rrset.ty,
+            signer.algorithm(),
+            (labels.len() - usize::from(wildcard)) as u8,
+            rrset.ttl,
+            validity,
+            signer.key_tag(),
+            signer.signer_name().to_ascii_lowercase(),
+            signature,
+        )
+    };
+
+    let mut rdatas = rrset
+        .records
+        .iter()
+        .map(Record::encode_to_vec)
+        .collect::<Vec<_>>();
+    rdatas.sort();
+
+    let mut owner = Vec::new();
+    for label in labels {
+        owner.push(label.as_bytes().len() as u8);
+        owner.extend_from_slice(label.as_bytes());
+    }
+    owner.push(0);
+
+    // With an empty signature, the RDATA is exactly the part covered by the signature.
+    let mut data = Record::RRSIG(rrsig(Vec::new())).encode_to_vec();
+    for rdata in rdatas {
+        data.extend_from_slice(&owner);
+        data.extend_from_slice(&rrset.ty.0.to_be_bytes());
+        data.extend_from_slice(&Class::IN.0.to_be_bytes());
+        data.extend_from_slice(&rrset.ttl.to_be_bytes());
+        data.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        data.extend_from_slice(&rdata);
+    }
+
+    let signature = signer.sign(&data)?;
+    log::trace!("signed {} {}: {}", name, rrset.ty, Hex(&signature));
+    Ok(rrsig(signature))
+}
+
+/// The records to respond to a query with.
+struct Answer<'a> {
+    rcode: RCode,
+    authoritative: bool,
+    /// The class of the records in the answer section.
+    class: Class,
+    answers: Vec<(&'a DomainName, u32, &'a Record<'static>)>,
+    authority: Vec<(&'a DomainName, u32, &'a Record<'static>)>,
+    additional: Vec<(&'a DomainName, u32, &'a Record<'static>)>,
+}
+
+/// TTL of the synthesized `HINFO` record, as suggested by RFC 8482.
+pub(crate) const RFC8482_TTL: u32 = 3789;
+
+/// Replaces the answers to a query for type `ANY` with a minimal response ([RFC 8482]).
+///
+/// Without DNSSEC, the answer is a single synthesized `HINFO` record. Since that record can't be
+/// signed, DNSSEC-aware clients get the first of the existing RRsets (and its signatures) instead.
+///
+/// [RFC 8482]: https://datatracker.ietf.org/doc/html/rfc8482
+fn minimize_any<'a>(answer: &mut Answer<'a>, hinfo: &'a Record<'static>, dnssec_ok: bool) {
+    let Some(&(name, _, first)) = answer.answers.first() else {
+        return;
+    };
+    if dnssec_ok {
+        // Each RRset is immediately followed by its signatures.
+        let ty = first.record_type();
+        let len = answer
+            .answers
+            .iter()
+            .take_while(|(_, _, record)| match record {
+                Record::RRSIG(rrsig) => rrsig.type_covered() == ty,
+                record => record.record_type() == ty,
+            })
+            .count();
+        answer.answers.truncate(len);
+    } else {
+        answer.answers = vec![(name, RFC8482_TTL, hinfo)];
+    }
+}
+
+fn is_dnssec_type(ty: Type) -> bool {
+    matches!(ty, Type::RRSIG | Type::NSEC)
+}
+
+/// Lowercases the domain names embedded in `record`, as required for signing
+/// ([RFC 4034, section 6.2]).
+///
+/// [RFC 4034, section 6.2]: https://datatracker.ietf.org/doc/html/rfc4034#section-6.2
+fn lowercase_record(record: Record<'static>) -> Record<'static> {
+    match record {
+        Record::CNAME(r) => Record::CNAME(CNAME::new(r.cname().to_ascii_lowercase())),
+        Record::MX(r) => Record::MX(MX::new(r.preference(), r.exchange().to_ascii_lowercase())),
+        Record::NS(r) => Record::NS(NS::new(r.nsdname().to_ascii_lowercase())),
+        Record::PTR(r) => Record::PTR(PTR::new(r.ptrdname().to_ascii_lowercase())),
+        Record::SRV(r) => Record::SRV(SRV::new(
+            r.priority(),
+            r.weight(),
+            r.port(),
+            r.target().to_ascii_lowercase(),
+        )),
+        Record::SOA(r) => Record::SOA(SOA::new(
+            r.mname().to_ascii_lowercase(),
+            r.rname().to_ascii_lowercase(),
+            r.serial(),
+            r.refresh(),
+            r.retry(),
+            r.expire(),
+            r.minimum_ttl(),
+        )),
+        record => record,
+    }
+}
+
+/// A zone served to a subset of clients (see [`Server::add_view`]).
+struct View {
+    acl: Acl,
+    zone: Zone,
+}
+
+/// Returns the zone that answers queries for `qname` from `source`.
+///
+/// This is the zone of the first view that allows `source`, if `qname` exists in it, and the main
+/// `zone` otherwise.
+fn select_zone<'a>(
+    zone: &'a Zone,
+    views: &'a [View],
+    source: IpAddr,
+    qname: &DomainName,
+) -> &'a Zone {
+    views
+        .iter()
+        .find(|view| view.acl.is_allowed(source))
+        .map(|view| &view.zone)
+        .filter(|view_zone| view_zone.exists(&qname.to_ascii_lowercase()))
+        .unwrap_or(zone)
+}
+
+/// Returns whether `name` is equal to or below `parent`.
+fn is_subdomain(name: &DomainName, parent: &DomainName) -> bool {
+    name.labels().ends_with(parent.labels())
+}
+
+/// A query passed to a [`Policy`].
+pub struct Request<'a> {
+    qname: &'a DomainName,
+    qtype: QType,
+    source: SocketAddr,
+    zone: &'a Zone,
+}
+
+impl<'a> Request<'a> {
+    /// Returns the queried name, as it appears in the query.
+    #[inline]
+    pub fn qname(&self) -> &'a DomainName {
+        self.qname
+    }
+
+    /// Returns the queried type.
+    #[inline]
+    pub fn qtype(&self) -> QType {
+        self.qtype
+    }
+
+    /// Returns the address the query was sent from.
+    #[inline]
+    pub fn source(&self) -> SocketAddr {
+        self.source
+    }
+
+    /// Returns the TTL and records of the queried name and type stored in the zone, if any.
+    ///
+    /// Policies can use this to reorder or filter the static records instead of replacing them.
+    pub fn zone_records(&self) -> Option<(u32, &'a [Record<'static>])> {
+        let ty = Type::try_from(self.qtype).ok()?;
+        let rrset = self.zone.rrset(&self.qname.to_ascii_lowercase(), ty)?;
+        Some((rrset.ttl, &rrset.records))
+    }
+}
+
+/// The outcome of consulting a [`Policy`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum PolicyAnswer {
+    /// The policy doesn't handle the query; the next policy (or the zone) answers it instead.
+    Continue,
+    /// Answers the query with the given records, all with the same TTL.
+    ///
+    /// The records are owned by the queried name. An empty list results in a "no data" response.
+    Records(u32, Vec<Record<'static>>),
+    /// Responds that the queried name does not exist.
+    NxDomain,
+    /// Refuses to answer the query.
+    Refuse,
+}
+
+/// A hook that synthesizes answers to queries at query time.
+///
+/// Policies are registered with [`Server::add_policy`] (for every name in the zone) or
+/// [`Server::add_name_policy`] (for a single name), and are implemented by closures taking a
+/// [`Request`] and returning a [`PolicyAnswer`].
+///
+/// Synthesized answers are never signed, so zones using policies should not be signed with
+/// [`Zone::sign`] if DNSSEC validation is expected to succeed for the affected names.
+///
+/// # Example
+///
+/// Rotating the `A` records of a name for every query (round-robin):
+///
+/// ```
+/// # use uwuhi::{server::{PolicyAnswer, Request, Server, Zone}, packet::QType};
+/// # let mut server = Server::new(Zone::new("example.com".parse().unwrap()));
+/// let mut counter = 0;
+/// server.add_name_policy("www.example.com".parse().unwrap(), move |req: &Request<'_>| {
+///     match req.zone_records() {
+///         Some((ttl, records)) if req.qtype() == QType::A => {
+///             counter += 1;
+///             let mut records = records.to_vec();
+///             let len = records.len();
+///             records.rotate_left(counter % len);
+///             PolicyAnswer::Records(ttl, records)
+///         }
+///         _ => PolicyAnswer::Continue,
+///     }
+/// });
+/// ```
+pub trait Policy: Send {
+    /// Decides how to answer `request`.
+    fn answer(&mut self, request: &Request<'_>) -> PolicyAnswer;
+}
+
+impl<F: FnMut(&Request<'_>) -> PolicyAnswer + Send> Policy for F {
+    fn answer(&mut self, request: &Request<'_>) -> PolicyAnswer {
+        self(request)
+    }
+}
+
+/// Strings that identify a server in answers to `CH TXT` queries.
+///
+/// Network scanners and operators commonly query the `TXT` records of `version.bind` and
+/// `hostname.bind` (or their standardized equivalents `version.server` and `id.server`,
+/// [RFC 4892]) in the CHAOS class to find out which software a server is running, and which
+/// instance of a server set answered. Such queries are refused unless the corresponding string has
+/// been set.
+///
+/// [RFC 4892]: https://datatracker.ietf.org/doc/html/rfc4892
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Identity {
+    version: Option<String>,
+    hostname: Option<String>,
+}
+
+impl Identity {
+    /// Creates an identity that doesn't reveal anything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the string returned for `version.bind` and `version.server`.
+    pub fn set_version(&mut self, version: impl Into<String>) {
+        self.version = Some(version.into());
+    }
+
+    /// Returns the string returned for `version.bind` and `version.server`.
+    #[inline]
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// Sets the string returned for `hostname.bind` and `id.server`.
+    pub fn set_hostname(&mut self, hostname: impl Into<String>) {
+        self.hostname = Some(hostname.into());
+    }
+
+    /// Returns the string returned for `hostname.bind` and `id.server`.
+    #[inline]
+    pub fn hostname(&self) -> Option<&str> {
+        self.hostname.as_deref()
+    }
+
+    /// Returns the `TXT` record to answer `question` with, if it is an identification query.
+    ///
+    /// The inner [`Option`] is [`None`] if the requested string hasn't been set, in which case the
+    /// query should be refused.
+    pub(crate) fn answer(&self, question: &decoder::Question) -> Option<Option<Record<'static>>> {
+        if question.qclass() != QClass::CH || !question.qtype().matches(Type::TXT) {
+            return None;
+        }
+        let name = question.qname().to_ascii_lowercase();
+        let text = if name == domain!("version.bind") || name == domain!("version.server") {
+            &self.version
+        } else if name == domain!("hostname.bind") || name == domain!("id.server") {
+            &self.hostname
+        } else {
+            return None;
+        };
+        Some(
+            text.as_ref()
+                .map(|text| Record::TXT(TXT::new([text.as_bytes().to_vec()]))),
+        )
+    }
+}
+
+/// I/O-less authoritative server logic.
+///
+/// You probably want to use [`SyncServer`] instead.
+pub struct Server {
+    zone: Zone,
+    views: Vec<View>,
+    policies: Vec<Box<dyn Policy>>,
+    name_policies: HashMap<DomainName, Vec<Box<dyn Policy>>>,
+    acl: Acl,
+    transfer_acl: Option<Acl>,
+    notify_targets: Vec<SocketAddr>,
+    primary: Option<SocketAddr>,
+    notified: bool,
+    tcp_idle_timeout: Duration,
+    identity: Identity,
+    minimal_any: bool,
+    rate_limiter: Option<RateLimiter>,
+    clock: Box<dyn Clock>,
+    response_buf: Vec<u8>,
+}
+
+impl Server {
+    /// Creates a server that answers queries for `zone`.
+    pub fn new(zone: Zone) -> Self {
+        Self {
+            zone,
+            views: Vec::new(),
+            policies: Vec::new(),
+            name_policies: HashMap::new(),
+            acl: Acl::new(),
+            transfer_acl: None,
+            notify_targets: Vec::new(),
+            primary: None,
+            notified: false,
+            tcp_idle_timeout: DEFAULT_TCP_IDLE_TIMEOUT,
+            identity: Identity::new(),
+            minimal_any: false,
+            rate_limiter: None,
+            clock: Box::new(SystemClock),
+            response_buf: vec![0; usize::from(EDNS_PAYLOAD_SIZE)],
+        }
+    }
+
+    /// Returns the zone served by this server.
+    #[inline]
+    pub fn zone(&self) -> &Zone {
+        &self.zone
+    }
+
+    /// Adds a view, which serves the contents of `zone` to clients whose source address is allowed
+    /// by `acl`.
+    ///
+    /// Views are layered on top of the zone passed to [`Server::new`]: names that exist in the
+    /// view's zone are answered from it, while all other names are answered from the main zone.
+    /// This way, a view only needs to contain the names whose answers differ, for example the
+    /// internal addresses of hosts that are also reachable from the outside.
+    ///
+    /// If several views allow a client, the one that was added first is used. Clients not allowed
+    /// by any view only see the main zone. [`Policy`] hooks are consulted for all clients, and see
+    /// the records of the client's view via [`Request::zone_records`].
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `zone` has a different apex than the main zone.
+    pub fn add_view(&mut self, acl: Acl, zone: Zone) {
+        assert!(
+            zone.apex == self.zone.apex,
+            "view apex {} does not match zone apex {}",
+            zone.apex,
+            self.zone.apex,
+        );
+        self.views.push(View { acl, zone });
+    }
+
+    /// Adds a [`Policy`] that is consulted for queries for any name in the zone.
+    ///
+    /// Policies are consulted in the order they were added, after the policies registered for the
+    /// queried name via [`Server::add_name_policy`]. The first one that doesn't return
+    /// [`PolicyAnswer::Continue`] determines the answer. If all of them do, the query is answered
+    /// from the zone.
+    pub fn add_policy(&mut self, policy: impl Policy + 'static) {
+        self.policies.push(Box::new(policy));
+    }
+
+    /// Adds a [`Policy`] that is consulted for queries for `name`.
+    ///
+    /// `name` does not need to exist in the zone, so this can be used to synthesize entirely
+    /// dynamic names.
+    pub fn add_name_policy(&mut self, name: DomainName, policy: impl Policy + 'static) {
+        self.name_policies
+            .entry(name.to_ascii_lowercase())
+            .or_default()
+            .push(Box::new(policy));
+    }
+
+    /// Restricts which hosts get answers.
+    ///
+    /// Queries from source addresses that `acl` doesn't allow are refused. By default, every host
+    /// is answered.
+    pub fn set_acl(&mut self, acl: Acl) {
+        self.acl = acl;
+    }
+
+    /// Allows zone transfers (`AXFR`) to hosts allowed by `acl`.
+    ///
+    /// Zone transfers are only served over TCP, and are refused by default. The whole zone has to
+    /// fit into a single message.
+    pub fn allow_transfers(&mut self, acl: Acl) {
+        self.transfer_acl = Some(acl);
+    }
+
+    /// Adds a secondary server that is sent a `NOTIFY` message when the zone changes.
+    ///
+    /// See [`Server::build_notify`].
+    pub fn add_notify_target(&mut self, addr: SocketAddr) {
+        self.notify_targets.push(addr);
+    }
+
+    /// Returns the secondary servers added via [`Server::add_notify_target`].
+    #[inline]
+    pub fn notify_targets(&self) -> &[SocketAddr] {
+        &self.notify_targets
+    }
+
+    /// Builds a `NOTIFY` message with ID `id`, announcing the current version of the zone to
+    /// secondary servers.
+    ///
+    /// The message includes the zone's `SOA` record as a hint. It should be sent to every
+    /// address in [`Server::notify_targets`], and resent until the secondary responds.
+    pub fn build_notify(&mut self, id: u16) -> Result<&[u8], Error> {
+        let mut header = Header::default();
+        header.set_id(id);
+        header.set_opcode(Opcode::NOTIFY);
+        header.set_authority(true);
+        let mut enc = MessageEncoder::new(&mut self.response_buf[..DNS_BUFFER_SIZE]);
+        enc.set_header(header);
+        enc.question(Question::new(&self.zone.apex).ty(QType::SOA));
+        let mut enc = enc.answers();
+        if let Some((rrset, _)) = self.zone.soa() {
+            enc.add_answer(ResourceRecord::new(&self.zone.apex, &rrset.records[0]).ttl(rrset.ttl));
+        }
+        let len = enc.finish()?;
+        Ok(&self.response_buf[..len])
+    }
+
+    /// Makes this server a secondary server, which accepts `NOTIFY` messages from `primary`.
+    ///
+    /// `NOTIFY` messages from any other address are refused. Whether a `NOTIFY` was received can
+    /// be checked via [`Server::take_notify`], after which the zone should be refreshed from the
+    /// primary (see the [`transfer`] module).
+    pub fn set_primary(&mut self, primary: SocketAddr) {
+        self.primary = Some(primary);
+    }
+
+    /// Returns the primary server set via [`Server::set_primary`].
+    #[inline]
+    pub fn primary(&self) -> Option<SocketAddr> {
+        self.primary
+    }
+
+    /// Returns whether a `NOTIFY` was received from the primary server since the last call.
+    pub fn take_notify(&mut self) -> bool {
+        std::mem::take(&mut self.notified)
+    }
+
+    /// Replaces the served zone with `zone`, for example after its configuration has changed.
+    ///
+    /// If both zones have an `SOA` record, the new zone is only accepted if its serial number is
+    /// newer than the current one ([RFC 1982] serial number arithmetic). A zone with the same
+    /// serial number is ignored, and `Ok(false)` is returned. An older serial number most likely
+    /// means that the zone file was edited without incrementing the serial, which would prevent
+    /// secondaries from picking up the change, so it is rejected with [`Error::InvalidValue`].
+    ///
+    /// Returns `Ok(true)` if the zone was replaced, which means that the secondary servers in
+    /// [`Server::notify_targets`] should be sent a `NOTIFY` (see [`Server::build_notify`]).
+    /// Returns [`Error::InvalidValue`] if `zone` has a different apex than the current zone.
+    ///
+    /// Only the main zone is replaced; views added via [`Server::add_view`] are kept.
+    ///
+    /// [RFC 1982]: https://datatracker.ietf.org/doc/html/rfc1982
+    pub fn reload(&mut self, zone: Zone) -> Result<bool, Error> {
+        if zone.apex != self.zone.apex {
+            log::warn!(
+                "refusing to reload zone {} with zone {}",
+                self.zone.apex,
+                zone.apex
+            );
+            return Err(Error::InvalidValue);
+        }
+        if let (Some(old), Some(new)) = (self.zone.serial(), zone.serial()) {
+            if new == old {
+                log::debug!("zone {} is unchanged (serial {})", zone.apex, new);
+                return Ok(false);
+            }
+            if !transfer::serial_newer(new, old) {
+                log::warn!(
+                    "refusing to reload zone {}: serial {} is older than {}",
+                    zone.apex,
+                    new,
+                    old
+                );
+                return Err(Error::InvalidValue);
+            }
+        }
+        log::info!(
+            "reloaded zone {} (serial {:?} -> {:?})",
+            zone.apex,
+            self.zone.serial(),
+            zone.serial()
+        );
+        self.zone = zone;
+        Ok(true)
+    }
+
+    /// Replaces the served zone.
+    pub(crate) fn set_zone(&mut self, zone: Zone) {
+        self.zone = zone;
+    }
+
+    /// Sets the time after which idle TCP connections should be closed.
+    ///
+    /// This timeout is announced to clients that include an `edns-tcp-keepalive` option in their
+    /// queries over TCP. Defaults to 10 seconds.
+    pub fn set_tcp_idle_timeout(&mut self, timeout: Duration) {
+        self.tcp_idle_timeout = timeout;
+    }
+
+    /// Returns the time after which idle TCP connections should be closed.
+    #[inline]
+    pub fn tcp_idle_timeout(&self) -> Duration {
+        self.tcp_idle_timeout
+    }
+
+    /// Sets the strings to answer `CH TXT` identification queries like `version.bind` with.
+    ///
+    /// By default, such queries are refused.
+    pub fn set_identity(&mut self, identity: Identity) {
+        self.identity = identity;
+    }
+
+    /// Sets whether queries for type `ANY` are answered minimally, as recommended by [RFC 8482].
+    ///
+    /// Instead of every record owned by the queried name, the response then only contains a
+    /// single `HINFO` record with the CPU set to `RFC8482`, or a single RRset if the client
+    /// requested DNSSEC records. This keeps responses small, so that the server is less useful for
+    /// amplification attacks. Disabled by default.
+    ///
+    /// [RFC 8482]: https://datatracker.ietf.org/doc/html/rfc8482
+    pub fn set_minimal_any(&mut self, minimal_any: bool) {
+        self.minimal_any = minimal_any;
+    }
+
+    /// Enables response rate limiting for queries received over UDP.
+    ///
+    /// Negative responses (like `NXDOMAIN`) are accounted to the zone apex instead of the queried
+    /// name. See the [`rrl`] module for details. By default, responses are not rate limited.
+    pub fn set_rate_limiter(&mut self, rate_limiter: RateLimiter) {
+        self.rate_limiter = Some(rate_limiter);
+    }
+
+    /// Sets the [`Clock`] used for response rate limiting.
+    ///
+    /// By default, the [`SystemClock`] is used.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Box::new(clock);
+    }
+
+    /// Consults the registered policies about a query.
+    fn apply_policies(
+        &mut self,
+        qname: &DomainName,
+        qtype: QType,
+        source: SocketAddr,
+    ) -> PolicyAnswer {
+        let request = Request {
+            qname,
+            qtype,
+            source,
+            zone: select_zone(&self.zone, &self.views, source.ip(), qname),
+        };
+        let name_policies = self
+            .name_policies
+            .get_mut(&qname.to_ascii_lowercase())
+            .into_iter()
+            .flatten();
+        for policy in name_policies.chain(&mut self.policies) {
+            match policy.answer(&request) {
+                PolicyAnswer::Continue => {}
+                answer => return answer,
+            }
+        }
+        PolicyAnswer::Continue
+    }
+
+    /// Handles an incoming DNS query from `source`, and returns the response to send back (if
+    /// any).
+    ///
+    /// Queries for names outside of the zone, and queries from sources denied by the ACL (see
+    /// [`Server::set_acl`]), are refused. If the query has an `OPT` record, the response includes an
+    /// Extended DNS Error explaining the refusal. Responses are limited to 512 bytes, unless the
+    /// query advertises a larger UDP payload size in an `OPT` record.
+    ///
+    /// Queries that can't be parsed get a `FORMERR` response, and queries with an opcode other than
+    /// `QUERY` or `NOTIFY` get `NOTIMP`. Both copy the ID and question of the query. Packets that
+    /// aren't queries, or whose header is cut off, are dropped without a response.
+    pub fn handle_packet(
+        &mut self,
+        packet: &[u8],
+        source: SocketAddr,
+    ) -> Result<Option<&[u8]>, Error> {
+        self.handle(packet, source, false)
+    }
+
+    /// Handles a DNS query received over a TCP connection from `source`, and returns the response
+    /// to send back (if any).
+    ///
+    /// Unlike [`Server::handle_packet`], responses are not limited by the UDP payload size. If the
+    /// query contains an `edns-tcp-keepalive` option, the response announces the idle timeout set
+    /// via [`Server::set_tcp_idle_timeout`].
+    pub fn handle_tcp_packet(
+        &mut self,
+        packet: &[u8],
+        source: SocketAddr,
+    ) -> Result<Option<&[u8]>, Error> {
+        self.handle(packet, source, true)
+    }
+
+    fn handle(
+        &mut self,
+        packet: &[u8],
+        source: SocketAddr,
+        tcp: bool,
+    ) -> Result<Option<&[u8]>, Error> {
+        let dec = MessageDecoder::new(packet)?;
+        let header = *dec.header();
+        if header.is_query() && header.opcode() == Opcode::NOTIFY {
+            return self.handle_notify(dec, source);
+        }
+        if !header.is_query() {
+            return Ok(None);
+        }
+        if header.opcode() != Opcode::QUERY {
+            log::debug!("unsupported opcode {} from {}", header.opcode(), source);
+            return self.error_response(packet, RCode::NOT_IMP);
+        }
+        let ParsedQuery {
+            question,
+            edns,
+            keepalive,
+        } = match ParsedQuery::decode(dec, tcp) {
+            Ok(query) => query,
+            Err(e) => {
+                log::debug!("malformed query from {}: {}", source, e);
+                return self.error_response(packet, RCode::FORM_ERR);
+            }
+        };
+        log::debug!("Q: {}", question);
+        // Clients must not send a timeout, that's up to the server.
+        let malformed = match keepalive {
+            Some(Ok(keepalive)) => keepalive.timeout().is_some(),
+            Some(Err(_)) => true,
+            None => false,
+        };
+        let dnssec_ok = edns.is_some_and(|(_, dnssec_ok)| dnssec_ok);
+
+        let identity = self.identity.answer(&question).flatten();
+        let in_zone = question.qclass().matches(Class::IN)
+            && is_subdomain(&question.qname().to_ascii_lowercase(), &self.zone.apex);
+        let allowed = self.acl.is_allowed(source.ip());
+        if !allowed {
+            log::debug!("refusing query from {} (denied by ACL)", source);
+        }
+        let transfer = question.qtype() == QType::AXFR;
+        let policy = match (in_zone || identity.is_some()) && allowed && !malformed {
+            true if identity.is_some() => PolicyAnswer::Continue,
+            true if transfer => PolicyAnswer::Continue,
+            true => self.apply_policies(question.qname(), question.qtype(), source),
+            false => PolicyAnswer::Refuse,
+        };
+        // Tell EDNS clients why their query was refused (RFC 8914).
+        let extended_error = if !allowed {
+            Some(ExtendedErrorCode::PROHIBITED)
+        } else if !in_zone && identity.is_none() {
+            Some(ExtendedErrorCode::NOT_AUTHORITATIVE)
+        } else {
+            None
+        };
+        let hinfo = Record::HINFO(HINFO::rfc8482());
+        let mut answer = Answer {
+            rcode: RCode::NO_ERROR,
+            authoritative: true,
+            class: Class::IN,
+            answers: Vec::new(),
+            authority: Vec::new(),
+            additional: Vec::new(),
+        };
+        let zone = select_zone(&self.zone, &self.views, source.ip(), question.qname());
+        match &policy {
+            PolicyAnswer::Continue if identity.is_some() => {
+                answer.class = Class::CH;
+                answer
+                    .answers
+                    .extend(identity.iter().map(|record| (question.qname(), 0, record)));
+            }
+            PolicyAnswer::Continue if transfer => {
+                let permitted = self
+                    .transfer_acl
+                    .as_ref()
+                    .is_some_and(|acl| acl.is_allowed(source.ip()));
+                if tcp && permitted {
+                    answer = self.zone.transfer();
+                } else {
+                    log::debug!("refusing zone transfer to {}", source);
+                    answer.rcode = RCode::REFUSED;
+                    answer.authoritative = false;
+                }
+            }
+            PolicyAnswer::Continue => {
+                answer = zone.lookup(question.qname(), question.qtype(), dnssec_ok);
+                if self.minimal_any && question.qtype() == QType::ALL {
+                    minimize_any(&mut answer, &hinfo, dnssec_ok);
+                }
+                zone.push_additional(&mut answer, dnssec_ok);
+            }
+            PolicyAnswer::Records(ttl, records) => {
+                answer.answers.extend(
+                    records
+                        .iter()
+                        .map(|record| (question.qname(), *ttl, record)),
+                );
+                if records.is_empty() {
+                    zone.push_denial(&mut answer, question.qname(), false);
+                }
+                zone.push_additional(&mut answer, false);
+            }
+            PolicyAnswer::NxDomain => {
+                answer.rcode = RCode::NX_DOMAIN;
+                zone.push_denial(&mut answer, question.qname(), false);
+            }
+            PolicyAnswer::Refuse => {
+                answer.rcode = RCode::REFUSED;
+                answer.authoritative = false;
+            }
+        }
+        if malformed {
+            log::debug!("malformed edns-tcp-keepalive option from {}", source);
+            answer.rcode = RCode::FORM_ERR;
+        }
+
+        // TCP clients can't spoof their address, so only UDP responses are rate limited.
+        if let (Some(rrl), false) = (&mut self.rate_limiter, tcp) {
+            // Negative responses share a limit, so that random names can't be used to bypass it.
+            let name = if answer.rcode == RCode::NO_ERROR && !answer.answers.is_empty() {
+                question.qname()
+            } else {
+                &self.zone.apex
+            };
+            match rrl.check(source.ip(), name, question.qtype(), self.clock.now()) {
+                RateLimitAction::Respond => {}
+                RateLimitAction::Slip => {
+                    log::trace!(
+                        "rate limit exceeded by {}, sending truncated response",
+                        source
+                    );
+                    let mut enc =
+                        MessageEncoder::response_to(&mut self.response_buf, &header, [&question]);
+                    enc.modify_header(|h| h.set_rcode(answer.rcode));
+                    let len = enc.finish()?;
+                    // The encoder only sets the truncation flag if it ran out of space.
+                    let header: &mut Header =
+                        bytemuck::from_bytes_mut(&mut self.response_buf[..size_of::<Header>()]);
+                    header.set_truncated(true);
+                    return Ok(Some(&self.response_buf[..len]));
+                }
+                RateLimitAction::Drop => {
+                    log::trace!("rate limit exceeded by {}, dropping response", source);
+                    return Ok(None);
+                }
+            }
+        }
+
+        let limit = match edns {
+            _ if tcp => usize::from(u16::MAX),
+            Some((payload_size, _)) => usize::from(payload_size.clamp(512, EDNS_PAYLOAD_SIZE)),
+            None => DNS_BUFFER_SIZE,
+        };
+        if self.response_buf.len() < limit {
+            self.response_buf.resize(limit, 0);
+        }
+        let mut enc =
+            MessageEncoder::response_to(&mut self.response_buf[..limit], &header, [&question]);
+        enc.modify_header(|h| {
+            h.set_authority(answer.authoritative);
+            h.set_rcode(answer.rcode);
+        });
+        for (name, ttl, record) in &answer.answers {
+            enc.add_answer(
+                ResourceRecord::new(name, record)
+                    .class(answer.class)
+                    .ttl(*ttl),
+            );
+        }
+        let mut enc = enc.authority();
+        for (name, ttl, record) in &answer.authority {
+            enc.add_authority(ResourceRecord::new(name, record).ttl(*ttl));
+        }
+        let keepalive = match keepalive {
+            Some(_) if !malformed => Some(TcpKeepalive::new(Some(self.tcp_idle_timeout))),
+            _ => None,
+        };
+        let write_opt = |opt: &mut OptEncoder<'_, '_>| {
+            opt.set_dnssec_ok(dnssec_ok);
+            if let Some(keepalive) = &keepalive {
+                opt.tcp_keepalive(keepalive);
+            }
+            if let Some(code) = extended_error {
+                opt.extended_error(&ExtendedError::new(code, ""));
+            }
+        };
+
+        // Additional records are optional, so they're left out rather than truncating the
+        // response. The `OPT` record is not, so room is reserved for it.
+        let mut budget = limit;
+        if edns.is_some() {
+            budget -= encoder::edns_len(write_opt);
+        }
+        let mut enc = enc.additional();
+        let mut rrset = Vec::new();
+        let mut additional = answer.additional.iter().peekable();
+        while let Some((name, ttl, record)) = additional.next() {
+            rrset.push(ResourceRecord::new(name, record).ttl(*ttl));
+            let next = additional.peek();
+            if next.is_some_and(|next| next.0 == *name && rrset_type(next.2) == rrset_type(record))
+            {
+                continue;
+            }
+            if !enc.try_add_additional(&rrset, budget) {
+                log::trace!("additional records don't fit into {} bytes", budget);
+                break;
+            }
+            rrset.clear();
+        }
+        if edns.is_some() {
+            write_opt(&mut enc.edns(EDNS_PAYLOAD_SIZE));
+        }
+        let len = enc.finish().ok().unwrap_or(limit); // truncated replies should still get sent
+        Ok(Some(&self.response_buf[..len]))
+    }
+
+    /// Acknowledges a `NOTIFY` message from the primary server, or refuses it if it was sent by
+    /// anyone else.
+    fn handle_notify(
+        &mut self,
+        mut dec: MessageDecoder<'_, section::Question>,
+        source: SocketAddr,
+    ) -> Result<Option<&[u8]>, Error> {
+        let header = *dec.header();
+        let question = match dec.next() {
+            Some(Ok(q)) => q,
+            Some(Err(_)) | None => {
+                log::debug!("malformed NOTIFY from {}", source);
+                return self.error_response(dec.message(), RCode::FORM_ERR);
+            }
+        };
+        log::debug!("NOTIFY from {}: {}", source, question);
+
+        let from_primary = self
+            .primary
+            .is_some_and(|primary| primary.ip() == source.ip().to_canonical());
+        let accepted = from_primary
+            && question.qtype() == QType::SOA
+            && question.qname().eq_ignore_ascii_case(&self.zone.apex);
+        if accepted {
+            self.notified = true;
+        } else {
+            log::debug!("refusing NOTIFY from {}", source);
+        }
+
+        let mut enc = MessageEncoder::response_to(
+            &mut self.response_buf[..DNS_BUFFER_SIZE],
+            &header,
+            [&question],
+        );
+        enc.modify_header(|h| {
+            h.set_authority(accepted);
+            h.set_rcode(if accepted {
+                RCode::NO_ERROR
+            } else {
+                RCode::REFUSED
+            });
+        });
+        let len = enc.finish()?;
+        Ok(Some(&self.response_buf[..len]))
+    }
+
+    /// Responds to `query` with an error, without answering it.
+    fn error_response(&mut self, query: &[u8], rcode: RCode) -> Result<Option<&[u8]>, Error> {
+        let buf = &mut self.response_buf[..DNS_BUFFER_SIZE];
+        let len = encoder::error_response(buf, query, rcode)?;
+        Ok(Some(&self.response_buf[..len]))
+    }
+}
+
+/// The parts of a query that [`Server::handle`] needs.
+struct ParsedQuery {
+    question: decoder::Question,
+    /// UDP payload size and `DO` flag from the `OPT` record.
+    edns: Option<(u16, bool)>,
+    keepalive: Option<Result<TcpKeepalive, Error>>,
+}
+
+impl ParsedQuery {
+    fn decode(mut dec: MessageDecoder<'_, section::Question>, tcp: bool) -> Result<Self, Error> {
+        // Like most servers, we only support a single question per query.
+        let question = dec.next().ok_or(Error::InvalidValue)??;
+
+        let mut edns = None;
+        let mut keepalive = None;
+        let mut dec = dec.additional()?;
+        for rr in dec.iter() {
+            let rr = rr?;
+            if let (Some(header), Some(options)) = (rr.edns_header(), rr.edns_options()) {
+                edns = Some((header.udp_payload_size(), header.dnssec_ok()));
+                for option in options {
+                    let option = option?;
+                    // The option must be ignored when received over UDP (RFC 7828, section 3.3.2).
+                    if tcp && option.code() == OptionCode::TCP_KEEPALIVE {
+                        keepalive = Some(TcpKeepalive::decode(&option));
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            question,
+            edns,
+            keepalive,
+        })
+    }
+}
+
+/// A synchronous authoritative DNS server, answering queries over UDP and TCP.
+pub struct SyncServer {
+    udp: UdpSocket,
+    tcp: TcpListener,
+    server: Arc<Mutex<Server>>,
+}
+
+impl SyncServer {
+    /// Creates a server for `zone`, listening on `bind_addr` (UDP and TCP).
+    ///
+    /// If `bind_addr` uses port 0, the TCP listener is bound to the port picked for the UDP
+    /// socket.
+    pub fn new(bind_addr: SocketAddr, zone: Zone) -> Result<Self, Error> {
+        let udp = UdpSocket::bind(bind_addr)?;
+        let tcp = TcpListener::bind(udp.local_addr()?)?;
+        Ok(Self {
+            udp,
+            tcp,
+            server: Arc::new(Mutex::new(Server::new(zone))),
+        })
+    }
+
+    /// Adds a [`Policy`] that is consulted for queries for any name in the zone.
+    ///
+    /// See [`Server::add_policy`] for details.
+    pub fn add_policy(&mut self, policy: impl Policy + 'static) {
+        self.server.lock().unwrap().add_policy(policy);
+    }
+
+    /// Adds a [`Policy`] that is consulted for queries for `name`.
+    ///
+    /// See [`Server::add_name_policy`] for details.
+    pub fn add_name_policy(&mut self, name: DomainName, policy: impl Policy + 'static) {
+        self.server.lock().unwrap().add_name_policy(name, policy);
+    }
+
+    /// Restricts which hosts get answers.
+    ///
+    /// See [`Server::set_acl`].
+    pub fn set_acl(&mut self, acl: Acl) {
+        self.server.lock().unwrap().set_acl(acl);
+    }
+
+    /// Allows zone transfers to hosts allowed by `acl`.
+    ///
+    /// See [`Server::allow_transfers`].
+    pub fn allow_transfers(&mut self, acl: Acl) {
+        self.server.lock().unwrap().allow_transfers(acl);
+    }
+
+    /// Adds a secondary server that is notified by [`SyncServer::notify`].
+    ///
+    /// See [`Server::add_notify_target`].
+    pub fn add_notify_target(&mut self, addr: SocketAddr) {
+        self.server.lock().unwrap().add_notify_target(addr);
+    }
+
+    /// Makes this server a secondary server for `primary`.
+    ///
+    /// When listening, the server refreshes its zone from `primary` on startup, and whenever
+    /// `primary` sends a `NOTIFY` message. The zone is fetched via a full zone transfer if the
+    /// primary's `SOA` serial number is newer than that of the current zone. See
+    /// [`Server::set_primary`].
+    pub fn set_primary(&mut self, primary: SocketAddr) {
+        self.server.lock().unwrap().set_primary(primary);
+    }
+
+    /// Sends a `NOTIFY` message to every secondary server added via
+    /// [`SyncServer::add_notify_target`], announcing the current version of the zone.
+    ///
+    /// Each secondary is sent the message up to 3 times, until it responds. Returns
+    /// [`Error::Timeout`] if any of them doesn't respond at all, and [`Error::Resolve`] if any of
+    /// them refuses the message.
+    pub fn notify(&self) -> Result<(), Error> {
+        notify_all(&self.server)
+    }
+
+    /// Atomically replaces the served zone with `zone`.
+    ///
+    /// Queries that are being answered while the zone is replaced are answered from the old zone,
+    /// and all later ones from the new zone. Neither the sockets nor open TCP connections are
+    /// affected.
+    ///
+    /// See [`Server::reload`] for how the serial numbers are compared. If the zone was replaced,
+    /// the secondary servers added via [`SyncServer::add_notify_target`] are notified in the
+    /// background (like [`SyncServer::notify`]), and `Ok(true)` is returned.
+    pub fn reload(&self, zone: Zone) -> Result<bool, Error> {
+        let has_targets = {
+            let mut server = self.server.lock().unwrap();
+            if !server.reload(zone)? {
+                return Ok(false);
+            }
+            !server.notify_targets().is_empty()
+        };
+        if has_targets {
+            let server = self.server.clone();
+            thread::spawn(move || {
+                if let Err(e) = notify_all(&server) {
+                    log::warn!("failed to notify secondaries of reloaded zone: {}", e);
+                }
+            });
+        }
+        Ok(true)
+    }
+
+    /// Adds a view, which serves `zone` to clients allowed by `acl`.
+    ///
+    /// See [`Server::add_view`] for details.
+    pub fn add_view(&mut self, acl: Acl, zone: Zone) {
+        self.server.lock().unwrap().add_view(acl, zone);
+    }
+
+    /// Sets the strings to answer `CH TXT` identification queries with.
+    ///
+    /// See [`Server::set_identity`].
+    pub fn set_identity(&mut self, identity: Identity) {
+        self.server.lock().unwrap().set_identity(identity);
+    }
+
+    /// Sets whether queries for type `ANY` are answered minimally.
+    ///
+    /// See [`Server::set_minimal_any`].
+    pub fn set_minimal_any(&mut self, minimal_any: bool) {
+        self.server.lock().unwrap().set_minimal_any(minimal_any);
+    }
+
+    /// Enables response rate limiting for queries received over UDP.
+    ///
+    /// See [`Server::set_rate_limiter`].
+    pub fn set_rate_limiter(&mut self, rate_limiter: RateLimiter) {
+        self.server.lock().unwrap().set_rate_limiter(rate_limiter);
+    }
+
+    /// Sets the time after which idle TCP connections are closed.
+    ///
+    /// See [`Server::set_tcp_idle_timeout`].
+    pub fn set_tcp_idle_timeout(&mut self, timeout: Duration) {
+        self.server.lock().unwrap().set_tcp_idle_timeout(timeout);
+    }
+
+    /// Returns the local address the server is listening on.
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.udp.local_addr()?)
+    }
+
+    /// Starts listening for and responding to queries.
+    ///
+    /// This spawns a background thread accepting TCP connections, and then blocks forever serving
+    /// UDP clients. It only returns when an error occurs.
+    pub fn listen_blocking(&mut self) -> Result<(), Error> {
+        let primary = self.server.lock().unwrap().primary();
+        if let Some(primary) = primary {
+            spawn_refresh(primary, self.server.clone());
+        }
+
+        let tcp = self.tcp.try_clone()?;
+        let server = self.server.clone();
+        thread::spawn(move || {
+            for conn in tcp.incoming() {
+                let conn = match conn {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        log::warn!("failed to accept TCP connection: {}", e);
+                        continue;
+                    }
+                };
+                let server = server.clone();
+                thread::spawn(move || {
+                    if let Err(e) = serve_tcp(conn, &server) {
+                        log::debug!("TCP connection error: {}", e);
+                    }
+                });
+            }
+        });
+
+        let mut recv_buf = [0; EDNS_PAYLOAD_SIZE as usize];
+        loop {
+            let (len, addr) = self.udp.recv_from(&mut recv_buf)?;
+            let packet = &recv_buf[..len];
+            log::trace!("recv from {}: {}", addr, Hex(packet));
+
+            let mut server = self.server.lock().unwrap();
+            match server.handle_packet(packet, addr) {
+                Ok(Some(resp)) => {
+                    self.udp.send_to(resp, addr)?;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    log::debug!("failed to handle query from {}: {}", addr, e);
+                }
+            }
+            if server.take_notify() {
+                if let Some(primary) = server.primary() {
+                    spawn_refresh(primary, self.server.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Serves a TCP client connection until it is closed or has been idle for longer than the server's
+/// idle timeout.
+/// Sends a `NOTIFY` message to every notify target of `server`, and waits for the responses.
+fn notify_all(server: &Mutex<Server>) -> Result<(), Error> {
+    let (targets, messages) = {
+        let mut server = server.lock().unwrap();
+        let targets = server.notify_targets().to_vec();
+        let messages = targets
+            .iter()
+            .map(|_| {
+                let id = Header::random_id();
+                server.build_notify(id).map(|msg| (id, msg.to_vec()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        (targets, messages)
+    };
+    let mut result = Ok(());
+    for (target, (id, msg)) in targets.into_iter().zip(messages) {
+        if let Err(e) = send_notify(target, id, &msg) {
+            log::warn!("failed to notify {}: {}", target, e);
+            result = Err(e);
+        }
+    }
+    result
+}
+
+/// Sends a `NOTIFY` message to `target` and waits for the response, retrying a few times.
+fn send_notify(target: SocketAddr, id: u16, msg: &[u8]) -> Result<(), Error> {
+    let bind_addr: SocketAddr = match target {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let sock = UdpSocket::bind(bind_addr)?;
+    sock.set_read_timeout(Some(NOTIFY_TIMEOUT))?;
+    sock.connect(target)?;
+    let mut buf = [0; DNS_BUFFER_SIZE];
+    for _ in 0..NOTIFY_ATTEMPTS {
+        sock.send(msg)?;
+        let len = match sock.recv(&mut buf) {
+            Ok(len) => len,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let response = &buf[..len];
+        let header = *MessageDecoder::new(response)?.header();
+        if header.id() != id || !header.is_response() || header.opcode() != Opcode::NOTIFY {
+            continue;
+        }
+        return match ResolveError::from_response(response, target) {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        };
+    }
+    Err(Error::Timeout)
+}
+
+/// Refreshes the zone of a secondary server from `primary` in the background.
+fn spawn_refresh(primary: SocketAddr, server: Arc<Mutex<Server>>) {
+    thread::spawn(move || {
+        if let Err(e) = refresh_zone(primary, &server) {
+            log::warn!("failed to refresh zone from {}: {}", primary, e);
+        }
+    });
+}
+
+/// Fetches the zone from `primary` if its serial number is newer than that of the served zone.
+fn refresh_zone(primary: SocketAddr, server: &Mutex<Server>) -> Result<(), Error> {
+    let (apex, serial) = {
+        let server = server.lock().unwrap();
+        (server.zone().apex().clone(), server.zone().serial())
+    };
+    let primary_serial = transfer::query_serial(primary, &apex, NOTIFY_TIMEOUT)?;
+    if serial.is_some_and(|serial| !transfer::serial_newer(primary_serial, serial)) {
+        log::debug!("zone {} is up to date (serial {})", apex, primary_serial);
+        return Ok(());
+    }
+    let zone = transfer::transfer_zone(primary, &apex, NOTIFY_TIMEOUT)?;
+    log::info!("transferred zone {} (serial {})", apex, primary_serial);
+    server.lock().unwrap().set_zone(zone);
+    Ok(())
+}
+
+fn serve_tcp(mut conn: TcpStream, server: &Mutex<Server>) -> io::Result<()> {
+    let peer = conn.peer_addr()?;
+    let mut buf = vec![0; usize::from(u16::MAX)];
+    loop {
+        let idle_timeout = server.lock().unwrap().tcp_idle_timeout();
+        conn.set_read_timeout(Some(idle_timeout))?;
+        let mut len = [0; 2];
+        match conn.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                log::trace!("closing idle TCP connection from {}", peer);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        }
+        let packet = &mut buf[..usize::from(u16::from_be_bytes(len))];
+        conn.read_exact(packet)?;
+        log::trace!("TCP recv from {}: {}", peer, Hex(packet));
+
+        // Don't hold the lock while writing to a potentially slow client.
+        let res = server
+            .lock()
+            .unwrap()
+            .handle_tcp_packet(packet, peer)
+            .map(|resp| resp.map(<[u8]>::to_vec));
+        match res {
+            Ok(Some(resp)) => {
+                // `handle_tcp_packet` never produces responses larger than 65535 bytes.
+                conn.write_all(&(resp.len() as u16).to_be_bytes())?;
+                conn.write_all(&resp)?;
+            }
+            Ok(None) => {}
+            Err(e) => log::debug!("failed to handle query from {}: {}", peer, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use crate::{
+        clock::ManualClock,
+        packet::{
+            dnssec::Algorithm,
+            encoder,
+            records::{A, AAAA},
+        },
+        resolver,
+    };
+
+    use super::*;
+
+    struct TestKey {
+        apex: DomainName,
+    }
+
+    impl Signer for TestKey {
+        fn algorithm(&self) -> Algorithm {
+            Algorithm::PRIVATEDNS
+        }
+
+        fn key_tag(&self) -> u16 {
+            1234
+        }
+
+        fn signer_name(&self) -> &DomainName {
+            &self.apex
+        }
+
+        fn sign(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+            Ok((data.len() as u32).to_be_bytes().to_vec())
+        }
+    }
+
+    fn domain(s: &str) -> DomainName {
+        s.parse().unwrap()
+    }
+
+    fn zone() -> Zone {
+        zone_with_serial(1)
+    }
+
+    fn zone_with_serial(serial: u32) -> Zone {
+        let apex = domain("Example.com");
+        let mut zone = Zone::new(apex.clone());
+        let soa = SOA::new(
+            domain("ns.example.com"),
+            domain("admin.example.com"),
+            serial,
+            2,
+            3,
+            4,
+            60,
+        );
+        zone.add(apex.clone(), 3600, Record::SOA(soa)).unwrap();
+        zone.add(
+            apex.clone(),
+            3600,
+            Record::NS(NS::new(domain("ns.example.com"))),
+        )
+        .unwrap();
+        let a = |ip| Record::A(A::new(Ipv4Addr::new(10, 0, 0, ip)));
+        zone.add(domain("ns.example.com"), 3600, a(1)).unwrap();
+        zone.add(domain("WWW.example.com"), 300, a(2)).unwrap();
+        zone.add(domain("www.example.com"), 300, a(3)).unwrap();
+        zone.add(domain("a.b.example.com"), 300, a(4)).unwrap();
+        zone.add(
+            domain("sub.example.com"),
+            3600,
+            Record::NS(NS::new(domain("ns.sub.example.com"))),
+        )
+        .unwrap();
+        zone.add(domain("ns.sub.example.com"), 3600, a(5)).unwrap();
+        zone
+    }
+
+    fn query(server: &mut Server, name: &str, qtype: QType, dnssec_ok: bool) -> Vec<String> {
+        let source = SocketAddr::from((Ipv4Addr::LOCALHOST, 5300));
+        query_from(server, source, name, qtype, dnssec_ok)
+    }
+
+    fn query_from(
+        server: &mut Server,
+        source: SocketAddr,
+        name: &str,
+        qtype: QType,
+        dnssec_ok: bool,
+    ) -> Vec<String> {
+        let name = domain(name);
+        let mut buf = [0; 512];
+        let mut enc = encoder::MessageEncoder::new(&mut buf);
+        enc.question(Question::new(&name).ty(qtype));
+        let mut enc = enc.answers().authority().additional();
+        enc.edns(1232).set_dnssec_ok(dnssec_ok);
+        let len = enc.finish().unwrap();
+
+        let response = server.handle_packet(&buf[..len], source).unwrap().unwrap();
+        let mut lines = Vec::new();
+        MessageDecoder::new(response)
+            .unwrap()
+            .format(|args| lines.push(args.to_string()))
+            .unwrap();
+        lines
+            .into_iter()
+            .filter(|line| !line.starts_with("Q:") && !line.contains("OPT"))
+            .collect()
+    }
+
+    #[test]
+    fn nsec_chain() {
+        let mut zone = zone();
+        assert_eq!(
+            zone.add(domain("example.org"), 0, Record::NS(NS::new(domain("a")))),
+            Err(Error::InvalidValue)
+        );
+        let key = TestKey {
+            apex: domain("example.com"),
+        };
+        zone.sign(&key, Validity::new(0, 100)).unwrap();
+        // Signing twice replaces the old signatures.
+        zone.sign(&key, Validity::new(0, 100)).unwrap();
+
+        let nsecs = zone
+            .records()
+            .filter_map(|(name, ttl, record)| match record {
+                Record::NSEC(nsec) => Some(format!("{} {} {}", name, ttl, nsec)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            nsecs,
+            [
+                "example.com. 60 a.b.example.com. NS SOA RRSIG NSEC",
+                "a.b.example.com. 60 ns.example.com. A RRSIG NSEC",
+                "ns.example.com. 60 sub.example.com. A RRSIG NSEC",
+                "sub.example.com. 60 www.example.com. NS RRSIG NSEC",
+                "www.example.com. 60 example.com. A RRSIG NSEC",
+            ]
+        );
+
+        let rrsigs = zone
+            .records()
+            .filter_map(|(name, _, record)| match record {
+                Record::RRSIG(sig) => Some(format!("{} {}", name, sig.type_covered())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rrsigs,
+            [
+                "example.com. SOA",
+                "example.com. NS",
+                "example.com. NSEC",
+                "a.b.example.com. A",
+                "a.b.example.com. NSEC",
+                "ns.example.com. A",
+                "ns.example.com. NSEC",
+                "sub.example.com. NSEC",
+                "www.example.com. A",
+                "www.example.com. NSEC",
+            ]
+        );
+
+        assert_eq!(
+            zone.sign(
+                &TestKey {
+                    apex: domain("other.com")
+                },
+                Validity::new(0, 100)
+            ),
+            Err(Error::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn dnssec_responses() {
+        let mut zone = zone();
+        zone.sign(
+            &TestKey {
+                apex: domain("example.com"),
+            },
+            Validity::new(0, 100),
+        )
+        .unwrap();
+        let mut server = Server::new(zone);
+
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: www.example.com.\t300\tIN\tA\t10.0.0.2",
+                "ANS: www.example.com.\t300\tIN\tA\t10.0.0.3",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "www.example.com", QType::A, false));
+
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: www.example.com.\t300\tIN\tA\t10.0.0.2",
+                "ANS: www.example.com.\t300\tIN\tA\t10.0.0.3",
+                "ANS: www.example.com.\t300\tIN\tRRSIG\tA\tPRIVATEDNS\t3\t300\t100\t0\t1234\texample.com.\t0000005d",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "WWW.example.com", QType::A, true));
+
+        // NODATA
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "AUTH: example.com.\t60\tIN\tSOA\tns.example.com.\tadmin.example.com.\t1\t2\t3\t4\t60",
+                "AUTH: example.com.\t60\tIN\tRRSIG\tSOA\tPRIVATEDNS\t2\t3600\t100\t0\t1234\texample.com.\t0000006d",
+                "AUTH: www.example.com.\t60\tIN\tNSEC\texample.com. A RRSIG NSEC",
+                "AUTH: www.example.com.\t60\tIN\tRRSIG\tNSEC\tPRIVATEDNS\t3\t60\t100\t0\t1234\texample.com.\t0000004f",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "www.example.com", QType::MX, true));
+
+        // Empty non-terminal
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "AUTH: example.com.\t60\tIN\tSOA\tns.example.com.\tadmin.example.com.\t1\t2\t3\t4\t60",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "b.example.com", QType::A, false));
+
+        // NXDOMAIN
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NX_DOMAIN, AA)",
+                "AUTH: example.com.\t60\tIN\tSOA\tns.example.com.\tadmin.example.com.\t1\t2\t3\t4\t60",
+                "AUTH: example.com.\t60\tIN\tRRSIG\tSOA\tPRIVATEDNS\t2\t3600\t100\t0\t1234\texample.com.\t0000006d",
+                "AUTH: example.com.\t60\tIN\tNSEC\ta.b.example.com. NS SOA RRSIG NSEC",
+                "AUTH: example.com.\t60\tIN\tRRSIG\tNSEC\tPRIVATEDNS\t2\t60\t100\t0\t1234\texample.com.\t0000004f",
+                "AUTH: a.b.example.com.\t60\tIN\tNSEC\tns.example.com. A RRSIG NSEC",
+                "AUTH: a.b.example.com.\t60\tIN\tRRSIG\tNSEC\tPRIVATEDNS\t4\t60\t100\t0\t1234\texample.com.\t00000052",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "nope.example.com", QType::A, true));
+
+        // Referral
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR)",
+                "AUTH: sub.example.com.\t3600\tIN\tNS\tns.sub.example.com.",
+                "AUTH: sub.example.com.\t60\tIN\tNSEC\twww.example.com. NS RRSIG NSEC",
+                "AUTH: sub.example.com.\t60\tIN\tRRSIG\tNSEC\tPRIVATEDNS\t3\t60\t100\t0\t1234\texample.com.\t00000053",
+                "ADDL: ns.sub.example.com.\t3600\tIN\tA\t10.0.0.5",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "host.sub.example.com", QType::A, true));
+
+        // Outside of the zone
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=REFUSED)",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "example.org", QType::A, true));
+    }
+
+    #[test]
+    fn policies() {
+        let mut server = Server::new(zone());
+        let mut counter = 0;
+        server.add_name_policy(
+            domain("WWW.example.com"),
+            move |req: &Request<'_>| match req.zone_records() {
+                Some((ttl, records)) => {
+                    counter += 1;
+                    let mut records = records.to_vec();
+                    let len = records.len();
+                    records.rotate_left(counter % len);
+                    PolicyAnswer::Records(ttl, records)
+                }
+                None => PolicyAnswer::Continue,
+            },
+        );
+        server.add_policy(|req: &Request<'_>| {
+            let labels = req.qname().labels();
+            match labels.first().map(|label| label.as_bytes()) {
+                Some(b"blocked") => PolicyAnswer::NxDomain,
+                Some(b"private") => PolicyAnswer::Refuse,
+                Some(b"client") if req.qtype() == QType::A => {
+                    let IpAddr::V4(ip) = req.source().ip() else {
+                        return PolicyAnswer::Continue;
+                    };
+                    PolicyAnswer::Records(0, vec![Record::A(A::new(ip))])
+                }
+                Some(b"client") => PolicyAnswer::Records(0, Vec::new()),
+                _ => PolicyAnswer::Continue,
+            }
+        });
+
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: www.example.com.\t300\tIN\tA\t10.0.0.3",
+                "ANS: www.example.com.\t300\tIN\tA\t10.0.0.2",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "www.example.com", QType::A, false));
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: www.example.com.\t300\tIN\tA\t10.0.0.2",
+                "ANS: www.example.com.\t300\tIN\tA\t10.0.0.3",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "www.example.com", QType::A, false));
+
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NX_DOMAIN, AA)",
+                "AUTH: example.com.\t60\tIN\tSOA\tns.example.com.\tadmin.example.com.\t1\t2\t3\t4\t60",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "blocked.example.com", QType::A, false));
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=REFUSED)",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "private.example.com", QType::A, false));
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: client.example.com.\t0\tIN\tA\t127.0.0.1",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "client.example.com", QType::A, false));
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "AUTH: example.com.\t60\tIN\tSOA\tns.example.com.\tadmin.example.com.\t1\t2\t3\t4\t60",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "client.example.com", QType::AAAA, false));
+
+        let mut acl = Acl::new();
+        acl.deny("127.0.0.0/8".parse().unwrap());
+        server.set_acl(acl);
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=REFUSED)",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "client.example.com", QType::A, false));
+        server.set_acl(Acl::new());
+
+        // Unhandled queries are answered from the zone.
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: a.b.example.com.\t300\tIN\tA\t10.0.0.4",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "a.b.example.com", QType::A, false));
+    }
+
+    #[test]
+    fn views() {
+        let mut server = Server::new(zone());
+        let mut internal = Zone::new(domain("example.com"));
+        let a = Record::A(A::new(Ipv4Addr::new(192, 168, 0, 2)));
+        internal.add(domain("www.example.com"), 60, a).unwrap();
+        let mut acl = Acl::new();
+        acl.allow("192.168.0.0/16".parse().unwrap());
+        server.add_view(acl, internal);
+
+        let inside = SocketAddr::from((Ipv4Addr::new(192, 168, 1, 1), 5300));
+        let outside = SocketAddr::from((Ipv4Addr::new(203, 0, 113, 1), 5300));
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: www.example.com.\t60\tIN\tA\t192.168.0.2",
+            ]
+        "#]]
+        .assert_debug_eq(&query_from(
+            &mut server,
+            inside,
+            "WWW.example.com",
+            QType::A,
+            false,
+        ));
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: www.example.com.\t300\tIN\tA\t10.0.0.2",
+                "ANS: www.example.com.\t300\tIN\tA\t10.0.0.3",
+            ]
+        "#]]
+        .assert_debug_eq(&query_from(
+            &mut server,
+            outside,
+            "www.example.com",
+            QType::A,
+            false,
+        ));
+
+        // Names that don't exist in the view fall through to the main zone.
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: ns.example.com.\t3600\tIN\tA\t10.0.0.1",
+            ]
+        "#]]
+        .assert_debug_eq(&query_from(
+            &mut server,
+            inside,
+            "ns.example.com",
+            QType::A,
+            false,
+        ));
+    }
+
+    #[test]
+    fn tcp_keepalive() {
+        let mut server = Server::new(zone());
+        server.set_tcp_idle_timeout(Duration::from_secs(30));
+        let source = SocketAddr::from((Ipv4Addr::LOCALHOST, 5300));
+        let mut buf = [0; 512];
+        let query =
+            resolver::encode_tcp_query(&mut buf, Header::random_id(), &domain("www.example.com"));
+
+        let response = server.handle_tcp_packet(query, source).unwrap().unwrap();
+        assert_eq!(
+            resolver::decode_tcp_keepalive(response).unwrap(),
+            Some(Duration::from_secs(30))
+        );
+        // The option is ignored over UDP.
+        let response = server.handle_packet(query, source).unwrap().unwrap();
+        assert_eq!(resolver::decode_tcp_keepalive(response).unwrap(), None);
+
+        // Queries must not specify a timeout.
+        let name = domain("www.example.com");
+        let mut enc = encoder::MessageEncoder::new(&mut buf);
+        enc.question(Question::new(&name).ty(QType::A));
+        let mut enc = enc.answers().authority().additional();
+        enc.edns(1232)
+            .tcp_keepalive(&TcpKeepalive::new(Some(Duration::from_secs(1))));
+        let len = enc.finish().unwrap();
+        let response = server
+            .handle_tcp_packet(&buf[..len], source)
+            .unwrap()
+            .unwrap();
+        let dec = MessageDecoder::new(response).unwrap();
+        assert_eq!(dec.header().rcode(), RCode::FORM_ERR);
+        assert_eq!(resolver::decode_tcp_keepalive(response).unwrap(), None);
+    }
+
+    #[test]
+    fn tcp_connection_reuse() {
+        let mut server = SyncServer::new((Ipv4Addr::LOCALHOST, 0).into(), zone()).unwrap();
+        server.set_tcp_idle_timeout(Duration::from_millis(200));
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.listen_blocking());
+
+        let mut conn = TcpStream::connect(addr).unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut buf = [0; 512];
+        for _ in 0..2 {
+            let query = resolver::encode_tcp_query(
+                &mut buf,
+                Header::random_id(),
+                &domain("www.example.com"),
+            );
+            conn.write_all(&(query.len() as u16).to_be_bytes()).unwrap();
+            conn.write_all(query).unwrap();
+
+            let mut len = [0; 2];
+            conn.read_exact(&mut len).unwrap();
+            let mut response = vec![0; usize::from(u16::from_be_bytes(len))];
+            conn.read_exact(&mut response).unwrap();
+            let mut ips = Vec::new();
+            resolver::decode_answer(&response, &mut ips).unwrap();
+            ips.sort();
+            assert_eq!(
+                ips,
+                [IpAddr::from([10, 0, 0, 2]), IpAddr::from([10, 0, 0, 3])]
+            );
+            assert_eq!(
+                resolver::decode_tcp_keepalive(&response).unwrap(),
+                Some(Duration::from_millis(200))
+            );
+        }
+
+        // The server closes the connection once it has been idle for too long.
+        assert_eq!(conn.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn minimal_any() {
+        let mut zone = zone();
+        zone.sign(
+            &TestKey {
+                apex: domain("example.com"),
+            },
+            Validity::new(0, 100),
+        )
+        .unwrap();
+        let mut server = Server::new(zone);
+        server.set_minimal_any(true);
+
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: example.com.\t3789\tIN\tHINFO\tRFC8482\t",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "example.com", QType::ALL, false));
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: example.com.\t3600\tIN\tSOA\tns.example.com.\tadmin.example.com.\t1\t2\t3\t4\t60",
+                "ANS: example.com.\t3600\tIN\tRRSIG\tSOA\tPRIVATEDNS\t2\t3600\t100\t0\t1234\texample.com.\t0000006d",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "example.com", QType::ALL, true));
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NX_DOMAIN, AA)",
+                "AUTH: example.com.\t60\tIN\tSOA\tns.example.com.\tadmin.example.com.\t1\t2\t3\t4\t60",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "missing.example.com", QType::ALL, false));
+    }
+
+    #[test]
+    fn additional_addresses() {
+        let mut zone = zone();
+        let mail = domain("mail.example.com");
+        zone.add(
+            mail.clone(),
+            300,
+            Record::A(A::new(Ipv4Addr::new(10, 0, 0, 6))),
+        )
+        .unwrap();
+        zone.add(
+            mail.clone(),
+            300,
+            Record::AAAA(AAAA::new(Ipv6Addr::LOCALHOST)),
+        )
+        .unwrap();
+        for exchange in ["Mail.example.com", "ns.sub.example.com", "mail.example.net"] {
+            let mx = Record::MX(MX::new(10, domain(exchange)));
+            zone.add(domain("example.com"), 3600, mx).unwrap();
+        }
+        let srv = Record::SRV(SRV::new(0, 0, 5060, mail));
+        zone.add(domain("_sip._udp.example.com"), 3600, srv)
+            .unwrap();
+        let mut server = Server::new(zone);
+        server.add_name_policy(domain("policy.example.com"), |_: &Request<'_>| {
+            PolicyAnswer::Records(0, vec![Record::NS(NS::new(domain("ns.sub.example.com")))])
+        });
+
+        // Addresses below a delegation point are only included as glue for `NS` records.
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: example.com.\t3600\tIN\tMX\t10 mail.example.com.",
+                "ANS: example.com.\t3600\tIN\tMX\t10 ns.sub.example.com.",
+                "ANS: example.com.\t3600\tIN\tMX\t10 mail.example.net.",
+                "ADDL: mail.example.com.\t300\tIN\tA\t10.0.0.6",
+                "ADDL: mail.example.com.\t300\tIN\tAAAA\t::1",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "example.com", QType::MX, false));
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: _sip._udp.example.com.\t3600\tIN\tSRV\t0\t0\t5060\tmail.example.com.",
+                "ADDL: mail.example.com.\t300\tIN\tA\t10.0.0.6",
+                "ADDL: mail.example.com.\t300\tIN\tAAAA\t::1",
+            ]
+        "#]]
+        .assert_debug_eq(&query(
+            &mut server,
+            "_sip._udp.example.com",
+            QType::SRV,
+            false,
+        ));
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: example.com.\t3600\tIN\tNS\tns.example.com.",
+                "ADDL: ns.example.com.\t3600\tIN\tA\t10.0.0.1",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "example.com", QType::NS, false));
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: policy.example.com.\t0\tIN\tNS\tns.sub.example.com.",
+                "ADDL: ns.sub.example.com.\t3600\tIN\tA\t10.0.0.5",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "policy.example.com", QType::NS, false));
+    }
+
+    #[test]
+    fn additional_budget() {
+        let mut zone = zone();
+        let service = domain("_ipp._tcp.example.com");
+        let instance = domain("Printer._ipp._tcp.example.com");
+        let host = domain("printer.example.com");
+        zone.add(
+            service.clone(),
+            300,
+            Record::PTR(PTR::new(instance.clone())),
+        )
+        .unwrap();
+        let srv = Record::SRV(SRV::new(0, 0, 631, host.clone()));
+        zone.add(instance.clone(), 300, srv).unwrap();
+        let txt = Record::TXT(TXT::new([b"rp=printers/1"]));
+        zone.add(instance, 300, txt).unwrap();
+        for i in 1..=3 {
+            let a = Record::A(A::new(Ipv4Addr::new(10, 0, 1, i)));
+            zone.add(host.clone(), 300, a).unwrap();
+        }
+        let mut server = Server::new(zone);
+
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: _ipp._tcp.example.com.\t300\tIN\tPTR\tprinter._ipp._tcp.example.com.",
+                "ADDL: printer._ipp._tcp.example.com.\t300\tIN\tSRV\t0\t0\t631\tprinter.example.com.",
+                "ADDL: printer._ipp._tcp.example.com.\t300\tIN\tTXT\trp=printers/1",
+                "ADDL: printer.example.com.\t300\tIN\tA\t10.0.1.1",
+                "ADDL: printer.example.com.\t300\tIN\tA\t10.0.1.2",
+                "ADDL: printer.example.com.\t300\tIN\tA\t10.0.1.3",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut server, "_ipp._tcp.example.com", QType::PTR, false));
+
+        // With many addresses, the address RRset is left out as a whole, without setting `TC`.
+        for i in 4..=90 {
+            let a = Record::A(A::new(Ipv4Addr::new(10, 0, 1, i)));
+            server.zone.add(host.clone(), 300, a).unwrap();
+        }
+        let lines = query(&mut server, "_ipp._tcp.example.com", QType::PTR, true);
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: _ipp._tcp.example.com.\t300\tIN\tPTR\tprinter._ipp._tcp.example.com.",
+                "ADDL: printer._ipp._tcp.example.com.\t300\tIN\tSRV\t0\t0\t631\tprinter.example.com.",
+                "ADDL: printer._ipp._tcp.example.com.\t300\tIN\tTXT\trp=printers/1",
+            ]
+        "#]]
+        .assert_debug_eq(&lines);
+    }
+
+    #[test]
+    fn rate_limiting() {
+        let clock = ManualClock::new();
+        let mut server = Server::new(zone());
+        let mut rrl = RateLimiter::new(1);
+        rrl.set_slip(1);
+        server.set_rate_limiter(rrl);
+        server.set_clock(clock.clone());
+
+        let name = domain("www.example.com");
+        let mut buf = [0; 512];
+        let mut enc = encoder::MessageEncoder::new(&mut buf);
+        enc.question(Question::new(&name));
+        let len = enc.finish().unwrap();
+        let query = &buf[..len];
+        let source = SocketAddr::from((Ipv4Addr::new(192, 0, 2, 1), 5300));
+        let header = |response: &[u8]| *MessageDecoder::new(response).unwrap().header();
+
+        let response = server.handle_packet(query, source).unwrap().unwrap();
+        assert!(!header(response).is_truncated());
+        assert_eq!(header(response).answer_count(), 2);
+        let response = server.handle_packet(query, source).unwrap().unwrap();
+        assert!(header(response).is_truncated());
+        assert_eq!(header(response).answer_count(), 0);
+
+        // TCP responses are never limited.
+        let response = server.handle_tcp_packet(query, source).unwrap().unwrap();
+        assert!(!header(response).is_truncated());
+
+        clock.advance(Duration::from_secs(1));
+        let response = server.handle_packet(query, source).unwrap().unwrap();
+        assert!(!header(response).is_truncated());
+
+        let mut rrl = RateLimiter::new(1);
+        rrl.set_slip(0);
+        server.set_rate_limiter(rrl);
+        assert!(server.handle_packet(query, source).unwrap().is_some());
+        assert_eq!(server.handle_packet(query, source).unwrap(), None);
+    }
+
+    #[test]
+    fn identity() {
+        let chaos_query = |server: &mut Server, name: &str, qtype: QType| {
+            let name = domain(name);
+            let mut buf = [0; 512];
+            let mut enc = encoder::MessageEncoder::new(&mut buf);
+            enc.question(Question::new(&name).class(QClass::CH).ty(qtype));
+            let len = enc.finish().unwrap();
+            let source = SocketAddr::from((Ipv4Addr::LOCALHOST, 5300));
+            let response = server.handle_packet(&buf[..len], source).unwrap().unwrap();
+            let mut lines = Vec::new();
+            MessageDecoder::new(response)
+                .unwrap()
+                .format(|args| lines.push(args.to_string()))
+                .unwrap();
+            lines.retain(|line| !line.starts_with("Q:"));
+            lines
+        };
+
+        let mut server = Server::new(zone());
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=REFUSED)",
+            ]
+        "#]]
+        .assert_debug_eq(&chaos_query(&mut server, "version.bind", QType::TXT));
+
+        let mut identity = Identity::new();
+        identity.set_version("uwuhi");
+        server.set_identity(identity);
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: VERSION.bind.\t0\tCH\tTXT\tuwuhi",
+            ]
+        "#]]
+        .assert_debug_eq(&chaos_query(&mut server, "VERSION.bind", QType::ALL));
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=REFUSED)",
+            ]
+        "#]]
+        .assert_debug_eq(&chaos_query(&mut server, "hostname.bind", QType::TXT));
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=REFUSED)",
+            ]
+        "#]]
+        .assert_debug_eq(&chaos_query(&mut server, "version.bind", QType::A));
+    }
+
+    #[test]
+    fn notify() {
+        let mut primary = Server::new(zone());
+        let notify = primary.build_notify(42).unwrap().to_vec();
+        let mut lines = Vec::new();
+        MessageDecoder::new(&notify)
+            .unwrap()
+            .format(|args| lines.push(args.to_string()))
+            .unwrap();
+        expect_test::expect![[r#"
+            [
+                "query (id=42, op=NOTIFY, rcode=NO_ERROR, AA)",
+                "Q: example.com.\tIN\tSOA",
+                "ANS: example.com.\t3600\tIN\tSOA\tns.example.com.\tadmin.example.com.\t1\t2\t3\t4\t60",
+            ]
+        "#]]
+        .assert_debug_eq(&lines);
+
+        let mut secondary = Server::new(Zone::new(domain("example.com")));
+        secondary.set_primary(SocketAddr::from((Ipv4Addr::new(10, 0, 0, 1), 53)));
+        let stranger = SocketAddr::from((Ipv4Addr::new(10, 0, 0, 9), 5300));
+        let response = secondary.handle_packet(&notify, stranger).unwrap().unwrap();
+        let header = *MessageDecoder::new(response).unwrap().header();
+        assert_eq!(header.rcode(), RCode::REFUSED);
+        assert!(!secondary.take_notify());
+
+        let source = SocketAddr::from((Ipv4Addr::new(10, 0, 0, 1), 5300));
+        let response = secondary.handle_packet(&notify, source).unwrap().unwrap();
+        let header = *MessageDecoder::new(response).unwrap().header();
+        assert!(header.is_response());
+        assert_eq!(header.id(), 42);
+        assert_eq!(header.opcode(), Opcode::NOTIFY);
+        assert_eq!(header.rcode(), RCode::NO_ERROR);
+        assert!(secondary.take_notify());
+        assert!(!secondary.take_notify());
+
+        // Zone transfers are refused over UDP.
+        primary.allow_transfers(Acl::new());
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=REFUSED)",
+            ]
+        "#]]
+        .assert_debug_eq(&query(&mut primary, "example.com", QType::AXFR, false));
+    }
+
+    #[test]
+    fn error_responses() {
+        let mut server = Server::new(zone());
+        let source = SocketAddr::from((Ipv4Addr::LOCALHOST, 5300));
+        let name = domain("www.example.com");
+        let mut header = Header::default();
+        header.set_id(99);
+
+        let mut buf = [0; 512];
+        let mut enc = encoder::MessageEncoder::new(&mut buf);
+        header.set_opcode(Opcode::STATUS);
+        enc.set_header(header);
+        enc.question(Question::new(&name).ty(QType::A));
+        let len = enc.finish().unwrap();
+        let response = server.handle_packet(&buf[..len], source).unwrap().unwrap();
+        let mut lines = Vec::new();
+        MessageDecoder::new(response)
+            .unwrap()
+            .format(|args| lines.push(args.to_string()))
+            .unwrap();
+        expect_test::expect![[r#"
+            [
+                "response (id=99, op=STATUS, rcode=NOT_IMP)",
+                "Q: www.example.com.\tIN\tA",
+            ]
+        "#]]
+        .assert_debug_eq(&lines);
+
+        // Queries without a question.
+        let mut enc = encoder::MessageEncoder::new(&mut buf);
+        header.set_opcode(Opcode::QUERY);
+        enc.set_header(header);
+        let len = enc.finish().unwrap();
+        let response = server.handle_packet(&buf[..len], source).unwrap().unwrap();
+        let header = *MessageDecoder::new(response).unwrap().header();
+        assert_eq!(header.id(), 99);
+        assert_eq!(header.rcode(), RCode::FORM_ERR);
+
+        // Queries whose additional section is cut off.
+        let query = resolver::encode_tcp_query(&mut buf, Header::random_id(), &name);
+        let query = &query[..query.len() - 1];
+        let response = server.handle_packet(query, source).unwrap().unwrap();
+        let mut dec = MessageDecoder::new(response).unwrap();
+        assert_eq!(dec.header().rcode(), RCode::FORM_ERR);
+        assert_eq!(dec.next().unwrap().unwrap().qname(), &name);
+
+        // Responses and truncated headers are dropped.
+        let mut buf = [0; 512];
+        let len = encoder::error_response(&mut buf, query, RCode::FORM_ERR).unwrap();
+        assert_eq!(server.handle_packet(&buf[..len], source).unwrap(), None);
+        assert!(server.handle_packet(&buf[..5], source).is_err());
+    }
+
+    #[test]
+    fn reload() {
+        let mut server = Server::new(zone());
+        let mut updated = zone_with_serial(2);
+        updated
+            .add(
+                domain("new.example.com"),
+                300,
+                Record::A(A::new(Ipv4Addr::new(10, 0, 0, 6))),
+            )
+            .unwrap();
+        assert!(query(&mut server, "new.example.com", QType::A, false)[0].contains("NX_DOMAIN"));
+
+        // Same serial: nothing to do. Older serial, or different apex: rejected.
+        assert_eq!(server.reload(zone()), Ok(false));
+        assert_eq!(server.reload(zone_with_serial(0)), Err(Error::InvalidValue));
+        assert_eq!(
+            server.reload(Zone::new(domain("example.org"))),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(server.zone().serial(), Some(1));
+
+        assert_eq!(server.reload(updated), Ok(true));
+        assert_eq!(server.zone().serial(), Some(2));
+        assert!(query(&mut server, "new.example.com", QType::A, false)[0].contains("NO_ERROR"));
+
+        // Serial numbers wrap around.
+        let mut server = Server::new(zone_with_serial(u32::MAX));
+        assert_eq!(server.reload(zone_with_serial(0)), Ok(true));
+    }
+
+    #[test]
+    fn reload_notifies() {
+        let secondary = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        secondary
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut primary = SyncServer::new((Ipv4Addr::LOCALHOST, 0).into(), zone()).unwrap();
+        primary.add_notify_target(secondary.local_addr().unwrap());
+
+        assert_eq!(primary.reload(zone()), Ok(false));
+        assert_eq!(primary.reload(zone_with_serial(5)), Ok(true));
+
+        let mut buf = [0; 512];
+        let (len, source) = secondary.recv_from(&mut buf).unwrap();
+        let mut lines = Vec::new();
+        MessageDecoder::new(&buf[..len])
+            .unwrap()
+            .format(|args| lines.push(args.to_string()))
+            .unwrap();
+        assert!(lines[0].contains("op=NOTIFY"), "{lines:?}");
+        assert!(lines[2].contains("\t5\t2\t3\t4\t60"), "{lines:?}");
+
+        // Acknowledge it, so the primary stops resending.
+        let mut header = *MessageDecoder::new(&buf[..len]).unwrap().header();
+        header.set_response(true);
+        let mut enc = encoder::MessageEncoder::new(&mut buf);
+        enc.set_header(header);
+        let len = enc.finish().unwrap();
+        secondary.send_to(&buf[..len], source).unwrap();
+    }
+
+    #[test]
+    fn zone_transfer() {
+        let mut primary = SyncServer::new((Ipv4Addr::LOCALHOST, 0).into(), zone()).unwrap();
+        let primary_addr = primary.local_addr().unwrap();
+        let timeout = Duration::from_secs(5);
+        let apex = domain("example.com");
+
+        // Transfers are refused until allowed.
+        let server = primary.server.clone();
+        thread::spawn(move || primary.listen_blocking());
+        match transfer::transfer_zone(primary_addr, &apex, timeout) {
+            Err(Error::Resolve(e)) => assert_eq!(e.rcode(), RCode::REFUSED),
+            res => panic!("unexpected result: {:?}", res.map(|zone| zone.serial())),
+        }
+        server.lock().unwrap().allow_transfers(Acl::new());
+
+        assert_eq!(transfer::query_serial(primary_addr, &apex, timeout), Ok(1));
+        let zone = transfer::transfer_zone(primary_addr, &apex, timeout).unwrap();
+        let expected = self::zone();
+        assert!(zone.records().eq(expected.records()));
+
+        // A secondary fetches the zone when it starts listening.
+        let mut secondary =
+            SyncServer::new((Ipv4Addr::LOCALHOST, 0).into(), Zone::new(apex.clone())).unwrap();
+        secondary.set_primary(primary_addr);
+        let secondary_server = secondary.server.clone();
+        thread::spawn(move || secondary.listen_blocking());
+        let deadline = std::time::Instant::now() + timeout;
+        while secondary_server.lock().unwrap().zone().serial() != Some(1) {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "zone was not transferred"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
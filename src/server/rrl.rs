@@ -0,0 +1,257 @@
+//! Response rate limiting.
+//!
+//! DNS runs over UDP, so the source address of a query can be spoofed. An attacker can use this to
+//! direct a server's responses at a victim, which then receives far more traffic than the attacker
+//! sent (a reflection attack). Response rate limiting (RRL) limits how many identical responses are
+//! sent to a network per second, while still answering legitimate clients that don't send many
+//! queries.
+//!
+//! Responses that exceed the limit are dropped, except for every *slip*th one, which is replaced by
+//! an empty, truncated response. That response is as small as the query, so it isn't useful for
+//! amplification, but it prompts legitimate clients to retry over TCP, where the source address
+//! can't be spoofed.
+//!
+//! A [`RateLimiter`] is used by passing it to [`Server::set_rate_limiter`]. It can also be used on
+//! its own to rate limit other UDP services.
+//!
+//! [`Server::set_rate_limiter`]: super::Server::set_rate_limiter
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Instant,
+};
+
+use crate::{name::DomainName, packet::QType};
+
+/// Number of buckets above which buckets that are no longer limited are evicted.
+const MAX_BUCKETS: usize = 10_000;
+
+/// What to do with a response, as decided by [`RateLimiter::check`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitAction {
+    /// Send the response.
+    Respond,
+    /// Send an empty response with the *truncated* bit set instead, so that legitimate clients
+    /// retry over TCP.
+    Slip,
+    /// Don't respond at all.
+    Drop,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    network: IpAddr,
+    qname: DomainName,
+    qtype: QType,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    last_update: Instant,
+    limited: u32,
+}
+
+/// Limits the rate of identical responses sent to each network.
+///
+/// Responses are grouped by the network of the client (the source address, truncated to a prefix
+/// of configurable length) and by the queried name and type. Each group has a token bucket that
+/// allows short bursts of up to one second's worth of responses.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    responses_per_second: u32,
+    slip: u32,
+    ipv4_prefix_len: u8,
+    ipv6_prefix_len: u8,
+    buckets: HashMap<Key, Bucket>,
+}
+
+impl RateLimiter {
+    /// Creates a rate limiter that allows `responses_per_second` identical responses per second to
+    /// each network.
+    ///
+    /// By default, networks are IPv4 `/24` and IPv6 `/56` prefixes, and every second limited
+    /// response is slipped.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `responses_per_second` is 0.
+    pub fn new(responses_per_second: u32) -> Self {
+        assert_ne!(responses_per_second, 0, "response rate must not be 0");
+        Self {
+            responses_per_second,
+            slip: 2,
+            ipv4_prefix_len: 24,
+            ipv6_prefix_len: 56,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Sets how many limited responses are dropped per slipped one.
+    ///
+    /// With a value of `n`, every `n`th limited response is replaced by a truncated response, and
+    /// the others are dropped. 1 slips every limited response, and 0 drops all of them.
+    pub fn set_slip(&mut self, slip: u32) {
+        self.slip = slip;
+    }
+
+    /// Sets the prefix lengths that determine which client addresses share a limit.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `ipv4` is larger than 32 or `ipv6` is larger than 128.
+    pub fn set_prefix_lengths(&mut self, ipv4: u8, ipv6: u8) {
+        assert!(ipv4 <= 32, "invalid IPv4 prefix length {ipv4}");
+        assert!(ipv6 <= 128, "invalid IPv6 prefix length {ipv6}");
+        self.ipv4_prefix_len = ipv4;
+        self.ipv6_prefix_len = ipv6;
+        self.buckets.clear();
+    }
+
+    /// Accounts for a response to a query for `qname` and `qtype` from `source` at `now`, and
+    /// decides whether to send it.
+    ///
+    /// Servers should pass the zone apex instead of the queried name for negative responses, so
+    /// that queries for random nonexistent names share a limit.
+    pub fn check(
+        &mut self,
+        source: IpAddr,
+        qname: &DomainName,
+        qtype: QType,
+        now: Instant,
+    ) -> RateLimitAction {
+        let key = Key {
+            network: self.network(source),
+            qname: qname.to_ascii_lowercase(),
+            qtype,
+        };
+        let rate = f64::from(self.responses_per_second);
+        if self.buckets.len() >= MAX_BUCKETS && !self.buckets.contains_key(&key) {
+            self.evict(now);
+        }
+        let bucket = self.buckets.entry(key).or_insert(Bucket {
+            tokens: rate,
+            last_update: now,
+            limited: 0,
+        });
+        let elapsed = now.saturating_duration_since(bucket.last_update);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * rate).min(rate);
+        bucket.last_update = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return RateLimitAction::Respond;
+        }
+
+        bucket.limited = bucket.limited.wrapping_add(1);
+        if self.slip != 0 && bucket.limited.is_multiple_of(self.slip) {
+            RateLimitAction::Slip
+        } else {
+            RateLimitAction::Drop
+        }
+    }
+
+    /// Returns the number of groups of responses that are currently tracked.
+    #[inline]
+    pub fn tracked(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// Removes the buckets that would be full by `now`, since they no longer limit anything.
+    fn evict(&mut self, now: Instant) {
+        let rate = f64::from(self.responses_per_second);
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.last_update);
+            bucket.tokens + elapsed.as_secs_f64() * rate < rate
+        });
+    }
+
+    fn network(&self, ip: IpAddr) -> IpAddr {
+        match ip.to_canonical() {
+            IpAddr::V4(ip) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.ipv4_prefix_len))
+                    .unwrap_or(0);
+                Ipv4Addr::from(u32::from(ip) & mask).into()
+            }
+            IpAddr::V6(ip) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.ipv6_prefix_len))
+                    .unwrap_or(0);
+                Ipv6Addr::from(u128::from(ip) & mask).into()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn limits() {
+        let mut rrl = RateLimiter::new(2);
+        let name = "www.example.com".parse().unwrap();
+        let client = IpAddr::from([192, 0, 2, 1]);
+        let neighbor = IpAddr::from([192, 0, 2, 99]);
+        let now = Instant::now();
+        let check =
+            |rrl: &mut RateLimiter, source, qtype, now| rrl.check(source, &name, qtype, now);
+
+        assert_eq!(
+            check(&mut rrl, client, QType::A, now),
+            RateLimitAction::Respond
+        );
+        assert_eq!(
+            check(&mut rrl, neighbor, QType::A, now),
+            RateLimitAction::Respond
+        );
+        assert_eq!(
+            check(&mut rrl, client, QType::A, now),
+            RateLimitAction::Drop
+        );
+        assert_eq!(
+            check(&mut rrl, client, QType::A, now),
+            RateLimitAction::Slip
+        );
+        assert_eq!(
+            check(&mut rrl, client, QType::A, now),
+            RateLimitAction::Drop
+        );
+
+        // Other query tuples and networks have their own limits.
+        assert_eq!(
+            check(&mut rrl, client, QType::AAAA, now),
+            RateLimitAction::Respond
+        );
+        let other = IpAddr::from([192, 0, 3, 1]);
+        assert_eq!(
+            check(&mut rrl, other, QType::A, now),
+            RateLimitAction::Respond
+        );
+        assert_eq!(rrl.tracked(), 3);
+
+        // The limit recovers over time.
+        let later = now + Duration::from_millis(500);
+        assert_eq!(
+            check(&mut rrl, client, QType::A, later),
+            RateLimitAction::Respond
+        );
+        assert_eq!(
+            check(&mut rrl, client, QType::A, later),
+            RateLimitAction::Slip
+        );
+
+        rrl.set_slip(0);
+        assert_eq!(
+            check(&mut rrl, client, QType::A, later),
+            RateLimitAction::Drop
+        );
+        assert_eq!(
+            check(&mut rrl, client, QType::A, later),
+            RateLimitAction::Drop
+        );
+    }
+}
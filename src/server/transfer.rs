@@ -0,0 +1,177 @@
+//! Zone transfers between primary and secondary servers.
+//!
+//! A secondary server keeps a copy of a zone that is maintained on a primary server. Whenever the
+//! zone changes, the primary sends a `NOTIFY` message ([RFC 1996]) to its secondaries, which then
+//! check the zone's `SOA` serial number and, if it has increased, fetch the new zone contents via
+//! a full zone transfer (`AXFR`, [RFC 5936]).
+//!
+//! [`Server`] implements both sides: see [`Server::add_notify_target`] and
+//! [`Server::allow_transfers`] for the primary role, and [`Server::set_primary`] for the secondary
+//! role. This module contains the client side of the transfer.
+//!
+//! [RFC 1996]: https://datatracker.ietf.org/doc/html/rfc1996
+//! [RFC 5936]: https://datatracker.ietf.org/doc/html/rfc5936
+//! [`Server`]: super::Server
+//! [`Server::add_notify_target`]: super::Server::add_notify_target
+//! [`Server::allow_transfers`]: super::Server::allow_transfers
+//! [`Server::set_primary`]: super::Server::set_primary
+
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket},
+    time::Duration,
+};
+
+use crate::{
+    hex::Hex,
+    name::DomainName,
+    packet::{
+        decoder::MessageDecoder,
+        encoder::{MessageEncoder, Question},
+        records::Record,
+        Header, QType, Type,
+    },
+    resolver::ResolveError,
+    Error, DNS_BUFFER_SIZE,
+};
+
+use super::Zone;
+
+/// Returns whether `serial` is newer than `than`, according to the serial number arithmetic of
+/// [RFC 1982].
+///
+/// [RFC 1982]: https://datatracker.ietf.org/doc/html/rfc1982
+pub fn serial_newer(serial: u32, than: u32) -> bool {
+    let diff = serial.wrapping_sub(than);
+    diff != 0 && diff < 1 << 31
+}
+
+/// Queries the `SOA` serial number of the zone at `apex` from the server at `primary`.
+///
+/// Returns [`Error::Timeout`] if the server doesn't answer within `timeout`, and
+/// [`Error::Resolve`] if it responds with an error.
+pub fn query_serial(
+    primary: SocketAddr,
+    apex: &DomainName,
+    timeout: Duration,
+) -> Result<u32, Error> {
+    let bind_addr: SocketAddr = match primary {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let sock = UdpSocket::bind(bind_addr)?;
+    sock.set_read_timeout(Some(timeout))?;
+    sock.connect(primary)?;
+
+    let id = Header::random_id();
+    let mut buf = [0; DNS_BUFFER_SIZE];
+    let query = encode_query(&mut buf, id, apex, QType::SOA);
+    sock.send(query)?;
+    loop {
+        let len = match sock.recv(&mut buf) {
+            Ok(len) => len,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Err(Error::Timeout),
+            Err(e) => return Err(e.into()),
+        };
+        let msg = &buf[..len];
+        log::trace!("recv from {}: {}", primary, Hex(msg));
+        let dec = MessageDecoder::new(msg)?;
+        if dec.header().id() != id || !dec.header().is_response() {
+            continue;
+        }
+        if let Some(e) = ResolveError::from_response(msg, primary) {
+            return Err(e.into());
+        }
+        let mut dec = dec.answers()?;
+        for rr in dec.iter() {
+            let rr = rr?;
+            if let Some(Ok(Record::SOA(soa))) = rr.as_enum() {
+                return Ok(soa.serial());
+            }
+        }
+        return Err(Error::InvalidValue);
+    }
+}
+
+/// Fetches the zone at `apex` from the server at `primary` via a full zone transfer (`AXFR`).
+///
+/// `timeout` applies to connecting and to every individual read. Returns [`Error::Resolve`] if
+/// the primary refuses the transfer, and [`Error::InvalidValue`] if the transfer is malformed (for
+/// example, if it doesn't start and end with the zone's `SOA` record).
+pub fn transfer_zone(
+    primary: SocketAddr,
+    apex: &DomainName,
+    timeout: Duration,
+) -> Result<Zone, Error> {
+    let mut conn = TcpStream::connect_timeout(&primary, timeout)?;
+    conn.set_read_timeout(Some(timeout))?;
+
+    let id = Header::random_id();
+    let mut buf = vec![0; usize::from(u16::MAX)];
+    let query = encode_query(&mut buf, id, apex, QType::AXFR);
+    let mut msg = (query.len() as u16).to_be_bytes().to_vec();
+    msg.extend_from_slice(query);
+    conn.write_all(&msg)?;
+
+    let mut zone = Zone::new(apex.clone());
+    let mut soa_count = 0;
+    while soa_count < 2 {
+        let mut len = [0; 2];
+        conn.read_exact(&mut len)?;
+        let msg = &mut buf[..usize::from(u16::from_be_bytes(len))];
+        conn.read_exact(msg)?;
+        log::trace!("TCP recv from {}: {}", primary, Hex(&*msg));
+
+        let dec = MessageDecoder::new(msg)?;
+        if dec.header().id() != id {
+            return Err(Error::InvalidValue);
+        }
+        if let Some(e) = ResolveError::from_response(msg, primary) {
+            return Err(e.into());
+        }
+        let mut dec = dec.answers()?;
+        for rr in dec.iter() {
+            let rr = rr?;
+            // The first record is the SOA, and the transfer ends with the same SOA repeated.
+            if rr.type_() == Type::SOA && rr.name().eq_ignore_ascii_case(apex) {
+                soa_count += 1;
+                if soa_count == 2 {
+                    break;
+                }
+            } else if soa_count == 0 {
+                return Err(Error::InvalidValue);
+            }
+            let Some(record) = rr.as_enum() else {
+                log::debug!("skipping unsupported record in transfer: {}", rr.type_());
+                continue;
+            };
+            zone.add(rr.name().clone(), rr.ttl(), record?.into_owned())?;
+        }
+    }
+    Ok(zone)
+}
+
+fn encode_query<'a>(buf: &'a mut [u8], id: u16, apex: &DomainName, qtype: QType) -> &'a [u8] {
+    let mut header = Header::default();
+    header.set_id(id);
+    let mut enc = MessageEncoder::new(buf);
+    enc.set_header(header);
+    enc.question(Question::new(apex).ty(qtype));
+    let len = enc.finish().unwrap();
+    &buf[..len]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serial_arithmetic() {
+        assert!(serial_newer(2, 1));
+        assert!(!serial_newer(1, 1));
+        assert!(!serial_newer(1, 2));
+        assert!(serial_newer(0, u32::MAX));
+        assert!(serial_newer(5, u32::MAX - 5));
+        assert!(!serial_newer(1 << 31, 0));
+    }
+}
@@ -1,12 +1,9 @@
 //! Service discovery and advertising.
 
-use std::{
-    collections::{btree_map::Entry, BTreeMap},
-    fmt,
-    str::FromStr,
-};
+use std::{cmp::Reverse, fmt, net::IpAddr, str::FromStr};
 
 use crate::{
+    label,
     name::{DomainName, Label},
     packet::records::{PTR, SRV, TXT},
     Error,
@@ -14,6 +11,7 @@ use crate::{
 
 pub mod advertising;
 pub mod discovery;
+pub mod lease;
 
 /// Transport protocol used by an advertised service (`_tcp` or `_udp`).
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -33,7 +31,10 @@ impl ServiceTransport {
     }
 
     pub fn to_label(&self) -> Label {
-        Label::new(self.as_str())
+        match self {
+            ServiceTransport::TCP => label!("_tcp"),
+            ServiceTransport::Other => label!("_udp"),
+        }
     }
 }
 
@@ -190,44 +191,150 @@ impl ServiceInstance {
     }
 }
 
+/// Displays the instance name as text, without escaping (see [`Label::to_string_lossy`]).
+///
+/// Use the [`Debug`](fmt::Debug) impl to see the raw bytes of the instance name.
 impl fmt::Display for ServiceInstance {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}.{}", self.instance_name, self.service,)
+        write!(
+            f,
+            "{}.{}",
+            self.instance_name.to_string_lossy(),
+            self.service
+        )
     }
 }
 
 impl fmt::Debug for ServiceInstance {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Display::fmt(self, f)
+        write!(f, "{}.{}", self.instance_name, self.service)
     }
 }
 
 /// Describes how a [`ServiceInstance`] can be reached, and supplies service metadata.
+///
+/// A service instance can be offered by several [`ServiceTarget`]s (one per [`SRV`] record), which
+/// differ in their priority and weight. There is always at least one target.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct InstanceDetails {
-    host: DomainName,
-    port: u16,
+    targets: Vec<ServiceTarget>,
     txt: TxtRecords,
 }
 
 impl InstanceDetails {
     pub fn new(host: DomainName, port: u16) -> Self {
+        Self::from_target(ServiceTarget::new(host, port))
+    }
+
+    /// Creates [`InstanceDetails`] with a single [`ServiceTarget`] and no TXT records.
+    pub fn from_target(target: ServiceTarget) -> Self {
         Self {
-            host,
-            port,
+            targets: vec![target],
             txt: TxtRecords::new(),
         }
     }
 
     /// Parses an [`SRV`] record containing instance details.
     pub fn from_srv(srv: &SRV<'_>) -> Result<Self, Error> {
-        Ok(Self {
+        Ok(Self::from_target(ServiceTarget::from_srv(srv)))
+    }
+
+    /// Returns the [`DomainName`] of the [best target][Self::best_target].
+    #[inline]
+    pub fn host(&self) -> &DomainName {
+        self.best_target().host()
+    }
+
+    /// Returns the port of the [best target][Self::best_target].
+    #[inline]
+    pub fn port(&self) -> u16 {
+        self.best_target().port()
+    }
+
+    /// Returns all targets offering this service instance, in the order they were added.
+    #[inline]
+    pub fn targets(&self) -> &[ServiceTarget] {
+        &self.targets
+    }
+
+    #[inline]
+    pub fn targets_mut(&mut self) -> &mut [ServiceTarget] {
+        &mut self.targets
+    }
+
+    /// Adds another target offering this service instance.
+    pub fn add_target(&mut self, target: ServiceTarget) {
+        self.targets.push(target);
+    }
+
+    /// Returns the preferred target to connect to.
+    ///
+    /// This is the target with the lowest priority value. Among targets with the same priority,
+    /// the one with the highest weight is chosen (RFC 2782 calls for a weighted random choice
+    /// instead; callers wanting load balancing can implement that via [`Self::targets`]).
+    pub fn best_target(&self) -> &ServiceTarget {
+        self.targets
+            .iter()
+            .min_by_key(|t| t.preference())
+            .expect("`InstanceDetails` must have at least one target")
+    }
+
+    /// Returns all targets, ordered from most to least preferred.
+    ///
+    /// The order is the one used by [`Self::best_target`], so the first target is the best one,
+    /// and the others are the ones to fall back to if it can't be reached.
+    pub fn targets_by_preference(&self) -> Vec<&ServiceTarget> {
+        let mut targets = self.targets.iter().collect::<Vec<_>>();
+        targets.sort_by_key(|t| t.preference());
+        targets
+    }
+
+    #[inline]
+    pub fn txt_records(&self) -> &TxtRecords {
+        &self.txt
+    }
+
+    #[inline]
+    pub fn txt_records_mut(&mut self) -> &mut TxtRecords {
+        &mut self.txt
+    }
+}
+
+/// A host and port offering a service instance, as described by an [`SRV`] record.
+///
+/// Also stores the addresses of the host, if they are known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceTarget {
+    host: DomainName,
+    port: u16,
+    priority: u16,
+    weight: u16,
+    addrs: Vec<IpAddr>,
+}
+
+impl ServiceTarget {
+    /// Creates a [`ServiceTarget`] with priority and weight 0 and no known addresses.
+    pub fn new(host: DomainName, port: u16) -> Self {
+        Self {
+            host,
+            port,
+            priority: 0,
+            weight: 0,
+            addrs: Vec::new(),
+        }
+    }
+
+    pub fn from_srv(srv: &SRV<'_>) -> Self {
+        Self {
             host: srv.target().clone(),
             port: srv.port(),
-            txt: TxtRecords::new(),
-        })
+            priority: srv.priority(),
+            weight: srv.weight(),
+            addrs: Vec::new(),
+        }
     }
 
-    /// Returns the [`DomainName`] on which the service can be found.
+    /// Returns the [`DomainName`] of the host offering the service.
     #[inline]
     pub fn host(&self) -> &DomainName {
         &self.host
@@ -239,42 +346,100 @@ impl InstanceDetails {
         self.port
     }
 
+    /// Returns the priority of this target. Lower values are preferred.
     #[inline]
-    pub fn txt_records(&self) -> &TxtRecords {
-        &self.txt
+    pub fn priority(&self) -> u16 {
+        self.priority
     }
 
     #[inline]
-    pub fn txt_records_mut(&mut self) -> &mut TxtRecords {
-        &mut self.txt
+    pub fn set_priority(&mut self, priority: u16) {
+        self.priority = priority;
+    }
+
+    /// Returns the relative weight of this target among targets of the same priority.
+    #[inline]
+    pub fn weight(&self) -> u16 {
+        self.weight
+    }
+
+    #[inline]
+    pub fn set_weight(&mut self, weight: u16) {
+        self.weight = weight;
+    }
+
+    /// Returns the known addresses of [`ServiceTarget::host`].
+    ///
+    /// This may be empty if no addresses were included in the response that described this
+    /// target, in which case the host name has to be resolved separately.
+    #[inline]
+    pub fn addrs(&self) -> &[IpAddr] {
+        &self.addrs
+    }
+
+    /// Adds a known address of the host, unless it is already present.
+    pub fn add_addr(&mut self, addr: IpAddr) {
+        if !self.addrs.contains(&addr) {
+            self.addrs.push(addr);
+        }
+    }
+
+    /// Returns the [`SRV`] record describing this target.
+    pub fn to_srv(&self) -> SRV<'static> {
+        SRV::new(self.priority, self.weight, self.port, self.host.clone())
+    }
+
+    /// Returns a sort key that orders more preferred targets first.
+    fn preference(&self) -> (u16, Reverse<u16>) {
+        (self.priority, Reverse(self.weight))
     }
 }
 
 /// List of `key=value` records stored in a DNS-SD TXT record of a service instance.
-#[derive(Debug)]
+///
+/// Entries are kept in the order they appear in the [`TXT`] record (or were added in), so that
+/// converting a [`TXT`] record to [`TxtRecords`] and back yields the same record. Keys are compared
+/// case-insensitively.
+///
+/// A key may appear more than once. RFC 6763 specifies that only the first occurrence of a key is
+/// meaningful, so [`TxtRecords::get`] and friends return the first one, but all entries are kept
+/// and can be inspected via [`TxtRecords::iter`] and [`TxtRecords::get_all`].
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TxtRecords {
-    // keys are lowercased
-    // FIXME this should keep the original order
-    map: BTreeMap<String, TxtRecord>,
+    entries: Vec<TxtRecord>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct TxtRecord {
     key: String,
     value: Option<Vec<u8>>,
 }
 
+impl TxtRecord {
+    fn value(&self) -> TxtRecordValue<'_> {
+        match &self.value {
+            Some(v) => TxtRecordValue::Value(v),
+            None => TxtRecordValue::NoValue,
+        }
+    }
+}
+
 impl TxtRecords {
     pub fn new() -> Self {
         Self {
-            map: BTreeMap::new(),
+            entries: Vec::new(),
         }
     }
 
     pub fn from_txt(txt: &TXT<'_>) -> Self {
-        let mut map = BTreeMap::new();
+        let mut entries = Vec::new();
 
         for entry in txt.entries() {
+            if entry.is_empty() {
+                // An empty TXT record consists of a single empty entry (RFC 6763, section 6.1).
+                continue;
+            }
+
             let mut split = entry.splitn(2, |&b| b == b'=');
             let key = split.next().unwrap();
             let key = match String::from_utf8(key.to_vec()) {
@@ -284,76 +449,148 @@ impl TxtRecords {
                     continue;
                 }
             };
-            let entry = map.entry(key.to_ascii_lowercase());
-            if let Entry::Occupied(_) = entry {
-                log::debug!("TXT key '{}' already occupied, ignoring", entry.key());
-            }
 
-            match split.next() {
-                Some(value) => {
-                    entry.or_insert(TxtRecord {
-                        key,
-                        value: Some(value.to_vec()),
-                    });
-                }
-                None => {
-                    // boolean flag
-                    entry.or_insert(TxtRecord { key, value: None });
-                }
-            }
+            entries.push(TxtRecord {
+                key,
+                // no value = boolean flag
+                value: split.next().map(<[u8]>::to_vec),
+            });
         }
 
-        Self { map }
+        Self { entries }
     }
 
     /// Adds a TXT record with no value.
     pub fn add_flag(&mut self, key: String) {
-        self.map
-            .insert(key.to_ascii_lowercase(), TxtRecord { key, value: None });
+        self.entries.push(TxtRecord { key, value: None });
+    }
+
+    /// Adds a TXT record with a (possibly empty) value.
+    pub fn add_value(&mut self, key: String, value: impl Into<Vec<u8>>) {
+        self.entries.push(TxtRecord {
+            key,
+            value: Some(value.into()),
+        });
+    }
+
+    /// Removes all records with the given key.
+    ///
+    /// Returns the number of removed records.
+    pub fn remove(&mut self, key: &str) -> usize {
+        let len = self.entries.len();
+        self.entries
+            .retain(|rec| !rec.key.eq_ignore_ascii_case(key));
+        len - self.entries.len()
     }
 
-    /// Returns an iterator over all key-value pairs.
+    /// Returns an iterator over all key-value pairs, in order.
     pub fn iter(&self) -> impl Iterator<Item = (&str, TxtRecordValue<'_>)> {
-        self.map.iter().map(|(_, rec)| match &rec.value {
-            Some(v) => (rec.key.as_str(), TxtRecordValue::Value(&v)),
-            None => (rec.key.as_str(), TxtRecordValue::NoValue),
-        })
+        self.entries
+            .iter()
+            .map(|rec| (rec.key.as_str(), rec.value()))
     }
 
+    /// Returns the value of the first record with the given key.
     pub fn get(&self, key: &str) -> Option<TxtRecordValue<'_>> {
-        self.map
-            .get(&key.to_ascii_lowercase())
-            .map(|rec| match &rec.value {
-                Some(v) => TxtRecordValue::Value(v),
-                None => TxtRecordValue::NoValue,
-            })
+        self.entries
+            .iter()
+            .find(|rec| rec.key.eq_ignore_ascii_case(key))
+            .map(TxtRecord::value)
+    }
+
+    /// Returns an iterator over the values of all records with the given key, in order.
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = TxtRecordValue<'a>> + 'a {
+        self.entries
+            .iter()
+            .filter(move |rec| rec.key.eq_ignore_ascii_case(key))
+            .map(TxtRecord::value)
+    }
+
+    /// Returns the value of the first record with the given key as a UTF-8 string.
+    ///
+    /// Returns [`None`] if there is no such record, if it has no value, or if the value is not
+    /// valid UTF-8.
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        match self.get(key)? {
+            TxtRecordValue::NoValue => None,
+            TxtRecordValue::Value(v) => std::str::from_utf8(v).ok(),
+        }
+    }
+
+    /// Parses the value of the first record with the given key via [`FromStr`].
+    ///
+    /// Returns [`None`] under the same conditions as [`TxtRecords::get_str`], and `Some(Err(_))`
+    /// if the value could not be parsed.
+    pub fn get_parsed<T: FromStr>(&self, key: &str) -> Option<Result<T, T::Err>> {
+        self.get_str(key).map(str::parse)
+    }
+
+    /// Returns the number of records.
+    pub fn len(&self) -> usize {
+        self.entries.len()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.map.is_empty()
+        self.entries.is_empty()
+    }
+
+    /// Encodes the key-value pairs as a DNS-SD [`TXT`] record.
+    ///
+    /// If there are no records, the result contains a single empty entry, as required by RFC 6763.
+    pub fn to_txt(&self) -> TXT<'static> {
+        if self.is_empty() {
+            // A TXT record is required by RFC 6763, even if it just contains an empty entry.
+            return TXT::new([b""]);
+        }
+
+        TXT::new(self.iter().map(|(k, v)| match v {
+            TxtRecordValue::NoValue => k.as_bytes().to_vec(),
+            TxtRecordValue::Value(v) => {
+                let mut kv = k.as_bytes().to_vec();
+                kv.push(b'=');
+                kv.extend_from_slice(v);
+                kv
+            }
+        }))
+    }
+}
+
+impl From<&TXT<'_>> for TxtRecords {
+    fn from(txt: &TXT<'_>) -> Self {
+        Self::from_txt(txt)
+    }
+}
+
+impl From<&TxtRecords> for TXT<'static> {
+    fn from(txt: &TxtRecords) -> Self {
+        txt.to_txt()
     }
 }
 
 impl fmt::Display for TxtRecords {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for (i, rec) in self.map.values().enumerate() {
+        for (i, rec) in self.entries.iter().enumerate() {
             if i != 0 {
                 f.write_str(" ")?;
             }
 
             f.write_str(&rec.key)?;
-            match &rec.value {
-                Some(v) => {
-                    f.write_str("=")?;
-                    v.escape_ascii().fmt(f)?;
-                }
-                None => {}
+            if let Some(v) = &rec.value {
+                f.write_str("=")?;
+                v.escape_ascii().fmt(f)?;
             }
         }
         Ok(())
     }
 }
 
+impl Default for TxtRecords {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum TxtRecordValue<'a> {
     NoValue,
     Value(&'a [u8]),
@@ -375,3 +612,65 @@ impl<'a> fmt::Debug for TxtRecordValue<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_instance() {
+        let instance = ServiceInstance::new(
+            Label::new(b"Anne's AirPort \xe2\x80\x94 2.4 GHz \xff"),
+            label!("_airport"),
+            ServiceTransport::TCP,
+        );
+        assert_eq!(
+            instance.to_string(),
+            "Anne's AirPort \u{2014} 2.4 GHz \u{fffd}._airport._tcp"
+        );
+        assert_eq!(
+            format!("{:?}", instance),
+            r"Anne\'s AirPort \xe2\x80\x94 2.4 GHz \xff._airport._tcp"
+        );
+    }
+
+    #[test]
+    fn txt_roundtrip() {
+        let txt = TXT::new([
+            &b"txtvers=1"[..],
+            b"Flag",
+            b"path=/a",
+            b"PATH=/b",
+            b"empty=",
+        ]);
+        let records = TxtRecords::from_txt(&txt);
+        assert_eq!(records.len(), 5);
+        assert_eq!(records.to_txt(), txt);
+        assert_eq!(records.to_string(), "txtvers=1 Flag path=/a PATH=/b empty=");
+
+        assert_eq!(records.get_str("path"), Some("/a"));
+        assert_eq!(records.get_all("Path").count(), 2);
+        assert_eq!(records.get("flag"), Some(TxtRecordValue::NoValue));
+        assert_eq!(records.get_str("flag"), None);
+        assert_eq!(records.get_str("empty"), Some(""));
+        assert_eq!(records.get_parsed::<u8>("txtvers"), Some(Ok(1)));
+        assert!(records.get_parsed::<u8>("path").unwrap().is_err());
+        assert_eq!(records.get("missing"), None);
+    }
+
+    #[test]
+    fn txt_edit() {
+        let mut records = TxtRecords::from_txt(&TXT::new([b""]));
+        assert!(records.is_empty());
+        assert_eq!(records.to_txt(), TXT::new([b""]));
+
+        records.add_value("b".into(), "2");
+        records.add_flag("a".into());
+        records.add_value("B".into(), "3");
+        assert_eq!(records.to_txt(), TXT::new([&b"b=2"[..], b"a", b"B=3"]));
+
+        assert_eq!(records.remove("b"), 2);
+        assert_eq!(records.remove("b"), 0);
+        assert_eq!(records.to_txt(), TXT::new([b"a"]));
+    }
+}
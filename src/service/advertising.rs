@@ -1,24 +1,163 @@
 //! Service advertising.
 
 use std::{
-    io,
-    net::{IpAddr, Ipv4Addr, SocketAddrV4, UdpSocket},
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    thread,
+    time::{Duration, Instant},
 };
 
 use crate::{
+    acl::Acl,
+    clock::{Backoff, Clock, SystemClock},
+    domain, label,
     name::{DomainName, Label},
     packet::{
-        decoder::MessageDecoder,
-        encoder::{MessageEncoder, ResourceRecord},
-        records::{Record, A, AAAA, PTR, SRV, TXT},
-        Class, Header, Opcode, RCode,
+        decoder::{self, MessageDecoder},
+        encoder::{MessageEncoder, Question, ResourceRecord},
+        records::{Record, A, AAAA, HINFO, PTR, SRV},
+        section, Class, Header, Opcode, QType, RCode, Type,
     },
+    server::{Identity, RFC8482_TTL},
+    Error,
 };
-use socket2::{Domain, Protocol, Socket, Type};
 
-use crate::MDNS_BUFFER_SIZE;
+use crate::{checked_message_size, MDNS_BUFFER_SIZE};
 
-use super::{InstanceDetails, ServiceInstance, TxtRecordValue};
+use super::{InstanceDetails, ServiceInstance};
+
+const MDNS_PORT: u16 = 5353;
+
+/// Minimum time between two multicasts of the same record ([RFC 6762, section 6]).
+///
+/// [RFC 6762, section 6]: https://datatracker.ietf.org/doc/html/rfc6762#section-6
+const MIN_MULTICAST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where multicast responses are sent.
+const MDNS_DESTINATION: SocketAddr =
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(224, 0, 0, 251), MDNS_PORT));
+
+/// Determines whether an [`Advertiser`] responds to mDNS queries via unicast or multicast.
+///
+/// Unicast responses cause less traffic for other hosts on the network, while multicast responses
+/// keep their caches up to date and let them notice conflicts. Regardless of the mode, records
+/// that haven't been multicast within a quarter of their TTL are always multicast, as required by
+/// [RFC 6762, section 5.4].
+///
+/// [RFC 6762, section 5.4]: https://datatracker.ietf.org/doc/html/rfc6762#section-5.4
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum ResponseMode {
+    /// Always multicast responses, even if the querier asked for a unicast response.
+    AlwaysMulticast,
+    /// Respond via unicast if every question in the query has the *unicast-response* (`QU`) bit
+    /// set, and via multicast otherwise.
+    ///
+    /// This is the behavior specified by RFC 6762, and the default.
+    #[default]
+    HonorUnicastBit,
+    /// Like [`ResponseMode::HonorUnicastBit`], but also respond via unicast to queries with a
+    /// single question, which typically come from a host looking up one specific record.
+    PreferUnicastWhenSingleton,
+}
+
+/// What an [`Advertiser`] did with an incoming packet.
+///
+/// Passed to the [`PacketHook`] along with the packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PacketDecision {
+    /// The packet was dropped because its source is denied by the [`Acl`].
+    Denied,
+    /// The packet could not be decoded.
+    Malformed,
+    /// The packet is not a standard query (for example, a response sent by another responder),
+    /// and was ignored.
+    Ignored,
+    /// The packet is a query, but this advertiser has no records matching it.
+    Unanswered,
+    /// A response was sent to the given address.
+    Responded(SocketAddr),
+    /// The packet is a response from another host that claims a name owned by this advertiser,
+    /// which was renamed as a result (see [`RenameEvent`]).
+    Conflict,
+}
+
+/// Describes how an [`Advertiser`] renamed a host or service instance after another host claimed
+/// its name.
+///
+/// Passed to the callback registered via [`Advertiser::set_rename_hook`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenameEvent {
+    old_name: DomainName,
+    new_name: DomainName,
+    source: SocketAddr,
+}
+
+impl RenameEvent {
+    /// Returns the name that was advertised before the conflict.
+    #[inline]
+    pub fn old_name(&self) -> &DomainName {
+        &self.old_name
+    }
+
+    /// Returns the name that is advertised now.
+    #[inline]
+    pub fn new_name(&self) -> &DomainName {
+        &self.new_name
+    }
+
+    /// Returns the address of the host that claimed the old name.
+    #[inline]
+    pub fn source(&self) -> SocketAddr {
+        self.source
+    }
+}
+
+/// A callback that is invoked when an [`Advertiser`] renames a host or service instance.
+///
+/// This can be used to show the name that is actually advertised in a user interface, or to
+/// forward the events to a channel. It is implemented for all closures with a matching signature,
+/// and registered via [`Advertiser::set_rename_hook`].
+pub trait RenameHook: Send {
+    /// Called after a name was changed.
+    fn on_rename(&mut self, event: &RenameEvent);
+}
+
+impl<F: FnMut(&RenameEvent) + Send> RenameHook for F {
+    fn on_rename(&mut self, event: &RenameEvent) {
+        self(event)
+    }
+}
+
+/// A hook that is called for every packet handled by an [`Advertiser`].
+///
+/// This can be used for audit logging, or to feed an intrusion detection system, without having to
+/// reimplement [`Advertiser::handle_query`]. It is implemented for all closures with a matching
+/// signature, and registered via [`Advertiser::set_packet_hook`].
+pub trait PacketHook: Send {
+    /// Called after `packet` from `source` has been handled.
+    ///
+    /// `header` is [`None`] if the packet is too short to contain a DNS header.
+    fn on_packet(
+        &mut self,
+        source: SocketAddr,
+        packet: &[u8],
+        header: Option<&Header>,
+        decision: PacketDecision,
+    );
+}
+
+impl<F: FnMut(SocketAddr, &[u8], Option<&Header>, PacketDecision) + Send> PacketHook for F {
+    fn on_packet(
+        &mut self,
+        source: SocketAddr,
+        packet: &[u8],
+        header: Option<&Header>,
+        decision: PacketDecision,
+    ) {
+        self(source, packet, header, decision)
+    }
+}
 
 pub struct SyncAdvertiser {
     adv: Advertiser,
@@ -29,7 +168,7 @@ impl SyncAdvertiser {
     ///
     /// `hostname` should be different from the system host name, to avoid conflicts with other
     /// installed mDNS responders.
-    pub fn new(hostname: Label, addr: IpAddr) -> io::Result<Self> {
+    pub fn new(hostname: Label, addr: IpAddr) -> Result<Self, Error> {
         Ok(Self {
             adv: Advertiser::new(hostname, addr)?,
         })
@@ -43,27 +182,176 @@ impl SyncAdvertiser {
         self.adv.add_instance(instance, details);
     }
 
+    /// Sets whether the records of type `ty` owned by `name` are shared with other hosts.
+    ///
+    /// See [`Advertiser::set_shared`].
+    pub fn set_shared(&mut self, name: &DomainName, ty: Type, shared: bool) -> bool {
+        self.adv.set_shared(name, ty, shared)
+    }
+
+    /// Sets the largest mDNS message to receive or send, in bytes.
+    ///
+    /// See [`Advertiser::set_max_message_size`].
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.adv.set_max_message_size(size);
+    }
+
+    /// Sets the IP TTL of outgoing multicast packets.
+    ///
+    /// See [`Advertiser::set_multicast_ttl`].
+    pub fn set_multicast_ttl(&mut self, ttl: u32) {
+        self.adv.set_multicast_ttl(ttl);
+    }
+
+    /// Sets whether outgoing multicast packets are looped back to the local machine.
+    ///
+    /// See [`Advertiser::set_multicast_loopback`].
+    pub fn set_multicast_loopback(&mut self, loopback: bool) {
+        self.adv.set_multicast_loopback(loopback);
+    }
+
+    /// Sets the interface to listen and respond on.
+    ///
+    /// See [`Advertiser::set_interface`].
+    pub fn set_interface(&mut self, interface: Ipv4Addr) {
+        self.adv.set_interface(interface);
+    }
+
+    /// Restricts which hosts get answers.
+    ///
+    /// See [`Advertiser::set_acl`].
+    pub fn set_acl(&mut self, acl: Acl) {
+        self.adv.set_acl(acl);
+    }
+
+    /// Sets whether responses are sent via unicast or multicast.
+    ///
+    /// See [`Advertiser::set_response_mode`].
+    pub fn set_response_mode(&mut self, mode: ResponseMode) {
+        self.adv.set_response_mode(mode);
+    }
+
+    /// Sets the strings to answer `CH TXT` identification queries with.
+    ///
+    /// See [`Advertiser::set_identity`].
+    pub fn set_identity(&mut self, identity: Identity) {
+        self.adv.set_identity(identity);
+    }
+
+    /// Sets whether legacy unicast queries for type `ANY` are answered minimally.
+    ///
+    /// See [`Advertiser::set_minimal_any`].
+    pub fn set_minimal_any(&mut self, minimal_any: bool) {
+        self.adv.set_minimal_any(minimal_any);
+    }
+
+    /// Sets a hook that is called for every received packet.
+    ///
+    /// See [`Advertiser::set_packet_hook`].
+    pub fn set_packet_hook(&mut self, hook: impl PacketHook + 'static) {
+        self.adv.set_packet_hook(hook);
+    }
+
+    /// Sets a callback that is invoked whenever a name is changed because of a conflict.
+    ///
+    /// See [`Advertiser::set_rename_hook`].
+    pub fn set_rename_hook(&mut self, hook: impl RenameHook + 'static) {
+        self.adv.set_rename_hook(hook);
+    }
+
+    /// Returns the host name that is currently advertised.
+    ///
+    /// See [`Advertiser::current_hostname`].
+    pub fn current_hostname(&self) -> &Label {
+        self.adv.current_hostname()
+    }
+
+    /// Returns the name under which `instance` is currently advertised.
+    ///
+    /// See [`Advertiser::current_instance_name`].
+    pub fn current_instance_name(&self, instance: &ServiceInstance) -> Option<&Label> {
+        self.adv.current_instance_name(instance)
+    }
+
+    /// Multicasts an announcement of all unique records right away.
+    ///
+    /// Returns whether an announcement was sent. See [`Advertiser::announce_now`].
+    pub fn announce_now(&mut self) -> Result<bool, Error> {
+        let sock = self.adv.create_socket()?;
+        match self.adv.announce_now()? {
+            Some(announcement) => {
+                sock.send_to(announcement, MDNS_DESTINATION)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
     /// Starts listening for and responding to queries.
     ///
     /// This method will block forever and never return, except when an error occurs.
-    pub fn listen_blocking(&mut self) -> io::Result<()> {
-        let sock = self.adv.create_socket()?;
-        let mut recv_buf = [0; MDNS_BUFFER_SIZE];
+    ///
+    /// Transient socket errors (see [`Error::is_transient`]), like those caused by the network
+    /// interface going down, are logged, and the socket is recreated after an increasing delay.
+    pub fn listen_blocking(&mut self) -> Result<(), Error> {
+        let mut sock = self.adv.create_socket()?;
+        let mut backoff = Backoff::default();
+        let mut recv_buf = vec![0; self.adv.max_message_size()];
         loop {
-            let (len, addr) = sock.recv_from(&mut recv_buf)?;
+            let (len, addr) = match sock.recv_from(&mut recv_buf) {
+                Ok(res) => res,
+                Err(e) => {
+                    sock = self.recover(e.into(), &mut backoff)?;
+                    continue;
+                }
+            };
+            backoff.reset();
             let packet = &recv_buf[..len];
 
             log::trace!("raw recv from {}: {:x?}", addr, packet);
 
-            match self.adv.handle_packet(packet) {
-                Ok(Some(resp)) => {
-                    sock.send_to(resp, addr)?;
+            match self.adv.handle_query(packet, addr) {
+                Ok(Some((resp, dest))) => {
+                    if let Err(e) = sock.send_to(resp, dest) {
+                        sock = self.recover(e.into(), &mut backoff)?;
+                    }
                 }
                 Ok(None) => {}
                 Err(e) => {
                     log::debug!("failed to handle packet: {}", e);
                 }
             }
+
+            // Announce names that were changed due to a conflict.
+            match self.adv.build_announcement() {
+                Ok(Some(announcement)) => {
+                    if let Err(e) = sock.send_to(announcement, MDNS_DESTINATION) {
+                        sock = self.recover(e.into(), &mut backoff)?;
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    log::warn!("failed to build announcement: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Returns a new socket if `error` is transient, and `error` otherwise.
+    fn recover(&self, error: Error, backoff: &mut Backoff) -> Result<UdpSocket, Error> {
+        if !error.is_transient() {
+            return Err(error);
+        }
+        log::warn!("mDNS socket error: {}; recreating socket", error);
+        loop {
+            thread::sleep(backoff.next_delay());
+            match self.adv.create_socket() {
+                Ok(sock) => return Ok(sock),
+                Err(e) if e.is_transient() => {
+                    log::warn!("failed to recreate mDNS socket: {}; retrying", e);
+                }
+                Err(e) => return Err(e),
+            }
         }
     }
 }
@@ -74,7 +362,21 @@ impl SyncAdvertiser {
 pub struct Advertiser {
     discovery_domain: DomainName,
     db: RecordDb,
+    /// Host names added via `new` and `add_name`, as currently advertised.
+    hostnames: Vec<Label>,
+    /// Service instances as added via `add_instance`, and as currently advertised.
+    instances: Vec<(ServiceInstance, ServiceInstance)>,
     response_buf: Vec<u8>,
+    multicast_ttl: u32,
+    multicast_loopback: bool,
+    interface: Ipv4Addr,
+    acl: Acl,
+    response_mode: ResponseMode,
+    identity: Identity,
+    minimal_any: bool,
+    packet_hook: Option<Box<dyn PacketHook>>,
+    rename_hook: Option<Box<dyn RenameHook>>,
+    clock: Box<dyn Clock>,
 }
 
 impl Advertiser {
@@ -82,20 +384,83 @@ impl Advertiser {
     ///
     /// `hostname` should be different from the system host name, to avoid conflicts with other
     /// installed mDNS responders.
-    pub fn new(hostname: Label, addr: IpAddr) -> io::Result<Self> {
+    pub fn new(hostname: Label, addr: IpAddr) -> Result<Self, Error> {
         let mut this = Self {
-            discovery_domain: DomainName::from_str("_services._dns-sd._udp.local.").unwrap(),
+            discovery_domain: domain!("_services._dns-sd._udp.local."),
             db: RecordDb::new(),
+            hostnames: Vec::new(),
+            instances: Vec::new(),
             response_buf: vec![0; MDNS_BUFFER_SIZE],
+            // RFC 6762 recommends sending all mDNS packets with an IP TTL of 255.
+            multicast_ttl: 255,
+            multicast_loopback: true,
+            interface: Ipv4Addr::UNSPECIFIED,
+            acl: Acl::new(),
+            response_mode: ResponseMode::default(),
+            identity: Identity::new(),
+            minimal_any: false,
+            packet_hook: None,
+            rename_hook: None,
+            clock: Box::new(SystemClock),
         };
         this.add_name(hostname, addr);
         Ok(this)
     }
 
+    /// Sets the largest mDNS message to receive or send, in bytes.
+    ///
+    /// Defaults to [`MDNS_BUFFER_SIZE`]. [`MDNS_MAX_MESSAGE_SIZE`] can be used on networks with
+    /// jumbo frames. Responses that don't fit are truncated.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is smaller than [`DNS_BUFFER_SIZE`] or larger than 65535.
+    ///
+    /// [`MDNS_MAX_MESSAGE_SIZE`]: crate::MDNS_MAX_MESSAGE_SIZE
+    /// [`DNS_BUFFER_SIZE`]: crate::DNS_BUFFER_SIZE
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.response_buf = vec![0; checked_message_size(size)];
+    }
+
+    /// Returns the largest mDNS message to receive or send, in bytes.
+    ///
+    /// Receive buffers passed to [`Advertiser::handle_packet`] should be at least this large.
+    #[inline]
+    pub fn max_message_size(&self) -> usize {
+        self.response_buf.len()
+    }
+
+    /// Sets the IP TTL of outgoing multicast packets sent from sockets created by
+    /// [`Advertiser::create_socket`].
+    ///
+    /// The default is 255, as recommended by RFC 6762.
+    pub fn set_multicast_ttl(&mut self, ttl: u32) {
+        self.multicast_ttl = ttl;
+    }
+
+    /// Sets whether multicast packets sent from sockets created by [`Advertiser::create_socket`]
+    /// are looped back to the local machine.
+    ///
+    /// This is enabled by default, so that other mDNS software on the same host can see the
+    /// advertised services.
+    pub fn set_multicast_loopback(&mut self, loopback: bool) {
+        self.multicast_loopback = loopback;
+    }
+
+    /// Sets the address of the interface that sockets created by [`Advertiser::create_socket`]
+    /// join the mDNS group on and send multicast packets from.
+    ///
+    /// By default ([`Ipv4Addr::UNSPECIFIED`]), the operating system picks an interface.
+    pub fn set_interface(&mut self, interface: Ipv4Addr) {
+        self.interface = interface;
+    }
+
     /// Adds an additional hostname and IP address to resolve.
     pub fn add_name(&mut self, hostname: Label, addr: IpAddr) {
-        let mut host_and_domain = DomainName::from_iter([hostname]);
-        host_and_domain.push_label(Label::new("local"));
+        let host_and_domain = host_domain(&hostname);
+        if !self.hostnames.contains(&hostname) {
+            self.hostnames.push(hostname);
+        }
 
         log::info!("{} <-> {}", addr, host_and_domain);
 
@@ -115,41 +480,25 @@ impl Advertiser {
         let service_domain = DomainName::from_iter([
             instance.service_name(),
             &instance.service_transport().to_label(),
-            &Label::new("local"),
-        ]);
-        let instance_domain = DomainName::from_iter([
-            instance.instance_name(),
-            instance.service_name(),
-            &instance.service_transport().to_label(),
-            &Label::new("local"),
+            &label!("local"),
         ]);
+        let instance_domain = instance_domain(&instance);
+        for target in details.targets() {
+            self.db.entries.push(Entry::new(
+                instance_domain.clone(),
+                Record::SRV(target.to_srv()),
+            ));
+        }
         self.db.entries.push(Entry::new(
             instance_domain.clone(),
-            Record::SRV(SRV::new(0, 0, details.port(), details.host().clone())),
+            Record::TXT(details.txt_records().to_txt()),
         ));
-        let txt = if details.txt_records().is_empty() {
-            // A TXT record is required by RFC 6763, even if it just contains an empty entry.
-            TXT::new([b""])
-        } else {
-            TXT::new(details.txt_records().iter().map(|(k, v)| match v {
-                TxtRecordValue::NoValue => k.as_bytes().to_vec(),
-                TxtRecordValue::Value(v) => {
-                    let mut kv = k.as_bytes().to_vec();
-                    kv.push(b'=');
-                    kv.extend_from_slice(v);
-                    kv
-                }
-            }))
-        };
-        self.db
-            .entries
-            .push(Entry::new(instance_domain.clone(), Record::TXT(txt)));
 
         self.db.entries.push(Entry::new(
             DomainName::from_iter([
                 instance.service_name(),
                 &instance.service_transport().to_label(),
-                &Label::new("local"),
+                &label!("local"),
             ]),
             Record::PTR(PTR::new(instance_domain.clone())),
         ));
@@ -158,6 +507,28 @@ impl Advertiser {
             self.discovery_domain.clone(),
             Record::PTR(PTR::new(service_domain.clone())),
         ));
+
+        self.instances.push((instance.clone(), instance));
+    }
+
+    /// Returns the host name passed to [`Advertiser::new`], as currently advertised.
+    ///
+    /// This differs from the original name if another host claimed it, see
+    /// [`Advertiser::set_rename_hook`].
+    pub fn current_hostname(&self) -> &Label {
+        &self.hostnames[0]
+    }
+
+    /// Returns the name under which `instance` (as passed to [`Advertiser::add_instance`]) is
+    /// currently advertised.
+    ///
+    /// This differs from the original name if another host claimed it, see
+    /// [`Advertiser::set_rename_hook`]. Returns [`None`] if `instance` was never added.
+    pub fn current_instance_name(&self, instance: &ServiceInstance) -> Option<&Label> {
+        self.instances
+            .iter()
+            .find(|(original, _)| original == instance)
+            .map(|(_, current)| current.instance_name())
     }
 
     /// Creates a correctly configured [`UdpSocket`] to listen for mDNS queries to this advertiser.
@@ -166,75 +537,658 @@ impl Advertiser {
     /// listening on the same port.
     ///
     /// When receiving data using the returned [`UdpSocket`], a receive buffer with a size of at
-    /// least [`MDNS_BUFFER_SIZE`] must be used, otherwise incoming mDNS queries may get truncated.
-    pub fn create_socket(&self) -> io::Result<UdpSocket> {
-        let sock = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
-        sock.set_reuse_address(true)?;
-        sock.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 5353).into())?;
+    /// least [`Advertiser::max_message_size`] must be used, otherwise incoming mDNS queries may get
+    /// truncated.
+    ///
+    /// The socket is configured according to [`Advertiser::set_multicast_ttl`],
+    /// [`Advertiser::set_multicast_loopback`] and [`Advertiser::set_interface`].
+    pub fn create_socket(&self) -> Result<UdpSocket, Error> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            use crate::net::{Interface, MulticastSocketBuilder};
+
+            let interface = if self.interface.is_unspecified() {
+                Interface::Default
+            } else {
+                Interface::Addr(self.interface)
+            };
+            Ok(MulticastSocketBuilder::mdns_v4()
+                .interface(interface)
+                .ttl(self.multicast_ttl)
+                .loopback(self.multicast_loopback)
+                .build()?)
+        }
+        // `socket2` doesn't support WebAssembly; fall back to `std` (which will likely fail).
+        #[cfg(target_arch = "wasm32")]
+        {
+            let sock = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 5353))?;
+            sock.join_multicast_v4(&Ipv4Addr::new(224, 0, 0, 251), &self.interface)?;
+            sock.set_multicast_ttl_v4(self.multicast_ttl)?;
+            sock.set_multicast_loop_v4(self.multicast_loopback)?;
+            Ok(sock)
+        }
+    }
+
+    /// Restricts which hosts get answers.
+    ///
+    /// Queries from source addresses that `acl` doesn't allow are ignored. By default, every host
+    /// is answered.
+    pub fn set_acl(&mut self, acl: Acl) {
+        self.acl = acl;
+    }
+
+    /// Sets whether responses are sent via unicast or multicast.
+    ///
+    /// Defaults to [`ResponseMode::HonorUnicastBit`].
+    pub fn set_response_mode(&mut self, mode: ResponseMode) {
+        self.response_mode = mode;
+    }
+
+    /// Sets the strings to answer `CH TXT` identification queries like `version.bind` with.
+    ///
+    /// By default, such queries are ignored, like any other query for records this advertiser
+    /// doesn't have. See [`Identity`] for details.
+    pub fn set_identity(&mut self, identity: Identity) {
+        self.identity = identity;
+    }
 
-        let sock = UdpSocket::from(sock);
-        sock.join_multicast_v4(&"224.0.0.251".parse().unwrap(), &Ipv4Addr::UNSPECIFIED)?;
+    /// Sets whether legacy unicast queries for type `ANY` are answered minimally, as recommended by
+    /// [RFC 8482].
+    ///
+    /// The response then only contains a single `HINFO` record with the CPU set to `RFC8482`,
+    /// instead of every record owned by the queried name. This reduces the potential for
+    /// amplification attacks via queries sent from the internet, if port 5353 is reachable from
+    /// there. Queries from mDNS queriers are always answered in full, since they rely on that to
+    /// detect conflicts. Disabled by default.
+    ///
+    /// [RFC 8482]: https://datatracker.ietf.org/doc/html/rfc8482
+    pub fn set_minimal_any(&mut self, minimal_any: bool) {
+        self.minimal_any = minimal_any;
+    }
 
-        Ok(sock)
+    /// Sets a [`PacketHook`] that is called for every packet passed to
+    /// [`Advertiser::handle_query`], along with what was done with it.
+    ///
+    /// This replaces any previously set hook.
+    pub fn set_packet_hook(&mut self, hook: impl PacketHook + 'static) {
+        self.packet_hook = Some(Box::new(hook));
+    }
+
+    /// Sets a callback that is invoked whenever a host or service instance is renamed because
+    /// another host claimed its name.
+    ///
+    /// When another host sends a response containing a record with the same name and type as one
+    /// of this advertiser's unique records (see [`Advertiser::set_shared`]), but with different
+    /// data, the name is in use by both hosts ([RFC 6762, section 9]). Host names are then renamed
+    /// from `host` to `host-2`, `host-3` and so on, and service instances from `Name` to
+    /// `Name (2)`, `Name (3)` and so on. Records referring to the old name, like `SRV` and `PTR`
+    /// records, are updated along with it, and the change is queued for the next
+    /// [`Advertiser::build_announcement`]. The new names should be probed for before they are
+    /// announced, see [`Advertiser::build_probe`].
+    ///
+    /// The current names can be queried via [`Advertiser::current_hostname`] and
+    /// [`Advertiser::current_instance_name`]. This replaces any previously set callback.
+    ///
+    /// [RFC 6762, section 9]: https://datatracker.ietf.org/doc/html/rfc6762#section-9
+    pub fn set_rename_hook(&mut self, hook: impl RenameHook + 'static) {
+        self.rename_hook = Some(Box::new(hook));
     }
 
-    /// Handles an incoming mDNS packet, and returns a response for it (if any).
+    /// Sets the [`Clock`] used to track when records were last multicast.
+    ///
+    /// By default, the [`SystemClock`] is used.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Box::new(clock);
+    }
+
+    /// Handles an incoming mDNS packet sent from `source`, and returns a response for it (if any).
+    ///
+    /// This ignores where the response should be sent; use [`Advertiser::handle_query`] to honor
+    /// the [`ResponseMode`].
+    pub fn handle_packet(
+        &mut self,
+        packet: &[u8],
+        source: SocketAddr,
+    ) -> Result<Option<&[u8]>, Error> {
+        Ok(self
+            .handle_query(packet, source)?
+            .map(|(response, _)| response))
+    }
+
+    /// Handles an incoming mDNS packet sent from `source`, and returns a response for it (if any),
+    /// along with the address to send it to.
+    ///
+    /// The destination is either `source` (for unicast responses) or the mDNS multicast group, as
+    /// determined by the [`ResponseMode`]. Queries sent from a port other than 5353 come from
+    /// simple resolvers that don't implement mDNS, and are always answered via unicast.
+    ///
+    /// The [`PacketHook`] set via [`Advertiser::set_packet_hook`] is called before this method
+    /// returns, even if the packet is malformed.
     ///
     /// This method does not perform I/O by itself, so it can be used in a *sans-io* fashion to
     /// build an async mDNS advertiser. If that's not needed, [`SyncAdvertiser::listen_blocking`]
     /// can be called instead.
-    pub fn handle_packet(&mut self, packet: &[u8]) -> io::Result<Option<&[u8]>> {
-        let mut dec = MessageDecoder::new(packet)?;
-        if !dec.header().is_query() {
-            return Ok(None);
+    pub fn handle_query(
+        &mut self,
+        packet: &[u8],
+        source: SocketAddr,
+    ) -> Result<Option<(&[u8], SocketAddr)>, Error> {
+        let result = self.decide(packet, source);
+        if let Some(hook) = &mut self.packet_hook {
+            let header = MessageDecoder::new(packet).ok().map(|dec| *dec.header());
+            let decision = match &result {
+                Ok((decision, _)) => *decision,
+                Err(_) => PacketDecision::Malformed,
+            };
+            hook.on_packet(source, packet, header.as_ref(), decision);
+        }
+        match result? {
+            (PacketDecision::Responded(destination), len) => {
+                Ok(Some((&self.response_buf[..len], destination)))
+            }
+            _ => Ok(None),
         }
+    }
+
+    /// Decides what to do with an incoming packet, and encodes the response into
+    /// `self.response_buf`, returning its length.
+    fn decide(
+        &mut self,
+        packet: &[u8],
+        source: SocketAddr,
+    ) -> Result<(PacketDecision, usize), Error> {
+        if !self.acl.is_allowed(source.ip()) {
+            log::trace!("ignoring packet from {} (denied by ACL)", source);
+            return Ok((PacketDecision::Denied, 0));
+        }
+        let mut dec = MessageDecoder::new(packet)?;
         if dec.header().opcode() != Opcode::QUERY {
-            return Ok(None);
+            return Ok((PacketDecision::Ignored, 0));
         }
         if dec.header().rcode() != RCode::NO_ERROR {
-            return Ok(None);
+            return Ok((PacketDecision::Ignored, 0));
+        }
+        if !dec.header().is_query() {
+            return Ok((self.handle_response(dec, source)?, 0));
         }
 
-        let mut header = Header::default();
-        header.set_id(dec.header().id());
-        header.set_response(true);
-        header.set_authority(true);
-        let mut enc = MessageEncoder::new(&mut *self.response_buf);
-        enc.set_header(header);
-        let mut enc = enc.answers();
+        trace_span!("handle_query", id = dec.header().id());
 
-        let mut have_relevant_answer = false;
+        let header = *dec.header();
+        let question_count = header.question_count();
+        let mut all_unicast = true;
+        let mut questions = Vec::new();
+        let mut answers: Vec<usize> = Vec::new();
+        // Records that aren't in the database: (name, class, TTL, record).
+        let mut synthesized = Vec::new();
+        let legacy = source.port() != MDNS_PORT;
         for res in dec.iter() {
             let q = res?;
             log::debug!("Q: {q}");
+            all_unicast &= q.prefers_unicast();
 
-            for entry in &self.db.entries {
-                if !q.qclass().matches(entry.class) {
-                    continue;
+            if let Some(Some(record)) = self.identity.answer(&q) {
+                synthesized.push((q.qname().clone(), Class::CH, 0, record));
+            }
+
+            // mDNS queriers rely on getting all records in response to `ANY` queries when probing,
+            // so only legacy queriers get minimal responses.
+            if self.minimal_any && legacy && q.qtype() == QType::ALL {
+                let exists = self.db.entries.iter().any(|entry| {
+                    q.qclass().matches(entry.class) && q.qname().eq_ignore_ascii_case(&entry.name)
+                });
+                if exists {
+                    let hinfo = Record::HINFO(HINFO::rfc8482());
+                    synthesized.push((q.qname().clone(), Class::IN, RFC8482_TTL, hinfo));
                 }
-                if !q.qtype().matches(entry.record.record_type()) {
-                    continue;
+                questions.push(q);
+                continue;
+            }
+
+            // `CNAME` records answer queries for any type. Their targets are looked up as well, so
+            // that queriers get the data they asked for without another round trip.
+            let mut qname = q.qname().clone();
+            for _ in 0..MAX_CNAME_CHAIN {
+                let mut target = None;
+                for (i, entry) in self.db.entries.iter().enumerate() {
+                    if !q.qclass().matches(entry.class) {
+                        continue;
+                    }
+                    if !q.qtype().is_answered_by(entry.record.record_type()) {
+                        continue;
+                    }
+                    if !qname.eq_ignore_ascii_case(&entry.name) {
+                        continue;
+                    }
+
+                    log::debug!("matches: {}", entry.record);
+                    if !answers.contains(&i) {
+                        answers.push(i);
+                    }
+                    match &entry.record {
+                        Record::CNAME(cname) if !q.qtype().matches(Type::CNAME) => {
+                            target = Some(cname.cname().clone());
+                        }
+                        _ => {}
+                    }
                 }
-                if q.qname() != &entry.name {
-                    continue;
+                match target {
+                    Some(target) => qname = target,
+                    None => break,
                 }
+            }
+            questions.push(q);
+        }
 
-                log::debug!("matches: {}", entry.record);
-                have_relevant_answer = true;
-                enc.add_answer(
-                    ResourceRecord::new(&entry.name, &entry.record)
-                        .class(entry.class)
-                        .ttl(entry.ttl),
-                );
+        let answered = !answers.is_empty() || !synthesized.is_empty();
+        trace_event!(answered = answered, "handled query");
+        if !answered {
+            return Ok((PacketDecision::Unanswered, 0));
+        }
+
+        let now = self.clock.now();
+        let unicast = legacy
+            || match self.response_mode {
+                ResponseMode::AlwaysMulticast => false,
+                ResponseMode::HonorUnicastBit => all_unicast,
+                ResponseMode::PreferUnicastWhenSingleton => all_unicast || question_count == 1,
+            };
+        // Records that haven't been multicast within a quarter of their TTL are multicast anyway,
+        // so that other hosts' caches stay up to date (RFC 6762, section 5.4).
+        let stale = answers.iter().any(|&i| {
+            let entry = &self.db.entries[i];
+            entry.last_multicast.is_none_or(|at| {
+                now.saturating_duration_since(at) >= Duration::from_secs(entry.ttl.into()) / 4
+            })
+        });
+        let multicast = !legacy && (!unicast || stale);
+
+        // Only legacy unicast responses repeat the questions (RFC 6762, section 6.7).
+        let questions = if legacy { &questions[..] } else { &[] };
+        let mut enc = MessageEncoder::response_to(&mut self.response_buf, &header, questions);
+        if multicast {
+            enc.modify_header(|h| h.set_id(0));
+        }
+        for &i in &answers {
+            let entry = &self.db.entries[i];
+            // Legacy resolvers would misinterpret the cache-flush bit (RFC 6762, section 6.7).
+            enc.add_answer(
+                ResourceRecord::new(&entry.name, &entry.record)
+                    .class(entry.wire_class(!legacy))
+                    .ttl(entry.ttl),
+            );
+        }
+        for (name, class, ttl, record) in &synthesized {
+            enc.add_answer(ResourceRecord::new(name, record).class(*class).ttl(*ttl));
+        }
+        let len = enc.finish().ok().unwrap_or(self.response_buf.len()); // truncated replies should still get sent
+
+        let destination = if multicast {
+            for &i in &answers {
+                self.db.entries[i].last_multicast = Some(now);
+            }
+            MDNS_DESTINATION
+        } else {
+            source
+        };
+        Ok((PacketDecision::Responded(destination), len))
+    }
+
+    /// Checks the records in a response from another host for conflicts with ours, and renames
+    /// the conflicting names.
+    fn handle_response(
+        &mut self,
+        dec: MessageDecoder<'_, section::Question>,
+        source: SocketAddr,
+    ) -> Result<PacketDecision, Error> {
+        let mut conflicts: Vec<DomainName> = Vec::new();
+        let mut check = |rr: decoder::ResourceRecord<'_>| -> Result<(), Error> {
+            if rr.ttl() == 0 {
+                // Goodbyes don't claim anything.
+                return Ok(());
+            }
+            let mut ours = self
+                .db
+                .entries
+                .iter()
+                .filter(|entry| {
+                    !entry.shared
+                        && entry.class == rr.class()
+                        && entry.matches(rr.name(), rr.type_())
+                })
+                .peekable();
+            if ours.peek().is_none() {
+                return Ok(());
+            }
+            // We only publish supported record types, so unsupported ones can't conflict.
+            let Some(record) = rr.as_enum() else {
+                return Ok(());
+            };
+            let record = record?;
+            if !ours.any(|entry| entry.record == record)
+                && !conflicts
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(rr.name()))
+            {
+                conflicts.push(rr.name().clone());
             }
+            Ok(())
+        };
+        let mut answers = dec.answers()?;
+        while let Some(rr) = answers.next() {
+            check(rr?)?;
+        }
+        let mut additional = answers.additional()?;
+        while let Some(rr) = additional.next() {
+            check(rr?)?;
         }
 
-        if have_relevant_answer {
-            let len = enc.finish().ok().unwrap_or(self.response_buf.len()); // truncated replies should still get sent
-            Ok(Some(&self.response_buf[..len]))
+        let mut renamed = false;
+        for name in conflicts {
+            renamed |= self.rename(&name, source);
+        }
+        Ok(if renamed {
+            PacketDecision::Conflict
         } else {
-            Ok(None)
+            PacketDecision::Ignored
+        })
+    }
+
+    /// Renames the host or service instance named `old` after `source` claimed it.
+    ///
+    /// Returns `false` if `old` is neither, and can't be renamed.
+    fn rename(&mut self, old: &DomainName, source: SocketAddr) -> bool {
+        let owned = |db: &RecordDb, name: &DomainName| {
+            db.entries
+                .iter()
+                .any(|entry| entry.name.eq_ignore_ascii_case(name))
+        };
+        let new = if let Some(i) = self
+            .hostnames
+            .iter()
+            .position(|hostname| host_domain(hostname).eq_ignore_ascii_case(old))
+        {
+            let mut hostname = self.hostnames[i].clone();
+            let new = loop {
+                hostname = next_label(&hostname, "-", "");
+                let name = host_domain(&hostname);
+                if !owned(&self.db, &name) {
+                    break name;
+                }
+            };
+            self.hostnames[i] = hostname;
+            new
+        } else if let Some(i) = self
+            .instances
+            .iter()
+            .position(|(_, current)| instance_domain(current).eq_ignore_ascii_case(old))
+        {
+            let mut instance = self.instances[i].1.clone();
+            let new = loop {
+                instance = ServiceInstance::from_service(
+                    next_label(instance.instance_name(), " (", ")"),
+                    instance.service().clone(),
+                );
+                let name = instance_domain(&instance);
+                if !owned(&self.db, &name) {
+                    break name;
+                }
+            };
+            self.instances[i].1 = instance;
+            new
+        } else {
+            log::warn!("{} claims {}, which cannot be renamed", source, old);
+            return false;
+        };
+
+        log::warn!("{} claims {}, renaming to {}", source, old, new);
+        self.db.rename(old, &new);
+        if let Some(hook) = &mut self.rename_hook {
+            hook.on_rename(&RenameEvent {
+                old_name: old.clone(),
+                new_name: new,
+                source,
+            });
+        }
+        true
+    }
+
+    /// Atomically replaces all records of type `ty` owned by `name` with `records`.
+    ///
+    /// Removed records are announced with a TTL of 0 (a "goodbye"), and new ones are announced
+    /// normally by the next [`Advertiser::build_announcement`]. Records that are present both
+    /// before and after the replacement are left untouched and not re-announced.
+    ///
+    /// Returns whether anything changed, or [`Error::InvalidValue`] if any of `records` isn't of
+    /// type `ty` (in which case nothing is changed).
+    pub fn replace_records(
+        &mut self,
+        name: &DomainName,
+        ty: Type,
+        records: Vec<Record<'static>>,
+    ) -> Result<bool, Error> {
+        if records.iter().any(|record| record.record_type() != ty) {
+            return Err(Error::InvalidValue);
+        }
+        Ok(self.db.replace_rrset(name, ty, records))
+    }
+
+    /// Adds `record` to `name`, unless `name` already owns an identical record.
+    ///
+    /// Returns whether the record was added. Added records are announced by the next
+    /// [`Advertiser::build_announcement`].
+    pub fn add_record_if_absent(&mut self, name: &DomainName, record: Record<'static>) -> bool {
+        self.db.add_if_absent(name, record)
+    }
+
+    /// Removes the record owned by `name` that has the same data as `record`.
+    ///
+    /// Returns whether a record was removed. Removed records are announced with a TTL of 0 by the
+    /// next [`Advertiser::build_announcement`], so that other hosts flush them from their caches.
+    pub fn remove_record(&mut self, name: &DomainName, record: &Record<'_>) -> bool {
+        self.db.remove_matching(name, record)
+    }
+
+    /// Sets whether the records of type `ty` owned by `name` are shared with other hosts.
+    ///
+    /// Unique records are owned by this host alone: they are probed for by
+    /// [`Advertiser::build_probe`], and sent with the *cache-flush* bit set, which tells other
+    /// hosts to discard any conflicting records they have cached ([RFC 6762, section 10.2]).
+    /// Shared records, like the `PTR` records that list the instances of a service, can be
+    /// published by several hosts at once. They are sent without the *cache-flush* bit, so that
+    /// they add to the records of other hosts instead of replacing them, and are never probed for.
+    ///
+    /// By default, `PTR` records are shared and all other records are unique. The setting applies
+    /// to the current records, as well as to records later added to the same name and type via
+    /// [`Advertiser::replace_records`] and [`Advertiser::add_record_if_absent`].
+    ///
+    /// Returns whether `name` owns any records of type `ty`.
+    ///
+    /// [RFC 6762, section 10.2]: https://datatracker.ietf.org/doc/html/rfc6762#section-10.2
+    pub fn set_shared(&mut self, name: &DomainName, ty: Type, shared: bool) -> bool {
+        let mut found = false;
+        for entry in &mut self.db.entries {
+            if entry.matches(name, ty) {
+                entry.shared = shared;
+                found = true;
+            }
+        }
+        found
+    }
+
+    /// Returns whether records were changed since the last [`Advertiser::build_announcement`].
+    pub fn has_pending_announcement(&self) -> bool {
+        !self.db.pending.is_empty()
+    }
+
+    /// Builds an unsolicited response announcing all records changed via
+    /// [`Advertiser::replace_records`], [`Advertiser::add_record_if_absent`] and
+    /// [`Advertiser::remove_record`] since the last call.
+    ///
+    /// Returns [`None`] if nothing changed. The announcement should be multicast to the mDNS group
+    /// twice, one second apart ([RFC 6762, section 8.3]).
+    ///
+    /// Returns [`Error::Truncated`] if the announcement doesn't fit into
+    /// [`Advertiser::max_message_size`] bytes. The changes remain queued in that case.
+    ///
+    /// [RFC 6762, section 8.3]: https://datatracker.ietf.org/doc/html/rfc6762#section-8.3
+    pub fn build_announcement(&mut self) -> Result<Option<&[u8]>, Error> {
+        if self.db.pending.is_empty() {
+            return Ok(None);
         }
+        let mut header = Header::default();
+        header.set_response(true);
+        header.set_authority(true);
+        let mut enc = MessageEncoder::new(&mut self.response_buf);
+        enc.set_header(header);
+        let mut enc = enc.answers();
+        for entry in &self.db.pending {
+            enc.add_answer(
+                ResourceRecord::new(&entry.name, &entry.record)
+                    .class(entry.wire_class(entry.ttl != 0))
+                    .ttl(entry.ttl),
+            );
+        }
+        let len = enc.finish()?;
+        self.db.pending.clear();
+        Ok(Some(&self.response_buf[..len]))
+    }
+
+    /// Builds an unsolicited response announcing all unique records of this advertiser right away,
+    /// and returns it.
+    ///
+    /// This is useful after state changes that other hosts should learn about immediately, for
+    /// example when a service becomes ready late. The records are sent with the *cache-flush* bit
+    /// set, so other hosts replace any stale records they have cached. Shared records (see
+    /// [`Advertiser::set_shared`]) are not included. The announcement should be multicast to the
+    /// mDNS group.
+    ///
+    /// Records that were multicast less than a second ago are left out, since [RFC 6762, section 6]
+    /// forbids multicasting a record more often than that. Returns [`None`] if that applies to all
+    /// unique records. Queued changes to the announced records are sent as part of this
+    /// announcement, so [`Advertiser::build_announcement`] won't repeat them.
+    ///
+    /// Returns [`Error::Truncated`] if the announcement doesn't fit into
+    /// [`Advertiser::max_message_size`] bytes.
+    ///
+    /// [RFC 6762, section 6]: https://datatracker.ietf.org/doc/html/rfc6762#section-6
+    pub fn announce_now(&mut self) -> Result<Option<&[u8]>, Error> {
+        let now = self.clock.now();
+        let eligible = self
+            .db
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| {
+                !entry.shared
+                    && entry.last_multicast.is_none_or(|at| {
+                        now.saturating_duration_since(at) >= MIN_MULTICAST_INTERVAL
+                    })
+            })
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        if eligible.is_empty() {
+            return Ok(None);
+        }
+
+        let mut header = Header::default();
+        header.set_response(true);
+        header.set_authority(true);
+        let mut enc = MessageEncoder::new(&mut self.response_buf);
+        enc.set_header(header);
+        let mut enc = enc.answers();
+        for &i in &eligible {
+            let entry = &self.db.entries[i];
+            enc.add_answer(
+                ResourceRecord::new(&entry.name, &entry.record)
+                    .class(entry.wire_class(true))
+                    .ttl(entry.ttl),
+            );
+        }
+        let len = enc.finish()?;
+
+        for &i in &eligible {
+            self.db.entries[i].last_multicast = Some(now);
+            let entry = &self.db.entries[i];
+            self.db.pending.retain(|pending| {
+                pending.ttl == 0
+                    || !(pending.name.eq_ignore_ascii_case(&entry.name)
+                        && pending.record == entry.record)
+            });
+        }
+        Ok(Some(&self.response_buf[..len]))
+    }
+
+    /// Builds an unsolicited response that withdraws every record of this advertiser.
+    ///
+    /// All records are sent with a TTL of 0 (a "goodbye", [RFC 6762, section 10.1]), which tells
+    /// other hosts to remove them from their caches. This should be multicast to the mDNS group
+    /// when the advertiser shuts down. The records themselves are kept, so that advertising can
+    /// resume later.
+    ///
+    /// Returns [`Error::Truncated`] if the goodbye doesn't fit into
+    /// [`Advertiser::max_message_size`] bytes.
+    ///
+    /// [RFC 6762, section 10.1]: https://datatracker.ietf.org/doc/html/rfc6762#section-10.1
+    pub fn build_goodbye(&mut self) -> Result<&[u8], Error> {
+        let mut header = Header::default();
+        header.set_response(true);
+        header.set_authority(true);
+        let mut enc = MessageEncoder::new(&mut self.response_buf);
+        enc.set_header(header);
+        let mut enc = enc.answers();
+        for entry in &self.db.entries {
+            enc.add_answer(
+                ResourceRecord::new(&entry.name, &entry.record)
+                    .class(entry.wire_class(false))
+                    .ttl(0),
+            );
+        }
+        let len = enc.finish()?;
+        Ok(&self.response_buf[..len])
+    }
+
+    /// Builds a probe query for the unique records of this advertiser, and returns it.
+    ///
+    /// Before answering queries for a name, mDNS responders have to verify that no other host on
+    /// the network claims records with the same name ([RFC 6762, section 8.1]). To do so, they send
+    /// a query for type `ANY` for each name, with the records they intend to use in the
+    /// *Authority* section, three times 250 ms apart. Other responders answer the query if they
+    /// already own one of the names, and simultaneously probing responders can compare the
+    /// proposed records to break the tie.
+    ///
+    /// The probe covers the host names added via [`Advertiser::new`] and [`Advertiser::add_name`]
+    /// and the service instance names added via [`Advertiser::add_instance`]. Shared records (see
+    /// [`Advertiser::set_shared`]) may legitimately be owned by other hosts too, and are not probed
+    /// for. The questions ask for unicast responses, as recommended for the first probe.
+    ///
+    /// Returns [`Error::Truncated`] if the probe doesn't fit into
+    /// [`Advertiser::max_message_size`] bytes.
+    ///
+    /// [RFC 6762, section 8.1]: https://datatracker.ietf.org/doc/html/rfc6762#section-8.1
+    pub fn build_probe(&mut self) -> Result<&[u8], Error> {
+        let unique = || self.db.entries.iter().filter(|entry| !entry.shared);
+        let mut names: Vec<&DomainName> = Vec::new();
+        for entry in unique() {
+            if !names.contains(&&entry.name) {
+                names.push(&entry.name);
+            }
+        }
+
+        let mut enc = MessageEncoder::new(&mut self.response_buf);
+        for name in names {
+            enc.question(Question::new(name).ty(QType::ALL).prefer_unicast(true));
+        }
+        let mut enc = enc.answers().authority();
+        for entry in unique() {
+            enc.add_authority(
+                ResourceRecord::new(&entry.name, &entry.record)
+                    .class(entry.class)
+                    .ttl(entry.ttl),
+            );
+        }
+        let len = enc.finish()?;
+        Ok(&self.response_buf[..len])
     }
 }
 
@@ -242,21 +1196,162 @@ struct RecordDb {
     // This could be, y'know, performant, by using literally any other data structure, but since
     // this is usually only gonna contain like 5 entries, it doesn't matter right now.
     entries: Vec<Entry>,
+    /// Records that changed since the last announcement. Removed records have a TTL of 0.
+    pending: Vec<Entry>,
 }
 
 impl RecordDb {
     fn new() -> Self {
         Self {
             entries: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Replaces all records of type `ty` owned by `name` with `records`.
+    ///
+    /// Returns whether anything changed.
+    fn replace_rrset(
+        &mut self,
+        name: &DomainName,
+        ty: Type,
+        records: Vec<Record<'static>>,
+    ) -> bool {
+        let shared = self.shared(name, ty);
+        let mut changed = false;
+        let mut i = 0;
+        while i < self.entries.len() {
+            let entry = &self.entries[i];
+            if entry.matches(name, ty) && !records.contains(&entry.record) {
+                let entry = self.entries.remove(i);
+                self.schedule_goodbye(entry);
+                changed = true;
+            } else {
+                i += 1;
+            }
+        }
+        for record in records {
+            changed |= self.insert_if_absent(name, record, shared);
+        }
+        changed
+    }
+
+    /// Returns whether the existing records of type `ty` owned by `name` are shared, or [`None`]
+    /// if there are none.
+    fn shared(&self, name: &DomainName, ty: Type) -> Option<bool> {
+        self.entries
+            .iter()
+            .find(|entry| entry.matches(name, ty))
+            .map(|entry| entry.shared)
+    }
+
+    /// Adds `record` to `name`, unless `name` already owns an identical record.
+    ///
+    /// Returns whether the record was added.
+    fn add_if_absent(&mut self, name: &DomainName, record: Record<'static>) -> bool {
+        let shared = self.shared(name, record.record_type());
+        self.insert_if_absent(name, record, shared)
+    }
+
+    /// Like [`RecordDb::add_if_absent`], but marks the record as shared according to `shared`
+    /// (or the default for its type, if [`None`]).
+    fn insert_if_absent(
+        &mut self,
+        name: &DomainName,
+        record: Record<'static>,
+        shared: Option<bool>,
+    ) -> bool {
+        if self.position(name, &record).is_some() {
+            return false;
+        }
+        let mut entry = Entry::new(name.clone(), record);
+        if let Some(shared) = shared {
+            entry.shared = shared;
         }
+        self.schedule(entry.clone());
+        self.entries.push(entry);
+        true
+    }
+
+    /// Moves all records owned by `old` to `new`, and makes `SRV` and `PTR` records pointing at
+    /// `old` point at `new` instead.
+    ///
+    /// The old records are scheduled for a goodbye, and the new ones for an announcement.
+    fn rename(&mut self, old: &DomainName, new: &DomainName) {
+        for i in 0..self.entries.len() {
+            let entry = &self.entries[i];
+            let name = if entry.name.eq_ignore_ascii_case(old) {
+                new.clone()
+            } else {
+                entry.name.clone()
+            };
+            let record = match &entry.record {
+                Record::SRV(srv) if srv.target().eq_ignore_ascii_case(old) => Record::SRV(
+                    SRV::new(srv.priority(), srv.weight(), srv.port(), new.clone()),
+                ),
+                Record::PTR(ptr) if ptr.ptrdname().eq_ignore_ascii_case(old) => {
+                    Record::PTR(PTR::new(new.clone()))
+                }
+                record => record.clone(),
+            };
+            if name == entry.name && record == entry.record {
+                continue;
+            }
+
+            self.schedule_goodbye(entry.clone());
+            let entry = &mut self.entries[i];
+            entry.name = name;
+            entry.record = record;
+            entry.last_multicast = None;
+            let entry = entry.clone();
+            self.schedule(entry);
+        }
+    }
+
+    /// Removes the record owned by `name` that is identical to `record`.
+    ///
+    /// Returns whether a record was removed.
+    fn remove_matching(&mut self, name: &DomainName, record: &Record<'_>) -> bool {
+        match self.position(name, record) {
+            Some(i) => {
+                let entry = self.entries.remove(i);
+                self.schedule_goodbye(entry);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn position(&self, name: &DomainName, record: &Record<'_>) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.name.eq_ignore_ascii_case(name) && entry.record == *record)
+    }
+
+    fn schedule_goodbye(&mut self, mut entry: Entry) {
+        entry.ttl = 0;
+        self.schedule(entry);
+    }
+
+    /// Queues `entry` for the next announcement, replacing any queued change to the same record.
+    fn schedule(&mut self, entry: Entry) {
+        self.pending.retain(|pending| {
+            !(pending.name.eq_ignore_ascii_case(&entry.name) && pending.record == entry.record)
+        });
+        self.pending.push(entry);
     }
 }
 
+#[derive(Clone)]
 struct Entry {
     name: DomainName,
     class: Class,
     ttl: u32,
     record: Record<'static>,
+    /// Whether other hosts may publish records of the same name and type.
+    shared: bool,
+    /// When this record was last sent in a multicast response.
+    last_multicast: Option<Instant>,
 }
 
 impl Entry {
@@ -265,9 +1360,679 @@ impl Entry {
             name,
             class: Class::IN,
             ttl: TTL,
+            shared: record.record_type() == Type::PTR,
             record,
+            last_multicast: None,
         }
     }
+
+    /// Returns the class to put on the wire, with the cache-flush bit set for unique records if
+    /// `cache_flush` is `true`.
+    fn wire_class(&self, cache_flush: bool) -> Class {
+        if cache_flush && !self.shared {
+            Class(self.class.0 | 0x8000)
+        } else {
+            self.class
+        }
+    }
+
+    fn matches(&self, name: &DomainName, ty: Type) -> bool {
+        self.record.record_type() == ty && self.name.eq_ignore_ascii_case(name)
+    }
 }
 
 const TTL: u32 = 120;
+
+/// Maximum number of `CNAME` records followed when answering a query, to break alias loops.
+const MAX_CNAME_CHAIN: usize = 8;
+
+fn host_domain(hostname: &Label) -> DomainName {
+    DomainName::from_iter([hostname.clone(), label!("local")])
+}
+
+fn instance_domain(instance: &ServiceInstance) -> DomainName {
+    DomainName::from_iter([
+        instance.instance_name(),
+        instance.service_name(),
+        &instance.service_transport().to_label(),
+        &label!("local"),
+    ])
+}
+
+/// Returns the name to try after `label` is found to be in use.
+///
+/// Appends `{start}2{end}` to `label`, or increments the number if `label` already ends with such
+/// a suffix. The rest of `label` is shortened if necessary, to keep it within
+/// [`Label::MAX_LEN`].
+fn next_label(label: &Label, start: &str, end: &str) -> Label {
+    let bytes = label.as_bytes();
+    let numbered = || {
+        let rest = bytes.strip_suffix(end.as_bytes())?;
+        let digits = rest.iter().rev().take_while(|b| b.is_ascii_digit()).count();
+        let (rest, digits) = rest.split_at(rest.len() - digits);
+        let n = std::str::from_utf8(digits).ok()?.parse::<u32>().ok()?;
+        let base = rest.strip_suffix(start.as_bytes())?;
+        ((2..u32::MAX).contains(&n) && !base.is_empty()).then_some((base, n))
+    };
+    let (base, n) = numbered().unwrap_or((bytes, 1));
+
+    let suffix = format!("{start}{}{end}", n + 1);
+    // Don't cut a UTF-8 encoded character in half.
+    let mut len = base.len().min(Label::MAX_LEN - suffix.len());
+    while len > 0 && len < base.len() && base[len] & 0xc0 == 0x80 {
+        len -= 1;
+    }
+    let mut label = base[..len].to_vec();
+    label.extend_from_slice(suffix.as_bytes());
+    Label::new(label)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        clock::ManualClock,
+        packet::{decoder::MessageDecoder, records::CNAME, QClass},
+        service::ServiceTransport,
+    };
+
+    use super::*;
+
+    #[test]
+    fn socket_options() {
+        let mut adv = Advertiser::new(label!("host"), Ipv4Addr::new(10, 0, 0, 1).into()).unwrap();
+        let sock = adv.create_socket().unwrap();
+        assert_eq!(sock.multicast_ttl_v4().unwrap(), 255);
+        assert!(sock.multicast_loop_v4().unwrap());
+
+        adv.set_multicast_ttl(1);
+        adv.set_multicast_loopback(false);
+        adv.set_interface(Ipv4Addr::LOCALHOST);
+        let sock = adv.create_socket().unwrap();
+        assert_eq!(sock.multicast_ttl_v4().unwrap(), 1);
+        assert!(!sock.multicast_loop_v4().unwrap());
+        let sock = socket2::SockRef::from(&sock);
+        assert_eq!(sock.multicast_if_v4().unwrap(), Ipv4Addr::LOCALHOST);
+    }
+
+    #[test]
+    fn probe() {
+        let mut adv = Advertiser::new(label!("host"), Ipv4Addr::new(10, 0, 0, 1).into()).unwrap();
+        adv.add_name(label!("host"), "fe80::1".parse().unwrap());
+        let instance = ServiceInstance::new(label!("web"), label!("_http"), ServiceTransport::TCP);
+        let mut details = InstanceDetails::new(domain!("host.local"), 80);
+        details.txt_records_mut().add_flag("flag".into());
+        adv.add_instance(instance, details);
+
+        let probe = adv.build_probe().unwrap();
+        let mut lines = Vec::new();
+        MessageDecoder::new(probe)
+            .unwrap()
+            .format(|args| lines.push(args.to_string()))
+            .unwrap();
+        expect_test::expect![[r#"
+            [
+                "query (id=0, op=QUERY, rcode=NO_ERROR)",
+                "Q: host.local.\tIN\tALL",
+                "Q: web._http._tcp.local.\tIN\tALL",
+                "AUTH: host.local.\t120\tIN\tA\t10.0.0.1",
+                "AUTH: host.local.\t120\tIN\tAAAA\tfe80::1",
+                "AUTH: web._http._tcp.local.\t120\tIN\tSRV\t0\t0\t80\thost.local.",
+                "AUTH: web._http._tcp.local.\t120\tIN\tTXT\tflag",
+            ]
+        "#]]
+        .assert_debug_eq(&lines);
+    }
+
+    #[test]
+    fn update_records() {
+        let mut adv = Advertiser::new(label!("host"), Ipv4Addr::new(10, 0, 0, 1).into()).unwrap();
+        assert!(!adv.has_pending_announcement());
+        assert_eq!(adv.build_announcement().unwrap(), None);
+
+        let host = domain!("HOST.local");
+        let a = |last| Record::A(A::new(Ipv4Addr::new(10, 0, 0, last)));
+        assert!(!adv.add_record_if_absent(&host, a(1)));
+        assert!(adv.add_record_if_absent(&host, a(2)));
+        assert!(adv
+            .replace_records(&host, Type::A, vec![a(2), a(3)])
+            .unwrap());
+        assert!(!adv
+            .replace_records(&host, Type::A, vec![a(3), a(2)])
+            .unwrap());
+        assert_eq!(
+            adv.replace_records(&host, Type::AAAA, vec![a(4)]),
+            Err(Error::InvalidValue)
+        );
+        assert!(adv.remove_record(&host, &a(3)));
+        assert!(!adv.remove_record(&host, &a(3)));
+        assert!(adv.has_pending_announcement());
+
+        let announcement = adv.build_announcement().unwrap().unwrap();
+        let mut lines = Vec::new();
+        MessageDecoder::new(announcement)
+            .unwrap()
+            .format(|args| lines.push(args.to_string()))
+            .unwrap();
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: HOST.local.\t120\tIN\tA\t10.0.0.2",
+                "ANS: host.local.\t0\tIN\tA\t10.0.0.1",
+                "ANS: HOST.local.\t0\tIN\tA\t10.0.0.3",
+            ]
+        "#]]
+        .assert_debug_eq(&lines);
+        assert!(!adv.has_pending_announcement());
+
+        let probe = adv.build_probe().unwrap();
+        let mut lines = Vec::new();
+        MessageDecoder::new(probe)
+            .unwrap()
+            .format(|args| lines.push(args.to_string()))
+            .unwrap();
+        assert_eq!(lines[2], "AUTH: HOST.local.\t120\tIN\tA\t10.0.0.2");
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[test]
+    fn response_destination() {
+        let clock = ManualClock::new();
+        let mut adv = Advertiser::new(label!("host"), Ipv4Addr::new(10, 0, 0, 1).into()).unwrap();
+        adv.set_clock(clock.clone());
+        let host = domain!("host.local");
+        let mut buf = [0; 512];
+        let mut query = |unicast: bool, questions: usize| {
+            let mut enc = MessageEncoder::new(&mut buf);
+            for _ in 0..questions {
+                enc.question(Question::new(&host).prefer_unicast(unicast));
+            }
+            let len = enc.finish().unwrap();
+            buf[..len].to_vec()
+        };
+        let (qu, qm, single) = (query(true, 2), query(false, 2), query(false, 1));
+        let peer: SocketAddr = "10.0.0.2:5353".parse().unwrap();
+        let destination = |adv: &mut Advertiser, query: &[u8], source| {
+            adv.handle_query(query, source).unwrap().unwrap().1
+        };
+
+        // Records that were never multicast are multicast, even if a unicast response was asked for.
+        assert_eq!(destination(&mut adv, &qu, peer), MDNS_DESTINATION);
+        assert_eq!(destination(&mut adv, &qu, peer), peer);
+        assert_eq!(destination(&mut adv, &qm, peer), MDNS_DESTINATION);
+        assert_eq!(destination(&mut adv, &single, peer), MDNS_DESTINATION);
+
+        adv.set_response_mode(ResponseMode::PreferUnicastWhenSingleton);
+        assert_eq!(destination(&mut adv, &single, peer), peer);
+        assert_eq!(destination(&mut adv, &qm, peer), MDNS_DESTINATION);
+
+        // After a quarter of the TTL, the records are multicast again.
+        clock.advance(Duration::from_secs(u64::from(TTL) / 4));
+        assert_eq!(destination(&mut adv, &single, peer), MDNS_DESTINATION);
+
+        adv.set_response_mode(ResponseMode::AlwaysMulticast);
+        assert_eq!(destination(&mut adv, &qu, peer), MDNS_DESTINATION);
+
+        // Legacy unicast queries (not sent from port 5353) are always answered directly.
+        let legacy: SocketAddr = "10.0.0.2:40000".parse().unwrap();
+        assert_eq!(destination(&mut adv, &qm, legacy), legacy);
+    }
+
+    #[test]
+    fn shared_records() {
+        let mut adv = Advertiser::new(label!("host"), Ipv4Addr::new(10, 0, 0, 1).into()).unwrap();
+        let instance = ServiceInstance::new(label!("web"), label!("_http"), ServiceTransport::TCP);
+        adv.add_instance(instance, InstanceDetails::new(domain!("host.local"), 80));
+
+        let cache_flush = |adv: &mut Advertiser, name: &DomainName, source: SocketAddr| {
+            let mut buf = [0; 512];
+            let mut enc = MessageEncoder::new(&mut buf);
+            enc.question(Question::new(name));
+            let len = enc.finish().unwrap();
+            let (response, _) = adv.handle_query(&buf[..len], source).unwrap().unwrap();
+            let mut dec = MessageDecoder::new(response).unwrap().answers().unwrap();
+            dec.iter()
+                .map(|rr| rr.unwrap().cache_flush())
+                .collect::<Vec<_>>()
+        };
+        let peer: SocketAddr = "10.0.0.2:5353".parse().unwrap();
+        let legacy: SocketAddr = "10.0.0.2:40000".parse().unwrap();
+        let host = domain!("host.local");
+        let service = domain!("_http._tcp.local");
+        assert_eq!(cache_flush(&mut adv, &host, peer), [true]);
+        assert_eq!(cache_flush(&mut adv, &host, legacy), [false]);
+        assert_eq!(cache_flush(&mut adv, &service, peer), [false]);
+
+        // Shared records are neither probed for nor sent with the cache-flush bit.
+        assert!(!adv.set_shared(&host, Type::AAAA, true));
+        assert!(adv.set_shared(&host, Type::A, true));
+        assert_eq!(cache_flush(&mut adv, &host, peer), [false]);
+        let probe = adv.build_probe().unwrap();
+        let dec = MessageDecoder::new(probe).unwrap();
+        assert_eq!(dec.header().question_count(), 1);
+        assert_eq!(dec.header().authoritative_count(), 2);
+
+        // Replacing the records keeps the setting.
+        let a = Record::A(A::new(Ipv4Addr::new(10, 0, 0, 2)));
+        assert!(adv.replace_records(&host, Type::A, vec![a]).unwrap());
+        assert_eq!(cache_flush(&mut adv, &host, peer), [false]);
+
+        // Goodbyes of unique records don't flush other hosts' caches either.
+        assert!(adv.set_shared(&service, Type::PTR, false));
+        let ptr = Record::PTR(PTR::new(domain!("other._http._tcp.local")));
+        assert!(adv.add_record_if_absent(&service, ptr));
+        let instance_domain = domain!("web._http._tcp.local");
+        assert!(adv.remove_record(&service, &Record::PTR(PTR::new(instance_domain))));
+        let announcement = adv.build_announcement().unwrap().unwrap();
+        let mut dec = MessageDecoder::new(announcement)
+            .unwrap()
+            .answers()
+            .unwrap();
+        let flags = dec
+            .iter()
+            .map(|rr| {
+                let rr = rr.unwrap();
+                (rr.ttl(), rr.cache_flush())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(flags, [(0, false), (TTL, false), (TTL, true), (0, false)]);
+    }
+
+    #[test]
+    fn identity() {
+        let mut adv = Advertiser::new(label!("host"), Ipv4Addr::new(10, 0, 0, 1).into()).unwrap();
+        let name = domain!("hostname.bind");
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf);
+        enc.question(Question::new(&name).class(QClass::CH).ty(QType::TXT));
+        let len = enc.finish().unwrap();
+        let query = &buf[..len];
+        let scanner: SocketAddr = "10.0.0.2:40000".parse().unwrap();
+        assert_eq!(adv.handle_query(query, scanner).unwrap(), None);
+
+        let mut identity = Identity::new();
+        identity.set_hostname("host.local");
+        adv.set_identity(identity);
+        let (response, destination) = adv.handle_query(query, scanner).unwrap().unwrap();
+        assert_eq!(destination, scanner);
+        let mut lines = Vec::new();
+        MessageDecoder::new(response)
+            .unwrap()
+            .format(|args| lines.push(args.to_string()))
+            .unwrap();
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "Q: hostname.bind.\tCH\tTXT",
+                "ANS: hostname.bind.\t0\tCH\tTXT\thost.local",
+            ]
+        "#]]
+        .assert_debug_eq(&lines);
+    }
+
+    #[test]
+    fn packet_hook() {
+        let mut adv = Advertiser::new(label!("host"), Ipv4Addr::new(10, 0, 0, 1).into()).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        adv.set_packet_hook(
+            move |source, packet: &[u8], header: Option<&Header>, decision| {
+                let id = header.map(|header| header.id());
+                sender.send((source, packet.len(), id, decision)).unwrap();
+            },
+        );
+
+        let query = |name: &DomainName, id: u16| {
+            let mut buf = [0; 512];
+            let mut header = Header::default();
+            header.set_id(id);
+            let mut enc = MessageEncoder::new(&mut buf);
+            enc.set_header(header);
+            enc.question(Question::new(name));
+            let len = enc.finish().unwrap();
+            buf[..len].to_vec()
+        };
+        let peer: SocketAddr = "10.0.0.2:5353".parse().unwrap();
+        let found = query(&domain!("host.local"), 1);
+        let missing = query(&domain!("other.local"), 2);
+        let response = adv.handle_packet(&found, peer).unwrap().unwrap().to_vec();
+        assert_eq!(adv.handle_packet(&missing, peer).unwrap(), None);
+        assert_eq!(adv.handle_packet(&response, peer).unwrap(), None);
+        assert!(adv.handle_packet(&[0; 4], peer).is_err());
+        let mut acl = Acl::new();
+        acl.deny("10.0.0.0/8".parse().unwrap());
+        adv.set_acl(acl);
+        assert_eq!(adv.handle_packet(&found, peer).unwrap(), None);
+
+        let events = receiver.try_iter().collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                (
+                    peer,
+                    found.len(),
+                    Some(1),
+                    PacketDecision::Responded(MDNS_DESTINATION)
+                ),
+                (peer, missing.len(), Some(2), PacketDecision::Unanswered),
+                (peer, response.len(), Some(0), PacketDecision::Ignored),
+                (peer, 4, None, PacketDecision::Malformed),
+                (peer, found.len(), Some(1), PacketDecision::Denied),
+            ]
+        );
+    }
+
+    #[test]
+    fn rename_on_conflict() {
+        let mut adv = Advertiser::new(label!("host"), Ipv4Addr::new(10, 0, 0, 1).into()).unwrap();
+        let instance = ServiceInstance::new(label!("web"), label!("_http"), ServiceTransport::TCP);
+        adv.add_instance(
+            instance.clone(),
+            InstanceDetails::new(domain!("host.local"), 80),
+        );
+        let (sender, receiver) = std::sync::mpsc::channel();
+        adv.set_rename_hook(move |event: &RenameEvent| sender.send(event.clone()).unwrap());
+
+        let response = |name: &DomainName, record: Record<'_>| {
+            let mut buf = [0; 512];
+            let mut header = Header::default();
+            header.set_response(true);
+            let mut enc = MessageEncoder::new(&mut buf);
+            enc.set_header(header);
+            let mut enc = enc.answers();
+            enc.add_answer(ResourceRecord::new(name, &record).ttl(TTL));
+            let len = enc.finish().unwrap();
+            buf[..len].to_vec()
+        };
+        let peer: SocketAddr = "10.0.0.2:5353".parse().unwrap();
+        let host = domain!("host.local");
+        let web = domain!("web._http._tcp.local");
+
+        // Our own records (for example, looped back responses) and goodbyes don't conflict.
+        let own = response(&host, Record::A(A::new(Ipv4Addr::new(10, 0, 0, 1))));
+        let other = Record::A(A::new(Ipv4Addr::new(10, 0, 0, 2)));
+        let mut goodbye = response(&host, other.clone());
+        let ttl_pos = goodbye.len() - 10;
+        goodbye[ttl_pos..ttl_pos + 4].fill(0);
+        for packet in [own, goodbye] {
+            assert_eq!(
+                adv.decide(&packet, peer).unwrap().0,
+                PacketDecision::Ignored
+            );
+        }
+        assert!(!adv.has_pending_announcement());
+
+        let packet = response(&host, other);
+        assert_eq!(
+            adv.decide(&packet, peer).unwrap().0,
+            PacketDecision::Conflict
+        );
+        assert_eq!(adv.current_hostname(), &label!("host-2"));
+        let srv = Record::SRV(SRV::new(0, 0, 8080, domain!("elsewhere.local")));
+        let packet = response(&web, srv);
+        assert_eq!(
+            adv.decide(&packet, peer).unwrap().0,
+            PacketDecision::Conflict
+        );
+        assert_eq!(
+            adv.current_instance_name(&instance),
+            Some(&label!("web (2)"))
+        );
+        // A second conflict bumps the number.
+        let packet = response(
+            &domain!("host-2.local"),
+            Record::AAAA(AAAA::new("fe80::2".parse().unwrap())),
+        );
+        assert_eq!(
+            adv.decide(&packet, peer).unwrap().0,
+            PacketDecision::Ignored
+        );
+        let packet = response(
+            &domain!("host-2.local"),
+            Record::A(A::new(Ipv4Addr::new(10, 0, 0, 2))),
+        );
+        assert_eq!(
+            adv.decide(&packet, peer).unwrap().0,
+            PacketDecision::Conflict
+        );
+        assert_eq!(adv.current_hostname(), &label!("host-3"));
+
+        let events = receiver.try_iter().collect::<Vec<_>>();
+        let names = events
+            .iter()
+            .map(|event| {
+                assert_eq!(event.source(), peer);
+                (event.old_name().to_string(), event.new_name().to_string())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                ("host.local.".into(), "host-2.local.".into()),
+                (
+                    "web._http._tcp.local.".into(),
+                    "web (2)._http._tcp.local.".into()
+                ),
+                ("host-2.local.".into(), "host-3.local.".into()),
+            ]
+        );
+
+        let announcement = adv.build_announcement().unwrap().unwrap();
+        let mut lines = Vec::new();
+        MessageDecoder::new(announcement)
+            .unwrap()
+            .format(|args| lines.push(args.to_string()))
+            .unwrap();
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: host.local.\t0\tIN\tA\t10.0.0.1",
+                "ANS: web._http._tcp.local.\t0\tIN\tSRV\t0\t0\t80\thost.local.",
+                "ANS: web._http._tcp.local.\t0\tIN\tSRV\t0\t0\t80\thost-2.local.",
+                "ANS: web._http._tcp.local.\t0\tIN\tTXT\t",
+                "ANS: web (2)._http._tcp.local.\t120\tIN\tTXT\t",
+                "ANS: _http._tcp.local.\t0\tIN\tPTR\tweb._http._tcp.local.",
+                "ANS: _http._tcp.local.\t120\tIN\tPTR\tweb (2)._http._tcp.local.",
+                "ANS: host-2.local.\t0\tIN\tA\t10.0.0.1",
+                "ANS: host-3.local.\t120\tIN\tA\t10.0.0.1",
+                "ANS: web (2)._http._tcp.local.\t0\tIN\tSRV\t0\t0\t80\thost-2.local.",
+                "ANS: web (2)._http._tcp.local.\t120\tIN\tSRV\t0\t0\t80\thost-3.local.",
+            ]
+        "#]]
+        .assert_debug_eq(&lines);
+    }
+
+    #[test]
+    fn announce_now() {
+        let clock = ManualClock::new();
+        let mut adv = Advertiser::new(label!("host"), Ipv4Addr::new(10, 0, 0, 1).into()).unwrap();
+        adv.set_clock(clock.clone());
+        adv.add_instance(
+            ServiceInstance::new(label!("web"), label!("_http"), ServiceTransport::TCP),
+            InstanceDetails::new(domain!("host.local"), 80),
+        );
+        let mut lines = Vec::new();
+        MessageDecoder::new(adv.announce_now().unwrap().unwrap())
+            .unwrap()
+            .format(|args| lines.push(args.to_string()))
+            .unwrap();
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: host.local.\t120\tIN\tA\t10.0.0.1",
+                "ANS: web._http._tcp.local.\t120\tIN\tSRV\t0\t0\t80\thost.local.",
+                "ANS: web._http._tcp.local.\t120\tIN\tTXT\t",
+            ]
+        "#]]
+        .assert_debug_eq(&lines);
+
+        // Records may only be multicast once per second.
+        assert_eq!(adv.announce_now().unwrap(), None);
+        clock.advance(Duration::from_millis(999));
+        assert_eq!(adv.announce_now().unwrap(), None);
+
+        // Changed records are announced right away, and not again by `build_announcement`.
+        let host = domain!("host.local");
+        let a = Record::A(A::new(Ipv4Addr::new(10, 0, 0, 2)));
+        adv.replace_records(&host, Type::A, vec![a]).unwrap();
+        let announcement = adv.announce_now().unwrap().unwrap();
+        assert_eq!(
+            MessageDecoder::new(announcement)
+                .unwrap()
+                .header()
+                .answer_count(),
+            1
+        );
+        let mut lines = Vec::new();
+        MessageDecoder::new(adv.build_announcement().unwrap().unwrap())
+            .unwrap()
+            .format(|args| lines.push(args.to_string()))
+            .unwrap();
+        assert_eq!(lines[1..], ["ANS: host.local.\t0\tIN\tA\t10.0.0.1"]);
+
+        clock.advance(Duration::from_millis(1));
+        assert!(adv.announce_now().unwrap().is_some());
+    }
+
+    #[test]
+    fn goodbye() {
+        let mut adv = Advertiser::new(label!("host"), Ipv4Addr::new(10, 0, 0, 1).into()).unwrap();
+        adv.add_instance(
+            ServiceInstance::new(label!("web"), label!("_http"), ServiceTransport::TCP),
+            InstanceDetails::new(domain!("host.local"), 80),
+        );
+        let mut lines = Vec::new();
+        MessageDecoder::new(adv.build_goodbye().unwrap())
+            .unwrap()
+            .format(|args| lines.push(args.to_string()))
+            .unwrap();
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: host.local.\t0\tIN\tA\t10.0.0.1",
+                "ANS: web._http._tcp.local.\t0\tIN\tSRV\t0\t0\t80\thost.local.",
+                "ANS: web._http._tcp.local.\t0\tIN\tTXT\t",
+                "ANS: _http._tcp.local.\t0\tIN\tPTR\tweb._http._tcp.local.",
+                "ANS: _services._dns-sd._udp.local.\t0\tIN\tPTR\t_http._tcp.local.",
+            ]
+        "#]]
+        .assert_debug_eq(&lines);
+
+        // The records are still there.
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf);
+        enc.question(Question::new(&domain!("host.local")).ty(QType::A));
+        let len = enc.finish().unwrap();
+        let peer = "10.0.0.2:5353".parse().unwrap();
+        assert!(adv.handle_query(&buf[..len], peer).unwrap().is_some());
+    }
+
+    #[test]
+    fn next_labels() {
+        let next = |label: &str, start, end| {
+            String::from_utf8(
+                next_label(&Label::new(label), start, end)
+                    .as_bytes()
+                    .to_vec(),
+            )
+            .unwrap()
+        };
+        assert_eq!(next("host", "-", ""), "host-2");
+        assert_eq!(next("host-9", "-", ""), "host-10");
+        assert_eq!(next("host-1", "-", ""), "host-1-2");
+        assert_eq!(next("-2", "-", ""), "-2-2");
+        assert_eq!(next("My Printer (2)", " (", ")"), "My Printer (3)");
+        assert_eq!(next("My Printer", " (", ")"), "My Printer (2)");
+        let long = "ä".repeat(31);
+        let renamed = next(&long, " (", ")");
+        assert_eq!(renamed, format!("{} (2)", "ä".repeat(29)));
+    }
+
+    #[test]
+    fn cname_answers() {
+        let mut adv = Advertiser::new(label!("host"), Ipv4Addr::new(10, 0, 0, 1).into()).unwrap();
+        let alias = |name: &DomainName, target: &DomainName| {
+            (name.clone(), Record::CNAME(CNAME::new(target.clone())))
+        };
+        let (www, loop1, loop2) = (domain!("www.local"), domain!("a.local"), domain!("b.local"));
+        for (name, record) in [
+            alias(&www, &domain!("host.local")),
+            alias(&loop1, &loop2),
+            alias(&loop2, &loop1),
+        ] {
+            adv.add_record_if_absent(&name, record);
+        }
+
+        let mut answers = |name: &DomainName, qtype: QType| {
+            let mut buf = [0; 512];
+            let mut enc = MessageEncoder::new(&mut buf);
+            enc.question(Question::new(name).ty(qtype));
+            let len = enc.finish().unwrap();
+            let source = "10.0.0.2:5353".parse().unwrap();
+            let (response, _) = adv.handle_query(&buf[..len], source).unwrap().unwrap();
+            let mut lines = Vec::new();
+            MessageDecoder::new(response)
+                .unwrap()
+                .format(|args| lines.push(args.to_string()))
+                .unwrap();
+            lines.retain(|line| line.starts_with("ANS:"));
+            lines
+        };
+        expect_test::expect![[r#"
+            [
+                "ANS: www.local.\t120\tIN\tCNAME\thost.local.",
+                "ANS: host.local.\t120\tIN\tA\t10.0.0.1",
+            ]
+        "#]]
+        .assert_debug_eq(&answers(&www, QType::A));
+        expect_test::expect![[r#"
+            [
+                "ANS: www.local.\t120\tIN\tCNAME\thost.local.",
+            ]
+        "#]]
+        .assert_debug_eq(&answers(&www, QType::CNAME));
+        expect_test::expect![[r#"
+            [
+                "ANS: www.local.\t120\tIN\tCNAME\thost.local.",
+            ]
+        "#]]
+        .assert_debug_eq(&answers(&www, QType::TXT));
+        expect_test::expect![[r#"
+            [
+                "ANS: a.local.\t120\tIN\tCNAME\tb.local.",
+                "ANS: b.local.\t120\tIN\tCNAME\ta.local.",
+            ]
+        "#]]
+        .assert_debug_eq(&answers(&loop1, QType::A));
+    }
+
+    #[test]
+    fn minimal_any() {
+        let mut adv = Advertiser::new(label!("host"), Ipv4Addr::new(10, 0, 0, 1).into()).unwrap();
+        adv.add_name(label!("host"), "fe80::1".parse().unwrap());
+        adv.set_minimal_any(true);
+        let name = domain!("host.local");
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf);
+        enc.question(Question::new(&name).ty(QType::ALL));
+        let len = enc.finish().unwrap();
+        let query = &buf[..len];
+
+        let answers = |adv: &mut Advertiser, source: SocketAddr| {
+            let (response, _) = adv.handle_query(query, source).unwrap().unwrap();
+            let mut lines = Vec::new();
+            MessageDecoder::new(response)
+                .unwrap()
+                .format(|args| lines.push(args.to_string()))
+                .unwrap();
+            lines.retain(|line| line.starts_with("ANS:"));
+            lines
+        };
+        expect_test::expect![[r#"
+            [
+                "ANS: host.local.\t3789\tIN\tHINFO\tRFC8482\t",
+            ]
+        "#]]
+        .assert_debug_eq(&answers(&mut adv, "10.0.0.2:40000".parse().unwrap()));
+        // mDNS queriers still get every record.
+        assert_eq!(answers(&mut adv, "10.0.0.2:5353".parse().unwrap()).len(), 2);
+    }
+}
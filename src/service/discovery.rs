@@ -3,26 +3,36 @@
 use std::{
     collections::{btree_map::Entry, BTreeMap},
     io,
-    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket},
     ops::ControlFlow,
     time::{Duration, Instant},
 };
 
 use crate::{
+    clock::{Clock, SystemClock},
+    domain,
     hex::Hex,
     name::DomainName,
     packet::{
-        decoder::MessageDecoder,
-        encoder::{self, MessageEncoder},
+        decoder::{MessageDecoder, ResourceRecord},
+        encoder::{self, MessageEncoder, QueryBuilder},
         records::Record,
         Header, QType,
     },
+    resolver::Resolve,
     Error,
 };
 
-use crate::MDNS_BUFFER_SIZE;
+use crate::{checked_message_size, default_max_message_size, DNS_BUFFER_SIZE, MDNS_BUFFER_SIZE};
 
-use super::{InstanceDetails, Service, ServiceInstance, TxtRecords};
+use super::{InstanceDetails, Service, ServiceInstance, ServiceTarget, TxtRecords};
+
+/// Handler invoked with every received message. Returns whether to stop listening for responses.
+type OnResponse<'a> = dyn FnMut(&[u8]) -> Result<ControlFlow<()>, Error> + 'a;
+
+/// Handler invoked with every record answering a question of a [`QueryBatch`]. Returns whether the
+/// question has been answered sufficiently.
+type OnRecord<'a> = dyn FnMut(&ResourceRecord<'_>) -> ControlFlow<()> + Send + 'a;
 
 /// A simple, synchronous DNS service discoverer.
 pub struct SyncDiscoverer {
@@ -30,6 +40,11 @@ pub struct SyncDiscoverer {
     server: SocketAddr,
     domain: DomainName,
     discovery_timeout: Duration,
+    query_id: Option<u16>,
+    max_message_size: usize,
+    clock: Box<dyn Clock>,
+    details_cache: DetailsCache,
+    liveness_probe: Option<Box<dyn LivenessProbe>>,
 }
 
 impl SyncDiscoverer {
@@ -38,33 +53,52 @@ impl SyncDiscoverer {
 
     /// Creates a new service discoverer that will request services of `domain` from the given DNS
     /// server.
-    pub fn new(server: SocketAddr, domain: DomainName) -> io::Result<Self> {
+    pub fn new(server: SocketAddr, domain: DomainName) -> Result<Self, Error> {
         let bind_addr: SocketAddr = if server.is_ipv6() {
             (Ipv6Addr::UNSPECIFIED, 0).into()
         } else {
             (Ipv4Addr::UNSPECIFIED, 0).into()
         };
+        Self::with_socket(UdpSocket::bind(bind_addr)?, server, domain)
+    }
+
+    /// Creates a new service discoverer that will use `sock` to request services of `domain` from
+    /// the given DNS server.
+    ///
+    /// This allows using a socket that was configured in ways this library does not support
+    /// directly (eg. bound to a specific interface or port). `sock` must use the same address
+    /// family as `server`.
+    ///
+    /// Note that the socket's read timeout will be overwritten (see
+    /// [`SyncDiscoverer::set_retransmit_timeout`]).
+    pub fn with_socket(
+        sock: UdpSocket,
+        server: SocketAddr,
+        domain: DomainName,
+    ) -> Result<Self, Error> {
         let mut this = Self {
-            sock: UdpSocket::bind(bind_addr)?,
+            sock,
             server,
             domain,
             discovery_timeout: Self::DEFAULT_DISCOVERY_TIMEOUT,
+            query_id: None,
+            max_message_size: default_max_message_size(server),
+            clock: Box::new(SystemClock),
+            details_cache: DetailsCache::new(),
+            liveness_probe: None,
         };
         this.set_retransmit_timeout(Self::DEFAULT_RETRANSMIT_TIMEOUT)?;
         Ok(this)
     }
 
     /// Creates an mDNS service discoverer that will browse the `.local` service domain.
-    pub fn new_multicast_v4() -> io::Result<Self> {
-        Self::new(
-            "224.0.0.251:5353".parse().unwrap(),
-            DomainName::from_str("local").unwrap(),
-        )
+    pub fn new_multicast_v4() -> Result<Self, Error> {
+        Self::new("224.0.0.251:5353".parse().unwrap(), domain!("local"))
     }
 
     /// Sets the time after which a discovery query is retransmitted, if no responses have been
     /// received in this amount of time.
-    pub fn set_retransmit_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+    pub fn set_retransmit_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
         self.sock.set_read_timeout(Some(timeout))?;
         Ok(())
     }
@@ -73,20 +107,92 @@ impl SyncDiscoverer {
     ///
     /// Calling any service discovery method will block for this amount of time while it waits for
     /// responses.
-    pub fn set_discovery_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+    pub fn set_discovery_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
         self.discovery_timeout = timeout;
         Ok(())
     }
 
+    /// Sets the [`Clock`] used to enforce the discovery timeout.
+    ///
+    /// By default, the [`SystemClock`] is used.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Box::new(clock);
+    }
+
+    /// Sets the largest DNS message this discoverer can receive, in bytes.
+    ///
+    /// Defaults to [`DNS_BUFFER_SIZE`] for unicast DNS servers, and to [`MDNS_BUFFER_SIZE`] for
+    /// mDNS. [`MDNS_MAX_MESSAGE_SIZE`] can be used for mDNS on networks with jumbo frames.
+    ///
+    /// When querying a unicast DNS server with a size larger than [`DNS_BUFFER_SIZE`], the size is
+    /// advertised to the server via an EDNS(0) `OPT` record.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is smaller than [`DNS_BUFFER_SIZE`] or larger than 65535.
+    ///
+    /// [`MDNS_MAX_MESSAGE_SIZE`]: crate::MDNS_MAX_MESSAGE_SIZE
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = checked_message_size(size);
+    }
+
+    /// Sets the message ID to use for discovery queries.
+    ///
+    /// By default (or when passing [`None`]), every query uses a new random ID. When querying a
+    /// unicast DNS server, responses whose ID doesn't match the query are ignored.
+    pub fn set_query_id(&mut self, id: Option<u16>) {
+        self.query_id = id;
+    }
+
+    /// Sets a [`LivenessProbe`] that checks whether freshly loaded instances can actually be
+    /// reached.
+    ///
+    /// mDNS caches frequently hold on to services whose host has gone away without sending a
+    /// goodbye packet. With a probe set, [`SyncDiscoverer::load_instance_details`] probes the
+    /// targets of the loaded details in order of preference until one of them responds, and
+    /// reports the ones that don't via [`SyncDiscoverer::report_unreachable`]. If none of the
+    /// targets respond, the error of the last probe is returned instead of the details. Cached
+    /// details whose targets have all been reported as unreachable are loaded again.
+    ///
+    /// [`TcpConnectProbe`] is a probe that works for most TCP-based services. This replaces any
+    /// previously set probe.
+    pub fn set_liveness_probe(&mut self, probe: impl LivenessProbe + 'static) {
+        self.liveness_probe = Some(Box::new(probe));
+    }
+
     /// Requests the [`InstanceDetails`] associated with a specific [`ServiceInstance`] from the
     /// server.
     ///
     /// The [`InstanceDetails`] contain hostname and port where the [`ServiceInstance`] can be
     /// reached as well as service-specific metadata (which may be omitted).
+    ///
+    /// Details loaded previously are returned from a cache until the TTL of their records runs
+    /// out. Use [`SyncDiscoverer::load_instance_details_with`] to bypass the cache.
     pub fn load_instance_details(
         &mut self,
         instance: &ServiceInstance,
-    ) -> io::Result<InstanceDetails> {
+    ) -> Result<InstanceDetails, Error> {
+        self.load_instance_details_with(instance, false)
+    }
+
+    /// Requests the [`InstanceDetails`] associated with a specific [`ServiceInstance`], querying
+    /// the server even if cached details are available if `refresh` is `true`.
+    ///
+    /// Either way, the cache is updated with the response.
+    pub fn load_instance_details_with(
+        &mut self,
+        instance: &ServiceInstance,
+        refresh: bool,
+    ) -> Result<InstanceDetails, Error> {
+        if !refresh {
+            if let Some(cached) = self.details_cache.get(instance, self.clock.now()) {
+                if self.liveness_probe.is_none() || cached.next_target().is_some() {
+                    log::trace!("using cached details of {}", instance);
+                    return Ok(cached.details().clone());
+                }
+            }
+        }
+
         let mut domain = DomainName::from_iter([
             &instance.instance_name,
             instance.service.name(),
@@ -94,57 +200,96 @@ impl SyncDiscoverer {
         ]);
         domain.extend(&self.domain);
 
-        let mut details = None;
-        let mut txt_records = None;
-        self.send_query(&domain, &[QType::SRV, QType::TXT], &mut |record| {
-            match record {
-                Record::SRV(srv) => {
-                    match InstanceDetails::from_srv(&srv) {
-                        Ok(det) => {
-                            // FIXME: respect SRV priority, as required by RFC 6763
-                            details = Some(det);
-                            // FIXME: breaking here ignores any subsequent TXT records!
-                            ControlFlow::Break(())
-                        }
-                        Err(e) => {
-                            log::debug!(
-                                "failed to read instance details from SRV ({:?}): {}",
-                                e,
-                                srv
-                            );
-                            ControlFlow::Continue(())
-                        }
+        let mut collector = DetailsCollector::new(domain.clone());
+        self.send_query(&domain, &[QType::SRV, QType::TXT], &mut |msg| {
+            collector.add_response(msg)?;
+            Ok(match collector.is_complete() {
+                true => ControlFlow::Break(()),
+                false => ControlFlow::Continue(()),
+            })
+        })?;
+
+        let ttl = collector.ttl().unwrap_or_default();
+        // If nothing arrived in time, there are no details to return.
+        let details = collector.finish().ok_or(Error::Timeout)?;
+        self.details_cache
+            .insert(instance.clone(), details.clone(), ttl, self.clock.now());
+
+        if let Some(probe) = &mut self.liveness_probe {
+            let mut error = None;
+            for target in details.targets_by_preference() {
+                match probe.check(target) {
+                    Ok(()) => {
+                        error = None;
+                        break;
+                    }
+                    Err(e) => {
+                        log::debug!(
+                            "{}:{} of {} is unreachable: {}",
+                            target.host(),
+                            target.port(),
+                            instance,
+                            e
+                        );
+                        self.details_cache
+                            .report_unreachable(instance, target, self.clock.now());
+                        error = Some(e);
                     }
                 }
-                Record::TXT(txt) => {
-                    txt_records = Some(TxtRecords::from_txt(&txt));
-                    ControlFlow::Continue(())
-                }
-                _ => ControlFlow::Continue(()),
             }
-        })?;
+            if let Some(e) = error {
+                return Err(e);
+            }
+        }
+        Ok(details)
+    }
 
-        match details {
-            Some(mut details) => {
-                if let Some(txt) = txt_records {
-                    // FIXME this can potentially combine a TXT from one machine with a SRV from
-                    // another
-                    details.txt = txt;
-                }
+    /// Returns the cached [`InstanceDetails`] of `instance`, unless they have expired.
+    ///
+    /// Use [`CachedDetails::remaining_ttl`] to find out how much longer they are valid.
+    pub fn cached_instance_details(&self, instance: &ServiceInstance) -> Option<&CachedDetails> {
+        self.details_cache.get(instance, self.clock.now())
+    }
 
-                Ok(details)
-            }
+    /// Records that `target` of `instance` could not be reached, and returns the next target to try
+    /// from the cached details, without querying the network again.
+    ///
+    /// See [`DetailsCache::report_unreachable`].
+    pub fn report_unreachable(
+        &mut self,
+        instance: &ServiceInstance,
+        target: &ServiceTarget,
+    ) -> Option<&ServiceTarget> {
+        self.details_cache
+            .report_unreachable(instance, target, self.clock.now())
+    }
 
-            // Didn't get a response in time.
-            None => Err(io::ErrorKind::TimedOut.into()),
-        }
+    /// Removes all cached [`InstanceDetails`].
+    pub fn clear_details_cache(&mut self) {
+        self.details_cache.clear();
+    }
+
+    /// Returns the cache of [`InstanceDetails`].
+    ///
+    /// With the `persistent-cache` feature, this can be saved to disk with `DetailsCache::save`,
+    /// so that later runs of the program can reuse it.
+    #[inline]
+    pub fn details_cache(&self) -> &DetailsCache {
+        &self.details_cache
+    }
+
+    /// Replaces the cache of [`InstanceDetails`] with `cache`.
+    ///
+    /// This is typically used to restore a cache loaded with `DetailsCache::load`.
+    pub fn set_details_cache(&mut self, cache: DetailsCache) {
+        self.details_cache = cache;
     }
 
     /// Starts service discovery and invokes `callback` with every discovered instance of `service`.
     ///
     /// The `callback` can control whether to keep discovering instances or to exit the discovery
     /// loop by returning a [`ControlFlow`] value.
-    pub fn discover_instances<C>(&mut self, service: &Service, mut callback: C) -> io::Result<()>
+    pub fn discover_instances<C>(&mut self, service: &Service, mut callback: C) -> Result<(), Error>
     where
         C: FnMut(&ServiceInstance) -> ControlFlow<()>,
     {
@@ -152,7 +297,7 @@ impl SyncDiscoverer {
         domain.extend(&self.domain);
 
         let mut instances = BTreeMap::new();
-        self.send_query(&domain, &[QType::PTR], &mut |record| {
+        let mut on_record = |record: Record<'_>| {
             let ptr = match record {
                 Record::PTR(ptr) => ptr,
                 _ => return ControlFlow::Continue(()),
@@ -178,6 +323,9 @@ impl SyncDiscoverer {
                     ControlFlow::Continue(())
                 }
             }
+        };
+        self.send_query(&domain, &[QType::PTR], &mut |msg| {
+            decode_answer(msg, &mut on_record)
         })
     }
 
@@ -187,60 +335,158 @@ impl SyncDiscoverer {
     /// mostly intended for maintenance and debugging, since applications typically know the service
     /// types they support already.
     ///
+    /// When using mDNS, the answers of all responders are merged (see [`ServiceTypeCollector`]).
+    /// The query is repeated at increasing intervals, starting at [`MIN_QUERY_INTERVAL`], and lists
+    /// the service types that were already discovered, so that responders only send new ones.
+    ///
     /// To discover *service instances*, use [`SyncDiscoverer::discover_instances`] instead.
-    pub fn discover_service_types<C>(&mut self, mut callback: C) -> io::Result<()>
+    pub fn discover_service_types<C>(&mut self, mut callback: C) -> Result<(), Error>
     where
         C: FnMut(&Service) -> ControlFlow<()>,
     {
-        let mut domain = DomainName::from_str("_services._dns-sd._udp").unwrap();
-        domain.extend(&self.domain);
-        let mut service_types = BTreeMap::new();
-        self.send_query(&domain, &[QType::PTR], &mut |record| {
-            let ptr = match record {
-                Record::PTR(ptr) => ptr,
-                _ => return ControlFlow::Continue(()),
-            };
-            let service = match Service::from_ptr(ptr) {
-                Ok(service) => service,
-                Err(e) => {
-                    log::warn!("failed to decode service: {:?}", e);
-                    return ControlFlow::Continue(());
+        let mut collector = ServiceTypeCollector::new(&self.domain);
+        collector.set_multicast(self.server.ip().is_multicast());
+        if self.server.ip().is_multicast() {
+            let retransmit_timeout = self.sock.read_timeout()?;
+            let res = self.enumerate_multicast(&mut collector, &mut callback);
+            self.sock.set_read_timeout(retransmit_timeout)?;
+            res
+        } else {
+            let domain = collector.enumeration_domain().clone();
+            self.send_query(&domain, &[QType::PTR], &mut |msg| {
+                collector.add_response(msg, &mut callback)
+            })
+        }
+    }
+
+    /// Sends all questions in `batch` at once, and passes the records answering each of them to its
+    /// callback.
+    ///
+    /// This saves round trips for operations that need several pieces of information at once, like
+    /// browsing for instances while also resolving a known one. Returns once every callback has
+    /// returned [`ControlFlow::Break`], or when the discovery timeout expires.
+    ///
+    /// See [`QueryBatch`] for how records are matched to questions.
+    pub fn query_batch(&mut self, batch: &mut QueryBatch<'_>) -> Result<(), Error> {
+        if batch.is_done() {
+            return Ok(());
+        }
+        let id = self.query_id.unwrap_or_else(Header::random_id);
+        batch.set_multicast(self.server.ip().is_multicast());
+        let mut send_buf = [0; MDNS_BUFFER_SIZE];
+        let mut packets = Vec::new();
+        batch.encode_queries(id, &mut send_buf, self.edns_payload_size(), |packet| {
+            packets.push(packet.to_vec())
+        })?;
+        let packets = packets.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        trace_span!("batch_query", questions = batch.len(), id, server = %self.server);
+        self.exchange(&packets, id, &mut |msg| batch.add_response(msg))
+    }
+
+    /// Runs a continuous mDNS service type enumeration query until the discovery timeout expires.
+    fn enumerate_multicast(
+        &mut self,
+        collector: &mut ServiceTypeCollector,
+        callback: &mut dyn FnMut(&Service) -> ControlFlow<()>,
+    ) -> Result<(), Error> {
+        let id = self.query_id.unwrap_or_else(Header::random_id);
+        trace_span!("service_type_enumeration", id, server = %self.server);
+
+        let discovery_start = self.clock.now();
+        let mut next_query = discovery_start;
+        let mut interval = MIN_QUERY_INTERVAL;
+        let mut send_buf = vec![0; self.max_message_size];
+        let mut recv_buf = vec![0; self.max_message_size];
+        loop {
+            let elapsed = self.clock.elapsed_since(discovery_start);
+            if elapsed >= self.discovery_timeout {
+                return Ok(());
+            }
+            if self.clock.now() >= next_query {
+                let mut res = Ok(());
+                collector.encode_queries(id, &mut send_buf, |packet| {
+                    if res.is_ok() {
+                        res = self.sock.send_to(packet, self.server).map(drop);
+                    }
+                })?;
+                res?;
+                next_query = self.clock.now() + interval;
+                interval *= 2;
+            }
+
+            // Wake up for the next query, or when discovery ends (whichever is first). Socket
+            // timeouts must not be zero.
+            let wait = next_query
+                .saturating_duration_since(self.clock.now())
+                .min(self.discovery_timeout - elapsed)
+                .max(Duration::from_millis(1));
+            self.sock.set_read_timeout(Some(wait))?;
+            let (b, addr) = match self.sock.recv_from(&mut recv_buf) {
+                Ok(res) => res,
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    continue;
                 }
+                Err(e) => return Err(e.into()),
             };
-            match service_types.entry(service) {
-                Entry::Vacant(e) => {
-                    let flow = callback(e.key());
-                    e.insert(());
-                    flow
-                }
-                Entry::Occupied(_) => {
-                    // Already discovered this service
-                    ControlFlow::Continue(())
-                }
+            let recv = &recv_buf[..b];
+            log::trace!("recv from {}: {}", addr, Hex(recv));
+            trace_event!(from = %addr, len = b, "received response");
+
+            match collector.add_response(recv, callback) {
+                Ok(ControlFlow::Continue(())) => {}
+                Ok(ControlFlow::Break(())) => return Ok(()),
+                Err(err) => log::warn!("failed to decode response: {:?}", err),
             }
-        })
+        }
     }
 
     fn send_query(
         &mut self,
         domain: &DomainName,
         qtypes: &[QType],
-        callback: &mut dyn FnMut(Record<'_>) -> ControlFlow<()>,
-    ) -> io::Result<()> {
+        on_response: &mut OnResponse<'_>,
+    ) -> Result<(), Error> {
         let mut send_buf = [0; MDNS_BUFFER_SIZE];
-        let data = encode_query(&mut send_buf, domain, qtypes);
+        let id = self.query_id.unwrap_or_else(Header::random_id);
+        let data =
+            encode_query_with_id(&mut send_buf, id, domain, qtypes, self.edns_payload_size());
+        trace_span!("discovery_query", %domain, ?qtypes, id, server = %self.server);
+        self.exchange(&[data], id, on_response)
+    }
 
-        let discovery_start = Instant::now();
+    /// Returns the UDP payload size to advertise via EDNS(0), if any.
+    ///
+    /// This is only needed for unicast DNS servers that have to send more than the default.
+    fn edns_payload_size(&self) -> Option<u16> {
+        let unicast = !self.server.ip().is_multicast();
+        (unicast && self.max_message_size > DNS_BUFFER_SIZE).then_some(self.max_message_size as u16)
+    }
+
+    /// Sends `packets` to the server, retransmitting them until `on_response` returns
+    /// [`ControlFlow::Break`] or the discovery timeout expires.
+    fn exchange(
+        &mut self,
+        packets: &[&[u8]],
+        id: u16,
+        on_response: &mut OnResponse<'_>,
+    ) -> Result<(), Error> {
+        let unicast = !self.server.ip().is_multicast();
+        let discovery_start = self.clock.now();
+        let mut recv_buf = vec![0; self.max_message_size];
         'retransmit: loop {
-            self.sock.send_to(data, self.server)?;
+            for packet in packets {
+                self.sock.send_to(packet, self.server)?;
+            }
 
             loop {
-                if discovery_start.elapsed() >= self.discovery_timeout {
+                if self.clock.elapsed_since(discovery_start) >= self.discovery_timeout {
                     // Max. discovery time exceeded.
                     return Ok(());
                 }
 
-                let mut recv_buf = [0; MDNS_BUFFER_SIZE];
                 let (b, addr) = match self.sock.recv_from(&mut recv_buf) {
                     Ok(res) => res,
                     Err(e)
@@ -249,12 +495,23 @@ impl SyncDiscoverer {
                     {
                         continue 'retransmit;
                     }
-                    Err(e) => return Err(e),
+                    Err(e) => return Err(e.into()),
                 };
                 let recv = &recv_buf[..b];
                 log::trace!("recv from {}: {}", addr, Hex(recv));
+                trace_event!(
+                    from = %addr,
+                    rtt_ms = self.clock.elapsed_since(discovery_start).as_millis() as u64,
+                    len = b,
+                    "received response",
+                );
+
+                if unicast && !is_response_to(recv, id) {
+                    log::debug!("ignoring message from {} with mismatched ID", addr);
+                    continue;
+                }
 
-                let res = decode_answer(recv, callback);
+                let res = on_response(recv);
 
                 match res {
                     Ok(ControlFlow::Continue(())) => {}
@@ -268,14 +525,37 @@ impl SyncDiscoverer {
     }
 }
 
+/// Writes a discovery query for `qtypes` of `domain` into `buf`, using a random message ID.
+///
+/// The given buffer must be large enough to fit the query, or this method will panic.
 pub fn encode_query<'a>(buf: &'a mut [u8], domain: &DomainName, qtypes: &[QType]) -> &'a [u8] {
+    encode_query_with_id(buf, Header::random_id(), domain, qtypes, None)
+}
+
+/// Writes a discovery query for `qtypes` of `domain` with message ID `id` into `buf`.
+///
+/// If `edns_payload_size` is [`Some`], an EDNS(0) `OPT` record advertising that UDP payload size
+/// is added to the query.
+///
+/// The given buffer must be large enough to fit the query, or this method will panic.
+pub fn encode_query_with_id<'a>(
+    buf: &'a mut [u8],
+    id: u16,
+    domain: &DomainName,
+    qtypes: &[QType],
+    edns_payload_size: Option<u16>,
+) -> &'a [u8] {
     let mut header = Header::default();
-    header.set_id(12345);
+    header.set_id(id);
     let mut enc = MessageEncoder::new(buf);
     enc.set_header(header);
     for qtype in qtypes {
         enc.question(encoder::Question::new(domain).ty(*qtype));
     }
+    let mut enc = enc.answers().authority().additional();
+    if let Some(size) = edns_payload_size {
+        enc.add_edns(size);
+    }
     let bytes = enc.finish().unwrap();
     let data = &buf[..bytes];
 
@@ -289,6 +569,18 @@ pub fn encode_query<'a>(buf: &'a mut [u8], domain: &DomainName, qtypes: &[QType]
     data
 }
 
+/// Returns whether `msg` is a response with message ID `id`.
+///
+/// Unicast DNS servers copy the ID of a query into their response, so this can be used to discard
+/// responses to other (or earlier) queries. mDNS responders generally ignore the ID of queries
+/// (RFC 6762, section 18.1), so responses received via multicast should not be filtered this way.
+pub fn is_response_to(msg: &[u8], id: u16) -> bool {
+    match MessageDecoder::new(msg) {
+        Ok(dec) => dec.header().is_response() && dec.header().id() == id,
+        Err(_) => false,
+    }
+}
+
 /// Decodes `recv` and invokes `callback` with every ANS record inside.
 pub fn decode_answer(
     recv: &[u8],
@@ -326,3 +618,1425 @@ pub fn decode_answer(
 
     Ok(ControlFlow::Continue(()))
 }
+
+/// A set of questions that are sent together, each with its own callback.
+///
+/// Records from the *Answer* and *Additional Records* sections of responses are passed to the
+/// callback of every question they answer, that is, when their owner name matches the question
+/// (ignoring ASCII case) and [`QType::is_answered_by`] accepts their type. A question is done once
+/// its callback returns [`ControlFlow::Break`], and the batch is done once all questions are.
+///
+/// Use [`SyncDiscoverer::query_batch`] to send a batch, or [`QueryBatch::encode_queries`] and
+/// [`QueryBatch::add_response`] to drive it manually.
+pub struct QueryBatch<'a> {
+    questions: Vec<BatchQuestion<'a>>,
+    multicast: bool,
+}
+
+struct BatchQuestion<'a> {
+    name: DomainName,
+    qtype: QType,
+    on_record: Box<OnRecord<'a>>,
+    done: bool,
+}
+
+impl<'a> QueryBatch<'a> {
+    /// Creates an empty batch.
+    pub fn new() -> Self {
+        Self {
+            questions: Vec::new(),
+            multicast: false,
+        }
+    }
+
+    /// Adds a question for records of type `qtype` owned by `name`, and the callback to invoke with
+    /// every record answering it.
+    pub fn add(
+        &mut self,
+        name: DomainName,
+        qtype: QType,
+        on_record: impl FnMut(&ResourceRecord<'_>) -> ControlFlow<()> + Send + 'a,
+    ) {
+        self.questions.push(BatchQuestion {
+            name,
+            qtype,
+            on_record: Box::new(on_record),
+            done: false,
+        });
+    }
+
+    /// Returns the number of questions in this batch.
+    pub fn len(&self) -> usize {
+        self.questions.len()
+    }
+
+    /// Returns whether this batch contains no questions.
+    pub fn is_empty(&self) -> bool {
+        self.questions.is_empty()
+    }
+
+    /// Returns whether the callbacks of all questions have returned [`ControlFlow::Break`].
+    pub fn is_done(&self) -> bool {
+        self.questions.iter().all(|q| q.done)
+    }
+
+    /// Sets whether the batch is sent via mDNS.
+    ///
+    /// Unicast DNS servers generally only answer the first question of a message, so unless this
+    /// is set, [`QueryBatch::encode_queries`] puts every question into a message of its own.
+    /// Defaults to `false`.
+    pub fn set_multicast(&mut self, multicast: bool) {
+        self.multicast = multicast;
+    }
+
+    /// Encodes the questions that aren't done yet with message ID `id`, and passes the resulting
+    /// messages to `on_packet`.
+    ///
+    /// If `edns_payload_size` is [`Some`], an EDNS(0) `OPT` record advertising that UDP payload size
+    /// is added to every message. Returns [`Error::Truncated`] if a message doesn't fit into `buf`.
+    pub fn encode_queries(
+        &self,
+        id: u16,
+        buf: &mut [u8],
+        edns_payload_size: Option<u16>,
+        mut on_packet: impl FnMut(&[u8]),
+    ) -> Result<(), Error> {
+        let pending = self
+            .questions
+            .iter()
+            .filter(|q| !q.done)
+            .collect::<Vec<_>>();
+        let per_message = if self.multicast { pending.len() } else { 1 };
+        for chunk in pending.chunks(per_message.max(1)) {
+            let mut header = Header::default();
+            header.set_id(id);
+            let mut enc = MessageEncoder::new(&mut *buf);
+            enc.set_header(header);
+            for q in chunk {
+                enc.question(encoder::Question::new(&q.name).ty(q.qtype));
+            }
+            let mut enc = enc.answers().authority().additional();
+            if let Some(size) = edns_payload_size {
+                enc.add_edns(size);
+            }
+            let len = enc.finish()?;
+            on_packet(&buf[..len]);
+        }
+        Ok(())
+    }
+
+    /// Passes the records in the DNS message `msg` to the callbacks of the questions they answer.
+    ///
+    /// Returns [`ControlFlow::Break`] once all questions are done. Messages that aren't responses
+    /// are ignored.
+    pub fn add_response(&mut self, msg: &[u8]) -> Result<ControlFlow<()>, Error> {
+        let dec = MessageDecoder::new(msg)?;
+        if !dec.header().is_response() {
+            return Ok(ControlFlow::Continue(()));
+        }
+
+        let mut dec = dec.answers()?;
+        for res in dec.iter() {
+            self.add_record(&res?);
+        }
+        let mut dec = dec.additional()?;
+        for res in dec.iter() {
+            self.add_record(&res?);
+        }
+        Ok(match self.is_done() {
+            true => ControlFlow::Break(()),
+            false => ControlFlow::Continue(()),
+        })
+    }
+
+    fn add_record(&mut self, rr: &ResourceRecord<'_>) {
+        for q in &mut self.questions {
+            if q.done
+                || !q.qtype.is_answered_by(rr.type_())
+                || !rr.name().eq_ignore_ascii_case(&q.name)
+            {
+                continue;
+            }
+            q.done = (q.on_record)(rr).is_break();
+        }
+    }
+}
+
+impl Default for QueryBatch<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Assembles [`InstanceDetails`] of a service instance from one or more DNS responses.
+///
+/// All [`SRV`] records for the instance become [`ServiceTarget`]s, and `A`/`AAAA` records for
+/// their hosts (typically included as additional records by mDNS responders) are attached to
+/// them. Records for other names are ignored, so responses from unrelated queries can be fed in.
+///
+/// [`SRV`]: crate::packet::records::SRV
+pub struct DetailsCollector {
+    instance_domain: DomainName,
+    targets: Vec<ServiceTarget>,
+    txt: Option<TxtRecords>,
+    addrs: Vec<(DomainName, IpAddr)>,
+    ttl: Option<u32>,
+}
+
+impl DetailsCollector {
+    /// Creates a collector for the service instance with the fully-qualified name
+    /// `instance_domain` (eg. `My Printer._ipp._tcp.local`).
+    pub fn new(instance_domain: DomainName) -> Self {
+        Self {
+            instance_domain,
+            targets: Vec::new(),
+            txt: None,
+            addrs: Vec::new(),
+            ttl: None,
+        }
+    }
+
+    /// Processes the *Answer* and *Additional Records* sections of the DNS message `msg`.
+    ///
+    /// Messages that aren't responses are ignored.
+    pub fn add_response(&mut self, msg: &[u8]) -> Result<(), Error> {
+        let dec = MessageDecoder::new(msg)?;
+        if !dec.header().is_response() {
+            return Ok(());
+        }
+
+        let mut dec = dec.answers()?;
+        for res in dec.iter() {
+            self.add_record(&res?);
+        }
+        let mut dec = dec.additional()?;
+        for res in dec.iter() {
+            self.add_record(&res?);
+        }
+        Ok(())
+    }
+
+    fn add_record(&mut self, rr: &ResourceRecord<'_>) {
+        let record = match rr.as_enum() {
+            Some(Ok(record)) => record,
+            Some(Err(e)) => {
+                log::debug!("failed to decode RR: {:?}", e);
+                return;
+            }
+            None => return,
+        };
+        if matches!(record, Record::SRV(_) | Record::TXT(_)) && rr.name() == &self.instance_domain {
+            self.ttl = Some(self.ttl.map_or(rr.ttl(), |ttl| ttl.min(rr.ttl())));
+        }
+        match record {
+            Record::SRV(srv) if rr.name() == &self.instance_domain => {
+                let target = ServiceTarget::from_srv(&srv);
+                if !self
+                    .targets
+                    .iter()
+                    .any(|t| t.host() == target.host() && t.port() == target.port())
+                {
+                    self.targets.push(target);
+                }
+            }
+            // Only the first TXT record is used; there shouldn't be more than one.
+            Record::TXT(txt) if rr.name() == &self.instance_domain && self.txt.is_none() => {
+                self.txt = Some(TxtRecords::from_txt(&txt));
+            }
+            Record::A(a) => self.addrs.push((rr.name().clone(), a.addr().into())),
+            Record::AAAA(aaaa) => self.addrs.push((rr.name().clone(), aaaa.addr().into())),
+            _ => {}
+        }
+    }
+
+    /// Returns whether both the [`SRV`] and the TXT record of the instance have been received.
+    ///
+    /// Addresses are attached on a best-effort basis and aren't required for completion.
+    ///
+    /// [`SRV`]: crate::packet::records::SRV
+    pub fn is_complete(&self) -> bool {
+        !self.targets.is_empty() && self.txt.is_some()
+    }
+
+    /// Returns the lowest TTL of the instance's [`SRV`] and TXT records received so far.
+    ///
+    /// This is how long the collected details remain valid.
+    ///
+    /// [`SRV`]: crate::packet::records::SRV
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl.map(|ttl| Duration::from_secs(ttl.into()))
+    }
+
+    /// Returns the collected [`InstanceDetails`], or [`None`] if no [`SRV`] record was received.
+    ///
+    /// [`SRV`]: crate::packet::records::SRV
+    pub fn finish(self) -> Option<InstanceDetails> {
+        let mut targets = self.targets.into_iter();
+        let mut details = InstanceDetails::from_target(targets.next()?);
+        for target in targets {
+            details.add_target(target);
+        }
+        for target in details.targets_mut() {
+            for (name, addr) in &self.addrs {
+                if name == target.host() {
+                    target.add_addr(*addr);
+                }
+            }
+        }
+        if let Some(txt) = self.txt {
+            *details.txt_records_mut() = txt;
+        }
+        Some(details)
+    }
+}
+
+/// A cache of [`InstanceDetails`], each valid for the TTL of the records they were loaded from.
+///
+/// Discoverers use this to answer repeated [`SyncDiscoverer::load_instance_details`] calls without
+/// querying the network again.
+#[derive(Debug, Clone, Default)]
+pub struct DetailsCache {
+    entries: BTreeMap<ServiceInstance, CachedDetails>,
+}
+
+impl DetailsCache {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the cached details of `instance`, unless they have expired at `now`.
+    pub fn get(&self, instance: &ServiceInstance, now: Instant) -> Option<&CachedDetails> {
+        self.entries
+            .get(instance)
+            .filter(|cached| !cached.remaining_ttl(now).is_zero())
+    }
+
+    /// Caches `details` of `instance`, which were received at `now` and are valid for `ttl`.
+    ///
+    /// Replaces any previously cached details of `instance`. Expired entries are removed.
+    pub fn insert(
+        &mut self,
+        instance: ServiceInstance,
+        details: InstanceDetails,
+        ttl: Duration,
+        now: Instant,
+    ) {
+        self.entries
+            .retain(|_, cached| !cached.remaining_ttl(now).is_zero());
+        if !ttl.is_zero() {
+            let cached = CachedDetails {
+                details,
+                received_at: now,
+                ttl,
+                unreachable: Vec::new(),
+            };
+            self.entries.insert(instance, cached);
+        }
+    }
+
+    /// Records that `target` of `instance` could not be reached, and returns the next target to try.
+    ///
+    /// The returned target is the most preferred one (see [`InstanceDetails::targets_by_preference`])
+    /// that hasn't been reported as unreachable yet. Returns [`None`] once all targets have been
+    /// reported, or if no details of `instance` are cached at `now`. Loading the details again
+    /// forgets about all unreachable targets.
+    pub fn report_unreachable(
+        &mut self,
+        instance: &ServiceInstance,
+        target: &ServiceTarget,
+        now: Instant,
+    ) -> Option<&ServiceTarget> {
+        let cached = self
+            .entries
+            .get_mut(instance)
+            .filter(|cached| !cached.remaining_ttl(now).is_zero())?;
+        if !cached.is_unreachable(target) {
+            cached
+                .unreachable
+                .push((target.host().clone(), target.port()));
+        }
+        cached.next_target()
+    }
+
+    /// Removes the cached details of `instance`.
+    ///
+    /// Returns whether any (possibly expired) details were cached.
+    pub fn remove(&mut self, instance: &ServiceInstance) -> bool {
+        self.entries.remove(instance).is_some()
+    }
+
+    /// Removes all cached details.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Writes the details that haven't expired at `now` to `writer`.
+    ///
+    /// Each entry is stored along with its remaining TTL, and the wall-clock time of the save is
+    /// recorded, so that [`DetailsCache::load`] can tell how much of the TTL is left by the time
+    /// the cache is loaded again (typically by a later invocation of the same program).
+    ///
+    /// The format is versioned; files written by incompatible versions of this library are
+    /// rejected when loading.
+    #[cfg(any(test, feature = "persistent-cache"))]
+    pub fn save(&self, writer: impl io::Write, now: Instant) -> Result<(), Error> {
+        let mut entries = Vec::new();
+        for (instance, cached) in &self.entries {
+            let ttl = cached.remaining_ttl(now).as_secs();
+            if ttl == 0 {
+                continue;
+            }
+            let ttl = u32::try_from(ttl).unwrap_or(u32::MAX);
+            entries.push(encode_cached_details(instance, cached.details(), ttl)?);
+        }
+        crate::cache_file::write(
+            writer,
+            crate::cache_file::CacheKind::Details,
+            entries.iter().map(Vec::as_slice),
+        )
+    }
+
+    /// Loads a cache previously written by [`DetailsCache::save`] from `reader`.
+    ///
+    /// Entries are treated as if they were received at `now`, with their TTLs reduced by the
+    /// (wall-clock) time that has passed since the cache was saved. Entries that have expired in
+    /// the meantime are dropped.
+    ///
+    /// Returns [`Error::InvalidValue`] if `reader` does not contain a details cache in a format
+    /// understood by this version of the library. Callers should usually just start with an empty
+    /// cache in that case.
+    #[cfg(any(test, feature = "persistent-cache"))]
+    pub fn load(reader: impl io::Read, now: Instant) -> Result<Self, Error> {
+        let (age, entries) =
+            crate::cache_file::read(reader, crate::cache_file::CacheKind::Details)?;
+        let mut cache = Self::new();
+        for msg in entries {
+            let mut dec = MessageDecoder::new(&msg)?;
+            let question = dec.iter().next().ok_or(Error::Eof)??;
+            let instance_domain = question.qname().clone();
+            let instance = instance_from_domain(&instance_domain).ok_or(Error::InvalidValue)?;
+
+            let mut collector = DetailsCollector::new(instance_domain);
+            collector.add_response(&msg)?;
+            let ttl = collector.ttl().unwrap_or_default().saturating_sub(age);
+            if let Some(details) = collector.finish() {
+                cache.insert(instance, details, ttl, now);
+            }
+        }
+        Ok(cache)
+    }
+}
+
+/// Encodes `details` of `instance` as a DNS response, the way [`DetailsCache::save`] stores them.
+///
+/// The question is the instance name without a domain; the answers are its `SRV` and `TXT`
+/// records, and the additional section contains the addresses of the targets.
+#[cfg(any(test, feature = "persistent-cache"))]
+fn encode_cached_details(
+    instance: &ServiceInstance,
+    details: &InstanceDetails,
+    ttl: u32,
+) -> Result<Vec<u8>, Error> {
+    use crate::packet::records::{A, AAAA};
+
+    let domain = DomainName::from_iter([
+        instance.instance_name(),
+        instance.service().name(),
+        &instance.service().transport().to_label(),
+    ]);
+    let srvs = details
+        .targets()
+        .iter()
+        .map(|target| Record::SRV(target.to_srv()))
+        .collect::<Vec<_>>();
+    let txt = Record::TXT(details.txt_records().to_txt());
+    let addrs = details
+        .targets()
+        .iter()
+        .flat_map(|target| target.addrs().iter().map(move |addr| (target.host(), addr)))
+        .map(|(host, addr)| {
+            let record = match addr {
+                IpAddr::V4(ip) => Record::A(A::new(*ip)),
+                IpAddr::V6(ip) => Record::AAAA(AAAA::new(*ip)),
+            };
+            (host, record)
+        })
+        .collect::<Vec<_>>();
+
+    let mut buf = vec![0; usize::from(u16::MAX)];
+    let mut enc = MessageEncoder::new(&mut buf);
+    let mut header = Header::default();
+    header.set_response(true);
+    enc.set_header(header);
+    enc.question(encoder::Question::new(&domain).ty(QType::SRV));
+    let mut enc = enc.answers();
+    for srv in &srvs {
+        enc.add_answer(encoder::ResourceRecord::new(&domain, srv).ttl(ttl));
+    }
+    enc.add_answer(encoder::ResourceRecord::new(&domain, &txt).ttl(ttl));
+    let mut enc = enc.authority().additional();
+    for (host, record) in &addrs {
+        enc.add_additional(encoder::ResourceRecord::new(host, record).ttl(ttl));
+    }
+    let len = enc.finish()?;
+    buf.truncate(len);
+    Ok(buf)
+}
+
+/// Parses a domain name of the form `<instance>.<service>.<transport>`, without a domain.
+#[cfg(any(test, feature = "persistent-cache"))]
+fn instance_from_domain(domain: &DomainName) -> Option<ServiceInstance> {
+    let [instance, service, transport] = domain.labels() else {
+        return None;
+    };
+    if !service.as_bytes().starts_with(b"_") {
+        return None;
+    }
+    let transport = std::str::from_utf8(transport.as_bytes())
+        .ok()?
+        .parse()
+        .ok()?;
+    Some(ServiceInstance::new(
+        instance.clone(),
+        service.clone(),
+        transport,
+    ))
+}
+
+/// [`InstanceDetails`] stored in a [`DetailsCache`], along with when they were received.
+///
+/// When passing cached details on (for example, in DNS responses), use
+/// [`CachedDetails::remaining_ttl`] rather than [`CachedDetails::original_ttl`], so that
+/// downstream caches don't keep them for longer than the records' owner intended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedDetails {
+    details: InstanceDetails,
+    received_at: Instant,
+    ttl: Duration,
+    /// Host and port of the targets reported via [`DetailsCache::report_unreachable`].
+    unreachable: Vec<(DomainName, u16)>,
+}
+
+impl CachedDetails {
+    /// Returns the cached [`InstanceDetails`].
+    #[inline]
+    pub fn details(&self) -> &InstanceDetails {
+        &self.details
+    }
+
+    /// Returns the point in time at which the details were received.
+    #[inline]
+    pub fn received_at(&self) -> Instant {
+        self.received_at
+    }
+
+    /// Returns the TTL the details had when they were received.
+    #[inline]
+    pub fn original_ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Returns the most preferred target that hasn't been reported as unreachable.
+    ///
+    /// Returns [`None`] if all targets have been reported (see
+    /// [`DetailsCache::report_unreachable`]).
+    pub fn next_target(&self) -> Option<&ServiceTarget> {
+        self.details
+            .targets_by_preference()
+            .into_iter()
+            .find(|target| !self.is_unreachable(target))
+    }
+
+    fn is_unreachable(&self, target: &ServiceTarget) -> bool {
+        self.unreachable
+            .iter()
+            .any(|(host, port)| *port == target.port() && host.eq_ignore_ascii_case(target.host()))
+    }
+
+    /// Returns the part of the TTL that is left at `now`.
+    ///
+    /// Returns [`Duration::ZERO`] once the details have expired.
+    pub fn remaining_ttl(&self, now: Instant) -> Duration {
+        self.ttl
+            .saturating_sub(now.saturating_duration_since(self.received_at))
+    }
+}
+
+/// Checks whether a [`ServiceTarget`] can be reached, for [`SyncDiscoverer::set_liveness_probe`].
+///
+/// This is implemented for all closures with a matching signature, which allows checking
+/// services in a protocol-specific way (for example, by sending an HTTP request).
+pub trait LivenessProbe: Send {
+    /// Checks whether `target` is reachable, returning an error if it isn't.
+    fn check(&mut self, target: &ServiceTarget) -> Result<(), Error>;
+}
+
+impl<F: FnMut(&ServiceTarget) -> Result<(), Error> + Send> LivenessProbe for F {
+    fn check(&mut self, target: &ServiceTarget) -> Result<(), Error> {
+        self(target)
+    }
+}
+
+/// A [`LivenessProbe`] that considers a target reachable if a TCP connection to it can be opened.
+///
+/// The connection is closed again right away. Each address of the target is tried in turn.
+/// Targets without any known addresses can't be probed and are considered reachable; use
+/// [`resolve_targets`] to look their addresses up first.
+#[derive(Debug, Clone)]
+pub struct TcpConnectProbe {
+    timeout: Duration,
+}
+
+impl TcpConnectProbe {
+    const DEFAULT_TIMEOUT: Duration = Duration::from_millis(500);
+
+    /// Creates a probe with the default connection timeout of 500 ms.
+    pub fn new() -> Self {
+        Self {
+            timeout: Self::DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Sets how long to wait for each connection attempt.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+}
+
+impl Default for TcpConnectProbe {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LivenessProbe for TcpConnectProbe {
+    fn check(&mut self, target: &ServiceTarget) -> Result<(), Error> {
+        let mut error = None;
+        for &ip in target.addrs() {
+            match TcpStream::connect_timeout(&SocketAddr::new(ip, target.port()), self.timeout) {
+                Ok(_) => return Ok(()),
+                Err(e) => error = Some(e),
+            }
+        }
+        match error {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Resolves the addresses of the targets of `details` whose addresses aren't known yet.
+///
+/// mDNS responders usually include the addresses of their targets in their responses, but targets
+/// of services discovered via unicast DNS (wide-area DNS-SD) often point to hosts outside of
+/// `.local`, whose addresses have to be looked up separately. Targets in the `.local` domain are
+/// resolved via `mdns`, and all other targets via `unicast`.
+///
+/// Targets whose addresses are already known, and the root target `.` (which signals that the
+/// service is unavailable), are skipped. Failure to resolve a target does not prevent the others
+/// from being resolved, but if no target has any addresses at the end, the last error is returned.
+///
+/// # Example
+///
+/// ```no_run
+/// # use uwuhi::{resolver::SyncResolver, service::discovery::{resolve_targets, SyncDiscoverer}};
+/// # fn main() -> Result<(), uwuhi::Error> {
+/// # let instance = todo!();
+/// let mut discoverer = SyncDiscoverer::new("192.168.0.1:53".parse().unwrap(), "example.com".parse()?)?;
+/// let mut details = discoverer.load_instance_details(&instance)?;
+///
+/// let mut mdns = SyncResolver::new_multicast_v4()?;
+/// let mut unicast = SyncResolver::new("192.168.0.1:53".parse().unwrap())?;
+/// resolve_targets(&mut details, &mut mdns, &mut unicast)?;
+/// println!("{:?}", details.best_target().addrs());
+/// # Ok(()) }
+/// ```
+pub fn resolve_targets(
+    details: &mut InstanceDetails,
+    mdns: &mut dyn Resolve,
+    unicast: &mut dyn Resolve,
+) -> Result<(), Error> {
+    let mut error = None;
+    for target in details.targets_mut() {
+        if !target.addrs().is_empty() || target.host().labels().is_empty() {
+            continue;
+        }
+        let is_local = target
+            .host()
+            .labels()
+            .last()
+            .is_some_and(|l| l.as_bytes().eq_ignore_ascii_case(b"local"));
+        let res = match is_local {
+            true => mdns.resolve_name(target.host()),
+            false => unicast.resolve_name(target.host()),
+        };
+        match res {
+            Ok(addrs) => {
+                for addr in addrs {
+                    target.add_addr(addr);
+                }
+            }
+            Err(e) => {
+                log::debug!("failed to resolve target '{}': {}", target.host(), e);
+                error = Some(e);
+            }
+        }
+    }
+    match error {
+        Some(e) if details.targets().iter().all(|t| t.addrs().is_empty()) => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Minimum time between the first two queries of a continuous mDNS query ([RFC 6762, section
+/// 5.2]). The interval doubles after every query.
+///
+/// [RFC 6762, section 5.2]: https://datatracker.ietf.org/doc/html/rfc6762#section-5.2
+pub const MIN_QUERY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Collects the service types advertised on a domain from one or more DNS responses.
+///
+/// mDNS responders answer service type enumeration queries for `_services._dns-sd._udp` with
+/// shared `PTR` records. Many responders usually answer, each at a slightly different time, and a
+/// single responder may spread its answers across several messages. This type merges the `PTR`
+/// records from the *Answer* and *Additional Records* sections of all responses it is fed. For
+/// mDNS (see [`ServiceTypeCollector::set_multicast`]), it also forgets service types again when a
+/// responder announces their removal (a "goodbye" record with a TTL of 0).
+///
+/// Continuous queries built with [`ServiceTypeCollector::encode_queries`] list every service type
+/// collected so far as a known answer, so responders only send the ones that are still missing.
+pub struct ServiceTypeCollector {
+    enumeration_domain: DomainName,
+    multicast: bool,
+    /// Service types in discovery order, with their `PTR` record and its TTL.
+    service_types: Vec<(Service, Record<'static>, u32)>,
+}
+
+impl ServiceTypeCollector {
+    /// Creates a collector for the service types advertised on `domain` (eg. `local`).
+    pub fn new(domain: &DomainName) -> Self {
+        let mut enumeration_domain = domain!("_services._dns-sd._udp");
+        enumeration_domain.extend(domain);
+        Self {
+            enumeration_domain,
+            multicast: false,
+            service_types: Vec::new(),
+        }
+    }
+
+    /// Sets whether responses are received via mDNS.
+    ///
+    /// In mDNS, records with a TTL of 0 announce that the record is going away ([RFC 6762, section
+    /// 10.1]), so they remove the service type instead of adding it. In unicast DNS, a TTL of 0
+    /// just means that the record must not be cached. Defaults to `false`.
+    ///
+    /// [RFC 6762, section 10.1]: https://datatracker.ietf.org/doc/html/rfc6762#section-10.1
+    pub fn set_multicast(&mut self, multicast: bool) {
+        self.multicast = multicast;
+    }
+
+    /// Returns the domain name that service type enumeration queries ask for.
+    #[inline]
+    pub fn enumeration_domain(&self) -> &DomainName {
+        &self.enumeration_domain
+    }
+
+    /// Returns an iterator over the service types collected so far, in the order they were
+    /// discovered.
+    pub fn service_types(&self) -> impl Iterator<Item = &Service> {
+        self.service_types.iter().map(|(service, ..)| service)
+    }
+
+    /// Processes the *Answer* and *Additional Records* sections of the DNS message `msg`, and
+    /// invokes `on_new` with every service type that wasn't known before.
+    ///
+    /// Returns [`ControlFlow::Break`] as soon as `on_new` does. Messages that aren't responses are
+    /// ignored.
+    pub fn add_response(
+        &mut self,
+        msg: &[u8],
+        on_new: &mut dyn FnMut(&Service) -> ControlFlow<()>,
+    ) -> Result<ControlFlow<()>, Error> {
+        let dec = MessageDecoder::new(msg)?;
+        if !dec.header().is_response() {
+            return Ok(ControlFlow::Continue(()));
+        }
+
+        let mut dec = dec.answers()?;
+        for res in dec.iter() {
+            if self.add_record(&res?, on_new).is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+        let mut dec = dec.additional()?;
+        for res in dec.iter() {
+            if self.add_record(&res?, on_new).is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    fn add_record(
+        &mut self,
+        rr: &ResourceRecord<'_>,
+        on_new: &mut dyn FnMut(&Service) -> ControlFlow<()>,
+    ) -> ControlFlow<()> {
+        if !rr.name().eq_ignore_ascii_case(&self.enumeration_domain) {
+            return ControlFlow::Continue(());
+        }
+        let ptr = match rr.as_enum() {
+            Some(Ok(Record::PTR(ptr))) => ptr,
+            Some(Err(e)) => {
+                log::debug!("failed to decode RR: {:?}", e);
+                return ControlFlow::Continue(());
+            }
+            _ => return ControlFlow::Continue(()),
+        };
+        let known = self.service_types.iter().position(|(_, record, _)| {
+            matches!(record, Record::PTR(known) if known.ptrdname().eq_ignore_ascii_case(ptr.ptrdname()))
+        });
+        match (known, rr.ttl()) {
+            (Some(i), 0) if self.multicast => {
+                log::debug!("service type {} was removed", self.service_types[i].0);
+                self.service_types.remove(i);
+                ControlFlow::Continue(())
+            }
+            (Some(i), ttl) => {
+                self.service_types[i].2 = ttl;
+                ControlFlow::Continue(())
+            }
+            (None, 0) if self.multicast => ControlFlow::Continue(()),
+            (None, ttl) => {
+                let service = match Service::from_ptr(ptr.clone()) {
+                    Ok(service) => service,
+                    Err(e) => {
+                        log::warn!("failed to decode service: {:?}", e);
+                        return ControlFlow::Continue(());
+                    }
+                };
+                let flow = on_new(&service);
+                self.service_types
+                    .push((service, Record::PTR(ptr.into_owned()), ttl));
+                flow
+            }
+        }
+    }
+
+    /// Encodes a service type enumeration query with message ID `id`, listing all collected service
+    /// types as known answers, and passes the resulting messages to `on_packet`.
+    ///
+    /// Each message is at most `buf.len()` bytes long. If the known answers don't fit in a single
+    /// message, they are spread across several, as described in [RFC 6762, section 7.2].
+    ///
+    /// [RFC 6762, section 7.2]: https://datatracker.ietf.org/doc/html/rfc6762#section-7.2
+    pub fn encode_queries(
+        &self,
+        id: u16,
+        buf: &mut [u8],
+        on_packet: impl FnMut(&[u8]),
+    ) -> Result<(), Error> {
+        let mut header = Header::default();
+        header.set_id(id);
+        let mut query = QueryBuilder::new();
+        query.set_header(header);
+        query.question(encoder::Question::new(&self.enumeration_domain).ty(QType::PTR));
+        for (_, record, ttl) in &self.service_types {
+            query.known_answer(
+                encoder::ResourceRecord::new(&self.enumeration_domain, record).ttl(*ttl),
+            );
+        }
+        query.encode_packets(buf, on_packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::{
+        encoder,
+        records::{A, PTR, SRV, TXT},
+    };
+
+    use super::*;
+
+    fn response(
+        answers: &[(&DomainName, Record<'_>)],
+        additional: &[(&DomainName, Record<'_>)],
+    ) -> Vec<u8> {
+        let mut buf = [0; MDNS_BUFFER_SIZE];
+        let mut enc = MessageEncoder::new(&mut buf);
+        let mut header = Header::default();
+        header.set_response(true);
+        enc.set_header(header);
+        let mut enc = enc.answers();
+        for (name, rdata) in answers {
+            enc.add_answer(encoder::ResourceRecord::new(name, rdata).ttl(120));
+        }
+        let mut enc = enc.authority().additional();
+        for (name, rdata) in additional {
+            enc.add_additional(encoder::ResourceRecord::new(name, rdata).ttl(120));
+        }
+        let len = enc.finish().unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn caller_provided_socket() {
+        let server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let sock = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let local = sock.local_addr().unwrap();
+        let handle = sock.try_clone().unwrap();
+
+        let mut discoverer =
+            SyncDiscoverer::with_socket(sock, server.local_addr().unwrap(), domain!("local"))
+                .unwrap();
+        // The read timeout of the socket is overwritten.
+        assert_eq!(
+            handle.read_timeout().unwrap(),
+            Some(SyncDiscoverer::DEFAULT_RETRANSMIT_TIMEOUT)
+        );
+        discoverer
+            .set_retransmit_timeout(Duration::from_millis(20))
+            .unwrap();
+        assert_eq!(
+            handle.read_timeout().unwrap(),
+            Some(Duration::from_millis(20))
+        );
+
+        discoverer
+            .set_discovery_timeout(Duration::from_millis(50))
+            .unwrap();
+        discoverer
+            .discover_service_types(|_| ControlFlow::Continue(()))
+            .unwrap();
+        // The query was sent from the provided socket.
+        let mut buf = [0; DNS_BUFFER_SIZE];
+        let (_, source) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(source, local);
+    }
+
+    #[test]
+    fn collect_details() {
+        let instance = DomainName::from_str("inst._http._tcp.local").unwrap();
+        let other = DomainName::from_str("other._http._tcp.local").unwrap();
+        let primary = DomainName::from_str("primary.local").unwrap();
+        let backup = DomainName::from_str("backup.local").unwrap();
+
+        let mut collector = DetailsCollector::new(instance.clone());
+        collector
+            .add_response(&response(
+                &[
+                    (&other, Record::SRV(SRV::new(0, 0, 1, &other))),
+                    (&instance, Record::SRV(SRV::new(1, 0, 8080, &backup))),
+                ],
+                &[(&backup, Record::A(A::new(Ipv4Addr::new(10, 0, 0, 2))))],
+            ))
+            .unwrap();
+        assert!(!collector.is_complete());
+
+        collector
+            .add_response(&response(
+                &[
+                    (&instance, Record::SRV(SRV::new(0, 5, 80, &primary))),
+                    (&instance, Record::TXT(TXT::new([b"path=/"]))),
+                ],
+                &[(&primary, Record::A(A::new(Ipv4Addr::new(10, 0, 0, 1))))],
+            ))
+            .unwrap();
+        assert!(collector.is_complete());
+
+        let details = collector.finish().unwrap();
+        assert_eq!(details.targets().len(), 2);
+        assert_eq!(details.host(), &primary);
+        assert_eq!(details.port(), 80);
+        assert_eq!(
+            details.best_target().addrs(),
+            [IpAddr::from(Ipv4Addr::new(10, 0, 0, 1))]
+        );
+        assert_eq!(details.targets()[0].host(), &backup);
+        assert_eq!(details.targets()[0].addrs().len(), 1);
+        assert_eq!(details.txt_records().get_str("path"), Some("/"));
+    }
+
+    #[test]
+    fn query_batch() {
+        let services = DomainName::from_str("_http._tcp.local").unwrap();
+        let instance = DomainName::from_str("inst._http._tcp.local").unwrap();
+        let host = DomainName::from_str("host.local").unwrap();
+        let (mut ptrs, mut srvs) = (Vec::new(), Vec::new());
+        let mut batch = QueryBatch::new();
+        batch.add(services.clone(), QType::PTR, |rr| {
+            ptrs.push(rr.to_string());
+            ControlFlow::Continue(())
+        });
+        batch.add(instance.clone(), QType::SRV, |rr| {
+            srvs.push(rr.to_string());
+            ControlFlow::Break(())
+        });
+
+        let mut packets = Vec::new();
+        let mut buf = [0; MDNS_BUFFER_SIZE];
+        batch
+            .encode_queries(1, &mut buf, Some(1232), |p| packets.push(p.to_vec()))
+            .unwrap();
+        assert_eq!(packets.len(), 2);
+        batch.set_multicast(true);
+        packets.clear();
+        batch
+            .encode_queries(1, &mut buf, None, |p| packets.push(p.to_vec()))
+            .unwrap();
+        assert_eq!(packets.len(), 1);
+        let dec = MessageDecoder::new(&packets[0]).unwrap();
+        assert_eq!(dec.header().question_count(), 2);
+
+        // Both questions are answered by one response, partly via additional records.
+        let ptr = Record::PTR(PTR::new(instance.clone()));
+        let srv = Record::SRV(SRV::new(0, 0, 80, host.clone()));
+        let a = Record::A(A::new(Ipv4Addr::new(10, 0, 0, 1)));
+        let msg = response(&[(&services, ptr)], &[(&instance, srv), (&host, a)]);
+        assert_eq!(batch.add_response(&msg), Ok(ControlFlow::Continue(())));
+        assert!(!batch.is_done());
+
+        // Only the PTR question is still pending.
+        packets.clear();
+        batch
+            .encode_queries(1, &mut buf, None, |p| packets.push(p.to_vec()))
+            .unwrap();
+        let mut dec = MessageDecoder::new(&packets[0]).unwrap();
+        assert_eq!(dec.header().question_count(), 1);
+        assert_eq!(dec.next().unwrap().unwrap().qtype(), QType::PTR);
+
+        let other = Record::SRV(SRV::new(0, 0, 81, host.clone()));
+        let msg = response(&[(&instance, other)], &[]);
+        assert_eq!(batch.add_response(&msg), Ok(ControlFlow::Continue(())));
+        drop(batch);
+        expect_test::expect![[r#"
+            (
+                [
+                    "_http._tcp.local.\t120\tIN\tPTR\tinst._http._tcp.local.",
+                ],
+                [
+                    "inst._http._tcp.local.\t120\tIN\tSRV\t0\t0\t80\thost.local.",
+                ],
+            )
+        "#]]
+        .assert_debug_eq(&(ptrs, srvs));
+    }
+
+    #[test]
+    fn response_id() {
+        let domain = DomainName::from_str("_http._tcp.local").unwrap();
+        let mut buf = [0; MDNS_BUFFER_SIZE];
+        let query = encode_query_with_id(&mut buf, 42, &domain, &[QType::PTR], None);
+        assert!(!is_response_to(query, 42), "queries are not responses");
+
+        let mut response = response(&[], &[]);
+        response[..2].copy_from_slice(&42u16.to_be_bytes());
+        assert!(is_response_to(&response, 42));
+        assert!(!is_response_to(&response, 43));
+        assert!(!is_response_to(&[], 42));
+    }
+
+    #[test]
+    fn merge_service_types() {
+        let domain = DomainName::from_str("local").unwrap();
+        let enumeration = DomainName::from_str("_services._dns-sd._udp.local").unwrap();
+        let upper = DomainName::from_str("_SERVICES._dns-sd._udp.local").unwrap();
+        let ptr = |name: &str| Record::PTR(PTR::new(DomainName::from_str(name).unwrap()));
+
+        let mut collector = ServiceTypeCollector::new(&domain);
+        collector.set_multicast(true);
+        assert_eq!(collector.enumeration_domain(), &enumeration);
+        let mut new = Vec::new();
+        let mut on_new = |service: &Service| {
+            new.push(service.to_string());
+            ControlFlow::Continue(())
+        };
+        assert!(collector
+            .add_response(
+                &response(
+                    &[
+                        (&enumeration, ptr("_http._tcp.local")),
+                        (&enumeration, ptr("_ipp._tcp.local")),
+                    ],
+                    &[(&upper, ptr("_ssh._tcp.local"))],
+                ),
+                &mut on_new,
+            )
+            .unwrap()
+            .is_continue());
+        // A second responder repeats one of them with different case, and adds another.
+        assert!(collector
+            .add_response(
+                &response(
+                    &[
+                        (&upper, ptr("_HTTP._tcp.local")),
+                        (&enumeration, ptr("_airplay._tcp.local")),
+                        (&domain, ptr("_bogus._tcp.local")),
+                    ],
+                    &[],
+                ),
+                &mut on_new,
+            )
+            .unwrap()
+            .is_continue());
+        assert_eq!(
+            new,
+            ["_http._tcp", "_ipp._tcp", "_ssh._tcp", "_airplay._tcp"]
+        );
+        assert_eq!(collector.service_types().count(), 4);
+
+        // Every known service type is listed in continuous queries. With a small buffer, they are
+        // spread across several messages.
+        let mut packets = Vec::new();
+        collector
+            .encode_queries(7, &mut [0; 100], |packet| packets.push(packet.to_vec()))
+            .unwrap();
+        assert!(packets.len() > 1);
+        let mut known = 0;
+        for packet in &packets {
+            let dec = MessageDecoder::new(packet).unwrap();
+            assert_eq!(dec.header().id(), 7);
+            known += dec.header().answer_count();
+        }
+        assert_eq!(known, 4);
+
+        // Goodbye packets remove service types again.
+        let ipp = ptr("_ipp._tcp.local");
+        let mut goodbye = [0; 512];
+        let mut header = Header::default();
+        header.set_response(true);
+        let mut enc = MessageEncoder::new(&mut goodbye);
+        enc.set_header(header);
+        let mut enc = enc.answers();
+        enc.add_answer(encoder::ResourceRecord::new(&enumeration, &ipp).ttl(0));
+        let len = enc.finish().unwrap();
+        let goodbye = &goodbye[..len];
+        assert!(collector
+            .add_response(goodbye, &mut |_| unreachable!())
+            .unwrap()
+            .is_continue());
+        let types = collector
+            .service_types()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(types, ["_http._tcp", "_ssh._tcp", "_airplay._tcp"]);
+    }
+
+    #[test]
+    fn resolve_target_hosts() {
+        use crate::resolver::hosts::HostsFile;
+
+        let mut mdns = HostsFile::parse("10.0.0.1 printer.local\n10.0.0.9 known.local");
+        let mut unicast = HostsFile::parse("192.0.2.1 printer.example.com");
+
+        let mut details = InstanceDetails::new(DomainName::from_str("Printer.LOCAL").unwrap(), 631);
+        details.add_target(ServiceTarget::new(
+            DomainName::from_str("printer.example.com").unwrap(),
+            631,
+        ));
+        let mut known = ServiceTarget::new(DomainName::from_str("known.local").unwrap(), 631);
+        known.add_addr(Ipv4Addr::new(10, 0, 0, 2).into());
+        details.add_target(known);
+        details.add_target(ServiceTarget::new(DomainName::ROOT, 0));
+        resolve_targets(&mut details, &mut mdns, &mut unicast).unwrap();
+
+        let addrs = details
+            .targets()
+            .iter()
+            .map(|t| t.addrs().to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            addrs,
+            [
+                vec![IpAddr::from([10, 0, 0, 1])],
+                vec![IpAddr::from([192, 0, 2, 1])],
+                vec![IpAddr::from([10, 0, 0, 2])],
+                vec![],
+            ]
+        );
+    }
+
+    #[test]
+    fn remaining_ttl() {
+        use crate::{label, service::ServiceTransport};
+
+        let instance = ServiceInstance::new(label!("inst"), label!("_http"), ServiceTransport::TCP);
+        let details = InstanceDetails::new(DomainName::from_str("host.local").unwrap(), 80);
+        let received_at = Instant::now();
+        let mut cache = DetailsCache::new();
+        cache.insert(
+            instance.clone(),
+            details.clone(),
+            Duration::from_secs(120),
+            received_at,
+        );
+
+        let at = |secs| received_at + Duration::from_secs(secs);
+        let cached = cache.get(&instance, at(30)).unwrap();
+        assert_eq!(cached.details(), &details);
+        assert_eq!(cached.received_at(), received_at);
+        assert_eq!(cached.original_ttl(), Duration::from_secs(120));
+        assert_eq!(cached.remaining_ttl(received_at), Duration::from_secs(120));
+        assert_eq!(cached.remaining_ttl(at(30)), Duration::from_secs(90));
+        assert_eq!(
+            cached.remaining_ttl(at(30) + Duration::from_millis(500)),
+            Duration::from_millis(89_500)
+        );
+        assert_eq!(cached.remaining_ttl(at(120)), Duration::ZERO);
+        assert_eq!(cached.remaining_ttl(at(500)), Duration::ZERO);
+        assert_eq!(cache.get(&instance, at(120)), None);
+
+        // Details with a TTL of 0 aren't cached at all.
+        let mut cache = DetailsCache::new();
+        cache.insert(instance.clone(), details, Duration::ZERO, received_at);
+        assert_eq!(cache.get(&instance, received_at), None);
+    }
+
+    #[test]
+    fn unreachable_targets() {
+        use crate::{label, service::ServiceTransport};
+
+        let instance = ServiceInstance::new(label!("inst"), label!("_http"), ServiceTransport::TCP);
+        let target = |host: &str, priority, weight| {
+            let mut target = ServiceTarget::new(DomainName::from_str(host).unwrap(), 80);
+            target.set_priority(priority);
+            target.set_weight(weight);
+            target
+        };
+        let (light, heavy, backup) = (
+            target("light.local", 0, 10),
+            target("heavy.local", 0, 20),
+            target("backup.local", 1, 50),
+        );
+        let mut details = InstanceDetails::from_target(light.clone());
+        details.add_target(backup.clone());
+        details.add_target(heavy.clone());
+        assert_eq!(details.targets_by_preference(), [&heavy, &light, &backup]);
+
+        let now = Instant::now();
+        let ttl = Duration::from_secs(120);
+        let mut cache = DetailsCache::new();
+        cache.insert(instance.clone(), details.clone(), ttl, now);
+        assert_eq!(
+            cache.get(&instance, now).unwrap().next_target(),
+            Some(&heavy)
+        );
+        assert_eq!(
+            cache.report_unreachable(&instance, &heavy, now),
+            Some(&light)
+        );
+        assert_eq!(
+            cache.report_unreachable(&instance, &heavy, now),
+            Some(&light)
+        );
+        assert_eq!(
+            cache.report_unreachable(&instance, &light, now),
+            Some(&backup)
+        );
+        assert_eq!(cache.report_unreachable(&instance, &backup, now), None);
+        assert_eq!(cache.get(&instance, now).unwrap().next_target(), None);
+
+        // Fresh details start over, and expired ones can't be used.
+        cache.insert(instance.clone(), details, ttl, now);
+        assert_eq!(
+            cache.report_unreachable(&instance, &light, now),
+            Some(&heavy)
+        );
+        assert_eq!(cache.report_unreachable(&instance, &heavy, now + ttl), None);
+    }
+
+    #[test]
+    fn cached_details() {
+        use std::{sync::mpsc, thread};
+
+        use crate::{clock::ManualClock, label, service::ServiceTransport};
+
+        let instance = ServiceInstance::new(label!("inst"), label!("_http"), ServiceTransport::TCP);
+        let instance_domain = DomainName::from_str("inst._http._tcp.local").unwrap();
+        let host = DomainName::from_str("host.local").unwrap();
+        let mut resp = response(
+            &[
+                (&instance_domain, Record::SRV(SRV::new(0, 0, 80, &host))),
+                (&instance_domain, Record::TXT(TXT::new([b"path=/"]))),
+            ],
+            &[],
+        );
+
+        // Answers every query, and reports how many it received.
+        let server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = server.local_addr().unwrap();
+        let (queries, received) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0; MDNS_BUFFER_SIZE];
+            while let Ok((_, from)) = server.recv_from(&mut buf) {
+                queries.send(()).unwrap();
+                resp[..2].copy_from_slice(&buf[..2]);
+                server.send_to(&resp, from).unwrap();
+            }
+        });
+
+        let clock = ManualClock::new();
+        let mut discoverer = SyncDiscoverer::new(addr, domain!("local")).unwrap();
+        discoverer.set_clock(clock.clone());
+        let details = discoverer.load_instance_details(&instance).unwrap();
+        assert_eq!(details.host(), &host);
+        assert_eq!(received.try_iter().count(), 1);
+
+        let cached = discoverer.load_instance_details(&instance).unwrap();
+        assert_eq!(cached, details);
+        assert_eq!(received.try_iter().count(), 0);
+
+        discoverer
+            .load_instance_details_with(&instance, true)
+            .unwrap();
+        assert_eq!(received.try_iter().count(), 1);
+
+        // The records have a TTL of 120 seconds, which counts down while they're cached.
+        clock.advance(Duration::from_secs(119));
+        discoverer.load_instance_details(&instance).unwrap();
+        assert_eq!(received.try_iter().count(), 0);
+        let cached = discoverer.cached_instance_details(&instance).unwrap();
+        assert_eq!(cached.original_ttl(), Duration::from_secs(120));
+        assert_eq!(cached.remaining_ttl(clock.now()), Duration::from_secs(1));
+        assert_eq!(cached.received_at() + Duration::from_secs(119), clock.now());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(discoverer.cached_instance_details(&instance), None);
+        discoverer.load_instance_details(&instance).unwrap();
+        assert_eq!(received.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn liveness_probe() {
+        use std::{
+            net::TcpListener,
+            sync::{Arc, Mutex},
+            thread,
+        };
+
+        use crate::{label, service::ServiceTransport};
+
+        let instance = ServiceInstance::new(label!("inst"), label!("_http"), ServiceTransport::TCP);
+        let instance_domain = DomainName::from_str("inst._http._tcp.local").unwrap();
+        let (dead, live) = (
+            DomainName::from_str("dead.local").unwrap(),
+            DomainName::from_str("live.local").unwrap(),
+        );
+        let mut resp = response(
+            &[
+                (&instance_domain, Record::SRV(SRV::new(0, 0, 80, &dead))),
+                (&instance_domain, Record::SRV(SRV::new(1, 0, 80, &live))),
+                (&instance_domain, Record::TXT(TXT::new([b"path=/"]))),
+            ],
+            &[],
+        );
+        let server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; MDNS_BUFFER_SIZE];
+            while let Ok((_, from)) = server.recv_from(&mut buf) {
+                resp[..2].copy_from_slice(&buf[..2]);
+                server.send_to(&resp, from).unwrap();
+            }
+        });
+
+        let probed = Arc::new(Mutex::new(Vec::new()));
+        let mut discoverer = SyncDiscoverer::new(addr, domain!("local")).unwrap();
+        discoverer.set_liveness_probe({
+            let probed = probed.clone();
+            let live = live.clone();
+            move |target: &ServiceTarget| {
+                probed.lock().unwrap().push(target.host().clone());
+                match *target.host() == live {
+                    true => Ok(()),
+                    false => Err(Error::Timeout),
+                }
+            }
+        });
+        discoverer.load_instance_details(&instance).unwrap();
+        assert_eq!(*probed.lock().unwrap(), [dead.clone(), live.clone()]);
+        let cached = discoverer.cached_instance_details(&instance).unwrap();
+        let next = cached.next_target().unwrap().clone();
+        assert_eq!(next.host(), &live);
+
+        // Once all targets are dead, cached details are loaded again, and loading fails.
+        discoverer.set_liveness_probe(|_: &ServiceTarget| Err(Error::Timeout));
+        assert_eq!(discoverer.report_unreachable(&instance, &next), None);
+        assert_eq!(
+            discoverer.load_instance_details(&instance),
+            Err(Error::Timeout)
+        );
+        let cached = discoverer.cached_instance_details(&instance).unwrap();
+        assert_eq!(cached.next_target(), None);
+
+        // TCP probes.
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let closed = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let closed_port = closed.local_addr().unwrap().port();
+        drop(closed);
+        let mut target = ServiceTarget::new(live, listener.local_addr().unwrap().port());
+        let mut probe = TcpConnectProbe::new();
+        probe.check(&target).unwrap();
+        target.add_addr(Ipv4Addr::LOCALHOST.into());
+        probe.check(&target).unwrap();
+        let mut target = ServiceTarget::new(dead, closed_port);
+        target.add_addr(Ipv4Addr::LOCALHOST.into());
+        assert!(probe.check(&target).is_err());
+    }
+
+    #[test]
+    fn persistent_details_cache() {
+        use crate::{label, service::ServiceTransport};
+
+        let instance =
+            ServiceInstance::new(label!("My Printer"), label!("_ipp"), ServiceTransport::TCP);
+        let expired = ServiceInstance::new(label!("old"), label!("_ipp"), ServiceTransport::TCP);
+        let mut details = InstanceDetails::new(DomainName::from_str("printer.local").unwrap(), 631);
+        details.targets_mut()[0].add_addr(Ipv4Addr::new(10, 0, 0, 1).into());
+        details.targets_mut()[0].add_addr(Ipv6Addr::LOCALHOST.into());
+        let mut backup = ServiceTarget::new(DomainName::from_str("backup.local").unwrap(), 8631);
+        backup.set_priority(1);
+        details.add_target(backup);
+        details
+            .txt_records_mut()
+            .add_value("rp".into(), "ipp/print");
+
+        let start = Instant::now();
+        let mut cache = DetailsCache::new();
+        cache.insert(
+            instance.clone(),
+            details.clone(),
+            Duration::from_secs(120),
+            start,
+        );
+        cache.insert(
+            expired.clone(),
+            details.clone(),
+            Duration::from_secs(10),
+            start,
+        );
+
+        let mut file = Vec::new();
+        let now = start + Duration::from_secs(30);
+        cache.save(&mut file, now).unwrap();
+
+        let later = now + Duration::from_secs(5);
+        let loaded = DetailsCache::load(&file[..], later).unwrap();
+        let cached = loaded.get(&instance, later).unwrap();
+        assert_eq!(cached.details(), &details);
+        // Loading doesn't take longer than a few seconds, so the remaining TTL is about 90s.
+        let ttl = cached.remaining_ttl(later);
+        assert!(
+            ttl <= Duration::from_secs(90) && ttl > Duration::from_secs(80),
+            "{ttl:?}"
+        );
+        assert_eq!(loaded.get(&expired, later), None);
+
+        // Other kinds of data are rejected.
+        assert!(DetailsCache::load(&b"uwuhi"[..], later).is_err());
+    }
+}
@@ -0,0 +1,213 @@
+//! Lease tracking for wide-area (DNS UPDATE) service registrations.
+//!
+//! When registering services with a unicast DNS server via DNS UPDATE, clients attach an
+//! [`UpdateLease`] option to request how long the records should live. The server answers with
+//! the lease it granted, and the client has to re-send the registration before the lease lapses.
+//!
+//! [`Leases`] keeps track of the granted lease of every registered instance and tells the caller
+//! when to refresh them, without performing any I/O itself. Like [`DsoSession`], it is driven by
+//! calling [`Leases::handle_timeout`] at the time returned by [`Leases::next_timeout`].
+//!
+//! [`DsoSession`]: crate::dso::DsoSession
+
+use std::{
+    collections::BTreeMap,
+    hash::{BuildHasher, Hasher, RandomState},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    packet::{
+        decoder::MessageDecoder,
+        edns::{OptionCode, UpdateLease},
+    },
+    Error,
+};
+
+use super::ServiceInstance;
+
+/// Shortest interval between two refresh attempts of the same registration.
+const MIN_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Events produced by [`Leases::handle_timeout`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LeaseEvent {
+    /// The registration of the instance should be re-sent.
+    ///
+    /// If the server doesn't answer, this event is emitted again after a while, until the lease
+    /// runs out.
+    Refresh(ServiceInstance),
+    /// The lease of the instance has run out without being refreshed.
+    ///
+    /// The server has removed (or will soon remove) the instance's records, and it is no longer
+    /// tracked.
+    Expired(ServiceInstance),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Lease {
+    expires_at: Instant,
+    refresh_at: Instant,
+}
+
+/// Tracks the leases of service instances registered via DNS UPDATE.
+///
+/// Refreshes are scheduled between 75% and 87.5% of the granted lease, with random jitter so that
+/// many clients registered at the same time don't all refresh at once. If a refresh goes
+/// unanswered, it is retried after half of the remaining lease has passed.
+#[derive(Debug, Clone, Default)]
+pub struct Leases {
+    leases: BTreeMap<ServiceInstance, Lease>,
+}
+
+impl Leases {
+    /// Creates an empty lease table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that `instance` was (re-)registered at `now`, and the server granted `lease`.
+    ///
+    /// This replaces any lease previously recorded for `instance`.
+    pub fn registered(&mut self, instance: ServiceInstance, lease: Duration, now: Instant) {
+        let jitter = lease / 8;
+        let jitter = match u64::try_from(jitter.as_millis()) {
+            Ok(0) | Err(_) => Duration::ZERO,
+            Ok(ms) => Duration::from_millis(random() % ms),
+        };
+        self.leases.insert(
+            instance,
+            Lease {
+                expires_at: now + lease,
+                refresh_at: now + lease * 3 / 4 + jitter,
+            },
+        );
+    }
+
+    /// Stops tracking the lease of `instance`, for example because it was deregistered.
+    ///
+    /// Returns whether `instance` was being tracked.
+    pub fn remove(&mut self, instance: &ServiceInstance) -> bool {
+        self.leases.remove(instance).is_some()
+    }
+
+    /// Returns the point in time at which the lease of `instance` runs out.
+    pub fn expires_at(&self, instance: &ServiceInstance) -> Option<Instant> {
+        self.leases.get(instance).map(|lease| lease.expires_at)
+    }
+
+    /// Returns an iterator over the tracked instances.
+    pub fn instances(&self) -> impl Iterator<Item = &ServiceInstance> {
+        self.leases.keys()
+    }
+
+    /// Returns the time at which [`Leases::handle_timeout`] should be called next.
+    pub fn next_timeout(&self) -> Option<Instant> {
+        self.leases.values().map(|lease| lease.refresh_at).min()
+    }
+
+    /// Handles expired timers, returning the instances that need to be refreshed or have expired.
+    pub fn handle_timeout(&mut self, now: Instant) -> Vec<LeaseEvent> {
+        let mut events = Vec::new();
+        self.leases.retain(|instance, lease| {
+            if now >= lease.expires_at {
+                events.push(LeaseEvent::Expired(instance.clone()));
+                return false;
+            }
+            if now >= lease.refresh_at {
+                let retry = ((lease.expires_at - now) / 2).max(MIN_RETRY_INTERVAL);
+                lease.refresh_at = (now + retry).min(lease.expires_at);
+                events.push(LeaseEvent::Refresh(instance.clone()));
+            }
+            true
+        });
+        events
+    }
+}
+
+/// Decodes the [`UpdateLease`] option from a server's response to a DNS UPDATE.
+///
+/// Returns [`None`] if the response doesn't contain one, in which case the server doesn't support
+/// leases and the registration remains until it is explicitly removed.
+pub fn granted_lease(msg: &[u8]) -> Result<Option<UpdateLease>, Error> {
+    let mut dec = MessageDecoder::new(msg)?.additional()?;
+    for rr in dec.iter() {
+        let rr = rr?;
+        for option in rr.edns_options().into_iter().flatten() {
+            let option = option?;
+            if option.code() == OptionCode::UL {
+                return UpdateLease::decode(&option).map(Some);
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        label,
+        packet::encoder::{MessageEncoder, Question},
+        service::ServiceTransport,
+    };
+
+    use super::*;
+
+    #[test]
+    fn refresh_schedule() {
+        let instance =
+            ServiceInstance::new(label!("Printer"), label!("_ipp"), ServiceTransport::TCP);
+        let start = Instant::now();
+        let lease = Duration::from_secs(3600);
+        let mut leases = Leases::new();
+        assert_eq!(leases.next_timeout(), None);
+        leases.registered(instance.clone(), lease, start);
+        assert_eq!(leases.expires_at(&instance), Some(start + lease));
+
+        let refresh = leases.next_timeout().unwrap();
+        assert!(refresh >= start + Duration::from_secs(2700), "{refresh:?}");
+        assert!(refresh < start + Duration::from_secs(3150), "{refresh:?}");
+        assert_eq!(leases.handle_timeout(start), []);
+        assert_eq!(
+            leases.handle_timeout(refresh),
+            [LeaseEvent::Refresh(instance.clone())]
+        );
+
+        // Unanswered refreshes are retried after half of the remaining lease.
+        let retry = leases.next_timeout().unwrap();
+        assert_eq!(retry, refresh + (start + lease - refresh) / 2);
+
+        // A successful refresh resets the lease.
+        leases.registered(instance.clone(), lease, retry);
+        assert_eq!(leases.expires_at(&instance), Some(retry + lease));
+
+        assert_eq!(
+            leases.handle_timeout(retry + lease),
+            [LeaseEvent::Expired(instance.clone())]
+        );
+        assert_eq!(leases.next_timeout(), None);
+        assert!(!leases.remove(&instance));
+    }
+
+    #[test]
+    fn decode_granted_lease() {
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf);
+        enc.question(Question::new(&"example.com".parse().unwrap()));
+        let mut enc = enc.answers().authority().additional();
+        enc.edns(1232)
+            .update_lease(&UpdateLease::new(Duration::from_secs(7200), None));
+        let len = enc.finish().unwrap();
+        let lease = granted_lease(&buf[..len]).unwrap().unwrap();
+        assert_eq!(lease.lease(), Duration::from_secs(7200));
+
+        let mut enc = MessageEncoder::new(&mut buf);
+        enc.question(Question::new(&"example.com".parse().unwrap()));
+        let len = enc.finish().unwrap();
+        assert_eq!(granted_lease(&buf[..len]).unwrap(), None);
+    }
+}
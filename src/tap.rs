@@ -1,37 +1,83 @@
 //! mDNS traffic tapping.
+//!
+//! Besides logging packets, [`SyncTap`] looks for suspicious patterns in the traffic it sees, like
+//! responses to questions nobody asked, with an [`AnomalyDetector`].
 
 use std::{
-    io,
-    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    collections::HashMap,
+    fmt, io,
+    net::{SocketAddr, UdpSocket},
+    thread,
+    time::{Duration, Instant},
 };
 
-use crate::{hex::Hex, packet::decoder::MessageDecoder, Error};
-use socket2::{Domain, Protocol, Socket, Type};
+use crate::{
+    clock::Backoff,
+    error::is_transient_io,
+    hex::Hex,
+    name::DomainName,
+    net::MulticastSocketBuilder,
+    packet::{arena::DecodeArena, decoder::MessageDecoder, RCode},
+    Error,
+};
 
 use crate::MDNS_BUFFER_SIZE;
 
 /// An mDNS tap that will log every received mDNS packet.
+///
+/// [`Anomaly`]s found in the traffic are logged as warnings, and passed to the hook set via
+/// [`SyncTap::set_anomaly_hook`].
 pub struct SyncTap {
     sock: UdpSocket,
+    arena: DecodeArena,
+    detector: AnomalyDetector,
+    anomaly_hook: Option<Box<dyn AnomalyHook>>,
 }
 
 impl SyncTap {
     /// Creates a new mDNS tap listening on port 5353.
-    pub fn new() -> io::Result<Self> {
-        let sock = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
-        sock.set_reuse_address(true)?;
-        sock.bind(&"0.0.0.0:5353".parse::<SocketAddr>().unwrap().into())?;
+    pub fn new() -> Result<Self, Error> {
+        Ok(Self {
+            sock: Self::create_socket()?,
+            arena: DecodeArena::new(),
+            detector: AnomalyDetector::new(),
+            anomaly_hook: None,
+        })
+    }
+
+    /// Sets an [`AnomalyHook`] that is called with every [`Anomaly`] detected in the traffic.
+    ///
+    /// This replaces any previously set hook.
+    pub fn set_anomaly_hook(&mut self, hook: impl AnomalyHook + 'static) {
+        self.anomaly_hook = Some(Box::new(hook));
+    }
+
+    /// Returns a mutable reference to the [`AnomalyDetector`], to configure it.
+    #[inline]
+    pub fn anomaly_detector_mut(&mut self) -> &mut AnomalyDetector {
+        &mut self.detector
+    }
 
-        let sock = UdpSocket::from(sock);
-        sock.join_multicast_v4(&"224.0.0.251".parse().unwrap(), &Ipv4Addr::UNSPECIFIED)?;
-        sock.set_multicast_loop_v4(true)?;
-        Ok(Self { sock })
+    fn create_socket() -> io::Result<UdpSocket> {
+        MulticastSocketBuilder::mdns_v4().loopback(true).build()
     }
 
-    pub fn listen(self) -> io::Result<()> {
+    /// Logs incoming packets until an error occurs.
+    ///
+    /// Transient socket errors (see [`Error::is_transient`]), like those caused by the network
+    /// interface going down, are logged, and the socket is recreated after an increasing delay.
+    pub fn listen(mut self) -> Result<(), Error> {
+        let mut backoff = Backoff::default();
         loop {
             let mut buf = [0; MDNS_BUFFER_SIZE];
-            let (len, addr) = self.sock.recv_from(&mut buf)?;
+            let (len, addr) = match self.sock.recv_from(&mut buf) {
+                Ok(res) => res,
+                Err(e) => {
+                    self.recover(e, &mut backoff)?;
+                    continue;
+                }
+            };
+            backoff.reset();
 
             let buf = &buf[..len];
             match self.process(addr, buf) {
@@ -47,10 +93,366 @@ impl SyncTap {
         }
     }
 
-    fn process(&self, addr: SocketAddr, msg: &[u8]) -> Result<(), Error> {
+    /// Recreates the socket if `error` is transient, and returns `error` otherwise.
+    fn recover(&mut self, error: io::Error, backoff: &mut Backoff) -> io::Result<()> {
+        if !is_transient_io(&error) {
+            return Err(error);
+        }
+        log::warn!("tap socket error: {}; recreating socket", error);
+        loop {
+            thread::sleep(backoff.next_delay());
+            match Self::create_socket() {
+                Ok(sock) => {
+                    self.sock = sock;
+                    return Ok(());
+                }
+                Err(e) if is_transient_io(&e) => {
+                    log::warn!("failed to recreate tap socket: {}; retrying", e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn process(&mut self, addr: SocketAddr, msg: &[u8]) -> Result<(), Error> {
         log::trace!("raw packet from {}: {} bytes {}", addr, msg.len(), Hex(msg));
 
-        let msg = MessageDecoder::new(msg)?;
-        msg.format(|args| log::debug!("{}", args))
+        // Malformed messages are reported below.
+        let anomalies = self.detector.process(addr, msg, Instant::now());
+        for anomaly in anomalies.unwrap_or_default() {
+            log::warn!("{}", anomaly);
+            if let Some(hook) = &mut self.anomaly_hook {
+                hook.on_anomaly(&anomaly);
+            }
+        }
+
+        match self.arena.decode(msg) {
+            Ok(msg) => {
+                msg.format(|args| log::debug!("{}", args));
+                Ok(())
+            }
+            // The streaming decoder logs everything up to the malformed part of the message.
+            Err(_) => MessageDecoder::new(msg)?.format(|args| log::debug!("{}", args)),
+        }
+    }
+}
+
+/// A suspicious pattern in mDNS or DNS traffic, found by an [`AnomalyDetector`].
+///
+/// None of these are proof of an attack: they are just as likely to be caused by misconfigured
+/// reflectors or buggy responders. Names are reported in lowercase.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Anomaly {
+    /// `source` sent a response to a question that wasn't asked recently.
+    ///
+    /// This is what blind spoofing attempts look like, but it can also be caused by a reflector
+    /// that forwards responses without the queries they answer.
+    UnsolicitedResponse {
+        source: SocketAddr,
+        id: u16,
+        qname: DomainName,
+    },
+    /// `source` answered a query differently than `first_source` did before.
+    ///
+    /// A spoofed response racing the legitimate one causes this.
+    ConflictingResponse {
+        source: SocketAddr,
+        first_source: SocketAddr,
+        id: u16,
+        qname: DomainName,
+    },
+    /// `source` sent a query with the same message ID as a recent query from `other_source`.
+    ///
+    /// Responses to both queries can be mixed up, which makes them easier to spoof.
+    DuplicateId {
+        source: SocketAddr,
+        other_source: SocketAddr,
+        id: u16,
+        qname: DomainName,
+    },
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsolicitedResponse { source, id, qname } => write!(
+                f,
+                "unsolicited response from {} (id={}) for '{}'",
+                source, id, qname
+            ),
+            Self::ConflictingResponse {
+                source,
+                first_source,
+                id,
+                qname,
+            } => write!(
+                f,
+                "response from {} (id={}) for '{}' conflicts with earlier response from {}",
+                source, id, qname, first_source
+            ),
+            Self::DuplicateId {
+                source,
+                other_source,
+                id,
+                qname,
+            } => write!(
+                f,
+                "query from {} for '{}' reuses id {} of a query from {}",
+                source, qname, id, other_source
+            ),
+        }
+    }
+}
+
+/// A callback that is invoked with every [`Anomaly`] found by a [`SyncTap`].
+///
+/// It is implemented for all closures with a matching signature, and registered via
+/// [`SyncTap::set_anomaly_hook`].
+pub trait AnomalyHook: Send {
+    /// Called when `anomaly` has been detected.
+    fn on_anomaly(&mut self, anomaly: &Anomaly);
+}
+
+impl<F: FnMut(&Anomaly) + Send> AnomalyHook for F {
+    fn on_anomaly(&mut self, anomaly: &Anomaly) {
+        self(anomaly)
+    }
+}
+
+/// Detects [`Anomaly`]s by matching the responses in observed traffic to the queries they answer.
+///
+/// Queries are tracked by message ID and question name for a limited time (the *window*, see
+/// [`AnomalyDetector::set_window`]), along with their source and the first response to them.
+///
+/// Regular mDNS queries and responses all use message ID 0, and many responders may legitimately
+/// answer the same question differently, so for those only responses that contain a question
+/// (which multicast responses usually don't) are checked against recent queries. Messages with
+/// other IDs (unicast DNS, and legacy unicast mDNS queries) are checked for every [`Anomaly`].
+#[derive(Debug, Clone)]
+pub struct AnomalyDetector {
+    window: Duration,
+    queries: HashMap<(u16, DomainName), SeenQuery>,
+}
+
+#[derive(Debug, Clone)]
+struct SeenQuery {
+    source: SocketAddr,
+    seen_at: Instant,
+    /// Source and summary of the first response.
+    response: Option<(SocketAddr, ResponseSummary)>,
+}
+
+/// What a response said, in a form that doesn't depend on how it was encoded.
+type ResponseSummary = (RCode, Vec<String>);
+
+impl AnomalyDetector {
+    const DEFAULT_WINDOW: Duration = Duration::from_secs(10);
+
+    /// Creates a detector with the default window of 10 seconds.
+    pub fn new() -> Self {
+        Self {
+            window: Self::DEFAULT_WINDOW,
+            queries: HashMap::new(),
+        }
+    }
+
+    /// Sets how long queries are remembered.
+    ///
+    /// Responses arriving later than this after their query are reported as unsolicited.
+    pub fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+
+    /// Processes `msg`, which was received from `source` at `now`, and returns the anomalies it
+    /// shows.
+    ///
+    /// Returns an error if `msg` can't be decoded. Messages must be passed in the order they were
+    /// received.
+    pub fn process(
+        &mut self,
+        source: SocketAddr,
+        msg: &[u8],
+        now: Instant,
+    ) -> Result<Vec<Anomaly>, Error> {
+        let window = self.window;
+        self.queries
+            .retain(|_, query| now.saturating_duration_since(query.seen_at) < window);
+
+        let mut dec = MessageDecoder::new(msg)?;
+        let header = *dec.header();
+        let id = header.id();
+        let qnames = dec
+            .iter()
+            .map(|q| Ok(q?.qname().to_ascii_lowercase()))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let mut anomalies = Vec::new();
+        if header.is_query() {
+            for qname in qnames {
+                if id != 0 {
+                    let other = self
+                        .queries
+                        .iter()
+                        .find(|((other_id, _), query)| *other_id == id && query.source != source);
+                    if let Some((_, query)) = other {
+                        anomalies.push(Anomaly::DuplicateId {
+                            source,
+                            other_source: query.source,
+                            id,
+                            qname: qname.clone(),
+                        });
+                    }
+                }
+                let query = SeenQuery {
+                    source,
+                    seen_at: now,
+                    response: None,
+                };
+                self.queries.insert((id, qname), query);
+            }
+            return Ok(anomalies);
+        }
+
+        let mut answers = Vec::new();
+        let mut dec = dec.answers()?;
+        for rr in dec.iter() {
+            let rr = rr?;
+            let data = match rr.as_enum() {
+                Some(record) => record?.to_string().to_ascii_lowercase(),
+                None => Hex(rr.rdata()).to_string(),
+            };
+            answers.push(format!(
+                "{} {} {}",
+                rr.name().to_ascii_lowercase(),
+                rr.type_(),
+                data
+            ));
+        }
+        answers.sort();
+        let summary = (header.rcode(), answers);
+
+        for qname in qnames {
+            let Some(query) = self.queries.get_mut(&(id, qname.clone())) else {
+                anomalies.push(Anomaly::UnsolicitedResponse { source, id, qname });
+                continue;
+            };
+            match &query.response {
+                None => query.response = Some((source, summary.clone())),
+                Some((first_source, first)) if id != 0 && *first != summary => {
+                    anomalies.push(Anomaly::ConflictingResponse {
+                        source,
+                        first_source: *first_source,
+                        id,
+                        qname,
+                    });
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(anomalies)
+    }
+}
+
+impl Default for AnomalyDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::{
+        domain,
+        packet::{
+            encoder::{MessageEncoder, Question, ResourceRecord},
+            records::{Record, A},
+            Header, QType,
+        },
+    };
+
+    use super::*;
+
+    fn message(id: u16, name: &DomainName, answer: Option<[u8; 4]>) -> Vec<u8> {
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf);
+        let mut header = Header::default();
+        header.set_id(id);
+        header.set_response(answer.is_some());
+        enc.set_header(header);
+        enc.question(Question::new(name).ty(QType::A));
+        let mut enc = enc.answers();
+        if let Some(ip) = answer {
+            let a = Record::A(A::new(ip.into()));
+            enc.add_answer(ResourceRecord::new(name, &a));
+        }
+        let len = enc.finish().unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn anomalies() {
+        let client = SocketAddr::from((Ipv4Addr::new(10, 0, 0, 2), 40000));
+        let other = SocketAddr::from((Ipv4Addr::new(10, 0, 0, 3), 40000));
+        let server = SocketAddr::from((Ipv4Addr::new(10, 0, 0, 1), 5353));
+        let spoofer = SocketAddr::from((Ipv4Addr::new(10, 0, 0, 66), 5353));
+        let name = domain!("Printer.local");
+        let lower = domain!("printer.local");
+
+        let now = Instant::now();
+        let mut detector = AnomalyDetector::new();
+        let mut process = |source, msg: Vec<u8>, now| detector.process(source, &msg, now).unwrap();
+
+        assert_eq!(process(client, message(7, &name, None), now), []);
+        // Retransmissions are fine.
+        assert_eq!(process(client, message(7, &name, None), now), []);
+        assert_eq!(
+            process(other, message(7, &lower, None), now),
+            [Anomaly::DuplicateId {
+                source: other,
+                other_source: client,
+                id: 7,
+                qname: lower.clone(),
+            }]
+        );
+
+        let answer = message(7, &lower, Some([10, 0, 0, 1]));
+        assert_eq!(process(server, answer.clone(), now), []);
+        assert_eq!(process(server, answer, now), []);
+        assert_eq!(
+            process(spoofer, message(7, &name, Some([10, 0, 0, 66])), now),
+            [Anomaly::ConflictingResponse {
+                source: spoofer,
+                first_source: server,
+                id: 7,
+                qname: lower.clone(),
+            }]
+        );
+        assert_eq!(
+            process(spoofer, message(8, &name, Some([10, 0, 0, 66])), now),
+            [Anomaly::UnsolicitedResponse {
+                source: spoofer,
+                id: 8,
+                qname: lower.clone(),
+            }]
+        );
+
+        // Regular mDNS responders may answer differently.
+        assert_eq!(process(client, message(0, &name, None), now), []);
+        assert_eq!(process(server, message(0, &name, Some([1; 4])), now), []);
+        assert_eq!(process(spoofer, message(0, &name, Some([2; 4])), now), []);
+
+        // Queries are forgotten after the window.
+        let later = now + AnomalyDetector::DEFAULT_WINDOW;
+        assert_eq!(
+            process(server, message(0, &name, Some([1; 4])), later),
+            [Anomaly::UnsolicitedResponse {
+                source: server,
+                id: 0,
+                qname: lower,
+            }]
+        );
     }
 }
@@ -0,0 +1,872 @@
+//! Helpers for testing code built on uwuhi.
+//!
+//! This module is only available with the `testing` feature. It allows exercising uwuhi's I/O-less
+//! components against recorded traffic, without touching the network or waiting for real time to
+//! pass:
+//!
+//! - [`parse_hex`] and [`read_pcap`] load fixtures: single messages written as hex strings, or
+//!   whole packet captures recorded with `tcpdump` or Wireshark.
+//! - [`Harness`] drives a [`Responder`] (an mDNS [`Advertiser`] or a unicast [`Server`]) with a
+//!   [`ManualClock`], delivers packets to it, and collects the packets it would send.
+//! - [`format_message`] renders a message as one line per header, question and record, which is
+//!   convenient to compare against expected output.
+//! - [`VirtualLan`] connects several responders and passive endpoints through an in-memory network
+//!   with configurable latency and packet loss, for tests involving more than one node (like
+//!   probing, name conflicts, or browsing several advertisers at once).
+//!
+//! Discovery-side components like [`DetailsCollector`] and [`ServiceTypeCollector`] consume raw
+//! messages directly, so captured responses can be fed to them via [`CapturedPacket::payload`].
+//!
+//! # Example
+//!
+//! ```
+//! # use uwuhi::{label, service::advertising::Advertiser, testing::{format_message, parse_hex, Harness}};
+//! let adv = Advertiser::new(label!("host"), [10, 0, 0, 1].into())?;
+//! let mut harness = Harness::new(adv);
+//! // A query for `host.local A` (with the `QU` bit set).
+//! let query = parse_hex("0000 0000 0001 0000 0000 0000 04686f7374 056c6f63616c 00 0001 8001")?;
+//! harness.deliver(&query, "10.0.0.2:5353".parse()?)?;
+//! let [sent] = harness.take_sent().try_into().unwrap();
+//! assert_eq!(format_message(sent.payload())?[1], "ANS: host.local.\t120\tIN\tA\t10.0.0.1");
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [`Advertiser`]: crate::service::advertising::Advertiser
+//! [`Server`]: crate::server::Server
+//! [`DetailsCollector`]: crate::service::discovery::DetailsCollector
+//! [`ServiceTypeCollector`]: crate::service::discovery::ServiceTypeCollector
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::{Clock, ManualClock},
+    packet::decoder::MessageDecoder,
+    server::Server,
+    service::advertising::Advertiser,
+    Error,
+};
+
+/// Parses a hex string into bytes.
+///
+/// Whitespace is ignored, and `#` starts a comment that extends to the end of the line, so
+/// fixtures can be annotated. Returns [`Error::InvalidValue`] if the string contains anything
+/// else, or an odd number of hex digits.
+pub fn parse_hex(hex: &str) -> Result<Vec<u8>, Error> {
+    let digits = hex
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .flat_map(|line| line.chars())
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16).map(|d| d as u8).ok_or(Error::InvalidValue))
+        .collect::<Result<Vec<_>, _>>()?;
+    if digits.len() % 2 != 0 {
+        return Err(Error::InvalidValue);
+    }
+    Ok(digits
+        .chunks(2)
+        .map(|pair| pair[0] << 4 | pair[1])
+        .collect())
+}
+
+/// Renders the DNS message `msg` as a list of lines.
+///
+/// The first line describes the header, and every question and resource record gets a line of
+/// its own, in the same format used by uwuhi's debug logging.
+pub fn format_message(msg: &[u8]) -> Result<Vec<String>, Error> {
+    let mut lines = Vec::new();
+    MessageDecoder::new(msg)?.format(|args| lines.push(args.to_string()))?;
+    Ok(lines)
+}
+
+/// A UDP datagram read from a packet capture by [`read_pcap`], or received by a node of a
+/// [`VirtualLan`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedPacket {
+    timestamp: Duration,
+    source: SocketAddr,
+    destination: SocketAddr,
+    payload: Vec<u8>,
+}
+
+impl CapturedPacket {
+    /// Returns the time at which the packet was captured, relative to the UNIX epoch (or to the
+    /// creation of the [`VirtualLan`]).
+    #[inline]
+    pub fn timestamp(&self) -> Duration {
+        self.timestamp
+    }
+
+    /// Returns the address the packet was sent from.
+    #[inline]
+    pub fn source(&self) -> SocketAddr {
+        self.source
+    }
+
+    /// Returns the address the packet was sent to.
+    #[inline]
+    pub fn destination(&self) -> SocketAddr {
+        self.destination
+    }
+
+    /// Returns the UDP payload of the packet (typically a DNS message).
+    #[inline]
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Returns whether the payload is a DNS response.
+    pub fn is_response(&self) -> bool {
+        MessageDecoder::new(&self.payload).is_ok_and(|dec| dec.header().is_response())
+    }
+}
+
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+
+/// Reads the UDP datagrams from a packet capture in the classic `pcap` format.
+///
+/// Captures of Ethernet, raw IP and Linux "cooked" (`tcpdump -i any`) links are supported, with
+/// microsecond or nanosecond timestamps. Packets other than unfragmented UDP over IPv4 or IPv6 are
+/// skipped. The newer `pcapng` format is not supported; Wireshark can convert such files via
+/// *File → Save As*.
+///
+/// Returns [`Error::InvalidValue`] if the file isn't a supported capture, and [`Error::Eof`] if it
+/// is cut off.
+pub fn read_pcap(data: &[u8]) -> Result<Vec<CapturedPacket>, Error> {
+    let magic = data.get(..4).ok_or(Error::Eof)?;
+    let (big_endian, nanos) = match magic {
+        [0xd4, 0xc3, 0xb2, 0xa1] => (false, false),
+        [0xa1, 0xb2, 0xc3, 0xd4] => (true, false),
+        [0x4d, 0x3c, 0xb2, 0xa1] => (false, true),
+        [0xa1, 0xb2, 0x3c, 0x4d] => (true, true),
+        _ => return Err(Error::InvalidValue),
+    };
+    let u32_at = |pos: usize| -> Result<u32, Error> {
+        let bytes: [u8; 4] = data
+            .get(pos..pos + 4)
+            .ok_or(Error::Eof)?
+            .try_into()
+            .unwrap();
+        Ok(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+    let link_type = u32_at(20)?;
+
+    let mut packets = Vec::new();
+    let mut pos = 24;
+    while pos < data.len() {
+        let secs = u32_at(pos)?;
+        let frac = u32_at(pos + 4)?;
+        let captured_len = u32_at(pos + 8)? as usize;
+        let frame = data
+            .get(pos + 16..pos + 16 + captured_len)
+            .ok_or(Error::Eof)?;
+        pos += 16 + captured_len;
+
+        let timestamp = Duration::from_secs(secs.into())
+            + if nanos {
+                Duration::from_nanos(frac.into())
+            } else {
+                Duration::from_micros(frac.into())
+            };
+        let ip = match link_type {
+            LINKTYPE_ETHERNET => frame.get(14..).map(|ip| (ip, ethertype(frame, 12))),
+            LINKTYPE_LINUX_SLL => frame.get(16..).map(|ip| (ip, ethertype(frame, 14))),
+            LINKTYPE_RAW => Some((frame, None)),
+            _ => return Err(Error::InvalidValue),
+        };
+        let Some((ip, ethertype)) = ip else {
+            continue;
+        };
+        if let Some((source, destination, payload)) = decode_udp(ip, ethertype) {
+            packets.push(CapturedPacket {
+                timestamp,
+                source,
+                destination,
+                payload: payload.to_vec(),
+            });
+        }
+    }
+    Ok(packets)
+}
+
+fn ethertype(frame: &[u8], pos: usize) -> Option<u16> {
+    let bytes = frame.get(pos..pos + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// Decodes an unfragmented UDP datagram from an IP packet.
+fn decode_udp(ip: &[u8], ethertype: Option<u16>) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    const IPPROTO_UDP: u8 = 17;
+
+    let version = ip.first()? >> 4;
+    let (source, destination, udp) = match (version, ethertype) {
+        (4, None | Some(0x0800)) => {
+            let header_len = usize::from(ip[0] & 0x0f) * 4;
+            let total_len = usize::from(u16::from_be_bytes([*ip.get(2)?, *ip.get(3)?]));
+            let fragment = u16::from_be_bytes([*ip.get(6)?, *ip.get(7)?]);
+            // Skip fragments: either the "more fragments" flag or the offset is set.
+            if fragment & 0x3fff != 0 || *ip.get(9)? != IPPROTO_UDP {
+                return None;
+            }
+            let source: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+            let destination: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+            (
+                IpAddr::from(Ipv4Addr::from(source)),
+                IpAddr::from(Ipv4Addr::from(destination)),
+                ip.get(header_len..total_len)?,
+            )
+        }
+        (6, None | Some(0x86dd)) => {
+            // Extension headers (including fragment headers) are not supported.
+            if *ip.get(6)? != IPPROTO_UDP {
+                return None;
+            }
+            let payload_len = usize::from(u16::from_be_bytes([*ip.get(4)?, *ip.get(5)?]));
+            let source: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+            let destination: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+            (
+                IpAddr::from(Ipv6Addr::from(source)),
+                IpAddr::from(Ipv6Addr::from(destination)),
+                ip.get(40..40 + payload_len)?,
+            )
+        }
+        _ => return None,
+    };
+    let source_port = u16::from_be_bytes([*udp.first()?, *udp.get(1)?]);
+    let destination_port = u16::from_be_bytes([*udp.get(2)?, *udp.get(3)?]);
+    let udp_len = usize::from(u16::from_be_bytes([*udp.get(4)?, *udp.get(5)?]));
+    Some((
+        SocketAddr::new(source, source_port),
+        SocketAddr::new(destination, destination_port),
+        udp.get(8..udp_len)?,
+    ))
+}
+
+/// A component that answers DNS messages without performing I/O.
+///
+/// This is implemented for the mDNS [`Advertiser`] and the unicast [`Server`], and used by
+/// [`Harness`] to drive them.
+pub trait Responder {
+    /// Makes the responder use `clock` as its source of time.
+    fn set_clock(&mut self, clock: ManualClock);
+
+    /// Handles `packet` from `source`, and returns the response and the address to send it to.
+    fn respond(
+        &mut self,
+        packet: &[u8],
+        source: SocketAddr,
+    ) -> Result<Option<(&[u8], SocketAddr)>, Error>;
+}
+
+impl Responder for Advertiser {
+    fn set_clock(&mut self, clock: ManualClock) {
+        Advertiser::set_clock(self, clock);
+    }
+
+    fn respond(
+        &mut self,
+        packet: &[u8],
+        source: SocketAddr,
+    ) -> Result<Option<(&[u8], SocketAddr)>, Error> {
+        self.handle_query(packet, source)
+    }
+}
+
+impl Responder for Server {
+    fn set_clock(&mut self, clock: ManualClock) {
+        Server::set_clock(self, clock);
+    }
+
+    /// Handles `packet` as if it was received via UDP.
+    fn respond(
+        &mut self,
+        packet: &[u8],
+        source: SocketAddr,
+    ) -> Result<Option<(&[u8], SocketAddr)>, Error> {
+        Ok(self
+            .handle_packet(packet, source)?
+            .map(|response| (response, source)))
+    }
+}
+
+/// A packet sent by the [`Responder`] in a [`Harness`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentPacket {
+    destination: SocketAddr,
+    payload: Vec<u8>,
+}
+
+impl SentPacket {
+    /// Returns the address the packet was sent to.
+    #[inline]
+    pub fn destination(&self) -> SocketAddr {
+        self.destination
+    }
+
+    /// Returns the contents of the packet.
+    #[inline]
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+}
+
+/// Drives a [`Responder`] with a mock clock and transport.
+///
+/// Packets are passed to the responder via [`Harness::deliver`] or [`Harness::replay`], and the
+/// packets it sends in response are collected instead of being sent over the network.
+pub struct Harness<R> {
+    responder: R,
+    clock: ManualClock,
+    sent: Vec<SentPacket>,
+}
+
+impl<R: Responder> Harness<R> {
+    /// Creates a harness for `responder`, and makes it use a [`ManualClock`].
+    pub fn new(mut responder: R) -> Self {
+        let clock = ManualClock::new();
+        responder.set_clock(clock.clone());
+        Self {
+            responder,
+            clock,
+            sent: Vec::new(),
+        }
+    }
+
+    /// Returns the responder under test.
+    #[inline]
+    pub fn responder(&mut self) -> &mut R {
+        &mut self.responder
+    }
+
+    /// Returns the clock used by the responder.
+    ///
+    /// Advancing the returned clock advances the time observed by the responder.
+    #[inline]
+    pub fn clock(&self) -> &ManualClock {
+        &self.clock
+    }
+
+    /// Delivers `packet` from `source` to the responder, and returns the packet it sent in
+    /// response (if any).
+    ///
+    /// The sent packet is also recorded, see [`Harness::sent`].
+    pub fn deliver(
+        &mut self,
+        packet: &[u8],
+        source: SocketAddr,
+    ) -> Result<Option<&SentPacket>, Error> {
+        let Some((response, destination)) = self.responder.respond(packet, source)? else {
+            return Ok(None);
+        };
+        self.sent.push(SentPacket {
+            destination,
+            payload: response.to_vec(),
+        });
+        Ok(self.sent.last())
+    }
+
+    /// Delivers the queries in `capture` to the responder, advancing the clock by the time that
+    /// passed between them.
+    ///
+    /// Responses in the capture are skipped, and so are packets the responder fails to decode.
+    /// Returns the number of packets that were delivered.
+    pub fn replay(&mut self, capture: &[CapturedPacket]) -> usize {
+        let mut delivered = 0;
+        let mut last = None;
+        for packet in capture {
+            if let Some(last) = last {
+                self.clock.advance(packet.timestamp().saturating_sub(last));
+            }
+            last = Some(packet.timestamp());
+            if packet.is_response() {
+                continue;
+            }
+            if let Err(e) = self.deliver(packet.payload(), packet.source()) {
+                log::debug!("failed to handle captured packet: {}", e);
+            }
+            delivered += 1;
+        }
+        delivered
+    }
+
+    /// Returns the packets sent by the responder so far, in order.
+    #[inline]
+    pub fn sent(&self) -> &[SentPacket] {
+        &self.sent
+    }
+
+    /// Returns and forgets the packets sent by the responder so far.
+    pub fn take_sent(&mut self) -> Vec<SentPacket> {
+        std::mem::take(&mut self.sent)
+    }
+}
+
+/// Identifies a node attached to a [`VirtualLan`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+/// An in-memory network connecting several nodes, all of which observe the same [`ManualClock`].
+///
+/// Nodes are either [`Responder`]s (added via [`VirtualLan::add_responder`]), which answer the
+/// packets they receive, or passive endpoints (added via [`VirtualLan::add_endpoint`]), which only
+/// record them. Passive endpoints stand in for discovery-side components: tests send their queries
+/// with [`VirtualLan::send`], and feed the packets collected via [`VirtualLan::take_received`] to
+/// a [`DetailsCollector`] or [`QueryBatch`].
+///
+/// Packets sent to a multicast address reach every node except the sender. Packets sent to a
+/// unicast address reach the node with exactly that address, and are dropped if there is none.
+/// Every packet arrives after the configured latency, unless it is lost. Nothing happens until
+/// time is advanced with [`VirtualLan::advance`] or [`VirtualLan::run_until_idle`].
+///
+/// Unsolicited packets like probes and announcements have to be built by the test and sent with
+/// [`VirtualLan::send`], since the [`Responder`] trait only covers answering packets.
+///
+/// [`DetailsCollector`]: crate::service::discovery::DetailsCollector
+/// [`QueryBatch`]: crate::service::discovery::QueryBatch
+pub struct VirtualLan<R = Advertiser> {
+    clock: ManualClock,
+    start: Instant,
+    nodes: Vec<LanNode<R>>,
+    /// Packets on their way to a node, in the order they were sent.
+    in_flight: Vec<(Instant, usize, CapturedPacket)>,
+    latency: Duration,
+    loss: f64,
+    rng: u64,
+}
+
+struct LanNode<R> {
+    addr: SocketAddr,
+    responder: Option<R>,
+    received: Vec<CapturedPacket>,
+}
+
+impl<R: Responder> VirtualLan<R> {
+    /// Upper bound on the number of packets delivered by [`VirtualLan::run_until_idle`].
+    const MAX_DELIVERIES: usize = 10_000;
+
+    /// Creates an empty network without latency or packet loss.
+    pub fn new() -> Self {
+        let clock = ManualClock::new();
+        Self {
+            start: clock.now(),
+            clock,
+            nodes: Vec::new(),
+            in_flight: Vec::new(),
+            latency: Duration::ZERO,
+            loss: 0.0,
+            rng: 0x853c_49e6_748f_ea9b,
+        }
+    }
+
+    /// Returns the clock shared by all nodes.
+    ///
+    /// Prefer [`VirtualLan::advance`] over advancing the clock directly, so that packets are
+    /// delivered on time.
+    #[inline]
+    pub fn clock(&self) -> &ManualClock {
+        &self.clock
+    }
+
+    /// Sets the time it takes for a packet to arrive.
+    pub fn set_latency(&mut self, latency: Duration) {
+        self.latency = latency;
+    }
+
+    /// Sets the probability (between 0 and 1) of a packet getting lost on the way to a node.
+    ///
+    /// Each receiver of a multicast packet loses it independently. Losses are pseudo-random, and
+    /// deterministic for a given seed (see [`VirtualLan::set_seed`]).
+    pub fn set_loss(&mut self, probability: f64) {
+        self.loss = probability.clamp(0.0, 1.0);
+    }
+
+    /// Seeds the pseudo-random number generator that decides which packets get lost.
+    pub fn set_seed(&mut self, seed: u64) {
+        // xorshift gets stuck at 0.
+        self.rng = seed.max(1);
+    }
+
+    /// Attaches `responder` to the network under `addr`, and makes it use the network's clock.
+    pub fn add_responder(&mut self, addr: SocketAddr, mut responder: R) -> NodeId {
+        responder.set_clock(self.clock.clone());
+        self.add_node(addr, Some(responder))
+    }
+
+    /// Attaches a passive endpoint to the network under `addr`.
+    ///
+    /// Endpoints don't answer anything, they only record the packets they receive.
+    pub fn add_endpoint(&mut self, addr: SocketAddr) -> NodeId {
+        self.add_node(addr, None)
+    }
+
+    fn add_node(&mut self, addr: SocketAddr, responder: Option<R>) -> NodeId {
+        self.nodes.push(LanNode {
+            addr,
+            responder,
+            received: Vec::new(),
+        });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Returns the address of `node`.
+    pub fn addr(&self, node: NodeId) -> SocketAddr {
+        self.nodes[node.0].addr
+    }
+
+    /// Returns the responder of `node`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `node` is a passive endpoint.
+    pub fn responder(&mut self, node: NodeId) -> &mut R {
+        self.nodes[node.0]
+            .responder
+            .as_mut()
+            .expect("node is not a responder")
+    }
+
+    /// Returns the packets `node` has received so far, in order.
+    ///
+    /// The timestamps of the packets are relative to the creation of the network.
+    pub fn received(&self, node: NodeId) -> &[CapturedPacket] {
+        &self.nodes[node.0].received
+    }
+
+    /// Returns and forgets the packets `node` has received so far.
+    pub fn take_received(&mut self, node: NodeId) -> Vec<CapturedPacket> {
+        std::mem::take(&mut self.nodes[node.0].received)
+    }
+
+    /// Sends `payload` from `from` to `destination`.
+    ///
+    /// The packet arrives after the configured latency, once time is advanced far enough.
+    pub fn send(&mut self, from: NodeId, destination: SocketAddr, payload: &[u8]) {
+        let arrival = self.clock.now() + self.latency;
+        let source = self.nodes[from.0].addr;
+        let receivers = (0..self.nodes.len())
+            .filter(|&i| match destination.ip().is_multicast() {
+                true => i != from.0,
+                false => self.nodes[i].addr == destination,
+            })
+            .collect::<Vec<_>>();
+        if receivers.is_empty() {
+            log::debug!(
+                "no node at {}, dropping packet from {}",
+                destination,
+                source
+            );
+        }
+        for i in receivers {
+            if self.lose() {
+                log::trace!("packet from {} to {} was lost", source, self.nodes[i].addr);
+                continue;
+            }
+            let packet = CapturedPacket {
+                timestamp: arrival - self.start,
+                source,
+                destination,
+                payload: payload.to_vec(),
+            };
+            self.in_flight.push((arrival, i, packet));
+        }
+    }
+
+    /// Advances time by `duration`, delivering all packets that arrive in the meantime.
+    ///
+    /// Responses of [`Responder`]s are sent right away, and are delivered too if they arrive within
+    /// `duration`.
+    pub fn advance(&mut self, duration: Duration) {
+        let end = self.clock.now() + duration;
+        while self.deliver_next(Some(end)) {}
+        self.clock
+            .advance(end.saturating_duration_since(self.clock.now()));
+    }
+
+    /// Delivers packets until none are left in flight, advancing time as needed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the nodes keep on sending packets to each other, since that would never end.
+    pub fn run_until_idle(&mut self) {
+        for _ in 0..Self::MAX_DELIVERIES {
+            if !self.deliver_next(None) {
+                return;
+            }
+        }
+        panic!("network did not become idle");
+    }
+
+    /// Delivers the earliest packet in flight, unless it arrives after `deadline`.
+    ///
+    /// Returns whether a packet was delivered.
+    fn deliver_next(&mut self, deadline: Option<Instant>) -> bool {
+        let Some(next) = (0..self.in_flight.len()).min_by_key(|&i| self.in_flight[i].0) else {
+            return false;
+        };
+        let arrival = self.in_flight[next].0;
+        if deadline.is_some_and(|deadline| arrival > deadline) {
+            return false;
+        }
+        let (_, to, packet) = self.in_flight.remove(next);
+        self.clock
+            .advance(arrival.saturating_duration_since(self.clock.now()));
+
+        let node = &mut self.nodes[to];
+        let response = match &mut node.responder {
+            Some(responder) => match responder.respond(packet.payload(), packet.source()) {
+                Ok(response) => response.map(|(payload, dest)| (payload.to_vec(), dest)),
+                Err(e) => {
+                    log::debug!("{} failed to handle packet: {}", node.addr, e);
+                    None
+                }
+            },
+            None => None,
+        };
+        node.received.push(packet);
+        if let Some((payload, destination)) = response {
+            self.send(NodeId(to), destination, &payload);
+        }
+        true
+    }
+
+    /// Returns whether the next packet should be lost.
+    fn lose(&mut self) -> bool {
+        if self.loss <= 0.0 {
+            return false;
+        }
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        ((self.rng >> 11) as f64 / (1u64 << 53) as f64) < self.loss
+    }
+}
+
+impl<R: Responder> Default for VirtualLan<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::ControlFlow;
+
+    use crate::{
+        label,
+        name::{DomainName, Label},
+        packet::{
+            encoder::{MessageEncoder, Question},
+            Header,
+        },
+        server::Zone,
+    };
+
+    use super::*;
+
+    #[test]
+    fn hex() {
+        assert_eq!(
+            parse_hex("00 ab # comment\nFF").unwrap(),
+            [0x00, 0xab, 0xff]
+        );
+        assert_eq!(parse_hex("abc"), Err(Error::InvalidValue));
+        assert_eq!(parse_hex("zz"), Err(Error::InvalidValue));
+    }
+
+    /// Builds a little-endian pcap file with microsecond timestamps and Ethernet framing.
+    fn pcap(packets: &[(u32, SocketAddr, SocketAddr, &[u8])]) -> Vec<u8> {
+        let mut file = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        file.extend_from_slice(&[0; 8]);
+        file.extend_from_slice(&65535u32.to_le_bytes());
+        file.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        for &(micros, source, destination, payload) in packets {
+            let (SocketAddr::V4(source), SocketAddr::V4(destination)) = (source, destination)
+            else {
+                unimplemented!()
+            };
+            let mut frame = vec![0; 12];
+            frame.extend_from_slice(&[0x08, 0x00]);
+            let total_len = (20 + 8 + payload.len()) as u16;
+            frame.extend_from_slice(&[0x45, 0]);
+            frame.extend_from_slice(&total_len.to_be_bytes());
+            frame.extend_from_slice(&[0, 0, 0x40, 0, 255, 17, 0, 0]);
+            frame.extend_from_slice(&source.ip().octets());
+            frame.extend_from_slice(&destination.ip().octets());
+            frame.extend_from_slice(&source.port().to_be_bytes());
+            frame.extend_from_slice(&destination.port().to_be_bytes());
+            frame.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+            frame.extend_from_slice(&[0, 0]);
+            frame.extend_from_slice(payload);
+
+            file.extend_from_slice(&1u32.to_le_bytes());
+            file.extend_from_slice(&micros.to_le_bytes());
+            file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            file.extend_from_slice(&frame);
+        }
+        file
+    }
+
+    fn query(name: &str) -> Vec<u8> {
+        let name = name.parse().unwrap();
+        let mut buf = [0; 512];
+        let mut enc = MessageEncoder::new(&mut buf);
+        enc.question(Question::new(&name));
+        let len = enc.finish().unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn replay_capture() {
+        let peer: SocketAddr = "10.0.0.2:5353".parse().unwrap();
+        let group: SocketAddr = "224.0.0.251:5353".parse().unwrap();
+        let mut response = vec![0; 12];
+        let header: &mut Header = bytemuck::from_bytes_mut(&mut response[..]);
+        header.set_response(true);
+        let file = pcap(&[
+            (0, peer, group, &query("host.local")),
+            (1000, peer, group, &response),
+            (500_000, peer, group, &query("other.local")),
+        ]);
+        let capture = read_pcap(&file).unwrap();
+        assert_eq!(capture.len(), 3);
+        assert_eq!(capture[0].source(), peer);
+        assert_eq!(capture[0].destination(), group);
+        assert_eq!(capture[2].timestamp(), Duration::from_micros(1_500_000));
+        assert!(capture[1].is_response());
+        assert!(read_pcap(&file[..file.len() - 1]).is_err());
+
+        let adv = Advertiser::new(label!("host"), Ipv4Addr::new(10, 0, 0, 1).into()).unwrap();
+        let mut harness = Harness::new(adv);
+        let start = crate::clock::Clock::now(harness.clock());
+        assert_eq!(harness.replay(&capture), 2);
+        assert_eq!(
+            crate::clock::Clock::now(harness.clock()) - start,
+            Duration::from_micros(500_000)
+        );
+        let [sent] = &harness.take_sent()[..] else {
+            panic!("expected a single response");
+        };
+        assert_eq!(sent.destination(), group);
+        expect_test::expect![[r#"
+            [
+                "response (id=0, op=QUERY, rcode=NO_ERROR, AA)",
+                "ANS: host.local.\t120\tIN\tA\t10.0.0.1",
+            ]
+        "#]]
+        .assert_debug_eq(&format_message(sent.payload()).unwrap());
+    }
+
+    #[test]
+    fn lan_conflict() {
+        let mut lan = VirtualLan::new();
+        lan.set_latency(Duration::from_millis(10));
+        let addr = |ip| SocketAddr::from((Ipv4Addr::new(10, 0, 0, ip), 5353));
+        let adv = |ip| Advertiser::new(label!("host"), Ipv4Addr::new(10, 0, 0, ip).into()).unwrap();
+        let first = lan.add_responder(addr(1), adv(1));
+        let second = lan.add_responder(addr(2), adv(2));
+        let group: SocketAddr = "224.0.0.251:5353".parse().unwrap();
+
+        // The second advertiser probes for the name the first one already uses.
+        let probe = lan.responder(second).build_probe().unwrap().to_vec();
+        lan.send(second, group, &probe);
+        lan.advance(Duration::from_millis(15));
+        assert_eq!(lan.received(first).len(), 1);
+        assert_eq!(lan.received(second).len(), 0);
+        lan.run_until_idle();
+        assert_eq!(lan.received(second).len(), 1);
+        assert_eq!(lan.clock().now() - lan.start, Duration::from_millis(20));
+        assert_eq!(lan.responder(first).current_hostname(), &label!("host"));
+        assert_eq!(lan.responder(second).current_hostname(), &label!("host-2"));
+        assert_eq!(lan.received(second)[0].source(), addr(1));
+        assert_eq!(
+            lan.received(second)[0].timestamp(),
+            Duration::from_millis(20)
+        );
+    }
+
+    #[test]
+    fn lan_browse() {
+        use crate::{
+            packet::{records::Record, QType},
+            service::{discovery::QueryBatch, InstanceDetails, ServiceInstance, ServiceTransport},
+        };
+
+        let mut lan = VirtualLan::new();
+        let group: SocketAddr = "224.0.0.251:5353".parse().unwrap();
+        for ip in 1..=3 {
+            let hostname = Label::new(format!("host{ip}"));
+            let mut adv =
+                Advertiser::new(hostname.clone(), Ipv4Addr::new(10, 0, 0, ip).into()).unwrap();
+            adv.add_instance(
+                ServiceInstance::new(hostname.clone(), label!("_http"), ServiceTransport::TCP),
+                InstanceDetails::new(DomainName::from_iter([hostname, label!("local")]), 80),
+            );
+            lan.add_responder(SocketAddr::from((Ipv4Addr::new(10, 0, 0, ip), 5353)), adv);
+        }
+        let browser = lan.add_endpoint("10.0.0.100:5353".parse().unwrap());
+
+        let mut instances = Vec::new();
+        let mut batch = QueryBatch::new();
+        batch.add("_http._tcp.local".parse().unwrap(), QType::PTR, |rr| {
+            if let Some(Ok(Record::PTR(ptr))) = rr.as_enum() {
+                instances.push(ptr.ptrdname().to_string());
+            }
+            ControlFlow::Continue(())
+        });
+        batch.set_multicast(true);
+        let mut queries = Vec::new();
+        batch
+            .encode_queries(0, &mut [0; 512], None, |query| queries.push(query.to_vec()))
+            .unwrap();
+        lan.send(browser, group, &queries[0]);
+
+        // With 50% packet loss, only some of the responders hear the query.
+        lan.set_loss(0.5);
+        lan.set_seed(1);
+        lan.run_until_idle();
+        let lossy = lan.take_received(browser);
+        assert!(lossy.len() < 3, "{}", lossy.len());
+
+        lan.set_loss(0.0);
+        lan.send(browser, group, &queries[0]);
+        lan.run_until_idle();
+        for packet in lan.take_received(browser) {
+            let flow = batch.add_response(packet.payload()).unwrap();
+            assert_eq!(flow, ControlFlow::Continue(()));
+        }
+        drop(batch);
+        instances.sort();
+        assert_eq!(
+            instances,
+            [
+                "host1._http._tcp.local.",
+                "host2._http._tcp.local.",
+                "host3._http._tcp.local."
+            ]
+        );
+    }
+
+    #[test]
+    fn server_harness() {
+        let mut harness = Harness::new(Server::new(Zone::new("example.com".parse().unwrap())));
+        let source: SocketAddr = "192.0.2.1:5300".parse().unwrap();
+        let sent = harness
+            .deliver(&query("www.example.com"), source)
+            .unwrap()
+            .unwrap();
+        assert_eq!(sent.destination(), source);
+        assert_eq!(
+            format_message(sent.payload()).unwrap()[0],
+            "response (id=0, op=QUERY, rcode=NX_DOMAIN, AA)"
+        );
+    }
+}
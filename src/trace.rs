@@ -0,0 +1,26 @@
+//! Optional [`tracing`] instrumentation.
+//!
+//! When the `tracing` feature is enabled, these macros create spans and emit events with
+//! structured fields. Otherwise they expand to nothing, so the fields are not evaluated. Plain
+//! `log` output is unaffected either way.
+//!
+//! [`tracing`]: https://docs.rs/tracing
+
+/// Enters a `DEBUG` span until the end of the enclosing block.
+///
+/// Must only be used in synchronous code, since the guard must not be held across `.await`
+/// points.
+macro_rules! trace_span {
+    ($($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!($($args)*).entered();
+    };
+}
+
+/// Emits a `DEBUG` event.
+macro_rules! trace_event {
+    ($($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($args)*);
+    };
+}
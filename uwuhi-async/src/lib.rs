@@ -1,6 +1,15 @@
 //! An async implementation of DNS, mDNS, and (m)DNS-based Service Discovery.
+//!
+//! The I/O types in this crate use the [`runtime::Runtime`] abstraction for sockets and timers.
+//! With the default `async-io` feature, they work out of the box on all platforms supported by
+//! `async-io`. Without it, the crate can be compiled for `wasm32` targets by plugging in a custom
+//! runtime.
+
+#[macro_use]
+mod trace;
 
 pub mod resolver;
+pub mod runtime;
 pub mod service;
 
 pub use uwuhi::*;
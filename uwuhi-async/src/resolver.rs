@@ -1,51 +1,94 @@
 //! DNS name resolution.
 
 use std::{
-    io,
-    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    future::Future,
+    io, mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    task::Poll,
     time::Duration,
 };
 
-use async_io::{Async, Timer};
-use futures_lite::future;
+use futures_lite::{future, AsyncReadExt, AsyncWriteExt};
+
 pub use uwuhi::resolver::*;
-use uwuhi::{name::DomainName, DNS_BUFFER_SIZE, MDNS_BUFFER_SIZE};
+use uwuhi::{
+    __private::{checked_message_size, default_max_message_size},
+    name::DomainName,
+    packet::{decoder::MessageDecoder, records::Record, Class, Header, QType},
+    resolver::cache::ResolverCache,
+    Error, DNS_BUFFER_SIZE, MDNS_BUFFER_SIZE,
+};
+
+use crate::runtime::{self, now, AsyncUdpSocket, DefaultRuntime, Runtime};
 
-pub struct AsyncResolver {
+pub struct AsyncResolver<R: Runtime = DefaultRuntime> {
     servers: Vec<SocketAddr>,
-    sock: Async<UdpSocket>,
+    sock: R::UdpSocket,
     ip_buf: Vec<IpAddr>,
     is_multicast: bool,
+    is_llmnr: bool,
+    max_message_size: usize,
     timeout: Duration,
 }
 
 impl AsyncResolver {
+    /// Creates a new DNS resolver that will contact the given server.
+    pub async fn new(server: SocketAddr) -> Result<Self, Error> {
+        Self::with_runtime(server).await
+    }
+
+    /// Creates a new mDNS resolver that will use IPv4.
+    pub async fn new_multicast_v4() -> Result<Self, Error> {
+        Self::new("224.0.0.251:5353".parse().unwrap()).await
+    }
+
+    /// Creates a new mDNS resolver that will use IPv6.
+    pub async fn new_multicast_v6() -> Result<Self, Error> {
+        Self::new("[ff02::fb]:5353".parse().unwrap()).await
+    }
+
+    /// Creates a new LLMNR resolver that will use IPv4.
+    pub async fn new_llmnr_v4() -> Result<Self, Error> {
+        Self::new("224.0.0.252:5355".parse().unwrap()).await
+    }
+
+    /// Creates a new LLMNR resolver that will use IPv6.
+    pub async fn new_llmnr_v6() -> Result<Self, Error> {
+        Self::new("[ff02::1:3]:5355".parse().unwrap()).await
+    }
+}
+
+impl<R: Runtime> AsyncResolver<R> {
     const DEFAULT_TIMEOUT: Duration = Duration::from_millis(500);
 
-    /// Creates a new DNS resolver that will contact the given server.
-    pub async fn new(server: SocketAddr) -> io::Result<Self> {
+    /// Creates a new DNS resolver that will contact the given server, using the [`Runtime`] `R`.
+    ///
+    /// This can also be used to create mDNS or LLMNR resolvers, by passing the corresponding
+    /// multicast address.
+    pub async fn with_runtime(server: SocketAddr) -> Result<Self, Error> {
         let bind_addr: SocketAddr = if server.is_ipv6() {
             (Ipv6Addr::UNSPECIFIED, 0).into()
         } else {
             (Ipv4Addr::UNSPECIFIED, 0).into()
         };
-        Ok(Self {
+        Ok(Self::with_socket(R::bind_udp(bind_addr)?, server))
+    }
+
+    /// Creates a new DNS resolver that will use `sock` to contact the given server.
+    ///
+    /// This allows using a socket that was configured in ways this library does not support
+    /// directly (eg. bound to a specific interface or port). `sock` must use the same address
+    /// family as `server`.
+    pub fn with_socket(sock: R::UdpSocket, server: SocketAddr) -> Self {
+        Self {
             servers: vec![server],
-            sock: Async::<UdpSocket>::bind(bind_addr)?,
+            sock,
             ip_buf: Vec::new(),
-            is_multicast: bind_addr.ip().is_multicast(),
+            is_multicast: server.ip().is_multicast(),
+            is_llmnr: server.ip().is_multicast() && server.port() == 5355,
+            max_message_size: default_max_message_size(server),
             timeout: Self::DEFAULT_TIMEOUT,
-        })
-    }
-
-    /// Creates a new mDNS resolver that will use IPv4.
-    pub async fn new_multicast_v4() -> io::Result<Self> {
-        Self::new("224.0.0.251:5353".parse().unwrap()).await
-    }
-
-    /// Creates a new mDNS resolver that will use IPv6.
-    pub async fn new_multicast_v6() -> io::Result<Self> {
-        Self::new("[ff02::fb]:5353".parse().unwrap()).await
+        }
     }
 
     /// Adds another server to be contacted by this resolver.
@@ -73,74 +116,644 @@ impl AsyncResolver {
         self.servers.push(server);
     }
 
+    /// Sets the largest DNS message this resolver can receive, in bytes.
+    ///
+    /// Defaults to [`DNS_BUFFER_SIZE`] for unicast DNS, and to [`MDNS_BUFFER_SIZE`] for mDNS and
+    /// LLMNR. [`MDNS_MAX_MESSAGE_SIZE`] can be used for mDNS on networks with jumbo frames.
+    ///
+    /// When using unicast DNS with a size larger than [`DNS_BUFFER_SIZE`], the resolver advertises
+    /// the size to servers via an EDNS(0) `OPT` record, allowing them to send larger responses.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is smaller than [`DNS_BUFFER_SIZE`], which every DNS client must support,
+    /// or larger than 65535, the maximum size of a UDP payload.
+    ///
+    /// [`MDNS_MAX_MESSAGE_SIZE`]: crate::MDNS_MAX_MESSAGE_SIZE
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = checked_message_size(size);
+    }
+
     /// Sets the timeout after which to abort a resolution attempt.
     ///
     /// This is the timeout for individual receive operations, not for the whole query. Packets that
     /// don't match the query that was sent will be ignored, but still reset the timeout.
-    pub fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+    ///
+    /// If no response at all arrives before the timeout passes, the query is sent once more before
+    /// giving up.
+    pub fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
         self.timeout = timeout;
         Ok(())
     }
 
     /// Attempts to resolve `hostname` using the configured DNS servers.
     ///
-    /// If the query times out, an error of type [`io::ErrorKind::WouldBlock`] or
-    /// [`io::ErrorKind::TimedOut`] will be returned.
+    /// If the query times out, [`Error::Timeout`] will be returned. If every server answered with
+    /// an error (or the query timed out after some did), [`Error::Resolve`] is returned instead.
     ///
     /// The resolver does not perform recursive resolution (it is a "stub resolver"). It does set
     /// the `RD` bit in the query, which instructs the server to perform recursion.
     pub async fn resolve(
         &mut self,
         hostname: &str,
-    ) -> io::Result<impl Iterator<Item = IpAddr> + '_> {
-        let name = DomainName::from_str(&hostname)?;
+    ) -> Result<impl Iterator<Item = IpAddr> + '_, Error> {
+        let name = DomainName::from_str(hostname)?;
         self.resolve_domain(&name).await
     }
 
     /// Attempts to resolve a [`DomainName`] using the configured DNS servers.
     ///
-    /// If the query times out, an error of type [`io::ErrorKind::WouldBlock`] or
-    /// [`io::ErrorKind::TimedOut`] will be returned.
+    /// If the query times out, [`Error::Timeout`] will be returned. If every server answered with
+    /// an error (or the query timed out after some did), [`Error::Resolve`] is returned instead.
     ///
     /// The resolver does not perform recursive resolution (it is a "stub resolver"). It does set
     /// the `RD` bit in the query, which instructs the server to perform recursion.
     pub async fn resolve_domain(
         &mut self,
         name: &DomainName,
-    ) -> io::Result<impl Iterator<Item = IpAddr> + '_> {
+    ) -> Result<impl Iterator<Item = IpAddr> + '_, Error> {
+        self.resolve_domain_with(name, &mut |_| {}).await
+    }
+
+    /// Like [`AsyncResolver::resolve_domain`], but passes every response that is used to
+    /// `on_response`.
+    async fn resolve_domain_with(
+        &mut self,
+        name: &DomainName,
+        on_response: &mut (dyn FnMut(&[u8]) + Send),
+    ) -> Result<impl Iterator<Item = IpAddr> + '_, Error> {
+        instrument!(
+            self.resolve_impl(name, on_response),
+            "resolve",
+            %name,
+            llmnr = self.is_llmnr,
+            servers = ?self.servers,
+        )
+        .await?;
+        Ok(self.ip_buf.iter().copied())
+    }
+
+    async fn resolve_impl(
+        &mut self,
+        name: &DomainName,
+        on_response: &mut (dyn FnMut(&[u8]) + Send),
+    ) -> Result<(), Error> {
         self.ip_buf.clear();
 
         let mut send_buf = [0; MDNS_BUFFER_SIZE];
-        let data = encode_query(&mut send_buf, name);
-
-        log::trace!("resolving '{}', raw query: {:x?}", name, data);
+        // LLMNR queries must contain exactly one question (RFC 4795 §2.1.1), so `A` and `AAAA`
+        // records are queried separately, with their own IDs.
+        let mut queries = Vec::new();
+        if self.is_llmnr {
+            for qtype in [QType::A, QType::AAAA] {
+                let id = Header::random_id();
+                queries.push(encode_llmnr_query(&mut send_buf, id, name, qtype).to_vec());
+            }
+        } else if !self.is_multicast && self.max_message_size > DNS_BUFFER_SIZE {
+            let data = encode_edns_query(&mut send_buf, name, self.max_message_size as u16);
+            queries.push(data.to_vec());
+        } else {
+            queries.push(encode_query(&mut send_buf, name).to_vec());
+        }
 
-        // FIXME: retransmit
-        for addr in &self.servers {
-            self.sock.send_to(data, *addr).await?;
+        for data in &queries {
+            log::trace!("resolving '{}', raw query: {:x?}", name, data);
+            for addr in &self.servers {
+                self.sock.send_to(data, *addr).await?;
+            }
         }
+        let mut retransmitted = false;
 
+        // Servers that answered with an error, and the last such error.
+        let mut failed = Vec::new();
+        let mut error = None;
+        // The LLMNR queries that have been answered.
+        let mut answered = Vec::new();
+        let mut recv_buf = vec![0; self.max_message_size];
         loop {
-            let mut recv_buf = [0; DNS_BUFFER_SIZE];
-            let timeout = async {
-                Timer::after(self.timeout).await;
-                Err(io::ErrorKind::TimedOut.into())
+            let Some(res) =
+                runtime::timeout::<R, _>(self.timeout, self.sock.recv_from(&mut recv_buf)).await
+            else {
+                if !retransmitted && answered.is_empty() && failed.is_empty() {
+                    // Nothing has been received, so the queries (or the responses) may have been
+                    // lost. Try once more.
+                    log::debug!("no response for '{}', retransmitting", name);
+                    retransmitted = true;
+                    for data in &queries {
+                        for addr in &self.servers {
+                            self.sock.send_to(data, *addr).await?;
+                        }
+                    }
+                    continue;
+                }
+                if !self.ip_buf.is_empty() {
+                    // Only one of the LLMNR queries was answered.
+                    return Ok(());
+                }
+                return Err(error.map_or(Error::Timeout, Error::from));
             };
-            let (b, addr) = future::or(self.sock.recv_from(&mut recv_buf), timeout).await?;
+            let (b, addr) = res?;
             let recv = &recv_buf[..b];
             log::trace!("recv from {}: {:x?}", addr, recv);
+            // `Instant` isn't available on all targets supported by this crate, so there's no RTT.
+            trace_event!(server = %addr, len = b, "received response");
+
+            // mDNS responders may set the ID of responses to 0, other responses have to carry the ID
+            // of one of our queries.
+            let index = if self.is_multicast && !self.is_llmnr {
+                0
+            } else {
+                match queries.iter().position(|q| is_response_to(recv, q)) {
+                    Some(index) => index,
+                    None => continue,
+                }
+            };
+            if self.is_llmnr && is_tentative_response(recv) {
+                log::debug!("ignoring tentative LLMNR response from {}", addr);
+                continue;
+            }
+            let mut tcp_buf = Vec::new();
+            let recv = self
+                .complete_response(addr, &queries[index], recv, &mut tcp_buf)
+                .await;
+            on_response(recv);
 
             match decode_answer(recv, &mut self.ip_buf) {
                 Ok(()) => {
-                    if !self.ip_buf.is_empty() {
-                        // We return once any answer contains IP addresses.
-                        return Ok(self.ip_buf.iter().copied());
+                    if !answered.contains(&index) {
+                        answered.push(index);
+                    }
+                    // We return once any answer contains IP addresses (for LLMNR, once both
+                    // queries have been answered).
+                    if !self.ip_buf.is_empty() && answered.len() == queries.len() {
+                        return Ok(());
                     }
                 }
                 Err(e) => {
                     log::warn!("failed to decode response from {}: {:?}", addr, e);
                 }
             }
+
+            if !self.is_multicast {
+                if let Some(e) = ResolveError::from_response(recv, addr) {
+                    log::debug!("{}", e);
+                    if !failed.contains(&addr) {
+                        failed.push(addr);
+                    }
+                    if failed.len() == self.servers.len() {
+                        return Err(e.into());
+                    }
+                    error = Some(e);
+                }
+            }
+        }
+    }
+
+    /// Looks up the host names that `addr` belongs to, via a reverse (`PTR`) query for
+    /// [`DomainName::arpa`].
+    ///
+    /// This is the async version of [`SyncResolver::resolve_addr`], and behaves like it.
+    pub async fn resolve_addr(&mut self, addr: IpAddr) -> io::Result<Vec<DomainName>> {
+        Ok(self.resolve_hostname(addr).await?)
+    }
+
+    /// Looks up the host names claiming `addr` on the local network, like `gethostbyaddr`.
+    ///
+    /// This is the async version of [`SyncResolver::resolve_hostname`], and behaves like it.
+    pub async fn resolve_hostname(&mut self, addr: IpAddr) -> Result<Vec<DomainName>, Error> {
+        let records = self.query(&DomainName::arpa(addr), QType::PTR).await?;
+        let mut names: Vec<DomainName> = Vec::new();
+        for record in records {
+            if let Record::PTR(ptr) = record {
+                let target = ptr.ptrdname();
+                if !names.iter().any(|n| n.eq_ignore_ascii_case(target)) {
+                    names.push(target.clone());
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    /// Queries the configured servers for records of type `qtype` owned by `name`, and returns
+    /// the records from the *Answer* section of the responses.
+    ///
+    /// This is the async version of [`SyncResolver::query`]. On mDNS and LLMNR resolvers,
+    /// responses are collected until the timeout passes and the records from all of them are
+    /// returned. If none arrive, [`Error::Timeout`] is returned.
+    ///
+    /// Unicast DNS resolvers return the records from the first response that contains any. If
+    /// every server responded without any matching records, an empty list is returned, unless one
+    /// of them answered with an error, which is returned as [`Error::Resolve`].
+    pub async fn query(
+        &mut self,
+        name: &DomainName,
+        qtype: QType,
+    ) -> Result<Vec<Record<'static>>, Error> {
+        self.query_with(name, qtype, &mut |_| {}).await
+    }
+
+    /// Like [`AsyncResolver::query`], but passes every response that is used to `on_response`.
+    async fn query_with(
+        &mut self,
+        name: &DomainName,
+        qtype: QType,
+        on_response: &mut (dyn FnMut(&[u8]) + Send),
+    ) -> Result<Vec<Record<'static>>, Error> {
+        let mut lookup = RecordLookup::new(name, qtype, &self.servers)?;
+        log::trace!(
+            "resolving {:?} of '{}', raw query: {:x?}",
+            qtype,
+            name,
+            lookup.query()
+        );
+        for addr in &self.servers {
+            self.sock.send_to(lookup.query(), *addr).await?;
+        }
+
+        let mut recv_buf = vec![0; self.max_message_size];
+        loop {
+            let Some(res) =
+                runtime::timeout::<R, _>(self.timeout, self.sock.recv_from(&mut recv_buf)).await
+            else {
+                match lookup.handle_timeout() {
+                    Some(res) => return res,
+                    None => {
+                        log::debug!("no response for {:?} of '{}', retransmitting", qtype, name);
+                        for addr in &self.servers {
+                            self.sock.send_to(lookup.query(), *addr).await?;
+                        }
+                        continue;
+                    }
+                }
+            };
+            let (b, addr) = res?;
+            let recv = &recv_buf[..b];
+            log::trace!("recv from {}: {:x?}", addr, recv);
+
+            if !lookup.accepts(recv) {
+                continue;
+            }
+            let mut tcp_buf = Vec::new();
+            let recv = self
+                .complete_response(addr, lookup.query(), recv, &mut tcp_buf)
+                .await;
+            on_response(recv);
+            if let Some(res) = lookup.handle_response(recv, addr) {
+                return res;
+            }
+        }
+    }
+
+    /// If `recv` is a truncated response from the unicast DNS server `server`, repeats `query`
+    /// over TCP ([RFC 7766]) and returns the complete response.
+    ///
+    /// Returns `recv` itself if it isn't truncated, or if the TCP query fails.
+    ///
+    /// [RFC 7766]: https://datatracker.ietf.org/doc/html/rfc7766
+    async fn complete_response<'b>(
+        &self,
+        server: SocketAddr,
+        query: &[u8],
+        recv: &'b [u8],
+        tcp_buf: &'b mut Vec<u8>,
+    ) -> &'b [u8] {
+        let truncated = MessageDecoder::new(recv).is_ok_and(|dec| dec.header().is_truncated());
+        if self.is_multicast || !truncated {
+            return recv;
+        }
+
+        log::debug!("response from {} is truncated, retrying over TCP", server);
+        match runtime::timeout::<R, _>(self.timeout, query_tcp::<R>(server, query)).await {
+            Some(Ok(resp)) if is_response_to(&resp, query) => {
+                log::trace!("TCP recv from {}: {:x?}", server, resp);
+                *tcp_buf = resp;
+                tcp_buf
+            }
+            Some(Ok(_)) => {
+                log::debug!("TCP response from {} doesn't match the query", server);
+                recv
+            }
+            Some(Err(e)) => {
+                log::debug!(
+                    "TCP query to {} failed, using truncated response: {}",
+                    server,
+                    e
+                );
+                recv
+            }
+            None => {
+                log::debug!(
+                    "TCP query to {} timed out, using truncated response",
+                    server
+                );
+                recv
+            }
+        }
+    }
+}
+
+/// Sends `query` to `server` over a new TCP connection, and returns the response.
+async fn query_tcp<R: Runtime>(server: SocketAddr, query: &[u8]) -> io::Result<Vec<u8>> {
+    let mut stream = R::connect_tcp(server).await?;
+    let len = u16::try_from(query.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "query too large"))?;
+    let mut framed = Vec::with_capacity(2 + query.len());
+    framed.extend_from_slice(&len.to_be_bytes());
+    framed.extend_from_slice(query);
+    stream.write_all(&framed).await?;
+
+    let mut len = [0; 2];
+    stream.read_exact(&mut len).await?;
+    let mut resp = vec![0; u16::from_be_bytes(len).into()];
+    stream.read_exact(&mut resp).await?;
+    Ok(resp)
+}
+
+/// An [`AsyncResolver`] that caches the answers it receives in a [`ResolverCache`].
+///
+/// This is the async version of [`CachingResolver`](cache::CachingResolver). Queries are answered
+/// from the cache while the records from a previous response are still fresh. On `wasm32`, where
+/// [`Instant`](std::time::Instant) isn't available, nothing is cached.
+pub struct AsyncCachingResolver<R: Runtime = DefaultRuntime> {
+    resolver: AsyncResolver<R>,
+    cache: ResolverCache,
+}
+
+impl<R: Runtime> AsyncCachingResolver<R> {
+    /// Creates an [`AsyncCachingResolver`] that sends queries via `resolver`.
+    pub fn new(resolver: AsyncResolver<R>) -> Self {
+        Self {
+            resolver,
+            cache: ResolverCache::new(),
+        }
+    }
+
+    /// Returns a reference to the wrapped [`AsyncResolver`].
+    #[inline]
+    pub fn resolver(&self) -> &AsyncResolver<R> {
+        &self.resolver
+    }
+
+    /// Returns a mutable reference to the wrapped [`AsyncResolver`].
+    ///
+    /// Queries made directly through the [`AsyncResolver`] bypass the cache.
+    #[inline]
+    pub fn resolver_mut(&mut self) -> &mut AsyncResolver<R> {
+        &mut self.resolver
+    }
+
+    /// Returns a reference to the [`ResolverCache`].
+    #[inline]
+    pub fn cache(&self) -> &ResolverCache {
+        &self.cache
+    }
+
+    /// Returns a mutable reference to the [`ResolverCache`].
+    #[inline]
+    pub fn cache_mut(&mut self) -> &mut ResolverCache {
+        &mut self.cache
+    }
+
+    /// Returns the wrapped [`AsyncResolver`], discarding the cache.
+    pub fn into_inner(self) -> AsyncResolver<R> {
+        self.resolver
+    }
+
+    /// Resolves `hostname` like [`AsyncResolver::resolve`], answering from the cache if possible.
+    pub async fn resolve(&mut self, hostname: &str) -> Result<Vec<IpAddr>, Error> {
+        let name = DomainName::from_str(hostname)?;
+        self.resolve_domain(&name).await
+    }
+
+    /// Resolves `name` like [`AsyncResolver::resolve_domain`], answering from the cache if
+    /// possible.
+    ///
+    /// Like [`CachingResolver::resolve_domain`](cache::CachingResolver::resolve_domain), the
+    /// cache is used if it contains fresh `A` or `AAAA` records of `name`.
+    pub async fn resolve_domain(&mut self, name: &DomainName) -> Result<Vec<IpAddr>, Error> {
+        let Some(now) = now() else {
+            return Ok(self.resolver.resolve_domain(name).await?.collect());
+        };
+        let a = self.cache.get(name, QType::A, Class::IN, now);
+        let aaaa = self.cache.get(name, QType::AAAA, Class::IN, now);
+        if a.is_some() || aaaa.is_some() {
+            log::trace!("answering query for '{}' from cache", name);
+            let ips = a
+                .into_iter()
+                .chain(aaaa)
+                .flat_map(|cached| cached.records())
+                .filter_map(|record| match record {
+                    Record::A(a) => Some(IpAddr::V4(a.addr().octets().into())),
+                    Record::AAAA(a) => Some(IpAddr::V6(a.addr().octets().into())),
+                    _ => None,
+                })
+                .collect();
+            return Ok(ips);
+        }
+
+        let cache = &mut self.cache;
+        let ips = self
+            .resolver
+            .resolve_domain_with(name, &mut |msg| {
+                if let Err(e) = cache.insert_response(msg, now) {
+                    log::debug!("failed to cache response: {}", e);
+                }
+            })
+            .await?;
+        Ok(ips.collect())
+    }
+
+    /// Looks up the records of type `qtype` owned by `name` like [`AsyncResolver::query`],
+    /// answering from the cache if possible.
+    pub async fn query(
+        &mut self,
+        name: &DomainName,
+        qtype: QType,
+    ) -> Result<Vec<Record<'static>>, Error> {
+        let Some(now) = now() else {
+            return self.resolver.query(name, qtype).await;
+        };
+        if let Some(cached) = self.cache.get(name, qtype, Class::IN, now) {
+            log::trace!("answering {:?} query for '{}' from cache", qtype, name);
+            return Ok(cached.records().to_vec());
+        }
+
+        let cache = &mut self.cache;
+        self.resolver
+            .query_with(name, qtype, &mut |msg| {
+                if let Err(e) = cache.insert_response(msg, now) {
+                    log::debug!("failed to cache response: {}", e);
+                }
+            })
+            .await
+    }
+}
+
+/// Resolves `host` via `resolver` and opens a TCP connection to `port` on one of its addresses.
+///
+/// This is the async version of [`uwuhi::resolver::connect_happy_eyeballs`], and races the
+/// connection attempts in the same way: they are made in the order given by
+/// [`sort_happy_eyeballs`], and a new one is started whenever the previous one hasn't succeeded
+/// after [`CONNECTION_ATTEMPT_DELAY`] (or has failed). The first connection to be established is
+/// returned, and all others are dropped.
+///
+/// If every attempt fails, the error from the last one is returned.
+pub async fn connect_happy_eyeballs<R: Runtime>(
+    resolver: &mut AsyncResolver<R>,
+    host: &str,
+    port: u16,
+) -> Result<R::TcpStream, Error> {
+    let ips = match host.parse::<IpAddr>() {
+        Ok(ip) => vec![ip],
+        Err(_) => resolver.resolve(host).await?.collect(),
+    };
+    if ips.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no addresses found for '{}'", host),
+        )
+        .into());
+    }
+
+    let mut addrs = sort_happy_eyeballs(&ips)
+        .into_iter()
+        .map(|ip| SocketAddr::new(ip, port));
+    let mut attempts = Vec::new();
+    let mut delay = Box::pin(R::sleep(CONNECTION_ATTEMPT_DELAY));
+    let mut error = None;
+    let mut start_next = true;
+    future::poll_fn(|cx| loop {
+        if mem::take(&mut start_next) {
+            if let Some(addr) = addrs.next() {
+                log::trace!("connecting to {}", addr);
+                let attempt = async move { (addr, R::connect_tcp(addr).await) };
+                attempts.push(Box::pin(attempt));
+                delay = Box::pin(R::sleep(CONNECTION_ATTEMPT_DELAY));
+            }
+        }
+        if attempts.is_empty() {
+            return Poll::Ready(Err(error.take().unwrap_or(Error::Timeout)));
+        }
+
+        let mut i = 0;
+        while i < attempts.len() {
+            match attempts[i].as_mut().poll(cx) {
+                Poll::Ready((addr, Ok(stream))) => {
+                    log::debug!("connected to {}", addr);
+                    return Poll::Ready(Ok(stream));
+                }
+                Poll::Ready((addr, Err(e))) => {
+                    log::debug!("failed to connect to {}: {}", addr, e);
+                    drop(attempts.swap_remove(i));
+                    error = Some(e.into());
+                    start_next = true;
+                }
+                Poll::Pending => i += 1,
+            }
+        }
+        if !start_next && addrs.len() != 0 && delay.as_mut().poll(cx).is_ready() {
+            start_next = true;
+        }
+        if !start_next {
+            return Poll::Pending;
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future::block_on;
+    use uwuhi::packet::{
+        encoder::{MessageEncoder, ResourceRecord},
+        records::{A, SRV},
+    };
+
+    use crate::runtime::mock::{set_tcp_responder, Mock, MockSocket};
+
+    use super::*;
+
+    /// Encodes a response to `query` that answers its first question with `answers`.
+    fn response(query: &[u8], answers: &[Record<'_>]) -> Vec<u8> {
+        let mut dec = MessageDecoder::new(query).unwrap();
+        let header = *dec.header();
+        let mut questions = Vec::new();
+        while let Some(question) = dec.next() {
+            questions.push(question.unwrap());
+        }
+        let mut buf = [0; MDNS_BUFFER_SIZE];
+        let mut enc = MessageEncoder::response_to(&mut buf, &header, &questions);
+        for answer in answers {
+            enc.add_answer(ResourceRecord::new(questions[0].qname(), answer).ttl(300));
         }
+        let len = enc.finish().unwrap();
+        buf[..len].to_vec()
+    }
+
+    fn srv() -> Record<'static> {
+        Record::SRV(SRV::new(
+            0,
+            100,
+            389,
+            DomainName::from_str("dc1.example.com").unwrap(),
+        ))
+    }
+
+    #[test]
+    fn retransmits_and_checks_ids() {
+        let mut queries = 0;
+        let sock = MockSocket::new(move |query, _| {
+            queries += 1;
+            if queries == 1 {
+                // The first query is lost.
+                return Vec::new();
+            }
+            let a = |ip| Record::A(A::new(ip));
+            let mut other = response(query, &[a(Ipv4Addr::new(192, 0, 2, 66))]);
+            other[1] ^= 1;
+            vec![other, response(query, &[a(Ipv4Addr::new(192, 0, 2, 1))])]
+        });
+        let sent = sock.sent();
+
+        let server = "192.0.2.53:53".parse().unwrap();
+        let mut resolver = AsyncResolver::<Mock>::with_socket(sock, server);
+        let ips = block_on(async {
+            let name = DomainName::from_str("example.com").unwrap();
+            let ips = resolver.resolve_domain(&name).await.unwrap();
+            ips.collect::<Vec<_>>()
+        });
+        assert_eq!(ips, [IpAddr::from(Ipv4Addr::new(192, 0, 2, 1))]);
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0], sent[1]);
+    }
+
+    #[test]
+    fn tcp_fallback() {
+        let sock = MockSocket::new(|query, _| {
+            let mut truncated = response(query, &[]);
+            truncated[2] |= 0x02; // TC
+            vec![truncated]
+        });
+        set_tcp_responder(|query| response(query, &[srv()]));
+
+        let server = "192.0.2.53:53".parse().unwrap();
+        let mut resolver = AsyncResolver::<Mock>::with_socket(sock, server);
+        let name = DomainName::from_str("_ldap._tcp.example.com").unwrap();
+        let records = block_on(resolver.query(&name, QType::SRV)).unwrap();
+        assert_eq!(records, [srv()]);
+    }
+
+    #[test]
+    fn timeout() {
+        let sock = MockSocket::new(|_, _| Vec::new());
+        let sent = sock.sent();
+        let server = "192.0.2.53:53".parse().unwrap();
+        let mut resolver = AsyncResolver::<Mock>::with_socket(sock, server);
+        let name = DomainName::from_str("_ldap._tcp.example.com").unwrap();
+        let res = block_on(resolver.query(&name, QType::SRV));
+        assert!(matches!(res, Err(Error::Timeout)), "{:?}", res);
+        // The query was retransmitted once.
+        assert_eq!(sent.lock().unwrap().len(), 2);
     }
 }
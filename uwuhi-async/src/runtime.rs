@@ -0,0 +1,367 @@
+//! Pluggable I/O runtime.
+//!
+//! The async resolver, service discoverer and service advertiser are generic over a [`Runtime`],
+//! which provides UDP sockets, TCP connections and timers. By default, [`AsyncIo`] (based on the `async-io` crate) is used.
+//!
+//! Disabling the default `async-io` feature removes that dependency, which allows this crate to be
+//! compiled for targets like `wasm32-unknown-unknown`. In that configuration, a custom [`Runtime`]
+//! has to be passed to the `with_runtime` constructors.
+
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use futures_lite::{AsyncRead, AsyncWrite};
+
+/// Provides sockets and timers to the async types in this crate.
+pub trait Runtime {
+    /// The UDP socket type used by this runtime.
+    type UdpSocket: AsyncUdpSocket;
+
+    /// The TCP stream type used by this runtime.
+    type TcpStream: AsyncRead + AsyncWrite + Unpin;
+
+    /// Creates a UDP socket bound to `addr`.
+    fn bind_udp(addr: SocketAddr) -> io::Result<Self::UdpSocket>;
+
+    /// Converts a `std` UDP socket that has already been bound and configured into this runtime's
+    /// socket type.
+    ///
+    /// This is used for sockets that need options this trait doesn't cover, like the mDNS socket
+    /// of [`AsyncAdvertiser`]. The default implementation returns an error of kind
+    /// [`io::ErrorKind::Unsupported`], for runtimes on targets without `std` sockets.
+    ///
+    /// [`AsyncAdvertiser`]: crate::service::advertising::AsyncAdvertiser
+    fn from_std_udp(_sock: std::net::UdpSocket) -> io::Result<Self::UdpSocket> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "runtime does not support `std` sockets",
+        ))
+    }
+
+    /// Opens a TCP connection to `addr`.
+    fn connect_tcp(addr: SocketAddr) -> impl Future<Output = io::Result<Self::TcpStream>>;
+
+    /// Returns a future that completes after `duration` has elapsed.
+    fn sleep(duration: Duration) -> impl Future<Output = ()>;
+}
+
+/// An asynchronous UDP socket.
+pub trait AsyncUdpSocket {
+    /// Sends `buf` to `addr`, returning the number of bytes sent.
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> impl Future<Output = io::Result<usize>>;
+
+    /// Receives a datagram into `buf`, returning its length and the address it was sent from.
+    fn recv_from(&self, buf: &mut [u8]) -> impl Future<Output = io::Result<(usize, SocketAddr)>>;
+}
+
+/// The [`Runtime`] used when none is specified.
+///
+/// This is [`AsyncIo`] when the `async-io` feature is enabled, and [`Unsupported`] otherwise.
+#[cfg(feature = "async-io")]
+pub type DefaultRuntime = AsyncIo;
+
+/// The [`Runtime`] used when none is specified.
+///
+/// This is [`AsyncIo`] when the `async-io` feature is enabled, and [`Unsupported`] otherwise.
+#[cfg(not(feature = "async-io"))]
+pub type DefaultRuntime = Unsupported;
+
+/// A [`Runtime`] backed by the `async-io` crate.
+#[cfg(feature = "async-io")]
+#[derive(Debug)]
+pub enum AsyncIo {}
+
+#[cfg(feature = "async-io")]
+impl Runtime for AsyncIo {
+    type UdpSocket = async_io::Async<std::net::UdpSocket>;
+    type TcpStream = async_io::Async<std::net::TcpStream>;
+
+    fn bind_udp(addr: SocketAddr) -> io::Result<Self::UdpSocket> {
+        async_io::Async::<std::net::UdpSocket>::bind(addr)
+    }
+
+    fn from_std_udp(sock: std::net::UdpSocket) -> io::Result<Self::UdpSocket> {
+        async_io::Async::new(sock)
+    }
+
+    fn connect_tcp(addr: SocketAddr) -> impl Future<Output = io::Result<Self::TcpStream>> {
+        async_io::Async::<std::net::TcpStream>::connect(addr)
+    }
+
+    async fn sleep(duration: Duration) {
+        async_io::Timer::after(duration).await;
+    }
+}
+
+#[cfg(feature = "async-io")]
+impl AsyncUdpSocket for async_io::Async<std::net::UdpSocket> {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> impl Future<Output = io::Result<usize>> {
+        async_io::Async::<std::net::UdpSocket>::send_to(self, buf, addr)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> impl Future<Output = io::Result<(usize, SocketAddr)>> {
+        async_io::Async::<std::net::UdpSocket>::recv_from(self, buf)
+    }
+}
+
+/// A [`Runtime`] that fails to create any sockets.
+///
+/// This is the [`DefaultRuntime`] when the `async-io` feature is disabled, similar to how the
+/// networking APIs in `std` return errors on targets without networking support.
+#[derive(Debug)]
+pub enum Unsupported {}
+
+impl Runtime for Unsupported {
+    type UdpSocket = UnsupportedSocket;
+    type TcpStream = UnsupportedSocket;
+
+    fn bind_udp(_: SocketAddr) -> io::Result<Self::UdpSocket> {
+        Err(unsupported())
+    }
+
+    async fn connect_tcp(_: SocketAddr) -> io::Result<Self::TcpStream> {
+        Err(unsupported())
+    }
+
+    async fn sleep(_: Duration) {
+        futures_lite::future::pending().await
+    }
+}
+
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "no async runtime configured")
+}
+
+/// The (uninhabited) socket type of the [`Unsupported`] runtime.
+#[derive(Debug)]
+pub enum UnsupportedSocket {}
+
+impl AsyncUdpSocket for UnsupportedSocket {
+    async fn send_to(&self, _: &[u8], _: SocketAddr) -> io::Result<usize> {
+        match *self {}
+    }
+
+    async fn recv_from(&self, _: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match *self {}
+    }
+}
+
+impl AsyncRead for UnsupportedSocket {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        _: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match *self {}
+    }
+}
+
+impl AsyncWrite for UnsupportedSocket {
+    fn poll_write(self: Pin<&mut Self>, _: &mut Context<'_>, _: &[u8]) -> Poll<io::Result<usize>> {
+        match *self {}
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match *self {}
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match *self {}
+    }
+}
+
+/// Runs `fut` to completion, or returns `None` if it doesn't complete within `duration`.
+pub(crate) async fn timeout<R: Runtime, T>(
+    duration: Duration,
+    fut: impl Future<Output = T>,
+) -> Option<T> {
+    futures_lite::future::or(async { Some(fut.await) }, async {
+        R::sleep(duration).await;
+        None
+    })
+    .await
+}
+
+/// Returns the current time, on targets where [`Instant`] is available.
+pub(crate) fn now() -> Option<Instant> {
+    if cfg!(target_arch = "wasm32") {
+        None
+    } else {
+        Some(Instant::now())
+    }
+}
+
+/// A [`Runtime`] for tests, whose sockets are connected to in-process responders, and whose timers
+/// expire immediately.
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::{
+        cell::Cell,
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+
+    /// Computes the datagrams sent in reply to a datagram sent to an address.
+    type Responder = dyn FnMut(&[u8], SocketAddr) -> Vec<Vec<u8>> + Send;
+
+    /// Computes the response to a DNS message sent over TCP.
+    type TcpResponder = fn(&[u8]) -> Vec<u8>;
+
+    /// The datagrams sent by a [`MockSocket`], and their destinations.
+    pub(crate) type Sent = Arc<Mutex<Vec<(Vec<u8>, SocketAddr)>>>;
+
+    thread_local! {
+        /// Answers DNS messages sent over TCP connections opened on the current thread.
+        static TCP_RESPONDER: Cell<Option<TcpResponder>> = const { Cell::new(None) };
+    }
+
+    /// Sets the function answering messages sent over [`Mock`] TCP connections opened by the
+    /// current thread. Without one, connecting fails.
+    pub(crate) fn set_tcp_responder(responder: TcpResponder) {
+        TCP_RESPONDER.with(|r| r.set(Some(responder)));
+    }
+
+    #[derive(Debug)]
+    pub(crate) enum Mock {}
+
+    impl Runtime for Mock {
+        type UdpSocket = MockSocket;
+        type TcpStream = MockStream;
+
+        fn bind_udp(_: SocketAddr) -> io::Result<Self::UdpSocket> {
+            Ok(MockSocket::new(|_, _| Vec::new()))
+        }
+
+        async fn connect_tcp(_: SocketAddr) -> io::Result<Self::TcpStream> {
+            match TCP_RESPONDER.with(Cell::get) {
+                Some(responder) => Ok(MockStream {
+                    responder,
+                    written: Vec::new(),
+                    response: VecDeque::new(),
+                }),
+                None => Err(io::ErrorKind::ConnectionRefused.into()),
+            }
+        }
+
+        async fn sleep(_: Duration) {}
+    }
+
+    /// A UDP socket that passes every datagram it sends to a responder, and receives the replies.
+    ///
+    /// Receiving never completes while no replies are queued, so [`timeout`] expires.
+    pub(crate) struct MockSocket {
+        responder: Mutex<Box<Responder>>,
+        inbox: Mutex<VecDeque<(Vec<u8>, SocketAddr)>>,
+        sent: Sent,
+    }
+
+    impl MockSocket {
+        pub(crate) fn new(
+            responder: impl FnMut(&[u8], SocketAddr) -> Vec<Vec<u8>> + Send + 'static,
+        ) -> Self {
+            Self {
+                responder: Mutex::new(Box::new(responder)),
+                inbox: Mutex::new(VecDeque::new()),
+                sent: Arc::default(),
+            }
+        }
+
+        /// Returns a handle to the list of datagrams sent by this socket, and their destinations.
+        pub(crate) fn sent(&self) -> Sent {
+            self.sent.clone()
+        }
+    }
+
+    impl AsyncUdpSocket for MockSocket {
+        async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+            self.sent.lock().unwrap().push((buf.to_vec(), addr));
+            let replies = (self.responder.lock().unwrap())(buf, addr);
+            let mut inbox = self.inbox.lock().unwrap();
+            inbox.extend(replies.into_iter().map(|reply| (reply, addr)));
+            Ok(buf.len())
+        }
+
+        async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            let next = self.inbox.lock().unwrap().pop_front();
+            let Some((msg, addr)) = next else {
+                return futures_lite::future::pending().await;
+            };
+            let len = msg.len().min(buf.len());
+            buf[..len].copy_from_slice(&msg[..len]);
+            Ok((len, addr))
+        }
+    }
+
+    /// A TCP connection to a DNS server that answers a single length-prefixed message.
+    pub(crate) struct MockStream {
+        responder: TcpResponder,
+        written: Vec<u8>,
+        response: VecDeque<u8>,
+    }
+
+    impl AsyncRead for MockStream {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = &mut *self;
+            if this.response.is_empty() && this.written.len() > 2 {
+                let query = &this.written[2..];
+                let response = (this.responder)(query);
+                this.response.extend((response.len() as u16).to_be_bytes());
+                this.response.extend(response);
+                this.written.clear();
+            }
+            let len = buf.len().min(this.response.len());
+            for (dest, byte) in buf.iter_mut().zip(this.response.drain(..len)) {
+                *dest = byte;
+            }
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    impl AsyncWrite for MockStream {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.written.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn timeout_expires() {
+        futures_lite::future::block_on(async {
+            let sock = MockSocket::new(|msg, _| vec![msg.to_vec()]);
+            let addr = "192.0.2.1:53".parse().unwrap();
+            let mut buf = [0; 16];
+            assert!(timeout::<Mock, _>(Duration::ZERO, sock.recv_from(&mut buf))
+                .await
+                .is_none());
+
+            sock.send_to(b"ping", addr).await.unwrap();
+            let res = timeout::<Mock, _>(Duration::ZERO, sock.recv_from(&mut buf)).await;
+            assert_eq!(res.unwrap().unwrap(), (4, addr));
+            assert_eq!(&buf[..4], b"ping");
+        });
+    }
+}
@@ -1,21 +1,38 @@
 //! Service advertising.
 
-use std::net::UdpSocket;
-use std::{io, net::IpAddr};
+use std::{
+    future::Future,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+};
 
-use async_io::Async;
+use futures_lite::future;
 use uwuhi::{
-    name::Label,
+    acl::Acl,
+    clock::Backoff,
+    name::{DomainName, Label},
+    packet::Type,
+    server::Identity,
     service::{InstanceDetails, ServiceInstance},
-    MDNS_BUFFER_SIZE,
+    Error,
 };
 
 pub use uwuhi::service::advertising::*;
 
+use crate::runtime::{AsyncUdpSocket, DefaultRuntime, Runtime};
+
+/// Where announcements and goodbyes are sent.
+const MDNS_DESTINATION: SocketAddr =
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(224, 0, 0, 251), 5353));
+
 /// Asynchronous mDNS service advertiser and name server.
-pub struct AsyncAdvertiser {
+///
+/// Use [`AsyncAdvertiser::listen`] to advertise until an error occurs, or
+/// [`AsyncAdvertiser::listen_until`] to stop advertising gracefully. A stopped advertiser keeps its
+/// names and service instances, and can be started again.
+pub struct AsyncAdvertiser<R: Runtime = DefaultRuntime> {
     adv: Advertiser,
-    sock: Async<UdpSocket>,
+    /// The mDNS socket, or [`None`] while the advertiser is shut down.
+    sock: Option<R::UdpSocket>,
 }
 
 impl AsyncAdvertiser {
@@ -23,10 +40,21 @@ impl AsyncAdvertiser {
     ///
     /// `hostname` should be different from the system host name, to avoid conflicts with other
     /// installed mDNS responders.
-    pub fn new(hostname: Label, addr: IpAddr) -> io::Result<Self> {
+    pub fn new(hostname: Label, addr: IpAddr) -> Result<Self, Error> {
+        Self::with_runtime(hostname, addr)
+    }
+}
+
+impl<R: Runtime> AsyncAdvertiser<R> {
+    /// Creates a new service advertiser that uses the domain `hostname.local`, using the
+    /// [`Runtime`] `R`.
+    ///
+    /// The mDNS socket is created by [`Advertiser::create_socket`], and passed to
+    /// [`Runtime::from_std_udp`].
+    pub fn with_runtime(hostname: Label, addr: IpAddr) -> Result<Self, Error> {
         let adv = Advertiser::new(hostname, addr)?;
         Ok(Self {
-            sock: Async::new(adv.create_socket()?)?,
+            sock: Some(R::from_std_udp(adv.create_socket()?)?),
             adv,
         })
     }
@@ -40,24 +68,296 @@ impl AsyncAdvertiser {
         self.adv.add_instance(instance, details);
     }
 
+    /// Sets whether the records of type `ty` owned by `name` are shared with other hosts.
+    ///
+    /// See [`Advertiser::set_shared`].
+    pub fn set_shared(&mut self, name: &DomainName, ty: Type, shared: bool) -> bool {
+        self.adv.set_shared(name, ty, shared)
+    }
+
+    /// Sets the largest mDNS message to receive or send, in bytes.
+    ///
+    /// See [`Advertiser::set_max_message_size`].
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.adv.set_max_message_size(size);
+    }
+
+    /// Sets the IP TTL of outgoing multicast packets.
+    ///
+    /// See [`Advertiser::set_multicast_ttl`].
+    pub fn set_multicast_ttl(&mut self, ttl: u32) -> Result<(), Error> {
+        self.adv.set_multicast_ttl(ttl);
+        self.recreate_socket()
+    }
+
+    /// Sets whether outgoing multicast packets are looped back to the local machine.
+    ///
+    /// See [`Advertiser::set_multicast_loopback`].
+    pub fn set_multicast_loopback(&mut self, loopback: bool) -> Result<(), Error> {
+        self.adv.set_multicast_loopback(loopback);
+        self.recreate_socket()
+    }
+
+    /// Sets the interface to listen and respond on.
+    ///
+    /// See [`Advertiser::set_interface`].
+    pub fn set_interface(&mut self, interface: Ipv4Addr) -> Result<(), Error> {
+        self.adv.set_interface(interface);
+        self.recreate_socket()
+    }
+
+    /// Restricts which hosts get answers.
+    ///
+    /// See [`Advertiser::set_acl`].
+    pub fn set_acl(&mut self, acl: Acl) {
+        self.adv.set_acl(acl);
+    }
+
+    /// Sets whether responses are sent via unicast or multicast.
+    ///
+    /// See [`Advertiser::set_response_mode`].
+    pub fn set_response_mode(&mut self, mode: ResponseMode) {
+        self.adv.set_response_mode(mode);
+    }
+
+    /// Sets the strings to answer `CH TXT` identification queries with.
+    ///
+    /// See [`Advertiser::set_identity`].
+    pub fn set_identity(&mut self, identity: Identity) {
+        self.adv.set_identity(identity);
+    }
+
+    /// Sets whether legacy unicast queries for type `ANY` are answered minimally.
+    ///
+    /// See [`Advertiser::set_minimal_any`].
+    pub fn set_minimal_any(&mut self, minimal_any: bool) {
+        self.adv.set_minimal_any(minimal_any);
+    }
+
+    /// Sets a hook that is called for every received packet.
+    ///
+    /// See [`Advertiser::set_packet_hook`].
+    pub fn set_packet_hook(&mut self, hook: impl PacketHook + 'static) {
+        self.adv.set_packet_hook(hook);
+    }
+
+    /// Sets a callback that is invoked whenever a name is changed because of a conflict.
+    ///
+    /// See [`Advertiser::set_rename_hook`].
+    pub fn set_rename_hook(&mut self, hook: impl RenameHook + 'static) {
+        self.adv.set_rename_hook(hook);
+    }
+
+    /// Returns the host name that is currently advertised.
+    ///
+    /// See [`Advertiser::current_hostname`].
+    pub fn current_hostname(&self) -> &Label {
+        self.adv.current_hostname()
+    }
+
+    /// Returns the name under which `instance` is currently advertised.
+    ///
+    /// See [`Advertiser::current_instance_name`].
+    pub fn current_instance_name(&self, instance: &ServiceInstance) -> Option<&Label> {
+        self.adv.current_instance_name(instance)
+    }
+
+    /// Replaces the socket with one reflecting the current socket options.
+    ///
+    /// While shut down, this does nothing; the socket is created when listening starts again.
+    fn recreate_socket(&mut self) -> Result<(), Error> {
+        if self.sock.is_some() {
+            self.sock = Some(self.new_socket()?);
+        }
+        Ok(())
+    }
+
+    fn new_socket(&self) -> Result<R::UdpSocket, Error> {
+        Ok(R::from_std_udp(self.adv.create_socket()?)?)
+    }
+
+    /// Returns the socket, creating (and joining the mDNS group) if the advertiser was shut down.
+    fn socket(&mut self) -> Result<&R::UdpSocket, Error> {
+        if self.sock.is_none() {
+            self.sock = Some(self.new_socket()?);
+        }
+        Ok(self.sock.as_ref().unwrap())
+    }
+
+    /// Returns `Ok` after recreating the socket if `error` is transient, and `error` otherwise.
+    async fn recover(&mut self, error: Error, backoff: &mut Backoff) -> Result<(), Error> {
+        if !error.is_transient() {
+            return Err(error);
+        }
+        log::warn!("mDNS socket error: {}; recreating socket", error);
+        loop {
+            R::sleep(backoff.next_delay()).await;
+            match self.new_socket() {
+                Ok(sock) => {
+                    self.sock = Some(sock);
+                    return Ok(());
+                }
+                Err(e) if e.is_transient() => {
+                    log::warn!("failed to recreate mDNS socket: {}; retrying", e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
     /// Listens for and replies to incoming DNS queries.
-    pub async fn listen(&mut self) -> io::Result<()> {
-        let mut recv_buf = [0; MDNS_BUFFER_SIZE];
+    ///
+    /// This only returns when an error occurs. Use [`AsyncAdvertiser::listen_until`] to stop
+    /// listening gracefully.
+    ///
+    /// Transient socket errors (see [`Error::is_transient`]), like those caused by the network
+    /// interface going down, are logged, and the socket is recreated after an increasing delay.
+    pub async fn listen(&mut self) -> Result<(), Error> {
+        Err(self.run().await)
+    }
+
+    /// Listens for and replies to incoming DNS queries until `shutdown` completes.
+    ///
+    /// When `shutdown` completes, all records are withdrawn by multicasting a goodbye (see
+    /// [`Advertiser::build_goodbye`]), and the socket is closed, which leaves the mDNS group. The
+    /// advertiser keeps its names and service instances, so calling this method (or
+    /// [`AsyncAdvertiser::listen`]) again resumes advertising them.
+    ///
+    /// `shutdown` can be any future, for example the receiving end of a channel, or a signal
+    /// handler. Errors are handled like in [`AsyncAdvertiser::listen`]; if one is returned, no
+    /// goodbye is sent.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use async_io::Timer;
+    /// # use uwuhi_async::{name::Label, service::advertising::AsyncAdvertiser};
+    /// # futures_lite::future::block_on(async {
+    /// let mut advertiser = AsyncAdvertiser::new(Label::new("my-host"), "10.0.0.1".parse()?)?;
+    /// // Advertise for an hour, then withdraw everything.
+    /// advertiser
+    ///     .listen_until(async {
+    ///         Timer::after(Duration::from_secs(3600)).await;
+    ///     })
+    ///     .await?;
+    /// // Later, advertise the same names again.
+    /// advertiser.listen().await?;
+    /// # Ok::<_, Box<dyn std::error::Error>>(())
+    /// # });
+    /// ```
+    pub async fn listen_until(&mut self, shutdown: impl Future<Output = ()>) -> Result<(), Error> {
+        let res = future::or(async { Err(self.run().await) }, async {
+            shutdown.await;
+            Ok(())
+        })
+        .await;
+        res?;
+        self.shutdown().await
+    }
+
+    /// Multicasts an announcement of all unique records right away.
+    ///
+    /// Returns whether an announcement was sent. See [`Advertiser::announce_now`].
+    pub async fn announce_now(&mut self) -> Result<bool, Error> {
+        self.socket()?;
+        let sock = self.sock.as_ref().unwrap();
+        match self.adv.announce_now()? {
+            Some(announcement) => {
+                sock.send_to(announcement, MDNS_DESTINATION).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Withdraws all records by multicasting a goodbye, and closes the socket.
+    ///
+    /// This is done automatically by [`AsyncAdvertiser::listen_until`]. Does nothing if the
+    /// advertiser is already shut down.
+    pub async fn shutdown(&mut self) -> Result<(), Error> {
+        let Some(sock) = self.sock.take() else {
+            return Ok(());
+        };
+        log::debug!("shutting down, sending goodbye");
+        let goodbye = self.adv.build_goodbye()?;
+        sock.send_to(goodbye, MDNS_DESTINATION).await?;
+        // Dropping the socket leaves the multicast group.
+        drop(sock);
+        Ok(())
+    }
+
+    /// The listening loop, which only returns when an error occurs.
+    async fn run(&mut self) -> Error {
+        let mut backoff = Backoff::default();
+        let mut recv_buf = vec![0; self.adv.max_message_size()];
         loop {
-            let (len, addr) = self.sock.recv_from(&mut recv_buf).await?;
-            let packet = &recv_buf[..len];
+            if let Err(e) = self.step(&mut recv_buf, &mut backoff).await {
+                return e;
+            }
+        }
+    }
+
+    /// Receives and handles a single packet.
+    async fn step(&mut self, recv_buf: &mut [u8], backoff: &mut Backoff) -> Result<(), Error> {
+        let sock = self.socket()?;
+        let (len, addr) = match sock.recv_from(recv_buf).await {
+            Ok(res) => res,
+            Err(e) => return self.recover(e.into(), backoff).await,
+        };
+        backoff.reset();
+        let packet = &recv_buf[..len];
 
-            log::trace!("raw recv from {}: {:x?}", addr, packet);
+        log::trace!("raw recv from {}: {:x?}", addr, packet);
 
-            match self.adv.handle_packet(packet) {
-                Ok(Some(resp)) => {
-                    self.sock.send_to(resp, addr).await?;
+        let sock = self.sock.as_ref().unwrap();
+        match self.adv.handle_query(packet, addr) {
+            Ok(Some((resp, dest))) => {
+                if let Err(e) = sock.send_to(resp, dest).await {
+                    return self.recover(e.into(), backoff).await;
                 }
-                Ok(None) => {}
-                Err(e) => {
-                    log::debug!("failed to handle packet: {}", e);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                log::debug!("failed to handle packet: {}", e);
+            }
+        }
+
+        // Announce names that were changed due to a conflict.
+        match self.adv.build_announcement() {
+            Ok(Some(announcement)) => {
+                if let Err(e) = sock.send_to(announcement, MDNS_DESTINATION).await {
+                    return self.recover(e.into(), backoff).await;
                 }
             }
+            Ok(None) => {}
+            Err(e) => {
+                log::warn!("failed to build announcement: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "async-io"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn socket_options() {
+        fn sock(adv: &AsyncAdvertiser) -> &std::net::UdpSocket {
+            adv.sock.as_ref().unwrap().get_ref()
         }
+
+        let addr = Ipv4Addr::new(10, 0, 0, 1).into();
+        let mut adv = AsyncAdvertiser::new(Label::new("host"), addr).unwrap();
+        assert_eq!(sock(&adv).multicast_ttl_v4().unwrap(), 255);
+        assert!(sock(&adv).multicast_loop_v4().unwrap());
+
+        // Changing the options recreates the socket.
+        adv.set_multicast_ttl(1).unwrap();
+        adv.set_multicast_loopback(false).unwrap();
+        assert_eq!(sock(&adv).multicast_ttl_v4().unwrap(), 1);
+        assert!(!sock(&adv).multicast_loop_v4().unwrap());
     }
 }
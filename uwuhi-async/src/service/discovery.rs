@@ -2,64 +2,92 @@
 
 use std::{
     collections::{btree_map::Entry, BTreeMap},
-    io,
-    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
     ops::ControlFlow,
-    time::{Duration, Instant},
+    time::Duration,
 };
 
-use async_io::{Async, Timer};
 use futures_lite::future;
+
 use uwuhi::{
+    __private::{checked_message_size, default_max_message_size},
+    domain,
     name::DomainName,
-    packet::{records::Record, QType},
-    service::{InstanceDetails, Service, ServiceInstance, TxtRecords},
-    MDNS_BUFFER_SIZE,
+    packet::{records::Record, Header, QType},
+    service::{InstanceDetails, Service, ServiceInstance, ServiceTarget},
+    Error, DNS_BUFFER_SIZE, MDNS_BUFFER_SIZE,
 };
 
 pub use uwuhi::service::discovery::*;
 
-pub struct AsyncDiscoverer {
-    sock: Async<UdpSocket>,
+use crate::runtime::{self, now, AsyncUdpSocket, DefaultRuntime, Runtime};
+
+/// Handler invoked with every received message. Returns whether to stop listening for responses.
+type OnResponse<'a> = dyn FnMut(&[u8]) -> Result<ControlFlow<()>, Error> + Send + 'a;
+
+pub struct AsyncDiscoverer<R: Runtime = DefaultRuntime> {
+    sock: R::UdpSocket,
     server: SocketAddr,
     domain: DomainName,
     retransmit_timeout: Duration,
     discovery_timeout: Duration,
+    query_id: Option<u16>,
+    max_message_size: usize,
+    details_cache: DetailsCache,
+    liveness_timeout: Option<Duration>,
 }
 
 impl AsyncDiscoverer {
+    /// Creates a new service discoverer that will request services of `domain` from the given DNS
+    /// server.
+    pub async fn new(server: SocketAddr, domain: DomainName) -> Result<Self, Error> {
+        Self::with_runtime(server, domain).await
+    }
+
+    /// Creates an mDNS service discoverer that will browse the `.local` service domain.
+    pub async fn new_multicast_v4() -> Result<Self, Error> {
+        Self::new("224.0.0.251:5353".parse().unwrap(), domain!("local")).await
+    }
+}
+
+impl<R: Runtime> AsyncDiscoverer<R> {
     const DEFAULT_RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(300);
     const DEFAULT_DISCOVERY_TIMEOUT: Duration = Duration::from_millis(1000);
 
     /// Creates a new service discoverer that will request services of `domain` from the given DNS
-    /// server.
-    pub async fn new(server: SocketAddr, domain: DomainName) -> io::Result<Self> {
+    /// server, using the [`Runtime`] `R`.
+    pub async fn with_runtime(server: SocketAddr, domain: DomainName) -> Result<Self, Error> {
         let bind_addr: SocketAddr = if server.is_ipv6() {
             (Ipv6Addr::UNSPECIFIED, 0).into()
         } else {
             (Ipv4Addr::UNSPECIFIED, 0).into()
         };
-        Ok(Self {
-            sock: Async::<UdpSocket>::bind(bind_addr)?,
+        Ok(Self::with_socket(R::bind_udp(bind_addr)?, server, domain))
+    }
+
+    /// Creates a new service discoverer that will use `sock` to request services of `domain` from
+    /// the given DNS server.
+    ///
+    /// This allows using a socket that was configured in ways this library does not support
+    /// directly (eg. bound to a specific interface or port). `sock` must use the same address
+    /// family as `server`.
+    pub fn with_socket(sock: R::UdpSocket, server: SocketAddr, domain: DomainName) -> Self {
+        Self {
+            sock,
             server,
             domain,
             retransmit_timeout: Self::DEFAULT_RETRANSMIT_TIMEOUT,
             discovery_timeout: Self::DEFAULT_DISCOVERY_TIMEOUT,
-        })
-    }
-
-    /// Creates an mDNS service discoverer that will browse the `.local` service domain.
-    pub async fn new_multicast_v4() -> io::Result<Self> {
-        Self::new(
-            "224.0.0.251:5353".parse().unwrap(),
-            DomainName::from_str("local").unwrap(),
-        )
-        .await
+            query_id: None,
+            max_message_size: default_max_message_size(server),
+            details_cache: DetailsCache::new(),
+            liveness_timeout: None,
+        }
     }
 
     /// Sets the time after which a discovery query is retransmitted, if no responses have been
     /// received in this amount of time.
-    pub fn set_retransmit_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+    pub fn set_retransmit_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
         self.retransmit_timeout = timeout;
         Ok(())
     }
@@ -68,20 +96,84 @@ impl AsyncDiscoverer {
     ///
     /// Calling any service discovery method will block for this amount of time while it waits for
     /// responses.
-    pub fn set_discovery_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+    pub fn set_discovery_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
         self.discovery_timeout = timeout;
         Ok(())
     }
 
+    /// Sets the largest DNS message this discoverer can receive, in bytes.
+    ///
+    /// Defaults to [`DNS_BUFFER_SIZE`] for unicast DNS servers, and to [`MDNS_BUFFER_SIZE`] for
+    /// mDNS. [`MDNS_MAX_MESSAGE_SIZE`] can be used for mDNS on networks with jumbo frames.
+    ///
+    /// When querying a unicast DNS server with a size larger than [`DNS_BUFFER_SIZE`], the size is
+    /// advertised to the server via an EDNS(0) `OPT` record.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is smaller than [`DNS_BUFFER_SIZE`] or larger than 65535.
+    ///
+    /// [`MDNS_MAX_MESSAGE_SIZE`]: crate::MDNS_MAX_MESSAGE_SIZE
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = checked_message_size(size);
+    }
+
+    /// Sets the message ID to use for discovery queries.
+    ///
+    /// By default (or when passing [`None`]), every query uses a new random ID. When querying a
+    /// unicast DNS server, responses whose ID doesn't match the query are ignored.
+    pub fn set_query_id(&mut self, id: Option<u16>) {
+        self.query_id = id;
+    }
+
+    /// Sets whether freshly loaded instances are checked for reachability by opening a TCP
+    /// connection to them, and how long to wait for each connection attempt.
+    ///
+    /// This works like a [`TcpConnectProbe`] set via `SyncDiscoverer::set_liveness_probe`: the
+    /// targets are tried in order of preference until one of them accepts a connection, the ones
+    /// that don't are reported via [`AsyncDiscoverer::report_unreachable`], and if none of them
+    /// accept, the last error is returned instead of the details. [`None`] disables the check,
+    /// which is the default.
+    ///
+    /// For protocol-specific checks, probe the targets yourself and report the unreachable ones.
+    pub fn set_tcp_liveness_check(&mut self, timeout: Option<Duration>) {
+        self.liveness_timeout = timeout;
+    }
+
     /// Requests the [`InstanceDetails`] associated with a specific [`ServiceInstance`] from the
     /// server.
     ///
     /// The [`InstanceDetails`] contain hostname and port where the [`ServiceInstance`] can be
     /// reached as well as service-specific metadata (which may be omitted).
+    ///
+    /// Details loaded previously are returned from a cache until the TTL of their records runs
+    /// out. Use [`AsyncDiscoverer::load_instance_details_with`] to bypass the cache. On `wasm32`,
+    /// where [`Instant`](std::time::Instant) isn't available, nothing is cached.
     pub async fn load_instance_details(
         &mut self,
         instance: &ServiceInstance,
-    ) -> io::Result<InstanceDetails> {
+    ) -> Result<InstanceDetails, Error> {
+        self.load_instance_details_with(instance, false).await
+    }
+
+    /// Requests the [`InstanceDetails`] associated with a specific [`ServiceInstance`], querying
+    /// the server even if cached details are available if `refresh` is `true`.
+    ///
+    /// Either way, the cache is updated with the response.
+    pub async fn load_instance_details_with(
+        &mut self,
+        instance: &ServiceInstance,
+        refresh: bool,
+    ) -> Result<InstanceDetails, Error> {
+        if let (false, Some(now)) = (refresh, now()) {
+            if let Some(cached) = self.details_cache.get(instance, now) {
+                if self.liveness_timeout.is_none() || cached.next_target().is_some() {
+                    log::trace!("using cached details of {}", instance);
+                    return Ok(cached.details().clone());
+                }
+            }
+        }
+
         let mut domain = DomainName::from_iter([
             instance.instance_name(),
             instance.service().name(),
@@ -89,51 +181,91 @@ impl AsyncDiscoverer {
         ]);
         domain.extend(&self.domain);
 
-        let mut details = None;
-        let mut txt_records = None;
-        self.send_query(&domain, &[QType::SRV, QType::TXT], &mut |record| {
-            match record {
-                Record::SRV(srv) => {
-                    match InstanceDetails::from_srv(&srv) {
-                        Ok(det) => {
-                            // FIXME: respect SRV priority, as required by RFC 6763
-                            details = Some(det);
-                            // FIXME: breaking here ignores any subsequent TXT records!
-                            ControlFlow::Break(())
-                        }
-                        Err(e) => {
-                            log::debug!(
-                                "failed to read instance details from SRV ({:?}): {}",
-                                e,
-                                srv
-                            );
-                            ControlFlow::Continue(())
-                        }
-                    }
-                }
-                Record::TXT(txt) => {
-                    txt_records = Some(TxtRecords::from_txt(&txt));
-                    ControlFlow::Continue(())
-                }
-                _ => ControlFlow::Continue(()),
-            }
+        let mut collector = DetailsCollector::new(domain.clone());
+        self.send_query(&domain, &[QType::SRV, QType::TXT], &mut |msg| {
+            collector.add_response(msg)?;
+            Ok(match collector.is_complete() {
+                true => ControlFlow::Break(()),
+                false => ControlFlow::Continue(()),
+            })
         })
         .await?;
 
-        match details {
-            Some(mut details) => {
-                if let Some(txt) = txt_records {
-                    // FIXME this can potentially combine a TXT from one machine with a SRV from
-                    // another
-                    *details.txt_records_mut() = txt;
-                }
+        let ttl = collector.ttl().unwrap_or_default();
+        // If nothing arrived in time, there are no details to return.
+        let details = collector.finish().ok_or(Error::Timeout)?;
+        if let Some(now) = now() {
+            self.details_cache
+                .insert(instance.clone(), details.clone(), ttl, now);
+        }
 
-                Ok(details)
+        if let Some(timeout) = self.liveness_timeout {
+            let mut error = None;
+            for target in details.targets_by_preference() {
+                match connect::<R>(target, timeout).await {
+                    Ok(()) => {
+                        error = None;
+                        break;
+                    }
+                    Err(e) => {
+                        log::debug!(
+                            "{}:{} of {} is unreachable: {}",
+                            target.host(),
+                            target.port(),
+                            instance,
+                            e
+                        );
+                        self.report_unreachable(instance, target);
+                        error = Some(e);
+                    }
+                }
+            }
+            if let Some(e) = error {
+                return Err(e);
             }
-
-            // Didn't get a response in time.
-            None => Err(io::ErrorKind::TimedOut.into()),
         }
+        Ok(details)
+    }
+
+    /// Returns the cached [`InstanceDetails`] of `instance`, unless they have expired.
+    ///
+    /// Use [`CachedDetails::remaining_ttl`] to find out how much longer they are valid.
+    pub fn cached_instance_details(&self, instance: &ServiceInstance) -> Option<&CachedDetails> {
+        self.details_cache.get(instance, now()?)
+    }
+
+    /// Records that `target` of `instance` could not be reached, and returns the next target to try
+    /// from the cached details, without querying the network again.
+    ///
+    /// See [`DetailsCache::report_unreachable`].
+    pub fn report_unreachable(
+        &mut self,
+        instance: &ServiceInstance,
+        target: &ServiceTarget,
+    ) -> Option<&ServiceTarget> {
+        self.details_cache
+            .report_unreachable(instance, target, now()?)
+    }
+
+    /// Removes all cached [`InstanceDetails`].
+    pub fn clear_details_cache(&mut self) {
+        self.details_cache.clear();
+    }
+
+    /// Returns the cache of [`InstanceDetails`].
+    ///
+    /// With the `persistent-cache` feature of `uwuhi`, this can be saved to disk with
+    /// `DetailsCache::save`, so that later runs of the program can reuse it.
+    #[inline]
+    pub fn details_cache(&self) -> &DetailsCache {
+        &self.details_cache
+    }
+
+    /// Replaces the cache of [`InstanceDetails`] with `cache`.
+    ///
+    /// This is typically used to restore a cache loaded with `DetailsCache::load`.
+    pub fn set_details_cache(&mut self, cache: DetailsCache) {
+        self.details_cache = cache;
     }
 
     /// Starts service discovery and invokes `callback` with every discovered instance of `service`.
@@ -144,7 +276,7 @@ impl AsyncDiscoverer {
         &mut self,
         service: &Service,
         mut callback: C,
-    ) -> io::Result<()>
+    ) -> Result<(), Error>
     where
         C: FnMut(&ServiceInstance) -> ControlFlow<()> + Send,
     {
@@ -152,7 +284,7 @@ impl AsyncDiscoverer {
         domain.extend(&self.domain);
 
         let mut instances = BTreeMap::new();
-        self.send_query(&domain, &[QType::PTR], &mut |record| {
+        let mut on_record = |record: Record<'_>| {
             let ptr = match record {
                 Record::PTR(ptr) => ptr,
                 _ => return ControlFlow::Continue(()),
@@ -178,6 +310,9 @@ impl AsyncDiscoverer {
                     ControlFlow::Continue(())
                 }
             }
+        };
+        self.send_query(&domain, &[QType::PTR], &mut |msg| {
+            decode_answer(msg, &mut on_record)
         })
         .await
     }
@@ -188,75 +323,175 @@ impl AsyncDiscoverer {
     /// mostly intended for maintenance and debugging, since applications typically know the service
     /// types they support already.
     ///
+    /// When using mDNS, the answers of all responders are merged (see [`ServiceTypeCollector`]).
+    /// The query is repeated at increasing intervals, starting at [`MIN_QUERY_INTERVAL`], and lists
+    /// the service types that were already discovered, so that responders only send new ones.
+    ///
     /// To discover *service instances*, use [`AsyncDiscoverer::discover_instances`] instead.
-    pub async fn discover_service_types<C>(&mut self, mut callback: C) -> io::Result<()>
+    pub async fn discover_service_types<C>(&mut self, mut callback: C) -> Result<(), Error>
     where
         C: FnMut(&Service) -> ControlFlow<()> + Send,
     {
-        let mut domain = DomainName::from_str("_services._dns-sd._udp").unwrap();
-        domain.extend(&self.domain);
-        let mut service_types = BTreeMap::new();
-        self.send_query(&domain, &[QType::PTR], &mut |record| {
-            let ptr = match record {
-                Record::PTR(ptr) => ptr,
-                _ => return ControlFlow::Continue(()),
-            };
-            let service = match Service::from_ptr(ptr) {
-                Ok(service) => service,
-                Err(e) => {
-                    log::warn!("failed to decode service: {:?}", e);
-                    return ControlFlow::Continue(());
-                }
-            };
-            match service_types.entry(service) {
-                Entry::Vacant(e) => {
-                    let flow = callback(e.key());
-                    e.insert(());
-                    flow
-                }
-                Entry::Occupied(_) => {
-                    // Already discovered this service
-                    ControlFlow::Continue(())
+        let mut collector = ServiceTypeCollector::new(&self.domain);
+        collector.set_multicast(self.server.ip().is_multicast());
+        if self.server.ip().is_multicast() {
+            // Stop once the max. discovery time is exceeded.
+            runtime::timeout::<R, _>(
+                self.discovery_timeout,
+                self.enumerate_multicast(&mut collector, &mut callback),
+            )
+            .await
+            .unwrap_or(Ok(()))
+        } else {
+            let domain = collector.enumeration_domain().clone();
+            self.send_query(&domain, &[QType::PTR], &mut |msg| {
+                collector.add_response(msg, &mut callback)
+            })
+            .await
+        }
+    }
+
+    /// Sends all questions in `batch` at once, and passes the records answering each of them to its
+    /// callback.
+    ///
+    /// Returns once every callback has returned [`ControlFlow::Break`], or when the discovery
+    /// timeout expires. See [`QueryBatch`] for how records are matched to questions.
+    pub async fn query_batch(&mut self, batch: &mut QueryBatch<'_>) -> Result<(), Error> {
+        if batch.is_done() {
+            return Ok(());
+        }
+        let id = self.query_id.unwrap_or_else(Header::random_id);
+        let unicast = !self.server.ip().is_multicast();
+        batch.set_multicast(!unicast);
+        let mut send_buf = [0; MDNS_BUFFER_SIZE];
+        let mut packets = Vec::new();
+        batch.encode_queries(id, &mut send_buf, self.edns_payload_size(), |packet| {
+            packets.push(packet.to_vec())
+        })?;
+        let packets = packets.iter().map(Vec::as_slice).collect::<Vec<_>>();
+
+        let mut on_response = |msg: &[u8]| batch.add_response(msg);
+        runtime::timeout::<R, _>(
+            self.discovery_timeout,
+            instrument!(
+                self.run_query(&packets, id, unicast, &mut on_response),
+                "batch_query",
+                id,
+                server = %self.server,
+            ),
+        )
+        .await
+        .unwrap_or(Ok(()))
+    }
+
+    /// Runs a continuous mDNS service type enumeration query.
+    async fn enumerate_multicast(
+        &self,
+        collector: &mut ServiceTypeCollector,
+        callback: &mut (dyn FnMut(&Service) -> ControlFlow<()> + Send),
+    ) -> Result<(), Error> {
+        let id = self.query_id.unwrap_or_else(Header::random_id);
+        let mut interval = MIN_QUERY_INTERVAL;
+        let mut send_buf = vec![0; self.max_message_size];
+        let mut recv_buf = vec![0; self.max_message_size];
+        loop {
+            let mut packets = Vec::new();
+            collector.encode_queries(id, &mut send_buf, |packet| packets.push(packet.to_vec()))?;
+            for packet in &packets {
+                self.sock.send_to(packet, self.server).await?;
+            }
+
+            // Receive responses until it's time for the next query.
+            let next_query = R::sleep(interval);
+            futures_lite::pin!(next_query);
+            interval *= 2;
+            loop {
+                let recv = self.sock.recv_from(&mut recv_buf);
+                let res = future::or(async { Some(recv.await) }, async {
+                    (&mut next_query).await;
+                    None
+                })
+                .await;
+                let Some(res) = res else { break };
+                let (b, addr) = res?;
+                let recv = &recv_buf[..b];
+                log::trace!("recv from {}: {}", addr, recv.escape_ascii());
+                trace_event!(from = %addr, len = b, "received response");
+
+                match collector.add_response(recv, callback) {
+                    Ok(ControlFlow::Continue(())) => {}
+                    Ok(ControlFlow::Break(())) => return Ok(()),
+                    Err(err) => log::warn!("failed to decode response: {:?}", err),
                 }
             }
-        })
-        .await
+        }
     }
 
     async fn send_query(
         &mut self,
         domain: &DomainName,
         qtypes: &[QType],
-        callback: &mut (dyn FnMut(Record<'_>) -> ControlFlow<()> + Send),
-    ) -> io::Result<()> {
+        on_response: &mut OnResponse<'_>,
+    ) -> Result<(), Error> {
         let mut send_buf = [0; MDNS_BUFFER_SIZE];
-        let data = encode_query(&mut send_buf, domain, qtypes);
+        let id = self.query_id.unwrap_or_else(Header::random_id);
+        let unicast = !self.server.ip().is_multicast();
+        let data =
+            encode_query_with_id(&mut send_buf, id, domain, qtypes, self.edns_payload_size());
+        let packets = [data];
 
-        let discovery_start = Instant::now();
+        // Stop once the max. discovery time is exceeded.
+        runtime::timeout::<R, _>(
+            self.discovery_timeout,
+            instrument!(
+                self.run_query(&packets, id, unicast, on_response),
+                "discovery_query",
+                %domain,
+                ?qtypes,
+                id,
+                server = %self.server,
+            ),
+        )
+        .await
+        .unwrap_or(Ok(()))
+    }
+
+    /// Returns the UDP payload size to advertise via EDNS(0), if any.
+    fn edns_payload_size(&self) -> Option<u16> {
+        let unicast = !self.server.ip().is_multicast();
+        (unicast && self.max_message_size > DNS_BUFFER_SIZE).then_some(self.max_message_size as u16)
+    }
+
+    async fn run_query(
+        &self,
+        packets: &[&[u8]],
+        id: u16,
+        unicast: bool,
+        on_response: &mut OnResponse<'_>,
+    ) -> Result<(), Error> {
+        let mut recv_buf = vec![0; self.max_message_size];
         'retransmit: loop {
-            self.sock.send_to(data, self.server).await?;
+            for packet in packets {
+                self.sock.send_to(packet, self.server).await?;
+            }
 
             loop {
-                if discovery_start.elapsed() >= self.discovery_timeout {
-                    // Max. discovery time exceeded.
-                    return Ok(());
-                }
-
-                let mut recv_buf = [0; MDNS_BUFFER_SIZE];
-                let timeout = async {
-                    Timer::after(self.retransmit_timeout).await;
-                    Err(())
-                };
-                let recv = async { Ok(self.sock.recv_from(&mut recv_buf).await) };
-                let (b, addr) = match future::or(recv, timeout).await {
-                    Ok(Ok(res)) => res,
-                    Ok(Err(e)) => return Err(e),
-                    Err(()) => continue 'retransmit,
+                let recv = self.sock.recv_from(&mut recv_buf);
+                let (b, addr) = match runtime::timeout::<R, _>(self.retransmit_timeout, recv).await
+                {
+                    Some(res) => res?,
+                    None => continue 'retransmit,
                 };
                 let recv = &recv_buf[..b];
                 log::trace!("recv from {}: {}", addr, recv.escape_ascii());
+                trace_event!(from = %addr, len = b, "received response");
+
+                if unicast && !is_response_to(recv, id) {
+                    log::debug!("ignoring message from {} with mismatched ID", addr);
+                    continue;
+                }
 
-                let res = decode_answer(recv, callback);
+                let res = on_response(recv);
 
                 match res {
                     Ok(ControlFlow::Continue(())) => {}
@@ -269,3 +504,22 @@ impl AsyncDiscoverer {
         }
     }
 }
+
+/// Opens (and closes) a TCP connection to any of the addresses of `target`.
+///
+/// Targets without any known addresses are considered reachable, like with [`TcpConnectProbe`].
+async fn connect<R: Runtime>(target: &ServiceTarget, timeout: Duration) -> Result<(), Error> {
+    let mut error = None;
+    for &ip in target.addrs() {
+        let addr = SocketAddr::new(ip, target.port());
+        match runtime::timeout::<R, _>(timeout, R::connect_tcp(addr)).await {
+            Some(Ok(_)) => return Ok(()),
+            Some(Err(e)) => error = Some(e.into()),
+            None => error = Some(Error::Timeout),
+        }
+    }
+    match error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
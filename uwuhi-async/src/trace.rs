@@ -0,0 +1,29 @@
+//! Optional [`tracing`] instrumentation.
+//!
+//! When the `tracing` feature is enabled, these macros attach spans to futures and emit events
+//! with structured fields. Otherwise they expand to (almost) nothing.
+//!
+//! [`tracing`]: https://docs.rs/tracing
+
+/// Instruments `$future` with a `DEBUG` span.
+///
+/// The span's fields are evaluated before `$future`, so they may borrow data that the future
+/// borrows mutably.
+macro_rules! instrument {
+    ($future:expr, $($span:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!($($span)*);
+        let future = $future;
+        #[cfg(feature = "tracing")]
+        let future = tracing::Instrument::instrument(future, span);
+        future
+    }};
+}
+
+/// Emits a `DEBUG` event.
+macro_rules! trace_event {
+    ($($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($args)*);
+    };
+}